@@ -1,17 +1,26 @@
+mod menu;
 mod opts;
+mod tui;
 
 use std::{
     io::{Read, Write},
-    os::unix::net::UnixStream,
-    path::PathBuf,
+    net::TcpStream,
+    os::{
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixStream},
+    },
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use clap::Parser;
+use clap::{CommandFactory, Parser, ValueEnum};
 use serde::Serialize;
-use serde_json::to_string;
-use wpaperd_ipc::{socket_path, IpcError, IpcMessage, IpcResponse};
+use wpaperd_ipc::{
+    read_message, seat_socket_path, socket_path, write_message, AnimationAction, CropRegion,
+    IpcError, IpcMessage, IpcResponse, ReloadScope, RemoteAddress, PROTOCOL_VERSION,
+};
 
-use crate::opts::{Opts, SubCmd};
+use crate::opts::{AnimationCmd, Opts, SubCmd, Switch};
 
 fn unquote(s: String) -> String {
     if s.starts_with('"') && s.ends_with('"') {
@@ -21,12 +30,159 @@ fn unquote(s: String) -> String {
     }
 }
 
+/// The daemon has a different working directory, send the absolute path
+fn absolute_path(path: PathBuf) -> PathBuf {
+    path.canonicalize().unwrap_or(path)
+}
+
+trait Connection: Read + Write {}
+
+impl<T: Read + Write> Connection for T {}
+
+/// Connect to a wpaperd listening for remote clients and send the token
+fn connect_remote(address: &str, token_file: &Path) -> Box<dyn Connection> {
+    let address: RemoteAddress = address.parse().unwrap_or_else(|err| {
+        eprintln!("invalid address passed to --remote: {err}");
+        std::process::exit(1);
+    });
+    let token = std::fs::read_to_string(token_file).unwrap_or_else(|err| {
+        eprintln!(
+            "the token could not be read from {}: {err}",
+            token_file.to_string_lossy()
+        );
+        std::process::exit(1);
+    });
+    let connected = match &address {
+        RemoteAddress::Tcp(tcp_address) => {
+            TcpStream::connect(tcp_address).map(|stream| Box::new(stream) as Box<dyn Connection>)
+        }
+        RemoteAddress::Abstract(name) => SocketAddr::from_abstract_name(name)
+            .and_then(|socket_address| UnixStream::connect_addr(&socket_address))
+            .map(|stream| Box::new(stream) as Box<dyn Connection>),
+    };
+    connected
+        .and_then(|mut conn| write_message(&mut conn, &token.trim()).map(|_| conn))
+        .unwrap_or_else(|err| {
+            eprintln!("could not connect to the remote wpaperd at {address}: {err}");
+            std::process::exit(1);
+        })
+}
+
+/// The wpaperd receiving the commands, chosen by the global options
+struct Client {
+    seat: Option<String>,
+    /// The address and the token file
+    remote: Option<(String, PathBuf)>,
+}
+
+impl Client {
+    fn connect(&self) -> Box<dyn Connection> {
+        match &self.remote {
+            Some((address, token_file)) => connect_remote(address, token_file),
+            None => {
+                let socket_path = match &self.seat {
+                    Some(seat) => seat_socket_path(Some(seat)),
+                    None => socket_path(),
+                };
+                Box::new(UnixStream::connect(socket_path.unwrap()).unwrap())
+            }
+        }
+    }
+
+    /// Send a message on a new connection and return the response
+    fn request(&self, msg: &IpcMessage) -> Result<IpcResponse, IpcError> {
+        let mut conn = self.connect();
+        write_message(&mut conn, msg).unwrap();
+        read_message(&mut conn).expect("wpaperd to return a valid json")
+    }
+
+    /// The images `monitor` picks from, asked for in as many parts as needed
+    fn images(&self, monitor: &str) -> Result<Vec<PathBuf>, IpcError> {
+        let (mut images, mut offset) = (Vec::new(), Some(0));
+        while let Some(from) = offset {
+            let msg = IpcMessage::Images {
+                monitor: monitor.to_string(),
+                offset: from,
+            };
+            let IpcResponse::Images { paths, next } = self.request(&msg)? else {
+                unreachable!()
+            };
+            images.extend(paths);
+            offset = next;
+        }
+        Ok(images)
+    }
+
+    /// The images `monitor` picks from with their thumbnails, like [Client::images]
+    fn thumbnails(&self, monitor: &str, size: u32) -> Result<Vec<(PathBuf, PathBuf)>, IpcError> {
+        let (mut thumbnails, mut offset) = (Vec::new(), Some(0));
+        while let Some(from) = offset {
+            let msg = IpcMessage::Thumbnails {
+                monitor: monitor.to_string(),
+                size,
+                offset: from,
+            };
+            let IpcResponse::Thumbnails { entries, next } = self.request(&msg)? else {
+                unreachable!()
+            };
+            thumbnails.extend(entries);
+            offset = next;
+        }
+        Ok(thumbnails)
+    }
+}
+
+/// How often `wpaperctl trace --follow` asks for the new events
+const TRACE_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Run `wpaperctl trace`
+fn trace(client: &Client, monitors: Vec<String>, follow: bool, json: bool) -> Result<(), IpcError> {
+    let mut after = None;
+    loop {
+        let msg = IpcMessage::Trace {
+            monitors: monitors.clone(),
+            after,
+        };
+        let IpcResponse::Trace { events, started } = client.request(&msg)? else {
+            unreachable!()
+        };
+        if started {
+            eprintln!("wpaperd has started recording the trace");
+        }
+        for event in events {
+            after = Some(event.id);
+            if json {
+                println!(
+                    "{}",
+                    serde_json::to_string(&event).expect("json encoding to work")
+                );
+                continue;
+            }
+            let duration = event
+                .duration
+                .map(|duration| format!(" ({:.1}ms)", duration.as_secs_f64() * 1000.0))
+                .unwrap_or_default();
+            println!(
+                "{} {} {:<7}{duration} {}",
+                event.time, event.monitor, event.stage, event.detail
+            );
+        }
+        if !follow {
+            return Ok(());
+        }
+        std::thread::sleep(TRACE_POLL_INTERVAL);
+    }
+}
+
 fn main() {
     let args = Opts::parse();
 
     let mut json_resp = false;
 
-    let mut conn = UnixStream::connect(socket_path().unwrap()).unwrap();
+    let client = Client {
+        seat: args.seat,
+        remote: args.remote.zip(args.token_file),
+    };
     let msg = match args.subcmd {
         SubCmd::GetWallpaper { monitor } => IpcMessage::CurrentWallpaper {
             monitor: unquote(monitor),
@@ -41,8 +197,20 @@ fn main() {
         SubCmd::PreviousWallpaper { monitors } => IpcMessage::PreviousWallpaper {
             monitors: monitors.into_iter().map(unquote).collect(),
         },
-        SubCmd::ReloadWallpaper { monitors } => IpcMessage::ReloadWallpaper {
-            monitors: monitors.into_iter().map(unquote).collect(),
+        SubCmd::ReloadWallpaper {
+            monitors,
+            outputs,
+            rescan_dirs,
+            config_only,
+        } => IpcMessage::Reload {
+            monitors: monitors.into_iter().chain(outputs).map(unquote).collect(),
+            scope: if config_only {
+                ReloadScope::Config
+            } else if rescan_dirs {
+                ReloadScope::Directories
+            } else {
+                ReloadScope::Outputs
+            },
         },
         SubCmd::PauseWallpaper { monitors } => IpcMessage::PauseWallpaper {
             monitors: monitors.into_iter().map(unquote).collect(),
@@ -53,13 +221,228 @@ fn main() {
         SubCmd::TogglePauseWallpaper { monitors } => IpcMessage::TogglePauseWallpaper {
             monitors: monitors.into_iter().map(unquote).collect(),
         },
+        SubCmd::HoldWallpaper { duration, monitors } => IpcMessage::HoldWallpaper {
+            monitors: monitors.into_iter().map(unquote).collect(),
+            duration,
+        },
+        SubCmd::Queue { monitor } => IpcMessage::Queue {
+            monitor: unquote(monitor),
+        },
+        SubCmd::QueueNext { monitor, path } => IpcMessage::QueueNext {
+            monitor: unquote(monitor),
+            path: absolute_path(path),
+        },
+        SubCmd::ShowOnce {
+            monitor,
+            path,
+            duration,
+        } => IpcMessage::ShowOnce {
+            monitor: unquote(monitor),
+            path: absolute_path(path),
+            duration,
+        },
+        SubCmd::QueueRemove { monitor, path } => IpcMessage::QueueRemove {
+            monitor: unquote(monitor),
+            path: absolute_path(path),
+        },
+        SubCmd::SetDuration {
+            monitor,
+            duration,
+            persist,
+        } => IpcMessage::SetDuration {
+            monitor: unquote(monitor),
+            duration,
+            persist,
+        },
+        SubCmd::SetMode { monitor, mode } => IpcMessage::SetMode {
+            monitor: unquote(monitor),
+            mode: mode
+                .to_possible_value()
+                .expect("no mode is skipped")
+                .get_name()
+                .to_string(),
+        },
+        SubCmd::Apply { file } => {
+            let mut config = String::new();
+            let res = if file == Path::new("-") {
+                std::io::stdin().read_to_string(&mut config).map(|_| ())
+            } else {
+                std::fs::read_to_string(&file).map(|content| config = content)
+            };
+            if let Err(err) = res {
+                eprintln!("unable to read {}: {err}", file.to_string_lossy());
+                std::process::exit(1);
+            }
+            IpcMessage::Apply { config }
+        }
+        SubCmd::ReduceMotion { state } => IpcMessage::ReduceMotion {
+            enabled: state.map(|state| matches!(state, Switch::On)),
+        },
+        SubCmd::HighContrast { state } => IpcMessage::HighContrast {
+            enabled: state.map(|state| matches!(state, Switch::On)),
+        },
+        SubCmd::Zen { state } => IpcMessage::Zen {
+            enabled: state.map(|state| matches!(state, Switch::On)),
+        },
+        SubCmd::Inhibit { name, timeout } => IpcMessage::Inhibit { name, timeout },
+        SubCmd::Uninhibit { name, all } => IpcMessage::Uninhibit {
+            name: (!all).then_some(name),
+        },
+        SubCmd::Backend { renderer, device } => IpcMessage::Backend {
+            renderer: renderer.map(|renderer| {
+                renderer
+                    .to_possible_value()
+                    .expect("no skipped values")
+                    .get_name()
+                    .to_string()
+            }),
+            device,
+        },
+        SubCmd::Animation(command) => {
+            let (monitors, action) = match command {
+                AnimationCmd::Pause { monitors } => (monitors, AnimationAction::Pause),
+                AnimationCmd::Resume { monitors } => (monitors, AnimationAction::Resume),
+                AnimationCmd::Seek { monitor, frame } => {
+                    (vec![monitor], AnimationAction::Seek { frame })
+                }
+            };
+            IpcMessage::Animation {
+                monitors: monitors.into_iter().map(unquote).collect(),
+                action,
+            }
+        }
+        SubCmd::Reset => IpcMessage::Reset,
+        SubCmd::DumpConfig { output } => {
+            match client.request(&IpcMessage::DumpConfig) {
+                Ok(IpcResponse::Config { config }) => match output {
+                    Some(file) => {
+                        if let Err(err) = std::fs::write(&file, config) {
+                            eprintln!("unable to write {}: {err}", file.to_string_lossy());
+                            std::process::exit(1);
+                        }
+                    }
+                    None => print!("{config}"),
+                },
+                Ok(_) => unreachable!(),
+                Err(err) => print_error(err),
+            }
+            return;
+        }
+        SubCmd::ListOutputs { json } => {
+            json_resp = json;
+            IpcMessage::ListOutputs
+        }
+        SubCmd::PrintMatches => IpcMessage::PrintMatches,
+        SubCmd::ConfigErrors => IpcMessage::ConfigErrors,
+        SubCmd::ConfigPath => IpcMessage::ConfigPath,
+        SubCmd::Stats { json } => {
+            json_resp = json;
+            IpcMessage::Stats
+        }
+        SubCmd::Status { json } => {
+            json_resp = json;
+            IpcMessage::Status
+        }
+        SubCmd::Crop {
+            monitor,
+            region,
+            clear: _,
+        } => IpcMessage::Crop {
+            monitor: unquote(monitor),
+            region: region.map(|region| CropRegion {
+                x: region.x,
+                y: region.y,
+                width: region.width,
+                height: region.height,
+            }),
+        },
+        SubCmd::Menu {
+            monitor,
+            launcher,
+            icons,
+        } => {
+            if let Err(err) = menu::run(&client, unquote(monitor), launcher, icons) {
+                print_error(err);
+            }
+            return;
+        }
+        SubCmd::Trace {
+            monitors,
+            follow,
+            json,
+        } => {
+            let monitors = monitors.into_iter().map(unquote).collect();
+            if let Err(err) = trace(&client, monitors, follow, json) {
+                print_error(err);
+            }
+            return;
+        }
+        SubCmd::Tui { monitor } => {
+            if let Err(err) = tui::run(&client, unquote(monitor)) {
+                print_error(err);
+            }
+            return;
+        }
+        SubCmd::Thumbnails { monitor, size } => {
+            match client.thumbnails(&unquote(monitor), size) {
+                Ok(entries) => {
+                    for (image, thumbnail) in entries {
+                        println!(
+                            "{}\t{}",
+                            image.to_string_lossy(),
+                            thumbnail.to_string_lossy()
+                        );
+                    }
+                }
+                Err(err) => print_error(err),
+            }
+            return;
+        }
+        SubCmd::PreviewStrip {
+            monitor,
+            count,
+            size,
+        } => IpcMessage::PreviewStrip {
+            monitor: unquote(monitor),
+            count: count as usize,
+            size,
+        },
+        SubCmd::Screenshot { monitor, path } => {
+            let monitor = unquote(monitor);
+            let path = path.unwrap_or_else(|| PathBuf::from(format!("wpaperd-{monitor}.png")));
+            // Saved by wpaperd, which has another working directory
+            let path = std::env::current_dir()
+                .map(|dir| dir.join(&path))
+                .unwrap_or(path);
+            IpcMessage::Screenshot { monitor, path }
+        }
+        SubCmd::CopyPath { output, image } => IpcMessage::CopyPath {
+            monitor: output.map(unquote),
+            image,
+        },
+        SubCmd::TextContrast { monitor, json } => {
+            json_resp = json;
+            IpcMessage::TextContrast {
+                monitor: unquote(monitor),
+            }
+        }
+        SubCmd::Capabilities => IpcMessage::Handshake {
+            version: PROTOCOL_VERSION,
+        },
+        SubCmd::Completions { shell } => {
+            let mut command = Opts::command();
+            clap_complete::generate(shell, &mut command, "wpaperctl", &mut std::io::stdout());
+            return;
+        }
+        SubCmd::GenMan { dir } => {
+            if let Err(err) = gen_man(dir.as_deref()) {
+                eprintln!("Could not write the man page: {err}");
+                std::process::exit(1);
+            }
+            return;
+        }
     };
-    conn.write_all(&serde_json::to_vec(&msg).unwrap()).unwrap();
-    let mut buf = String::new();
-    conn.read_to_string(&mut buf).unwrap();
-    let res: Result<IpcResponse, IpcError> =
-        serde_json::from_str(&buf).expect("wpaperd to return a valid json");
-    match res {
+    match client.request(&msg) {
         Ok(resp) => match resp {
             IpcResponse::CurrentWallpaper { path } => println!("{}", path.to_string_lossy()),
             IpcResponse::AllWallpapers { entries: paths } => {
@@ -86,17 +469,335 @@ fn main() {
                     }
                 }
             }
-            IpcResponse::Ok => (),
-        },
-        Err(err) => match err {
-            IpcError::MonitorNotFound { monitor } => {
-                eprintln!("monitor {monitor} could not be found")
+            IpcResponse::Queue { queued, upcoming } => {
+                for path in queued.iter().chain(upcoming.iter()) {
+                    println!("{}", path.to_string_lossy());
+                }
+            }
+            IpcResponse::Outputs { outputs } => {
+                if json_resp {
+                    println!(
+                        "{}",
+                        serde_json::to_string(&outputs).expect("json encoding to work")
+                    );
+                } else {
+                    for output in outputs {
+                        println!("{}: {}", output.name, output.description);
+                        println!("  resolution: {}x{}", output.width, output.height);
+                        println!("  scale: {}", output.scale);
+                        println!("  transform: {}", output.transform);
+                        println!("  section: {}", output.section.as_deref().unwrap_or("none"));
+                        println!(
+                            "  wallpaper: {}",
+                            output
+                                .wallpaper
+                                .map(|path| path.to_string_lossy().to_string())
+                                .unwrap_or_else(|| "none".to_string())
+                        );
+                    }
+                }
             }
-            IpcError::DrawErrors(errors) => {
-                for (monitor, err) in errors {
-                    eprintln!("Wallpaper could not be drawn for monitor {monitor}: {err}")
+            IpcResponse::Matches {
+                matches,
+                unmatched_sections,
+            } => {
+                for output_match in matches {
+                    let sections = if output_match.sections.is_empty() {
+                        "no section".to_string()
+                    } else {
+                        output_match.sections.join(" -> ")
+                    };
+                    println!("{}: {sections}", output_match.output);
+                    if output_match.runtime_changes {
+                        println!("  changed at runtime with wpaperctl");
+                    }
+                    if let Some(error) = output_match.error {
+                        println!("  error: {error}");
+                    }
+                }
+                for section in unmatched_sections {
+                    println!("section {section} is not used by any output");
+                }
+            }
+            IpcResponse::Handshake {
+                version,
+                capabilities,
+            } => {
+                println!("protocol version {version}");
+                for capability in capabilities {
+                    println!("{capability}");
+                }
+            }
+            IpcResponse::ReduceMotion { enabled } => {
+                println!("reduce motion: {}", if enabled { "on" } else { "off" })
+            }
+            IpcResponse::HighContrast { enabled } => {
+                println!("high contrast: {}", if enabled { "on" } else { "off" })
+            }
+            IpcResponse::Zen { enabled } => {
+                println!("zen: {}", if enabled { "on" } else { "off" })
+            }
+            IpcResponse::Backend {
+                renderer,
+                device,
+                driver,
+            } => {
+                match device {
+                    Some(device) => println!("backend: {renderer} ({device})"),
+                    None => println!("backend: {renderer}"),
+                }
+                if let Some(driver) = driver {
+                    println!("driver: {driver}");
                 }
             }
+            IpcResponse::Inhibitors { inhibitors } => {
+                for inhibitor in inhibitors {
+                    match inhibitor.expires_in {
+                        Some(expires_in) => println!(
+                            "{} (expires in {})",
+                            inhibitor.name,
+                            humantime::format_duration(Duration::from_secs(expires_in.as_secs()))
+                        ),
+                        None => println!("{}", inhibitor.name),
+                    }
+                }
+            }
+            IpcResponse::ConfigErrors { errors } => {
+                for error in &errors {
+                    println!("{error}\n");
+                }
+                if !errors.is_empty() {
+                    std::process::exit(1);
+                }
+            }
+            IpcResponse::ConfigPath {
+                path,
+                exists,
+                system_path,
+                defaults_file,
+            } => {
+                if exists {
+                    println!("{}", path.to_string_lossy());
+                } else {
+                    println!("{} (not created yet)", path.to_string_lossy());
+                }
+                if let Some(system_path) = system_path {
+                    println!("{} (system)", system_path.to_string_lossy());
+                }
+                if let Some(defaults_file) = defaults_file {
+                    println!("{} (defaults)", defaults_file.to_string_lossy());
+                }
+            }
+            // Handled with the command
+            IpcResponse::Config { .. } => unreachable!(),
+            IpcResponse::Trace { .. } => unreachable!(),
+            IpcResponse::Images { .. } => unreachable!(),
+            IpcResponse::Thumbnails { .. } => unreachable!(),
+            IpcResponse::PreviewStrip { path, .. } => println!("{}", path.to_string_lossy()),
+            IpcResponse::Screenshot { path, .. } => println!("{}", path.to_string_lossy()),
+            IpcResponse::Stats(stats) => {
+                if json_resp {
+                    println!(
+                        "{}",
+                        serde_json::to_string(&stats).expect("json encoding to work")
+                    );
+                } else {
+                    println!("uptime: {}s", stats.uptime_secs);
+                    println!("wallpapers shown: {}", stats.rotations);
+                    let decoded: u64 = stats.decode_times.iter().map(|bucket| bucket.count).sum();
+                    println!("images decoded: {decoded}");
+                    if let Some(average) = stats.decode_time_total_ms.checked_div(decoded) {
+                        println!("  average time: {average}ms");
+                        for bucket in stats.decode_times {
+                            match bucket.max_ms {
+                                Some(max_ms) => println!("  up to {max_ms}ms: {}", bucket.count),
+                                None => println!("  longer: {}", bucket.count),
+                            }
+                        }
+                    }
+                    let requests = stats.cache_hits + stats.cache_misses;
+                    if requests > 0 {
+                        println!(
+                            "cache hit rate: {:.1}%",
+                            stats.cache_hits as f64 * 100.0 / requests as f64
+                        );
+                    }
+                    if let Some(memory) = stats.memory_bytes {
+                        println!("memory: {:.1} MiB", memory as f64 / (1024.0 * 1024.0));
+                    }
+                    println!("errors: {}", stats.errors);
+                }
+            }
+            IpcResponse::Animations { animations } => {
+                for animation in animations {
+                    let state = if animation.paused {
+                        "paused"
+                    } else {
+                        "playing"
+                    };
+                    if animation.frames == 0 {
+                        println!("{}: decoding the frames, {state}", animation.monitor);
+                    } else {
+                        println!(
+                            "{}: frame {} of 0-{}, {state}",
+                            animation.monitor,
+                            animation.frame,
+                            animation.frames - 1
+                        );
+                    }
+                }
+            }
+            IpcResponse::Status { outputs } => {
+                if json_resp {
+                    println!(
+                        "{}",
+                        serde_json::to_string(&outputs).expect("json encoding to work")
+                    );
+                } else {
+                    let now = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_secs();
+                    for output in outputs {
+                        println!("{}:", output.name);
+                        println!(
+                            "  wallpaper: {}",
+                            output
+                                .wallpaper
+                                .map(|path| path.to_string_lossy().to_string())
+                                .unwrap_or_else(|| "none".to_string())
+                        );
+                        if let Some(metadata) = output.metadata {
+                            let mut details = Vec::new();
+                            if let (Some(width), Some(height)) = (metadata.width, metadata.height) {
+                                details.push(format!("{width}x{height}"));
+                            }
+                            details.extend(metadata.format);
+                            details.push(format!(
+                                "{:.1} MiB",
+                                metadata.size as f64 / (1024.0 * 1024.0)
+                            ));
+                            details.extend(metadata.camera);
+                            details.extend(metadata.taken);
+                            println!("  image: {}", details.join(", "));
+                        }
+                        if output.paused {
+                            println!("  paused");
+                        } else if let Some(next_change) = output.next_change {
+                            println!(
+                                "  next change in {}",
+                                humantime::format_duration(Duration::from_secs(
+                                    next_change.saturating_sub(now)
+                                ))
+                            );
+                        }
+                        if let Some(error) = output.error {
+                            println!("  error: {error}");
+                        }
+                    }
+                }
+            }
+            IpcResponse::TextContrast(contrast) => {
+                if json_resp {
+                    println!(
+                        "{}",
+                        serde_json::to_string(&contrast).expect("json encoding to work")
+                    );
+                } else {
+                    println!("foreground: {}", contrast.foreground);
+                    println!("luminance: {:.3}", contrast.luminance);
+                    println!("light text contrast: {:.1}:1", contrast.light_contrast);
+                    println!("dark text contrast: {:.1}:1", contrast.dark_contrast);
+                    println!("wallpaper: {}", contrast.wallpaper.to_string_lossy());
+                }
+            }
+            IpcResponse::Ok => (),
         },
+        Err(err) => print_error(err),
+    }
+}
+
+/// Print the man page built from the same definitions of the arguments, for `wpaperctl gen-man`
+fn gen_man(dir: Option<&Path>) -> std::io::Result<()> {
+    match dir {
+        Some(dir) => clap_mangen::generate_to(Opts::command(), dir),
+        None => clap_mangen::Man::new(Opts::command()).render(&mut std::io::stdout()),
+    }
+}
+
+fn print_error(err: IpcError) {
+    match err {
+        IpcError::MonitorNotFound { monitor } => {
+            eprintln!("monitor {monitor} could not be found")
+        }
+        IpcError::DrawErrors(errors) => {
+            for (monitor, err) in errors {
+                eprintln!("Wallpaper could not be drawn for monitor {monitor}: {err}")
+            }
+        }
+        IpcError::InvalidPath { path } => {
+            eprintln!("{} is not an image file", path.to_string_lossy())
+        }
+        IpcError::NotQueued { path } => {
+            eprintln!("{} is not in the queue", path.to_string_lossy())
+        }
+        IpcError::InvalidConfig { error } => eprintln!("Invalid configuration: {error}"),
+        IpcError::NoWallpaper { monitor } => {
+            eprintln!("monitor {monitor} is not showing an image")
+        }
+        IpcError::SidecarNotSaved { path, error } => {
+            eprintln!(
+                "the sidecar file of {} could not be saved: {error}",
+                path.to_string_lossy()
+            )
+        }
+        IpcError::InvalidMessage { error } => {
+            eprintln!("wpaperd could not read the message: {error}")
+        }
+        IpcError::Unauthorized => {
+            eprintln!("the remote wpaperd has refused the token")
+        }
+        IpcError::LocalOnly => {
+            eprintln!("this command can only be sent to the wpaperd running on this machine")
+        }
+        IpcError::NoTextRegion { monitor } => {
+            eprintln!("monitor {monitor} doesn't have text-region set")
+        }
+        IpcError::InhibitorNotFound { name } => {
+            eprintln!("inhibitor {name} could not be found")
+        }
+        IpcError::NoAnimation { monitor } => {
+            eprintln!("monitor {monitor} is not showing an animated image")
+        }
+        IpcError::NoUpcomingImages { monitor } => {
+            eprintln!("monitor {monitor} has no images queued or upcoming")
+        }
+        IpcError::ScreenshotFailed { monitor, error } => {
+            eprintln!("the wallpaper of monitor {monitor} could not be saved: {error}")
+        }
+        IpcError::PreviewStripNotSaved { path, error } => {
+            eprintln!(
+                "the preview strip {} could not be saved: {error}",
+                path.to_string_lossy()
+            )
+        }
+        IpcError::FrameNotFound { monitor, frames: 0 } => {
+            eprintln!("the frames of the animation on monitor {monitor} are still being decoded")
+        }
+        IpcError::FrameNotFound { monitor, frames } => {
+            eprintln!(
+                "the animation on monitor {monitor} has frames from 0 to {}",
+                frames - 1
+            )
+        }
+        IpcError::ClipboardUnavailable { error } => {
+            eprintln!("the wallpaper could not be copied to the clipboard: {error}")
+        }
+        IpcError::VersionMismatch { daemon, client } => {
+            eprintln!(
+                "wpaperd implements protocol version {daemon} and wpaperctl version {client}, \
+                 install the same version of both and restart wpaperd"
+            )
+        }
     }
 }
@@ -0,0 +1,140 @@
+//! `wpaperctl menu`, turning a dmenu-like launcher into a wallpaper picker.
+
+use std::{
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use wpaperd_ipc::{IpcError, IpcMessage};
+
+use crate::Client;
+
+/// Longest side of the thumbnails shown with `--icons`
+const ICON_SIZE: u32 = 256;
+
+/// List the images of `monitor`, let the user choose one and show it
+pub fn run(
+    client: &Client,
+    monitor: String,
+    launcher: Option<String>,
+    icons: bool,
+) -> Result<(), IpcError> {
+    let entries: Vec<(PathBuf, Option<PathBuf>)> = if icons {
+        client
+            .thumbnails(&monitor, ICON_SIZE)?
+            .into_iter()
+            .map(|(image, thumbnail)| (image, Some(thumbnail)))
+            .collect()
+    } else {
+        client
+            .images(&monitor)?
+            .into_iter()
+            .map(|path| (path, None))
+            .collect()
+    };
+
+    // Show the paths relative to the directory containing all the images
+    let prefix = common_dir(entries.iter().map(|(image, _)| image.as_path()));
+    let names: Vec<String> = entries
+        .iter()
+        .map(|(image, _)| {
+            image
+                .strip_prefix(&prefix)
+                .unwrap_or(image)
+                .to_string_lossy()
+                .to_string()
+        })
+        .collect();
+    let mut list = String::new();
+    for (name, (_, thumbnail)) in names.iter().zip(&entries) {
+        list.push_str(name);
+        if let Some(thumbnail) = thumbnail {
+            list.push_str(&format!("\0icon\x1f{}", thumbnail.to_string_lossy()));
+        }
+        list.push('\n');
+    }
+
+    let selection = match launcher {
+        Some(launcher) => run_launcher(&launcher, &list),
+        None => read_selection(&list),
+    }
+    .unwrap_or_else(|err| {
+        eprintln!("the image could not be chosen: {err}");
+        std::process::exit(1);
+    });
+    let selection = selection.trim_end_matches(['\n', '\r']);
+    if selection.is_empty() {
+        // Nothing has been chosen
+        return Ok(());
+    }
+    let path = names
+        .iter()
+        .position(|name| name == selection)
+        .map(|index| entries[index].0.clone())
+        .unwrap_or_else(|| PathBuf::from(selection));
+
+    show(client, &monitor, path)
+}
+
+/// Show `path` on `monitor` right away
+pub fn show(client: &Client, monitor: &str, path: PathBuf) -> Result<(), IpcError> {
+    client.request(&IpcMessage::QueueNext {
+        monitor: monitor.to_string(),
+        path,
+    })?;
+    client.request(&IpcMessage::NextWallpaper {
+        monitors: vec![monitor.to_string()],
+    })?;
+    Ok(())
+}
+
+/// Print the list and read the line chosen from stdin
+fn read_selection(list: &str) -> io::Result<String> {
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(list.as_bytes())?;
+    stdout.flush()?;
+    let mut selection = String::new();
+    io::stdin().lock().read_line(&mut selection)?;
+    Ok(selection)
+}
+
+/// Pass the list to the launcher and return the line it prints, empty when it is cancelled
+fn run_launcher(launcher: &str, list: &str) -> io::Result<String> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(launcher)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    child
+        .stdin
+        .take()
+        .expect("the stdin of the launcher to be piped")
+        .write_all(list.as_bytes())?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Ok(String::new());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .to_string())
+}
+
+/// The deepest directory containing all the paths
+pub fn common_dir<'a>(mut paths: impl Iterator<Item = &'a Path>) -> PathBuf {
+    let Some(first) = paths.next() else {
+        return PathBuf::new();
+    };
+    let mut prefix = first.parent().unwrap_or(first).to_path_buf();
+    for path in paths {
+        while !path.starts_with(&prefix) {
+            if !prefix.pop() {
+                return PathBuf::new();
+            }
+        }
+    }
+    prefix
+}
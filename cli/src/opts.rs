@@ -1,12 +1,56 @@
+use std::{path::PathBuf, time::Duration};
+
 use clap::Parser;
+use wpaperd_ipc::BackgroundMode;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 pub struct Opts {
+    /// Send the command to the wpaperd running on this seat, the current one by default
+    #[clap(long, global = true)]
+    pub seat: Option<String>,
+    /// Send the command to a wpaperd listening on this address, <host>:<port> or
+    /// @<abstract socket name>
+    #[clap(long, global = true, requires = "token_file", conflicts_with = "seat")]
+    pub remote: Option<String>,
+    /// File containing the token expected by the remote wpaperd
+    #[clap(long, global = true, requires = "remote")]
+    pub token_file: Option<PathBuf>,
     #[clap(subcommand)]
     pub subcmd: SubCmd,
 }
 
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum Switch {
+    On,
+    Off,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum Renderer {
+    Gpu,
+    Software,
+}
+
+/// A region of an image, in pixels
+#[derive(Clone, Copy)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(clap::Subcommand)]
+pub enum AnimationCmd {
+    /// Stay on the frame shown
+    Pause { monitors: Vec<String> },
+    /// Play the animations again
+    Resume { monitors: Vec<String> },
+    /// Show a frame of the animation, the first one is 0. A paused animation stays on it
+    Seek { monitor: String, frame: usize },
+}
+
 #[derive(clap::Subcommand)]
 pub enum SubCmd {
     #[clap(visible_alias = "get")]
@@ -20,12 +64,258 @@ pub enum SubCmd {
     NextWallpaper { monitors: Vec<String> },
     #[clap(visible_alias = "previous")]
     PreviousWallpaper { monitors: Vec<String> },
+    /// Rescan the directories of the displays and load their wallpapers again from the disk
     #[clap(visible_alias = "reload")]
-    ReloadWallpaper { monitors: Vec<String> },
+    ReloadWallpaper {
+        monitors: Vec<String>,
+        /// Another display to reload, like the ones passed without the option
+        #[clap(long = "output", value_name = "OUTPUT")]
+        outputs: Vec<String>,
+        /// Only rescan the directories, the wallpapers shown stay the same
+        #[clap(long, conflicts_with = "config_only")]
+        rescan_dirs: bool,
+        /// Only read the configuration files again, for all the displays
+        #[clap(long, conflicts_with_all = ["monitors", "outputs"])]
+        config_only: bool,
+    },
     #[clap(visible_alias = "pause")]
     PauseWallpaper { monitors: Vec<String> },
     #[clap(visible_alias = "resume")]
     ResumeWallpaper { monitors: Vec<String> },
     #[clap(visible_alias = "toggle-pause")]
     TogglePauseWallpaper { monitors: Vec<String> },
+    /// Keep the current wallpaper for a while (e.g. "30m"), then resume changing it
+    #[clap(visible_alias = "hold")]
+    HoldWallpaper {
+        #[clap(value_parser = humantime::parse_duration)]
+        duration: Duration,
+        monitors: Vec<String>,
+    },
+    /// Print the images that will be shown next, starting with the queued ones
+    Queue { monitor: String },
+    /// Show this image next, before the other queued images
+    QueueNext { monitor: String, path: PathBuf },
+    /// Remove an image added with queue-next
+    QueueRemove { monitor: String, path: PathBuf },
+    /// Show this image now, the images shown next stay the same
+    ShowOnce {
+        monitor: String,
+        path: PathBuf,
+        /// Go back to the previous image after this time (e.g. "5m"), pausing the wallpaper
+        /// meanwhile. Otherwise the image is shown until the wallpaper changes
+        #[clap(long = "for", value_parser = humantime::parse_duration)]
+        duration: Option<Duration>,
+    },
+    /// Change how often the wallpaper is changed, without editing the configuration file
+    SetDuration {
+        monitor: String,
+        #[clap(value_parser = humantime::parse_duration)]
+        duration: Duration,
+        /// Keep the new duration when wpaperd is restarted
+        #[clap(short, long)]
+        persist: bool,
+    },
+    /// Change how the wallpaper is displayed, without editing the configuration file
+    SetMode {
+        monitor: String,
+        #[clap(value_enum)]
+        mode: BackgroundMode,
+    },
+    /// Apply a configuration fragment over the running one, until wpaperd is restarted or reset
+    Apply {
+        /// TOML file with the same format of the configuration, `-` to read it from stdin
+        file: PathBuf,
+    },
+    /// Disable the transitions on all the displays, until wpaperd is restarted or reset.
+    /// Toggle it when no value is passed
+    ReduceMotion {
+        #[clap(value_enum)]
+        state: Option<Switch>,
+    },
+    /// Show a solid color in place of the wallpapers, until wpaperd is restarted or reset.
+    /// Toggle it when no value is passed
+    HighContrast {
+        #[clap(value_enum)]
+        state: Option<Switch>,
+    },
+    /// Show a neutral color or image on all the displays and stop changing the wallpapers, until
+    /// it is turned off. Toggle it when no value is passed
+    Zen {
+        #[clap(value_enum)]
+        state: Option<Switch>,
+    },
+    /// Stop changing the wallpapers on all the displays, e.g. while a game is running or the
+    /// screen is being recorded, until all the inhibitors are removed. Print the inhibitors left
+    Inhibit {
+        /// Running inhibit again with the same name replaces its timeout
+        #[clap(default_value = "default")]
+        name: String,
+        /// Remove the inhibitor after this time, e.g. 2h
+        #[clap(long, value_parser = humantime::parse_duration)]
+        timeout: Option<Duration>,
+    },
+    /// Remove an inhibitor added with inhibit
+    Uninhibit {
+        #[clap(default_value = "default", conflicts_with = "all")]
+        name: String,
+        /// Remove all the inhibitors
+        #[clap(long)]
+        all: bool,
+    },
+    /// Switch the backend drawing the wallpapers, until wpaperd is restarted or reset. Print the
+    /// backend used and its driver
+    Backend {
+        /// Only print the backend used when not passed
+        #[clap(value_enum)]
+        renderer: Option<Renderer>,
+        /// The GPU used on the systems with more GPUs, by its index or its PCI tag, e.g.
+        /// pci-0000_03_00_0. The default one when not passed
+        #[clap(long, requires = "renderer")]
+        device: Option<String>,
+    },
+    /// Pause or resume the animated images shown, or show one of their frames
+    #[clap(visible_alias = "anim", subcommand)]
+    Animation(AnimationCmd),
+    /// Drop all the changes made with apply, set-duration, set-mode, reduce-motion,
+    /// high-contrast and backend
+    Reset,
+    /// Print the configuration file with the changes made with apply, set-duration, set-mode,
+    /// reduce-motion, high-contrast and backend applied over it
+    DumpConfig {
+        /// Write it to this file instead, e.g. the configuration file to keep the changes
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Print the outputs with their geometry, configuration section and wallpaper
+    ListOutputs {
+        #[clap(short, long)]
+        json: bool,
+    },
+    /// Print which sections of the configuration are used by each output
+    PrintMatches,
+    /// Print the errors found when the configuration file was last read, exit with 1 if any
+    ConfigErrors,
+    /// Print the path of the configuration file used by wpaperd
+    ConfigPath,
+    /// Print the wallpapers shown, the time taken to decode the images, the memory used and
+    /// the errors since wpaperd started
+    Stats {
+        #[clap(short, long)]
+        json: bool,
+    },
+    /// Print the wallpaper of each monitor, whether it is paused, when it changes and the error
+    /// preventing it from showing a wallpaper, if any
+    Status {
+        #[clap(short, long)]
+        json: bool,
+    },
+    /// Print each stage of the rotation of the wallpapers: the timers, the pauses, the images
+    /// picked, decoded, scaled and committed, with their times. The first call starts recording
+    /// them, unless wpaperd has been started with --trace
+    Trace {
+        monitors: Vec<String>,
+        /// Keep printing the new events
+        #[clap(short, long)]
+        follow: bool,
+        /// Print each event as a JSON object on its own line
+        #[clap(short, long)]
+        json: bool,
+    },
+    /// Only show a region of the image displayed, saved into the sidecar file of the image
+    Crop {
+        monitor: String,
+        /// The region in pixels, as x,y,width,height
+        #[clap(value_parser = parse_region, required_unless_present = "clear")]
+        region: Option<Region>,
+        /// Show the whole image again
+        #[clap(long, conflicts_with = "region")]
+        clear: bool,
+    },
+    /// Print the images the output picks from, each followed by a tab and its thumbnail, for the
+    /// image pickers. The thumbnails are created and cached by wpaperd
+    Thumbnails {
+        monitor: String,
+        /// Longest side of the thumbnails, in pixels
+        #[clap(long, default_value = "256", value_parser = clap::value_parser!(u32).range(1..))]
+        size: u32,
+    },
+    /// Save the thumbnails of the next images of the output, the queued ones first, side by side
+    /// in a single PNG and print its path, for the widgets previewing the upcoming wallpapers
+    PreviewStrip {
+        monitor: String,
+        /// How many images are in the strip, at most
+        #[clap(long, default_value = "5", value_parser = clap::value_parser!(u64).range(1..))]
+        count: u64,
+        /// Side of the square each thumbnail is centered in, in pixels
+        #[clap(long, default_value = "256", value_parser = clap::value_parser!(u32).range(1..))]
+        size: u32,
+    },
+    /// Save the wallpaper drawn on the output into a PNG, exactly as wpaperd renders it, after
+    /// the mode, the filters and the transitions
+    Screenshot {
+        monitor: String,
+        /// Where the PNG is saved, wpaperd-<output>.png in the current directory by default
+        path: Option<PathBuf>,
+    },
+    /// Put the path of the wallpaper shown on the clipboard, and print it
+    CopyPath {
+        /// The output whose wallpaper is copied, the first one by default
+        #[clap(long)]
+        output: Option<String>,
+        /// Copy the image too, for the programs accepting images
+        #[clap(long)]
+        image: bool,
+    },
+    /// Choose the image shown by the output from a menu: the images are listed one per line, in
+    /// the dmenu format, and the line selected is shown immediately
+    Menu {
+        monitor: String,
+        /// Run this dmenu-like command to choose the image (e.g. "rofi -dmenu" or
+        /// "fuzzel --dmenu"), otherwise the list is printed and the selection is read from stdin
+        #[clap(long)]
+        launcher: Option<String>,
+        /// Add the thumbnails as icons, in the format supported by rofi and fuzzel
+        #[clap(long)]
+        icons: bool,
+    },
+    /// Browse the images of a monitor in the terminal, with their thumbnails, and choose one
+    Tui { monitor: String },
+    /// Print whether light or dark text is readable over the region of the wallpaper set in
+    /// text-region, along with the contrast ratios
+    TextContrast {
+        monitor: String,
+        #[clap(short, long)]
+        json: bool,
+    },
+    /// Print the IPC protocol version and the messages supported by wpaperd
+    Capabilities,
+    /// Print the completions of wpaperctl for the shell
+    Completions {
+        #[clap(value_enum)]
+        shell: clap_complete::Shell,
+    },
+    /// Print the man page of wpaperctl
+    GenMan {
+        /// Write a man page for wpaperctl and one for each subcommand into this directory instead
+        #[clap(long)]
+        dir: Option<PathBuf>,
+    },
+}
+
+fn parse_region(region: &str) -> Result<Region, String> {
+    let values = region
+        .split(',')
+        .map(|value| value.trim().parse::<u32>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| err.to_string())?;
+    match values[..] {
+        [x, y, width, height] if width > 0 && height > 0 => Ok(Region {
+            x,
+            y,
+            width,
+            height,
+        }),
+        [_, _, _, _] => Err("the width and the height must be greater than 0".to_string()),
+        _ => Err("expected x,y,width,height".to_string()),
+    }
 }
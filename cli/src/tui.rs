@@ -0,0 +1,546 @@
+//! `wpaperctl tui`, browse the images of an output in the terminal.
+//!
+//! The terminal is driven with plain ANSI escape sequences, put in raw mode with termios. The
+//! screen is only drawn again when a key is pressed or the terminal is resized. The thumbnails
+//! are shown using the kitty graphics protocol or as sixel, in the terminals supporting them;
+//! the wallpaper is only changed once an image is chosen.
+
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    fs::File,
+    io::{self, Write},
+    mem,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use wpaperd_ipc::{IpcError, IpcMessage, IpcResponse};
+
+use crate::{
+    menu::{common_dir, show},
+    Client,
+};
+
+/// Longest side of the thumbnails shown next to the list
+const PREVIEW_SIZE: u32 = 512;
+/// How long the terminal has to answer the query of its features, in milliseconds
+const QUERY_TIMEOUT: i32 = 200;
+/// Size of the cells when the terminal doesn't tell it, in pixels
+const DEFAULT_CELL_SIZE: (usize, usize) = (10, 20);
+
+/// Set by the handler of SIGWINCH
+static RESIZED: AtomicBool = AtomicBool::new(false);
+
+struct Entry {
+    name: String,
+    path: PathBuf,
+    thumbnail: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Graphics {
+    Kitty,
+    Sixel,
+}
+
+/// Restore the terminal when dropped, also on errors
+struct RawTerminal {
+    saved: libc::termios,
+}
+
+impl RawTerminal {
+    fn new() -> io::Result<Self> {
+        let mut saved = unsafe { mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut saved) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut raw = saved;
+        unsafe { libc::cfmakeraw(&mut raw) };
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        extern "C" fn on_resize(_: libc::c_int) {
+            RESIZED.store(true, Ordering::Relaxed);
+        }
+        unsafe {
+            libc::signal(
+                libc::SIGWINCH,
+                on_resize as extern "C" fn(libc::c_int) as libc::sighandler_t,
+            );
+        }
+        // Switch to the alternate screen and hide the cursor
+        print!("\x1b[?1049h\x1b[?25l");
+        io::stdout().flush()?;
+        Ok(Self { saved })
+    }
+
+    /// Rows, columns and the size of a cell in pixels
+    fn size(&self) -> (usize, usize, (usize, usize)) {
+        let mut size: libc::winsize = unsafe { mem::zeroed() };
+        if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } != 0
+            || size.ws_row == 0
+            || size.ws_col == 0
+        {
+            return (24, 80, DEFAULT_CELL_SIZE);
+        }
+        let (rows, cols) = (size.ws_row as usize, size.ws_col as usize);
+        let cell = if size.ws_xpixel == 0 || size.ws_ypixel == 0 {
+            DEFAULT_CELL_SIZE
+        } else {
+            (
+                (size.ws_xpixel as usize / cols).max(1),
+                (size.ws_ypixel as usize / rows).max(1),
+            )
+        };
+        (rows, cols, cell)
+    }
+
+    /// Wait for the input, `timeout` in milliseconds or forever when negative. Return nothing
+    /// when the timeout expires or the terminal is resized
+    fn read(&self, timeout: i32) -> io::Result<Vec<u8>> {
+        let mut poll = libc::pollfd {
+            fd: libc::STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        match unsafe { libc::poll(&mut poll, 1, timeout) } {
+            0 => return Ok(Vec::new()),
+            -1 => {
+                let err = io::Error::last_os_error();
+                return match err.kind() {
+                    io::ErrorKind::Interrupted => Ok(Vec::new()),
+                    _ => Err(err),
+                };
+            }
+            _ => {}
+        }
+        let mut input = vec![0; 64];
+        let read = unsafe { libc::read(libc::STDIN_FILENO, input.as_mut_ptr().cast(), 64) };
+        if read <= 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the terminal has been closed",
+            ));
+        }
+        input.truncate(read as usize);
+        Ok(input)
+    }
+
+    /// The graphics supported by the terminal: kitty is found from the environment, sixel by
+    /// asking the terminal for its features
+    fn graphics(&self) -> Option<Graphics> {
+        if env::var_os("KITTY_WINDOW_ID").is_some()
+            || env::var("TERM").is_ok_and(|term| term.contains("kitty"))
+            || env::var("TERM_PROGRAM")
+                .is_ok_and(|program| ["WezTerm", "ghostty"].contains(&&*program))
+        {
+            return Some(Graphics::Kitty);
+        }
+        // Primary device attributes, e.g. `\x1b[?62;4;22c`: 4 is sixel
+        print!("\x1b[c");
+        io::stdout().flush().ok()?;
+        let mut answer = Vec::new();
+        while !answer.ends_with(b"c") {
+            let input = self.read(QUERY_TIMEOUT).ok()?;
+            if input.is_empty() {
+                return None;
+            }
+            answer.extend(input);
+        }
+        let answer = String::from_utf8_lossy(&answer);
+        let attributes = answer.trim_start_matches("\x1b[?").trim_end_matches('c');
+        attributes
+            .split(';')
+            .any(|attribute| attribute == "4")
+            .then_some(Graphics::Sixel)
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        print!("\x1b_Ga=d\x1b\\\x1b[?25h\x1b[?1049l");
+        let _ = io::stdout().flush();
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &self.saved);
+        }
+    }
+}
+
+enum Key {
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Enter,
+    Quit,
+}
+
+fn parse_key(input: &[u8]) -> Option<Key> {
+    Some(match input {
+        b"\x1b[A" | b"k" => Key::Up,
+        b"\x1b[B" | b"j" => Key::Down,
+        b"\x1b[5~" => Key::PageUp,
+        b"\x1b[6~" => Key::PageDown,
+        b"\x1b[H" | b"g" => Key::Home,
+        b"\x1b[F" | b"G" => Key::End,
+        b"\r" | b"\n" => Key::Enter,
+        b"\x1b" | b"q" | b"\x03" => Key::Quit,
+        _ => return None,
+    })
+}
+
+/// Browse the images of `monitor`. Enter shows the one selected as wallpaper, Esc or q leaves
+/// the wallpaper as it is
+pub fn run(client: &Client, monitor: String) -> Result<(), IpcError> {
+    let terminal = RawTerminal::new().unwrap_or_else(|err| {
+        eprintln!("the terminal could not be set up: {err}");
+        std::process::exit(1);
+    });
+    let graphics = terminal.graphics();
+    let images: Vec<(PathBuf, Option<PathBuf>)> = if graphics.is_some() {
+        client
+            .thumbnails(&monitor, PREVIEW_SIZE)?
+            .into_iter()
+            .map(|(image, thumbnail)| (image, Some(thumbnail)))
+            .collect()
+    } else {
+        client
+            .images(&monitor)?
+            .into_iter()
+            .map(|path| (path, None))
+            .collect()
+    };
+    if images.is_empty() {
+        drop(terminal);
+        eprintln!("monitor {monitor} has no images to choose from");
+        return Ok(());
+    }
+    let current = match client.request(&IpcMessage::CurrentWallpaper {
+        monitor: monitor.clone(),
+    })? {
+        IpcResponse::CurrentWallpaper { path } => path,
+        _ => unreachable!(),
+    };
+
+    let prefix = common_dir(images.iter().map(|(image, _)| image.as_path()));
+    let entries: Vec<Entry> = images
+        .into_iter()
+        .map(|(path, thumbnail)| Entry {
+            name: path
+                .strip_prefix(&prefix)
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .to_string(),
+            path,
+            thumbnail,
+        })
+        .collect();
+
+    let mut selected = entries
+        .iter()
+        .position(|entry| entry.path == current)
+        .unwrap_or(0);
+    let mut preview = Preview::new(graphics);
+    let mut redraw = true;
+    loop {
+        let (rows, cols, cell) = terminal.size();
+        if RESIZED.swap(false, Ordering::Relaxed) {
+            preview.clear();
+            redraw = true;
+        }
+        if redraw {
+            let screen = Screen {
+                entries: &entries,
+                selected,
+                current: &current,
+                monitor: &monitor,
+                rows,
+                cols,
+                cell,
+            };
+            screen.draw(&mut preview);
+            redraw = false;
+        }
+
+        let input = match terminal.read(-1) {
+            Ok(input) => input,
+            Err(err) => {
+                drop(terminal);
+                eprintln!("reading the terminal: {err}");
+                return Ok(());
+            }
+        };
+        if input.is_empty() {
+            continue;
+        }
+        let page = rows.saturating_sub(3).max(1);
+        let previous = selected;
+        match parse_key(&input) {
+            Some(Key::Up) => selected = selected.saturating_sub(1),
+            Some(Key::Down) => selected = (selected + 1).min(entries.len() - 1),
+            Some(Key::PageUp) => selected = selected.saturating_sub(page),
+            Some(Key::PageDown) => selected = (selected + page).min(entries.len() - 1),
+            Some(Key::Home) => selected = 0,
+            Some(Key::End) => selected = entries.len() - 1,
+            Some(Key::Enter) => {
+                drop(terminal);
+                if current != entries[selected].path {
+                    show(client, &monitor, entries[selected].path.clone())?;
+                }
+                return Ok(());
+            }
+            Some(Key::Quit) => {
+                drop(terminal);
+                return Ok(());
+            }
+            None => {}
+        }
+        redraw = selected != previous;
+    }
+}
+
+/// The thumbnails sent to the terminal, each one is transmitted or encoded once
+struct Preview {
+    graphics: Option<Graphics>,
+    /// The ids of the images transmitted to kitty
+    transmitted: HashSet<usize>,
+    /// The sixel images encoded for the current size of the terminal
+    sixels: HashMap<usize, String>,
+}
+
+impl Preview {
+    fn new(graphics: Option<Graphics>) -> Self {
+        Self {
+            graphics,
+            transmitted: HashSet::new(),
+            sixels: HashMap::new(),
+        }
+    }
+
+    /// Forget the sixel images, after a resize
+    fn clear(&mut self) {
+        self.sixels.clear();
+    }
+
+    /// The escape sequences showing the thumbnail of entry `index` at the cursor, within
+    /// `cols` x `rows` cells
+    fn show(
+        &mut self,
+        index: usize,
+        thumbnail: &Path,
+        cols: usize,
+        rows: usize,
+        cell: (usize, usize),
+    ) -> String {
+        match self.graphics {
+            Some(Graphics::Kitty) => {
+                let id = index + 1;
+                let mut sequence = String::new();
+                if self.transmitted.insert(id) {
+                    // Transmit the thumbnail by path, it is kept by the terminal
+                    sequence.push_str(&format!(
+                        "\x1b_Gf=100,t=f,a=t,q=2,i={id};{}\x1b\\",
+                        base64(thumbnail.to_string_lossy().as_bytes())
+                    ));
+                }
+                // Scale it to the width of the preview column
+                sequence.push_str(&format!("\x1b_Ga=p,q=2,C=1,i={id},c={cols}\x1b\\"));
+                sequence
+            }
+            Some(Graphics::Sixel) => self
+                .sixels
+                .entry(index)
+                .or_insert_with(|| {
+                    sixel_thumbnail(thumbnail, cols * cell.0, rows * cell.1).unwrap_or_default()
+                })
+                .clone(),
+            None => String::new(),
+        }
+    }
+}
+
+struct Screen<'a> {
+    entries: &'a [Entry],
+    selected: usize,
+    current: &'a Path,
+    monitor: &'a str,
+    rows: usize,
+    cols: usize,
+    /// Size of a cell in pixels
+    cell: (usize, usize),
+}
+
+impl Screen<'_> {
+    fn draw(&self, preview: &mut Preview) {
+        let Self {
+            entries,
+            selected,
+            current,
+            monitor,
+            rows,
+            cols,
+            cell,
+        } = *self;
+        let list_width = if preview.graphics.is_some() {
+            cols / 2
+        } else {
+            cols
+        };
+        let list_rows = rows.saturating_sub(2).max(1);
+        // Keep the selection in the middle of the list
+        let first = selected
+            .saturating_sub(list_rows / 2)
+            .min(entries.len().saturating_sub(list_rows));
+        let truncate = |text: &str, width: usize| text.chars().take(width).collect::<String>();
+
+        let mut screen = String::from("\x1b_Ga=d,q=2\x1b\\\x1b[2J\x1b[H");
+        screen.push_str(&format!(
+            "\x1b[1m{}\x1b[0m",
+            truncate(
+                &format!("{monitor}: ↑/↓ browse, Enter show, Esc quit"),
+                cols
+            )
+        ));
+        for (row, (index, entry)) in entries
+            .iter()
+            .enumerate()
+            .skip(first)
+            .take(list_rows)
+            .enumerate()
+        {
+            // Move to the start of the line, the terminal is in raw mode
+            screen.push_str(&format!("\x1b[{};1H", row + 2));
+            let marker = if entry.path == current { '*' } else { ' ' };
+            let line = truncate(&format!("{marker} {}", entry.name), list_width);
+            if index == selected {
+                screen.push_str(&format!("\x1b[7m{line:list_width$}\x1b[0m"));
+            } else {
+                screen.push_str(&line);
+            }
+        }
+        screen.push_str(&format!(
+            "\x1b[{rows};1H{}",
+            truncate(&format!("{}/{}", selected + 1, entries.len()), cols)
+        ));
+        if let Some(thumbnail) = &entries[selected].thumbnail {
+            screen.push_str(&format!("\x1b[2;{}H", list_width + 2));
+            screen.push_str(&preview.show(
+                selected,
+                thumbnail,
+                cols.saturating_sub(list_width + 2).max(1),
+                list_rows,
+                cell,
+            ));
+        }
+        let mut stdout = io::stdout().lock();
+        let _ = stdout.write_all(screen.as_bytes());
+        let _ = stdout.flush();
+    }
+}
+
+/// Decode the PNG thumbnail and encode it as sixel, scaled down to fit `width`x`height` pixels
+fn sixel_thumbnail(path: &Path, width: usize, height: usize) -> Option<String> {
+    let mut decoder = png::Decoder::new(File::open(path).ok()?);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().ok()?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).ok()?;
+    let channels = info.color_type.samples();
+    let (image_width, image_height) = (info.width as usize, info.height as usize);
+    let scale = f64::min(
+        width as f64 / image_width as f64,
+        height as f64 / image_height as f64,
+    )
+    .min(1.0);
+    let scaled_width = ((image_width as f64 * scale) as usize).max(1);
+    let scaled_height = ((image_height as f64 * scale) as usize).max(1);
+    // Nearest neighbour, the thumbnails are small already
+    let mut pixels = Vec::with_capacity(scaled_width * scaled_height * 4);
+    for y in 0..scaled_height {
+        let row = &data[y * image_height / scaled_height * info.line_size..];
+        for x in 0..scaled_width {
+            let pixel = &row[x * image_width / scaled_width * channels..][..channels];
+            pixels.extend_from_slice(&match channels {
+                1 => [pixel[0], pixel[0], pixel[0], 255],
+                2 => [pixel[0], pixel[0], pixel[0], pixel[1]],
+                3 => [pixel[0], pixel[1], pixel[2], 255],
+                _ => [pixel[0], pixel[1], pixel[2], pixel[3]],
+            });
+        }
+    }
+    Some(sixel(&pixels, scaled_width, scaled_height))
+}
+
+/// Encode the RGBA `pixels` as sixel, with the 216 colors of a 6x6x6 cube. The transparent
+/// pixels are left as they are
+fn sixel(pixels: &[u8], width: usize, height: usize) -> String {
+    let level = |channel: u8| (channel as usize * 5 + 127) / 255;
+    let mut sixel = format!("\x1bP0;1q\"1;1;{width};{height}");
+    for color in 0..216 {
+        sixel.push_str(&format!(
+            "#{color};2;{};{};{}",
+            color / 36 * 20,
+            color / 6 % 6 * 20,
+            color % 6 * 20
+        ));
+    }
+    for band in (0..height).step_by(6) {
+        // The bits of each column, for each color used in the band
+        let mut colors: HashMap<usize, Vec<u8>> = HashMap::new();
+        for y in band..(band + 6).min(height) {
+            for x in 0..width {
+                let pixel = &pixels[(y * width + x) * 4..][..4];
+                if pixel[3] < 128 {
+                    continue;
+                }
+                let color = level(pixel[0]) * 36 + level(pixel[1]) * 6 + level(pixel[2]);
+                colors.entry(color).or_insert_with(|| vec![0; width])[x] |= 1 << (y - band);
+            }
+        }
+        for (color, columns) in colors {
+            sixel.push_str(&format!("#{color}"));
+            let mut columns = columns.into_iter().peekable();
+            while let Some(bits) = columns.next() {
+                let mut count = 1;
+                while columns.next_if_eq(&bits).is_some() {
+                    count += 1;
+                }
+                let character = (63 + bits) as char;
+                if count > 3 {
+                    sixel.push_str(&format!("!{count}{character}"));
+                } else {
+                    sixel.extend((0..count).map(|_| character));
+                }
+            }
+            // Back to the start of the band for the next color
+            sixel.push('$');
+        }
+        sixel.push('-');
+    }
+    sixel.push_str("\x1b\\");
+    sixel
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let value = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(value >> (18 - i * 6) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
@@ -0,0 +1,23 @@
+extern crate gl_generator;
+
+use gl_generator::{Api, Fallbacks, Profile, Registry, StructGenerator};
+use std::env;
+use std::fs::File;
+use std::path::Path;
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    let outdir = env::var("OUT_DIR").unwrap();
+    let mut file = File::create(Path::new(&outdir).join("gl_bindings.rs")).unwrap();
+
+    Registry::new(
+        Api::Gles2,
+        (2, 0),
+        Profile::Core,
+        Fallbacks::All,
+        ["GL_EXT_texture_border_clamp", "GL_EXT_sRGB"],
+    )
+    .write_bindings(StructGenerator, &mut file)
+    .unwrap();
+}
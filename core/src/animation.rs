@@ -0,0 +1,287 @@
+//! The animated images, GIF, WebP and APNG, played on the displays with `animate` set.
+//!
+//! The first frame is shown like any other image, meanwhile all the frames are decoded in the
+//! pool of the [crate::image_loader], scaled like the first one and kept in memory until another
+//! image is shown. Each frame lasts its own delay, with the precision of the format: the APNG
+//! delays are fractions of a second, e.g. 1/30, and are not rounded to the milliseconds. Like the
+//! browsers, the delays of 10ms or less last 100ms instead, the GIFs made for them often set 0.
+//! `animation-speed` divides all the delays.
+//!
+//! `wpaperctl animation` pauses them, resumes them or shows a chosen frame; with `start-paused`
+//! set, they stay on their first frame until resumed. The timer of a paused animation is stopped.
+
+use std::{
+    path::Path,
+    sync::{
+        mpsc::{Receiver, TryRecvError},
+        Arc,
+    },
+    time::Duration,
+};
+
+use color_eyre::{eyre::Context, Result};
+use image::{AnimationDecoder, Delay, Frames, ImageDecoder, ImageFormat, RgbaImage};
+
+use crate::image_loader::{image_limits, DecodeLimits, ImageFile};
+
+/// The delays up to this one are replaced by [CLAMPED_DELAY]
+const MIN_DELAY: Duration = Duration::from_millis(10);
+const CLAMPED_DELAY: Duration = Duration::from_millis(100);
+
+/// How often the frames being decoded are checked
+pub const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+pub struct Frame {
+    /// Shared with the surface drawing it, without copying its pixels
+    pub image: Arc<RgbaImage>,
+    /// How long it is shown at normal speed
+    pub delay: Duration,
+}
+
+/// The delay of a frame, clamped like the browsers do
+pub fn frame_delay(delay: Delay) -> Duration {
+    let (numer, denom) = delay.numer_denom_ms();
+    let delay = Duration::from_nanos(numer as u64 * 1_000_000 / denom.max(1) as u64);
+    if delay <= MIN_DELAY {
+        CLAMPED_DELAY
+    } else {
+        delay
+    }
+}
+
+/// True if the image can hold an animation, judging from its extension
+pub fn may_be_animated(path: &Path) -> bool {
+    matches!(
+        ImageFormat::from_path(path),
+        Ok(ImageFormat::Gif | ImageFormat::WebP | ImageFormat::Png)
+    )
+}
+
+/// True if the image has more than one frame. The headers of the APNGs and of the WebPs tell
+/// it, the GIFs are decoded up to their second frame
+pub fn is_animated(path: &Path, limits: &DecodeLimits) -> bool {
+    if !may_be_animated(path) {
+        return false;
+    }
+    match (ImageFormat::from_path(path), decode_frames(path, limits)) {
+        (Ok(ImageFormat::Gif), Ok(Some(frames))) => frames.take(2).count() == 2,
+        (_, Ok(Some(_))) => true,
+        _ => false,
+    }
+}
+
+/// The frames of the animated image, `None` when it is a still one
+pub fn decode_frames(path: &Path, limits: &DecodeLimits) -> Result<Option<Frames<'static>>> {
+    let file = || ImageFile::open(path).with_context(|| format!("opening image {path:?}"));
+    let decoding = || format!("decoding the frames of image {path:?}");
+    match ImageFormat::from_path(path) {
+        #[cfg(feature = "gif")]
+        Ok(ImageFormat::Gif) => {
+            let mut decoder =
+                image::codecs::gif::GifDecoder::new(file()?).with_context(decoding)?;
+            let (width, height) = decoder.dimensions();
+            limits.check(path, width, height)?;
+            decoder
+                .set_limits(image_limits(limits))
+                .with_context(decoding)?;
+            Ok(Some(decoder.into_frames()))
+        }
+        #[cfg(feature = "webp")]
+        Ok(ImageFormat::WebP) => {
+            let decoder = image::codecs::webp::WebPDecoder::new(file()?).with_context(decoding)?;
+            if !decoder.has_animation() {
+                return Ok(None);
+            }
+            let (width, height) = decoder.dimensions();
+            limits.check(path, width, height)?;
+            Ok(Some(decoder.into_frames()))
+        }
+        Ok(ImageFormat::Png) => {
+            let decoder =
+                image::codecs::png::PngDecoder::with_limits(file()?, image_limits(limits))
+                    .with_context(decoding)?;
+            if !decoder.is_apng().with_context(decoding)? {
+                return Ok(None);
+            }
+            let (width, height) = decoder.dimensions();
+            limits.check(path, width, height)?;
+            Ok(Some(decoder.apng().with_context(decoding)?.into_frames()))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// The animation of the image shown on a display
+pub struct Animation {
+    /// Its timer stops when it doesn't match the one of the display anymore
+    pub id: u64,
+    /// Receive the frames from the image loader, `None` for the still images
+    loading: Option<Receiver<Option<Vec<Frame>>>>,
+    frames: Vec<Frame>,
+    /// The frame shown
+    index: usize,
+    /// Stay on the frame shown
+    paused: bool,
+    /// Its timer is running, it stops while the animation is paused
+    pub ticking: bool,
+}
+
+pub enum Step<'a> {
+    /// The frames are still being decoded
+    Wait,
+    /// Show the frame, then wait for its delay
+    Show(&'a Frame),
+    /// Stop the timer until the animation is resumed
+    Paused,
+    /// This is a still image, or its frames could not be decoded
+    Stop,
+}
+
+impl Animation {
+    pub fn new(id: u64, loading: Receiver<Option<Vec<Frame>>>, paused: bool) -> Self {
+        Self {
+            id,
+            loading: Some(loading),
+            frames: Vec::new(),
+            index: 0,
+            paused,
+            ticking: true,
+        }
+    }
+
+    /// Go to the next frame, the first one once they are ready: the image shown until then might
+    /// not be part of the animation, like the default image of the APNGs
+    pub fn step(&mut self) -> Step<'_> {
+        if let Some(loading) = &self.loading {
+            match loading.try_recv() {
+                Err(TryRecvError::Empty) => return Step::Wait,
+                Ok(Some(frames)) if frames.len() > 1 => {
+                    self.frames = frames;
+                    self.loading = None;
+                    return Step::Show(&self.frames[0]);
+                }
+                Ok(_) | Err(TryRecvError::Disconnected) => return Step::Stop,
+            }
+        }
+        if self.paused {
+            self.ticking = false;
+            return Step::Paused;
+        }
+        self.index = (self.index + 1) % self.frames.len();
+        Step::Show(&self.frames[self.index])
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Return true when the timer must be started again
+    pub fn set_paused(&mut self, paused: bool) -> bool {
+        self.paused = paused;
+        !paused && !self.ticking
+    }
+
+    /// Go to `frame`, `None` when it doesn't exist or the frames are still being decoded
+    pub fn seek(&mut self, frame: usize) -> Option<&Frame> {
+        if self.loading.is_some() || frame >= self.frames.len() {
+            return None;
+        }
+        self.index = frame;
+        Some(&self.frames[frame])
+    }
+
+    /// The frame shown, `None` while the frames are being decoded
+    pub fn frame(&self) -> Option<&Frame> {
+        self.frames.get(self.index)
+    }
+
+    /// The frame shown and the number of frames, zero while they are being decoded
+    pub fn position(&self) -> (usize, usize) {
+        (self.index, self.frames.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    #[cfg(feature = "gif")]
+    #[test]
+    fn test_is_animated() {
+        let path =
+            |name| std::env::temp_dir().join(format!("wpaperd-{}-{name}", std::process::id()));
+        let (animated, still) = (path("animated.gif"), path("still.gif"));
+        for (file, count) in [(&animated, 2), (&still, 1)] {
+            let mut encoder =
+                image::codecs::gif::GifEncoder::new(std::fs::File::create(file).unwrap());
+            encoder
+                .encode_frames((0..count).map(|_| image::Frame::new(RgbaImage::new(2, 2))))
+                .unwrap();
+        }
+        let limits = DecodeLimits::default();
+        let results = [
+            is_animated(&animated, &limits),
+            is_animated(&still, &limits),
+        ];
+        std::fs::remove_file(&animated).unwrap();
+        std::fs::remove_file(&still).unwrap();
+
+        assert_eq!(results, [true, false]);
+        assert!(!is_animated(Path::new("/nonexistent/image.jpg"), &limits));
+    }
+
+    #[test]
+    fn test_frame_delay() {
+        assert_eq!(
+            frame_delay(Delay::from_numer_denom_ms(40, 1)),
+            Duration::from_millis(40)
+        );
+        // 1/30 of a second, not rounded to 33ms
+        assert_eq!(
+            frame_delay(Delay::from_numer_denom_ms(1000, 30)),
+            Duration::from_nanos(33_333_333)
+        );
+        assert_eq!(frame_delay(Delay::from_numer_denom_ms(0, 1)), CLAMPED_DELAY);
+        assert_eq!(
+            frame_delay(Delay::from_numer_denom_ms(10, 1)),
+            CLAMPED_DELAY
+        );
+        assert_eq!(
+            frame_delay(Delay::from_numer_denom_ms(11, 1)),
+            Duration::from_millis(11)
+        );
+
+        let (sender, receiver) = mpsc::channel();
+        let mut animation = Animation::new(1, receiver, false);
+        assert!(matches!(animation.step(), Step::Wait));
+        let frame = |delay| Frame {
+            image: Arc::new(RgbaImage::new(1, 1)),
+            delay: Duration::from_millis(delay),
+        };
+        sender.send(Some(vec![frame(20), frame(30)])).unwrap();
+        let delays: Vec<_> = (0..3)
+            .map(|_| match animation.step() {
+                Step::Show(frame) => frame.delay.as_millis(),
+                _ => 0,
+            })
+            .collect();
+        assert_eq!(delays, [20, 30, 20]);
+
+        // Paused on the second frame
+        assert!(animation.seek(1).is_some());
+        assert!(!animation.set_paused(true));
+        assert!(matches!(animation.step(), Step::Paused));
+        assert_eq!(animation.position(), (1, 2));
+        assert!(animation.seek(2).is_none());
+        assert!(animation.seek(1).is_some());
+        // The timer stopped with the last step
+        assert!(animation.set_paused(false));
+
+        let (sender, receiver) = mpsc::channel();
+        let mut still = Animation::new(2, receiver, true);
+        sender.send(None).unwrap();
+        assert!(matches!(still.step(), Step::Stop));
+    }
+}
@@ -0,0 +1,313 @@
+//! Audio-reactive wallpapers, enabled by the `audio` feature and by setting `audio-reactive` on
+//! the displays.
+//!
+//! The sound played is captured from the default output of PipeWire by `pw-record`, that must be
+//! installed at runtime; it is started the first time a display asks for the levels and stopped
+//! once none has `audio-reactive` set. A thread reads the raw samples and computes the overall
+//! level and [AUDIO_BANDS] frequency bands, spaced logarithmically from 40Hz to 16kHz, every
+//! [HOP] samples. They are passed to the fragment shader as uniforms, from 0 (silence) to 1 (full
+//! scale), that makes the wallpaper pulse with the bass.
+//!
+//! The levels rise immediately and fall slowly, so that the wallpaper doesn't flicker.
+
+use std::{
+    f32::consts::PI,
+    io::{ErrorKind, Read},
+    process::{Child, Command, Stdio},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use color_eyre::{eyre::Context, Result};
+use log::warn;
+
+pub use crate::render::AUDIO_BANDS;
+
+/// The recorder of PipeWire, part of pipewire-tools
+const RECORDER: &str = "pw-record";
+const SAMPLE_RATE: usize = 48000;
+/// Samples in each FFT, about 43ms
+const WINDOW: usize = 2048;
+/// Samples read between two FFTs
+const HOP: usize = 1024;
+const MIN_FREQUENCY: f32 = 40.0;
+const MAX_FREQUENCY: f32 = 16000.0;
+/// The levels below are silence
+const FLOOR_DB: f32 = -60.0;
+/// How much of the difference a falling level loses at each hop
+const RELEASE: f32 = 0.2;
+
+/// How often the displays draw the levels, unless max-fps is lower
+pub const FRAME_INTERVAL: Duration = Duration::from_millis(33);
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct AudioLevels {
+    /// The level of the whole signal
+    pub level: f32,
+    /// From the lowest frequencies to the highest
+    pub bands: [f32; AUDIO_BANDS],
+}
+
+impl AudioLevels {
+    /// True when the difference can be seen
+    pub fn differs(&self, other: &AudioLevels) -> bool {
+        std::iter::once((self.level, other.level))
+            .chain(self.bands.iter().copied().zip(other.bands.iter().copied()))
+            .any(|(a, b)| (a - b).abs() >= 0.005)
+    }
+}
+
+/// Map an amplitude, relative to the full scale, to 0..1
+fn normalize(amplitude: f32) -> f32 {
+    let db = 20.0 * amplitude.max(f32::MIN_POSITIVE).log10();
+    ((db - FLOOR_DB) / -FLOOR_DB).clamp(0.0, 1.0)
+}
+
+/// Rise immediately, fall slowly
+fn smooth(previous: f32, current: f32) -> f32 {
+    if current >= previous {
+        current
+    } else {
+        previous + (current - previous) * RELEASE
+    }
+}
+
+/// The iterative radix-2 FFT, in place; the length must be a power of two
+fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let t_re = re[b] * cos - im[b] * sin;
+                let t_im = re[b] * sin + im[b] * cos;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// Compute the levels from the samples, mono at [SAMPLE_RATE]
+struct Analyzer {
+    /// The last [WINDOW] samples
+    samples: Vec<f32>,
+    /// Hann window
+    window: Vec<f32>,
+    /// The FFT bins of each band, the first one included and the last one excluded
+    bands: [(usize, usize); AUDIO_BANDS],
+    levels: AudioLevels,
+}
+
+impl Analyzer {
+    fn new() -> Self {
+        let bin = |frequency: f32| (frequency * WINDOW as f32 / SAMPLE_RATE as f32) as usize;
+        let edge = |band: usize| {
+            MIN_FREQUENCY * (MAX_FREQUENCY / MIN_FREQUENCY).powf(band as f32 / AUDIO_BANDS as f32)
+        };
+        Self {
+            samples: vec![0.0; WINDOW],
+            window: (0..WINDOW)
+                .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / WINDOW as f32).cos())
+                .collect(),
+            bands: std::array::from_fn(|band| {
+                let first = bin(edge(band)).max(1);
+                (first, bin(edge(band + 1)).max(first + 1))
+            }),
+            levels: AudioLevels::default(),
+        }
+    }
+
+    /// Add [HOP] samples and return the new levels
+    fn push(&mut self, hop: &[f32]) -> AudioLevels {
+        self.samples.drain(..hop.len());
+        self.samples.extend_from_slice(hop);
+
+        let rms = (hop.iter().map(|s| s * s).sum::<f32>() / hop.len() as f32).sqrt();
+        // A full scale sine has an RMS of 1/√2
+        self.levels.level = smooth(self.levels.level, normalize(rms * std::f32::consts::SQRT_2));
+
+        let mut re: Vec<f32> = self
+            .samples
+            .iter()
+            .zip(&self.window)
+            .map(|(sample, weight)| sample * weight)
+            .collect();
+        let mut im = vec![0.0; WINDOW];
+        fft(&mut re, &mut im);
+        // The amplitude of a sine in a single bin, the Hann window halves it
+        let scale = 4.0 / WINDOW as f32;
+        for (level, &(first, last)) in self.levels.bands.iter_mut().zip(&self.bands) {
+            let peak = (first..last)
+                .map(|bin| (re[bin] * re[bin] + im[bin] * im[bin]).sqrt() * scale)
+                .fold(0.0, f32::max);
+            *level = smooth(*level, normalize(peak));
+        }
+        self.levels
+    }
+}
+
+/// `pw-record` and the levels computed by its thread
+struct Recorder {
+    child: Child,
+    levels: Arc<Mutex<AudioLevels>>,
+}
+
+impl Recorder {
+    fn spawn() -> Result<Self> {
+        let mut child = Command::new(RECORDER)
+            .args(["--raw", "--format", "f32", "--channels", "1"])
+            .args(["--rate", &SAMPLE_RATE.to_string()])
+            // Capture what is played, instead of the microphone
+            .args(["-P", "{ stream.capture.sink = true }", "-"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .with_context(|| format!("running {RECORDER}"))?;
+        let mut stdout = child.stdout.take().expect("stdout to be piped");
+        let levels = Arc::new(Mutex::new(AudioLevels::default()));
+        let shared = levels.clone();
+        let spawned = std::thread::Builder::new()
+            .name("wpaperd-audio".to_string())
+            .spawn(move || {
+                let mut analyzer = Analyzer::new();
+                let mut bytes = vec![0; HOP * 4];
+                while stdout.read_exact(&mut bytes).is_ok() {
+                    let hop: Vec<f32> = bytes
+                        .chunks_exact(4)
+                        .map(|sample| f32::from_ne_bytes(sample.try_into().unwrap()))
+                        .collect();
+                    *shared.lock().unwrap() = analyzer.push(&hop);
+                }
+                *shared.lock().unwrap() = AudioLevels::default();
+            });
+        if let Err(err) = spawned {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(err).context("spawning the thread reading the audio");
+        }
+        Ok(Self { child, levels })
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[derive(Default)]
+enum State {
+    #[default]
+    Stopped,
+    Running(Recorder),
+    /// Not started again until [AudioCapture::retry] is called by a reload
+    Failed,
+}
+
+/// The capture of the sound played, shared by all the displays
+#[derive(Default)]
+pub struct AudioCapture {
+    state: State,
+}
+
+impl AudioCapture {
+    /// The current levels, starting the capture when needed. They stay at zero when it fails
+    pub fn levels(&mut self) -> AudioLevels {
+        if let State::Stopped = self.state {
+            self.state = match Recorder::spawn() {
+                Ok(recorder) => State::Running(recorder),
+                Err(err) => {
+                    let not_found = err
+                        .downcast_ref::<std::io::Error>()
+                        .is_some_and(|err| err.kind() == ErrorKind::NotFound);
+                    if not_found {
+                        warn!("{RECORDER} was not found, install pipewire to use audio-reactive");
+                    } else {
+                        warn!("{err:?}");
+                    }
+                    State::Failed
+                }
+            };
+        }
+        let State::Running(recorder) = &mut self.state else {
+            return AudioLevels::default();
+        };
+        if let Ok(Some(status)) = recorder.child.try_wait() {
+            warn!("{RECORDER} exited with {status}, the audio is not captured anymore");
+            self.state = State::Failed;
+            return AudioLevels::default();
+        }
+        *recorder.levels.lock().unwrap()
+    }
+
+    /// Stop the capture, it starts again with the next call to [AudioCapture::levels]
+    pub fn stop(&mut self) {
+        self.state = State::Stopped;
+    }
+
+    /// Start the capture again with the next call to [AudioCapture::levels], if it failed
+    pub fn retry(&mut self) {
+        if let State::Failed = self.state {
+            self.state = State::Stopped;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_analyzer() {
+        let mut analyzer = Analyzer::new();
+        assert_eq!(analyzer.push(&[0.0; HOP]), AudioLevels::default());
+
+        // A full scale sine at 60Hz, in the first band
+        let sine = |frequency: f32, from: usize| -> Vec<f32> {
+            (from..from + HOP)
+                .map(|i| (2.0 * PI * frequency * i as f32 / SAMPLE_RATE as f32).sin())
+                .collect()
+        };
+        analyzer.push(&sine(60.0, 0));
+        let levels = analyzer.push(&sine(60.0, HOP));
+        assert!(levels.level > 0.95);
+        assert!(levels.bands[0] > 0.9);
+        assert!(levels.bands[AUDIO_BANDS - 1] < 0.5);
+
+        // The levels fall slowly once it stops
+        let silent = analyzer.push(&[0.0; HOP]);
+        assert!(silent.level < levels.level && silent.level > 0.5);
+        for _ in 0..50 {
+            analyzer.push(&[0.0; HOP]);
+        }
+        assert!(!analyzer.levels.differs(&AudioLevels::default()));
+
+        let mut analyzer = Analyzer::new();
+        analyzer.push(&sine(8000.0, 0));
+        let levels = analyzer.push(&sine(8000.0, HOP));
+        assert!(levels.bands[AUDIO_BANDS - 1] > 0.9);
+        assert!(levels.bands[0] < 0.5);
+    }
+}
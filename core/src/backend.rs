@@ -0,0 +1,355 @@
+//! The backend drawing the wallpapers, set in the `[backend]` section.
+//!
+//! wpaperd always draws with OpenGL ES through EGL; the backend selects which driver of Mesa does
+//! it: `gpu`, the default, or `software` for llvmpipe, that draws on the CPU. On the systems with
+//! more GPUs, `device` selects the one used. Both select an EGL device, listed with
+//! `EGL_EXT_device_enumeration`, that is passed to `eglGetPlatformDisplay` with
+//! `EGL_EXT_explicit_device`: to switch the backend at runtime the display is terminated and
+//! the one of the new device is initialized, then the surfaces create their contexts once more.
+//!
+//! When neither is set, Mesa picks the device as usual, following `DRI_PRIME` and
+//! `LIBGL_ALWAYS_SOFTWARE` if wpaperd has been started with them.
+//!
+//! `device` is the index of a GPU, its PCI tag, e.g. `pci-0000_03_00_0`, or the path of one of its
+//! DRM nodes, e.g. `/dev/dri/renderD129`; `wpaperd --capabilities` lists them. On the laptops with
+//! hybrid graphics, setting it to the integrated GPU avoids waking up the discrete one just to
+//! draw the wallpapers.
+
+use std::{
+    ffi::{c_char, c_void, CStr},
+    fmt, fs,
+    path::{Path, PathBuf},
+    ptr,
+};
+
+use color_eyre::{
+    eyre::{anyhow, bail, ensure, Context},
+    owo_colors::OwoColorize,
+    Result, Section,
+};
+use egl::API as egl;
+use serde::Deserialize;
+use smithay_client_toolkit::reexports::client::{protocol::wl_display::WlDisplay, Proxy};
+
+/// The DRM devices, one directory for each node
+const DRM_CLASS: &str = "/sys/class/drm";
+
+/// `EGL_PLATFORM_WAYLAND_KHR`, the displays of `eglGetPlatformDisplay` taking a `wl_display`
+const PLATFORM_WAYLAND: egl::Enum = 0x31D8;
+/// `EGL_DEVICE_EXT`, the attribute of `eglGetPlatformDisplay` selecting the device
+const DEVICE_ATTRIBUTE: egl::Attrib = 0x322C;
+/// `EGL_DRM_RENDER_NODE_FILE_EXT`, the path of the render node of a device
+const DRM_RENDER_NODE_FILE: egl::Int = 0x3377;
+/// The extension of the device of Mesa drawing with llvmpipe
+const SOFTWARE_DEVICE_EXTENSION: &str = "EGL_MESA_device_software";
+/// Needed to select the device of the display
+const CLIENT_EXTENSIONS: [&str; 2] = ["EGL_EXT_device_enumeration", "EGL_EXT_explicit_device"];
+
+type QueryDevices =
+    unsafe extern "system" fn(egl::Int, *mut *mut c_void, *mut egl::Int) -> egl::Boolean;
+type QueryDeviceString = unsafe extern "system" fn(*mut c_void, egl::Int) -> *const c_char;
+
+#[derive(Deserialize, Clone, PartialEq, Eq, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Backend {
+    pub renderer: Option<RendererKind>,
+    /// The GPU used, e.g. `1`, `pci-0000_03_00_0` or `/dev/dri/renderD129`, ignored by the
+    /// software renderer
+    pub device: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum RendererKind {
+    Gpu,
+    Software,
+}
+
+impl fmt::Display for RendererKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RendererKind::Gpu => "gpu",
+            RendererKind::Software => "software",
+        })
+    }
+}
+
+impl std::str::FromStr for RendererKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gpu" => Ok(RendererKind::Gpu),
+            "software" => Ok(RendererKind::Software),
+            _ => Err(format!("{s:?} is not a renderer, use gpu or software")),
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.renderer.unwrap_or(RendererKind::Gpu))?;
+        match &self.device {
+            Some(device) if self.renderer != Some(RendererKind::Software) => {
+                write!(f, " ({device})")
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Backend {
+    /// The render node of the GPU selected by `device`, `None` when Mesa picks the GPU
+    pub fn render_node(&self) -> Result<Option<PathBuf>> {
+        let device = match &self.device {
+            Some(_) if self.renderer == Some(RendererKind::Software) => return Ok(None),
+            Some(device) => device,
+            None => return Ok(None),
+        };
+        let nodes = render_nodes();
+        let by_tag = |tag: &str| nodes.iter().find(|node| node.tag.as_deref() == Some(tag));
+        let node = if let Ok(index) = device.parse::<usize>() {
+            nodes.get(index)
+        } else if device.starts_with('/') {
+            match nodes.iter().find(|node| node.path == Path::new(device)) {
+                Some(node) => Some(node),
+                // A card node, the render node of the same GPU is used
+                None => by_tag(&resolve_node(Path::new(device))?),
+            }
+        } else {
+            by_tag(device)
+        };
+        node.map(|node| Some(node.path.clone()))
+            .ok_or_else(|| anyhow!("no GPU matches {device:?}"))
+            .with_context(|| {
+                format!(
+                    "selecting the {} of the backend",
+                    "device".bold().italic().blue()
+                )
+            })
+            .with_suggestion(|| {
+                format!(
+                    "Run {} to list the GPUs",
+                    "wpaperd --capabilities".italic().yellow()
+                )
+            })
+    }
+
+    /// The EGL device drawing with this backend, `None` when Mesa picks it
+    fn egl_device(&self) -> Result<Option<*mut c_void>> {
+        let software = self.renderer == Some(RendererKind::Software);
+        let render_node = self.render_node()?;
+        if !software && render_node.is_none() {
+            return Ok(None);
+        }
+        egl_devices()?
+            .into_iter()
+            .find(|device| {
+                if software {
+                    device
+                        .extensions
+                        .split(' ')
+                        .any(|extension| extension == SOFTWARE_DEVICE_EXTENSION)
+                } else {
+                    device.render_node == render_node
+                }
+            })
+            .map(|device| Some(device.handle))
+            .ok_or_else(|| anyhow!("no EGL device draws with the {self} backend"))
+    }
+
+    /// Get the EGL display of `wl_display` drawing with this backend and initialize it
+    pub fn display(&self, wl_display: &WlDisplay) -> Result<egl::Display> {
+        let mut attributes = Vec::new();
+        if let Some(device) = self.egl_device()? {
+            attributes.extend([DEVICE_ATTRIBUTE, device as egl::Attrib]);
+        }
+        attributes.push(egl::ATTRIB_NONE);
+        let egl_display = unsafe {
+            egl.get_platform_display(
+                PLATFORM_WAYLAND,
+                wl_display.id().as_ptr() as *mut c_void,
+                &attributes,
+            )
+        }
+        .context("getting the EGL display from the WlDisplay")?;
+        egl.initialize(egl_display)
+            .with_context(|| format!("initializing the egl display with the {self} backend"))?;
+        Ok(egl_display)
+    }
+}
+
+/// A device listed by EGL
+struct EglDevice {
+    handle: *mut c_void,
+    /// `None` for the software device
+    render_node: Option<PathBuf>,
+    extensions: String,
+}
+
+/// The devices that can be passed to `eglGetPlatformDisplay`
+fn egl_devices() -> Result<Vec<EglDevice>> {
+    let extensions = egl
+        .query_string(None, egl::EXTENSIONS)
+        .context("querying the EGL client extensions")?
+        .to_string_lossy();
+    for extension in CLIENT_EXTENSIONS {
+        ensure!(
+            extensions
+                .split(' ')
+                .any(|supported| supported == extension),
+            "EGL doesn't support {extension}, needed to select the device of the backend"
+        );
+    }
+    let (Some(query_devices), Some(query_device_string)) = (
+        egl.get_proc_address("eglQueryDevicesEXT"),
+        egl.get_proc_address("eglQueryDeviceStringEXT"),
+    ) else {
+        bail!("EGL doesn't provide the functions of EGL_EXT_device_enumeration");
+    };
+    // Their signatures are the ones of the extensions
+    let (query_devices, query_device_string) = unsafe {
+        (
+            std::mem::transmute::<extern "system" fn(), QueryDevices>(query_devices),
+            std::mem::transmute::<extern "system" fn(), QueryDeviceString>(query_device_string),
+        )
+    };
+
+    let mut count = 0;
+    ensure!(
+        unsafe { query_devices(0, ptr::null_mut(), &mut count) } == egl::TRUE,
+        "counting the EGL devices"
+    );
+    let mut handles = vec![ptr::null_mut(); count.max(0) as usize];
+    ensure!(
+        unsafe { query_devices(count, handles.as_mut_ptr(), &mut count) } == egl::TRUE,
+        "listing the EGL devices"
+    );
+    handles.truncate(count.max(0) as usize);
+    let string = |handle, name| {
+        let string = unsafe { query_device_string(handle, name) };
+        (!string.is_null()).then(|| {
+            unsafe { CStr::from_ptr(string) }
+                .to_string_lossy()
+                .into_owned()
+        })
+    };
+    Ok(handles
+        .into_iter()
+        .map(|handle| EglDevice {
+            handle,
+            render_node: string(handle, DRM_RENDER_NODE_FILE).map(PathBuf::from),
+            extensions: string(handle, egl::EXTENSIONS).unwrap_or_default(),
+        })
+        .collect())
+}
+
+/// A GPU, found from one of its DRM render nodes
+#[derive(Debug)]
+pub struct RenderNode {
+    /// E.g. `/dev/dri/renderD128`
+    pub path: PathBuf,
+    /// Its PCI tag, `None` when it is not a PCI device
+    pub tag: Option<String>,
+    /// The kernel driver, e.g. `i915` or `amdgpu`
+    pub driver: Option<String>,
+}
+
+/// The render nodes of the GPUs, sorted by their path
+pub fn render_nodes() -> Vec<RenderNode> {
+    let Ok(entries) = fs::read_dir(DRM_CLASS) else {
+        return Vec::new();
+    };
+    let mut nodes: Vec<RenderNode> = entries
+        .flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("renderD"))
+        .map(|entry| {
+            let device = entry.path().join("device");
+            RenderNode {
+                path: Path::new("/dev/dri").join(entry.file_name()),
+                tag: pci_tag(&device),
+                driver: fs::read_link(device.join("driver"))
+                    .ok()
+                    .and_then(|driver| Some(driver.file_name()?.to_string_lossy().into_owned())),
+            }
+        })
+        .collect();
+    nodes.sort_by(|a, b| a.path.cmp(&b.path));
+    nodes
+}
+
+/// The PCI tag of the GPU owning the node, either a render node or a card one
+fn resolve_node(node: &Path) -> Result<String> {
+    node.file_name()
+        .and_then(|name| pci_tag(&Path::new(DRM_CLASS).join(name).join("device")))
+        .ok_or_else(|| anyhow!("{node:?} is not the DRM node of a PCI device"))
+        .with_context(|| {
+            format!(
+                "selecting the {} of the backend",
+                "device".bold().italic().blue()
+            )
+        })
+        .with_suggestion(|| {
+            format!(
+                "Run {} to list the GPUs",
+                "wpaperd --capabilities".italic().yellow()
+            )
+        })
+}
+
+/// The PCI tag of the device in sysfs, its directory is named after the address
+fn pci_tag(device: &Path) -> Option<String> {
+    let device = fs::canonicalize(device).ok()?;
+    pci_tag_of(device.file_name()?.to_str()?)
+}
+
+/// Mesa takes the address `0000:03:00.0` as `pci-0000_03_00_0`
+fn pci_tag_of(address: &str) -> Option<String> {
+    let is_address = address.len() == 12
+        && address.chars().enumerate().all(|(index, c)| match index {
+            4 | 7 => c == ':',
+            10 => c == '.',
+            _ => c.is_ascii_hexdigit(),
+        });
+    is_address.then(|| format!("pci-{}", address.replace([':', '.'], "_")))
+}
+
+/// Release the EGL display, all the contexts and the surfaces created on it are destroyed
+pub fn terminate(egl_display: egl::Display) -> Result<()> {
+    egl.make_current(egl_display, None, None, None)
+        .context("releasing the EGL context")?;
+    egl.terminate(egl_display)
+        .context("terminating the egl display")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_node() {
+        // The software renderer ignores the device
+        let backend: Backend =
+            toml::from_str("renderer = 'software'\ndevice = 'pci-0000_03_00_0'").unwrap();
+        assert_eq!(backend.render_node().unwrap(), None);
+        assert_eq!(backend.to_string(), "software");
+        // Nothing set, Mesa picks the GPU
+        assert_eq!(Backend::default().render_node().unwrap(), None);
+        let backend: Backend =
+            toml::from_str("renderer = 'gpu'\ndevice = 'pci-ffff_ff_1f_7'").unwrap();
+        assert!(backend.render_node().is_err());
+        assert_eq!(backend.to_string(), "gpu (pci-ffff_ff_1f_7)");
+        let backend: Backend = toml::from_str("device = '4096'").unwrap();
+        assert!(backend.render_node().is_err());
+        assert!(toml::from_str::<Backend>("renderer = 'vulkan'").is_err());
+
+        assert_eq!(
+            pci_tag_of("0000:03:00.0").as_deref(),
+            Some("pci-0000_03_00_0")
+        );
+        assert_eq!(pci_tag_of("card0"), None);
+        assert_eq!(pci_tag_of("0000:03:00:0"), None);
+        let node: Backend = toml::from_str("device = '/dev/dri/renderD255'").unwrap();
+        assert!(node.render_node().is_err());
+    }
+}
@@ -0,0 +1,162 @@
+//! Measure the time taken by each step of loading an image, for `wpaperd bench`.
+//!
+//! The steps are the same done by [crate::image_loader::ImageLoader] and by the renderer
+//! when a new wallpaper is shown.
+
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use color_eyre::{
+    eyre::{Context, ContextCompat},
+    Result,
+};
+use egl::API as egl;
+
+use crate::{
+    image_loader::{decode, downscale_to_display, downscale_to_fit, DecodeLimits},
+    render::{gl, load_texture},
+    sidecar::Sidecar,
+    wallpaper_info::{BackgroundMode, ResizeFilter},
+};
+
+pub struct BenchOptions {
+    /// Size of the display, in pixels
+    pub width: u32,
+    pub height: u32,
+    pub mode: BackgroundMode,
+    pub resize_filter: ResizeFilter,
+    /// See [crate::image_loader::resize]
+    pub linear_scaling: bool,
+    /// The times returned are the average of this many runs
+    pub iterations: u32,
+}
+
+/// Average time taken by each step
+#[derive(Default)]
+pub struct BenchTimes {
+    pub decode: Duration,
+    /// Crop the image and apply the filters set in its sidecar file
+    pub filter: Duration,
+    /// Scaling on the CPU, only done with [ResizeFilter::Lanczos3] and for the images bigger
+    /// than the maximum texture size. Otherwise the GPU scales the image while drawing
+    pub scale: Duration,
+    /// None when there is no EGL display to upload the image to
+    pub upload: Option<Duration>,
+}
+
+/// Load `path` `options.iterations` times. The image is uploaded to a texture when
+/// `egl_display` is set, it must have been initialized already
+pub fn bench(
+    path: &Path,
+    options: &BenchOptions,
+    egl_display: Option<egl::Display>,
+) -> Result<BenchTimes> {
+    let gl = egl_display.map(offscreen_context).transpose()?;
+    let max_texture_size = gl.as_ref().map(|gl| unsafe {
+        let mut max_texture_size = 0;
+        gl.GetIntegerv(gl::MAX_TEXTURE_SIZE, &mut max_texture_size);
+        max_texture_size.try_into().unwrap_or(0)
+    });
+
+    let mut times = BenchTimes {
+        upload: gl.as_ref().map(|_| Duration::ZERO),
+        ..Default::default()
+    };
+    for _ in 0..options.iterations {
+        let start = Instant::now();
+        let image = decode(path, &DecodeLimits::default())?;
+        times.decode += start.elapsed();
+
+        let start = Instant::now();
+        let image = Sidecar::load(path).apply(image);
+        times.filter += start.elapsed();
+
+        let start = Instant::now();
+        let mut image = match max_texture_size {
+            Some(max_size) if max_size > 0 => {
+                downscale_to_fit(image, max_size, options.linear_scaling)
+            }
+            _ => image,
+        };
+        if options.resize_filter == ResizeFilter::Lanczos3 {
+            image = downscale_to_display(
+                image,
+                options.width,
+                options.height,
+                options.mode,
+                options.linear_scaling,
+            );
+        }
+        let image = image.into_rgba8();
+        times.scale += start.elapsed();
+
+        if let (Some(gl), Some(upload)) = (&gl, &mut times.upload) {
+            let start = Instant::now();
+            let texture = load_texture(
+                gl,
+                image.into(),
+                options.resize_filter,
+                options.linear_scaling,
+            )
+            .context("uploading the image")?;
+            unsafe {
+                // Wait for the upload to complete
+                gl.Finish();
+                *upload += start.elapsed();
+                gl.DeleteTextures(1, &texture);
+            }
+        }
+    }
+
+    times.decode /= options.iterations;
+    times.filter /= options.iterations;
+    times.scale /= options.iterations;
+    if let Some(upload) = &mut times.upload {
+        *upload /= options.iterations;
+    }
+    Ok(times)
+}
+
+/// Make current an EGL context drawing on a 1x1 pbuffer, the textures do not need a window
+fn offscreen_context(egl_display: egl::Display) -> Result<gl::Gl> {
+    const ATTRIBUTES: [i32; 9] = [
+        egl::SURFACE_TYPE,
+        egl::PBUFFER_BIT,
+        egl::RED_SIZE,
+        8,
+        egl::GREEN_SIZE,
+        8,
+        egl::BLUE_SIZE,
+        8,
+        egl::NONE,
+    ];
+    let config = egl
+        .choose_first_config(egl_display, &ATTRIBUTES)
+        .context("choosing an EGL configuration")?
+        .context("no EGL configuration supports pbuffers")?;
+
+    const CONTEXT_ATTRIBUTES: [i32; 5] = [
+        egl::CONTEXT_MAJOR_VERSION,
+        2,
+        egl::CONTEXT_MINOR_VERSION,
+        0,
+        egl::NONE,
+    ];
+    let context = egl
+        .create_context(egl_display, config, None, &CONTEXT_ATTRIBUTES)
+        .context("creating an EGL context")?;
+
+    const SURFACE_ATTRIBUTES: [i32; 5] = [egl::WIDTH, 1, egl::HEIGHT, 1, egl::NONE];
+    let surface = egl
+        .create_pbuffer_surface(egl_display, config, &SURFACE_ATTRIBUTES)
+        .context("creating an EGL pbuffer surface")?;
+    egl.make_current(egl_display, Some(surface), Some(surface), Some(context))
+        .context("making the EGL context current")?;
+
+    Ok(gl::Gl::load_with(|name| {
+        egl.get_proc_address(name)
+            .expect("egl.get_proc_address to work") as *const std::ffi::c_void
+    }))
+}
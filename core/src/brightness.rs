@@ -0,0 +1,142 @@
+//! Dim the wallpaper in the dark, following an ambient light sensor or the time of day.
+//!
+//! The sensor is the first device of the iio subsystem measuring the illuminance, as found in most
+//! laptops. Below [DARK_LUX] the wallpaper has the `night` brightness and above [BRIGHT_LUX] the
+//! `day` one; in between it changes on a logarithmic scale, like the eye perceives the light.
+//! Following the time, the brightness goes from `day` to `night` in the hour after `dusk`, and
+//! back in the hour after `dawn`.
+
+use std::{fs, time::Duration};
+
+use chrono::{Local, NaiveTime};
+use serde::Deserialize;
+
+use crate::quiet_hours::deserialize_time;
+
+/// How often the brightness is computed again
+pub const UPDATE_INTERVAL: Duration = Duration::from_secs(30);
+/// The illuminance of a dark room, in lux
+const DARK_LUX: f32 = 5.0;
+/// The illuminance of a lit office, in lux
+const BRIGHT_LUX: f32 = 300.0;
+/// Minutes taken to go from one brightness to the other, following the time
+const FADE_MINUTES: i64 = 60;
+const IIO_DEVICES: &str = "/sys/bus/iio/devices";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BrightnessSource {
+    #[default]
+    Time,
+    /// The ambient light sensor, the time is used when there is none
+    Sensor,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Brightness {
+    pub source: BrightnessSource,
+    /// From 0 to 1, used with a lot of light or during the day
+    pub day: f32,
+    /// From 0 to 1, used in the dark or during the night
+    pub night: f32,
+    #[serde(deserialize_with = "deserialize_time")]
+    pub dawn: NaiveTime,
+    #[serde(deserialize_with = "deserialize_time")]
+    pub dusk: NaiveTime,
+}
+
+impl Default for Brightness {
+    fn default() -> Self {
+        Self {
+            source: BrightnessSource::default(),
+            day: 1.0,
+            night: 0.6,
+            dawn: NaiveTime::from_hms_opt(7, 0, 0).unwrap(),
+            dusk: NaiveTime::from_hms_opt(20, 0, 0).unwrap(),
+        }
+    }
+}
+
+impl Brightness {
+    /// The brightness to use now, reading the sensor if needed
+    pub fn now(&self) -> f32 {
+        let lux = match self.source {
+            BrightnessSource::Sensor => read_lux(),
+            BrightnessSource::Time => None,
+        };
+        self.value(Local::now().time(), lux)
+    }
+
+    /// The brightness at `time`, or with the illuminance `lux` when it has been read
+    pub fn value(&self, time: NaiveTime, lux: Option<f32>) -> f32 {
+        let amount = match lux {
+            Some(lux) => {
+                let range = BRIGHT_LUX.ln() - DARK_LUX.ln();
+                ((lux.max(DARK_LUX).ln() - DARK_LUX.ln()) / range).min(1.0)
+            }
+            None => {
+                // Minutes passed since dawn and dusk, the last one that happened is the smallest
+                let since = |start: NaiveTime| {
+                    time.signed_duration_since(start)
+                        .num_minutes()
+                        .rem_euclid(24 * 60)
+                };
+                let (since_dawn, since_dusk) = (since(self.dawn), since(self.dusk));
+                let faded = |minutes: i64| minutes.min(FADE_MINUTES) as f32 / FADE_MINUTES as f32;
+                if since_dawn <= since_dusk {
+                    faded(since_dawn)
+                } else {
+                    1.0 - faded(since_dusk)
+                }
+            }
+        };
+        self.night + (self.day - self.night) * amount
+    }
+}
+
+/// The illuminance in lux measured by the first iio sensor found, if any
+pub fn read_lux() -> Option<f32> {
+    let mut devices: Vec<_> = fs::read_dir(IIO_DEVICES)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .collect();
+    devices.sort_unstable();
+    devices.into_iter().find_map(|device| {
+        let read = |name: &str| {
+            fs::read_to_string(device.join(name))
+                .ok()?
+                .trim()
+                .parse::<f32>()
+                .ok()
+        };
+        read("in_illuminance_input").or_else(|| {
+            Some(read("in_illuminance_raw")? * read("in_illuminance_scale").unwrap_or(1.0))
+        })
+    })
+}
+
+/// The directories read to find the sensor, its files are links into /sys/devices
+pub fn sensor_paths() -> [&'static str; 2] {
+    [IIO_DEVICES, "/sys/devices"]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value() {
+        let brightness: Brightness = toml::from_str("night = 0.5\ndusk = \"21:00\"").unwrap();
+        let time = |hour, minute| NaiveTime::from_hms_opt(hour, minute, 0).unwrap();
+        assert_eq!(brightness.value(time(12, 0), None), 1.0);
+        assert_eq!(brightness.value(time(21, 30), None), 0.75);
+        assert_eq!(brightness.value(time(3, 0), None), 0.5);
+        assert_eq!(brightness.value(time(7, 30), None), 0.75);
+
+        // The sensor takes precedence over the time
+        assert_eq!(brightness.value(time(12, 0), Some(1.0)), 0.5);
+        assert_eq!(brightness.value(time(3, 0), Some(1000.0)), 1.0);
+    }
+}
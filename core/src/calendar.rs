@@ -0,0 +1,218 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use chrono::{Datelike, NaiveDate, Weekday};
+use color_eyre::{
+    eyre::{anyhow, Context},
+    Report,
+};
+use serde::{
+    de::{Error, MapAccess, Visitor},
+    Deserialize,
+};
+
+use crate::config::{tilde_expansion, tilde_expansion_deserialize};
+
+/// A directory used in place of `path` on some days
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CalendarEntry {
+    #[serde(deserialize_with = "deserialize_path")]
+    pub path: PathBuf,
+    /// The days of the week when the entry applies, all of them if empty
+    #[serde(default)]
+    pub weekdays: Vec<CalendarWeekday>,
+    /// The dates when the entry applies, all of them if empty
+    #[serde(default)]
+    pub dates: Vec<DateRange>,
+}
+
+impl CalendarEntry {
+    pub fn matches(&self, date: NaiveDate) -> bool {
+        (self.weekdays.is_empty()
+            || self
+                .weekdays
+                .iter()
+                .any(|weekday| weekday.0 == date.weekday()))
+            && (self.dates.is_empty() || self.dates.iter().any(|range| range.contains(date)))
+    }
+}
+
+/// Read the seasons of a section, `"<date range>" = "<path>"`, as calendar entries in the order
+/// they are written
+pub fn deserialize_seasons<'de, D>(deserializer: D) -> Result<Option<Vec<CalendarEntry>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    struct SeasonsVisitor;
+
+    impl<'de> Visitor<'de> for SeasonsVisitor {
+        type Value = Vec<CalendarEntry>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a table of date ranges and paths")
+        }
+
+        fn visit_map<M: MapAccess<'de>>(self, mut map: M) -> Result<Self::Value, M::Error> {
+            let mut entries = Vec::new();
+            while let Some((range, path)) = map.next_entry::<String, String>()? {
+                let range = DateRange::try_from(range).map_err(M::Error::custom)?;
+                entries.push(CalendarEntry {
+                    path: tilde_expansion(Path::new(&path)),
+                    weekdays: Vec::new(),
+                    dates: vec![range],
+                });
+            }
+            Ok(entries)
+        }
+    }
+
+    deserializer.deserialize_map(SeasonsVisitor).map(Some)
+}
+
+fn deserialize_path<'de, D>(deserializer: D) -> Result<PathBuf, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    tilde_expansion_deserialize(deserializer).map(Option::unwrap_or_default)
+}
+
+/// A day of the week, i.e. `mon` or `monday`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct CalendarWeekday(pub Weekday);
+
+impl TryFrom<String> for CalendarWeekday {
+    type Error = Report;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Weekday::from_str(&value)
+            .map(Self)
+            .map_err(|_| anyhow!("{value:?} is not a day of the week"))
+    }
+}
+
+/// A day of the year, without the year
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MonthDay {
+    month: u32,
+    day: u32,
+}
+
+impl FromStr for MonthDay {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (month, day) = s
+            .split_once('-')
+            .ok_or_else(|| anyhow!("{s:?} is not in the format MM-DD"))?;
+        let month: u32 = month
+            .parse()
+            .with_context(|| format!("{s:?} is not in the format MM-DD"))?;
+        let day: u32 = day
+            .parse()
+            .with_context(|| format!("{s:?} is not in the format MM-DD"))?;
+        // Use a leap year so that 02-29 is accepted
+        NaiveDate::from_ymd_opt(2000, month, day).ok_or_else(|| anyhow!("{s:?} is not a date"))?;
+        Ok(Self { month, day })
+    }
+}
+
+/// Either a single day (`12-25`) or a range of days (`12-01..12-31`), both ends included.
+/// The range can wrap around the end of the year, i.e. `12-20..01-06`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct DateRange {
+    start: MonthDay,
+    end: MonthDay,
+}
+
+impl DateRange {
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        let date = MonthDay {
+            month: date.month(),
+            day: date.day(),
+        };
+        if self.start <= self.end {
+            self.start <= date && date <= self.end
+        } else {
+            date >= self.start || date <= self.end
+        }
+    }
+}
+
+impl TryFrom<String> for DateRange {
+    type Error = Report;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let (start, end) = match value.split_once("..") {
+            Some((start, end)) => (start.trim().parse()?, end.trim().parse()?),
+            None => {
+                let day = value.trim().parse()?;
+                (day, day)
+            }
+        };
+        Ok(Self { start, end })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_calendar_entry() {
+        let entry: CalendarEntry = toml::from_str(
+            r#"
+            path = "/christmas"
+            weekdays = ["sat", "sunday"]
+            dates = ["12-20..01-06", "03-01"]
+            "#,
+        )
+        .unwrap();
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        // Saturday
+        assert!(entry.matches(date(2026, 12, 26)));
+        assert!(entry.matches(date(2027, 1, 3)));
+        // Thursday
+        assert!(!entry.matches(date(2026, 12, 24)));
+        assert!(!entry.matches(date(2027, 1, 9)));
+        // Sunday
+        assert!(entry.matches(date(2026, 3, 1)));
+        assert!(!entry.matches(date(2026, 3, 8)));
+
+        assert!(DateRange::try_from("02-30".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_seasons() {
+        #[derive(Deserialize)]
+        struct Section {
+            #[serde(deserialize_with = "deserialize_seasons")]
+            seasons: Option<Vec<CalendarEntry>>,
+        }
+        let section: Section = toml::from_str(
+            r#"
+            [seasons]
+            "12-24..12-26" = "/christmas"
+            "12-01..02-28" = "/winter"
+            "#,
+        )
+        .unwrap();
+        let seasons = section.seasons.unwrap();
+        let season = |m, d| {
+            let date = NaiveDate::from_ymd_opt(2026, m, d).unwrap();
+            seasons
+                .iter()
+                .find(|entry| entry.matches(date))
+                .map(|entry| entry.path.to_str().unwrap())
+        };
+        // The first range containing the date is used
+        assert_eq!(season(12, 25), Some("/christmas"));
+        assert_eq!(season(1, 10), Some("/winter"));
+        assert_eq!(season(6, 1), None);
+    }
+}
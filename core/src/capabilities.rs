@@ -0,0 +1,104 @@
+//! What this build of wpaperd supports: the image formats, enabled by the cargo features, the
+//! Wayland protocols and the backends. Printed by `wpaperd --capabilities`.
+
+use std::path::Path;
+
+/// An image format and whether it can be decoded
+#[derive(Debug)]
+pub struct Codec {
+    pub name: &'static str,
+    /// Lowercase, the files are recognized by their extension
+    pub extensions: &'static [&'static str],
+    /// The cargo feature enabling the format, `None` when wpaperd cannot decode it at all
+    pub feature: Option<&'static str>,
+    pub enabled: bool,
+}
+
+macro_rules! codec {
+    ($name:literal, [$($extension:literal),+], $feature:literal) => {
+        Codec {
+            name: $name,
+            extensions: &[$($extension),+],
+            feature: Some($feature),
+            enabled: cfg!(feature = $feature),
+        }
+    };
+    ($name:literal, [$($extension:literal),+]) => {
+        Codec {
+            name: $name,
+            extensions: &[$($extension),+],
+            feature: None,
+            enabled: false,
+        }
+    };
+}
+
+pub const CODECS: &[Codec] = &[
+    codec!("AVIF", ["avif"], "avif"),
+    codec!("BMP", ["bmp"], "bmp"),
+    codec!("DDS", ["dds"], "dds"),
+    codec!("OpenEXR", ["exr"], "exr"),
+    codec!("Farbfeld", ["ff", "farbfeld"], "ff"),
+    codec!("GIF", ["gif"], "gif"),
+    codec!("Radiance HDR", ["hdr"], "hdr"),
+    codec!("HEIF", ["heic", "heif"], "heif"),
+    codec!("ICO", ["ico"], "ico"),
+    codec!("JPEG", ["jpg", "jpeg", "jfif"], "jpeg"),
+    // Saving the lock images and the thumbnails needs it, it is always enabled
+    Codec {
+        name: "PNG",
+        extensions: &["png", "apng"],
+        feature: None,
+        enabled: true,
+    },
+    codec!("PNM", ["pbm", "pgm", "ppm", "pam", "pnm"], "pnm"),
+    codec!("QOI", ["qoi"], "qoi"),
+    codec!("TGA", ["tga"], "tga"),
+    codec!("TIFF", ["tif", "tiff"], "tiff"),
+    codec!("WebP", ["webp"], "webp"),
+    codec!("JPEG XL", ["jxl"]),
+    codec!("SVG", ["svg", "svgz"]),
+];
+
+/// The Wayland protocols used, and whether the compositor must support them
+pub const PROTOCOLS: &[(&str, bool)] = &[
+    ("wl_compositor", true),
+    ("wl_shm", true),
+    ("wl_output", true),
+    ("zwlr_layer_shell_v1", true),
+    ("zxdg_output_manager_v1", false),
+];
+
+/// The backends used, and whether they are available in this build
+pub fn backends() -> [(&'static str, bool); 4] {
+    [
+        ("OpenGL ES 3.2 through EGL", true),
+        ("PipeWire audio capture", cfg!(feature = "audio")),
+        ("Landlock sandbox", cfg!(target_os = "linux")),
+        ("seccomp sandbox", cfg!(target_os = "linux")),
+    ]
+}
+
+/// The format of the file, found from its extension
+pub fn codec_for(path: &Path) -> Option<&'static Codec> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    CODECS
+        .iter()
+        .find(|codec| codec.extensions.contains(&extension.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codec_for() {
+        let name = |path| codec_for(Path::new(path)).map(|codec| codec.name);
+        assert_eq!(name("/wallpapers/image.JPG"), Some("JPEG"));
+        assert_eq!(name("image.png"), Some("PNG"));
+        assert_eq!(name("image.txt"), None);
+        assert_eq!(name("image"), None);
+        let jxl = codec_for(Path::new("image.jxl")).unwrap();
+        assert!(!jxl.enabled && jxl.feature.is_none());
+    }
+}
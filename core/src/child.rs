@@ -0,0 +1,93 @@
+//! The commands run by wpaperd with a timeout: the hooks, the `command` source and the HEIF
+//! converter.
+//!
+//! Each one is started in its own process group. `sh -c` runs the commands in child processes
+//! of its own, so killing `sh` alone would leave them running, still holding its pipes; the
+//! whole group is killed instead.
+
+use std::{
+    io::{self, Read},
+    os::unix::process::CommandExt,
+    process::{Child, Command, ExitStatus},
+    time::{Duration, Instant},
+};
+
+/// How often the commands are checked for their exit
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Start `command` in a new process group
+pub fn spawn(command: &mut Command) -> io::Result<Child> {
+    command.process_group(0).spawn()
+}
+
+/// Wait for `child` to exit for up to `timeout`, then kill its process group and return `None`
+pub fn wait_timeout(child: &mut Child, timeout: Duration) -> io::Result<Option<ExitStatus>> {
+    // Too long to be reached
+    let deadline = Instant::now().checked_add(timeout);
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            kill(child);
+            return Ok(None);
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Kill the process group of `child`, started by [spawn], and reap it
+pub fn kill(child: &mut Child) {
+    // The id of the group is the one of its first process
+    let group = child.id() as libc::pid_t;
+    unsafe {
+        libc::kill(-group, libc::SIGKILL);
+    }
+    let _ = child.wait();
+}
+
+/// Read all of `reader`, failing as soon as it exceeds `limit` bytes instead of cutting it
+pub fn read_to_end_limited<R: Read>(reader: R, limit: u64) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    reader.take(limit + 1).read_to_end(&mut data)?;
+    if data.len() as u64 > limit {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("it is bigger than {} MiB", limit / 1024 / 1024),
+        ));
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kill_group() {
+        // The subshell keeps the pipe open after sh is killed, unless the group is killed
+        let mut child = spawn(
+            Command::new("sh")
+                .arg("-c")
+                .arg("(sleep 30; echo late) & sleep 30")
+                .stdout(std::process::Stdio::piped()),
+        )
+        .unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let started = Instant::now();
+        let status = wait_timeout(&mut child, Duration::from_millis(200)).unwrap();
+        assert!(status.is_none());
+        assert_eq!(read_to_end_limited(stdout, 16).unwrap(), b"");
+        assert!(started.elapsed() < Duration::from_secs(10));
+
+        let mut child = spawn(&mut Command::new("true")).unwrap();
+        let status = wait_timeout(&mut child, Duration::from_secs(5)).unwrap();
+        assert!(status.unwrap().success());
+    }
+
+    #[test]
+    fn test_read_to_end_limited() {
+        assert_eq!(read_to_end_limited(&b"1234"[..], 4).unwrap(), b"1234");
+        assert!(read_to_end_limited(&b"12345"[..], 4).is_err());
+    }
+}
@@ -0,0 +1,274 @@
+//! Put the wallpaper shown on the clipboard for `wpaperctl copy-path`, using the wlr data control
+//! protocol.
+//!
+//! The clipboard on Wayland holds an offer rather than the data, and the program making it must
+//! send the data each time it is pasted. wpaperd keeps running, so it does it itself instead of
+//! leaving a process behind like wl-copy. The path is offered as text and as a URI, and with
+//! `--image` the content of the file is offered too. The files are sent in a separate thread,
+//! so that a slow reader doesn't block the event loop.
+
+use std::{
+    fs::File,
+    io::{self, Write},
+    os::fd::OwnedFd,
+    path::{Path, PathBuf},
+    thread,
+};
+
+use image::ImageFormat;
+use log::{info, warn};
+use smithay_client_toolkit::reexports::client::{
+    event_created_child, globals::GlobalList, protocol::wl_seat::WlSeat, Connection, Dispatch,
+    QueueHandle,
+};
+use smithay_client_toolkit::reexports::protocols_wlr::data_control::v1::client::{
+    zwlr_data_control_device_v1::{self, ZwlrDataControlDeviceV1},
+    zwlr_data_control_manager_v1::{self, ZwlrDataControlManagerV1},
+    zwlr_data_control_offer_v1::{self, ZwlrDataControlOfferV1},
+    zwlr_data_control_source_v1::{self, ZwlrDataControlSourceV1},
+};
+
+use crate::Wpaperd;
+
+const TEXT_MIME_TYPES: &[&str] = &[
+    "text/plain;charset=utf-8",
+    "text/plain",
+    "UTF8_STRING",
+    "STRING",
+    "TEXT",
+];
+const URI_LIST_MIME_TYPE: &str = "text/uri-list";
+
+/// What is pasted from the clipboard
+pub struct ClipboardContent {
+    path: PathBuf,
+    /// The MIME type of the image, when its content is offered
+    image: Option<String>,
+}
+
+impl ClipboardContent {
+    pub fn new(path: PathBuf, image: bool) -> Self {
+        let image = image.then(|| image_mime_type(&path));
+        Self { path, image }
+    }
+
+    fn mime_types(&self) -> impl Iterator<Item = &str> {
+        self.image
+            .as_deref()
+            .into_iter()
+            .chain([URI_LIST_MIME_TYPE])
+            .chain(TEXT_MIME_TYPES.iter().copied())
+    }
+
+    /// Write the image, its URI or its path into `fd`, in the calling thread
+    fn send(path: &Path, image: bool, uri: bool, fd: OwnedFd) -> io::Result<()> {
+        let mut file = File::from(fd);
+        if image {
+            io::copy(&mut File::open(path)?, &mut file)?;
+        } else if uri {
+            writeln!(file, "file://{}\r", percent_encode(path))?;
+        } else {
+            file.write_all(path.to_string_lossy().as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Clipboard {
+    /// None when the compositor doesn't support the protocol
+    manager: Option<ZwlrDataControlManagerV1>,
+    device: Option<ZwlrDataControlDeviceV1>,
+    source: Option<ZwlrDataControlSourceV1>,
+}
+
+impl Clipboard {
+    pub fn new(globals: &GlobalList, qh: &QueueHandle<Wpaperd>) -> Self {
+        let manager = globals
+            .bind::<ZwlrDataControlManagerV1, _, _>(qh, 1..=2, ())
+            .inspect_err(|_| {
+                info!(
+                    "The compositor doesn't support the data control protocol, \
+                     the wallpapers can't be copied to the clipboard"
+                )
+            })
+            .ok();
+        Self {
+            manager,
+            device: None,
+            source: None,
+        }
+    }
+
+    /// Replace the selection of `seat` with `content`
+    pub fn copy(
+        &mut self,
+        seat: &WlSeat,
+        content: ClipboardContent,
+        qh: &QueueHandle<Wpaperd>,
+    ) -> Result<(), String> {
+        let manager = self
+            .manager
+            .as_ref()
+            .ok_or("the compositor doesn't support the wlr data control protocol")?;
+        let device = self
+            .device
+            .get_or_insert_with(|| manager.get_data_device(seat, qh, ()));
+        let mime_types: Vec<String> = content.mime_types().map(str::to_string).collect();
+        let source = manager.create_data_source(qh, content);
+        for mime_type in mime_types {
+            source.offer(mime_type);
+        }
+        device.set_selection(Some(&source));
+        if let Some(previous) = self.source.replace(source) {
+            previous.destroy();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Clipboard {
+    fn drop(&mut self) {
+        if let Some(device) = &self.device {
+            device.destroy();
+        }
+        if let Some(manager) = &self.manager {
+            manager.destroy();
+        }
+    }
+}
+
+/// The MIME type of the image at `path`, from its extension
+fn image_mime_type(path: &Path) -> String {
+    match ImageFormat::from_path(path) {
+        Ok(format) => format.to_mime_type().to_string(),
+        // e.g. image/heic, the image crate doesn't know the formats of the other decoders
+        Err(_) => format!(
+            "image/{}",
+            path.extension()
+                .map(|extension| extension.to_string_lossy().to_lowercase())
+                .unwrap_or_default()
+        ),
+    }
+}
+
+/// Encode the characters of the path that are not allowed in a URI
+fn percent_encode(path: &Path) -> String {
+    use std::os::unix::ffi::OsStrExt;
+
+    path.as_os_str()
+        .as_bytes()
+        .iter()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                (*byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+impl Dispatch<ZwlrDataControlManagerV1, ()> for Wpaperd {
+    fn event(
+        _state: &mut Self,
+        _proxy: &ZwlrDataControlManagerV1,
+        _event: zwlr_data_control_manager_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ZwlrDataControlDeviceV1, ()> for Wpaperd {
+    fn event(
+        state: &mut Self,
+        _proxy: &ZwlrDataControlDeviceV1,
+        event: zwlr_data_control_device_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            // The selections of the other programs are not read
+            zwlr_data_control_device_v1::Event::Selection { id: Some(offer) }
+            | zwlr_data_control_device_v1::Event::PrimarySelection { id: Some(offer) } => {
+                offer.destroy();
+            }
+            zwlr_data_control_device_v1::Event::Finished => {
+                if let Some(device) = state.clipboard.device.take() {
+                    device.destroy();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    event_created_child!(Wpaperd, ZwlrDataControlDeviceV1, [
+        zwlr_data_control_device_v1::EVT_DATA_OFFER_OPCODE => (ZwlrDataControlOfferV1, ())
+    ]);
+}
+
+impl Dispatch<ZwlrDataControlOfferV1, ()> for Wpaperd {
+    fn event(
+        _state: &mut Self,
+        _proxy: &ZwlrDataControlOfferV1,
+        _event: zwlr_data_control_offer_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ZwlrDataControlSourceV1, ClipboardContent> for Wpaperd {
+    fn event(
+        state: &mut Self,
+        proxy: &ZwlrDataControlSourceV1,
+        event: zwlr_data_control_source_v1::Event,
+        content: &ClipboardContent,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_data_control_source_v1::Event::Send { mime_type, fd } => {
+                let path = content.path.clone();
+                let image = content.image.as_ref() == Some(&mime_type);
+                let uri = mime_type == URI_LIST_MIME_TYPE;
+                thread::spawn(move || {
+                    if let Err(err) = ClipboardContent::send(&path, image, uri, fd) {
+                        warn!("Sending {path:?} to the clipboard: {err}");
+                    }
+                });
+            }
+            zwlr_data_control_source_v1::Event::Cancelled => {
+                // Another program has replaced the selection
+                if state.clipboard.source.as_ref() == Some(proxy) {
+                    state.clipboard.source = None;
+                }
+                proxy.destroy();
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content() {
+        let content = ClipboardContent::new(PathBuf::from("/walls/sunset.jpg"), true);
+        let mime_types: Vec<&str> = content.mime_types().collect();
+        assert_eq!(mime_types[..2], ["image/jpeg", URI_LIST_MIME_TYPE]);
+        assert!(mime_types.contains(&"text/plain"));
+        let content = ClipboardContent::new(PathBuf::from("/walls/sunset.heic"), false);
+        assert_eq!(content.mime_types().next(), Some(URI_LIST_MIME_TYPE));
+        assert_eq!(image_mime_type(Path::new("a.heic")), "image/heic");
+
+        assert_eq!(
+            percent_encode(Path::new("/walls/my sunset #2.png")),
+            "/walls/my%20sunset%20%232.png"
+        );
+    }
+}
@@ -0,0 +1,158 @@
+//! The first wallpapers shown at startup.
+//!
+//! The images of all the displays are decoded in parallel by the [crate::image_loader], but they
+//! are ready at different times, depending on their size and on the disk they are read from. The
+//! displays that are ready wait for the others, for up to [MAX_WAIT] after the first one, so that
+//! all the wallpapers fade in together instead of one after the other. The progress of each
+//! display is logged.
+//!
+//! With `startup-delay` set, the displays show their `background-color` during the delay and
+//! start loading their wallpapers only afterwards, so that wpaperd doesn't compete with the rest
+//! of the session for the disk.
+
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+
+use color_eyre::owo_colors::OwoColorize;
+use log::info;
+
+/// How long the displays that are ready wait for the others
+pub const MAX_WAIT: Duration = Duration::from_secs(1);
+
+pub struct ColdStart {
+    started: Instant,
+    /// The displays that have not loaded their first image yet
+    pending: HashSet<String>,
+    /// How many displays are shown at startup
+    displays: usize,
+    /// When the first display has been ready
+    first_ready: Option<Instant>,
+    finished: bool,
+    /// Set during `startup-delay`
+    delayed: bool,
+}
+
+impl Default for ColdStart {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            pending: HashSet::new(),
+            displays: 0,
+            first_ready: None,
+            finished: false,
+            delayed: false,
+        }
+    }
+}
+
+impl ColdStart {
+    /// Called when the surface of a display is created, the ones connected after the first
+    /// wallpapers have been shown don't wait
+    pub fn add_display(&mut self, name: &str) {
+        if !self.finished && self.pending.insert(name.to_string()) {
+            self.displays += 1;
+        }
+    }
+
+    /// The display is not shown anymore, or it has nothing to load
+    pub fn remove_display(&mut self, name: &str) {
+        if self.pending.remove(name) {
+            self.displays -= 1;
+        }
+    }
+
+    /// Wait until [ColdStart::resume] is called before loading the wallpapers
+    pub fn delay(&mut self) {
+        self.delayed = true;
+    }
+
+    /// `startup-delay` has passed, the time taken by the displays is counted from now
+    pub fn resume(&mut self) {
+        self.delayed = false;
+        self.started = Instant::now();
+    }
+
+    #[inline]
+    pub fn is_delayed(&self) -> bool {
+        self.delayed
+    }
+
+    /// The first image of the display has been loaded, or it could not be
+    pub fn ready(&mut self, name: &str) {
+        if !self.pending.remove(name) {
+            return;
+        }
+        self.first_ready.get_or_insert_with(Instant::now);
+        info!(
+            "Display {} is ready after {:.2}s ({}/{})",
+            name.bold().magenta(),
+            self.started.elapsed().as_secs_f32(),
+            self.displays - self.pending.len(),
+            self.displays
+        );
+    }
+
+    /// True while the displays that are ready must wait for the others
+    pub fn is_holding(&mut self) -> bool {
+        if self.finished {
+            return false;
+        }
+        let timed_out = self
+            .first_ready
+            .is_some_and(|first_ready| first_ready.elapsed() >= MAX_WAIT);
+        if !self.pending.is_empty() && !timed_out {
+            return true;
+        }
+        self.finished = true;
+        if self.pending.is_empty() {
+            info!(
+                "Showing the first wallpapers of {} displays after {:.2}s",
+                self.displays,
+                self.started.elapsed().as_secs_f32()
+            );
+        } else {
+            let mut pending: Vec<_> = self.pending.drain().collect();
+            pending.sort();
+            info!(
+                "Showing the first wallpapers after {:.2}s, without waiting for {}",
+                self.started.elapsed().as_secs_f32(),
+                pending.join(", ").bold().magenta()
+            );
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_holding() {
+        let mut cold_start = ColdStart::default();
+        cold_start.add_display("DP-1");
+        cold_start.add_display("DP-2");
+        cold_start.add_display("DP-3");
+        assert!(cold_start.is_holding());
+        cold_start.ready("DP-1");
+        cold_start.remove_display("DP-3");
+        assert!(cold_start.is_holding());
+        cold_start.ready("DP-2");
+        assert!(!cold_start.is_holding());
+        assert_eq!(cold_start.displays, 2);
+
+        // Connected afterwards
+        cold_start.add_display("HDMI-A-1");
+        assert!(!cold_start.is_holding());
+
+        // A display that never gets ready doesn't hold the others forever
+        let mut cold_start = ColdStart::default();
+        cold_start.add_display("DP-1");
+        cold_start.add_display("DP-2");
+        cold_start.ready("DP-1");
+        cold_start.first_ready = Some(Instant::now() - MAX_WAIT);
+        assert!(!cold_start.is_holding());
+    }
+}
@@ -0,0 +1,256 @@
+//! The dark or light color scheme of the desktop, to show other directories in dark mode.
+//!
+//! It is the `color-scheme` key of `org.freedesktop.appearance`, read from the settings of the
+//! XDG desktop portal with `gdbus`, the tool of GLib, that must be installed at runtime. Once a
+//! display sets `color-scheme`, `gdbus monitor` is started to follow the changes and it is stopped
+//! once no display sets it anymore. The scheme is light when the desktop has no preference.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::{BufRead, BufReader, ErrorKind},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+};
+
+use color_eyre::{
+    eyre::{anyhow, Context, ContextCompat},
+    Result,
+};
+use log::{debug, warn};
+use serde::{Deserialize, Deserializer};
+use smithay_client_toolkit::reexports::calloop::{
+    self,
+    channel::{Event, Sender},
+    LoopHandle,
+};
+
+use crate::{config::tilde_expansion, Wpaperd};
+
+const GDBUS: &str = "gdbus";
+const PORTAL: [&str; 4] = [
+    "--dest",
+    "org.freedesktop.portal.Desktop",
+    "--object-path",
+    "/org/freedesktop/portal/desktop",
+];
+const NAMESPACE: &str = "org.freedesktop.appearance";
+const KEY: &str = "color-scheme";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorScheme {
+    Dark,
+    Light,
+}
+
+impl fmt::Display for ColorScheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ColorScheme::Dark => "dark",
+            ColorScheme::Light => "light",
+        })
+    }
+}
+
+impl ColorScheme {
+    /// Read the value of the portal: 1 prefers dark, 2 prefers light and 0 has no preference
+    fn from_portal(value: u32) -> Self {
+        match value {
+            1 => ColorScheme::Dark,
+            _ => ColorScheme::Light,
+        }
+    }
+}
+
+pub fn deserialize_color_scheme_paths<'de, D>(
+    deserializer: D,
+) -> Result<Option<BTreeMap<ColorScheme, PathBuf>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let paths = BTreeMap::<ColorScheme, String>::deserialize(deserializer)?;
+    Ok(Some(
+        paths
+            .into_iter()
+            .map(|(scheme, path)| (scheme, tilde_expansion(Path::new(&path))))
+            .collect(),
+    ))
+}
+
+/// The value in the output of gdbus, e.g. `(<<uint32 1>>,)`
+fn parse_value(output: &str) -> Option<ColorScheme> {
+    let value = output.split("uint32 ").nth(1)?;
+    let end = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    Some(ColorScheme::from_portal(value[..end].parse().ok()?))
+}
+
+/// The new value in a line printed by `gdbus monitor`, when it is a change of the color scheme
+fn parse_signal(line: &str) -> Option<ColorScheme> {
+    let setting = format!("('{NAMESPACE}', '{KEY}', ");
+    let (_, value) = line.split_once("SettingChanged ")?.1.split_once(&setting)?;
+    parse_value(value)
+}
+
+/// Read the color scheme once
+fn read() -> Result<ColorScheme> {
+    let call = |method: &str| {
+        Command::new(GDBUS)
+            .args(["call", "--session"])
+            .args(PORTAL)
+            .args(["--method", method, NAMESPACE, KEY])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+    };
+    let mut output = call("org.freedesktop.portal.Settings.ReadOne")
+        .with_context(|| format!("running {GDBUS}"))?;
+    if !output.status.success() {
+        // ReadOne has been added by the version 2 of the portal, Read wraps the value in another
+        // variant
+        output = call("org.freedesktop.portal.Settings.Read")
+            .with_context(|| format!("running {GDBUS}"))?;
+    }
+    if !output.status.success() {
+        return Err(anyhow!(
+            "reading {NAMESPACE} {KEY} from the settings portal: {GDBUS} exited with {}",
+            output.status
+        ));
+    }
+    let output = String::from_utf8_lossy(&output.stdout);
+    parse_value(&output)
+        .with_context(|| format!("unexpected reply from the settings portal: {output:?}"))
+}
+
+/// Follow the color scheme in another thread, the displays are updated with
+/// [crate::config::Config::set_color_scheme] each time it changes
+pub struct ColorSchemeWatcher {
+    sender: Sender<ColorScheme>,
+    /// `gdbus monitor`, while a display sets `color-scheme`
+    monitor: Option<Child>,
+}
+
+impl ColorSchemeWatcher {
+    pub fn new(event_loop_handle: &LoopHandle<'static, Wpaperd>) -> Result<Self> {
+        let (sender, channel) = calloop::channel::channel();
+        event_loop_handle
+            .insert_source(channel, |event, _, wpaperd| {
+                if let Event::Msg(scheme) = event {
+                    wpaperd.config.set_color_scheme(Some(scheme));
+                }
+            })
+            .map_err(|e| anyhow!("inserting the color scheme channel in the event loop: {e}"))?;
+        Ok(Self {
+            sender,
+            monitor: None,
+        })
+    }
+
+    /// Called when the configuration is loaded, `enabled` when a display sets `color-scheme`.
+    /// When `gdbus` fails, it is started again by the next reload
+    pub fn update(&mut self, enabled: bool) {
+        if !enabled {
+            self.stop();
+            return;
+        }
+        if let Some(Ok(Some(status))) = self.monitor.as_mut().map(Child::try_wait) {
+            debug!("{GDBUS} monitor exited with {status}");
+            self.monitor = None;
+        }
+        if self.monitor.is_some() {
+            return;
+        }
+        match self.start() {
+            Ok(monitor) => self.monitor = Some(monitor),
+            Err(err) => {
+                let not_found = err
+                    .downcast_ref::<std::io::Error>()
+                    .is_some_and(|err| err.kind() == ErrorKind::NotFound);
+                if not_found {
+                    warn!("{GDBUS} was not found, install glib to use color-scheme");
+                } else {
+                    warn!("{err:?}");
+                }
+            }
+        }
+    }
+
+    fn start(&self) -> Result<Child> {
+        // Started before reading the value, so that no change is missed
+        let mut monitor = Command::new(GDBUS)
+            .args(["monitor", "--session"])
+            .args(PORTAL)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .with_context(|| format!("running {GDBUS} monitor"))?;
+        let stdout = monitor.stdout.take().expect("stdout to be piped");
+        let sender = self.sender.clone();
+        let spawned = std::thread::Builder::new()
+            .name("color-scheme".to_string())
+            .spawn(move || {
+                match read() {
+                    Ok(scheme) => {
+                        if sender.send(scheme).is_err() {
+                            return;
+                        }
+                    }
+                    Err(err) => warn!("{err:?}"),
+                }
+                for line in BufReader::new(stdout).lines() {
+                    let Ok(line) = line else {
+                        break;
+                    };
+                    if let Some(scheme) = parse_signal(&line) {
+                        if sender.send(scheme).is_err() {
+                            break;
+                        }
+                    }
+                }
+            });
+        if let Err(err) = spawned {
+            let _ = monitor.kill();
+            let _ = monitor.wait();
+            return Err(err).context("spawning the color scheme thread");
+        }
+        Ok(monitor)
+    }
+
+    fn stop(&mut self) {
+        if let Some(mut monitor) = self.monitor.take() {
+            let _ = monitor.kill();
+            let _ = monitor.wait();
+        }
+    }
+}
+
+impl Drop for ColorSchemeWatcher {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse_value("(<uint32 1>,)\n"), Some(ColorScheme::Dark));
+        assert_eq!(parse_value("(<<uint32 2>>,)\n"), Some(ColorScheme::Light));
+        assert_eq!(parse_value("(<<uint32 0>>,)\n"), Some(ColorScheme::Light));
+        assert_eq!(parse_value("()"), None);
+
+        let changed = "/org/freedesktop/portal/desktop: \
+            org.freedesktop.portal.Settings.SettingChanged \
+            ('org.freedesktop.appearance', 'color-scheme', <uint32 1>)";
+        assert_eq!(parse_signal(changed), Some(ColorScheme::Dark));
+        let accent = "/org/freedesktop/portal/desktop: \
+            org.freedesktop.portal.Settings.SettingChanged \
+            ('org.freedesktop.appearance', 'contrast', <uint32 1>)";
+        assert_eq!(parse_signal(accent), None);
+    }
+}
@@ -0,0 +1,172 @@
+//! The color spaces of the images and of the displays.
+//!
+//! The images are assumed to be sRGB, unless the ICC profile embedded in them is a Display P3 one,
+//! like the photos taken by the iPhones. The displays are assumed to be sRGB as well, unless their
+//! `color-space` says otherwise, e.g. for the wide-gamut monitors that show the sRGB colors
+//! oversaturated. When the two differ, the shader converts the colors in linear light; when they
+//! match, the colors are drawn as they are, so that the sRGB displays are not converted twice.
+
+use std::path::Path;
+
+use image::{io::Reader as ImageReader, ImageDecoder};
+use serde::Deserialize;
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorSpace {
+    #[default]
+    Srgb,
+    DisplayP3,
+}
+
+/// Row-major matrices converting linear light, both spaces use the D65 white point
+const P3_TO_SRGB: [f32; 9] = [
+    1.224_94, -0.224_94, 0.0, //
+    -0.042_06, 1.042_06, 0.0, //
+    -0.019_64, -0.078_64, 1.098_28,
+];
+const SRGB_TO_P3: [f32; 9] = [
+    0.822_46, 0.177_54, 0.0, //
+    0.033_19, 0.966_81, 0.0, //
+    0.017_08, 0.072_40, 0.910_52,
+];
+
+impl ColorSpace {
+    /// The space of the image, read from the ICC profile embedded in it
+    pub fn of_image(path: &Path) -> Self {
+        ImageReader::open(path)
+            .ok()
+            .and_then(|reader| reader.with_guessed_format().ok())
+            .and_then(|reader| reader.into_decoder().ok())
+            .and_then(|mut decoder| decoder.icc_profile().ok().flatten())
+            .map_or(ColorSpace::Srgb, |profile| Self::of_icc_profile(&profile))
+    }
+
+    /// The profiles are recognized by their description, e.g. "Display P3" for the ones of Apple
+    fn of_icc_profile(profile: &[u8]) -> Self {
+        match icc_description(profile) {
+            Some(description) if description.contains("P3") => ColorSpace::DisplayP3,
+            _ => ColorSpace::Srgb,
+        }
+    }
+
+    /// The matrix converting the colors from this space to `target`, `None` when they match
+    pub fn conversion(self, target: ColorSpace) -> Option<[f32; 9]> {
+        match (self, target) {
+            (ColorSpace::DisplayP3, ColorSpace::Srgb) => Some(P3_TO_SRGB),
+            (ColorSpace::Srgb, ColorSpace::DisplayP3) => Some(SRGB_TO_P3),
+            _ => None,
+        }
+    }
+}
+
+/// The sRGB transfer function, from the encoded value to linear light. Display P3 uses it as well
+pub fn srgb_decode(value: f32) -> f32 {
+    if value <= 0.040_45 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// The inverse of [srgb_decode]
+pub fn srgb_encode(linear: f32) -> f32 {
+    if linear <= 0.003_130_8 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Read the `desc` tag of the profile, either a `desc` text (ICC v2) or the first string of a
+/// `mluc` (ICC v4)
+fn icc_description(profile: &[u8]) -> Option<String> {
+    let u32_at = |offset: usize| -> Option<usize> {
+        let bytes = profile.get(offset..offset + 4)?;
+        Some(u32::from_be_bytes(bytes.try_into().ok()?) as usize)
+    };
+    // The tag table follows the header of 128 bytes, each entry is 12 bytes long
+    let tags = u32_at(128)?;
+    let (offset, size) = (0..tags.min(100)).find_map(|index| {
+        let entry = 132 + index * 12;
+        (profile.get(entry..entry + 4)? == b"desc")
+            .then(|| Some((u32_at(entry + 4)?, u32_at(entry + 8)?)))
+            .flatten()
+    })?;
+    let tag = profile.get(offset..offset.checked_add(size)?)?;
+    match tag.get(0..4)? {
+        b"desc" => {
+            let length = u32::from_be_bytes(tag.get(8..12)?.try_into().ok()?) as usize;
+            let text = tag.get(12..12 + length)?;
+            Some(
+                String::from_utf8_lossy(text)
+                    .trim_end_matches('\0')
+                    .to_string(),
+            )
+        }
+        b"mluc" => {
+            let length = u32::from_be_bytes(tag.get(20..24)?.try_into().ok()?) as usize;
+            let start = u32::from_be_bytes(tag.get(24..28)?.try_into().ok()?) as usize;
+            let units: Vec<u16> = tag
+                .get(start..start + length)?
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            Some(String::from_utf16_lossy(&units))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A profile with only the `desc` tag, with the given content
+    fn profile(tag: &[u8]) -> Vec<u8> {
+        let mut profile = vec![0; 128];
+        profile.extend(1u32.to_be_bytes());
+        profile.extend(b"desc");
+        profile.extend(144u32.to_be_bytes());
+        profile.extend((tag.len() as u32).to_be_bytes());
+        profile.extend(tag);
+        profile
+    }
+
+    #[test]
+    fn test_of_icc_profile() {
+        // ICC v4, as embedded by the iPhones
+        let name: Vec<u8> = "Display P3"
+            .encode_utf16()
+            .flat_map(u16::to_be_bytes)
+            .collect();
+        let mut mluc = b"mluc\0\0\0\0".to_vec();
+        mluc.extend(1u32.to_be_bytes());
+        mluc.extend(12u32.to_be_bytes());
+        mluc.extend(b"enUS");
+        mluc.extend((name.len() as u32).to_be_bytes());
+        mluc.extend(28u32.to_be_bytes());
+        mluc.extend(&name);
+        assert_eq!(
+            ColorSpace::of_icc_profile(&profile(&mluc)),
+            ColorSpace::DisplayP3
+        );
+
+        // ICC v2
+        let mut desc = b"desc\0\0\0\0".to_vec();
+        desc.extend(17u32.to_be_bytes());
+        desc.extend(b"sRGB IEC61966-2.1\0");
+        assert_eq!(
+            ColorSpace::of_icc_profile(&profile(&desc)),
+            ColorSpace::Srgb
+        );
+        assert_eq!(ColorSpace::of_icc_profile(&[0; 16]), ColorSpace::Srgb);
+
+        assert_eq!(ColorSpace::Srgb.conversion(ColorSpace::Srgb), None);
+        // The white stays white
+        let matrix = ColorSpace::DisplayP3.conversion(ColorSpace::Srgb).unwrap();
+        for row in matrix.chunks(3) {
+            assert!((row.iter().sum::<f32>() - 1.0).abs() < 1e-3);
+        }
+    }
+}
@@ -0,0 +1,378 @@
+//! The IPC of Sway and Hyprland, found from the environment, used in addition to the Wayland
+//! protocols when `compositor-ipc` is enabled.
+//!
+//! It names the displays that wl_output doesn't name, i.e. before its version 4: the output listed
+//! by the compositor at the same position and with the same model gives its name and description.
+//! The workspaces shown on each display are followed by [crate::workspaces::WorkspaceWatcher].
+
+use std::{
+    env,
+    io::{BufRead, BufReader, Read, Write},
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use color_eyre::{
+    eyre::{ensure, Context, ContextCompat},
+    Result,
+};
+use log::debug;
+use serde_json::Value;
+use smithay_client_toolkit::{output::OutputInfo, reexports::calloop::channel::Sender};
+
+/// How long a reply of the compositor is waited for
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+const SWAY_GET_WORKSPACES: u32 = 1;
+const SWAY_SUBSCRIBE: u32 = 2;
+const SWAY_GET_OUTPUTS: u32 = 3;
+const SWAY_MAGIC: &[u8] = b"i3-ipc";
+
+/// The events of Hyprland after which the monitors are listed again
+const HYPRLAND_EVENTS: [&str; 10] = [
+    "workspace",
+    "workspacev2",
+    "focusedmon",
+    "focusedmonv2",
+    "moveworkspace",
+    "moveworkspacev2",
+    "renameworkspace",
+    "monitoradded",
+    "monitoraddedv2",
+    "configreloaded",
+];
+
+/// The workspace shown on each display, `None` when it shows none
+pub type Workspaces = Vec<(String, Option<String>)>;
+
+/// A display as listed by the compositor
+#[derive(Debug, PartialEq)]
+pub struct IpcOutput {
+    pub name: String,
+    pub description: String,
+    pub model: String,
+    /// In the global compositor space, like the one of wl_output
+    pub location: (i32, i32),
+}
+
+pub enum Compositor {
+    /// The path of the IPC socket
+    Sway(PathBuf),
+    /// The directory of the sockets of the instance
+    Hyprland(PathBuf),
+}
+
+impl Compositor {
+    /// The compositor wpaperd is running on, when its IPC is supported
+    pub fn detect() -> Option<Self> {
+        if let Some(socket) = env::var_os("SWAYSOCK") {
+            Some(Self::Sway(PathBuf::from(socket)))
+        } else {
+            let signature = env::var_os("HYPRLAND_INSTANCE_SIGNATURE")?;
+            Some(Self::Hyprland(hyprland_dir(Path::new(&signature))))
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Compositor::Sway(_) => "sway",
+            Compositor::Hyprland(_) => "hyprland",
+        }
+    }
+
+    /// The displays connected
+    pub fn outputs(&self) -> Result<Vec<IpcOutput>> {
+        match self {
+            Compositor::Sway(socket) => {
+                let mut stream = sway_connect(socket)?;
+                stream.set_read_timeout(Some(TIMEOUT))?;
+                sway_send(&mut stream, SWAY_GET_OUTPUTS, b"")?;
+                Ok(sway_outputs(&sway_receive(&mut stream)?))
+            }
+            Compositor::Hyprland(dir) => {
+                Ok(hyprland_outputs(&hyprland_request(dir, b"j/monitors")?))
+            }
+        }
+    }
+
+    /// Send the workspaces each time they change, until the receiver is dropped
+    pub fn watch_workspaces(&self, sender: &Sender<Workspaces>) -> Result<()> {
+        match self {
+            Compositor::Sway(socket) => watch_sway(socket, sender),
+            Compositor::Hyprland(dir) => watch_hyprland(dir, sender),
+        }
+    }
+}
+
+/// Fill the name and the description of the display when wl_output doesn't send them, from the
+/// outputs listed by the compositor
+pub fn complete_output_info(info: &mut OutputInfo, outputs: &[IpcOutput]) -> bool {
+    // The position sent by xdg-output is the one in the global compositor space
+    let location = info.logical_position.unwrap_or(info.location);
+    let Some(output) = find_output(outputs, location, &info.model) else {
+        return false;
+    };
+    info.name.get_or_insert_with(|| output.name.clone());
+    info.description
+        .get_or_insert_with(|| output.description.clone());
+    true
+}
+
+fn find_output<'a>(
+    outputs: &'a [IpcOutput],
+    location: (i32, i32),
+    model: &str,
+) -> Option<&'a IpcOutput> {
+    outputs
+        .iter()
+        .find(|output| output.location == location && output.model == model)
+}
+
+fn sway_connect(socket: &Path) -> Result<UnixStream> {
+    UnixStream::connect(socket).with_context(|| format!("connecting to the sway socket {socket:?}"))
+}
+
+/// Send a message to the IPC socket of Sway
+fn sway_send(stream: &mut UnixStream, kind: u32, payload: &[u8]) -> Result<()> {
+    let mut message = SWAY_MAGIC.to_vec();
+    message.extend((payload.len() as u32).to_ne_bytes());
+    message.extend(kind.to_ne_bytes());
+    message.extend(payload);
+    stream
+        .write_all(&message)
+        .context("writing to the sway socket")
+}
+
+/// Receive a reply or an event from the IPC socket of Sway
+fn sway_receive(stream: &mut UnixStream) -> Result<Value> {
+    let mut header = [0; 14];
+    stream
+        .read_exact(&mut header)
+        .context("reading from the sway socket")?;
+    ensure!(
+        header.starts_with(SWAY_MAGIC),
+        "invalid message from the sway socket"
+    );
+    let length = u32::from_ne_bytes(header[6..10].try_into().unwrap());
+    let mut payload = vec![0; length as usize];
+    stream
+        .read_exact(&mut payload)
+        .context("reading from the sway socket")?;
+    serde_json::from_slice(&payload).context("parsing the message of the sway socket")
+}
+
+/// The outputs in a reply to GET_OUTPUTS of Sway
+fn sway_outputs(outputs: &Value) -> Vec<IpcOutput> {
+    outputs
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|output| {
+            let field = |name: &str| output[name].as_str().unwrap_or_default();
+            Some(IpcOutput {
+                name: output["name"].as_str()?.to_string(),
+                description: format!("{} {} {}", field("make"), field("model"), field("serial")),
+                model: field("model").to_string(),
+                location: (
+                    output["rect"]["x"].as_i64()? as i32,
+                    output["rect"]["y"].as_i64()? as i32,
+                ),
+            })
+        })
+        .collect()
+}
+
+/// The workspaces visible in a reply to GET_WORKSPACES of Sway
+fn sway_visible(workspaces: &Value) -> Workspaces {
+    workspaces
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|workspace| workspace["visible"].as_bool() == Some(true))
+        .filter_map(|workspace| {
+            Some((
+                workspace["output"].as_str()?.to_string(),
+                Some(workspace["name"].as_str()?.to_string()),
+            ))
+        })
+        .collect()
+}
+
+/// List the workspaces each time they change, until wpaperd exits
+fn watch_sway(socket: &Path, sender: &Sender<Workspaces>) -> Result<()> {
+    let mut events = sway_connect(socket)?;
+    // The replies would mix with the events on the same socket
+    let mut queries = sway_connect(socket)?;
+    sway_send(&mut events, SWAY_SUBSCRIBE, br#"["workspace","output"]"#)?;
+    let reply = sway_receive(&mut events)?;
+    ensure!(
+        reply["success"].as_bool() == Some(true),
+        "subscribing to the workspace events of sway: {reply}"
+    );
+    debug!("Watching the workspaces of sway");
+    loop {
+        sway_send(&mut queries, SWAY_GET_WORKSPACES, b"")?;
+        let workspaces = sway_visible(&sway_receive(&mut queries)?);
+        if sender.send(workspaces).is_err() {
+            return Ok(());
+        }
+        sway_receive(&mut events)?;
+    }
+}
+
+/// The directory of the sockets of Hyprland, in `/tmp` for the versions before 0.40
+fn hyprland_dir(signature: &Path) -> PathBuf {
+    let runtime_dir = dirs::runtime_dir().map(|dir| dir.join("hypr").join(signature));
+    match runtime_dir {
+        Some(dir) if dir.exists() => dir,
+        _ => Path::new("/tmp/hypr").join(signature),
+    }
+}
+
+/// Send a command to Hyprland, e.g. `j/monitors`, and parse its reply
+fn hyprland_request(dir: &Path, command: &[u8]) -> Result<Value> {
+    let socket = dir.join(".socket.sock");
+    let mut stream = UnixStream::connect(&socket)
+        .with_context(|| format!("connecting to the hyprland socket {socket:?}"))?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream
+        .write_all(command)
+        .context("writing to the hyprland socket")?;
+    let mut reply = Vec::new();
+    stream
+        .read_to_end(&mut reply)
+        .context("reading from the hyprland socket")?;
+    serde_json::from_slice(&reply).context("parsing the reply of the hyprland socket")
+}
+
+/// The outputs in the reply to `j/monitors` of Hyprland
+fn hyprland_outputs(monitors: &Value) -> Vec<IpcOutput> {
+    monitors
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|monitor| {
+            Some(IpcOutput {
+                name: monitor["name"].as_str()?.to_string(),
+                description: monitor["description"].as_str()?.to_string(),
+                model: monitor["model"].as_str().unwrap_or_default().to_string(),
+                location: (monitor["x"].as_i64()? as i32, monitor["y"].as_i64()? as i32),
+            })
+        })
+        .collect()
+}
+
+/// The active workspaces in the reply to `j/monitors` of Hyprland
+fn hyprland_active(monitors: &Value) -> Workspaces {
+    monitors
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|monitor| {
+            Some((
+                monitor["name"].as_str()?.to_string(),
+                monitor["activeWorkspace"]["name"]
+                    .as_str()
+                    .map(str::to_string),
+            ))
+        })
+        .collect()
+}
+
+/// List the monitors each time the workspaces change, until wpaperd exits
+fn watch_hyprland(dir: &Path, sender: &Sender<Workspaces>) -> Result<()> {
+    let socket = dir.join(".socket2.sock");
+    let events = UnixStream::connect(&socket)
+        .with_context(|| format!("connecting to the hyprland socket {socket:?}"))?;
+    let mut events = BufReader::new(events).lines();
+    debug!("Watching the workspaces of hyprland");
+    loop {
+        let monitors = hyprland_request(dir, b"j/monitors")?;
+        if sender.send(hyprland_active(&monitors)).is_err() {
+            return Ok(());
+        }
+        loop {
+            let line = events
+                .next()
+                .context("the hyprland socket has been closed")?
+                .context("reading from the hyprland socket")?;
+            let event = line.split(">>").next().unwrap_or_default();
+            if HYPRLAND_EVENTS.contains(&event) {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_replies() {
+        let workspaces = json!([
+            { "name": "1", "output": "DP-1", "visible": false },
+            { "name": "2: web", "output": "DP-1", "visible": true },
+            { "name": "3", "output": "HDMI-A-1", "visible": true },
+        ]);
+        assert_eq!(
+            sway_visible(&workspaces),
+            [
+                ("DP-1".to_string(), Some("2: web".to_string())),
+                ("HDMI-A-1".to_string(), Some("3".to_string())),
+            ]
+        );
+        let outputs = json!([{
+            "name": "DP-1",
+            "make": "Dell Inc.",
+            "model": "DELL U2720Q",
+            "serial": "ABC123",
+            "rect": { "x": 1920, "y": 0, "width": 2560, "height": 1440 },
+        }]);
+        let outputs = sway_outputs(&outputs);
+        assert_eq!(
+            outputs,
+            [IpcOutput {
+                name: "DP-1".to_string(),
+                description: "Dell Inc. DELL U2720Q ABC123".to_string(),
+                model: "DELL U2720Q".to_string(),
+                location: (1920, 0),
+            }]
+        );
+
+        assert_eq!(
+            find_output(&outputs, (1920, 0), "DELL U2720Q").map(|output| &output.name),
+            Some(&"DP-1".to_string())
+        );
+        assert_eq!(find_output(&outputs, (0, 0), "DELL U2720Q"), None);
+
+        let monitors = json!([
+            {
+                "name": "DP-1",
+                "description": "Dell Inc. DELL U2720Q ABC123",
+                "model": "DELL U2720Q",
+                "x": 0,
+                "y": 0,
+                "activeWorkspace": { "id": 4, "name": "4" },
+            },
+            {
+                "name": "DP-2",
+                "description": "LG Electronics 27GL850",
+                "x": 2560,
+                "y": 0,
+                "activeWorkspace": { "id": -98, "name": "special:term" },
+            },
+        ]);
+        assert_eq!(
+            hyprland_active(&monitors),
+            [
+                ("DP-1".to_string(), Some("4".to_string())),
+                ("DP-2".to_string(), Some("special:term".to_string())),
+            ]
+        );
+        assert_eq!(hyprland_outputs(&monitors)[1].location, (2560, 0));
+    }
+}
@@ -0,0 +1,3014 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, fs,
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use chrono::{Local, NaiveDate};
+use color_eyre::{
+    eyre::{anyhow, ensure, Context, ContextCompat, Report},
+    owo_colors::OwoColorize,
+    Result, Section, SectionExt,
+};
+use dirs::home_dir;
+use log::{debug, error, warn};
+use serde::Deserialize;
+use toml_edit::DocumentMut;
+
+use crate::{
+    backend::Backend,
+    brightness::{Brightness, BrightnessSource},
+    calendar::{deserialize_seasons, CalendarEntry},
+    color_scheme::{deserialize_color_scheme_paths, ColorScheme},
+    color_space::ColorSpace,
+    conflicts::OnConflict,
+    cron::CronSchedule,
+    diagnostic::{locate, strip_colors},
+    events::{Event, EventSender},
+    filelist_cache::FilelistOptions,
+    filter::ImageFilter,
+    hooks::Hooks,
+    image_loader::DecodeLimits,
+    image_picker::ImagePicker,
+    lock_image::LockImage,
+    quiet_hours::QuietHours,
+    render::Transition,
+    script::SelectionScript,
+    text_region::TextRegion,
+    theme::Theme,
+    tone_mapping::ToneMapping,
+    wallpaper_info::{
+        BackgroundMode, Color, HorizontalAlign, InitialImage, Pool, Recolor, ResizeFilter,
+        Rotation, Sorting, Source, TransitionSkip, VerticalAlign, WallpaperInfo,
+    },
+    watcher::{symlink_chain, Watcher},
+    weather::{deserialize_weather_paths, Condition, Weather},
+    workspaces::deserialize_workspace_paths,
+};
+
+#[derive(Default, Deserialize, PartialEq, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SerializedWallpaperInfo {
+    #[serde(default, deserialize_with = "tilde_expansion_deserialize")]
+    pub path: Option<PathBuf>,
+    #[serde(default, with = "humantime_serde")]
+    pub duration: Option<Duration>,
+    /// Change the wallpaper at the times matching this cron expression, in place of `duration`
+    ///
+    /// See [crate::cron::CronSchedule]
+    pub schedule: Option<CronSchedule>,
+    /// Change the wallpaper once per calendar period, in place of `duration` and `schedule`
+    ///
+    /// See [crate::wallpaper_info::Rotation]
+    pub rotation: Option<Rotation>,
+    #[serde(rename = "apply-shadow")]
+    pub apply_shadow: Option<bool>,
+    pub sorting: Option<Sorting>,
+    /// Go through the sorted images in the opposite order
+    ///
+    /// See [crate::wallpaper_info::WallpaperInfo]
+    pub reverse: Option<bool>,
+    pub mode: Option<BackgroundMode>,
+    #[serde(rename = "queue-size")]
+    pub queue_size: Option<usize>,
+    #[serde(rename = "transition-time")]
+    pub transition_time: Option<u32>,
+    /// Maximum number of frames drawn each second during the transitions
+    #[serde(rename = "max-fps")]
+    pub max_fps: Option<u32>,
+    /// How the images are scaled to the size of the display
+    ///
+    /// See [crate::wallpaper_info::ResizeFilter]
+    #[serde(rename = "resize-filter")]
+    pub resize_filter: Option<ResizeFilter>,
+    /// Longest side the images are decoded at, in pixels
+    ///
+    /// See [crate::image_loader::decode_capped]
+    #[serde(rename = "max-decode-size")]
+    pub max_decode_size: Option<u32>,
+    /// Dither the high bit depth images shown on the display when reducing them to 8 bits,
+    /// `dither` of the `tone-mapping` section by default
+    pub dither: Option<bool>,
+    /// Scale the images in linear light, `true` by default
+    ///
+    /// See [crate::image_loader::resize]
+    #[serde(rename = "linear-scaling")]
+    pub linear_scaling: Option<bool>,
+    /// The color space of the display, the images are converted to it
+    ///
+    /// See [crate::color_space::ColorSpace]
+    #[serde(rename = "color-space")]
+    pub color_space: Option<ColorSpace>,
+    /// Draw the images in shades of this color
+    ///
+    /// See [crate::wallpaper_info::Recolor]
+    pub tint: Option<Color>,
+    /// Draw the images with a gradient from the first color to the second one, in place of `tint`
+    ///
+    /// See [crate::wallpaper_info::Recolor]
+    pub duotone: Option<[Color; 2]>,
+    /// Size of the tiles relative to the image, in tile mode
+    ///
+    /// See [crate::wallpaper_info::WallpaperInfo]
+    #[serde(rename = "tile-scale")]
+    pub tile_scale: Option<f32>,
+    /// Space between the tiles in pixels, in tile mode
+    ///
+    /// See [crate::wallpaper_info::WallpaperInfo]
+    #[serde(rename = "tile-gap")]
+    pub tile_gap: Option<u32>,
+    /// Play the animated images instead of showing their first frame
+    ///
+    /// See [crate::animation]
+    pub animate: Option<bool>,
+    /// Divides the delays of the frames of the animations
+    ///
+    /// See [crate::animation]
+    #[serde(rename = "animation-speed")]
+    pub animation_speed: Option<f32>,
+    /// Keep the animations on their first frame until they are resumed
+    ///
+    /// See [crate::animation]
+    #[serde(rename = "start-paused")]
+    pub start_paused: Option<bool>,
+    /// How much the wallpaper pulses with the audio played, from 0 to 1, needs the `audio`
+    /// feature
+    #[serde(rename = "audio-reactive")]
+    pub audio_reactive: Option<f32>,
+    /// Shift the image towards the pointer by up to this fraction of the display
+    ///
+    /// See [crate::parallax]
+    pub parallax: Option<f32>,
+
+    /// Determines if we should show the transition between black and first
+    /// wallpaper. `Some(false)` means we instantly cut to the first wallpaper,
+    /// `Some(true)` means we fade from black to the first wallpaper.
+    ///
+    /// See [crate::wallpaper_info::WallpaperInfo]
+    #[serde(rename = "initial-transition")]
+    pub initial_transition: Option<bool>,
+    pub transition: Option<Transition>,
+
+    /// Shown at startup until the first wallpaper has been loaded
+    ///
+    /// See [crate::wallpaper_info::WallpaperInfo]
+    #[serde(rename = "background-color")]
+    pub background_color: Option<Color>,
+
+    /// Shown when no wallpaper can be loaded
+    ///
+    /// See [crate::wallpaper_info::WallpaperInfo]
+    #[serde(rename = "error-color")]
+    pub error_color: Option<Color>,
+
+    /// Determine the offset for the wallpaper to be drawn into the screen
+    /// Must be from 0.0 to 1.0, by default is 0.0 in tile mode and 0.5 in all the others
+    ///
+    /// See [crate::wallpaper_info::WallpaperInfo]
+    pub offset: Option<f32>,
+    /// Align the image to a side of the display when it is cropped, in place of `offset`
+    ///
+    /// See [crate::wallpaper_info::HorizontalAlign]
+    #[serde(rename = "horizontal-align")]
+    pub horizontal_align: Option<HorizontalAlign>,
+    /// See [crate::wallpaper_info::VerticalAlign]
+    #[serde(rename = "vertical-align")]
+    pub vertical_align: Option<VerticalAlign>,
+
+    /// Follow the symlinks when reading the directory in `path`
+    ///
+    /// See [crate::filelist_cache::FilelistOptions]
+    #[serde(rename = "follow-symlinks")]
+    pub follow_symlinks: Option<bool>,
+
+    /// Skip the images in `path` that have the same content of another one
+    ///
+    /// See [crate::filelist_cache::FilelistOptions]
+    pub deduplicate: Option<bool>,
+
+    /// Skip the images in `path` rated below this number of stars
+    ///
+    /// See [crate::xmp]
+    #[serde(rename = "min-rating")]
+    pub min_rating: Option<u8>,
+
+    /// Only show the images in `path` matching this expression
+    ///
+    /// See [crate::filter]
+    pub filter: Option<String>,
+
+    /// Seed used to pick the images when sorting is random
+    ///
+    /// See [crate::wallpaper_info::WallpaperInfo]
+    pub seed: Option<u64>,
+
+    /// Share the images picked randomly with the other displays showing the same directory
+    ///
+    /// See [crate::wallpaper_info::Pool]
+    pub pool: Option<Pool>,
+
+    /// Change the image at the same time on all the machines using the same configuration
+    ///
+    /// See [crate::sync_clock::SyncClock]
+    pub sync: Option<bool>,
+
+    /// The image to show when wpaperd starts
+    ///
+    /// See [crate::wallpaper_info::InitialImage]
+    pub initial: Option<InitialImage>,
+
+    /// File listing the images to show, in place of `path`
+    ///
+    /// See [crate::source::PlaylistSource]
+    #[serde(default, deserialize_with = "tilde_expansion_deserialize")]
+    pub playlist: Option<PathBuf>,
+
+    /// Url of the image to download each time, in place of `path`
+    ///
+    /// See [crate::source::UrlSource]
+    pub url: Option<String>,
+
+    /// Command asked for the images to show, in place of `path`
+    ///
+    /// See [crate::source::ProviderSource]
+    pub provider: Option<String>,
+
+    /// Command run each time the image must change, printing it, in place of `path`
+    ///
+    /// See [crate::source::CommandSource]
+    pub command: Option<String>,
+
+    /// Directory whose image modified last is shown, in place of `path`
+    ///
+    /// See [crate::source::NewestSource]
+    #[serde(default, deserialize_with = "tilde_expansion_deserialize")]
+    pub newest: Option<PathBuf>,
+
+    /// Blur the image shown by `newest`, the value is the standard deviation in pixels
+    #[serde(rename = "newest-blur")]
+    pub newest_blur: Option<f32>,
+
+    /// Script choosing the images in the directory set in `path`
+    ///
+    /// See [crate::script::SelectionScript]
+    #[serde(default, deserialize_with = "tilde_expansion_deserialize")]
+    pub script: Option<PathBuf>,
+
+    /// Show the same images of another display, in place of all the other values
+    ///
+    /// See [crate::wallpaper_info::WallpaperInfo]
+    pub mirror: Option<String>,
+
+    /// Directories used in place of `path` on some days
+    ///
+    /// See [crate::calendar::CalendarEntry]
+    pub calendar: Option<Vec<CalendarEntry>>,
+    /// Directories used in place of `path` during date ranges, after the calendar
+    ///
+    /// See [crate::calendar::deserialize_seasons]
+    #[serde(default, deserialize_with = "deserialize_seasons")]
+    pub seasons: Option<Vec<CalendarEntry>>,
+    /// Directories used in place of `path`, and of the calendar, during some weather conditions
+    ///
+    /// See [crate::weather::Weather]
+    #[serde(default, deserialize_with = "deserialize_weather_paths")]
+    pub weather: Option<BTreeMap<Condition, PathBuf>>,
+    /// Directories used in place of `path`, of the calendar and of the weather, in dark or light
+    /// mode
+    ///
+    /// See [crate::color_scheme]
+    #[serde(
+        default,
+        rename = "color-scheme",
+        deserialize_with = "deserialize_color_scheme_paths"
+    )]
+    pub color_scheme: Option<BTreeMap<ColorScheme, PathBuf>>,
+    /// Directories used in place of `path`, of the calendar, of the weather and of the color
+    /// scheme, on some workspaces
+    ///
+    /// See [crate::workspaces]
+    #[serde(default, deserialize_with = "deserialize_workspace_paths")]
+    pub workspaces: Option<BTreeMap<String, PathBuf>>,
+    /// Show the wallpaper of the new workspace with a transition
+    #[serde(rename = "workspace-transition")]
+    pub workspace_transition: Option<bool>,
+    /// Show these kinds of images without a transition
+    #[serde(rename = "transition-skip")]
+    pub transition_skip: Option<Vec<TransitionSkip>>,
+
+    /// Save a blurred and dimmed copy of each wallpaper, for the screen lockers
+    ///
+    /// See [crate::lock_image::LockImage]
+    #[serde(rename = "lock-image")]
+    pub lock_image: Option<LockImage>,
+
+    /// Region of the display where text is drawn over the wallpaper, e.g. by a bar
+    ///
+    /// See [crate::text_region::TextRegion]
+    #[serde(rename = "text-region")]
+    pub text_region: Option<TextRegion>,
+
+    /// Dim the wallpaper in the dark, following the light sensor or the time
+    ///
+    /// See [crate::brightness::Brightness]
+    pub brightness: Option<Brightness>,
+
+    /// Commands run before and after the wallpaper changes
+    ///
+    /// See [crate::hooks::Hooks]
+    pub hooks: Option<Hooks>,
+
+    /// Name of the set in `[set.<name>]` used as base for this section
+    ///
+    /// See [Config::new_from_path]
+    pub set: Option<String>,
+}
+
+impl SerializedWallpaperInfo {
+    /// Return a new struct with the values set in `other` replacing the ones in `self`
+    pub fn merge(&self, other: &Self) -> Self {
+        // Setting any source replaces the one in `self`
+        let source = if other.source_attributes().is_empty() {
+            self
+        } else {
+            other
+        };
+        Self {
+            path: source.path.clone(),
+            // duration, schedule and rotation replace each other
+            duration: match (&other.duration, &other.schedule) {
+                (Some(duration), _) => Some(*duration),
+                _ if other.rotation.is_some() => None,
+                (None, Some(_)) => None,
+                (None, None) => self.duration,
+            },
+            schedule: match (&other.schedule, &other.duration) {
+                (Some(schedule), _) => Some(schedule.clone()),
+                _ if other.rotation.is_some() => None,
+                (None, Some(_)) => None,
+                (None, None) => self.schedule.clone(),
+            },
+            rotation: match (&other.rotation, &other.duration, &other.schedule) {
+                (Some(rotation), _, _) => Some(*rotation),
+                (None, None, None) => self.rotation,
+                _ => None,
+            },
+            apply_shadow: other.apply_shadow.or(self.apply_shadow),
+            sorting: other.sorting.or(self.sorting),
+            reverse: other.reverse.or(self.reverse),
+            mode: other.mode.or(self.mode),
+            queue_size: other.queue_size.or(self.queue_size),
+            transition_time: other.transition_time.or(self.transition_time),
+            max_fps: other.max_fps.or(self.max_fps),
+            resize_filter: other.resize_filter.or(self.resize_filter),
+            max_decode_size: other.max_decode_size.or(self.max_decode_size),
+            dither: other.dither.or(self.dither),
+            linear_scaling: other.linear_scaling.or(self.linear_scaling),
+            color_space: other.color_space.or(self.color_space),
+            // tint and duotone replace each other
+            tint: match (other.tint, other.duotone) {
+                (Some(tint), _) => Some(tint),
+                (None, Some(_)) => None,
+                (None, None) => self.tint,
+            },
+            duotone: match (other.duotone, other.tint) {
+                (Some(duotone), _) => Some(duotone),
+                (None, Some(_)) => None,
+                (None, None) => self.duotone,
+            },
+            tile_scale: other.tile_scale.or(self.tile_scale),
+            tile_gap: other.tile_gap.or(self.tile_gap),
+            animate: other.animate.or(self.animate),
+            animation_speed: other.animation_speed.or(self.animation_speed),
+            start_paused: other.start_paused.or(self.start_paused),
+            audio_reactive: other.audio_reactive.or(self.audio_reactive),
+            parallax: other.parallax.or(self.parallax),
+            initial_transition: other.initial_transition.or(self.initial_transition),
+            transition: match (&self.transition, &other.transition) {
+                (Some(transition), Some(other)) => Some(transition.merge(other)),
+                (transition, other) => other.clone().or_else(|| transition.clone()),
+            },
+            background_color: other.background_color.or(self.background_color),
+            error_color: other.error_color.or(self.error_color),
+            offset: other.offset.or(self.offset),
+            horizontal_align: other.horizontal_align.or(self.horizontal_align),
+            vertical_align: other.vertical_align.or(self.vertical_align),
+            pool: other.pool.or(self.pool),
+            sync: other.sync.or(self.sync),
+            follow_symlinks: other.follow_symlinks.or(self.follow_symlinks),
+            deduplicate: other.deduplicate.or(self.deduplicate),
+            min_rating: other.min_rating.or(self.min_rating),
+            filter: other.filter.clone().or_else(|| self.filter.clone()),
+            seed: other.seed.or(self.seed),
+            initial: other.initial.clone().or_else(|| self.initial.clone()),
+            playlist: source.playlist.clone(),
+            url: source.url.clone(),
+            provider: source.provider.clone(),
+            command: source.command.clone(),
+            newest: source.newest.clone(),
+            newest_blur: source.newest_blur,
+            script: other.script.clone().or_else(|| self.script.clone()),
+            mirror: other.mirror.clone().or_else(|| self.mirror.clone()),
+            calendar: match (&other.calendar, other.source_attributes().is_empty()) {
+                (Some(calendar), _) => Some(calendar.clone()),
+                // The path set replaces the ones in the calendar too
+                (None, false) => None,
+                (None, true) => self.calendar.clone(),
+            },
+            seasons: match (&other.seasons, other.source_attributes().is_empty()) {
+                (Some(seasons), _) => Some(seasons.clone()),
+                (None, false) => None,
+                (None, true) => self.seasons.clone(),
+            },
+            weather: match (&other.weather, other.source_attributes().is_empty()) {
+                (Some(weather), _) => Some(weather.clone()),
+                (None, false) => None,
+                (None, true) => self.weather.clone(),
+            },
+            color_scheme: match (&other.color_scheme, other.source_attributes().is_empty()) {
+                (Some(color_scheme), _) => Some(color_scheme.clone()),
+                (None, false) => None,
+                (None, true) => self.color_scheme.clone(),
+            },
+            workspaces: match (&other.workspaces, other.source_attributes().is_empty()) {
+                (Some(workspaces), _) => Some(workspaces.clone()),
+                (None, false) => None,
+                (None, true) => self.workspaces.clone(),
+            },
+            workspace_transition: other.workspace_transition.or(self.workspace_transition),
+            transition_skip: other
+                .transition_skip
+                .clone()
+                .or_else(|| self.transition_skip.clone()),
+            lock_image: other.lock_image.or(self.lock_image),
+            text_region: other.text_region.or(self.text_region),
+            brightness: other.brightness.or(self.brightness),
+            hooks: other.hooks.clone().or_else(|| self.hooks.clone()),
+            set: other.set.clone().or_else(|| self.set.clone()),
+        }
+    }
+
+    /// The entries of the calendar followed by the seasons
+    fn calendar_entries(&self) -> impl Iterator<Item = &CalendarEntry> {
+        self.calendar.iter().chain(&self.seasons).flatten()
+    }
+
+    /// Use the first entry of the calendar or the seasons that applies to `date` in place of the
+    /// path
+    fn for_date(&self, date: NaiveDate) -> Self {
+        match self.calendar_entries().find(|entry| entry.matches(date)) {
+            Some(entry) => Self {
+                path: Some(entry.path.clone()),
+                playlist: None,
+                url: None,
+                provider: None,
+                command: None,
+                newest: None,
+                ..self.clone()
+            },
+            None => self.clone(),
+        }
+    }
+
+    /// Use the directory set for the current weather `condition` in place of the path
+    fn for_weather(&self, condition: Option<Condition>) -> Self {
+        match condition.and_then(|condition| self.weather.as_ref()?.get(&condition)) {
+            Some(path) => Self {
+                path: Some(path.clone()),
+                playlist: None,
+                url: None,
+                provider: None,
+                command: None,
+                newest: None,
+                ..self.clone()
+            },
+            None => self.clone(),
+        }
+    }
+
+    /// Use the directory set for the color `scheme` of the desktop in place of the path
+    fn for_color_scheme(&self, scheme: Option<ColorScheme>) -> Self {
+        match scheme.and_then(|scheme| self.color_scheme.as_ref()?.get(&scheme)) {
+            Some(path) => Self {
+                path: Some(path.clone()),
+                playlist: None,
+                url: None,
+                provider: None,
+                command: None,
+                newest: None,
+                ..self.clone()
+            },
+            None => self.clone(),
+        }
+    }
+
+    /// Use the directory set for the active `workspace` in place of the path
+    fn for_workspace(&self, workspace: Option<&String>) -> Self {
+        match workspace.and_then(|workspace| self.workspaces.as_ref()?.get(workspace)) {
+            Some(path) => Self {
+                path: Some(path.clone()),
+                playlist: None,
+                url: None,
+                provider: None,
+                command: None,
+                newest: None,
+                ..self.clone()
+            },
+            None => self.clone(),
+        }
+    }
+
+    /// The attributes set among the ones deciding where the images come from
+    fn source_attributes(&self) -> Vec<&'static str> {
+        [
+            ("path", self.path.is_some()),
+            ("playlist", self.playlist.is_some()),
+            ("url", self.url.is_some()),
+            ("provider", self.provider.is_some()),
+            ("command", self.command.is_some()),
+            ("newest", self.newest.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(attribute, set)| set.then_some(attribute))
+        .collect()
+    }
+
+    /// Validate the source set with `playlist`, `url`, `provider`, `command` or `newest`, if any
+    fn source(&self) -> Result<Option<Source>> {
+        let attributes = self.source_attributes();
+        if attributes.len() > 1 {
+            return Err(anyhow!(
+                "attributes {} cannot be set together",
+                attributes
+                    .iter()
+                    .map(|attribute| attribute.bold().italic().blue().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ))
+            .with_suggestion(|| "keep only one of them".to_string());
+        }
+
+        if let Some(playlist) = &self.playlist {
+            if !playlist.is_file() {
+                return Err(anyhow!(
+                    "path {} for attribute {} is not an existing file",
+                    playlist.to_string_lossy().italic().yellow(),
+                    "playlist".bold().italic().blue(),
+                ))
+                .with_suggestion(|| {
+                    format!(
+                        "set attribute {} to a file listing an image per line",
+                        "playlist".bold().italic().blue(),
+                    )
+                });
+            }
+            Ok(Some(Source::Playlist(playlist.clone())))
+        } else if let Some(url) = &self.url {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err(anyhow!(
+                    "url {} for attribute {} is not valid",
+                    url.italic().yellow(),
+                    "url".bold().italic().blue(),
+                ))
+                .with_suggestion(|| {
+                    format!(
+                        "set attribute {} to a url starting with {} or {}",
+                        "url".bold().italic().blue(),
+                        "http://".italic().yellow(),
+                        "https://".italic().yellow(),
+                    )
+                });
+            }
+            Ok(Some(Source::Url(url.clone())))
+        } else if let Some(provider) = &self.provider {
+            ensure!(
+                !provider.trim().is_empty(),
+                "attribute {} is empty",
+                "provider".bold().italic().blue()
+            );
+            Ok(Some(Source::Provider(provider.clone())))
+        } else if let Some(command) = &self.command {
+            ensure!(
+                !command.trim().is_empty(),
+                "attribute {} is empty",
+                "command".bold().italic().blue()
+            );
+            Ok(Some(Source::Command(command.clone())))
+        } else if let Some(newest) = &self.newest {
+            if !newest.is_dir() {
+                return Err(missing_path(format!(
+                    "path {} for attribute {} is not an existing directory",
+                    newest.to_string_lossy().italic().yellow(),
+                    "newest".bold().italic().blue(),
+                )))
+                .with_suggestion(|| {
+                    format!(
+                        "set attribute {} to the directory of the screenshots",
+                        "newest".bold().italic().blue(),
+                    )
+                });
+            }
+            if let Some(blur) = self.newest_blur {
+                ensure!(
+                    blur >= 0.0 && blur.is_finite(),
+                    "value {} for attribute {} must be a positive number",
+                    blur.italic().yellow(),
+                    "newest-blur".bold().italic().blue()
+                );
+            }
+            Ok(Some(Source::Newest {
+                dir: newest.clone(),
+                blur: self.newest_blur.filter(|blur| *blur > 0.0),
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// The options used to read the directory in `path`
+    pub fn filelist_options(&self, default: &Self) -> FilelistOptions {
+        let follow_symlinks = match (&self.follow_symlinks, &default.follow_symlinks) {
+            (Some(follow_symlinks), _) | (None, Some(follow_symlinks)) => *follow_symlinks,
+            (None, None) => FilelistOptions::default().follow_symlinks,
+        };
+        let deduplicate = match (&self.deduplicate, &default.deduplicate) {
+            (Some(deduplicate), _) | (None, Some(deduplicate)) => *deduplicate,
+            (None, None) => FilelistOptions::default().deduplicate,
+        };
+        let sorting = self.sorting.or(default.sorting);
+        FilelistOptions {
+            follow_symlinks,
+            deduplicate,
+            sort_by_date: sorting == Some(Sorting::ExifDate),
+            min_rating: self.min_rating.or(default.min_rating),
+        }
+    }
+
+    pub fn apply_and_validate(&self, default: &Self) -> Result<WallpaperInfo> {
+        // The path or source set in this section replaces the one in the default section
+        let path_inherited = self.source_attributes().is_empty();
+        let source_info = if path_inherited { default } else { self };
+        let source = if path_inherited {
+            source_info
+                .source()
+                .with_context(|| format!("in {} configuration", "default".magenta().bold()))?
+        } else {
+            source_info.source()?
+        };
+        let path = match (&source_info.path, &source) {
+            (_, Some(_)) => Path::new(""),
+            (Some(path), None) => path,
+            (None, None) => {
+                return Err(anyhow!(
+                    "attribute {} is not set",
+                    "path".bold().italic().blue(),
+                ))
+                .with_suggestion(|| {
+                    format!(
+                        "add attribute {} in the display section of the configuration:\npath = \"</path/to/image>\"",
+                        "path".bold().italic().blue(),
+                    )
+                });
+            }
+        }
+        .to_path_buf();
+        // Ensure that a path exists
+        if source.is_none() && !path.exists() {
+            return Err(missing_path(format!(
+                "path {} for attribute {}{} does not exist",
+                path.to_string_lossy().italic().yellow(),
+                "path".bold().italic().blue(),
+                if path_inherited {
+                    format!(
+                        " (inherited from {} configuration)",
+                        "default".magenta().bold()
+                    )
+                } else {
+                    "".to_string()
+                }
+            )))
+            .with_suggestion(|| {
+                format!(
+                    "set attribute {} to an existing file or directory",
+                    "path".bold().italic().blue(),
+                )
+            });
+        }
+
+        for (condition, path) in self.weather.iter().flatten() {
+            if !path.exists() {
+                return Err(missing_path(format!(
+                    "path {} for condition {} in attribute {} does not exist",
+                    path.to_string_lossy().italic().yellow(),
+                    condition.italic().yellow(),
+                    "weather".bold().italic().blue(),
+                )))
+                .with_suggestion(|| "set it to an existing file or directory".to_string());
+            }
+        }
+        for (scheme, path) in self.color_scheme.iter().flatten() {
+            if !path.exists() {
+                return Err(missing_path(format!(
+                    "path {} for scheme {} in attribute {} does not exist",
+                    path.to_string_lossy().italic().yellow(),
+                    scheme.italic().yellow(),
+                    "color-scheme".bold().italic().blue(),
+                )))
+                .with_suggestion(|| "set it to an existing file or directory".to_string());
+            }
+        }
+        for (workspace, path) in self.workspaces.iter().flatten() {
+            if !path.exists() {
+                return Err(missing_path(format!(
+                    "path {} for workspace {} in attribute {} does not exist",
+                    path.to_string_lossy().italic().yellow(),
+                    workspace.italic().yellow(),
+                    "workspaces".bold().italic().blue(),
+                )))
+                .with_suggestion(|| "set it to an existing file or directory".to_string());
+            }
+        }
+        for entry in self.seasons.iter().flatten() {
+            if !entry.path.exists() {
+                return Err(missing_path(format!(
+                    "path {} in attribute {} does not exist",
+                    entry.path.to_string_lossy().italic().yellow(),
+                    "seasons".bold().italic().blue(),
+                )))
+                .with_suggestion(|| "set it to an existing file or directory".to_string());
+            }
+        }
+        for entry in self.calendar.iter().flatten() {
+            if !entry.path.exists() {
+                return Err(missing_path(format!(
+                    "path {} in attribute {} does not exist",
+                    entry.path.to_string_lossy().italic().yellow(),
+                    "calendar".bold().italic().blue(),
+                )))
+                .with_suggestion(|| "set it to an existing file or directory".to_string());
+            }
+            if entry.weekdays.is_empty() && entry.dates.is_empty() {
+                return Err(anyhow!(
+                    "entry for path {} in attribute {} is always used",
+                    entry.path.to_string_lossy().italic().yellow(),
+                    "calendar".bold().italic().blue(),
+                ))
+                .with_suggestion(|| {
+                    format!(
+                        "set {} or {} for the entry",
+                        "weekdays".bold().italic().blue(),
+                        "dates".bold().italic().blue(),
+                    )
+                });
+            }
+        }
+
+        if let (Some(_), Some(_)) = (&self.duration, &self.schedule) {
+            return Err(anyhow!(
+                "Attributes {} and {} are both set.",
+                "duration".bold().italic().blue(),
+                "schedule".bold().italic().blue()
+            )
+            .with_suggestion(|| {
+                format!(
+                    "Remove either {} or {}",
+                    "duration".bold().italic().blue(),
+                    "schedule".bold().italic().blue()
+                )
+            }));
+        }
+        if self.rotation.is_some() && (self.duration.is_some() || self.schedule.is_some()) {
+            return Err(anyhow!(
+                "Attribute {} is set together with {} or {}.",
+                "rotation".bold().italic().blue(),
+                "duration".bold().italic().blue(),
+                "schedule".bold().italic().blue()
+            )
+            .with_suggestion(|| {
+                format!(
+                    "Remove either {} or the other one",
+                    "rotation".bold().italic().blue()
+                )
+            }));
+        }
+        let rotation = match (&self.rotation, &default.rotation) {
+            // This section set duration or schedule, which replace the rotation in default
+            (None, Some(_)) if self.duration.is_some() || self.schedule.is_some() => None,
+            (None, Some(_)) if path.is_file() && !path_inherited => None,
+            (Some(rotation), _) | (None, Some(rotation)) => Some(*rotation),
+            (None, None) => None,
+        };
+        if rotation.is_some() && !path.is_dir() {
+            return Err(anyhow!(
+                "Attribute {} requires {} to be a directory",
+                "rotation".bold().italic().blue(),
+                "path".bold().italic().blue()
+            )
+            .with_suggestion(|| {
+                format!(
+                    "Either remove {} or set {} to a directory",
+                    "rotation".bold().italic().blue(),
+                    "path".bold().italic().blue()
+                )
+            }));
+        }
+
+        let schedule = match (&self.schedule, &default.schedule) {
+            // The rotation replaces schedule
+            _ if rotation.is_some() => None,
+            // This section set duration, which replaces the schedule in default
+            (None, Some(_)) if self.duration.is_some() => None,
+            // schedule is inherited from default, but this section set path to a file, ignore
+            // schedule
+            (None, Some(_)) if path.is_file() && !path_inherited => None,
+            (Some(schedule), _) | (None, Some(schedule)) => Some(schedule.clone()),
+            (None, None) => None,
+        };
+        // schedule can only be set when path is a directory
+        if schedule.is_some() && !path.is_dir() && source.is_none() {
+            return Err(anyhow!(
+                "Attribute {} is set to a file and attribute {} is also set.",
+                "path".bold().italic().blue(),
+                "schedule".bold().italic().blue()
+            )
+            .with_suggestion(|| {
+                format!(
+                    "Either remove {} or set {} to a directory",
+                    "schedule".bold().italic().blue(),
+                    "path".bold().italic().blue()
+                )
+            }));
+        }
+
+        let duration = match (&self.duration, &default.duration) {
+            // The schedule and the rotation replace duration
+            _ if schedule.is_some() || rotation.is_some() => None,
+            // duration is inherited from default, but this section set path to a file, ignore
+            // duration
+            (None, Some(_)) if path.is_file() && !path_inherited => None,
+            (Some(duration), _) | (None, Some(duration)) => Some(*duration),
+            (None, None) => None,
+        };
+        // duration can only be set when path is a directory
+        if duration.is_some() && !path.is_dir() && source.is_none() {
+            // Do no use bail! to add suggestion
+            return Err(anyhow!(
+                "Attribute {} is set to a file and attribute {} is also set.",
+                "path".bold().italic().blue(),
+                "duration".bold().italic().blue()
+            )
+            .with_suggestion(|| {
+                format!(
+                    "Either remove {} or set {} to a directory",
+                    "path".bold().italic().blue(),
+                    "duration".bold().italic().blue()
+                )
+            }));
+        }
+
+        let script = match (&self.script, &default.script) {
+            // The script is inherited from default, but this section doesn't use a directory
+            (None, Some(_)) if !path.is_dir() => None,
+            (Some(script), _) | (None, Some(script)) => Some(script.clone()),
+            (None, None) => None,
+        };
+        if let Some(script) = &script {
+            if !path.is_dir() {
+                return Err(anyhow!(
+                    "Attribute {} is set, but attribute {} is not set to a directory.",
+                    "script".bold().italic().blue(),
+                    "path".bold().italic().blue(),
+                )
+                .with_suggestion(|| {
+                    format!(
+                        "Either remove {} or set {} to a directory",
+                        "script".bold().italic().blue(),
+                        "path".bold().italic().blue()
+                    )
+                }));
+            }
+            // Report the errors in the script along with the ones in the configuration
+            SelectionScript::new(script).with_context(|| {
+                format!(
+                    "while validating attribute {}",
+                    "script".bold().italic().blue()
+                )
+            })?;
+        }
+
+        let filter = match (&self.filter, &default.filter) {
+            // The filter is inherited from default, but this section doesn't use a directory
+            (None, Some(_)) if !path.is_dir() => None,
+            (Some(filter), _) | (None, Some(filter)) => Some(filter),
+            (None, None) => None,
+        };
+        let filter = if let Some(filter) = filter {
+            if !path.is_dir() {
+                return Err(anyhow!(
+                    "Attribute {} is set, but attribute {} is not set to a directory.",
+                    "filter".bold().italic().blue(),
+                    "path".bold().italic().blue(),
+                )
+                .with_suggestion(|| {
+                    format!(
+                        "Either remove {} or set {} to a directory",
+                        "filter".bold().italic().blue(),
+                        "path".bold().italic().blue()
+                    )
+                }));
+            }
+            Some(ImageFilter::parse(filter).with_context(|| {
+                format!(
+                    "while validating attribute {} = {}",
+                    "filter".bold().italic().blue(),
+                    format!("{filter:?}").italic().yellow()
+                )
+            })?)
+        } else {
+            None
+        };
+
+        let sorting = match (&self.sorting, &default.sorting) {
+            (None, Some(_)) if path.is_file() && !path_inherited => None,
+            // The script decides the order
+            (None, Some(_)) if script.is_some() => None,
+            // The sources decide the order by themselves
+            (None, Some(_)) if source.is_some() => None,
+            (Some(sorting), _) | (None, Some(sorting)) => Some(*sorting),
+            (None, None) => None,
+        };
+
+        if let (Some(_), Some(_)) = (&sorting, &script) {
+            return Err(anyhow!(
+                "Attribute {} is set and attribute {} is also set.",
+                "script".bold().italic().blue(),
+                "sorting".bold().italic().blue()
+            )
+            .with_suggestion(|| {
+                format!(
+                    "Remove {}, the images are shown in the order decided by the script",
+                    "sorting".bold().italic().blue(),
+                )
+            }));
+        }
+        if let (Some(_), Some(_)) = (&sorting, &source) {
+            let attribute = source_info.source_attributes()[0];
+            return Err(anyhow!(
+                "Attribute {} is set and attribute {} is also set.",
+                attribute.bold().italic().blue(),
+                "sorting".bold().italic().blue()
+            )
+            .with_suggestion(|| {
+                format!(
+                    "Remove {}, the images are shown in the order decided by {}",
+                    "sorting".bold().italic().blue(),
+                    attribute.bold().italic().blue()
+                )
+            }));
+        }
+        // sorting can only be set when path is a directory
+        if sorting.is_some() && !path.is_dir() {
+            // Do no use bail! to add suggestion
+            return Err(anyhow!(
+                "Attribute {} is set to a file and attribute {} is also set.",
+                "path".bold().italic().blue(),
+                "sorting".bold().italic().blue()
+            )
+            .with_suggestion(|| {
+                format!(
+                    "Either remove {} or set {} to a directory",
+                    "path".bold().italic().blue(),
+                    "sorting".bold().italic().blue()
+                )
+            }));
+        }
+
+        let mode = match (&self.mode, &default.mode) {
+            (Some(mode), _) | (None, Some(mode)) => *mode,
+            (None, None) => BackgroundMode::default(),
+        };
+        let drawn_images_queue_size = match (&self.queue_size, &default.queue_size) {
+            (Some(size), _) | (None, Some(size)) => *size,
+            (None, None) => ImagePicker::DEFAULT_DRAWN_IMAGES_QUEUE_SIZE,
+        };
+        let initial_transition = match (&self.initial_transition, &default.initial_transition) {
+            (Some(initial_transition), _) | (None, Some(initial_transition)) => *initial_transition,
+            (None, None) => true,
+        };
+
+        let transition = match (&self.transition, &default.transition) {
+            // The parameters not set are taken from the same transition in the default section
+            (Some(transition), Some(default)) => default.merge(transition),
+            (Some(transition), None) | (None, Some(transition)) => transition.clone(),
+            (None, None) => Transition::Fade {},
+        };
+
+        let background_color = match (&self.background_color, &default.background_color) {
+            (Some(color), _) | (None, Some(color)) => *color,
+            (None, None) => Color::default(),
+        };
+        let error_color = match (&self.error_color, &default.error_color) {
+            (Some(color), _) | (None, Some(color)) => *color,
+            (None, None) => Color::ERROR,
+        };
+
+        let transition_time = match (&self.transition_time, &default.transition_time) {
+            (Some(transition_time), _) | (None, Some(transition_time)) => *transition_time,
+            (None, None) => transition.default_transition_time(),
+        };
+
+        let max_fps = match (&self.max_fps, &default.max_fps) {
+            (Some(max_fps), _) | (None, Some(max_fps)) => Some(*max_fps),
+            (None, None) => None,
+        };
+        if max_fps == Some(0) {
+            return Err(anyhow!(
+                "Attribute {} must be greater than 0",
+                "max-fps".bold().italic().blue()
+            )
+            .with_suggestion(|| {
+                format!(
+                    "Remove {} to draw frames as fast as the display refreshes",
+                    "max-fps".bold().italic().blue()
+                )
+            }));
+        }
+
+        let resize_filter = match (&self.resize_filter, &default.resize_filter) {
+            (Some(resize_filter), _) | (None, Some(resize_filter)) => *resize_filter,
+            (None, None) => ResizeFilter::default(),
+        };
+
+        let max_decode_size = match (&self.max_decode_size, &default.max_decode_size) {
+            (Some(max_decode_size), _) | (None, Some(max_decode_size)) => Some(*max_decode_size),
+            (None, None) => None,
+        };
+        if max_decode_size == Some(0) {
+            return Err(anyhow!(
+                "Attribute {} must be greater than 0",
+                "max-decode-size".bold().italic().blue()
+            )
+            .with_suggestion(|| {
+                format!(
+                    "Remove {} to decode the images at their full size",
+                    "max-decode-size".bold().italic().blue()
+                )
+            }));
+        }
+
+        let dither = self.dither.or(default.dither);
+
+        let linear_scaling = match (&self.linear_scaling, &default.linear_scaling) {
+            (Some(linear_scaling), _) | (None, Some(linear_scaling)) => *linear_scaling,
+            (None, None) => true,
+        };
+        let color_space = self.color_space.or(default.color_space).unwrap_or_default();
+
+        if self.tint.is_some() && self.duotone.is_some() {
+            return Err(anyhow!(
+                "Attributes {} and {} cannot be set together",
+                "tint".bold().italic().blue(),
+                "duotone".bold().italic().blue(),
+            )
+            .with_suggestion(|| {
+                format!(
+                    "Remove {} to draw the images in shades of a single color",
+                    "duotone".bold().italic().blue()
+                )
+            }));
+        }
+        // The attribute set in this section replaces the other one in default
+        let recolor = match (self.tint, self.duotone, default.tint, default.duotone) {
+            (Some(tint), _, _, _) => Some(Recolor::Tint(tint)),
+            (None, Some([shadow, highlight]), _, _) => Some(Recolor::Duotone(shadow, highlight)),
+            (None, None, Some(tint), _) => Some(Recolor::Tint(tint)),
+            (None, None, None, Some([shadow, highlight])) => {
+                Some(Recolor::Duotone(shadow, highlight))
+            }
+            (None, None, None, None) => None,
+        };
+
+        let tile_scale = match (&self.tile_scale, &default.tile_scale) {
+            (Some(tile_scale), _) | (None, Some(tile_scale)) => *tile_scale,
+            (None, None) => 1.0,
+        };
+        if !(tile_scale > 0.0 && tile_scale.is_finite()) {
+            return Err(anyhow!(
+                "Attribute {} must be greater than 0",
+                "tile-scale".bold().italic().blue()
+            )
+            .with_suggestion(|| {
+                format!(
+                    "Set {} to {} to draw the tiles at the size of the image",
+                    "tile-scale".bold().italic().blue(),
+                    "1.0".italic().yellow()
+                )
+            }));
+        }
+
+        let animate = self.animate.or(default.animate).unwrap_or(false);
+        let start_paused = self.start_paused.or(default.start_paused).unwrap_or(false);
+        let animation_speed = self
+            .animation_speed
+            .or(default.animation_speed)
+            .unwrap_or(1.0);
+        if !(animation_speed > 0.0 && animation_speed.is_finite()) {
+            return Err(anyhow!(
+                "Attribute {} must be greater than 0",
+                "animation-speed".bold().italic().blue()
+            )
+            .with_suggestion(|| {
+                format!(
+                    "Set {} to {} to show the animations as still images",
+                    "animate".bold().italic().blue(),
+                    "false".italic().yellow()
+                )
+            }));
+        }
+
+        let audio_reactive = self
+            .audio_reactive
+            .or(default.audio_reactive)
+            .unwrap_or(0.0);
+        if !(0.0..=1.0).contains(&audio_reactive) {
+            return Err(anyhow!(
+                "Attribute {} must be between 0 and 1",
+                "audio-reactive".bold().italic().blue()
+            ));
+        }
+        if audio_reactive > 0.0 && !cfg!(feature = "audio") {
+            return Err(anyhow!(
+                "Attribute {} needs the audio feature",
+                "audio-reactive".bold().italic().blue()
+            )
+            .with_suggestion(|| {
+                format!(
+                    "Build wpaperd with {}",
+                    "--features audio".italic().yellow()
+                )
+            }));
+        }
+
+        let workspace_transition = self
+            .workspace_transition
+            .or(default.workspace_transition)
+            .unwrap_or(true);
+        let transition_skip = self
+            .transition_skip
+            .clone()
+            .or_else(|| default.transition_skip.clone())
+            .unwrap_or_default();
+
+        if self
+            .min_rating
+            .or(default.min_rating)
+            .is_some_and(|rating| rating > 5)
+        {
+            return Err(anyhow!(
+                "Attribute {} must be between 0 and 5",
+                "min-rating".bold().italic().blue()
+            )
+            .with_suggestion(|| {
+                format!(
+                    "It is the number of stars, e.g. {} only shows the images with 4 or 5 stars",
+                    "4".italic().yellow()
+                )
+            }));
+        }
+
+        let parallax = self.parallax.or(default.parallax).unwrap_or(0.0);
+        if !(0.0..=0.25).contains(&parallax) {
+            return Err(anyhow!(
+                "Attribute {} must be between 0 and 0.25",
+                "parallax".bold().italic().blue()
+            )
+            .with_suggestion(|| {
+                format!(
+                    "It is a fraction of the display, e.g. {} shifts the image by up to 2%",
+                    "0.02".italic().yellow()
+                )
+            }));
+        }
+
+        let tile_gap = match (&self.tile_gap, &default.tile_gap) {
+            (Some(tile_gap), _) | (None, Some(tile_gap)) => *tile_gap,
+            (None, None) => 0,
+        };
+
+        let offset = match (&self.offset, &default.offset) {
+            (Some(offset), _) | (None, Some(offset)) => Some(*offset),
+            (None, None) => None,
+        };
+
+        let horizontal_align = match (&self.horizontal_align, &default.horizontal_align) {
+            (Some(align), _) | (None, Some(align)) => Some(*align),
+            (None, None) => None,
+        };
+
+        let vertical_align = match (&self.vertical_align, &default.vertical_align) {
+            (Some(align), _) | (None, Some(align)) => Some(*align),
+            (None, None) => None,
+        };
+
+        let seed = match (&self.seed, &default.seed) {
+            (Some(seed), _) | (None, Some(seed)) => Some(*seed),
+            (None, None) => None,
+        };
+
+        let reverse = match (&self.reverse, &default.reverse) {
+            (Some(reverse), _) | (None, Some(reverse)) => *reverse,
+            (None, None) => false,
+        };
+
+        let pool = match (&self.pool, &default.pool) {
+            (Some(pool), _) | (None, Some(pool)) => *pool,
+            (None, None) => Pool::default(),
+        };
+
+        let sync = match (&self.sync, &default.sync) {
+            (Some(sync), _) | (None, Some(sync)) => *sync,
+            (None, None) => false,
+        };
+        if sync && duration.is_none() && rotation.is_none() {
+            return Err(anyhow!(
+                "Attribute {} requires {} to be set",
+                "sync".bold().italic().blue(),
+                "duration".bold().italic().blue()
+            )
+            .with_suggestion(|| {
+                format!(
+                    "Set {} to a directory and {} to the same value on all the machines",
+                    "path".bold().italic().blue(),
+                    "duration".bold().italic().blue()
+                )
+            }));
+        }
+
+        let lock_image = match (&self.lock_image, &default.lock_image) {
+            (Some(lock_image), _) | (None, Some(lock_image)) => Some(*lock_image),
+            (None, None) => None,
+        };
+        if let Some(lock_image) = &lock_image {
+            if !(lock_image.blur >= 0.0 && lock_image.blur.is_finite()) {
+                return Err(anyhow!(
+                    "Value {} of {} must be a positive number",
+                    "blur".bold().italic().blue(),
+                    "lock-image".bold().italic().blue()
+                )
+                .with_suggestion(|| {
+                    format!(
+                        "Set {} to {} to not blur the image",
+                        "blur".bold().italic().blue(),
+                        "0.0".italic().yellow()
+                    )
+                }));
+            }
+            if !(0.0..=1.0).contains(&lock_image.dim) {
+                return Err(anyhow!(
+                    "Value {} of {} must be between 0 and 1",
+                    "dim".bold().italic().blue(),
+                    "lock-image".bold().italic().blue()
+                )
+                .with_suggestion(|| {
+                    format!(
+                        "Set {} to {} to keep the brightness of the image",
+                        "dim".bold().italic().blue(),
+                        "0.0".italic().yellow()
+                    )
+                }));
+            }
+        }
+
+        let text_region = match (&self.text_region, &default.text_region) {
+            (Some(region), _) | (None, Some(region)) => Some(*region),
+            (None, None) => None,
+        };
+        if text_region.is_some_and(|region| region.width == 0 || region.height == 0) {
+            return Err(anyhow!(
+                "The width and the height of {} must be greater than 0",
+                "text-region".bold().italic().blue()
+            )
+            .with_suggestion(|| {
+                format!(
+                    "Set {} to the area covered by the bar, e.g. {}",
+                    "text-region".bold().italic().blue(),
+                    "{ x = 0, y = 0, width = 1920, height = 30 }"
+                        .italic()
+                        .yellow()
+                )
+            }));
+        }
+
+        let hooks = match (&self.hooks, &default.hooks) {
+            (Some(hooks), _) | (None, Some(hooks)) => Some(hooks.clone()),
+            (None, None) => None,
+        };
+        if hooks
+            .as_ref()
+            .is_some_and(|hooks| hooks.timeout.is_zero() || hooks.retry.is_zero())
+        {
+            return Err(anyhow!(
+                "Values {} and {} of {} must be greater than 0",
+                "timeout".bold().italic().blue(),
+                "retry".bold().italic().blue(),
+                "hooks".bold().italic().blue()
+            )
+            .with_suggestion(|| {
+                format!(
+                    "Remove them to use the default values, e.g. {}",
+                    "hooks = { pre-change = \"! pgrep -x obs\" }"
+                        .italic()
+                        .yellow()
+                )
+            }));
+        }
+
+        let brightness = match (&self.brightness, &default.brightness) {
+            (Some(brightness), _) | (None, Some(brightness)) => Some(*brightness),
+            (None, None) => None,
+        };
+        if let Some(brightness) = &brightness {
+            for (attribute, value) in [("day", brightness.day), ("night", brightness.night)] {
+                if !(0.0..=1.0).contains(&value) {
+                    return Err(anyhow!(
+                        "Value {} of {} must be between 0 and 1",
+                        attribute.bold().italic().blue(),
+                        "brightness".bold().italic().blue()
+                    )
+                    .with_suggestion(|| {
+                        format!(
+                            "Set {} to {} to keep the brightness of the image",
+                            attribute.bold().italic().blue(),
+                            "1.0".italic().yellow()
+                        )
+                    }));
+                }
+            }
+        }
+
+        let initial = match (&self.initial, &default.initial) {
+            (Some(initial), _) | (None, Some(initial)) => Some(initial.clone()),
+            (None, None) => None,
+        };
+        if let Some(InitialImage::Path(initial_path)) = &initial {
+            if !initial_path.is_file() {
+                return Err(anyhow!(
+                    "path {} for attribute {} is not an existing file",
+                    initial_path.to_string_lossy().italic().yellow(),
+                    "initial".bold().italic().blue(),
+                )
+                .with_suggestion(|| {
+                    format!(
+                        "Set {} to {}, {}, {} or the path to an existing image",
+                        "initial".bold().italic().blue(),
+                        "\"first\"".italic().yellow(),
+                        "\"random\"".italic().yellow(),
+                        "\"last-shown\"".italic().yellow(),
+                    )
+                }));
+            }
+        }
+
+        let filelist_options = self.filelist_options(default);
+
+        Ok(WallpaperInfo {
+            path,
+            duration,
+            schedule,
+            rotation,
+            apply_shadow: false,
+            sorting,
+            reverse,
+            mode,
+            drawn_images_queue_size,
+            transition_time,
+            max_fps,
+            resize_filter,
+            max_decode_size,
+            dither,
+            linear_scaling,
+            color_space,
+            recolor,
+            tile_scale,
+            tile_gap,
+            animate,
+            animation_speed,
+            start_paused,
+            audio_reactive,
+            parallax,
+            workspace: None,
+            workspace_transition,
+            transition_skip,
+            initial_transition,
+            transition,
+            background_color,
+            error_color,
+            offset,
+            horizontal_align,
+            vertical_align,
+            follow_symlinks: filelist_options.follow_symlinks,
+            deduplicate: filelist_options.deduplicate,
+            min_rating: filelist_options.min_rating,
+            filter,
+            seed,
+            pool,
+            sync,
+            initial,
+            source,
+            script,
+            // Set by Config::get_output_by_name
+            lock_image,
+            text_region,
+            brightness,
+            hooks,
+            high_contrast: None,
+            zen_image: None,
+            mirror: None,
+            theme: None,
+        })
+    }
+}
+
+#[derive(Deserialize, Default)]
+pub struct Config {
+    #[serde(flatten)]
+    data: HashMap<String, SerializedWallpaperInfo>,
+    /// The named sets of values, shared by the sections setting `set = "<name>"`
+    #[serde(default, rename = "set")]
+    sets: HashMap<String, SerializedWallpaperInfo>,
+    /// See [crate::quiet_hours::QuietHours]
+    #[serde(default, rename = "quiet-hours")]
+    pub quiet_hours: Option<QuietHours>,
+    /// See [crate::image_loader::DecodeLimits]
+    #[serde(default, rename = "decode-limits")]
+    pub decode_limits: DecodeLimits,
+    /// See [crate::tone_mapping::ToneMapping]
+    #[serde(default, rename = "tone-mapping")]
+    pub tone_mapping: ToneMapping,
+    /// See [crate::backend::Backend]
+    #[serde(default)]
+    backend: Backend,
+    /// Value of backend set at runtime, it takes precedence over the file
+    #[serde(skip)]
+    backend_override: Option<Backend>,
+    /// See [crate::theme::Theme]
+    #[serde(default)]
+    pub theme: Option<Theme>,
+    /// See [crate::weather::Weather]
+    #[serde(default)]
+    pub weather: Option<Weather>,
+    /// Disable the transitions on all the displays
+    #[serde(default, rename = "reduce-motion")]
+    reduce_motion: bool,
+    /// Value of reduce-motion set at runtime, it takes precedence over the file
+    #[serde(skip)]
+    reduce_motion_override: Option<bool>,
+    /// Show a solid color in place of the wallpapers on all the displays
+    #[serde(default, rename = "high-contrast")]
+    high_contrast: bool,
+    /// The color shown when high-contrast is enabled, black by default
+    #[serde(default, rename = "high-contrast-color")]
+    high_contrast_color: Color,
+    /// The color shown on all the displays by `wpaperctl zen`, a dark gray by default
+    #[serde(default, rename = "zen-color")]
+    zen_color: Option<Color>,
+    /// The image shown by `wpaperctl zen` in place of zen-color
+    #[serde(
+        default,
+        rename = "zen-image",
+        deserialize_with = "tilde_expansion_deserialize"
+    )]
+    zen_image: Option<PathBuf>,
+    /// What to do when another program draws the background, see [crate::conflicts]
+    #[serde(default, rename = "on-conflict")]
+    pub on_conflict: OnConflict,
+    /// Show the background colors for this long at startup before loading the wallpapers, see
+    /// [crate::cold_start]
+    #[serde(default, rename = "startup-delay", with = "humantime_serde")]
+    pub startup_delay: Option<Duration>,
+    /// The files read per second the first time each directory is read at startup
+    #[serde(default, rename = "startup-scan-rate")]
+    pub startup_scan_rate: Option<NonZeroU32>,
+    /// Use the IPC of Sway and Hyprland, enabled by default, see [crate::compositor]
+    #[serde(default, rename = "compositor-ipc")]
+    compositor_ipc: Option<bool>,
+    /// A file with values of the default section, merged beneath it. Relative to the directory
+    /// of the configuration
+    #[serde(
+        default,
+        rename = "defaults-file",
+        deserialize_with = "tilde_expansion_deserialize"
+    )]
+    defaults_file: Option<PathBuf>,
+    /// Value of high-contrast set at runtime, it takes precedence over the file
+    #[serde(skip)]
+    high_contrast_override: Option<bool>,
+    /// Zen has been enabled with `wpaperctl zen`, it is never saved in the file
+    #[serde(skip)]
+    zen: bool,
+    #[serde(skip)]
+    default: SerializedWallpaperInfo,
+    #[serde(skip)]
+    any: SerializedWallpaperInfo,
+    #[serde(skip)]
+    pub path: PathBuf,
+    #[serde(skip)]
+    /// Receives [Event::ConfigChanged] when the files change
+    pub events: Option<EventSender>,
+    /// Seed passed from the command line, used by the displays that don't set one
+    #[serde(skip)]
+    pub seed: Option<u64>,
+    /// Number of threads loading the images, passed from the command line
+    #[serde(skip)]
+    pub threads: Option<usize>,
+    /// The seat wpaperd is running on, the sections named `<seat>/<display>` take precedence
+    /// over the ones named after the display
+    #[serde(skip)]
+    pub seat: Option<String>,
+    /// Values changed at runtime for each display, they take precedence over the file
+    #[serde(skip)]
+    overrides: HashMap<String, SerializedWallpaperInfo>,
+    /// The same values as written in TOML, used by [Config::dump]
+    #[serde(skip)]
+    override_values: toml::Table,
+    #[serde(skip)]
+    overrides_changed: bool,
+    /// The day has changed and some displays have a calendar
+    #[serde(skip)]
+    day_changed: bool,
+    /// The last weather condition fetched, see [crate::weather::WeatherWatcher]
+    #[serde(skip)]
+    weather_condition: Option<Condition>,
+    /// The weather has changed and some displays have directories for it
+    #[serde(skip)]
+    weather_changed: bool,
+    /// The color scheme of the desktop, see [crate::color_scheme::ColorSchemeWatcher]
+    #[serde(skip)]
+    color_scheme: Option<ColorScheme>,
+    /// The color scheme has changed and some displays have directories for it
+    #[serde(skip)]
+    color_scheme_changed: bool,
+    /// The workspace shown on each display, see [crate::workspaces::WorkspaceWatcher]
+    #[serde(skip)]
+    active_workspaces: HashMap<String, String>,
+    /// A workspace has changed on a display with directories for it
+    #[serde(skip)]
+    workspaces_changed: bool,
+    /// When the configuration file started failing to load, see [Config::update]
+    #[serde(skip)]
+    invalid_since: Option<Instant>,
+    /// The configuration file must be read again after [RELOAD_RETRY_INTERVAL]
+    #[serde(skip)]
+    retry_reload: bool,
+    /// The errors found in the configuration file when it was last read, without colors
+    #[serde(skip)]
+    errors: Vec<String>,
+    /// The first class of [Config::errors], parse errors first
+    #[serde(skip)]
+    error_kind: Option<ConfigErrorKind>,
+    /// How the configuration file is read, kept for the reloads
+    #[serde(skip)]
+    pub options: LoadOptions,
+}
+
+/// How the configuration file is read, passed from the command line
+#[derive(Default, Clone)]
+pub struct LoadOptions {
+    /// Refuse the whole file when a section has an error
+    pub strict: bool,
+    /// Values used when the default section doesn't set them
+    pub cli_defaults: SerializedWallpaperInfo,
+    /// System-wide configuration, the file of the user is merged over it
+    pub system_path: Option<PathBuf>,
+}
+
+/// The class of an error found in the configuration, told apart by `wpaperd --check-config`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ConfigErrorKind {
+    /// The file cannot be read, it is not valid TOML or it has an unknown attribute
+    Parse,
+    /// The configuration file or a path set in it does not exist
+    MissingPath,
+    /// A value is not valid or it conflicts with another one
+    Invalid,
+}
+
+impl ConfigErrorKind {
+    pub fn of(err: &Report) -> Self {
+        if err.chain().any(|err| err.is::<MissingPath>()) {
+            ConfigErrorKind::MissingPath
+        } else if err.chain().any(|err| {
+            err.is::<toml::de::Error>()
+                || err.is::<serde_json::Error>()
+                || err.is::<std::io::Error>()
+        }) {
+            ConfigErrorKind::Parse
+        } else {
+            ConfigErrorKind::Invalid
+        }
+    }
+
+    /// The exit code of `wpaperd --check-config`, from sysexits.h
+    pub fn exit_code(self) -> i32 {
+        match self {
+            // EX_DATAERR
+            ConfigErrorKind::Parse => 65,
+            // EX_NOINPUT
+            ConfigErrorKind::MissingPath => 66,
+            // EX_CONFIG
+            ConfigErrorKind::Invalid => 78,
+        }
+    }
+}
+
+/// A path that doesn't exist, see [ConfigErrorKind::MissingPath]
+#[derive(Debug)]
+struct MissingPath(String);
+
+impl fmt::Display for MissingPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for MissingPath {}
+
+fn missing_path(message: String) -> Report {
+    Report::new(MissingPath(message))
+}
+
+/// True if the configuration is written in JSON, e.g. by home-manager, instead of TOML
+pub fn is_json(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "json")
+}
+
+/// Parse a configuration file in the format given by its extension
+fn parse_table(path: &Path, content: &str) -> Result<toml::Table> {
+    if is_json(path) && !content.trim().is_empty() {
+        serde_json::from_str(content)
+            .with_context(|| format!("parsing JSON configuration {path:?}"))
+    } else {
+        Ok(toml::from_str(content)?)
+    }
+}
+
+/// Read the values of the default section from `defaults_file`, written without the header
+fn read_defaults(defaults_file: &Path) -> Result<toml::Table> {
+    if !defaults_file.exists() {
+        return Err(missing_path(format!(
+            "defaults file {defaults_file:?} does not exist"
+        )))
+        .with_suggestion(|| {
+            format!(
+                "Create the file or remove {} from the configuration",
+                "defaults-file".bold().italic().blue()
+            )
+        });
+    }
+    let content = fs::read_to_string(defaults_file)
+        .with_context(|| format!("reading defaults file {defaults_file:?}"))?;
+    parse_table(defaults_file, &content)
+        .with_context(|| format!("in defaults file {defaults_file:?}"))
+}
+
+/// How long the configuration file can stay invalid after a change before reporting it, it
+/// might still be being written
+const RELOAD_GRACE_PERIOD: Duration = Duration::from_secs(3);
+/// How often the configuration file is read again during [RELOAD_GRACE_PERIOD]
+pub const RELOAD_RETRY_INTERVAL: Duration = Duration::from_millis(250);
+
+impl Config {
+    /// Read the configuration file. The sections with an error are ignored, unless `strict` is
+    /// true, in which case the whole file is refused.
+    ///
+    /// The file is merged over the system-wide one, if any, and it can be missing in that case.
+    /// The values of `defaults-file` are merged between the two, beneath the default section.
+    /// The sections setting `set` are merged over the values of that set, then over the default
+    /// section like the others, which is merged over `cli_defaults`
+    pub fn new_from_path(path: &Path, options: &LoadOptions) -> Result<Self> {
+        let system_path = options
+            .system_path
+            .as_deref()
+            .filter(|system_path| *system_path != path && system_path.exists());
+        if !path.exists() && system_path.is_none() {
+            return Err(missing_path(format!("File {path:?} does not exists")));
+        }
+        let content = if path.exists() {
+            fs::read_to_string(path)?
+        } else {
+            String::new()
+        };
+        // The TOML errors already show where they are in the file, the JSON ones have the line
+        let mut config: Self = if is_json(path) && path.exists() {
+            serde_json::from_str(&content)
+                .with_context(|| format!("parsing JSON configuration {path:?}"))?
+        } else {
+            toml::from_str(&content)?
+        };
+        let system = match system_path {
+            Some(system_path) => {
+                let system_content = fs::read_to_string(system_path)
+                    .with_context(|| format!("reading system configuration {system_path:?}"))?;
+                let system = parse_table(system_path, &system_content)
+                    .with_context(|| format!("in system configuration {system_path:?}"))?;
+                config = layer(system.clone(), parse_table(path, &content)?)
+                    .try_into()
+                    .with_context(|| {
+                        format!("merging over system configuration {system_path:?}")
+                    })?;
+                system
+            }
+            None => toml::Table::new(),
+        };
+        if let Some(defaults_file) = &config.defaults_file {
+            // An absolute path replaces the directory
+            let defaults_file = path.parent().unwrap_or(Path::new("")).join(defaults_file);
+            let defaults = read_defaults(&defaults_file)?;
+            let defaults =
+                toml::Table::from_iter([("default".to_string(), toml::Value::Table(defaults))]);
+            config = layer(layer(system, defaults), parse_table(path, &content)?)
+                .try_into()
+                .with_context(|| format!("merging over defaults file {defaults_file:?}"))?;
+            config.defaults_file = Some(defaults_file);
+        }
+        if let Some(weather) = &config.weather {
+            weather.validate().with_context(|| {
+                format!("while validating section {}", "weather".bold().magenta())
+            })?;
+        }
+        let cli_defaults = &options.cli_defaults;
+        let mut errors = Vec::new();
+        let mut error_kind = None;
+        for name in ["default", "any"] {
+            let info = config.data.get(name).cloned().unwrap_or_default();
+            // An unknown set is reported while validating the section below
+            let info = with_set(&config.sets, &info).unwrap_or(info);
+            match name {
+                "default" => config.default = cli_defaults.merge(&info),
+                _ => config.any = info,
+            }
+        }
+        if let Some(default) = config.data.get_mut("default") {
+            default.clone_from(&config.default);
+        }
+        config.data.retain(|name, info| {
+            let validated = with_set(&config.sets, info).and_then(|resolved| {
+                *info = resolved;
+                // The default configuration does not follow these rules
+                // We still need the default configuration here because the path needs to be cached
+                if info == &config.default {
+                    Ok(())
+                } else {
+                    info.apply_and_validate(&config.default).map(|_| ())
+                }
+            });
+            match validated
+                .with_context(|| format!("while validating display {}", name.bold().magenta()))
+            {
+                Ok(_) => true,
+                Err(err) => {
+                    let kind = ConfigErrorKind::of(&err);
+                    error_kind =
+                        Some(error_kind.map_or(kind, |first: ConfigErrorKind| first.min(kind)));
+                    let mut message = strip_colors(&format!("{err:#}"));
+                    let err = match locate(&content, name, &format!("{err:#}")) {
+                        Some(location) => {
+                            message = format!("{message}\n{location}");
+                            err.section(location.header("Location:"))
+                        }
+                        None => err,
+                    };
+                    // We do not want to exit when error occurs, print it and go forward
+                    warn!("{err:?}");
+                    errors.push(message);
+                    false
+                }
+            }
+        });
+
+        config.path = path.to_path_buf();
+        if options.strict && !errors.is_empty() {
+            return Err(anyhow!(
+                "{} not valid in strict mode",
+                if errors.len() == 1 {
+                    "1 section is".to_string()
+                } else {
+                    format!("{} sections are", errors.len())
+                }
+            ))
+            .with_suggestion(|| {
+                "fix the errors above, or run wpaperd without --strict to ignore the sections with an error"
+                    .to_string()
+            });
+        }
+        errors.sort();
+        config.options = options.clone();
+        config.errors = errors;
+        config.error_kind = error_kind;
+        Ok(config)
+    }
+
+    /// Used in place of the configuration file when it doesn't exist: all the displays use the
+    /// values passed from the command line, showing the pictures directory when no path is passed
+    pub fn without_file(path: &Path, options: &LoadOptions) -> Self {
+        let mut default = options.cli_defaults.clone();
+        if default.source_attributes().is_empty() {
+            default.path = dirs::picture_dir();
+        }
+        Self {
+            default,
+            path: path.to_path_buf(),
+            options: options.clone(),
+            ..Self::default()
+        }
+    }
+
+    /// Apply the overrides set at runtime for `name`, if any
+    fn overridden(&self, name: &str, info: &SerializedWallpaperInfo) -> SerializedWallpaperInfo {
+        match self.overrides.get(name) {
+            Some(overrides) => info.merge(overrides),
+            None => info.clone(),
+        }
+    }
+
+    /// Return the configuration of the display and the default one, before validating them
+    fn serialized_output(&self, name: &str) -> (SerializedWallpaperInfo, SerializedWallpaperInfo) {
+        let default = self.overridden("default", &self.default);
+        let info = match self.display_section(name) {
+            Some(section) => self.data[section].clone(),
+            None => self.overridden("any", &self.any),
+        };
+        let mut info = self.overridden(name, &info);
+        if let Some(seat) = &self.seat {
+            // The values changed for the seat take precedence, like its section
+            info = self.overridden(&format!("{seat}/{name}"), &info);
+        }
+        let today = Local::now().date_naive();
+        // From the lowest precedence to the highest: the calendar, the weather, the color scheme
+        // and the workspace
+        let workspace = self.active_workspaces.get(name);
+        (
+            info.for_date(today)
+                .for_weather(self.weather_condition)
+                .for_color_scheme(self.color_scheme)
+                .for_workspace(workspace),
+            default
+                .for_date(today)
+                .for_weather(self.weather_condition)
+                .for_color_scheme(self.color_scheme)
+                .for_workspace(workspace),
+        )
+    }
+
+    /// The section named after the display, the one for the seat first
+    fn display_section(&self, name: &str) -> Option<&str> {
+        self.seat
+            .as_ref()
+            .and_then(|seat| self.data.get_key_value(&format!("{seat}/{name}")))
+            .or_else(|| self.data.get_key_value(name))
+            .map(|(section, _)| section.as_str())
+    }
+
+    /// The section of the configuration used by the display, if any
+    pub fn matched_section<'a>(&'a self, name: &'a str) -> Option<&'a str> {
+        self.sections_chain(name).first().copied()
+    }
+
+    /// The sections of the configuration used by the display, from the one with the highest
+    /// precedence to the lowest
+    pub fn sections_chain<'a>(&'a self, name: &'a str) -> Vec<&'a str> {
+        let mut sections = Vec::new();
+        if let Some(section) = self.display_section(name) {
+            sections.push(section);
+        } else if self.data.contains_key("any") {
+            sections.push("any");
+        }
+        if name != "default" && self.data.contains_key("default") {
+            sections.push("default");
+        }
+        sections
+    }
+
+    /// True if the display has values changed at runtime
+    pub fn has_overrides(&self, name: &str) -> bool {
+        ["default", "any", name]
+            .iter()
+            .any(|section| self.overrides.contains_key(*section))
+    }
+
+    pub fn section_names(&self) -> impl Iterator<Item = &String> {
+        self.data.keys()
+    }
+
+    pub fn get_output_by_name(&self, name: &str) -> Result<WallpaperInfo> {
+        let (info, default) = self.serialized_output(name);
+        if let Some(mirror) = info.mirror {
+            return self.get_mirror(name, mirror);
+        }
+        let mut wallpaper_info = info.apply_and_validate(&default)?;
+        wallpaper_info.seed = wallpaper_info.seed.or(self.seed);
+        // Only the workspaces with their own directory change the wallpaper
+        wallpaper_info.workspace = self
+            .active_workspaces
+            .get(name)
+            .filter(|workspace| {
+                [&info, &default].iter().any(|info| {
+                    info.workspaces
+                        .as_ref()
+                        .is_some_and(|workspaces| workspaces.contains_key(*workspace))
+                })
+            })
+            .cloned();
+        if self.reduce_motion() {
+            wallpaper_info.transition_time = 0;
+            wallpaper_info.initial_transition = false;
+            wallpaper_info.animate = false;
+            wallpaper_info.audio_reactive = 0.0;
+            wallpaper_info.parallax = 0.0;
+        }
+        if self.zen {
+            wallpaper_info.high_contrast = Some(self.zen_color.unwrap_or(Color::NEUTRAL));
+            wallpaper_info.zen_image.clone_from(&self.zen_image);
+        }
+        // It takes precedence over zen
+        if self.high_contrast() {
+            wallpaper_info.high_contrast = Some(self.high_contrast_color);
+            wallpaper_info.zen_image = None;
+        }
+        wallpaper_info.theme = self
+            .theme
+            .clone()
+            .filter(|theme| theme.output.is_none() || theme.output.as_deref() == Some(name));
+        Ok(wallpaper_info)
+    }
+
+    /// The display `name` shows the images of `mirror`, using its configuration
+    fn get_mirror(&self, name: &str, mirror: String) -> Result<WallpaperInfo> {
+        if mirror == name || self.serialized_output(&mirror).0.mirror.is_some() {
+            return Err(anyhow!(
+                "Attribute {} must be the name of a display that doesn't mirror another one",
+                "mirror".bold().italic().blue()
+            )
+            .with_suggestion(|| {
+                format!(
+                    "Set {} to the name of the display showing the images",
+                    "mirror".bold().italic().blue()
+                )
+            }));
+        }
+        let mut wallpaper_info = self
+            .get_output_by_name(&mirror)
+            .with_context(|| format!("while reading display {}", mirror.bold().magenta()))?;
+        // The images are picked by the display being mirrored, don't join its group or pool
+        wallpaper_info.duration = None;
+        wallpaper_info.schedule = None;
+        wallpaper_info.rotation = None;
+        wallpaper_info.sorting = None;
+        wallpaper_info.pool = Pool::Independent;
+        wallpaper_info.sync = false;
+        wallpaper_info.source = None;
+        wallpaper_info.script = None;
+        wallpaper_info.mirror = Some(mirror);
+        wallpaper_info.theme = None;
+        Ok(wallpaper_info)
+    }
+
+    /// The file set as `defaults-file`, relative to the directory of the configuration
+    pub fn defaults_file(&self) -> Option<&PathBuf> {
+        self.defaults_file.as_ref()
+    }
+
+    /// The configuration file, the system-wide one and the defaults file, when set
+    pub fn config_files(&self) -> impl Iterator<Item = &PathBuf> {
+        std::iter::once(&self.path)
+            .chain(&self.options.system_path)
+            .chain(&self.defaults_file)
+    }
+
+    /// Reload the configuration when the files are written, created or replaced by a rename, also
+    /// when they didn't exist at startup. The symlinks are followed through the directories of
+    /// their targets, so that replacing a target is noticed as well. Return the directories
+    /// watched; call it again after each reload, in case a symlink points somewhere else
+    pub fn listen_to_changes(&self, watcher: &mut Watcher) -> Result<Vec<PathBuf>> {
+        let events = self.events.as_ref().unwrap().clone();
+        let handler = move |kind| {
+            if let hotwatch::EventKind::Create(_) | hotwatch::EventKind::Modify(_) = kind {
+                events.send(Event::ConfigChanged);
+            }
+        };
+        let files: Vec<PathBuf> = self
+            .config_files()
+            .flat_map(|path| symlink_chain(path))
+            .collect();
+        watcher
+            .watch_files(&files, handler)
+            .with_context(|| format!("watching files {files:?}"))
+    }
+
+    /// Change the values of a display at runtime, without touching the configuration file.
+    /// The change is reverted if the resulting configuration is not valid.
+    pub fn add_override(&mut self, name: &str, values: toml::Table) -> Result<()> {
+        self.add_overrides(toml::Table::from_iter([(
+            name.to_string(),
+            toml::Value::Table(values),
+        )]))
+    }
+
+    /// Change the duration of a display at runtime, see [Config::add_override]
+    pub fn override_duration(&mut self, name: &str, duration: Duration) -> Result<()> {
+        let duration = humantime_serde::re::humantime::format_duration(duration).to_string();
+        self.add_override(
+            name,
+            toml::Table::from_iter([("duration".to_string(), toml::Value::String(duration))]),
+        )
+    }
+
+    /// Same as [Config::add_override], for multiple sections at once. `default` and `any`
+    /// are applied over the respective sections.
+    pub fn add_overrides(&mut self, values: toml::Table) -> Result<()> {
+        let overrides: HashMap<String, SerializedWallpaperInfo> = values.clone().try_into()?;
+        let previous = self.overrides.clone();
+        for (name, info) in overrides {
+            // Switching to another set replaces the values with the ones of the set
+            let info = with_set(&self.sets, &info)
+                .with_context(|| format!("while validating display {}", name.bold().magenta()))?;
+            let merged = self
+                .overrides
+                .get(&name)
+                .cloned()
+                .unwrap_or_default()
+                .merge(&info);
+            self.overrides.insert(name, merged);
+        }
+
+        // Changing the default section affects all the others
+        let names: Vec<String> = self
+            .data
+            .keys()
+            .chain(self.overrides.keys())
+            .filter(|name| *name != "default")
+            .cloned()
+            .collect();
+        for name in names {
+            if let Err(err) = self
+                .get_output_by_name(&name)
+                .with_context(|| format!("while validating display {}", name.bold().magenta()))
+            {
+                self.overrides = previous;
+                return Err(err);
+            }
+        }
+        for (name, values) in values {
+            if let toml::Value::Table(values) = values {
+                let section = self
+                    .override_values
+                    .entry(name)
+                    .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+                if let toml::Value::Table(section) = section {
+                    for key in replaced_keys(&values) {
+                        section.remove(key);
+                    }
+                    section.extend(values);
+                }
+            }
+        }
+        self.overrides_changed = true;
+        Ok(())
+    }
+
+    /// Return the configuration file with the values changed at runtime applied over it,
+    /// keeping its comments and formatting
+    pub fn dump(&self) -> Result<String> {
+        let content = fs::read_to_string(&self.path)
+            .with_context(|| format!("reading configuration file {:?}", self.path))?;
+        // The JSON configurations are dumped as TOML, they have no comments to keep anyway
+        let content = if is_json(&self.path) {
+            toml::to_string(&parse_table(&self.path, &content)?)
+                .context("converting the configuration to TOML")?
+        } else {
+            content
+        };
+        let mut document: DocumentMut =
+            content.parse().context("parsing the configuration file")?;
+        apply_override_values(&mut document, &self.override_values)?;
+        if let Some(reduce_motion) = self.reduce_motion_override {
+            document["reduce-motion"] = toml_edit::value(reduce_motion);
+        }
+        if let Some(high_contrast) = self.high_contrast_override {
+            document["high-contrast"] = toml_edit::value(high_contrast);
+        }
+        if let Some(backend) = &self.backend_override {
+            let mut table = toml_edit::Table::new();
+            if let Some(renderer) = backend.renderer {
+                table["renderer"] = toml_edit::value(renderer.to_string());
+            }
+            if let Some(device) = &backend.device {
+                table["device"] = toml_edit::value(device);
+            }
+            document["backend"] = toml_edit::Item::Table(table);
+        }
+        Ok(document.to_string())
+    }
+
+    /// Remove all the values changed at runtime
+    pub fn reset_overrides(&mut self) {
+        if !self.overrides.is_empty()
+            || self.reduce_motion_override.is_some()
+            || self.high_contrast_override.is_some()
+            || self.backend_override.is_some()
+        {
+            self.overrides.clear();
+            self.override_values.clear();
+            self.reduce_motion_override = None;
+            self.high_contrast_override = None;
+            self.backend_override = None;
+            self.overrides_changed = true;
+        }
+    }
+
+    /// True if the transitions are disabled
+    pub fn reduce_motion(&self) -> bool {
+        self.reduce_motion_override.unwrap_or(self.reduce_motion)
+    }
+
+    /// Enable or disable reduce-motion at runtime, toggle it when `enabled` is `None`.
+    /// Return the new value
+    pub fn set_reduce_motion(&mut self, enabled: Option<bool>) -> bool {
+        let enabled = enabled.unwrap_or(!self.reduce_motion());
+        if enabled != self.reduce_motion() {
+            self.overrides_changed = true;
+        }
+        self.reduce_motion_override = Some(enabled);
+        enabled
+    }
+
+    /// True if a solid color is shown in place of the wallpapers
+    pub fn high_contrast(&self) -> bool {
+        self.high_contrast_override.unwrap_or(self.high_contrast)
+    }
+
+    /// Enable or disable high-contrast at runtime, toggle it when `enabled` is `None`.
+    /// Return the new value
+    pub fn set_high_contrast(&mut self, enabled: Option<bool>) -> bool {
+        let enabled = enabled.unwrap_or(!self.high_contrast());
+        if enabled != self.high_contrast() {
+            self.overrides_changed = true;
+        }
+        self.high_contrast_override = Some(enabled);
+        enabled
+    }
+
+    /// True if the zen color or image is shown in place of the wallpapers
+    pub fn zen(&self) -> bool {
+        self.zen
+    }
+
+    /// Enable or disable zen, toggle it when `enabled` is `None`. Return the new value
+    pub fn set_zen(&mut self, enabled: Option<bool>) -> bool {
+        let enabled = enabled.unwrap_or(!self.zen);
+        if enabled != self.zen {
+            self.zen = enabled;
+            self.overrides_changed = true;
+        }
+        enabled
+    }
+
+    /// The backend drawing the wallpapers
+    pub fn backend(&self) -> &Backend {
+        self.backend_override.as_ref().unwrap_or(&self.backend)
+    }
+
+    /// Change the backend at runtime, `None` restores the one of the file. The surfaces are
+    /// recreated by [crate::wpaperd::Wpaperd::switch_backend]. Return the previous value
+    pub fn set_backend(&mut self, backend: Option<Backend>) -> Option<Backend> {
+        std::mem::replace(&mut self.backend_override, backend)
+    }
+
+    /// Return true if the overrides have changed since the last call
+    pub fn take_overrides_changed(&mut self) -> bool {
+        std::mem::take(&mut self.overrides_changed)
+    }
+
+    /// Called at midnight, the paths in the calendars might change
+    pub fn change_day(&mut self) {
+        self.day_changed = self
+            .data
+            .values()
+            .chain(self.overrides.values())
+            .any(|info| info.calendar_entries().next().is_some());
+    }
+
+    /// Called when the weather condition changes, the paths of the displays might change
+    pub fn set_weather_condition(&mut self, condition: Option<Condition>) {
+        self.weather_condition = condition;
+        self.weather_changed = self
+            .data
+            .values()
+            .chain(self.overrides.values())
+            .any(|info| info.weather.is_some());
+    }
+
+    /// Return true if the weather changed since the last call and the displays must be updated
+    pub fn take_weather_changed(&mut self) -> bool {
+        std::mem::take(&mut self.weather_changed)
+    }
+
+    /// Called when the color scheme of the desktop changes, the paths of the displays might change
+    pub fn set_color_scheme(&mut self, scheme: Option<ColorScheme>) {
+        if self.color_scheme != scheme {
+            self.color_scheme = scheme;
+            self.color_scheme_changed = self.uses_color_scheme();
+        }
+    }
+
+    /// Return true if the color scheme changed since the last call and the displays must be
+    /// updated
+    pub fn take_color_scheme_changed(&mut self) -> bool {
+        std::mem::take(&mut self.color_scheme_changed)
+    }
+
+    /// True if a display has directories for the color schemes
+    pub fn uses_color_scheme(&self) -> bool {
+        self.data
+            .values()
+            .chain(self.overrides.values())
+            .chain([&self.default, &self.any])
+            .any(|info| info.color_scheme.is_some())
+    }
+
+    /// Called when the active workspace of display `name` changes, its path might change
+    pub fn set_workspace(&mut self, name: &str, workspace: Option<String>) {
+        let previous = match workspace {
+            Some(workspace) => self.active_workspaces.insert(name.to_string(), workspace),
+            None => self.active_workspaces.remove(name),
+        };
+        if previous.as_ref() != self.active_workspaces.get(name) {
+            self.workspaces_changed |= self.uses_workspaces();
+        }
+    }
+
+    /// Return true if a workspace changed since the last call and the displays must be updated
+    pub fn take_workspaces_changed(&mut self) -> bool {
+        std::mem::take(&mut self.workspaces_changed)
+    }
+
+    /// True if the IPC of the compositor can be used
+    pub fn compositor_ipc(&self) -> bool {
+        self.compositor_ipc.unwrap_or(true)
+    }
+
+    /// True if a display has directories for some workspaces
+    pub fn uses_workspaces(&self) -> bool {
+        self.data
+            .values()
+            .chain(self.overrides.values())
+            .chain([&self.default, &self.any])
+            .any(|info| info.workspaces.is_some())
+    }
+
+    /// True if the wallpapers must not be changed automatically now
+    pub fn is_quiet(&self) -> bool {
+        self.quiet_hours
+            .as_ref()
+            .is_some_and(|quiet_hours| quiet_hours.contains(Local::now().time()))
+    }
+
+    /// True if some display follows the ambient light sensor
+    pub fn reads_light_sensor(&self) -> bool {
+        self.data
+            .values()
+            .chain([&self.default, &self.any])
+            .filter_map(|info| info.brightness)
+            .any(|brightness| brightness.source == BrightnessSource::Sensor)
+    }
+
+    /// Return true if the day changed since the last call and the displays must be updated
+    pub fn take_day_changed(&mut self) -> bool {
+        std::mem::take(&mut self.day_changed)
+    }
+
+    /// The directories to cache, along with the options used to read them
+    pub fn paths(&self) -> Vec<(PathBuf, FilelistOptions)> {
+        let mut paths: Vec<_> = self
+            .data
+            .keys()
+            .chain(self.overrides.keys())
+            .map(|name| self.serialized_output(name))
+            .chain(std::iter::once(self.serialized_output("any")))
+            .filter_map(|(info, default)| {
+                // A source set in the display replaces the path of the default section
+                let source = if info.source_attributes().is_empty() {
+                    &default
+                } else {
+                    &info
+                };
+                // The directory of newest is watched like the others
+                let path = source.path.as_ref().or(source.newest.as_ref());
+                path.map(|p| (p.to_path_buf(), info.filelist_options(&default)))
+            })
+            .collect();
+        paths.sort_unstable();
+        paths.dedup();
+        paths
+    }
+
+    /// The files and directories read by the displays: the paths, with the ones in the calendars,
+    /// the seasons, the weather, the color schemes and the workspaces, the playlists, the scripts and the initial
+    /// images
+    pub fn accessed_paths(&self) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = self
+            .data
+            .values()
+            .chain(self.overrides.values())
+            .chain([&self.default, &self.any])
+            .flat_map(|info| {
+                let initial = match &info.initial {
+                    Some(InitialImage::Path(path)) => Some(path.clone()),
+                    _ => None,
+                };
+                info.path
+                    .clone()
+                    .into_iter()
+                    .chain(info.playlist.clone())
+                    .chain(info.newest.clone())
+                    .chain(info.script.clone())
+                    .chain(initial)
+                    .chain(info.calendar_entries().map(|entry| entry.path.clone()))
+                    .chain(
+                        info.weather
+                            .iter()
+                            .flat_map(|weather| weather.values().cloned()),
+                    )
+                    .chain(
+                        info.color_scheme
+                            .iter()
+                            .flat_map(|color_scheme| color_scheme.values().cloned()),
+                    )
+                    .chain(
+                        info.workspaces
+                            .iter()
+                            .flat_map(|workspaces| workspaces.values().cloned()),
+                    )
+            })
+            .collect();
+        paths.sort_unstable();
+        paths.dedup();
+        paths
+    }
+
+    /// Return true if the struct changed. The last valid configuration is kept when the file
+    /// cannot be loaded, and it is read again until it becomes valid or [RELOAD_GRACE_PERIOD]
+    /// expires, as editors might write it in multiple steps
+    pub fn update(&mut self) -> bool {
+        let in_grace_period = self
+            .invalid_since
+            .map_or(true, |since| since.elapsed() < RELOAD_GRACE_PERIOD);
+        // When the config file has been written into
+        let new_config = match fs::metadata(&self.path) {
+            // The file has just been truncated to be written again
+            Ok(metadata) if metadata.len() == 0 && in_grace_period => {
+                Err(anyhow!("file {:?} is empty", self.path))
+            }
+            _ => Config::new_from_path(&self.path, &self.options),
+        }
+        .with_context(|| {
+            format!(
+                "updating configuration from file {}",
+                self.path.to_string_lossy()
+            )
+        });
+        if new_config.is_ok() {
+            self.invalid_since = None;
+        }
+        match new_config {
+            Ok(new_config) if new_config != *self => {
+                *self = Config {
+                    events: self.events.take(),
+                    seed: self.seed,
+                    threads: self.threads,
+                    seat: self.seat.clone(),
+                    overrides: std::mem::take(&mut self.overrides),
+                    override_values: std::mem::take(&mut self.override_values),
+                    overrides_changed: self.overrides_changed,
+                    reduce_motion_override: self.reduce_motion_override,
+                    high_contrast_override: self.high_contrast_override,
+                    zen: self.zen,
+                    backend_override: self.backend_override.take(),
+                    weather_condition: self.weather_condition,
+                    color_scheme: self.color_scheme,
+                    active_workspaces: std::mem::take(&mut self.active_workspaces),
+                    ..new_config
+                };
+                true
+            }
+            Ok(new_config) => {
+                // Do nothing, the new config is the same as the loaded one
+                self.errors = new_config.errors;
+                self.error_kind = new_config.error_kind;
+                false
+            }
+            Err(err) if in_grace_period => {
+                debug!("{err:?}");
+                self.invalid_since.get_or_insert_with(Instant::now);
+                self.retry_reload = true;
+                false
+            }
+            Err(err) => {
+                error!("{err:?}");
+                self.errors = vec![strip_colors(&format!("{err:#}"))];
+                self.error_kind = Some(ConfigErrorKind::of(&err));
+                // The next change starts a new grace period
+                self.invalid_since = None;
+                false
+            }
+        }
+    }
+
+    /// The errors found in the configuration file, the sections with an error are ignored
+    pub fn errors(&self) -> &[String] {
+        &self.errors
+    }
+
+    /// The class of the first error in [Config::errors], if any
+    pub fn error_kind(&self) -> Option<ConfigErrorKind> {
+        self.error_kind
+    }
+
+    /// Return true if the configuration file must be read again after [RELOAD_RETRY_INTERVAL]
+    pub fn take_retry_reload(&mut self) -> bool {
+        std::mem::take(&mut self.retry_reload)
+    }
+}
+
+impl PartialEq for Config {
+    fn eq(&self, other: &Self) -> bool {
+        self.data == other.data
+            && self.quiet_hours == other.quiet_hours
+            && self.decode_limits == other.decode_limits
+            && self.tone_mapping == other.tone_mapping
+            && self.backend == other.backend
+            && self.theme == other.theme
+            && self.weather == other.weather
+            && self.reduce_motion == other.reduce_motion
+            && self.high_contrast == other.high_contrast
+            && self.high_contrast_color == other.high_contrast_color
+            && self.zen_color == other.zen_color
+            && self.zen_image == other.zen_image
+            && self.on_conflict == other.on_conflict
+            && self.compositor_ipc == other.compositor_ipc
+    }
+}
+
+/// Merge the sections of the user configuration over the ones of the system configuration,
+/// each value set by the user replaces the system one
+fn layer(mut system: toml::Table, user: toml::Table) -> toml::Table {
+    fn merge_section(base: &mut toml::Table, values: toml::Table) {
+        for key in replaced_keys(&values) {
+            base.remove(key);
+        }
+        base.extend(values);
+    }
+
+    for (name, values) in user {
+        match (system.get_mut(&name), values) {
+            (Some(toml::Value::Table(sets)), toml::Value::Table(values)) if name == "set" => {
+                for (set, values) in values {
+                    match (sets.get_mut(&set), values) {
+                        (Some(toml::Value::Table(base)), toml::Value::Table(values)) => {
+                            merge_section(base, values)
+                        }
+                        (_, values) => {
+                            sets.insert(set, values);
+                        }
+                    }
+                }
+            }
+            (Some(toml::Value::Table(base)), toml::Value::Table(values))
+                if name != "quiet-hours" =>
+            {
+                merge_section(base, values)
+            }
+            (_, values) => {
+                system.insert(name, values);
+            }
+        }
+    }
+    system
+}
+
+/// Merge `info` over the values of the set it uses, if any
+fn with_set(
+    sets: &HashMap<String, SerializedWallpaperInfo>,
+    info: &SerializedWallpaperInfo,
+) -> Result<SerializedWallpaperInfo> {
+    let Some(name) = &info.set else {
+        return Ok(info.clone());
+    };
+    let Some(set) = sets.get(name) else {
+        let mut names: Vec<&String> = sets.keys().collect();
+        names.sort();
+        return Err(anyhow!(
+            "set {} for attribute {} is not defined",
+            name.italic().yellow(),
+            "set".bold().italic().blue(),
+        ))
+        .with_suggestion(|| {
+            if names.is_empty() {
+                format!("add a section {}", format!("[set.{name}]").bold().magenta())
+            } else {
+                format!(
+                    "use one of the sets defined: {}",
+                    names
+                        .iter()
+                        .map(|name| name.italic().yellow().to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            }
+        });
+    };
+    if set.set.is_some() {
+        return Err(anyhow!(
+            "set {} cannot use another set with attribute {}",
+            name.italic().yellow(),
+            "set".bold().italic().blue(),
+        ))
+        .with_suggestion(|| {
+            format!(
+                "remove attribute {} from {}",
+                "set".bold().italic().blue(),
+                format!("[set.{name}]").bold().magenta()
+            )
+        });
+    }
+    Ok(set.merge(info))
+}
+
+/// The keys that `values` replaces in a section even when it doesn't set them, as done by
+/// [SerializedWallpaperInfo::merge]
+fn replaced_keys(values: &toml::Table) -> Vec<&'static str> {
+    const TIMING: [&str; 3] = ["duration", "schedule", "rotation"];
+    const SOURCE: [&str; 6] = ["path", "playlist", "url", "provider", "command", "newest"];
+    const RECOLOR: [&str; 2] = ["tint", "duotone"];
+    let mut keys = Vec::new();
+    if TIMING.iter().any(|key| values.contains_key(*key)) {
+        keys.extend(TIMING);
+    }
+    if RECOLOR.iter().any(|key| values.contains_key(*key)) {
+        keys.extend(RECOLOR);
+    }
+    if SOURCE.iter().any(|key| values.contains_key(*key)) {
+        keys.extend(SOURCE);
+        keys.extend([
+            "newest-blur",
+            "calendar",
+            "seasons",
+            "weather",
+            "color-scheme",
+            "workspaces",
+        ]);
+    }
+    keys
+}
+
+/// Write the sections in `overrides` over the ones in `document`. A display without its own
+/// section used the `any` one, which is copied into the new section
+fn apply_override_values(document: &mut DocumentMut, overrides: &toml::Table) -> Result<()> {
+    for (name, values) in overrides {
+        let toml::Value::Table(values) = values else {
+            continue;
+        };
+        if !document.contains_key(name) {
+            let mut section = match document.get("any").and_then(|any| any.as_table()) {
+                Some(any) if name != "default" => any.clone(),
+                _ => toml_edit::Table::new(),
+            };
+            section.decor_mut().clear();
+            document.insert(name, toml_edit::Item::Table(section));
+        }
+        let section = document[name.as_str()]
+            .as_table_like_mut()
+            .with_context(|| format!("section {} is not a table", name.bold().magenta()))?;
+        for key in replaced_keys(values) {
+            section.remove(key);
+        }
+        for (key, value) in values {
+            // The values printed by toml are valid TOML values
+            let value: toml_edit::Value = value
+                .to_string()
+                .parse()
+                .with_context(|| format!("converting value of {}", key.bold().italic().blue()))?;
+            section.insert(key, toml_edit::Item::Value(value));
+        }
+    }
+    Ok(())
+}
+
+pub fn tilde_expansion_deserialize<'de, D>(deserializer: D) -> Result<Option<PathBuf>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let path = String::deserialize(deserializer)?;
+    Ok(Some(tilde_expansion(Path::new(&path))))
+}
+
+pub fn tilde_expansion(path: &Path) -> PathBuf {
+    path.strip_prefix("~")
+        .map_or(path.to_path_buf(), |p| home_dir().unwrap().join(p))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_precedence() {
+        let dir = std::env::temp_dir().join(format!("wpaperd-config-{}", std::process::id()));
+        let wallpapers = dir.join("wallpapers");
+        fs::create_dir_all(&wallpapers).unwrap();
+        let file = dir.join("config.toml");
+        fs::write(
+            &file,
+            format!(
+                r#"
+[default]
+path = "{}"
+duration = "30m"
+mode = "fit"
+transition.bounce = {{ bounces = 5.0, shadow-height = 0.2 }}
+
+[any]
+mode = "tile"
+
+[DP-1]
+sorting = "ascending"
+transition.bounce = {{ shadow-height = 0.1 }}
+
+["seat1/DP-1"]
+sorting = "descending"
+"#,
+                wallpapers.display()
+            ),
+        )
+        .unwrap();
+        let mut config = Config::new_from_path(
+            &file,
+            &LoadOptions {
+                strict: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        // The display section takes the values it doesn't set from the default one, along with
+        // the parameters of the same transition
+        let info = config.get_output_by_name("DP-1").unwrap();
+        assert_eq!(info.path, wallpapers);
+        assert_eq!(info.duration, Some(Duration::from_secs(30 * 60)));
+        assert_eq!(info.mode, BackgroundMode::Fit);
+        assert_eq!(info.sorting, Some(Sorting::Ascending));
+        assert_eq!(
+            info.transition,
+            Transition::Bounce {
+                shadow_colour: None,
+                shadow_height: Some(0.1),
+                bounces: Some(5.0)
+            }
+        );
+
+        // The displays without a section use the any one, over the default one
+        let info = config.get_output_by_name("HDMI-A-1").unwrap();
+        assert_eq!(info.mode, BackgroundMode::Tile);
+        assert_eq!(info.duration, Some(Duration::from_secs(30 * 60)));
+
+        // The section of the seat replaces the one of the display
+        config.seat = Some("seat1".to_string());
+        let info = config.get_output_by_name("DP-1").unwrap();
+        assert_eq!(info.sorting, Some(Sorting::Descending));
+        assert_eq!(info.mode, BackgroundMode::Fit);
+
+        // The values changed at runtime take precedence over the file, the ones of the default
+        // section apply to all the displays
+        config
+            .add_overrides(
+                toml::from_str(
+                    r#"
+default = { duration = "1m" }
+"seat1/DP-1" = { mode = "center" }
+"#,
+                )
+                .unwrap(),
+            )
+            .unwrap();
+        let info = config.get_output_by_name("DP-1").unwrap();
+        assert_eq!(info.mode, BackgroundMode::Center);
+        assert_eq!(info.duration, Some(Duration::from_secs(60)));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_sets() {
+        let dir = std::env::temp_dir().join(format!("wpaperd-sets-{}", std::process::id()));
+        let nature = dir.join("nature");
+        fs::create_dir_all(&nature).unwrap();
+        let file = dir.join("config.toml");
+        fs::write(
+            &file,
+            format!(
+                r#"
+[default]
+duration = "30m"
+
+[set.nature]
+path = "{}"
+sorting = "ascending"
+mode = "fit"
+
+[DP-1]
+set = "nature"
+
+[DP-2]
+set = "nature"
+mode = "tile"
+
+[DP-3]
+set = "city"
+"#,
+                nature.display()
+            ),
+        )
+        .unwrap();
+        let config = Config::new_from_path(&file, &LoadOptions::default()).unwrap();
+
+        // The section takes the values from the set, then from the default section
+        let info = config.get_output_by_name("DP-1").unwrap();
+        assert_eq!(info.path, nature);
+        assert_eq!(info.sorting, Some(Sorting::Ascending));
+        assert_eq!(info.duration, Some(Duration::from_secs(30 * 60)));
+        let info = config.get_output_by_name("DP-2").unwrap();
+        assert_eq!(info.path, nature);
+        assert_eq!(info.mode, BackgroundMode::Tile);
+
+        // The sets that don't exist are reported like the other errors
+        assert_eq!(config.errors().len(), 1);
+        assert!(config.errors()[0].contains("set city for attribute set is not defined"));
+        assert!(!config.section_names().any(|name| name == "DP-3"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_zen() {
+        let dir = std::env::temp_dir().join(format!("wpaperd-zen-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("config.toml");
+        fs::write(
+            &file,
+            format!(
+                "zen-color = \"#102030\"\nhigh-contrast-color = \"#ffffff\"\n\n[default]\npath = \"{}\"\n",
+                dir.display()
+            ),
+        )
+        .unwrap();
+        let mut config = Config::new_from_path(&file, &LoadOptions::default()).unwrap();
+        assert_eq!(
+            config.get_output_by_name("DP-1").unwrap().high_contrast,
+            None
+        );
+
+        assert!(config.set_zen(None));
+        assert!(config.take_overrides_changed());
+        let info = config.get_output_by_name("DP-1").unwrap();
+        assert_eq!(info.high_contrast, Some(Color([0x10, 0x20, 0x30])));
+        // It is kept when the overrides are dropped, and high-contrast comes first
+        config.reset_overrides();
+        config.set_high_contrast(Some(true));
+        assert!(config.zen());
+        let info = config.get_output_by_name("DP-1").unwrap();
+        assert_eq!(info.high_contrast, Some(Color([0xff; 3])));
+
+        config.set_high_contrast(Some(false));
+        assert!(!config.set_zen(Some(false)));
+        assert_eq!(
+            config.get_output_by_name("DP-1").unwrap().high_contrast,
+            None
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_defaults_file() {
+        let dir = std::env::temp_dir().join(format!("wpaperd-defaults-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("config.toml");
+        fs::write(
+            dir.join("shared.toml"),
+            format!(
+                "path = \"{}\"\nduration = \"10m\"\nmode = \"fit\"\n",
+                dir.display()
+            ),
+        )
+        .unwrap();
+        fs::write(
+            &file,
+            "defaults-file = \"shared.toml\"\n\n[default]\nduration = \"1h\"\n\n[DP-1]\n",
+        )
+        .unwrap();
+        let config = Config::new_from_path(&file, &LoadOptions::default()).unwrap();
+        assert_eq!(config.defaults_file(), Some(&dir.join("shared.toml")));
+        assert!(config
+            .config_files()
+            .any(|path| *path == dir.join("shared.toml")));
+        // The default section of the configuration goes over the file
+        let info = config.get_output_by_name("DP-1").unwrap();
+        assert_eq!(info.path, dir);
+        assert_eq!(info.mode, BackgroundMode::Fit);
+        assert_eq!(info.duration, Some(Duration::from_secs(60 * 60)));
+
+        fs::remove_file(dir.join("shared.toml")).unwrap();
+        let err = Config::new_from_path(&file, &LoadOptions::default())
+            .err()
+            .unwrap();
+        assert_eq!(ConfigErrorKind::of(&err), ConfigErrorKind::MissingPath);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_json() {
+        let dir = std::env::temp_dir().join(format!("wpaperd-json-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("config.json");
+        let path = dir.display();
+        fs::write(
+            &file,
+            format!(
+                r#"{{
+  "default": {{ "path": "{path}", "duration": "30m", "transition-time": 500 }},
+  "DP-1": {{ "mode": "fit", "queue-size": 5 }},
+  "high-contrast": true
+}}"#
+            ),
+        )
+        .unwrap();
+        let config = Config::new_from_path(&file, &LoadOptions::default()).unwrap();
+        let info = config.get_output_by_name("DP-1").unwrap();
+        assert_eq!(info.path, dir);
+        assert_eq!(info.mode, BackgroundMode::Fit);
+        assert_eq!(info.duration, Some(Duration::from_secs(30 * 60)));
+        assert_eq!(info.transition_time, 500);
+        assert!(config.high_contrast());
+        assert!(config.dump().unwrap().contains("queue-size = 5"));
+
+        fs::write(&file, r#"{ "any": { "unknown": 1 } }"#).unwrap();
+        let err = Config::new_from_path(&file, &LoadOptions::default())
+            .err()
+            .unwrap();
+        assert_eq!(ConfigErrorKind::of(&err), ConfigErrorKind::Parse);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_error_kinds() {
+        let dir = std::env::temp_dir().join(format!("wpaperd-errors-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("config.toml");
+        let error_kind = |content: &str| {
+            fs::write(&file, content).unwrap();
+            match Config::new_from_path(&file, &LoadOptions::default()) {
+                Ok(config) => config.error_kind(),
+                Err(err) => Some(ConfigErrorKind::of(&err)),
+            }
+        };
+
+        let path = dir.display();
+        assert_eq!(error_kind(&format!("[any]\npath = \"{path}\"")), None);
+        assert_eq!(error_kind("[any]\npath = "), Some(ConfigErrorKind::Parse));
+        assert_eq!(
+            error_kind(&format!("[any]\npath = \"{path}\"\nunknown = 1")),
+            Some(ConfigErrorKind::Parse)
+        );
+        assert_eq!(
+            error_kind("[any]\npath = \"/does/not/exist\""),
+            Some(ConfigErrorKind::MissingPath)
+        );
+        // The missing path comes before the conflict of the other section
+        assert_eq!(
+            error_kind(&format!(
+                "[DP-1]\npath = \"{path}\"\nset = \"city\"\n[DP-2]\npath = \"/does/not/exist\""
+            )),
+            Some(ConfigErrorKind::MissingPath)
+        );
+        assert_eq!(
+            error_kind(&format!("[DP-1]\npath = \"{path}\"\nset = \"city\"")),
+            Some(ConfigErrorKind::Invalid)
+        );
+        assert_eq!(ConfigErrorKind::Invalid.exit_code(), 78);
+
+        fs::remove_dir_all(&dir).unwrap();
+        let err = Config::new_from_path(&file, &LoadOptions::default())
+            .err()
+            .unwrap();
+        assert_eq!(ConfigErrorKind::of(&err), ConfigErrorKind::MissingPath);
+    }
+
+    #[test]
+    fn test_layer() {
+        let system: toml::Table = toml::from_str(
+            r#"
+reduce-motion = true
+
+[default]
+playlist = "/usr/share/wallpapers/list"
+duration = "1h"
+mode = "center"
+
+[set.lab]
+path = "/usr/share/wallpapers/lab"
+
+[quiet-hours]
+start = "22:00"
+end = "07:00"
+"#,
+        )
+        .unwrap();
+        let user: toml::Table = toml::from_str(
+            r#"
+[default]
+path = "~/Pictures"
+schedule = "0 * * * *"
+
+[set.lab]
+sorting = "ascending"
+
+[quiet-hours]
+start = "23:00"
+"#,
+        )
+        .unwrap();
+        let expected: toml::Table = toml::from_str(
+            r#"
+reduce-motion = true
+
+[default]
+path = "~/Pictures"
+schedule = "0 * * * *"
+mode = "center"
+
+[set.lab]
+path = "/usr/share/wallpapers/lab"
+sorting = "ascending"
+
+[quiet-hours]
+start = "23:00"
+"#,
+        )
+        .unwrap();
+        assert_eq!(layer(system, user), expected);
+    }
+
+    #[test]
+    fn test_apply_override_values() {
+        let mut document: DocumentMut = r#"
+# Shown everywhere
+[any]
+path = "~/Pictures"
+duration = "30m"
+
+[DP-1]
+path = "~/Wallpapers" # the big one
+schedule = "0 * * * *"
+"#
+        .parse()
+        .unwrap();
+        let overrides: toml::Table = toml::from_str(
+            r#"
+DP-1 = { duration = "10m", mode = "fit" }
+HDMI-A-1 = { sorting = "ascending" }
+"#,
+        )
+        .unwrap();
+        apply_override_values(&mut document, &overrides).unwrap();
+
+        let dumped: toml::Table = toml::from_str(&document.to_string()).unwrap();
+        let expected: toml::Table = toml::from_str(
+            r#"
+any = { path = "~/Pictures", duration = "30m" }
+# The duration replaces the schedule
+DP-1 = { path = "~/Wallpapers", duration = "10m", mode = "fit" }
+# The display used the any section
+HDMI-A-1 = { path = "~/Pictures", duration = "30m", sorting = "ascending" }
+"#,
+        )
+        .unwrap();
+        assert_eq!(dumped, expected);
+        // The comments are kept
+        assert!(document.to_string().contains("# the big one"));
+    }
+}
@@ -0,0 +1,140 @@
+//! Other programs drawing the background, e.g. swaybg, which make the displays flicker when both
+//! are running.
+//!
+//! Wayland doesn't let a client see the surfaces of the others, so the running processes are read
+//! from /proc and compared with the wallpaper programs known. swaybg and mpvpaper tell the display
+//! they draw on, the other programs are assumed to draw on all of them. A script drawing with
+//! layer-shell directly cannot be detected.
+
+use std::{fs, path::Path, time::Duration};
+
+use serde::Deserialize;
+
+/// How often the processes are read again
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The programs drawing the background, by the name of their executable
+const PROGRAMS: &[&str] = &[
+    "swaybg",
+    "hyprpaper",
+    "swww-daemon",
+    "wbg",
+    "mpvpaper",
+    "oguri",
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnConflict {
+    /// Log a warning and keep drawing
+    #[default]
+    Warn,
+    /// Stop drawing on the displays of the other program, until it exits
+    Yield,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Conflict {
+    pub program: String,
+    pub pid: u32,
+    /// `None` when it draws on all the displays
+    pub outputs: Option<Vec<String>>,
+}
+
+impl Conflict {
+    /// Read the arguments of process `pid`, `None` if it is not a wallpaper program
+    fn from_cmdline(pid: u32, args: &[String]) -> Option<Self> {
+        let program = Path::new(args.first()?).file_name()?.to_str()?;
+        if !PROGRAMS.contains(&program) {
+            return None;
+        }
+        let outputs: Vec<String> = match program {
+            // swaybg -o DP-1 -i image.png -o HDMI-A-1 -i other.png
+            "swaybg" => args
+                .windows(2)
+                .filter(|pair| pair[0] == "-o" || pair[0] == "--output")
+                .map(|pair| pair[1].clone())
+                .collect(),
+            // mpvpaper [options] DP-1 video.mp4
+            "mpvpaper" if args.len() >= 3 => vec![args[args.len() - 2].clone()],
+            _ => Vec::new(),
+        };
+        let all = outputs.is_empty()
+            || outputs
+                .iter()
+                .any(|output| output == "*" || output == "ALL");
+        Some(Self {
+            program: program.to_string(),
+            pid,
+            outputs: (!all).then_some(outputs),
+        })
+    }
+
+    /// True if it draws on display `output`
+    pub fn covers(&self, output: &str) -> bool {
+        self.outputs
+            .as_ref()
+            .map_or(true, |outputs| outputs.iter().any(|name| name == output))
+    }
+
+    /// The displays it draws on, for the logs
+    pub fn displays(&self) -> String {
+        match &self.outputs {
+            Some(outputs) => format!("display {}", outputs.join(", ")),
+            None => "all the displays".to_string(),
+        }
+    }
+}
+
+/// The wallpaper programs running, besides wpaperd
+pub fn detect() -> Vec<Conflict> {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    let own_pid = std::process::id();
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let pid: u32 = entry.file_name().to_str()?.parse().ok()?;
+            if pid == own_pid {
+                return None;
+            }
+            let cmdline = fs::read(entry.path().join("cmdline")).ok()?;
+            let args: Vec<String> = cmdline
+                .split(|byte| *byte == 0)
+                .filter(|arg| !arg.is_empty())
+                .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                .collect();
+            Conflict::from_cmdline(pid, &args)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conflict(cmdline: &str) -> Option<Conflict> {
+        let args: Vec<String> = cmdline.split(' ').map(str::to_string).collect();
+        Conflict::from_cmdline(1, &args)
+    }
+
+    #[test]
+    fn test_from_cmdline() {
+        let swaybg = conflict("/usr/bin/swaybg -o DP-1 -i image.png -m fill").unwrap();
+        assert_eq!(swaybg.program, "swaybg");
+        assert!(swaybg.covers("DP-1"));
+        assert!(!swaybg.covers("HDMI-A-1"));
+
+        let swaybg = conflict("swaybg -o * -c #000000").unwrap();
+        assert!(swaybg.covers("HDMI-A-1"));
+        assert_eq!(swaybg.displays(), "all the displays");
+
+        let mpvpaper = conflict("mpvpaper -o no-audio HDMI-A-1 video.mp4").unwrap();
+        assert_eq!(mpvpaper.outputs, Some(vec!["HDMI-A-1".to_string()]));
+
+        assert!(conflict("swww-daemon").unwrap().covers("DP-1"));
+        assert!(conflict("/usr/bin/wpaperd -d").is_none());
+        assert!(conflict("vim swaybg").is_none());
+    }
+}
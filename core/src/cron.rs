@@ -0,0 +1,244 @@
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, NaiveDateTime, Timelike};
+use color_eyre::{
+    eyre::{anyhow, bail, ensure, Context},
+    Report, Result,
+};
+use serde::{de::IgnoredAny, Deserialize};
+
+/// How many days are checked when looking for the next time, enough to find 29 February
+const MAX_DAYS: usize = 366 * 8;
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// A cron expression with the five standard fields: minute, hour, day of the month, month and day
+/// of the week. Each field can be `*`, a value, a range (`1-5`), a step (`*/2` or `1-10/3`) or a
+/// list of them (`1,15`). Months and days of the week can also be written by name
+/// (`jan`, `mon`). `@hourly`, `@daily`, `@weekly`, `@monthly` and `@yearly` are supported too.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "ScheduleValue")]
+pub struct CronSchedule {
+    expression: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    /// 0 is Sunday
+    weekdays: u64,
+    /// Like in cron, when both the day of the month and of the week are set, a day matches if any
+    /// of them does
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+/// The value of `schedule`. The weekday and date entries of [crate::calendar] used to be named
+/// `schedule` too, they are told apart to point to their new name
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ScheduleValue {
+    Expression(String),
+    Entries(Vec<IgnoredAny>),
+}
+
+impl TryFrom<ScheduleValue> for CronSchedule {
+    type Error = Report;
+
+    fn try_from(value: ScheduleValue) -> Result<Self, Self::Error> {
+        match value {
+            ScheduleValue::Expression(expression) => Self::try_from(expression),
+            ScheduleValue::Entries(entries) => bail!(
+                "schedule is a cron expression, the {} directories used on some weekdays or dates \
+                 are set in calendar, e.g. [[DP-3.calendar]]",
+                entries.len()
+            ),
+        }
+    }
+}
+
+impl TryFrom<String> for CronSchedule {
+    type Error = Report;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let expression = match value.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            expression => expression,
+        };
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        ensure!(
+            fields.len() == 5,
+            "cron expression {value:?} must have 5 fields: minute, hour, day of the month, month \
+             and day of the week"
+        );
+        let parse = |field: &str, name: &str, min, max, names: &[&str]| {
+            parse_field(field, min, max, names)
+                .with_context(|| format!("parsing the field {name} of cron expression {value:?}"))
+        };
+        let mut weekdays = parse(fields[4], "day of the week", 0, 7, &WEEKDAYS)?;
+        // 7 is Sunday as well
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+        let schedule = Self {
+            minutes: parse(fields[0], "minute", 0, 59, &[])?,
+            hours: parse(fields[1], "hour", 0, 23, &[])?,
+            days: parse(fields[2], "day of the month", 1, 31, &[])?,
+            months: parse(fields[3], "month", 1, 12, &MONTHS)?,
+            weekdays,
+            days_restricted: !fields[2].starts_with('*'),
+            weekdays_restricted: !fields[4].starts_with('*'),
+            expression: value,
+        };
+        ensure!(
+            schedule.next_after(Local::now()).is_some(),
+            "cron expression {:?} never triggers",
+            schedule.expression
+        );
+        Ok(schedule)
+    }
+}
+
+/// Return a bitset with the values allowed by the field
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<u64> {
+    let value = |value: &str| -> Result<u32> {
+        let value = match names
+            .iter()
+            .position(|name| value.eq_ignore_ascii_case(name))
+        {
+            // The first month is 1, the first day of the week is 0
+            Some(index) => index as u32 + min,
+            None => value
+                .parse()
+                .with_context(|| format!("{value:?} is not a number"))?,
+        };
+        ensure!(
+            (min..=max).contains(&value),
+            "{value} is not between {min} and {max}"
+        );
+        Ok(value)
+    };
+
+    let mut bitset = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (
+                range,
+                step.parse()
+                    .with_context(|| format!("step {step:?} is not a number"))?,
+            ),
+            None => (part, 1),
+        };
+        ensure!(step > 0, "step must be greater than 0");
+        let (start, end) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((start, end)) => (value(start)?, value(end)?),
+                // 5/10 means from 5 to the end, every 10
+                None if step > 1 => (value(range)?, max),
+                None => (value(range)?, value(range)?),
+            },
+        };
+        ensure!(start <= end, "range {range:?} is empty");
+        bitset |= (start..=end)
+            .step_by(step)
+            .fold(0, |bitset, value| bitset | (1 << value));
+    }
+    if bitset == 0 {
+        return Err(anyhow!("field {field:?} is empty"));
+    }
+    Ok(bitset)
+}
+
+impl CronSchedule {
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        let day = self.days & (1 << date.day()) != 0;
+        let weekday = self.weekdays & (1 << date.weekday().num_days_from_sunday()) != 0;
+        let day = match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => day || weekday,
+            (true, false) => day,
+            (false, true) => weekday,
+            (false, false) => true,
+        };
+        day && self.months & (1 << date.month()) != 0
+    }
+
+    /// The first time after `time` that matches the expression
+    pub fn next_after(&self, time: DateTime<Local>) -> Option<DateTime<Local>> {
+        let time = time.naive_local();
+        // Start from the next minute
+        let start = time.date().and_hms_opt(time.hour(), time.minute(), 0)? + Duration::minutes(1);
+        let mut date = start.date();
+        for _ in 0..MAX_DAYS {
+            if self.matches_day(date) {
+                let next = (0..24)
+                    .filter(|hour| self.hours & (1 << hour) != 0)
+                    .flat_map(|hour| {
+                        (0..60)
+                            .filter(|minute| self.minutes & (1 << minute) != 0)
+                            .filter_map(move |minute| date.and_hms_opt(hour, minute, 0))
+                    })
+                    .filter(|next: &NaiveDateTime| *next >= start)
+                    // Skip the times that don't exist because of daylight saving time
+                    .find_map(|next| next.and_local_timezone(Local).earliest());
+                if next.is_some() {
+                    return next;
+                }
+            }
+            date = date.succ_opt()?;
+        }
+        None
+    }
+
+    /// How long until the next time that matches the expression
+    pub fn until_next(&self) -> std::time::Duration {
+        let now = Local::now();
+        self.next_after(now)
+            .and_then(|next| (next - now).to_std().ok())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn test_next_after() {
+        let schedule = CronSchedule::try_from("30 */2 * * mon-fri".to_string()).unwrap();
+        // Saturday 14 March 2026
+        let time = Local.with_ymd_and_hms(2026, 3, 14, 10, 0, 0).unwrap();
+        assert_eq!(
+            schedule.next_after(time),
+            Local.with_ymd_and_hms(2026, 3, 16, 0, 30, 0).single()
+        );
+        let time = Local.with_ymd_and_hms(2026, 3, 16, 0, 30, 0).unwrap();
+        assert_eq!(
+            schedule.next_after(time),
+            Local.with_ymd_and_hms(2026, 3, 16, 2, 30, 0).single()
+        );
+
+        assert!(CronSchedule::try_from("0 0 31 2 *".to_string()).is_err());
+        assert!(CronSchedule::try_from("60 * * * *".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_calendar_entries() {
+        #[derive(Debug, Deserialize)]
+        struct Section {
+            #[allow(dead_code)]
+            schedule: CronSchedule,
+        }
+        assert!(toml::from_str::<Section>("schedule = '@daily'").is_ok());
+        let err =
+            toml::from_str::<Section>("[[schedule]]\npath = '/wallpapers'\nweekdays = ['sat']")
+                .unwrap_err();
+        assert!(err.to_string().contains("[[DP-3.calendar]]"));
+    }
+}
@@ -0,0 +1,165 @@
+//! Order the work of the loader threads, so that the wallpapers asked for with wpaperctl don't
+//! wait behind the rotation of the other displays or the frames of the animations.
+//!
+//! A job is not spawned into the pool directly: each one queued spawns a task that runs the most
+//! urgent job queued when a thread becomes free. The jobs that are not interactive take at most
+//! `max-concurrent` threads of the pool, so that one is usually free when a command arrives. The
+//! limit is soft, a running job can't be interrupted and the image crate runs its own parallel
+//! work in the same pool.
+
+use std::{
+    cmp::Reverse,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Mutex},
+};
+
+use rayon::ThreadPool;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Priority {
+    /// The frames of the animations, decoded while the still image is shown
+    Background,
+    /// The wallpapers changing on their own, e.g. at the end of the duration
+    Visible,
+    /// Asked for with wpaperctl, e.g. `next-wallpaper`
+    Interactive,
+}
+
+struct Job {
+    id: u64,
+    priority: Priority,
+    run: Box<dyn FnOnce() + Send>,
+}
+
+#[derive(Default)]
+struct Queue {
+    jobs: Vec<Job>,
+    next_id: u64,
+    /// The jobs running that are not interactive
+    running: usize,
+    limit: usize,
+}
+
+impl Queue {
+    /// The job with the highest priority that can start now, the oldest one first
+    fn pop(&mut self) -> Option<Job> {
+        let index = self
+            .jobs
+            .iter()
+            .enumerate()
+            .filter(|(_, job)| job.priority == Priority::Interactive || self.running < self.limit)
+            .max_by_key(|(_, job)| (job.priority, Reverse(job.id)))
+            .map(|(index, _)| index)?;
+        let job = self.jobs.remove(index);
+        if job.priority != Priority::Interactive {
+            self.running += 1;
+        }
+        Some(job)
+    }
+}
+
+pub struct DecodeQueue {
+    queue: Arc<Mutex<Queue>>,
+}
+
+impl DecodeQueue {
+    /// Run at most `limit` jobs that are not interactive at once
+    pub fn new(limit: usize) -> Self {
+        let queue = Queue {
+            limit: limit.max(1),
+            ..Queue::default()
+        };
+        Self {
+            queue: Arc::new(Mutex::new(queue)),
+        }
+    }
+
+    pub fn set_limit(&self, limit: usize) {
+        self.queue.lock().unwrap().limit = limit.max(1);
+    }
+
+    /// Queue `job` and return its id, it runs in `pool` once the jobs more urgent have started
+    pub fn push(
+        &self,
+        pool: &ThreadPool,
+        priority: Priority,
+        job: impl FnOnce() + Send + 'static,
+    ) -> u64 {
+        let id = {
+            let mut queue = self.queue.lock().unwrap();
+            let id = queue.next_id;
+            queue.next_id += 1;
+            queue.jobs.push(Job {
+                id,
+                priority,
+                run: Box::new(job),
+            });
+            id
+        };
+        let queue = self.queue.clone();
+        pool.spawn(move || run_queued(&queue));
+        id
+    }
+
+    /// Raise the priority of the job `id` if it hasn't started yet
+    pub fn raise(&self, id: u64, priority: Priority) {
+        let mut queue = self.queue.lock().unwrap();
+        if let Some(job) = queue.jobs.iter_mut().find(|job| job.id == id) {
+            job.priority = job.priority.max(priority);
+        }
+    }
+}
+
+/// Run the queued jobs until none can start. A task that finds the limit reached ends, the
+/// thread of a running job picks up its job when it's done
+fn run_queued(queue: &Mutex<Queue>) {
+    loop {
+        let Some(job) = queue.lock().unwrap().pop() else {
+            return;
+        };
+        let limited = job.priority != Priority::Interactive;
+        // The job reports its own panics by dropping its sender, the queue must keep going
+        let _ = panic::catch_unwind(AssertUnwindSafe(job.run));
+        if limited {
+            queue.lock().unwrap().running -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use rayon::ThreadPoolBuilder;
+
+    use super::*;
+
+    #[test]
+    fn test_priority() {
+        let pool = ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        let queue = DecodeQueue::new(1);
+        let (unblock, blocked) = mpsc::channel::<()>();
+        queue.push(&pool, Priority::Visible, move || {
+            let _ = blocked.recv();
+        });
+        let (sender, order) = mpsc::channel();
+        for (name, priority) in [
+            ("frames", Priority::Background),
+            ("rotation", Priority::Visible),
+            ("next", Priority::Interactive),
+            ("show", Priority::Visible),
+        ] {
+            let sender = sender.clone();
+            let id = queue.push(&pool, priority, move || sender.send(name).unwrap());
+            if name == "show" {
+                queue.raise(id, Priority::Interactive);
+            }
+        }
+        drop(sender);
+        unblock.send(()).unwrap();
+        assert_eq!(
+            order.iter().collect::<Vec<_>>(),
+            ["next", "show", "rotation", "frames"]
+        );
+    }
+}
@@ -0,0 +1,94 @@
+//! Point at the part of the configuration file that a validation error is about.
+//!
+//! The errors of [crate::config::SerializedWallpaperInfo::apply_and_validate] name the
+//! attributes styled in the same way, so the attribute is found by looking for its styled name
+//! in the message.
+
+use std::ops::Range;
+
+use color_eyre::owo_colors::OwoColorize;
+use toml_edit::{ImDocument, Item};
+
+/// Return the line, the column and a snippet of `content` showing the attribute of `section`
+/// that `message` is about, or the section itself
+pub fn locate(content: &str, section: &str, message: &str) -> Option<String> {
+    let document = ImDocument::parse(content).ok()?;
+    let (key, item) = document.as_table().get_key_value(section)?;
+    let table = item.as_table_like()?;
+    let span = table
+        .iter()
+        .find(|(attribute, _)| message.contains(&attribute.bold().italic().blue().to_string()))
+        .and_then(|(attribute, _)| {
+            let (key, value) = table.get_key_value(attribute)?;
+            value_span(value).or_else(|| key.span())
+        })
+        .or_else(|| key.span())?;
+    Some(snippet(content, span))
+}
+
+fn value_span(item: &Item) -> Option<Range<usize>> {
+    match item {
+        Item::Value(value) => value.span(),
+        Item::Table(table) => table.span(),
+        Item::ArrayOfTables(array) => array.span(),
+        Item::None => None,
+    }
+}
+
+/// Show the line containing `span`, with carets under it
+pub fn snippet(content: &str, span: Range<usize>) -> String {
+    let line_start = content[..span.start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = content[span.start..]
+        .find('\n')
+        .map_or(content.len(), |i| span.start + i);
+    let line = &content[line_start..line_end];
+    let number = content[..span.start].matches('\n').count() + 1;
+    let column = content[line_start..span.start].chars().count() + 1;
+    let width = content[span.start..span.end.min(line_end)]
+        .chars()
+        .count()
+        .max(1);
+    let gutter = " ".repeat(number.to_string().len());
+    format!(
+        "at line {number}, column {column}\n{gutter} |\n{number} | {line}\n{gutter} | {}{}",
+        " ".repeat(column - 1),
+        "^".repeat(width)
+    )
+}
+
+/// Remove the colors from `text`, for the messages sent to the clients
+pub fn strip_colors(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Skip the escape sequence until its final letter
+            chars.by_ref().find(|c| c.is_ascii_alphabetic());
+        } else {
+            stripped.push(c);
+        }
+    }
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locate() {
+        let content =
+            "[default]\npath = \"~/Pictures\"\n\n[DP-1]\nduration = \"30m\"\nmode = \"fti\"\n";
+        let message = format!("unknown value for {}", "mode".bold().italic().blue());
+        assert_eq!(
+            locate(content, "DP-1", &message).unwrap(),
+            "at line 6, column 8\n  |\n6 | mode = \"fti\"\n  |        ^^^^^"
+        );
+        // The section header is shown when no attribute is named
+        assert_eq!(
+            locate(content, "DP-1", "invalid").unwrap(),
+            "at line 4, column 2\n  |\n4 | [DP-1]\n  |  ^^^^"
+        );
+        assert_eq!(strip_colors(&message), "unknown value for mode");
+    }
+}
@@ -6,6 +6,7 @@ use smithay_client_toolkit::{
 #[derive(Debug)]
 pub struct DisplayInfo {
     pub name: String,
+    pub description: String,
     pub width: i32,
     pub height: i32,
     pub scale: i32,
@@ -14,8 +15,12 @@ pub struct DisplayInfo {
 
 impl DisplayInfo {
     pub fn new(info: OutputInfo) -> Self {
+        let description = info
+            .description
+            .unwrap_or_else(|| format!("{} {}", info.make, info.model));
         Self {
             name: info.name.unwrap_or_default(),
+            description,
             width: 0,
             height: 0,
             scale: info.scale_factor,
@@ -60,10 +65,26 @@ impl DisplayInfo {
     }
 
     #[inline]
+    #[allow(unused)]
     pub fn ratio(&self) -> f32 {
         self.adjusted_width() as f32 / self.adjusted_height() as f32
     }
 
+    /// The transform in a human readable form, e.g. `flipped-90`
+    pub fn transform_name(&self) -> &'static str {
+        match self.transform {
+            Transform::Normal => "normal",
+            Transform::_90 => "90",
+            Transform::_180 => "180",
+            Transform::_270 => "270",
+            Transform::Flipped => "flipped",
+            Transform::Flipped90 => "flipped-90",
+            Transform::Flipped180 => "flipped-180",
+            Transform::Flipped270 => "flipped-270",
+            _ => "unknown",
+        }
+    }
+
     pub fn change_size(&mut self, configure: LayerSurfaceConfigure) -> bool {
         let new_width = configure.new_size.0 as i32;
         let new_height = configure.new_size.1 as i32;
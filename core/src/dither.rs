@@ -0,0 +1,72 @@
+//! The reduction of the images scaled in the loader to the 8 bits per channel of the textures.
+//!
+//! The scaling averages the pixels, creating levels in between the 8-bit ones even for the 8-bit
+//! images, so [crate::image_loader::resize] keeps 16 bits. Rounding them back would show bands
+//! in the smooth gradients, e.g. a sky or a vignette; they are dithered instead, unless `dither`
+//! is disabled. Dithering the 8-bit texture when drawing it cannot bring back the levels lost.
+
+use image::{DynamicImage, Rgba, RgbaImage};
+
+/// 4x4 Bayer matrix, the offsets added before quantizing are spread evenly over 16 pixels
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Reduce the image to 8 bits per channel, the 8-bit images are returned as they are
+pub fn quantize(image: DynamicImage, dither: bool) -> RgbaImage {
+    let color = image.color();
+    if color.bytes_per_pixel() == color.channel_count() {
+        return image.into_rgba8();
+    }
+    let image = image.into_rgba32f();
+    RgbaImage::from_fn(image.width(), image.height(), |x, y| {
+        let pixel = image.get_pixel(x, y).0;
+        let offset = if dither {
+            (BAYER[y as usize % 4][x as usize % 4] as f32 + 0.5) / 16.0
+        } else {
+            0.5
+        };
+        let channel = |value: f32, offset: f32| {
+            (value.clamp(0.0, 1.0) * 255.0 + offset).floor().min(255.0) as u8
+        };
+        Rgba([
+            channel(pixel[0], offset),
+            channel(pixel[1], offset),
+            channel(pixel[2], offset),
+            channel(pixel[3], 0.5),
+        ])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{imageops::FilterType, ImageBuffer, Rgb};
+
+    use super::*;
+    use crate::image_loader::resize;
+
+    #[test]
+    fn test_quantize() {
+        let levels = |image: RgbaImage| image.pixels().map(|pixel| pixel.0[0]).collect::<Vec<_>>();
+        // A 16-bit gradient step between two 8-bit values is dithered over the pixels
+        let step = DynamicImage::ImageRgb16(ImageBuffer::from_pixel(4, 4, Rgb([33025u16; 3])));
+        let dithered = levels(quantize(step.clone(), true));
+        assert!(dithered.contains(&128) && dithered.contains(&129));
+        let rounded = levels(quantize(step, false));
+        assert!(rounded.iter().all(|level| *level == rounded[0]));
+
+        // The 8-bit levels are kept
+        let rgb8 = DynamicImage::ImageRgb8(ImageBuffer::from_pixel(4, 4, Rgb([100u8; 3])));
+        assert!(levels(quantize(rgb8, true))
+            .iter()
+            .all(|level| *level == 100));
+
+        // Scaling an 8-bit image down creates the levels in between, they are dithered too
+        let gradient = DynamicImage::ImageRgb8(ImageBuffer::from_fn(16, 16, |x, _| {
+            Rgb([100 + (x % 2) as u8; 3])
+        }));
+        for linear in [true, false] {
+            let scaled = resize(gradient.clone(), 8, 8, FilterType::Triangle, linear);
+            let dithered = levels(quantize(scaled, true));
+            assert!(dithered.iter().any(|level| *level != dithered[0]));
+        }
+    }
+}
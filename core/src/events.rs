@@ -0,0 +1,95 @@
+//! The events sent to the event loop by the threads watching the files, the signals and the IPC.
+//!
+//! The watchers can send many events in a burst, e.g. when a directory of images is copied or an
+//! editor writes the configuration in multiple steps. The pending events are kept in a set, so
+//! that each one is queued only once until the event loop takes them, and the loop is only woken
+//! up when a new one is queued.
+
+use std::{
+    collections::BTreeSet,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use color_eyre::{eyre::Context, Result};
+use smithay_client_toolkit::reexports::calloop::ping::{make_ping, Ping, PingSource};
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Event {
+    /// A configuration file has been written or replaced, or `SIGHUP` has been received
+    ConfigChanged,
+    /// A file has been added, removed or modified in this watched directory
+    DirChanged(PathBuf),
+    /// Read the configuration again, asked with `wpaperctl reload --config-only`
+    IpcCommand,
+}
+
+#[derive(Clone)]
+pub struct EventSender {
+    pending: Arc<Mutex<BTreeSet<Event>>>,
+    ping: Ping,
+}
+
+impl EventSender {
+    /// Queue `event` unless it's already pending
+    pub fn send(&self, event: Event) {
+        if self.pending.lock().unwrap().insert(event) {
+            self.ping.ping();
+        }
+    }
+}
+
+pub struct EventReceiver {
+    pending: Arc<Mutex<BTreeSet<Event>>>,
+}
+
+impl EventReceiver {
+    /// The events sent since the last call, each one once
+    pub fn take(&self) -> Vec<Event> {
+        std::mem::take(&mut *self.pending.lock().unwrap())
+            .into_iter()
+            .collect()
+    }
+}
+
+/// The source must be inserted into the event loop, it wakes it up when an event is sent
+pub fn channel() -> Result<(EventSender, EventReceiver, PingSource)> {
+    let (ping, source) = make_ping().context("Unable to create a calloop::ping::Ping")?;
+    let pending = Arc::new(Mutex::new(BTreeSet::new()));
+    Ok((
+        EventSender {
+            pending: pending.clone(),
+            ping,
+        },
+        EventReceiver { pending },
+        source,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_coalescing() {
+        let (sender, receiver, _source) = channel().unwrap();
+        for _ in 0..1000 {
+            sender.send(Event::DirChanged(PathBuf::from("/wallpapers")));
+            sender.send(Event::ConfigChanged);
+        }
+        sender
+            .clone()
+            .send(Event::DirChanged(PathBuf::from("/other")));
+        assert_eq!(
+            receiver.take(),
+            [
+                Event::ConfigChanged,
+                Event::DirChanged(PathBuf::from("/other")),
+                Event::DirChanged(PathBuf::from("/wallpapers")),
+            ]
+        );
+        assert!(receiver.take().is_empty());
+        sender.send(Event::IpcCommand);
+        assert_eq!(receiver.take(), [Event::IpcCommand]);
+    }
+}
@@ -0,0 +1,201 @@
+//! Read the date a photo was taken from its EXIF metadata, for `sorting = "exif-date"`, and the
+//! camera that took it, for `wpaperctl status`.
+
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Local, NaiveDateTime};
+use image::ImageFormat;
+
+/// Tag of the IFD containing the EXIF specific tags
+const EXIF_IFD_TAG: u16 = 0x8769;
+/// Tag of the date and time when the photo was taken
+const DATE_TIME_ORIGINAL_TAG: u16 = 0x9003;
+/// Tag of the date and time when the file was last changed, used when the other is missing
+const DATE_TIME_TAG: u16 = 0x0132;
+/// Tags of the manufacturer and the model of the camera
+const MAKE_TAG: u16 = 0x010f;
+const MODEL_TAG: u16 = 0x0110;
+const ASCII_TYPE: u16 = 2;
+const LONG_TYPE: u16 = 4;
+
+/// Sort `files` by the date the photos were taken, falling back to the modification time.
+/// The files with the same date keep their order.
+pub fn sort_by_capture_date(files: Vec<PathBuf>) -> Vec<PathBuf> {
+    let mut files: Vec<(Option<NaiveDateTime>, PathBuf)> = files
+        .into_iter()
+        .map(|file| (capture_date(&file).or_else(|| modified_date(&file)), file))
+        .collect();
+    files.sort_by_key(|(date, _)| *date);
+    files.into_iter().map(|(_, file)| file).collect()
+}
+
+/// The date stored in the EXIF metadata of a JPEG image
+pub fn capture_date(path: &Path) -> Option<NaiveDateTime> {
+    parse_date(&exif_data(path)?)
+}
+
+/// The camera and the date stored in the EXIF metadata of a JPEG image
+pub fn camera_and_date(path: &Path) -> (Option<String>, Option<NaiveDateTime>) {
+    match exif_data(path) {
+        Some(exif) => (parse_camera(&exif), parse_date(&exif)),
+        None => (None, None),
+    }
+}
+
+fn exif_data(path: &Path) -> Option<Vec<u8>> {
+    if ImageFormat::from_path(path).ok()? != ImageFormat::Jpeg {
+        return None;
+    }
+    let mut decoder = jpeg_decoder::Decoder::new(BufReader::new(File::open(path).ok()?));
+    decoder.read_info().ok()?;
+    decoder.exif_data().map(<[u8]>::to_vec)
+}
+
+fn modified_date(path: &Path) -> Option<NaiveDateTime> {
+    let modified = path
+        .metadata()
+        .and_then(|metadata| metadata.modified())
+        .ok()?;
+    Some(DateTime::<Local>::from(modified).naive_local())
+}
+
+/// Parse the TIFF structure of the EXIF data, starting at its header
+fn parse_date(exif: &[u8]) -> Option<NaiveDateTime> {
+    let tiff = Tiff::new(exif)?;
+    let ifd0 = tiff.u32(4)? as usize;
+    let original = tiff
+        .find(ifd0, EXIF_IFD_TAG, LONG_TYPE)
+        .and_then(|(_, exif_ifd)| tiff.u32(exif_ifd))
+        .and_then(|exif_ifd| tiff.date(exif_ifd as usize, DATE_TIME_ORIGINAL_TAG));
+    original.or_else(|| tiff.date(ifd0, DATE_TIME_TAG))
+}
+
+/// The make and the model, the latter often already starts with the former
+fn parse_camera(exif: &[u8]) -> Option<String> {
+    let tiff = Tiff::new(exif)?;
+    let ifd0 = tiff.u32(4)? as usize;
+    let make = tiff.text(ifd0, MAKE_TAG).filter(|make| !make.is_empty());
+    let model = tiff.text(ifd0, MODEL_TAG).filter(|model| !model.is_empty());
+    match (make, model) {
+        (Some(make), Some(model)) if !model.starts_with(make) => Some(format!("{make} {model}")),
+        (_, Some(model)) => Some(model.to_string()),
+        (make, None) => make.map(str::to_string),
+    }
+}
+
+struct Tiff<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a [u8]) -> Option<Self> {
+        let little_endian = match data.get(..4)? {
+            b"II*\0" => true,
+            b"MM\0*" => false,
+            _ => return None,
+        };
+        Some(Self {
+            data,
+            little_endian,
+        })
+    }
+
+    fn u16(&self, offset: usize) -> Option<u16> {
+        let bytes = self.data.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        let bytes = self.data.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    }
+
+    /// Find the entry with `tag` in the IFD at `ifd`. Return its count and the offset of its
+    /// value field
+    fn find(&self, ifd: usize, tag: u16, field_type: u16) -> Option<(u32, usize)> {
+        let entries = self.u16(ifd)? as usize;
+        (0..entries)
+            .map(|i| ifd + 2 + i * 12)
+            .find(|&entry| self.u16(entry) == Some(tag))
+            .filter(|&entry| self.u16(entry + 2) == Some(field_type))
+            .and_then(|entry| Some((self.u32(entry + 4)?, entry + 8)))
+    }
+
+    fn text(&self, ifd: usize, tag: u16) -> Option<&'a str> {
+        let (count, value) = self.find(ifd, tag, ASCII_TYPE)?;
+        // The values longer than 4 bytes are stored elsewhere
+        let offset = if count > 4 {
+            self.u32(value)? as usize
+        } else {
+            value
+        };
+        let text = self.data.get(offset..offset + count as usize)?;
+        Some(
+            std::str::from_utf8(text)
+                .ok()?
+                .trim_end_matches('\0')
+                .trim(),
+        )
+    }
+
+    /// The dates are stored as "YYYY:MM:DD HH:MM:SS"
+    fn date(&self, ifd: usize, tag: u16) -> Option<NaiveDateTime> {
+        NaiveDateTime::parse_from_str(self.text(ifd, tag)?, "%Y:%m:%d %H:%M:%S").ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_date() {
+        // Little endian header, IFD0 with the pointer to the EXIF IFD and a DateTime, then the
+        // EXIF IFD with DateTimeOriginal
+        let mut exif = b"II*\0".to_vec();
+        exif.extend(8u32.to_le_bytes());
+        // IFD0 at 8, 2 entries
+        exif.extend(2u16.to_le_bytes());
+        exif.extend(EXIF_IFD_TAG.to_le_bytes());
+        exif.extend(LONG_TYPE.to_le_bytes());
+        exif.extend(1u32.to_le_bytes());
+        exif.extend(38u32.to_le_bytes());
+        exif.extend(DATE_TIME_TAG.to_le_bytes());
+        exif.extend(ASCII_TYPE.to_le_bytes());
+        exif.extend(20u32.to_le_bytes());
+        exif.extend(56u32.to_le_bytes());
+        exif.extend(0u32.to_le_bytes());
+        // EXIF IFD at 38, 1 entry
+        exif.extend(1u16.to_le_bytes());
+        exif.extend(DATE_TIME_ORIGINAL_TAG.to_le_bytes());
+        exif.extend(ASCII_TYPE.to_le_bytes());
+        exif.extend(20u32.to_le_bytes());
+        exif.extend(76u32.to_le_bytes());
+        exif.extend(0u32.to_le_bytes());
+        assert_eq!(exif.len(), 56);
+        exif.extend(b"2024:05:01 10:00:00\0");
+        exif.extend(b"2019:07:14 18:30:05\0");
+
+        let date = |text| NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S").unwrap();
+        assert_eq!(parse_date(&exif), Some(date("2019-07-14 18:30:05")));
+
+        // Without the EXIF IFD, the DateTime of IFD0 is used
+        exif[10..12].copy_from_slice(&0u16.to_le_bytes());
+        assert_eq!(parse_date(&exif), Some(date("2024-05-01 10:00:00")));
+
+        assert_eq!(parse_date(b"not exif data"), None);
+    }
+}
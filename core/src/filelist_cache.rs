@@ -0,0 +1,719 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{self, Read},
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::{anyhow, Context, Result};
+use log::{debug, error, warn};
+use smithay_client_toolkit::reexports::{
+    calloop::{
+        self,
+        ping::Ping,
+        timer::{TimeoutAction, Timer},
+        LoopHandle,
+    },
+    client::QueueHandle,
+};
+use walkdir::WalkDir;
+
+use crate::{
+    capabilities::codec_for,
+    events::{Event, EventSender},
+    exif::sort_by_capture_date,
+    stable_hash::StableHasher,
+    wallpaper_info::Source,
+    watcher::Watcher,
+    wpaperd::Wpaperd,
+    xmp,
+};
+
+/// After this time, a directory that is still being read is considered unavailable.
+/// This usually happens on network filesystems that are not responding.
+const FILESYSTEM_TIMEOUT: Duration = Duration::from_secs(10);
+/// The directories no longer used by any display are kept for this time, so that reverting a
+/// change of `path` doesn't read them again
+const REMOVED_FILELIST_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilelistStatus {
+    /// The directory is being read for the first time
+    Loading,
+    Ready,
+    /// Reading the directory is taking too long, the list of files might be outdated
+    Unavailable,
+}
+
+/// Options that change how a directory is read. The same directory might be
+/// cached multiple times with different options.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FilelistOptions {
+    pub follow_symlinks: bool,
+    /// Skip the images with the same content of another one
+    pub deduplicate: bool,
+    /// Sort the images by the date they were taken, instead of by path
+    pub sort_by_date: bool,
+    /// Skip the images rated below this number of stars
+    pub min_rating: Option<u8>,
+}
+
+impl Default for FilelistOptions {
+    fn default() -> Self {
+        Self {
+            follow_symlinks: true,
+            deduplicate: false,
+            sort_by_date: false,
+            min_rating: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Filelist {
+    path: PathBuf,
+    options: FilelistOptions,
+    filelist: Arc<Vec<PathBuf>>,
+    /// Incremented each time the directory changes, shared by all the filelists of the same path
+    generation: Arc<AtomicUsize>,
+    /// The generation that has been read last
+    loaded_generation: Option<usize>,
+    loading: Option<(JoinHandle<Vec<PathBuf>>, Instant)>,
+    status: FilelistStatus,
+    /// The files read per second the first time the directory is read, see `startup-scan-rate`
+    scan_rate: Option<NonZeroU32>,
+    /// The directory is being read slowly, it can take longer than [FILESYSTEM_TIMEOUT]
+    throttled: bool,
+    /// The directory has been read again since the last [FilelistCache::take_refreshed]
+    refreshed: bool,
+}
+
+impl Filelist {
+    fn new(path: &Path, options: FilelistOptions, generation: Arc<AtomicUsize>) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            options,
+            filelist: Arc::new(Vec::new()),
+            generation,
+            loaded_generation: None,
+            loading: None,
+            status: FilelistStatus::Loading,
+            scan_rate: None,
+            throttled: false,
+            refreshed: false,
+        }
+    }
+
+    fn is_outdated(&self) -> bool {
+        self.loaded_generation != Some(self.generation.load(Ordering::Acquire))
+    }
+
+    /// Read the directory in a background thread, so that a slow filesystem
+    /// doesn't block the event loop. `ping` is called once the thread has finished.
+    fn populate(&mut self, ping: Ping) {
+        // Only one thread at a time, outdated will be checked again once it has finished
+        if self.loading.is_some() {
+            return;
+        }
+        self.loaded_generation = Some(self.generation.load(Ordering::Acquire));
+        let path = self.path.clone();
+        let options = self.options;
+        // Only the first read is slowed down
+        let scan_rate = self.scan_rate.take();
+        self.throttled = scan_rate.is_some();
+        let handle = std::thread::spawn(move || {
+            let filelist = read_directory(&path, options, scan_rate);
+            ping.ping();
+            filelist
+        });
+        self.loading = Some((handle, Instant::now()));
+    }
+
+    /// Check the background thread. Return the new status if it has changed.
+    fn check_loading(&mut self) -> Option<FilelistStatus> {
+        let (handle, started) = self.loading.take()?;
+        if handle.is_finished() {
+            match handle.join() {
+                Ok(filelist) => self.filelist = Arc::new(filelist),
+                Err(err) => error!("reading directory {:?} failed: {err:?}", self.path),
+            }
+            self.refreshed = true;
+            return self.set_status(FilelistStatus::Ready);
+        }
+
+        let timed_out = !self.throttled && started.elapsed() >= FILESYSTEM_TIMEOUT;
+        self.loading = Some((handle, started));
+        if timed_out {
+            self.set_status(FilelistStatus::Unavailable)
+        } else {
+            None
+        }
+    }
+
+    fn set_status(&mut self, status: FilelistStatus) -> Option<FilelistStatus> {
+        if self.status != status {
+            self.status = status;
+            Some(status)
+        } else {
+            None
+        }
+    }
+}
+
+/// Slow down the walk of a directory to `rate` entries per second
+struct Throttle {
+    rate: NonZeroU32,
+    started: Instant,
+    entries: u32,
+}
+
+impl Throttle {
+    fn new(rate: NonZeroU32) -> Self {
+        Self {
+            rate,
+            started: Instant::now(),
+            entries: 0,
+        }
+    }
+
+    fn wait(&mut self) {
+        self.entries = self.entries.saturating_add(1);
+        let expected = Duration::from_secs(self.entries.into()) / self.rate.get();
+        if let Some(remaining) = expected.checked_sub(self.started.elapsed()) {
+            std::thread::sleep(remaining);
+        }
+    }
+}
+
+fn read_directory(
+    path: &Path,
+    options: FilelistOptions,
+    scan_rate: Option<NonZeroU32>,
+) -> Vec<PathBuf> {
+    // The same file could be reached from multiple symlinks
+    let mut visited = HashSet::new();
+    let mut throttle = scan_rate.map(Throttle::new);
+    let files = WalkDir::new(path)
+        .follow_links(options.follow_symlinks)
+        .sort_by_file_name()
+        .into_iter()
+        .inspect(|_| {
+            if let Some(throttle) = &mut throttle {
+                throttle.wait();
+            }
+        })
+        .filter_map(|e| match e {
+            Ok(e) => Some(e),
+            Err(err) => {
+                // walkdir detects the loops and stops descending into them
+                if let Some(ancestor) = err.loop_ancestor() {
+                    warn!(
+                        "symlink {:?} creates a loop with {ancestor:?}, skipping it",
+                        err.path().unwrap_or(path)
+                    );
+                }
+                None
+            }
+        })
+        // When the symlinks are not followed, skip them entirely
+        // (file_type() returns the type of the target otherwise)
+        .filter(|e| e.file_type().is_file())
+        .filter(|e| match codec_for(e.path()) {
+            Some(codec) if codec.enabled => true,
+            Some(codec) => {
+                debug!(
+                    "skipping {:?}: {} images are not supported by this build, see wpaperd --capabilities",
+                    e.path(),
+                    codec.name
+                );
+                false
+            }
+            None => false,
+        })
+        .filter(|e| {
+            if options.follow_symlinks {
+                let canonical = e
+                    .path()
+                    .canonicalize()
+                    .unwrap_or_else(|_| e.path().to_path_buf());
+                visited.insert(canonical)
+            } else {
+                true
+            }
+        })
+        .filter(|e| {
+            let Some(min_rating) = options.min_rating else {
+                return true;
+            };
+            let rating = xmp::rating(e.path());
+            if rating < min_rating as i8 {
+                debug!("skipping {:?}, it is rated {rating}", e.path());
+            }
+            rating >= min_rating as i8
+        })
+        .map(|e| e.path().to_path_buf())
+        .collect();
+
+    let files = if options.deduplicate {
+        remove_duplicates(files)
+    } else {
+        files
+    };
+    if options.sort_by_date {
+        sort_by_capture_date(files)
+    } else {
+        files
+    }
+}
+
+/// Remove the files that have the same content of a previous one in the list.
+/// Only the files with the same size are hashed, so that most of them are never read, and the
+/// files with the same hash are compared byte by byte before being removed.
+fn remove_duplicates(files: Vec<PathBuf>) -> Vec<PathBuf> {
+    let sizes: Vec<Option<u64>> = files
+        .iter()
+        .map(|file| file.metadata().map(|metadata| metadata.len()).ok())
+        .collect();
+    let mut sizes_count: HashMap<u64, usize> = HashMap::new();
+    for size in sizes.iter().flatten() {
+        *sizes_count.entry(*size).or_default() += 1;
+    }
+
+    let mut kept: HashMap<(u64, u64), Vec<PathBuf>> = HashMap::new();
+    files
+        .into_iter()
+        .zip(sizes)
+        .filter(|(file, size)| match size {
+            Some(size) if sizes_count[size] > 1 => match hash_file(file) {
+                Ok(hash) => {
+                    let same_hash = kept.entry((*size, hash)).or_default();
+                    let original = same_hash.iter().find(|original| {
+                        same_content(original, file).unwrap_or_else(|err| {
+                            warn!("comparing file {file:?} with {original:?}: {err}");
+                            false
+                        })
+                    });
+                    if let Some(original) = original {
+                        debug!("skipping {file:?}, it is a duplicate of {original:?}");
+                        return false;
+                    }
+                    same_hash.push(file.clone());
+                    true
+                }
+                Err(err) => {
+                    warn!("hashing file {file:?}: {err}");
+                    true
+                }
+            },
+            _ => true,
+        })
+        .map(|(file, _)| file)
+        .collect()
+}
+
+fn hash_file(path: &Path) -> io::Result<u64> {
+    let mut file = File::open(path)?;
+    let mut hasher = StableHasher::new();
+    let mut buffer = [0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.write(&buffer[..read]);
+    }
+    Ok(hasher.finish())
+}
+
+/// Both files are expected to have the same size
+fn same_content(a: &Path, b: &Path) -> io::Result<bool> {
+    let (mut a, mut b) = (File::open(a)?, File::open(b)?);
+    let mut buffer_a = [0; 64 * 1024];
+    let mut buffer_b = [0; 64 * 1024];
+    loop {
+        let read = a.read(&mut buffer_a)?;
+        if read == 0 {
+            return Ok(b.read(&mut buffer_b)? == 0);
+        }
+        b.read_exact(&mut buffer_b[..read])?;
+        if buffer_a[..read] != buffer_b[..read] {
+            return Ok(false);
+        }
+    }
+}
+
+pub struct FilelistCache {
+    cache: Vec<Filelist>,
+    /// The filelists removed by the last reloads, with the time they have been removed
+    removed: Vec<(Filelist, Instant)>,
+    /// The directories being watched, with the generation counter of each one
+    watched: HashMap<PathBuf, Arc<AtomicUsize>>,
+    /// The images set directly as path, watched through their directory, with a flag set when
+    /// they change
+    watched_files: HashMap<PathBuf, (PathBuf, Arc<AtomicBool>)>,
+    event_loop_handle: LoopHandle<'static, Wpaperd>,
+    qh: QueueHandle<Wpaperd>,
+    /// Wakes up the event loop to read the outdated directories
+    ping: Ping,
+    /// Receives [Event::DirChanged] when a watched directory changes
+    events: EventSender,
+    /// No directory is read during `startup-delay`
+    paused: bool,
+}
+
+impl FilelistCache {
+    pub fn new(
+        paths: Vec<(PathBuf, FilelistOptions)>,
+        watcher: &mut Watcher,
+        events: EventSender,
+        event_loop_handle: LoopHandle<'static, Wpaperd>,
+        qh: QueueHandle<Wpaperd>,
+    ) -> Result<(Ping, Self)> {
+        let (ping, ping_source) =
+            calloop::ping::make_ping().context("Unable to create a calloop::ping::Ping")?;
+
+        let mut filelist_cache = Self {
+            cache: Vec::new(),
+            removed: Vec::new(),
+            watched: HashMap::new(),
+            watched_files: HashMap::new(),
+            event_loop_handle: event_loop_handle.clone(),
+            qh: qh.clone(),
+            ping: ping.clone(),
+            events,
+            paused: false,
+        };
+        filelist_cache.update_paths(paths, watcher, ping.clone());
+        let ping_clone = ping.clone();
+        event_loop_handle
+            .insert_source(ping_source, move |_, _, wpaperd| {
+                update_cache_and_surfaces(wpaperd, &qh, &ping_clone);
+            })
+            .map_err(|e| anyhow!("inserting the filelist event listener in the event loop: {e}"))?;
+
+        Ok((ping, filelist_cache))
+    }
+
+    /// The event loop of wpaperd, also used by the surfaces for their own timers
+    pub fn event_loop_handle(&self) -> &LoopHandle<'static, Wpaperd> {
+        &self.event_loop_handle
+    }
+
+    /// Called at startup: read the directories only after [FilelistCache::resume], then at most
+    /// `scan_rate` files per second the first time
+    pub fn delay_startup(&mut self, delay: bool, scan_rate: Option<NonZeroU32>) {
+        self.paused = delay;
+        for filelist in &mut self.cache {
+            filelist.scan_rate = scan_rate;
+        }
+    }
+
+    /// Start reading the directories after `startup-delay`
+    pub fn resume(&mut self, event_loop_ping: &Ping) {
+        self.paused = false;
+        event_loop_ping.ping();
+    }
+
+    pub fn get(&self, path: &Path, options: FilelistOptions) -> Arc<Vec<PathBuf>> {
+        debug_assert!(path.is_dir());
+        self.filelist(path, options).filelist.clone()
+    }
+
+    pub fn status(&self, path: &Path, options: FilelistOptions) -> FilelistStatus {
+        self.filelist(path, options).status
+    }
+
+    fn filelist(&self, path: &Path, options: FilelistOptions) -> &Filelist {
+        self.cache
+            .iter()
+            .find(|filelist| filelist.path == path && filelist.options == options)
+            .expect("path passed to Filelist::get has been cached")
+    }
+
+    /// Read the directory `path` again, after [Event::DirChanged] or when asked with `wpaperctl
+    /// reload`, e.g. on a network filesystem that doesn't send the events. The list of files read
+    /// before is used in the meantime
+    pub fn rescan(&self, path: &Path) {
+        for filelist in self.cache.iter().filter(|filelist| filelist.path == path) {
+            filelist.generation.fetch_add(1, Ordering::Release);
+        }
+        self.ping.ping();
+    }
+
+    /// paths must be sorted
+    pub fn update_paths(
+        &mut self,
+        paths: Vec<(PathBuf, FilelistOptions)>,
+        watcher: &mut Watcher,
+        event_loop_ping: Ping,
+    ) {
+        self.removed
+            .retain(|(_, removed)| removed.elapsed() < REMOVED_FILELIST_TIMEOUT);
+        let (cache, removed): (Vec<_>, Vec<_>) = std::mem::take(&mut self.cache)
+            .into_iter()
+            .partition(|filelist| {
+                paths.contains(&(filelist.path.clone(), filelist.options)) && filelist.path.exists()
+            });
+        self.cache = cache;
+        let now = Instant::now();
+        self.removed.extend(
+            removed
+                .into_iter()
+                .filter(|filelist| filelist.path.exists())
+                .map(|filelist| (filelist, now)),
+        );
+
+        self.watched.retain(|watched_path, _| {
+            let path_exists = watched_path.exists();
+            if path_exists && paths.iter().any(|(path, _)| path == watched_path) {
+                true
+            } else {
+                // Stop watching paths that have been removed
+                // Check that it exists before
+                if path_exists {
+                    if let Err(err) = watcher
+                        .unwatch(watched_path)
+                        .with_context(|| format!("unwatch error on path {:?}", watched_path))
+                    {
+                        error!("{err:?}");
+                    }
+                }
+                // and remove them from the map
+                false
+            }
+        });
+
+        self.update_watched_files(&paths, watcher, &event_loop_ping);
+
+        for (path, options) in paths {
+            if self
+                .cache
+                .iter()
+                .any(|filelist| filelist.path == path && filelist.options == options)
+            {
+                continue;
+            }
+            // Skip paths that don't exists and files
+            if !path.exists() || !path.is_dir() {
+                continue;
+            }
+            let restored = self
+                .removed
+                .iter()
+                .position(|(filelist, _)| filelist.path == path && filelist.options == options)
+                .map(|index| self.removed.swap_remove(index).0);
+            let generation = match self.watched.get(&path) {
+                Some(generation) => generation.clone(),
+                None => {
+                    let generation = match &restored {
+                        // The directory might have changed while it wasn't watched, it is read
+                        // again in the background while its old list of files is used
+                        Some(filelist) => {
+                            filelist.generation.fetch_add(1, Ordering::Release);
+                            filelist.generation.clone()
+                        }
+                        None => Arc::new(AtomicUsize::new(0)),
+                    };
+                    let events = self.events.clone();
+                    let watched_path = path.clone();
+                    if let Err(err) = watcher
+                        .watch(&path, move |kind| match kind {
+                            hotwatch::EventKind::Create(_)
+                            | hotwatch::EventKind::Remove(_)
+                            | hotwatch::EventKind::Modify(_) => {
+                                // We could manually update the list of files with the information
+                                // we get here, but the inotify on linux is not reliable,
+                                // so we prefer to always trigger an update and just reload
+                                // the entire list
+                                // See: https://github.com/notify-rs/notify/issues/412
+                                events.send(Event::DirChanged(watched_path.clone()));
+                            }
+                            _ => {}
+                        })
+                        .with_context(|| format!("watch error on path {:?}", &path))
+                    {
+                        error!("{err:?}");
+                    }
+                    self.watched.insert(path.clone(), generation.clone());
+                    generation
+                }
+            };
+            match restored {
+                Some(mut filelist) => {
+                    debug!("reusing the files read from {path:?}");
+                    filelist.generation = generation;
+                    self.cache.push(filelist);
+                }
+                None => self.cache.push(Filelist::new(&path, options, generation)),
+            }
+        }
+
+        // Let the event loop start reading the new directories, so that the surfaces
+        // get notified when they are ready
+        event_loop_ping.ping();
+    }
+
+    /// Watch the images set as path, so that they are shown again when a program overwrites them
+    fn update_watched_files(
+        &mut self,
+        paths: &[(PathBuf, FilelistOptions)],
+        watcher: &mut Watcher,
+        event_loop_ping: &Ping,
+    ) {
+        self.watched_files.retain(|file, (dir, _)| {
+            if file.is_file() && paths.iter().any(|(path, _)| path == file) {
+                return true;
+            }
+            if let Err(err) = watcher
+                .unwatch(dir)
+                .with_context(|| format!("unwatch error on path {:?}", file))
+            {
+                error!("{err:?}");
+            }
+            false
+        });
+
+        for (file, _) in paths.iter().filter(|(path, _)| path.is_file()) {
+            let Some(dir) = file.parent().and_then(|dir| dir.canonicalize().ok()) else {
+                continue;
+            };
+            // A directory can only be watched once, its images are already reloaded
+            if self.watched_files.contains_key(file)
+                || self
+                    .watched_files
+                    .values()
+                    .any(|(watched, _)| *watched == dir)
+                || self.watched.contains_key(&dir)
+                || paths.iter().any(|(path, _)| *path == dir)
+            {
+                continue;
+            }
+            let changed = Arc::new(AtomicBool::new(false));
+            let changed_clone = changed.clone();
+            let ping_clone = event_loop_ping.clone();
+            match watcher.watch_file(file, move |kind| {
+                if let hotwatch::EventKind::Create(_) | hotwatch::EventKind::Modify(_) = kind {
+                    changed_clone.store(true, Ordering::Release);
+                    ping_clone.ping();
+                }
+            }) {
+                Ok(()) => {
+                    self.watched_files.insert(file.clone(), (dir, changed));
+                }
+                Err(err) => error!(
+                    "{:?}",
+                    err.wrap_err(format!("watch error on path {file:?}"))
+                ),
+            }
+        }
+    }
+
+    /// The images set as path that have changed since the last call
+    fn take_changed_files(&self) -> Vec<PathBuf> {
+        self.watched_files
+            .iter()
+            .filter(|(_, (_, changed))| changed.swap(false, Ordering::AcqRel))
+            .map(|(file, _)| file.clone())
+            .collect()
+    }
+
+    /// The directories that have been read again since the last call
+    fn take_refreshed(&mut self) -> Vec<PathBuf> {
+        self.cache
+            .iter_mut()
+            .filter_map(|filelist| {
+                std::mem::take(&mut filelist.refreshed).then(|| filelist.path.clone())
+            })
+            .collect()
+    }
+
+    /// Start reading the outdated directories and collect the ones that have finished.
+    /// Return the directories whose status has changed.
+    pub fn update_cache(
+        &mut self,
+        event_loop_ping: Ping,
+    ) -> Vec<(PathBuf, FilelistOptions, FilelistStatus)> {
+        self.removed
+            .retain(|(_, removed)| removed.elapsed() < REMOVED_FILELIST_TIMEOUT);
+        let mut changed = Vec::new();
+        for filelist in &mut self.cache {
+            if let Some(status) = filelist.check_loading() {
+                changed.push((filelist.path.clone(), filelist.options, status));
+            }
+            if filelist.is_outdated() && filelist.loading.is_none() && !self.paused {
+                filelist.populate(event_loop_ping.clone());
+                // Check again after the timeout, the thread might be stuck
+                let qh = self.qh.clone();
+                let ping = event_loop_ping.clone();
+                if let Err(err) = self.event_loop_handle.insert_source(
+                    Timer::from_duration(FILESYSTEM_TIMEOUT),
+                    move |_, _, wpaperd| {
+                        update_cache_and_surfaces(wpaperd, &qh, &ping);
+                        TimeoutAction::Drop
+                    },
+                ) {
+                    error!("inserting the filelist timeout in the event loop: {err}");
+                }
+            }
+        }
+        changed
+    }
+}
+
+/// Update the cache and let the surfaces know when their directory is ready or unavailable
+fn update_cache_and_surfaces(wpaperd: &mut Wpaperd, qh: &QueueHandle<Wpaperd>, ping: &Ping) {
+    let changed = wpaperd
+        .filelist_cache
+        .borrow_mut()
+        .update_cache(ping.clone());
+    let changed_files = wpaperd.filelist_cache.borrow().take_changed_files();
+    let refreshed = wpaperd.filelist_cache.borrow_mut().take_refreshed();
+    for surface in wpaperd.surfaces.iter_mut().filter(|surface| {
+        changed_files.contains(&surface.wallpaper_info.path)
+            && surface.image_picker.current_image() == surface.wallpaper_info.path
+    }) {
+        // Draw the new content of the image without a transition
+        surface.image_picker.reload();
+        surface.queue_draw(qh);
+    }
+    for surface in wpaperd.surfaces.iter_mut().filter(|surface| {
+        matches!(
+            &surface.wallpaper_info.source,
+            Some(Source::Newest { dir, .. }) if refreshed.contains(dir)
+        )
+    }) {
+        // An image might have been added, the source keeps the one shown otherwise
+        let path = surface.wallpaper_info.path.clone();
+        surface.image_picker.next_image(&path, qh);
+        surface.queue_draw(qh);
+    }
+    for (path, options, status) in changed {
+        for surface in wpaperd.surfaces.iter_mut().filter(|surface| {
+            surface.wallpaper_info.path == path
+                && surface.wallpaper_info.filelist_options() == options
+        }) {
+            match status {
+                FilelistStatus::Ready => {
+                    // The surface was waiting for this directory to be read
+                    if !surface.image_picker.current_image().starts_with(&path) {
+                        surface.image_picker.directory_read(&path);
+                        surface.image_picker.next_image(&path, qh);
+                        surface.queue_draw(qh);
+                    }
+                }
+                FilelistStatus::Unavailable => warn!(
+                    "The wallpaper source {path:?} for display {} is unavailable",
+                    surface.name()
+                ),
+                FilelistStatus::Loading => {}
+            }
+        }
+    }
+}
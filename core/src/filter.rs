@@ -0,0 +1,439 @@
+//! The `filter` expression, choosing which images of the directory in `path` can be shown, e.g.
+//! `width >= 2560 && tag('dark') && !recently_shown(20)`.
+//!
+//! The values are numbers and the attributes of each image:
+//! - `width` and `height`, in pixels
+//! - `rating`, the stars set by the photo managers, see [crate::xmp]
+//!
+//! They are compared with `==`, `!=`, `<`, `<=`, `>` and `>=`; the comparisons with the size of an
+//! image that can't be read are false. `tag('name')` is true when the image has the keyword
+//! `name`, ignoring the case, and `recently_shown(n)` when it is one of the last `n` images shown
+//! on any display. They are combined with `!`, `&&`, `||` and parentheses.
+//!
+//! The expression is evaluated each time an image is picked, since the images shown change; the
+//! attributes of each image are read only once.
+
+use std::{
+    cell::OnceCell,
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::{
+    eyre::{anyhow, ensure},
+    Result,
+};
+
+use crate::xmp;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageFilter {
+    source: String,
+    expr: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Value, Comparison, Value),
+    Tag(String),
+    RecentlyShown(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Value {
+    Number(f64),
+    Width,
+    Height,
+    Rating,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+impl Comparison {
+    fn compare(self, left: f64, right: f64) -> bool {
+        match self {
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+            Comparison::Less => left < right,
+            Comparison::LessEqual => left <= right,
+            Comparison::Greater => left > right,
+            Comparison::GreaterEqual => left >= right,
+        }
+    }
+}
+
+/// The attributes of an image, read the first time they are used
+#[derive(Default)]
+struct Attributes {
+    size: OnceCell<Option<(u32, u32)>>,
+    keywords: OnceCell<Vec<String>>,
+    rating: OnceCell<i8>,
+}
+
+/// The attributes of the images filtered, kept until the configuration changes
+#[derive(Default)]
+pub struct AttributeCache {
+    images: HashMap<PathBuf, Attributes>,
+}
+
+impl AttributeCache {
+    pub fn clear(&mut self) {
+        self.images.clear();
+    }
+}
+
+impl fmt::Display for ImageFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl ImageFilter {
+    pub fn parse(source: &str) -> Result<Self> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            position: 0,
+        };
+        let expr = parser.or()?;
+        if let Some((token, column)) = parser.tokens.get(parser.position) {
+            return Err(anyhow!("unexpected {token} at column {column}"));
+        }
+        Ok(Self {
+            source: source.to_string(),
+            expr,
+        })
+    }
+
+    /// The largest `n` of `recently_shown(n)`, how many of the images shown last are needed
+    pub fn history_size(&self) -> usize {
+        fn size(expr: &Expr) -> usize {
+            match expr {
+                Expr::Or(left, right) | Expr::And(left, right) => size(left).max(size(right)),
+                Expr::Not(expr) => size(expr),
+                Expr::RecentlyShown(count) => *count,
+                Expr::Compare(..) | Expr::Tag(_) => 0,
+            }
+        }
+        size(&self.expr)
+    }
+
+    /// The images in `files` matching the expression. `history` has the images shown last, the
+    /// most recent first
+    pub fn apply(
+        &self,
+        files: &[PathBuf],
+        cache: &mut AttributeCache,
+        history: &[PathBuf],
+    ) -> Vec<PathBuf> {
+        let ranks: HashMap<&Path, usize> = history
+            .iter()
+            .enumerate()
+            .map(|(rank, image)| (image.as_path(), rank))
+            .rev()
+            .collect();
+        files
+            .iter()
+            .filter(|file| {
+                let attributes = cache.images.entry(file.to_path_buf()).or_default();
+                let rank = ranks.get(file.as_path()).copied();
+                self.expr.matches(file, attributes, rank)
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+impl Expr {
+    fn matches(&self, path: &Path, attributes: &Attributes, rank: Option<usize>) -> bool {
+        match self {
+            Expr::Or(left, right) => {
+                left.matches(path, attributes, rank) || right.matches(path, attributes, rank)
+            }
+            Expr::And(left, right) => {
+                left.matches(path, attributes, rank) && right.matches(path, attributes, rank)
+            }
+            Expr::Not(expr) => !expr.matches(path, attributes, rank),
+            Expr::Compare(left, comparison, right) => {
+                match (left.get(path, attributes), right.get(path, attributes)) {
+                    (Some(left), Some(right)) => comparison.compare(left, right),
+                    _ => false,
+                }
+            }
+            Expr::Tag(tag) => attributes
+                .keywords
+                .get_or_init(|| xmp::keywords(path))
+                .iter()
+                .any(|keyword| keyword.eq_ignore_ascii_case(tag)),
+            Expr::RecentlyShown(count) => rank.is_some_and(|rank| rank < *count),
+        }
+    }
+}
+
+impl Value {
+    fn get(self, path: &Path, attributes: &Attributes) -> Option<f64> {
+        let size = || {
+            *attributes
+                .size
+                .get_or_init(|| image::image_dimensions(path).ok())
+        };
+        match self {
+            Value::Number(number) => Some(number),
+            Value::Width => size().map(|(width, _)| width as f64),
+            Value::Height => size().map(|(_, height)| height as f64),
+            Value::Rating => Some(*attributes.rating.get_or_init(|| xmp::rating(path)) as f64),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Identifier(String),
+    Number(f64),
+    String(String),
+    Symbol(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Identifier(name) => write!(f, "{name:?}"),
+            Token::Number(number) => write!(f, "{number}"),
+            Token::String(string) => write!(f, "'{string}'"),
+            Token::Symbol(symbol) => write!(f, "{symbol:?}"),
+        }
+    }
+}
+
+const SYMBOLS: [&str; 13] = [
+    "&&", "||", "==", "!=", "<=", ">=", "<", ">", "!", "(", ")", ",", "=",
+];
+
+/// Split the expression, each token with its column starting from 1
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+    while let Some(start) = rest.find(|c: char| !c.is_whitespace()) {
+        rest = &rest[start..];
+        let column = source.len() - rest.len() + 1;
+        let c = rest.chars().next().unwrap_or_default();
+        let (token, length) = if c.is_ascii_alphabetic() || c == '_' {
+            let length = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            (Token::Identifier(rest[..length].to_string()), length)
+        } else if c.is_ascii_digit() {
+            let length = rest
+                .find(|c: char| !c.is_ascii_digit() && c != '.')
+                .unwrap_or(rest.len());
+            let number = rest[..length]
+                .parse()
+                .map_err(|_| anyhow!("invalid number {:?} at column {column}", &rest[..length]))?;
+            (Token::Number(number), length)
+        } else if c == '\'' || c == '"' {
+            let length = rest[1..]
+                .find(c)
+                .ok_or_else(|| anyhow!("unterminated string at column {column}"))?;
+            (Token::String(rest[1..length + 1].to_string()), length + 2)
+        } else {
+            let symbol = SYMBOLS
+                .into_iter()
+                .find(|symbol| rest.starts_with(symbol))
+                .ok_or_else(|| anyhow!("unexpected {c:?} at column {column}"))?;
+            ensure!(
+                symbol != "=",
+                "unexpected \"=\" at column {column}, compare with \"==\""
+            );
+            (Token::Symbol(symbol), symbol.len())
+        };
+        tokens.push((token, column));
+        rest = &rest[length..];
+    }
+    Ok(tokens)
+}
+
+/// Parse the tokens, with `!` binding the tightest, then the comparisons, `&&` and `||`
+struct Parser<'a> {
+    tokens: &'a [(Token, usize)],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Result<(Token, usize)> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or_else(|| anyhow!("unexpected end of the expression"))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn accept(&mut self, symbol: &str) -> bool {
+        let accepted =
+            matches!(self.tokens.get(self.position), Some((Token::Symbol(s), _)) if *s == symbol);
+        if accepted {
+            self.position += 1;
+        }
+        accepted
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<()> {
+        match self.next()? {
+            (Token::Symbol(s), _) if s == symbol => Ok(()),
+            (token, column) => Err(anyhow!(
+                "expected {symbol:?} at column {column}, found {token}"
+            )),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut expr = self.and()?;
+        while self.accept("||") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut expr = self.unary()?;
+        while self.accept("&&") {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        if self.accept("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.accept("(") {
+            let expr = self.or()?;
+            self.expect(")")?;
+            return Ok(expr);
+        }
+        let (token, column) = self.next()?;
+        match token {
+            Token::Identifier(name) if name == "tag" => {
+                self.expect("(")?;
+                let tag = match self.next()? {
+                    (Token::String(tag), _) => tag,
+                    (token, column) => {
+                        return Err(anyhow!(
+                            "expected the name of a tag at column {column}, found {token}"
+                        ))
+                    }
+                };
+                self.expect(")")?;
+                Ok(Expr::Tag(tag))
+            }
+            Token::Identifier(name) if name == "recently_shown" => {
+                self.expect("(")?;
+                let count = match self.next()? {
+                    (Token::Number(count), _) if count.fract() == 0.0 && count >= 0.0 => {
+                        count as usize
+                    }
+                    (token, column) => {
+                        return Err(anyhow!(
+                            "expected the number of images at column {column}, found {token}"
+                        ))
+                    }
+                };
+                self.expect(")")?;
+                Ok(Expr::RecentlyShown(count))
+            }
+            token => {
+                let left = value(token, column)?;
+                let (token, column) = self.next()?;
+                let comparison = match token {
+                    Token::Symbol("==") => Comparison::Equal,
+                    Token::Symbol("!=") => Comparison::NotEqual,
+                    Token::Symbol("<") => Comparison::Less,
+                    Token::Symbol("<=") => Comparison::LessEqual,
+                    Token::Symbol(">") => Comparison::Greater,
+                    Token::Symbol(">=") => Comparison::GreaterEqual,
+                    token => {
+                        return Err(anyhow!(
+                            "expected a comparison at column {column}, found {token}"
+                        ))
+                    }
+                };
+                let (token, column) = self.next()?;
+                Ok(Expr::Compare(left, comparison, value(token, column)?))
+            }
+        }
+    }
+}
+
+fn value(token: Token, column: usize) -> Result<Value> {
+    match token {
+        Token::Number(number) => Ok(Value::Number(number)),
+        Token::Identifier(name) => match name.as_str() {
+            "width" => Ok(Value::Width),
+            "height" => Ok(Value::Height),
+            "rating" => Ok(Value::Rating),
+            _ => Err(anyhow!(
+                "unknown attribute {name:?} at column {column}, valid ones are \"width\", \
+                \"height\", \"rating\", \"tag\" and \"recently_shown\""
+            )),
+        },
+        token => Err(anyhow!(
+            "expected a value at column {column}, found {token}"
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter() {
+        let filter =
+            ImageFilter::parse("width >= 2560 && !recently_shown(2) || rating > 3").unwrap();
+        assert_eq!(filter.history_size(), 2);
+        let files = [PathBuf::from("a.jpg"), PathBuf::from("b.jpg")];
+        let mut cache = AttributeCache::default();
+        // The sizes of the missing images can't be read, and they are unrated
+        for file in &files {
+            let attributes = cache.images.entry(file.clone()).or_default();
+            let _ = attributes.size.set(Some((3840, 2160)));
+            let _ = attributes.rating.set(0);
+        }
+        let history = [PathBuf::from("c.jpg"), PathBuf::from("a.jpg")];
+        assert_eq!(
+            filter.apply(&files, &mut cache, &history),
+            [PathBuf::from("b.jpg")]
+        );
+        assert_eq!(
+            filter.apply(&files, &mut cache, &history[..1]),
+            files.to_vec()
+        );
+
+        assert!(ImageFilter::parse("tag('dark') && (height < 1080 || tag(\"light\"))").is_ok());
+        let errors = [
+            ("width = 10", "compare with"),
+            ("size > 10", "unknown attribute"),
+            ("tag(dark)", "column 5"),
+            ("width >", "end of the expression"),
+            ("(width > 1", "end of the expression"),
+            ("width > 1 height", "unexpected \"height\" at column 11"),
+        ];
+        for (source, error) in errors {
+            let err = ImageFilter::parse(source).unwrap_err().to_string();
+            assert!(err.contains(error), "{source}: {err}");
+        }
+    }
+}
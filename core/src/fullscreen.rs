@@ -0,0 +1,131 @@
+//! Track the fullscreen windows using the wlr foreign toplevel management protocol, so that the
+//! displays fully covered by a window can stop changing their wallpaper.
+
+use std::collections::HashMap;
+
+use log::info;
+use smithay_client_toolkit::reexports::client::{
+    event_created_child, globals::GlobalList, protocol::wl_output::WlOutput, Connection, Dispatch,
+    Proxy, QueueHandle,
+};
+use smithay_client_toolkit::reexports::protocols_wlr::foreign_toplevel::v1::client::{
+    zwlr_foreign_toplevel_handle_v1::{self, ZwlrForeignToplevelHandleV1},
+    zwlr_foreign_toplevel_manager_v1::{self, ZwlrForeignToplevelManagerV1},
+};
+
+use crate::Wpaperd;
+
+#[derive(Default)]
+struct Toplevel {
+    outputs: Vec<WlOutput>,
+    fullscreen: bool,
+    /// The state sent by the compositor, applied on the done event
+    pending_fullscreen: bool,
+}
+
+pub struct FullscreenTracker {
+    /// None when the compositor doesn't support the protocol
+    manager: Option<ZwlrForeignToplevelManagerV1>,
+    toplevels: HashMap<ZwlrForeignToplevelHandleV1, Toplevel>,
+}
+
+impl FullscreenTracker {
+    pub fn new(globals: &GlobalList, qh: &QueueHandle<Wpaperd>) -> Self {
+        let manager = globals
+            .bind::<ZwlrForeignToplevelManagerV1, _, _>(qh, 1..=3, ())
+            .inspect_err(|_| {
+                info!(
+                    "The compositor doesn't support the foreign toplevel management protocol, \
+                     fullscreen windows can't be detected"
+                )
+            })
+            .ok();
+        Self {
+            manager,
+            toplevels: HashMap::new(),
+        }
+    }
+
+    /// True if a fullscreen window is shown on `output`
+    pub fn covers(&self, output: &WlOutput) -> bool {
+        self.toplevels
+            .values()
+            .any(|toplevel| toplevel.fullscreen && toplevel.outputs.contains(output))
+    }
+}
+
+impl Drop for FullscreenTracker {
+    fn drop(&mut self) {
+        if let Some(manager) = &self.manager {
+            manager.stop();
+        }
+    }
+}
+
+impl Dispatch<ZwlrForeignToplevelManagerV1, ()> for Wpaperd {
+    fn event(
+        state: &mut Self,
+        _proxy: &ZwlrForeignToplevelManagerV1,
+        event: zwlr_foreign_toplevel_manager_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_foreign_toplevel_manager_v1::Event::Toplevel { toplevel } => {
+                state
+                    .fullscreen
+                    .toplevels
+                    .insert(toplevel, Toplevel::default());
+            }
+            zwlr_foreign_toplevel_manager_v1::Event::Finished => {
+                state.fullscreen.manager = None;
+                state.fullscreen.toplevels.clear();
+            }
+            _ => {}
+        }
+    }
+
+    event_created_child!(Wpaperd, ZwlrForeignToplevelManagerV1, [
+        zwlr_foreign_toplevel_manager_v1::EVT_TOPLEVEL_OPCODE => (ZwlrForeignToplevelHandleV1, ())
+    ]);
+}
+
+impl Dispatch<ZwlrForeignToplevelHandleV1, ()> for Wpaperd {
+    fn event(
+        state: &mut Self,
+        proxy: &ZwlrForeignToplevelHandleV1,
+        event: zwlr_foreign_toplevel_handle_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let zwlr_foreign_toplevel_handle_v1::Event::Closed = event {
+            state.fullscreen.toplevels.remove(proxy);
+            proxy.destroy();
+            return;
+        }
+        let Some(toplevel) = state.fullscreen.toplevels.get_mut(proxy) else {
+            return;
+        };
+        match event {
+            zwlr_foreign_toplevel_handle_v1::Event::OutputEnter { output } => {
+                toplevel.outputs.push(output);
+            }
+            zwlr_foreign_toplevel_handle_v1::Event::OutputLeave { output } => {
+                toplevel.outputs.retain(|o| o.id() != output.id());
+            }
+            zwlr_foreign_toplevel_handle_v1::Event::State { state } => {
+                // An array of u32 in the native endianness
+                toplevel.pending_fullscreen = state.chunks_exact(4).any(|value| {
+                    u32::from_ne_bytes(value.try_into().unwrap())
+                        == zwlr_foreign_toplevel_handle_v1::State::Fullscreen as u32
+                });
+            }
+            zwlr_foreign_toplevel_handle_v1::Event::Done => {
+                toplevel.fullscreen = toplevel.pending_fullscreen;
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! The handoff between two instances of wpaperd, for `wpaperd --replace`.
+//!
+//! The new instance moves the socket of the running one aside before creating its own, so that
+//! wpaperctl talks to the new instance right away. Its displays are mapped only once their first
+//! wallpaper has been loaded, and it is shown without a transition. Then the old instance is sent
+//! [IpcMessage::Handoff] and exits, leaving the socket and the runtime state to the new one. The
+//! surfaces of the new instance are mapped before the old ones are removed, so the background is
+//! never left empty.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use color_eyre::{
+    eyre::{anyhow, Context},
+    Result, Section,
+};
+use wpaperd_ipc::{request, IpcError, IpcMessage, IpcResponse, PROTOCOL_VERSION};
+
+/// The old instance is asked to exit after this time, even if some displays are still loading
+const TIMEOUT: Duration = Duration::from_secs(10);
+const IPC_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub struct Handoff {
+    /// The socket of the running instance, moved aside
+    socket: PathBuf,
+    started: Instant,
+}
+
+impl Handoff {
+    /// Called before creating the socket, return None when no instance is running
+    pub fn start(socket_path: &Path) -> Result<Option<Self>> {
+        let capabilities = match request(
+            socket_path,
+            &IpcMessage::Handshake {
+                version: PROTOCOL_VERSION,
+            },
+            IPC_TIMEOUT,
+        ) {
+            Ok(Ok(IpcResponse::Handshake { capabilities, .. })) => capabilities,
+            Ok(Err(IpcError::VersionMismatch { daemon, .. })) => {
+                return Err(anyhow!(
+                    "the running instance of wpaperd implements protocol version {daemon}, \
+                     instead of {PROTOCOL_VERSION}"
+                ))
+                .suggestion("Stop it and start wpaperd again, it cannot be replaced");
+            }
+            _ => return Ok(None),
+        };
+        if !capabilities
+            .iter()
+            .any(|capability| capability == "handoff")
+        {
+            return Err(anyhow!(
+                "the running instance of wpaperd cannot be replaced"
+            ))
+            .suggestion("Stop it and start wpaperd again, it is too old to support --replace");
+        }
+        let socket = socket_path.with_extension("sock.replaced");
+        fs::rename(socket_path, &socket)
+            .with_context(|| format!("moving the socket {socket_path:?} to {socket:?}"))?;
+        Ok(Some(Self {
+            socket,
+            started: Instant::now(),
+        }))
+    }
+
+    pub fn is_timed_out(&self) -> bool {
+        self.started.elapsed() >= TIMEOUT
+    }
+
+    /// Ask the old instance to exit, once the displays of this one have been drawn
+    pub fn finish(self) -> Result<()> {
+        let res = request(&self.socket, &IpcMessage::Handoff, IPC_TIMEOUT);
+        fs::remove_file(&self.socket).with_context(|| {
+            format!("removing the socket of the old instance {:?}", self.socket)
+        })?;
+        match res.context("connecting to the old instance of wpaperd")? {
+            Ok(_) => Ok(()),
+            Err(err) => Err(anyhow!(
+                "the old instance of wpaperd refused to exit: {err:?}"
+            )),
+        }
+    }
+}
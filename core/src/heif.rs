@@ -0,0 +1,228 @@
+//! HEIF and HEIC stills, the format of the photos taken by the iPhones, enabled by the `heif`
+//! feature.
+//!
+//! The image crate cannot decode them, so they are converted to PNG by `heif-dec`, the tool
+//! shipped with libheif (named `heif-convert` before libheif 1.17), into a temporary file that is
+//! decoded as usual and removed right away. Only the primary image of the file is shown.
+//!
+//! The size stored in the header is checked against the [DecodeLimits] before converting it.
+//! The temporary file is created by wpaperd, only readable by the user, in `XDG_RUNTIME_DIR`,
+//! and the converter is killed when it doesn't exit in [TIMEOUT].
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{ErrorKind, Read, Seek, SeekFrom},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use color_eyre::{
+    eyre::{bail, ensure, Context, ContextCompat},
+    Result,
+};
+use image::DynamicImage;
+
+use crate::{
+    child,
+    image_loader::{self, DecodeLimits},
+};
+
+/// The tools of libheif converting the images, the newest first
+const CONVERTERS: &[&str] = &["heif-dec", "heif-convert"];
+/// The converter is killed if it doesn't exit in time
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// The metadata of the photos takes a few KiB, the files with a bigger one are refused
+const MAX_META_SIZE: u64 = 16 * 1024 * 1024;
+/// Only the end of the errors of the converter is kept
+const MAX_STDERR_SIZE: u64 = 64 * 1024;
+
+/// The images are decoded in parallel, each one needs its own temporary file
+static CONVERSIONS: AtomicUsize = AtomicUsize::new(0);
+
+pub fn is_heif(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            extension.eq_ignore_ascii_case("heic") || extension.eq_ignore_ascii_case("heif")
+        })
+}
+
+/// Decode the primary image of `path`, refusing it when it exceeds `limits`
+pub fn decode(path: &Path, limits: &DecodeLimits) -> Result<DynamicImage> {
+    let (width, height) =
+        dimensions(path).with_context(|| format!("reading the size of image {path:?}"))?;
+    limits.check(path, width, height)?;
+    let png = create_output()?;
+    let image = convert(path, &png).and_then(|_| image_loader::decode(&png, limits));
+    let _ = std::fs::remove_file(&png);
+    image.with_context(|| format!("decoding image {path:?}"))
+}
+
+/// Create the empty file the converter writes into, so that nobody else can create it first
+fn create_output() -> Result<PathBuf> {
+    let dir = dirs::runtime_dir().unwrap_or_else(std::env::temp_dir);
+    loop {
+        let png = dir.join(format!(
+            "wpaperd-heif-{}-{}.png",
+            std::process::id(),
+            CONVERSIONS.fetch_add(1, Ordering::Relaxed)
+        ));
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&png)
+        {
+            Ok(_) => return Ok(png),
+            // Left by another wpaperd, or by one that has been killed while converting
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err).with_context(|| format!("creating file {png:?}")),
+        }
+    }
+}
+
+fn convert(path: &Path, png: &Path) -> Result<()> {
+    for converter in CONVERTERS {
+        let spawned = child::spawn(
+            Command::new(converter)
+                .arg(path)
+                .arg(png)
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::piped()),
+        );
+        let mut converting = match spawned {
+            Ok(converting) => converting,
+            Err(err) if err.kind() == ErrorKind::NotFound => continue,
+            Err(err) => return Err(err).with_context(|| format!("running {converter}")),
+        };
+        let stderr = converting.stderr.take().context("stderr not available")?;
+        // Read it in another thread, the converter blocks when the pipe is full
+        let errors = std::thread::spawn(move || {
+            let mut errors = String::new();
+            let _ = stderr.take(MAX_STDERR_SIZE).read_to_string(&mut errors);
+            errors
+        });
+        let status = child::wait_timeout(&mut converting, TIMEOUT)
+            .with_context(|| format!("running {converter}"))?;
+        let errors = errors.join().unwrap_or_default();
+        match status {
+            Some(status) if status.success() => return Ok(()),
+            Some(status) => bail!("{converter} failed with {status}: {}", errors.trim()),
+            None => bail!("{converter} did not exit in {}s", TIMEOUT.as_secs()),
+        }
+    }
+    bail!("heif-dec was not found, install libheif to show HEIF images")
+}
+
+/// The size of the image, read from the `ispe` properties in the `meta` box. The biggest width
+/// and height are taken, the file also stores the sizes of the tiles and of the thumbnail
+pub(crate) fn dimensions(path: &Path) -> Result<(u32, u32)> {
+    let mut file = File::open(path)?;
+    let meta = loop {
+        let mut header = [0; 8];
+        file.read_exact(&mut header)
+            .context("the file has no meta box")?;
+        let (size, kind) = header.split_at(4);
+        let size = match u32::from_be_bytes(size.try_into().unwrap()) {
+            0 => bail!("the file has no meta box"),
+            1 => {
+                let mut size = [0; 8];
+                file.read_exact(&mut size)?;
+                u64::from_be_bytes(size).saturating_sub(16)
+            }
+            size => u64::from(size).saturating_sub(8),
+        };
+        if kind == b"meta" {
+            ensure!(size <= MAX_META_SIZE, "the meta box takes {size} bytes");
+            let mut meta = vec![0; size as usize];
+            file.read_exact(&mut meta)?;
+            break meta;
+        }
+        file.seek(SeekFrom::Current(size as i64))?;
+    };
+    // Skip the version and the flags of the meta box
+    let properties = meta
+        .get(4..)
+        .and_then(|meta| child_box(meta, b"iprp"))
+        .and_then(|iprp| child_box(iprp, b"ipco"))
+        .context("the file has no image properties")?;
+    boxes(properties)
+        .filter(|(kind, _)| kind == b"ispe")
+        .filter_map(|(_, ispe)| {
+            let size = |at: usize| Some(u32::from_be_bytes(ispe.get(at..at + 4)?.try_into().ok()?));
+            // After the version and the flags
+            Some((size(4)?, size(8)?))
+        })
+        .reduce(|(width, height), (other_width, other_height)| {
+            (width.max(other_width), height.max(other_height))
+        })
+        .context("the file has no image spatial extents")
+}
+
+/// The type and the content of the boxes in `data`
+fn boxes(mut data: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    std::iter::from_fn(move || {
+        let size = u32::from_be_bytes(data.get(..4)?.try_into().ok()?) as usize;
+        let item = (data.get(4..8)?, data.get(8..size)?);
+        data = &data[size..];
+        Some(item)
+    })
+}
+
+fn child_box<'a>(data: &'a [u8], kind: &[u8]) -> Option<&'a [u8]> {
+    boxes(data)
+        .find(|(other, _)| *other == kind)
+        .map(|(_, content)| content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_heif() {
+        assert!(is_heif(Path::new("/photos/IMG_0001.HEIC")));
+        assert!(is_heif(Path::new("image.heif")));
+        assert!(!is_heif(Path::new("image.heic.png")));
+        assert!(!is_heif(Path::new("heic")));
+    }
+
+    #[test]
+    fn test_dimensions() {
+        fn boxed(kind: &[u8], content: &[u8]) -> Vec<u8> {
+            let mut data = (content.len() as u32 + 8).to_be_bytes().to_vec();
+            data.extend(kind);
+            data.extend(content);
+            data
+        }
+        let ispe = |width: u32, height: u32| {
+            let mut content = vec![0; 4];
+            content.extend(width.to_be_bytes());
+            content.extend(height.to_be_bytes());
+            boxed(b"ispe", &content)
+        };
+        // The primary image, made of tiles, and its thumbnail
+        let ipco = [ispe(512, 512), ispe(4032, 3024), ispe(320, 240)].concat();
+        let meta = [
+            vec![0; 4],
+            boxed(b"hdlr", &[0; 24]),
+            boxed(b"iprp", &boxed(b"ipco", &ipco)),
+        ]
+        .concat();
+        let file = [boxed(b"ftyp", b"heic\0\0\0\0"), boxed(b"meta", &meta)].concat();
+
+        let path = std::env::temp_dir().join(format!("wpaperd-test-{}.heic", std::process::id()));
+        std::fs::write(&path, file).unwrap();
+        let size = dimensions(&path);
+        std::fs::write(&path, boxed(b"ftyp", b"heic")).unwrap();
+        let missing = dimensions(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(size.unwrap(), (4032, 3024));
+        assert!(missing.is_err());
+    }
+}
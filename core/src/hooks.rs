@@ -0,0 +1,192 @@
+//! Commands run before and after the wallpaper of a display changes.
+//!
+//! `pre-change` runs before each automatic change, the ones triggered by `duration`, `schedule` or
+//! the clock, and can veto it: when it fails or doesn't exit within `timeout`, the image shown is
+//! kept and the hook runs again after `retry`, e.g. to not change the wallpaper while the screen is
+//! recorded. The commands of the user, like `wpaperctl next`, do not wait for it.
+//!
+//! `post-change` runs after each new image has been shown, nothing waits for it.
+//!
+//! Both are run with `sh -c`, the display is passed in `WPAPERD_OUTPUT` and the image shown in
+//! `WPAPERD_WALLPAPER`; `post-change` gets the image shown before in `WPAPERD_PREVIOUS`.
+
+use std::{
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Stdio},
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use color_eyre::{
+    eyre::{bail, Context},
+    Result,
+};
+use log::{info, warn};
+use serde::Deserialize;
+
+use crate::child;
+
+/// How often the timer of the display checks whether `pre-change` has exited
+pub const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Hooks {
+    /// Run before each automatic change, a non-zero exit status vetoes it
+    pub pre_change: Option<String>,
+    /// Run after each new image has been shown
+    pub post_change: Option<String>,
+    /// The hooks are killed if they don't exit in time
+    #[serde(default = "default_timeout", with = "humantime_serde")]
+    pub timeout: Duration,
+    /// How long a vetoed change waits before running `pre-change` again
+    #[serde(default = "default_retry", with = "humantime_serde")]
+    pub retry: Duration,
+}
+
+fn default_timeout() -> Duration {
+    Duration::from_secs(10)
+}
+
+fn default_retry() -> Duration {
+    Duration::from_secs(60)
+}
+
+pub enum PreChangeStatus {
+    /// The wallpaper can change
+    Allowed,
+    /// The hook is still running
+    Running,
+    /// Keep the image shown and try again after [Hooks::retry]
+    Vetoed,
+}
+
+/// The `pre-change` hook of a display, run in another thread
+#[derive(Default)]
+pub struct PreChangeHook {
+    thread_handle: Option<JoinHandle<bool>>,
+}
+
+impl PreChangeHook {
+    /// Called when the wallpaper should change: start the hook, then return its result once it
+    /// has exited
+    pub fn poll(
+        &mut self,
+        hooks: Option<&Hooks>,
+        output: &str,
+        wallpaper: &Path,
+    ) -> PreChangeStatus {
+        if let Some(handle) = self.thread_handle.take() {
+            if !handle.is_finished() {
+                self.thread_handle = Some(handle);
+                return PreChangeStatus::Running;
+            }
+            return if handle.join().unwrap_or(false) {
+                PreChangeStatus::Allowed
+            } else {
+                PreChangeStatus::Vetoed
+            };
+        }
+        let Some((command, timeout)) =
+            hooks.and_then(|hooks| Some((hooks.pre_change.clone()?, hooks.timeout)))
+        else {
+            return PreChangeStatus::Allowed;
+        };
+        let env = vec![
+            ("WPAPERD_OUTPUT", PathBuf::from(output)),
+            ("WPAPERD_WALLPAPER", wallpaper.to_path_buf()),
+        ];
+        let output = output.to_string();
+        self.thread_handle = Some(std::thread::spawn(move || {
+            match run(&command, timeout, &env) {
+                Ok(status) if status.success() => true,
+                Ok(status) => {
+                    info!("Keeping the wallpaper of {output}, pre-change exited with {status}");
+                    false
+                }
+                Err(err) => {
+                    warn!("Keeping the wallpaper of {output}: {err:?}");
+                    false
+                }
+            }
+        }));
+        PreChangeStatus::Running
+    }
+}
+
+/// Run the `post-change` hook of `output` in another thread
+pub fn post_change(hooks: &Hooks, output: &str, wallpaper: &Path, previous: &Path) {
+    let Some(command) = hooks.post_change.clone() else {
+        return;
+    };
+    let timeout = hooks.timeout;
+    let env = vec![
+        ("WPAPERD_OUTPUT", PathBuf::from(output)),
+        ("WPAPERD_WALLPAPER", wallpaper.to_path_buf()),
+        ("WPAPERD_PREVIOUS", previous.to_path_buf()),
+    ];
+    std::thread::spawn(move || match run(&command, timeout, &env) {
+        Ok(status) if status.success() => {}
+        Ok(status) => warn!("The post-change hook {command:?} failed with {status}"),
+        Err(err) => warn!("{err:?}"),
+    });
+}
+
+/// Run the command, killing it with the processes it started after `timeout`
+fn run(command: &str, timeout: Duration, env: &[(&str, PathBuf)]) -> Result<ExitStatus> {
+    let mut child = child::spawn(
+        Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .envs(env.iter().map(|(key, value)| (key, value)))
+            .stdin(Stdio::null()),
+    )
+    .with_context(|| format!("running hook {command:?}"))?;
+    match child::wait_timeout(&mut child, timeout)? {
+        Some(status) => Ok(status),
+        None => bail!(
+            "hook {command:?} did not exit in {}",
+            humantime_serde::re::humantime::format_duration(timeout)
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        let env = [("WPAPERD_OUTPUT", PathBuf::from("DP-1"))];
+        let timeout = Duration::from_secs(5);
+        assert!(run("test $WPAPERD_OUTPUT = DP-1", timeout, &env)
+            .unwrap()
+            .success());
+        assert!(!run("exit 1", timeout, &env).unwrap().success());
+        assert!(run("sleep 5", Duration::from_millis(100), &env).is_err());
+
+        let hooks: Hooks = toml::from_str("pre-change = 'exit 1'\nretry = '5m'").unwrap();
+        assert_eq!(hooks.retry, Duration::from_secs(300));
+        let mut hook = PreChangeHook::default();
+        let wallpaper = Path::new("/image.png");
+        assert!(matches!(
+            hook.poll(Some(&hooks), "DP-1", wallpaper),
+            PreChangeStatus::Running
+        ));
+        while hook
+            .thread_handle
+            .as_ref()
+            .is_some_and(|handle| !handle.is_finished())
+        {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(matches!(
+            hook.poll(Some(&hooks), "DP-1", wallpaper),
+            PreChangeStatus::Vetoed
+        ));
+        assert!(matches!(
+            hook.poll(None, "DP-1", wallpaper),
+            PreChangeStatus::Allowed
+        ));
+    }
+}
@@ -0,0 +1,1282 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{self, BufRead, BufReader, Cursor, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::{
+        mpsc::{self, Receiver, TryRecvError},
+        Arc, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+use color_eyre::{
+    eyre::{anyhow, Context},
+    owo_colors::OwoColorize,
+    Result, Section,
+};
+use image::{
+    imageops::{self, FilterType},
+    io::{Limits, Reader as ImageReader},
+    DynamicImage, GrayImage, ImageBuffer, ImageDecoder, ImageFormat, RgbImage, Rgba, RgbaImage,
+};
+use jpeg_decoder::PixelFormat;
+use log::{info, warn};
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use serde::Deserialize;
+use wpaperd_ipc::{ImageMetadata, TraceStage};
+
+use crate::{
+    animation::{self, Frame},
+    color_space::{srgb_decode, srgb_encode, ColorSpace},
+    decode_queue::{DecodeQueue, Priority},
+    dither, image_metadata,
+    metrics::Metrics,
+    sidecar::Sidecar,
+    tone_mapping::ToneMapping,
+    trace::Tracer,
+    wallpaper_info::BackgroundMode,
+};
+
+/// The limits applied to the images before decoding them, so that a crafted or broken image
+/// can't use all the memory. The images exceeding them are skipped with a warning, like the ones
+/// taking longer than `timeout` to load
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct DecodeLimits {
+    pub max_width: u32,
+    pub max_height: u32,
+    /// Width multiplied by height
+    pub max_pixels: u64,
+    /// Memory that can be allocated by the decoder, in MiB. The bigger images are decoded at a
+    /// reduced size when they are downscaled anyway, see [decode_capped], or refused
+    pub max_memory: u64,
+    /// Frames of an animation, the ones with more are shown as still images
+    pub max_frames: usize,
+    /// Memory taken by all the frames of an animation once scaled, in MiB
+    pub max_animation_memory: u64,
+    /// How long decoding and scaling an image can take, zero disables it
+    #[serde(with = "humantime_serde")]
+    pub timeout: Duration,
+    /// How many images can be decoded at once for the rotation and the animations, the ones
+    /// asked for with wpaperctl don't wait for them. One less than the threads when `None`
+    pub max_concurrent: Option<usize>,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self {
+            max_width: 65535,
+            max_height: 65535,
+            max_pixels: 1 << 30,
+            max_memory: 1024,
+            max_frames: 1000,
+            max_animation_memory: 256,
+            timeout: Duration::from_secs(30),
+            max_concurrent: None,
+        }
+    }
+}
+
+impl DecodeLimits {
+    /// Refuse the images bigger than the limits, knowing their size from the header
+    pub(crate) fn check(&self, path: &Path, width: u32, height: u32) -> Result<()> {
+        let exceeded = if width > self.max_width {
+            Some("max-width")
+        } else if height > self.max_height {
+            Some("max-height")
+        } else if width as u64 * height as u64 > self.max_pixels {
+            Some("max-pixels")
+        } else {
+            None
+        };
+        match exceeded {
+            Some(limit) => Err(anyhow!(
+                "image {path:?} of size {width}x{height} exceeds the limit {} of {}",
+                limit.bold().italic().blue(),
+                "decode-limits".bold().magenta(),
+            ))
+            .with_suggestion(|| {
+                format!(
+                    "raise {} in the {} section if the image is trusted",
+                    limit.bold().italic().blue(),
+                    "decode-limits".bold().magenta(),
+                )
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Refuse the images taking more than `max-memory` once decoded, before allocating them
+    pub(crate) fn check_memory(&self, path: &Path, bytes: u64) -> Result<()> {
+        if bytes <= self.max_memory * 1024 * 1024 {
+            return Ok(());
+        }
+        Err(anyhow!(
+            "image {path:?} takes {} MiB once decoded, it exceeds the limit {} of {}",
+            bytes.div_ceil(1024 * 1024),
+            "max-memory".bold().italic().blue(),
+            "decode-limits".bold().magenta(),
+        ))
+        .with_suggestion(|| {
+            format!(
+                "set {} to decode it at a reduced size, or raise {}",
+                "max-decode-size".bold().italic().blue(),
+                "max-memory".bold().italic().blue(),
+            )
+        })
+    }
+}
+
+/// How an image is scaled in the pool, the images are loaded once for each one
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct Scaling {
+    /// Cap the longest side of the image, see `max-decode-size`
+    pub max_decode_size: Option<u32>,
+    /// Scale in linear light, see [resize]
+    pub linear: bool,
+    /// Downscale the image to the display it is shown on, with `resize-filter = "lanczos3"`
+    pub display: Option<DisplayFit>,
+    /// Replace the `dither` of [ToneMapping] for the display
+    pub dither: Option<bool>,
+}
+
+/// The size of a display, in pixels, and its mode. The mode set in the sidecar file of the
+/// image takes precedence
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct DisplayFit {
+    pub width: u32,
+    pub height: u32,
+    pub mode: BackgroundMode,
+}
+
+/// JPEG images with a side bigger than this get a preview decoded at a reduced scale first
+const PREVIEW_MIN_SIZE: u16 = 4096;
+/// Requested size of the longest side of the preview
+const PREVIEW_SIZE: u16 = 1024;
+
+struct Image {
+    data: Option<(RgbaImage, ImageInfo)>,
+    /// Receive the decoded image from the thread pool, along with the time taken
+    result: Option<Receiver<(Option<LoadedImage>, Duration)>>,
+    requesters: Vec<String>,
+    /// Receive the preview of the image, if it has been requested
+    preview: Option<Receiver<(RgbaImage, ImageInfo)>>,
+    /// Set by the thread loading the image, the time spent in the queue of the pool doesn't count
+    started: Arc<OnceLock<Instant>>,
+    /// The id of the job in the [DecodeQueue]
+    job: u64,
+}
+
+/// The image loaded in the thread pool, with the time spent decoding it (applying its settings
+/// included) and scaling it
+struct LoadedImage {
+    image: RgbaImage,
+    info: ImageInfo,
+    decode: Duration,
+    scale: Duration,
+}
+
+/// What is read from the file of the image along with it in the pool, so that the event loop
+/// doesn't open it again
+#[derive(Clone, Debug)]
+pub struct ImageInfo {
+    /// Read from the ICC profile embedded in the image
+    pub color_space: ColorSpace,
+    /// Returned by `wpaperctl status`, see [image_metadata]
+    pub metadata: ImageMetadata,
+    /// The image has more than one frame, see [animation::is_animated]
+    pub animated: bool,
+}
+
+impl ImageInfo {
+    fn read(path: &Path, limits: &DecodeLimits) -> Self {
+        Self {
+            color_space: ColorSpace::of_image(path),
+            metadata: image_metadata::read(path),
+            animated: animation::is_animated(path, limits),
+        }
+    }
+}
+
+pub enum ImageLoaderStatus {
+    Loaded(RgbaImage, ImageInfo),
+    /// A low resolution version of the image, shown while the full image is being decoded
+    Preview(RgbaImage, ImageInfo),
+    Waiting,
+    Error,
+}
+
+pub struct ImageLoader {
+    /// The images being loaded, with the way they are scaled
+    images: HashMap<(PathBuf, Scaling), Image>,
+    /// The smallest maximum texture size of the displays, bigger images are downscaled
+    max_texture_size: Option<u32>,
+    limits: DecodeLimits,
+    tone_mapping: ToneMapping,
+    /// The images that took longer than [DecodeLimits::timeout], they are not loaded again until
+    /// wpaperd is restarted
+    timed_out: HashSet<PathBuf>,
+    /// Decode, resize and filter the images, also used by the image crate for its own
+    /// parallel work
+    pool: ThreadPool,
+    /// The order in which the images are loaded by the pool
+    queue: DecodeQueue,
+    metrics: Rc<RefCell<Metrics>>,
+    tracer: Rc<RefCell<Tracer>>,
+}
+
+impl ImageLoader {
+    /// Use `threads` threads to load the images, one for each CPU when `None`
+    pub fn new(
+        threads: Option<usize>,
+        metrics: Rc<RefCell<Metrics>>,
+        tracer: Rc<RefCell<Tracer>>,
+    ) -> Result<Self> {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(threads.unwrap_or(0))
+            .thread_name(|index| format!("wpaperd-loader-{index}"))
+            // By default rayon aborts, the image that was being loaded is reported as an error
+            .panic_handler(|_| {})
+            .build()
+            .context("creating the thread pool for loading the images")?;
+        let queue = DecodeQueue::new(default_concurrency(&pool));
+        Ok(Self {
+            images: HashMap::new(),
+            max_texture_size: None,
+            limits: DecodeLimits::default(),
+            tone_mapping: ToneMapping::default(),
+            timed_out: HashSet::new(),
+            pool,
+            queue,
+            metrics,
+            tracer,
+        })
+    }
+
+    /// The threads loading the images
+    pub fn pool(&self) -> &ThreadPool {
+        &self.pool
+    }
+
+    /// Called for each display, the images must fit in all of them
+    pub fn set_max_texture_size(&mut self, max_texture_size: u32) {
+        self.max_texture_size = Some(
+            self.max_texture_size
+                .map_or(max_texture_size, |size| size.min(max_texture_size)),
+        );
+    }
+
+    pub fn limits(&self) -> &DecodeLimits {
+        &self.limits
+    }
+
+    /// Used for the images loaded from now on
+    pub fn set_limits(&mut self, limits: DecodeLimits) {
+        self.queue.set_limit(
+            limits
+                .max_concurrent
+                .unwrap_or_else(|| default_concurrency(&self.pool)),
+        );
+        self.limits = limits;
+    }
+
+    /// Used for the high bit depth images loaded from now on
+    pub fn set_tone_mapping(&mut self, tone_mapping: ToneMapping) {
+        self.tone_mapping = tone_mapping;
+    }
+
+    /// True if loading the image has been given up, the display should pick another one
+    pub fn has_timed_out(&self, path: &Path) -> bool {
+        self.timed_out.contains(path)
+    }
+
+    /// Load the image in a separate thread. When `preview` is true, a quick preview of the big
+    /// JPEG images is returned first. The image is scaled following `scaling`, so that nothing
+    /// is left for the renderer but the upload. The images with a higher `priority` are loaded
+    /// first, an image already queued is raised to it
+    pub fn background_load(
+        &mut self,
+        path: PathBuf,
+        requester_name: String,
+        preview: bool,
+        scaling: Scaling,
+        priority: Priority,
+    ) -> ImageLoaderStatus {
+        let key = (path, scaling);
+        let path = &key.0;
+        if let Some(image) = self.images.get_mut(&key) {
+            if image.result.is_some() && image.started.get().is_none() {
+                self.queue.raise(image.job, priority);
+            }
+            if let Some(receiver) = image.result.take() {
+                match receiver.try_recv() {
+                    Ok((Some(loaded), time)) => {
+                        self.metrics.borrow_mut().decoded(time);
+                        let mut tracer = self.tracer.borrow_mut();
+                        for requester in &image.requesters {
+                            tracer.record(
+                                requester,
+                                TraceStage::Decode,
+                                Some(loaded.decode),
+                                || format!("{path:?}"),
+                            );
+                            tracer.record(requester, TraceStage::Scale, Some(loaded.scale), || {
+                                format!("{}x{}", loaded.image.width(), loaded.image.height())
+                            });
+                        }
+                        image.data = Some((loaded.image, loaded.info));
+                    }
+                    Ok((None, _)) => {
+                        self.metrics.borrow_mut().error();
+                        self.images.remove(&key);
+                        return ImageLoaderStatus::Error;
+                    }
+                    Err(TryRecvError::Disconnected) => {
+                        warn!("loading image {path:?} panicked");
+                        self.metrics.borrow_mut().error();
+                        self.images.remove(&key);
+                        return ImageLoaderStatus::Error;
+                    }
+                    Err(TryRecvError::Empty)
+                        if !self.limits.timeout.is_zero()
+                            && image.started.get().is_some_and(|started| {
+                                started.elapsed() >= self.limits.timeout
+                            }) =>
+                    {
+                        // The thread can't be stopped, its result is dropped when it ends
+                        warn!(
+                            "loading image {path:?} took longer than the {} of {}, skipping it",
+                            "timeout".bold().italic().blue(),
+                            "decode-limits".bold().magenta(),
+                        );
+                        self.metrics.borrow_mut().error();
+                        self.timed_out.insert(path.clone());
+                        self.images.remove(&key);
+                        return ImageLoaderStatus::Error;
+                    }
+                    Err(TryRecvError::Empty) => {
+                        // the image is still being loaded
+                        // reassign the receiver
+                        image.result = Some(receiver);
+                        if let Some((data, info)) = image
+                            .preview
+                            .as_ref()
+                            .and_then(|receiver| receiver.try_recv().ok())
+                        {
+                            image.preview = None;
+                            return ImageLoaderStatus::Preview(data, info);
+                        }
+                        return ImageLoaderStatus::Waiting;
+                    }
+                }
+            }
+            if let Some((data, info)) = &image.data {
+                // If the requesters is only one and it's the same as the current
+                if image.requesters.len() == 1
+                    && image.requesters.first().unwrap() == &requester_name
+                {
+                    // Just send it up and remove it from the map
+                    let (data, info) = self.images.remove(&key).unwrap().data.unwrap();
+                    ImageLoaderStatus::Loaded(data, info)
+                } else {
+                    // otherwise this image has been requested by multiple surfaces
+                    self.metrics.borrow_mut().cache_access(true);
+                    let requesters = &mut image.requesters;
+                    if let Some(index) = requesters.iter().position(|name| name == &requester_name)
+                    {
+                        requesters.remove(index);
+                    }
+                    ImageLoaderStatus::Loaded(data.clone(), info.clone())
+                }
+            } else {
+                // The decoded image is not ready yet
+                ImageLoaderStatus::Waiting
+            }
+        } else if self.timed_out.contains(path) {
+            ImageLoaderStatus::Error
+        } else {
+            // Start loading a new image
+            self.metrics.borrow_mut().cache_access(false);
+            let path_clone = path.clone();
+            let key = key.clone();
+            let max_texture_size = self.max_texture_size;
+            let limits = self.limits;
+            let tone_mapping = self.tone_mapping;
+            let (sender, receiver) = mpsc::channel();
+            let (result_sender, result) = mpsc::channel();
+            let started = Arc::new(OnceLock::new());
+            let started_clone = started.clone();
+            let job = self.queue.push(&self.pool, priority, move || {
+                let start = *started_clone.get_or_init(Instant::now);
+                let sidecar = Sidecar::load(&path_clone);
+                let info = ImageInfo::read(&path_clone, &limits);
+                // The crop region is in the coordinates of the full image
+                if preview && sidecar.crop.is_none() {
+                    if let Some(image) = decode_preview(&path_clone, &limits) {
+                        // The surface might have stopped waiting for it
+                        let _ = sender.send((sidecar.apply(image).into_rgba8(), info.clone()));
+                    }
+                }
+                // The image might not be needed anymore
+                let image = load(
+                    &path_clone,
+                    &sidecar,
+                    info,
+                    max_texture_size,
+                    scaling,
+                    &limits,
+                    &tone_mapping,
+                );
+                let _ = result_sender.send((image, start.elapsed()));
+            });
+            let image = Image {
+                requesters: vec![requester_name],
+                result: Some(result),
+                data: None,
+                preview: preview.then_some(receiver),
+                started,
+                job,
+            };
+            self.images.insert(key, image);
+            ImageLoaderStatus::Waiting
+        }
+    }
+
+    /// Decode all the frames of an animated image in a separate thread, scaled like
+    /// [ImageLoader::background_load] does. `None` is received for the still images and for the
+    /// animations exceeding the `max-frames` or the `max-animation-memory` of [DecodeLimits]
+    pub fn load_frames(&self, path: PathBuf, scaling: Scaling) -> Receiver<Option<Vec<Frame>>> {
+        let max_texture_size = self.max_texture_size;
+        let limits = self.limits;
+        let (sender, receiver) = mpsc::channel();
+        self.queue.push(&self.pool, Priority::Background, move || {
+            let frames = load_frames(&path, max_texture_size, scaling, &limits)
+                .inspect_err(|err| warn!("{err:?}"))
+                .ok()
+                .flatten();
+            // The display might show another image by now
+            let _ = sender.send(frames);
+        });
+        receiver
+    }
+
+    /// Check that there are no threads waiting on zero requesters
+    #[cfg(debug_assertions)]
+    pub fn check_lingering_threads(&mut self) {
+        debug_assert!(!self
+            .images
+            .iter()
+            .any(|(_, image)| { image.requesters.is_empty() }));
+    }
+}
+
+/// Keep a thread of the pool for the images asked for with wpaperctl
+fn default_concurrency(pool: &ThreadPool) -> usize {
+    pool.current_num_threads().saturating_sub(1).max(1)
+}
+
+/// Decode the image and apply its settings, run in the loader thread
+fn load(
+    path: &Path,
+    sidecar: &Sidecar,
+    info: ImageInfo,
+    max_texture_size: Option<u32>,
+    scaling: Scaling,
+    limits: &DecodeLimits,
+    tone_mapping: &ToneMapping,
+) -> Option<LoadedImage> {
+    let started = Instant::now();
+    let max_size = max_size(max_texture_size, scaling.max_decode_size);
+    // The crop region is in the coordinates of the full image, and the cap applies to it: the
+    // image is decoded at the scale keeping the region within the cap, and the region is scaled
+    // along with it
+    let (decode_size, full_width) = match (max_size, sidecar.crop) {
+        (Some(max_size), Some(crop)) => match dimensions(path) {
+            Some((width, height)) => {
+                let region = crop.width.max(crop.height).max(1) as u64;
+                let cap = (max_size as u64 * width.max(height) as u64).div_ceil(region);
+                (Some(u32::try_from(cap).unwrap_or(u32::MAX)), Some(width))
+            }
+            None => (None, None),
+        },
+        (max_size, _) => (max_size, None),
+    };
+    match decode_capped(path, decode_size, limits, scaling.linear) {
+        Ok(image) => {
+            let image = match full_width {
+                Some(full_width) if image.width() != full_width => sidecar
+                    .scaled(image.width() as f64 / full_width as f64)
+                    .apply(tone_mapping.apply(image)),
+                _ => sidecar.apply(tone_mapping.apply(image)),
+            };
+            let decode = started.elapsed();
+            let image = dither::quantize(
+                scale(image, sidecar, max_texture_size, scaling),
+                scaling.dither.unwrap_or(tone_mapping.dither),
+            );
+            Some(LoadedImage {
+                image,
+                info,
+                decode,
+                scale: started.elapsed() - decode,
+            })
+        }
+        Err(err) => {
+            warn!("{err:?}");
+            None
+        }
+    }
+}
+
+/// The smallest of the texture size and `max-decode-size`
+fn max_size(max_texture_size: Option<u32>, max_decode_size: Option<u32>) -> Option<u32> {
+    match (max_texture_size, max_decode_size) {
+        (Some(texture_size), Some(decode_size)) => Some(texture_size.min(decode_size)),
+        (max_size, None) | (None, max_size) => max_size,
+    }
+}
+
+/// Downscale the image to the smallest of the texture size and `max-decode-size`, then to the
+/// display with [Scaling::display]
+fn scale(
+    image: DynamicImage,
+    sidecar: &Sidecar,
+    max_texture_size: Option<u32>,
+    scaling: Scaling,
+) -> DynamicImage {
+    let image = match max_size(max_texture_size, scaling.max_decode_size) {
+        Some(max_size) => downscale_to_fit(image, max_size, scaling.linear),
+        None => image,
+    };
+    match scaling.display {
+        Some(display) => downscale_to_display(
+            image,
+            display.width,
+            display.height,
+            sidecar.mode.unwrap_or(display.mode),
+            scaling.linear,
+        ),
+        None => image,
+    }
+}
+
+/// Decode the frames of the animation and apply the settings of the image to each one, run in
+/// the loader thread
+fn load_frames(
+    path: &Path,
+    max_texture_size: Option<u32>,
+    scaling: Scaling,
+    limits: &DecodeLimits,
+) -> Result<Option<Vec<Frame>>> {
+    let Some(decoded) = animation::decode_frames(path, limits)? else {
+        return Ok(None);
+    };
+    let sidecar = Sidecar::load(path);
+    let max_memory = limits.max_animation_memory * 1024 * 1024;
+    let mut memory = 0;
+    let mut frames = Vec::new();
+    for frame in decoded {
+        if frames.len() == limits.max_frames {
+            warn!(
+                "image {path:?} has more frames than the limit {} of {}, showing it as a still image",
+                "max-frames".bold().italic().blue(),
+                "decode-limits".bold().magenta(),
+            );
+            return Ok(None);
+        }
+        let frame = frame.with_context(|| format!("decoding the frames of image {path:?}"))?;
+        let delay = animation::frame_delay(frame.delay());
+        let image = sidecar.apply(DynamicImage::from(frame.into_buffer()));
+        let image = scale(image, &sidecar, max_texture_size, scaling).into_rgba8();
+        memory += image.as_raw().len() as u64;
+        if memory > max_memory {
+            warn!(
+                "the frames of image {path:?} exceed the limit {} of {}, showing it as a still image",
+                "max-animation-memory".bold().italic().blue(),
+                "decode-limits".bold().magenta(),
+            );
+            return Ok(None);
+        }
+        frames.push(Frame {
+            image: Arc::new(image),
+            delay,
+        });
+    }
+    Ok(Some(frames))
+}
+
+/// Decode a big JPEG image at a reduced scale (1/2, 1/4 or 1/8), which is much faster than
+/// decoding it entirely. Return None for the other images.
+fn decode_preview(path: &Path, limits: &DecodeLimits) -> Option<DynamicImage> {
+    decode_jpeg_scaled(path, PREVIEW_MIN_SIZE as u32, PREVIEW_SIZE as u32, limits)
+}
+
+/// Decode the image with its longest side capped to `max_size`. The JPEG and the PNG images are
+/// decoded at a reduced scale, the others are decoded entirely, within `max-memory`, and
+/// downscaled right away, so that only the smaller image is kept in memory. See [resize] for
+/// `linear`
+pub fn decode_capped(
+    path: &Path,
+    max_size: Option<u32>,
+    limits: &DecodeLimits,
+    linear: bool,
+) -> Result<DynamicImage> {
+    let Some(max_size) = max_size else {
+        return decode(path, limits);
+    };
+    let scaled = decode_jpeg_scaled(path, max_size, max_size, limits)
+        .or_else(|| decode_png_scaled(path, max_size, limits, linear));
+    let image = match scaled {
+        Some(image) => image,
+        None => decode(path, limits)?,
+    };
+    Ok(downscale_to_fit(image, max_size, linear))
+}
+
+/// Decode a PNG image whose longest side is at least twice `size`, averaging each block of
+/// pixels while its rows are read, so that the full image is never in memory. The result keeps
+/// its longest side at least `size` and the 16 bits of the source. Return None for the other
+/// images, the interlaced ones included, and for the ones exceeding `limits`, refused by
+/// [decode]. See [resize] for `linear`
+fn decode_png_scaled(
+    path: &Path,
+    size: u32,
+    limits: &DecodeLimits,
+    linear: bool,
+) -> Option<DynamicImage> {
+    if ImageFormat::from_path(path).ok()? != ImageFormat::Png {
+        return None;
+    }
+    let mut decoder = png::Decoder::new_with_limits(
+        ImageFile::open(path).ok()?,
+        png::Limits {
+            bytes: usize::try_from(limits.max_memory * 1024 * 1024).unwrap_or(usize::MAX),
+        },
+    );
+    // Palettes and the grays of less than 8 bits become 8-bit samples, the transparency an alpha
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().ok()?;
+    let (width, height, interlaced) = {
+        let info = reader.info();
+        (info.width, info.height, info.interlaced)
+    };
+    limits.check(path, width, height).ok()?;
+    let factor = width.max(height) / size.max(1);
+    // The rows of the interlaced images come in multiple passes
+    if interlaced || factor < 2 {
+        return None;
+    }
+    let (color, depth) = reader.output_color_type();
+    let channels = color.samples();
+    let sixteen = depth == png::BitDepth::Sixteen;
+    let sample = |data: &[u8], index: usize| -> f32 {
+        if sixteen {
+            u16::from_be_bytes([data[index * 2], data[index * 2 + 1]]) as f32 / 65535.0
+        } else {
+            data[index] as f32 / 255.0
+        }
+    };
+    let decode = srgb_to_linear();
+    let to_linear = |data: &[u8], index: usize| -> f32 {
+        match (linear, sixteen) {
+            (false, _) => sample(data, index),
+            (true, false) => decode[data[index] as usize] as f32 / 65535.0,
+            (true, true) => srgb_decode(sample(data, index)),
+        }
+    };
+
+    let (scaled_width, scaled_height) = (width.div_ceil(factor), height.div_ceil(factor));
+    let mut sums = vec![[0f32; 4]; scaled_width as usize];
+    let mut pixels = Vec::with_capacity(scaled_width as usize * scaled_height as usize * 4);
+    for y in 0..height {
+        let row = reader
+            .next_row()
+            .inspect_err(|err| warn!("decoding image {path:?} at a reduced scale: {err}"))
+            .ok()??;
+        let data = row.data();
+        for x in 0..width as usize {
+            let index = x * channels;
+            let [r, g, b, a] = match channels {
+                1 | 2 => {
+                    let gray = to_linear(data, index);
+                    let alpha = if channels == 2 {
+                        sample(data, index + 1)
+                    } else {
+                        1.0
+                    };
+                    [gray, gray, gray, alpha]
+                }
+                _ => [
+                    to_linear(data, index),
+                    to_linear(data, index + 1),
+                    to_linear(data, index + 2),
+                    if channels == 4 {
+                        sample(data, index + 3)
+                    } else {
+                        1.0
+                    },
+                ],
+            };
+            let sum = &mut sums[x / factor as usize];
+            sum[0] += r;
+            sum[1] += g;
+            sum[2] += b;
+            sum[3] += a;
+        }
+        if (y + 1) % factor != 0 && y + 1 != height {
+            continue;
+        }
+        // The last blocks of a row and the last row of blocks can be smaller
+        let block_height = (y % factor + 1) as f32;
+        for (x, sum) in sums.iter_mut().enumerate() {
+            let block_width = (width - x as u32 * factor).min(factor) as f32;
+            let count = block_width * block_height;
+            for (channel, value) in sum.iter().enumerate() {
+                let mut value = value / count;
+                if linear && channel < 3 {
+                    value = srgb_encode(value.clamp(0.0, 1.0));
+                }
+                pixels.push((value.clamp(0.0, 1.0) * 65535.0).round() as u16);
+            }
+            *sum = [0.0; 4];
+        }
+    }
+    let image = ImageBuffer::<Rgba<u16>, _>::from_raw(scaled_width, scaled_height, pixels)?;
+    Some(if sixteen {
+        DynamicImage::ImageRgba16(image)
+    } else {
+        DynamicImage::ImageRgba8(DynamicImage::ImageRgba16(image).into_rgba8())
+    })
+}
+
+/// Decode a JPEG image whose longest side is bigger than `min_size` at the smallest scale
+/// (1/2, 1/4 or 1/8) keeping it at least `size`. Return None for the other images, and for the
+/// ones exceeding `limits`, which are refused by [decode]
+fn decode_jpeg_scaled(
+    path: &Path,
+    min_size: u32,
+    size: u32,
+    limits: &DecodeLimits,
+) -> Option<DynamicImage> {
+    if ImageFormat::from_path(path).ok()? != ImageFormat::Jpeg {
+        return None;
+    }
+    let mut decoder = jpeg_decoder::Decoder::new(ImageFile::open(path).ok()?);
+    decoder.read_info().ok()?;
+    let info = decoder.info()?;
+    limits
+        .check(path, info.width as u32, info.height as u32)
+        .ok()?;
+    let longest_side = info.width.max(info.height) as u32;
+    if longest_side <= min_size {
+        return None;
+    }
+    let scale = |side: u16| (side as u32 * size / longest_side).max(1) as u16;
+    let (width, height) = decoder.scale(scale(info.width), scale(info.height)).ok()?;
+    let pixels = decoder
+        .decode()
+        .inspect_err(|err| warn!("decoding image {path:?} at a reduced scale: {err:?}"))
+        .ok()?;
+    let (width, height) = (width as u32, height as u32);
+    match info.pixel_format {
+        PixelFormat::L8 => GrayImage::from_raw(width, height, pixels).map(DynamicImage::from),
+        PixelFormat::RGB24 => RgbImage::from_raw(width, height, pixels).map(DynamicImage::from),
+        // Leave the conversion of the other formats to the image crate
+        PixelFormat::L16 | PixelFormat::CMYK32 => None,
+    }
+}
+
+/// The files bigger than this are read at once, see [ImageFile]
+const READ_AT_ONCE_MIN_SIZE: u64 = 16 * 1024 * 1024;
+
+/// The content of an image file. The big ones, e.g. the panoramas of hundreds of MB, are read
+/// into memory with a single read, instead of one for each few KiB asked by the decoders, and
+/// the decoders seek in the buffer. They are not mapped in memory: another program overwriting
+/// the file while it is decoded would kill wpaperd with SIGBUS, while a read only returns the
+/// content found, which the decoder refuses
+pub(crate) enum ImageFile {
+    Buffered(Cursor<Vec<u8>>),
+    Read(BufReader<File>),
+}
+
+impl ImageFile {
+    pub(crate) fn open(path: &Path) -> io::Result<Self> {
+        Self::open_with(path, READ_AT_ONCE_MIN_SIZE)
+    }
+
+    fn open_with(path: &Path, read_at_once_min_size: u64) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let metadata = file.metadata()?;
+        if metadata.is_file() && metadata.len() >= read_at_once_min_size {
+            // The size is only a hint, the file might be written meanwhile
+            let mut content = Vec::with_capacity(usize::try_from(metadata.len()).unwrap_or(0));
+            file.read_to_end(&mut content)?;
+            return Ok(ImageFile::Buffered(Cursor::new(content)));
+        }
+        Ok(ImageFile::Read(BufReader::new(file)))
+    }
+}
+
+impl Read for ImageFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            ImageFile::Buffered(content) => content.read(buf),
+            ImageFile::Read(file) => file.read(buf),
+        }
+    }
+}
+
+impl BufRead for ImageFile {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        match self {
+            ImageFile::Buffered(content) => content.fill_buf(),
+            ImageFile::Read(file) => file.fill_buf(),
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        match self {
+            ImageFile::Buffered(content) => content.consume(amt),
+            ImageFile::Read(file) => file.consume(amt),
+        }
+    }
+}
+
+impl Seek for ImageFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            ImageFile::Buffered(content) => content.seek(pos),
+            ImageFile::Read(file) => file.seek(pos),
+        }
+    }
+}
+
+/// Decode the image, refusing it when it exceeds `limits`. Only the first frame of the
+/// animations is decoded
+pub fn decode(path: &Path, limits: &DecodeLimits) -> Result<DynamicImage> {
+    #[cfg(feature = "heif")]
+    if crate::heif::is_heif(path) {
+        return crate::heif::decode(path, limits);
+    }
+    let mut reader =
+        ImageReader::new(ImageFile::open(path).with_context(|| format!("opening image {path:?}"))?);
+    if let Ok(format) = ImageFormat::from_path(path) {
+        reader.set_format(format);
+    }
+    reader.limits(image_limits(limits));
+    let decoder = reader
+        .into_decoder()
+        .with_context(|| format!("decoding image {path:?}"))?;
+    let (width, height) = decoder.dimensions();
+    limits.check(path, width, height)?;
+    limits.check_memory(path, decoder.total_bytes())?;
+    DynamicImage::from_decoder(decoder).with_context(|| format!("decoding image {path:?}"))
+}
+
+/// The size of the image, read from its header
+fn dimensions(path: &Path) -> Option<(u32, u32)> {
+    #[cfg(feature = "heif")]
+    if crate::heif::is_heif(path) {
+        return crate::heif::dimensions(path).ok();
+    }
+    let mut reader = ImageReader::new(ImageFile::open(path).ok()?);
+    reader.set_format(ImageFormat::from_path(path).ok()?);
+    reader.into_dimensions().ok()
+}
+
+/// The limits enforced by the decoders of the image crate
+pub(crate) fn image_limits(limits: &DecodeLimits) -> Limits {
+    let mut image_limits = Limits::default();
+    image_limits.max_image_width = Some(limits.max_width);
+    image_limits.max_image_height = Some(limits.max_height);
+    image_limits.max_alloc = Some(limits.max_memory * 1024 * 1024);
+    image_limits
+}
+
+/// Downscale the image, keeping its aspect ratio, if it's bigger than the textures supported
+/// by the GPU. See [resize] for `linear`
+pub fn downscale_to_fit(image: DynamicImage, max_size: u32, linear: bool) -> DynamicImage {
+    if image.width() <= max_size && image.height() <= max_size {
+        return image;
+    }
+    info!(
+        "downscaling image of size {}x{} to fit in the maximum texture size {max_size}",
+        image.width(),
+        image.height()
+    );
+    // Same rounding as DynamicImage::resize
+    let ratio = f64::min(
+        max_size as f64 / image.width() as f64,
+        max_size as f64 / image.height() as f64,
+    );
+    let width = ((image.width() as f64 * ratio).round() as u32).max(1);
+    let height = ((image.height() as f64 * ratio).round() as u32).max(1);
+    resize(image, width, height, FilterType::Triangle, linear)
+}
+
+/// Resize the image to `width`x`height`. When `linear` is true, the pixels are averaged in
+/// linear light: averaging the sRGB values darkens the bright details over a dark background,
+/// e.g. the stars or the city lights, and blurs the edges with a lot of contrast. The 8-bit images
+/// are returned with 16 bits, so that the levels created in between can be dithered, see
+/// [crate::dither]
+pub fn resize(
+    image: DynamicImage,
+    width: u32,
+    height: u32,
+    filter: FilterType,
+    linear: bool,
+) -> DynamicImage {
+    let color = image.color();
+    match image {
+        _ if !linear && color.bytes_per_pixel() == color.channel_count() => {
+            return DynamicImage::ImageRgba16(image.into_rgba16())
+                .resize_exact(width, height, filter)
+        }
+        // The floating point images already hold linear light
+        _ if !linear => return image.resize_exact(width, height, filter),
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => {
+            return image.resize_exact(width, height, filter)
+        }
+        DynamicImage::ImageLuma16(_)
+        | DynamicImage::ImageLumaA16(_)
+        | DynamicImage::ImageRgb16(_)
+        | DynamicImage::ImageRgba16(_) => {
+            let mut linear = image.into_rgba32f();
+            for pixel in linear.pixels_mut() {
+                for channel in &mut pixel.0[..3] {
+                    *channel = srgb_decode(*channel);
+                }
+            }
+            let mut resized = imageops::resize(&linear, width, height, filter);
+            for pixel in resized.pixels_mut() {
+                for channel in &mut pixel.0[..3] {
+                    *channel = srgb_encode(channel.clamp(0.0, 1.0));
+                }
+            }
+            return DynamicImage::ImageRgba16(DynamicImage::ImageRgba32F(resized).into_rgba16());
+        }
+        _ => {}
+    }
+    let decode = srgb_to_linear();
+    let mut linear = ImageBuffer::<Rgba<u16>, _>::new(image.width(), image.height());
+    for (pixel, source) in linear.pixels_mut().zip(image.into_rgba8().pixels()) {
+        let [r, g, b, a] = source.0;
+        pixel.0 = [
+            decode[r as usize],
+            decode[g as usize],
+            decode[b as usize],
+            a as u16 * 257,
+        ];
+    }
+    let mut resized = imageops::resize(&linear, width, height, filter);
+    let encode = linear_to_srgb();
+    for pixel in resized.pixels_mut() {
+        for channel in &mut pixel.0[..3] {
+            *channel = encode[*channel as usize];
+        }
+    }
+    DynamicImage::ImageRgba16(resized)
+}
+
+/// The linear value of each 8-bit sRGB value, on 16 bits
+fn srgb_to_linear() -> &'static [u16; 256] {
+    static TABLE: OnceLock<[u16; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        std::array::from_fn(|value| (srgb_decode(value as f32 / 255.0) * 65535.0).round() as u16)
+    })
+}
+
+/// The 16-bit sRGB value of each 16-bit linear value
+fn linear_to_srgb() -> &'static [u16] {
+    static TABLE: OnceLock<Vec<u16>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        (0..=u16::MAX)
+            .map(|linear| (srgb_encode(linear as f32 / 65535.0) * 65535.0).round() as u16)
+            .collect()
+    })
+}
+
+/// Downscale the image to the size it's drawn at on a display of `width`x`height` pixels, so
+/// that the GPU doesn't have to. Used by [crate::wallpaper_info::ResizeFilter::Lanczos3].
+pub fn downscale_to_display(
+    image: DynamicImage,
+    width: u32,
+    height: u32,
+    mode: BackgroundMode,
+    linear: bool,
+) -> DynamicImage {
+    let width_scale = width as f32 / image.width() as f32;
+    let height_scale = height as f32 / image.height() as f32;
+    let (width_scale, height_scale) = match mode {
+        BackgroundMode::Stretch => (width_scale, height_scale),
+        BackgroundMode::Center => {
+            let scale = width_scale.max(height_scale);
+            (scale, scale)
+        }
+        BackgroundMode::Fit | BackgroundMode::FitBorderColor => {
+            let scale = width_scale.min(height_scale);
+            (scale, scale)
+        }
+        // The tiles are drawn at the size of the image, the pixel art is only scaled up
+        BackgroundMode::Tile | BackgroundMode::Integer => return image,
+    };
+    // Upscaling is left to the GPU
+    let scaled = |side: u32, scale: f32| ((side as f32 * scale.min(1.0)).round() as u32).max(1);
+    let (scaled_width, scaled_height) = (
+        scaled(image.width(), width_scale),
+        scaled(image.height(), height_scale),
+    );
+    if (scaled_width, scaled_height) == (image.width(), image.height()) {
+        return image;
+    }
+    resize(
+        image,
+        scaled_width,
+        scaled_height,
+        FilterType::Lanczos3,
+        linear,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_downscale_to_fit() {
+        let image = downscale_to_fit(RgbaImage::new(400, 100).into(), 200, true);
+        assert_eq!((image.width(), image.height()), (200, 50));
+        let image = downscale_to_fit(RgbaImage::new(100, 100).into(), 200, true);
+        assert_eq!((image.width(), image.height()), (100, 100));
+    }
+
+    #[test]
+    fn test_resize() {
+        // A white line next to a black one
+        let image = || {
+            DynamicImage::from(RgbaImage::from_fn(2, 1, |x, _| {
+                image::Rgba([(x * 255) as u8, (x * 255) as u8, (x * 255) as u8, 255])
+            }))
+        };
+        let pixel = |image: DynamicImage| image.into_rgba8().get_pixel(0, 0).0;
+        // Half of the light, not half of the sRGB value
+        assert_eq!(
+            pixel(resize(image(), 1, 1, FilterType::Triangle, true)),
+            [188, 188, 188, 255]
+        );
+        assert_eq!(
+            pixel(resize(image(), 1, 1, FilterType::Triangle, false))[0],
+            128
+        );
+    }
+
+    #[test]
+    fn test_downscale_to_display() {
+        let image = || DynamicImage::from(RgbaImage::new(400, 100));
+        let size = |image: DynamicImage| (image.width(), image.height());
+        assert_eq!(
+            size(downscale_to_display(
+                image(),
+                200,
+                200,
+                BackgroundMode::Fit,
+                true
+            )),
+            (200, 50)
+        );
+        // The image covers the display and is cropped
+        assert_eq!(
+            size(downscale_to_display(
+                image(),
+                200,
+                50,
+                BackgroundMode::Center,
+                true
+            )),
+            (200, 50)
+        );
+        assert_eq!(
+            size(downscale_to_display(
+                image(),
+                100,
+                200,
+                BackgroundMode::Center,
+                true
+            )),
+            (400, 100)
+        );
+        assert_eq!(
+            size(downscale_to_display(
+                image(),
+                100,
+                50,
+                BackgroundMode::Stretch,
+                true
+            )),
+            (100, 50)
+        );
+    }
+
+    #[test]
+    fn test_decode_preview() {
+        let path = std::env::temp_dir().join(format!("wpaperd-test-{}.jpg", std::process::id()));
+        RgbImage::new(8192, 64).save(&path).unwrap();
+        let preview = decode_preview(&path, &DecodeLimits::default());
+        std::fs::remove_file(&path).unwrap();
+
+        // Scaled by 1/8
+        let preview = preview.unwrap();
+        assert_eq!((preview.width(), preview.height()), (1024, 8));
+    }
+
+    #[test]
+    fn test_timeout() {
+        let mut loader = ImageLoader::new(Some(1), Rc::default(), Rc::default()).unwrap();
+        // Keep the only thread busy, the image is never loaded
+        let (unblock, blocked) = mpsc::channel::<()>();
+        loader.pool().spawn(move || {
+            let _ = blocked.recv();
+        });
+        let path = PathBuf::from("/nonexistent/stuck.png");
+        let load = |loader: &mut ImageLoader| {
+            loader.background_load(
+                path.clone(),
+                "DP-1".to_string(),
+                false,
+                Scaling::default(),
+                Priority::Visible,
+            )
+        };
+        assert!(matches!(load(&mut loader), ImageLoaderStatus::Waiting));
+        // Waiting in the queue doesn't count
+        assert!(matches!(load(&mut loader), ImageLoaderStatus::Waiting));
+
+        let image = loader.images.values().next().unwrap();
+        image
+            .started
+            .set(Instant::now() - DecodeLimits::default().timeout)
+            .unwrap();
+        assert!(matches!(load(&mut loader), ImageLoaderStatus::Error));
+        assert!(loader.has_timed_out(&path));
+        // Not loaded again
+        assert!(matches!(load(&mut loader), ImageLoaderStatus::Error));
+        assert!(loader.images.is_empty());
+        unblock.send(()).unwrap();
+    }
+
+    #[test]
+    fn test_image_file() {
+        let path =
+            std::env::temp_dir().join(format!("wpaperd-buffered-{}.png", std::process::id()));
+        let image = RgbImage::from_fn(16, 8, |x, y| image::Rgb([x as u8, y as u8, 0]));
+        image.save(&path).unwrap();
+        let buffered = ImageFile::open_with(&path, 0).unwrap();
+        let read = ImageFile::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(read, ImageFile::Read(_)));
+        assert!(matches!(buffered, ImageFile::Buffered(_)));
+        for file in [buffered, read] {
+            let mut reader = ImageReader::new(file);
+            reader.set_format(ImageFormat::Png);
+            assert_eq!(reader.decode().unwrap().into_rgb8(), image);
+        }
+    }
+
+    #[test]
+    fn test_decode_capped() {
+        let path =
+            |ext| std::env::temp_dir().join(format!("wpaperd-capped-{}.{ext}", std::process::id()));
+        let size = |image: DynamicImage| (image.width(), image.height());
+        let (jpeg, png) = (path("jpg"), path("png"));
+        RgbImage::new(8192, 64).save(&jpeg).unwrap();
+        RgbImage::new(800, 400).save(&png).unwrap();
+
+        // Decoded at 1/4 of the size, then downscaled to the exact size
+        let limits = DecodeLimits::default();
+        let jpeg_image = decode_capped(&jpeg, Some(1500), &limits, true).map(size);
+        let png_image = decode_capped(&png, Some(200), &limits, true).map(size);
+        let png_full = decode_capped(&png, None, &limits, true).map(size);
+        // Both the JPEG and the other decoders refuse the images exceeding the limits
+        let limits = DecodeLimits {
+            max_pixels: 100_000,
+            ..limits
+        };
+        let jpeg_refused = decode_capped(&jpeg, Some(1500), &limits, true).is_err();
+        let png_refused = decode_capped(&png, None, &limits, true).is_err();
+        std::fs::remove_file(&jpeg).unwrap();
+        std::fs::remove_file(&png).unwrap();
+
+        assert_eq!(jpeg_image.unwrap(), (1500, 12));
+        assert_eq!(png_image.unwrap(), (200, 100));
+        assert_eq!(png_full.unwrap(), (800, 400));
+        assert!(jpeg_refused);
+        assert!(png_refused);
+    }
+
+    #[test]
+    fn test_decode_png_scaled() {
+        let path = std::env::temp_dir().join(format!("wpaperd-scaled-{}.png", std::process::id()));
+        // Alternating black and white columns, 16 bits per channel
+        let image = ImageBuffer::<image::Rgb<u16>, _>::from_fn(9, 4, |x, _| {
+            image::Rgb([if x % 2 == 0 { 0 } else { 65535 }; 3])
+        });
+        image.save(&path).unwrap();
+        let limits = DecodeLimits::default();
+        let averaged = decode_png_scaled(&path, 4, &limits, false);
+        let linear = decode_png_scaled(&path, 4, &limits, true);
+        let not_scaled = decode_png_scaled(&path, 5, &limits, false);
+        let capped = decode_capped(&path, Some(4), &limits, false);
+        std::fs::remove_file(&path).unwrap();
+
+        let DynamicImage::ImageRgba16(averaged) = averaged.unwrap() else {
+            panic!("the 16 bits must be kept");
+        };
+        // 2x2 blocks, the last column is a block of its own
+        assert_eq!(averaged.dimensions(), (5, 2));
+        assert_eq!(averaged.get_pixel(0, 0).0, [32768, 32768, 32768, 65535]);
+        assert_eq!(averaged.get_pixel(4, 1).0, [0, 0, 0, 65535]);
+        // Averaged in linear light, the gray is brighter
+        let linear = linear.unwrap().into_rgba16();
+        assert!(linear.get_pixel(0, 0).0[0] > 48000);
+        assert!(not_scaled.is_none());
+        assert_eq!(capped.unwrap().width(), 4);
+    }
+
+    #[test]
+    fn test_decode_animation() {
+        use image::{codecs::gif::GifEncoder, Frame, Rgba};
+
+        let path = std::env::temp_dir().join(format!("wpaperd-test-{}.gif", std::process::id()));
+        let frame = |color| Frame::new(RgbaImage::from_pixel(4, 4, Rgba(color)));
+        GifEncoder::new(File::create(&path).unwrap())
+            .encode_frames([frame([255, 0, 0, 255]), frame([0, 0, 255, 255])])
+            .unwrap();
+        let image = decode(&path, &DecodeLimits::default());
+        let frames = load_frames(
+            &path,
+            Some(2),
+            Scaling {
+                linear: true,
+                ..Scaling::default()
+            },
+            &DecodeLimits::default(),
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        // The animations are shown as still images, with their first frame
+        let image = image.unwrap().to_rgba8();
+        assert_eq!(image.get_pixel(0, 0), &Rgba([255, 0, 0, 255]));
+
+        // Unless animate is set, the frames are scaled like the still images
+        let frames = frames.unwrap().unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[1].image.dimensions(), (2, 2));
+        assert_eq!(frames[1].image.get_pixel(0, 0), &Rgba([0, 0, 255, 255]));
+        // No delay set, the browsers use 100ms
+        assert_eq!(frames[0].delay, Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,61 @@
+//! The metadata of the wallpaper shown, returned by `wpaperctl status` so that the bars can show
+//! it without reading the file again. Only the headers of the file are read.
+
+use std::{fs, path::Path};
+
+use image::io::Reader as ImageReader;
+use wpaperd_ipc::ImageMetadata;
+
+use crate::exif;
+
+pub fn read(path: &Path) -> ImageMetadata {
+    let reader = ImageReader::open(path)
+        .ok()
+        .and_then(|reader| reader.with_guessed_format().ok());
+    // The formats that the image crate cannot read, e.g. HEIF, are named after their extension
+    let format = reader
+        .as_ref()
+        .and_then(|reader| reader.format())
+        .map(|format| format!("{format:?}").to_lowercase())
+        .or_else(|| {
+            path.extension()
+                .map(|extension| extension.to_string_lossy().to_lowercase())
+        });
+    let dimensions = reader.and_then(|reader| reader.into_dimensions().ok());
+    let (camera, taken) = exif::camera_and_date(path);
+    ImageMetadata {
+        width: dimensions.map(|(width, _)| width),
+        height: dimensions.map(|(_, height)| height),
+        format,
+        size: fs::metadata(path).map_or(0, |metadata| metadata.len()),
+        camera,
+        taken: taken.map(|taken| taken.format("%Y-%m-%d %H:%M:%S").to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wallpaper_info::Color;
+
+    #[test]
+    fn test_read() {
+        let dir = std::env::temp_dir().join(format!("wpaperd-metadata-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("image.png");
+        Color([0x20, 0x40, 0x80]).image().save(&file).unwrap();
+
+        let metadata = read(&file);
+        assert_eq!(metadata.width, Some(1));
+        assert_eq!(metadata.height, Some(1));
+        assert_eq!(metadata.format.as_deref(), Some("png"));
+        assert_eq!(metadata.size, fs::metadata(&file).unwrap().len());
+        assert_eq!(metadata.camera, None);
+
+        let metadata = read(&dir.join("missing.heic"));
+        assert_eq!(metadata.format.as_deref(), Some("heic"));
+        assert_eq!(metadata.width, None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,1376 @@
+use std::{
+    cell::RefCell,
+    collections::{HashSet, VecDeque},
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::Arc,
+    time::Instant,
+};
+
+use log::warn;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use smithay_client_toolkit::reexports::client::{protocol::wl_surface::WlSurface, QueueHandle};
+
+use crate::{
+    filelist_cache::{FilelistCache, FilelistOptions, FilelistStatus},
+    filter::{AttributeCache, ImageFilter},
+    script::SelectionScript,
+    source::{new_source, SourceAction, SourceRequest, SourceStatus, WallpaperSource},
+    state::{ImageStats, State},
+    sync_clock::SyncClock,
+    wallpaper_groups::{Mirror, SharedPool, WallpaperGroup, WallpaperGroups},
+    wallpaper_info::{InitialImage, Pool, Sorting, Source, WallpaperInfo},
+    wpaperd::Wpaperd,
+};
+
+#[derive(Debug)]
+pub struct Queue {
+    buffer: VecDeque<PathBuf>,
+    current: usize,
+    tail: usize,
+    size: usize,
+    /// Used to pick the new images, seeded when the random order must be reproducible
+    rng: Box<StdRng>,
+}
+
+impl Queue {
+    pub fn with_capacity(size: usize) -> Self {
+        Self {
+            buffer: VecDeque::with_capacity(size),
+            current: 0,
+            tail: size - 1,
+            size,
+            rng: Box::new(StdRng::from_entropy()),
+        }
+    }
+
+    pub fn seeded(mut self, seed: Option<u64>) -> Self {
+        self.reseed(seed);
+        self
+    }
+
+    fn reseed(&mut self, seed: Option<u64>) {
+        *self.rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+    }
+
+    #[cfg(test)]
+    fn current(&self) -> &Path {
+        &self.buffer[self.current]
+    }
+
+    fn next(&mut self) -> Option<(&Path, usize)> {
+        let next_index = (self.current + 1) % self.size;
+        if !self.is_full() {
+            if next_index < self.buffer.len() {
+                self.current = next_index;
+                Some((&self.buffer[next_index], next_index))
+            } else {
+                None
+            }
+        } else if self.current != self.tail {
+            self.current = next_index;
+            Some((&self.buffer[next_index], next_index))
+        } else {
+            None
+        }
+    }
+
+    fn previous(&mut self) -> Option<(&Path, usize)> {
+        let prev_index = (self.current + self.size - 1) % self.size;
+        if prev_index != self.tail {
+            self.current = prev_index;
+            Some((&self.buffer[prev_index], prev_index))
+        } else {
+            None
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.buffer.len() == self.size
+    }
+
+    /// The images that will be returned by [Queue::next]
+    fn upcoming(&self) -> Vec<PathBuf> {
+        let mut upcoming = Vec::new();
+        let mut current = self.current;
+        loop {
+            let next_index = (current + 1) % self.size;
+            let has_next = if self.is_full() {
+                current != self.tail
+            } else {
+                next_index < self.buffer.len()
+            };
+            if !has_next {
+                break upcoming;
+            }
+            current = next_index;
+            upcoming.push(self.buffer[current].clone());
+        }
+    }
+
+    fn contains(&self, p: &PathBuf) -> bool {
+        self.buffer.contains(p)
+    }
+
+    fn set_current_to(&mut self, p: &Path) {
+        if let Some(index) = self.buffer.iter().position(|path| p == path) {
+            self.current = index;
+        }
+    }
+
+    fn push(&mut self, p: PathBuf) {
+        // Avoid duplicates
+        if self.buffer.contains(&p) {
+            return;
+        };
+
+        if self.is_full() {
+            self.buffer.pop_front();
+            self.buffer.push_back(p);
+        } else {
+            self.buffer.push_back(p);
+            self.current = self.buffer.len() - 1;
+        }
+    }
+
+    fn has_reached_end(&self) -> bool {
+        self.current == self.tail
+    }
+
+    fn resize(&mut self, new_size: usize) {
+        if !self.is_full() {
+            self.buffer.reserve_exact(new_size);
+            self.size = new_size;
+        } else {
+            let relative_current = (self.current + self.size - self.tail) % self.size;
+            self.current = (self.tail + self.size - new_size % self.size) % self.size;
+            let relative_current = (relative_current + self.size - self.current - 1) % new_size;
+            let mut new_buf = VecDeque::new();
+            while let Some((prev, _)) = self.next() {
+                new_buf.push_back(prev.to_path_buf());
+            }
+            self.current = relative_current;
+            self.tail = new_size - 1;
+            self.size = new_size;
+            self.buffer = new_buf;
+        }
+    }
+}
+
+enum ImagePickerAction {
+    Next,
+    Previous,
+}
+
+/// An image shown outside of the sequence, see [ImagePicker::show_once]
+struct ShownOnce {
+    /// The image on the display, once it has been loaded
+    shown: Option<PathBuf>,
+    /// The image to load, the current one when going back to it
+    pending: Option<PathBuf>,
+    /// Go back to the current image when the hold of the display expires
+    held: bool,
+}
+
+struct GroupedRandom {
+    surface: WlSurface,
+    group: Rc<RefCell<WallpaperGroup>>,
+}
+
+impl Drop for GroupedRandom {
+    fn drop(&mut self) {
+        self.group.borrow_mut().surfaces.remove(&self.surface);
+    }
+}
+
+/// Membership of a shared pool, see [crate::wallpaper_info::Pool]
+struct PoolMember {
+    surface: WlSurface,
+    pool: Rc<RefCell<SharedPool>>,
+}
+
+impl Drop for PoolMember {
+    fn drop(&mut self) {
+        self.pool.borrow_mut().leave(&self.surface);
+    }
+}
+
+/// A display showing the same images of another one, see [crate::wallpaper_info::WallpaperInfo]
+struct MirrorMember {
+    surface: WlSurface,
+    mirror: Rc<RefCell<Mirror>>,
+}
+
+impl Drop for MirrorMember {
+    fn drop(&mut self) {
+        self.mirror.borrow_mut().surfaces.remove(&self.surface);
+    }
+}
+
+enum ImagePickerSorting {
+    Random(Queue),
+    GroupedRandom(GroupedRandom),
+    Ascending(usize),
+    Descending(usize),
+}
+
+impl ImagePickerSorting {
+    fn new(
+        wallpaper_info: &WallpaperInfo,
+        wl_surface: &WlSurface,
+        groups: Rc<RefCell<WallpaperGroups>>,
+        filelist_cache: Rc<RefCell<FilelistCache>>,
+    ) -> Self {
+        match wallpaper_info.picker_sorting() {
+            None | Some(Sorting::Random | Sorting::LeastRecentlyShown) => {
+                Self::new_random(wallpaper_info.drawn_images_queue_size, wallpaper_info.seed)
+            }
+            Some(Sorting::GroupedRandom { group }) => Self::new_grouped_random(
+                groups,
+                group,
+                wl_surface,
+                wallpaper_info.drawn_images_queue_size,
+                wallpaper_info.seed,
+            ),
+            Some(Sorting::Ascending | Sorting::ExifDate) => {
+                let files_len = filelist_cache
+                    .clone()
+                    .borrow()
+                    .get(&wallpaper_info.path, wallpaper_info.filelist_options())
+                    .len();
+                Self::new_ascending(files_len)
+            }
+            Some(Sorting::Descending) => Self::new_descending(),
+        }
+    }
+
+    fn new_random(queue_size: usize, seed: Option<u64>) -> Self {
+        Self::Random(Queue::with_capacity(queue_size).seeded(seed))
+    }
+
+    fn new_descending() -> ImagePickerSorting {
+        Self::Descending(0)
+    }
+
+    fn new_ascending(files_len: usize) -> ImagePickerSorting {
+        // The list of files might still be loading
+        Self::Ascending(files_len.saturating_sub(1))
+    }
+
+    fn new_grouped_random(
+        groups: Rc<RefCell<WallpaperGroups>>,
+        group: u8,
+        wl_surface: &WlSurface,
+        queue_size: usize,
+        seed: Option<u64>,
+    ) -> Self {
+        Self::GroupedRandom(GroupedRandom {
+            surface: wl_surface.clone(),
+            group: groups
+                .borrow_mut()
+                .get_or_insert(group, wl_surface, queue_size, seed),
+        })
+    }
+}
+
+pub struct ImagePicker {
+    current_img: PathBuf,
+    pub image_changed_instant: Instant,
+    action: Option<ImagePickerAction>,
+    sorting: ImagePickerSorting,
+    filelist_cache: Rc<RefCell<FilelistCache>>,
+    filelist_options: FilelistOptions,
+    /// The image to pick before the first one is shown
+    initial: Option<InitialImage>,
+    /// Images requested by the user, shown before the ones picked by the sorting method
+    queued: VecDeque<PathBuf>,
+    reload: bool,
+    /// The name of the display, sent to the source
+    output: String,
+    /// Decide the images in place of the sorting method, when the display doesn't use a path
+    source: Option<Box<dyn WallpaperSource>>,
+    /// The source is fetching an image
+    source_requested: bool,
+    /// Choose the images in the directory in place of the sorting method
+    script: Option<SelectionScript>,
+    groups: Rc<RefCell<WallpaperGroups>>,
+    /// Set when the display shares the images of its directory with other displays
+    pool: Option<PoolMember>,
+    /// Set when the display shows the images of another one
+    mirror: Option<MirrorMember>,
+    /// Set when the display changes the image at the same time as the other machines
+    clock: Option<SyncClock>,
+    /// The slot whose image must be shown next, see [crate::sync_clock]
+    synced_slot: Option<u64>,
+    /// Shown in place of the current image, which is still used to pick the next one
+    once: Option<ShownOnce>,
+    /// The random order prefers the images not shown for the longest time
+    least_recently_shown: bool,
+    /// When the images have been shown, for `least-recently-shown` and `filter`
+    state: Rc<RefCell<State>>,
+    /// Only the images matching it are picked
+    filter: Option<ImageFilter>,
+    /// The attributes of the images read by the filter
+    attributes: RefCell<AttributeCache>,
+}
+
+impl ImagePicker {
+    pub const DEFAULT_DRAWN_IMAGES_QUEUE_SIZE: usize = 10;
+    /// How many images are listed by [ImagePicker::upcoming_images] for the sorted methods
+    const UPCOMING_IMAGES: usize = 10;
+    pub fn new(
+        wallpaper_info: &WallpaperInfo,
+        wl_surface: &WlSurface,
+        filelist_cache: Rc<RefCell<FilelistCache>>,
+        groups: Rc<RefCell<WallpaperGroups>>,
+        output: &str,
+        state: Rc<RefCell<State>>,
+    ) -> Self {
+        let last_shown = state
+            .borrow()
+            .display(output)
+            .and_then(|display| display.last_shown.clone());
+        let initial = match &wallpaper_info.initial {
+            Some(InitialImage::LastShown) => last_shown.map(InitialImage::Path),
+            // The sorted images continue from the one shown before the restart
+            None if wallpaper_info.is_sorted() => last_shown.map(InitialImage::Path),
+            initial => initial.clone(),
+        };
+        let pool = new_pool_member(wallpaper_info, wl_surface, &groups, output);
+        let mirror = new_mirror_member(wallpaper_info.mirror.as_deref(), wl_surface, &groups);
+        let clock = SyncClock::new(wallpaper_info);
+        Self {
+            current_img: PathBuf::from(""),
+            image_changed_instant: Instant::now(),
+            action: Some(ImagePickerAction::Next),
+            sorting: ImagePickerSorting::new(
+                wallpaper_info,
+                wl_surface,
+                groups.clone(),
+                filelist_cache.clone(),
+            ),
+            filelist_cache,
+            filelist_options: wallpaper_info.filelist_options(),
+            initial,
+            queued: VecDeque::new(),
+            reload: false,
+            output: output.to_string(),
+            source: wallpaper_info
+                .source
+                .as_ref()
+                .map(|source| new_source(source, output)),
+            source_requested: false,
+            script: wallpaper_info.script.as_deref().and_then(new_script),
+            groups,
+            pool,
+            mirror,
+            // Start from the image shown by the other machines
+            synced_slot: clock.as_ref().map(SyncClock::current_slot),
+            clock,
+            once: None,
+            least_recently_shown: wallpaper_info.sorting == Some(Sorting::LeastRecentlyShown),
+            state,
+            filter: wallpaper_info.filter.clone(),
+            attributes: RefCell::new(AttributeCache::default()),
+        }
+    }
+
+    /// The images in the directory that can be picked, only the part of the display when the
+    /// pool is partitioned
+    fn files(&self, path: &Path) -> Arc<Vec<PathBuf>> {
+        let files = self.filtered_files(path);
+        let partition = self
+            .pool
+            .as_ref()
+            .and_then(|member| member.pool.borrow().partition(&member.surface, &files));
+        match partition {
+            Some(partition) => Arc::new(partition),
+            None => files,
+        }
+    }
+
+    /// The images in the directory matching the filter. When none of them matches it, it is
+    /// ignored
+    fn filtered_files(&self, path: &Path) -> Arc<Vec<PathBuf>> {
+        let files = self
+            .filelist_cache
+            .borrow()
+            .get(path, self.filelist_options);
+        let Some(filter) = &self.filter else {
+            return files;
+        };
+        let history = self.state.borrow().recently_shown(filter.history_size());
+        let filtered = filter.apply(&files, &mut self.attributes.borrow_mut(), &history);
+        if filtered.is_empty() && !files.is_empty() {
+            warn!("No image in directory {path:?} matches the filter {filter}, ignoring it");
+            return files;
+        }
+        Arc::new(filtered)
+    }
+
+    /// Get the image to show at startup, if it doesn't depend on the sorting method
+    fn initial_image(
+        &mut self,
+        files: &[PathBuf],
+        qh: &QueueHandle<Wpaperd>,
+    ) -> Option<(usize, PathBuf)> {
+        let index = match self.initial.as_ref()? {
+            InitialImage::First => 0,
+            InitialImage::Random => match self.sorting {
+                // Same as picking the next image
+                ImagePickerSorting::Random(_) | ImagePickerSorting::GroupedRandom(_) => {
+                    return None
+                }
+                ImagePickerSorting::Ascending(_) | ImagePickerSorting::Descending(_) => {
+                    rand::thread_rng().gen_range(0..files.len())
+                }
+            },
+            // It has been already replaced by the path in the state, if any
+            InitialImage::LastShown => return None,
+            InitialImage::Path(path) => files.iter().position(|file| file == path)?,
+        };
+        let path = files[index].to_path_buf();
+        if let ImagePickerSorting::GroupedRandom(group) = &self.sorting {
+            let mut group = group.group.borrow_mut();
+            // Another display of the group has already picked the image
+            if !group.current_image.as_os_str().is_empty() || group.loading_image.is_some() {
+                return None;
+            }
+            group.loading_image = Some((index, path.clone()));
+            group.queue_all_surfaces(qh);
+        }
+        Some((index, path))
+    }
+
+    /// Get the first image queued by the user, if any
+    fn queued_image(
+        &mut self,
+        files: &[PathBuf],
+        qh: &QueueHandle<Wpaperd>,
+    ) -> Option<(usize, PathBuf)> {
+        let path = self.queued.front()?.to_path_buf();
+        let index = match &mut self.sorting {
+            // usize::MAX makes sure that the image is pushed in the queue
+            ImagePickerSorting::Random(_) => usize::MAX,
+            ImagePickerSorting::GroupedRandom(group) => {
+                let mut group = group.group.borrow_mut();
+                // Follow the image chosen by the group
+                if self.current_img != group.current_image || group.loading_image.is_some() {
+                    return None;
+                }
+                group.loading_image = Some((usize::MAX, path.clone()));
+                group.queue_all_surfaces(qh);
+                usize::MAX
+            }
+            ImagePickerSorting::Ascending(current_index)
+            | ImagePickerSorting::Descending(current_index) => files
+                .iter()
+                .position(|file| file == &path)
+                .unwrap_or(*current_index),
+        };
+        Some((index, path))
+    }
+
+    /// Get the next image based on the sorting method
+    fn get_image_path(&mut self, files: &[PathBuf], qh: &QueueHandle<Wpaperd>) -> (usize, PathBuf) {
+        if let (Some(clock), Some(slot)) = (&self.clock, self.synced_slot) {
+            if !matches!(self.action, Some(ImagePickerAction::Previous)) {
+                let index = clock.image_index(slot, files.len());
+                if files[index] == self.current_img {
+                    // It won't be shown again
+                    self.synced_slot = None;
+                }
+                return (index, files[index].to_path_buf());
+            }
+        }
+        if self.current_img.as_os_str().is_empty() {
+            if let Some(initial_image) = self.initial_image(files, qh) {
+                return initial_image;
+            }
+        }
+        if let Some(ImagePickerAction::Next) = self.action {
+            if let Some(queued_image) = self.queued_image(files, qh) {
+                return queued_image;
+            }
+        }
+        if let Some(script) = &mut self.script {
+            if self.action.is_none() && self.current_img.exists() {
+                let index = files
+                    .iter()
+                    .position(|file| file == &self.current_img)
+                    .unwrap_or(0);
+                return (index, self.current_img.to_path_buf());
+            }
+            let previous = matches!(self.action, Some(ImagePickerAction::Previous));
+            match script.select(&self.output, previous, &self.current_img, files) {
+                Ok(image) => return image,
+                // Use the sorting method instead
+                Err(err) => warn!("{err:?}"),
+            }
+        }
+
+        match (&self.action, &mut self.sorting) {
+            (
+                None,
+                ImagePickerSorting::Ascending(current_index)
+                | ImagePickerSorting::Descending(current_index),
+            ) if self.current_img.exists() => (*current_index, self.current_img.to_path_buf()),
+            (_, ImagePickerSorting::GroupedRandom(group))
+                if group.group.borrow().loading_image.is_some() =>
+            {
+                let group = group.group.borrow();
+                let (index, loading_image) = group.loading_image.as_ref().unwrap();
+                (*index, loading_image.to_path_buf())
+            }
+            (_, ImagePickerSorting::GroupedRandom(group))
+                if group.group.borrow().current_image != self.current_img =>
+            {
+                let group = group.group.borrow();
+                (group.index, group.current_image.clone())
+            }
+            (None, ImagePickerSorting::Random(_) | ImagePickerSorting::GroupedRandom(_))
+                if self.current_img.exists() =>
+            {
+                (0, self.current_img.to_path_buf())
+            }
+            (None | Some(ImagePickerAction::Next), ImagePickerSorting::Random(queue)) => {
+                // Avoid the images shown by the other displays of the pool
+                let excluded = self
+                    .pool
+                    .as_ref()
+                    .map(|member| member.pool.borrow().excluded(&member.surface))
+                    .unwrap_or_default();
+                if self.least_recently_shown {
+                    let state = self.state.borrow();
+                    next_least_recent_image(&self.current_img, queue, files, &excluded, |image| {
+                        state.image_stats(image)
+                    })
+                } else {
+                    next_random_image(&self.current_img, queue, files, &excluded)
+                }
+            }
+            (None | Some(ImagePickerAction::Next), ImagePickerSorting::GroupedRandom(group)) => {
+                let mut group = group.group.borrow_mut();
+                if self.current_img == group.current_image {
+                    // start loading a new image
+                    let (index, path) = next_random_image(
+                        &self.current_img,
+                        &mut group.queue,
+                        files,
+                        &HashSet::new(),
+                    );
+                    group.loading_image = Some((index, path.to_path_buf()));
+                    group.queue_all_surfaces(qh);
+                    (index, path)
+                } else {
+                    (group.index, group.current_image.clone())
+                }
+            }
+            (Some(ImagePickerAction::Previous), ImagePickerSorting::Random(queue)) => {
+                get_previous_image_for_random(&self.current_img, queue)
+            }
+            (Some(ImagePickerAction::Previous), ImagePickerSorting::GroupedRandom(group)) => {
+                let mut group = group.group.borrow_mut();
+                let queue = &mut group.queue;
+                let (index, path) = get_previous_image_for_random(&self.current_img, queue);
+                if path != group.current_image {
+                    group.loading_image = Some((index, path.to_path_buf()));
+                    group.queue_all_surfaces(qh);
+                }
+                (index, path)
+            }
+            (
+                None | Some(ImagePickerAction::Next),
+                ImagePickerSorting::Descending(current_index),
+            )
+            | (Some(ImagePickerAction::Previous), ImagePickerSorting::Ascending(current_index)) => {
+                let index = if files.get(*current_index) == Some(&self.current_img) {
+                    *current_index
+                } else {
+                    // if the current img doesn't correspond to the index we have
+                    // try looking for it in files
+                    match files.iter().position(|file| file == &self.current_img) {
+                        Some(new_index) => new_index,
+                        None => {
+                            // if we don't find it, use the last index as starting point
+                            // if the current_index is too big, start from last image
+                            // this is a fail safe in case many files gets deleted
+                            if *current_index >= files.len() {
+                                0
+                            } else {
+                                *current_index
+                            }
+                        }
+                    }
+                };
+                let index = if index == 0 {
+                    files.len() - 1
+                } else {
+                    index - 1
+                };
+                (index, files[index].to_path_buf())
+            }
+            (Some(ImagePickerAction::Previous), ImagePickerSorting::Descending(current_index))
+            | (
+                None | Some(ImagePickerAction::Next),
+                ImagePickerSorting::Ascending(current_index),
+            ) => {
+                let index = if files.get(*current_index) == Some(&self.current_img) {
+                    *current_index
+                } else {
+                    // The files are not sorted by path with exif-date
+                    files
+                        .iter()
+                        .position(|file| file == &self.current_img)
+                        .unwrap_or(*current_index)
+                };
+                let index = (index + 1) % files.len();
+                (index, files[index].to_path_buf())
+            }
+        }
+    }
+
+    /// Get the next image from the source, None if there is no need to change it or if it's
+    /// still being fetched
+    fn get_image_from_source(&mut self) -> Option<(PathBuf, usize)> {
+        let source = self.source.as_mut()?;
+        if self.reload && !self.current_img.as_os_str().is_empty() {
+            return Some((self.current_img.clone(), 0));
+        }
+        if !self.source_requested {
+            let action = match self.action {
+                Some(ImagePickerAction::Next) => SourceAction::Next,
+                Some(ImagePickerAction::Previous) => SourceAction::Previous,
+                None => return None,
+            };
+            if self.current_img.as_os_str().is_empty() {
+                if let Some(InitialImage::Path(path)) = &self.initial {
+                    return Some((path.clone(), 0));
+                }
+            }
+            if action == SourceAction::Next {
+                if let Some(path) = self.queued.front() {
+                    return Some((path.clone(), 0));
+                }
+            }
+            source.request(SourceRequest {
+                output: self.output.clone(),
+                action,
+                current: Some(self.current_img.clone()).filter(|p| !p.as_os_str().is_empty()),
+            });
+            self.source_requested = true;
+        }
+        match source.poll() {
+            SourceStatus::Ready(path) => {
+                self.source_requested = false;
+                Some((path, 0))
+            }
+            SourceStatus::Waiting => None,
+            SourceStatus::Error(err) => {
+                warn!("{err}");
+                self.source_requested = false;
+                self.action = None;
+                None
+            }
+        }
+    }
+
+    /// True if the source is still fetching the image
+    pub fn is_waiting_for_source(&self) -> bool {
+        self.source_requested
+    }
+
+    /// True when `path` is a directory without images, once it has been read
+    pub fn has_no_images(&self, path: &Path) -> bool {
+        if self.mirror.is_some() || self.source.is_some() || !path.is_dir() {
+            return false;
+        }
+        let cache = self.filelist_cache.borrow();
+        cache.get(path, self.filelist_options).is_empty()
+            && cache.status(path, self.filelist_options) != FilelistStatus::Loading
+    }
+
+    pub fn get_image_from_path(
+        &mut self,
+        path: &Path,
+        qh: &QueueHandle<Wpaperd>,
+    ) -> Option<(PathBuf, usize)> {
+        if let Some(once) = self.once.as_ref().filter(|_| self.action.is_none()) {
+            // Keep it until the next change
+            return once.pending.clone().map(|path| (path, 0));
+        }
+        let displayed = self.current_image();
+        if let Some(member) = &self.mirror {
+            // Follow the display being mirrored
+            let image = member.mirror.borrow().image.clone();
+            (!image.as_os_str().is_empty() && (image != displayed || self.reload))
+                .then_some((image, 0))
+        } else if self.source.is_some() {
+            self.get_image_from_source()
+        } else if path.is_dir() {
+            let files = self.files(path);
+
+            // There are no images, forcefully break out of the loop
+            if files.is_empty() {
+                // The directory is still being read, the surface will be notified when it's ready
+                if self
+                    .filelist_cache
+                    .borrow()
+                    .status(path, self.filelist_options)
+                    != FilelistStatus::Loading
+                {
+                    warn!("Directory {path:?} does not contain any valid image files.");
+                }
+                None
+            } else {
+                let (index, img_path) = self.get_image_path(&files, qh);
+                if img_path == displayed && !self.reload {
+                    None
+                } else {
+                    Some((img_path, index))
+                }
+            }
+        } else if path == displayed && !self.reload {
+            None
+        } else {
+            // path is not a directory, also it's not the current image or we need to reload
+            Some((path.to_path_buf(), 0))
+        }
+    }
+
+    pub fn update_current_image(&mut self, img_path: PathBuf, index: usize) {
+        match (self.action.take(), &mut self.sorting) {
+            (Some(ImagePickerAction::Next), ImagePickerSorting::Random(queue)) => {
+                if queue.has_reached_end() || queue.buffer.get(index).is_none() {
+                    queue.push(img_path.clone());
+                }
+            }
+            (None | Some(ImagePickerAction::Previous), ImagePickerSorting::Random { .. }) => {}
+            (
+                None | Some(ImagePickerAction::Previous),
+                ImagePickerSorting::GroupedRandom(group),
+            ) => {
+                let mut group = group.group.borrow_mut();
+                group.loading_image = None;
+                group.current_image.clone_from(&img_path);
+                group.index = index;
+            }
+            (
+                _,
+                ImagePickerSorting::Ascending(current_index)
+                | ImagePickerSorting::Descending(current_index),
+            ) => *current_index = index,
+            (Some(ImagePickerAction::Next), ImagePickerSorting::GroupedRandom(group)) => {
+                let mut group = group.group.borrow_mut();
+                let queue = &mut group.queue;
+                if queue.has_reached_end() || queue.buffer.get(index).is_none() {
+                    queue.push(img_path.clone());
+                }
+                group.loading_image = None;
+                group.current_image.clone_from(&img_path);
+                group.index = index;
+            }
+        }
+
+        if let Some(member) = &self.pool {
+            let queue_size = match &self.sorting {
+                ImagePickerSorting::Random(queue) => queue.size,
+                _ => Self::DEFAULT_DRAWN_IMAGES_QUEUE_SIZE,
+            };
+            member
+                .pool
+                .borrow_mut()
+                .show(&member.surface, &img_path, queue_size);
+        }
+        if self.queued.front() == Some(&img_path) {
+            self.queued.pop_front();
+        }
+        if let Some(script) = &mut self.script {
+            script.push_history(img_path.clone());
+        }
+        self.current_img = img_path;
+        self.initial = None;
+        self.synced_slot = None;
+        self.once = None;
+    }
+
+    /// Show `path` until the next change, without changing the sequence. If `held` is true, the
+    /// current image comes back when [ImagePicker::end_held_show_once] is called
+    pub fn show_once(&mut self, path: PathBuf, held: bool) {
+        let shown = self.once.take().and_then(|once| once.shown);
+        self.once = Some(ShownOnce {
+            shown,
+            pending: Some(path),
+            held,
+        });
+    }
+
+    /// Go back to the current image, if the one shown once was held
+    pub fn end_held_show_once(&mut self) {
+        match &mut self.once {
+            Some(once) if once.held && once.shown.is_some() => {
+                once.pending = Some(self.current_img.clone());
+                once.held = false;
+            }
+            Some(once) if once.held => self.once = None,
+            _ => {}
+        }
+    }
+
+    /// Return true if `path` is the image shown once (or the current one shown again after it),
+    /// which must not be added to the sequence
+    pub fn once_loaded(&mut self, path: &Path) -> bool {
+        match &mut self.once {
+            Some(once) if once.pending.as_deref() == Some(path) => {
+                if path == self.current_img {
+                    self.once = None;
+                } else {
+                    once.shown = once.pending.take();
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Show this image next, before the ones already queued
+    pub fn queue_next(&mut self, path: PathBuf) {
+        self.queued.push_front(path);
+    }
+
+    /// Remove an image queued with [ImagePicker::queue_next].
+    /// Return false if it wasn't queued.
+    pub fn remove_queued(&mut self, path: &Path) -> bool {
+        let len = self.queued.len();
+        self.queued.retain(|queued| queued != path);
+        self.queued.len() != len
+    }
+
+    /// Return the images queued by the user and the ones that the sorting method will pick next.
+    /// The random sorting only knows the upcoming images after going back with previous.
+    pub fn upcoming_images(&self, path: &Path) -> (Vec<PathBuf>, Vec<PathBuf>) {
+        let queued = self.queued.iter().cloned().collect();
+        let upcoming = match &self.sorting {
+            ImagePickerSorting::Random(queue) => queue.upcoming(),
+            ImagePickerSorting::GroupedRandom(group) => group.group.borrow().queue.upcoming(),
+            ImagePickerSorting::Ascending(current_index)
+            | ImagePickerSorting::Descending(current_index) => {
+                if !path.is_dir() {
+                    return (queued, Vec::new());
+                }
+                let files = self.files(path);
+                if files.is_empty() {
+                    return (queued, Vec::new());
+                }
+                let current_index = files
+                    .iter()
+                    .position(|file| file == &self.current_img)
+                    .unwrap_or(*current_index);
+                let ascending = matches!(self.sorting, ImagePickerSorting::Ascending(_));
+                (1..files.len().min(Self::UPCOMING_IMAGES + 1))
+                    .map(|offset| {
+                        let index = if ascending {
+                            (current_index + offset) % files.len()
+                        } else {
+                            (current_index + files.len() - offset) % files.len()
+                        };
+                        files[index].clone()
+                    })
+                    .collect()
+            }
+        };
+        (queued, upcoming)
+    }
+
+    /// The new directory has been read after the path changed, start from its first image
+    pub fn directory_read(&mut self, path: &Path) {
+        if let ImagePickerSorting::Ascending(_) = self.sorting {
+            self.sorting = ImagePickerSorting::new_ascending(self.files(path).len());
+        }
+    }
+
+    /// Update wallpaper by going down 1 index through the cached image paths
+    /// Expiry timer reset even if already at the first cached image
+    pub fn previous_image(&mut self) {
+        self.action = Some(ImagePickerAction::Previous);
+    }
+
+    /// Update wallpaper by going up 1 index through the cached image paths
+    pub fn next_image(&mut self, path: &Path, qh: &QueueHandle<Wpaperd>) {
+        self.action = Some(ImagePickerAction::Next);
+        // The source and the script are asked when the image is loaded
+        if self.source.is_none() && self.script.is_none() {
+            self.get_image_from_path(path, qh);
+        }
+    }
+
+    /// Show the image of `slot`, see [crate::sync_clock]
+    pub fn show_slot(&mut self, slot: u64, path: &Path, qh: &QueueHandle<Wpaperd>) {
+        self.synced_slot = Some(slot);
+        self.next_image(path, qh);
+    }
+
+    pub fn clock(&self) -> Option<&SyncClock> {
+        self.clock.as_ref()
+    }
+
+    /// Follow the new clock, showing the image of the current slot
+    pub fn update_clock(&mut self, clock: Option<SyncClock>) {
+        self.synced_slot = clock.as_ref().map(SyncClock::current_slot);
+        self.clock = clock;
+    }
+
+    /// The position in the images, for the trace of the rotation
+    pub fn queue_state(&self) -> String {
+        let sorting = match &self.sorting {
+            ImagePickerSorting::Random(queue) => format!(
+                "random, {} of {} images in the history",
+                queue.buffer.len(),
+                queue.size
+            ),
+            ImagePickerSorting::GroupedRandom(group) => {
+                format!("grouped random, index {}", group.group.borrow().index)
+            }
+            ImagePickerSorting::Ascending(index) => format!("ascending, index {index}"),
+            ImagePickerSorting::Descending(index) => format!("descending, index {index}"),
+        };
+        format!("{sorting}, {} queued", self.queued.len())
+    }
+
+    /// The image displayed, which can be one shown once
+    pub fn current_image(&self) -> PathBuf {
+        self.once
+            .as_ref()
+            .and_then(|once| once.shown.clone())
+            .unwrap_or_else(|| self.current_img.clone())
+    }
+
+    /// Return true if the path changed
+    pub fn update_sorting(
+        &mut self,
+        new_sorting: Option<Sorting>,
+        path: &Path,
+        filelist_options: FilelistOptions,
+        path_changed: bool,
+        drawn_images_queue_size: usize,
+        seed: Option<u64>,
+    ) {
+        self.filelist_options = filelist_options;
+        self.least_recently_shown = new_sorting == Some(Sorting::LeastRecentlyShown);
+        // It keeps the images shown in a queue, like the random order
+        let new_sorting = new_sorting.map(|sorting| match sorting {
+            Sorting::LeastRecentlyShown => Sorting::Random,
+            sorting => sorting,
+        });
+        if let Some(new_sorting) = new_sorting {
+            match (&mut self.sorting, new_sorting) {
+                // The files are read again when changing to or from exif-date
+                (_, Sorting::Ascending | Sorting::ExifDate) if path_changed => {
+                    self.sorting = ImagePickerSorting::new_ascending(self.files(path).len());
+                }
+                (_, Sorting::Descending) if path_changed => {
+                    self.sorting = ImagePickerSorting::new_descending();
+                }
+                (ImagePickerSorting::Descending(current_index), Sorting::Ascending) => {
+                    self.sorting = ImagePickerSorting::Ascending(*current_index)
+                }
+                (ImagePickerSorting::Ascending(current_index), Sorting::Descending) => {
+                    self.sorting = ImagePickerSorting::Descending(*current_index)
+                }
+                (ImagePickerSorting::Random { .. }, Sorting::Ascending | Sorting::Descending) => {
+                    let files = self.files(path);
+                    let index = files.binary_search(&self.current_img).ok();
+                    self.sorting = match new_sorting {
+                        Sorting::Random
+                        | Sorting::GroupedRandom { .. }
+                        | Sorting::ExifDate
+                        | Sorting::LeastRecentlyShown => unreachable!(),
+                        Sorting::Ascending => match index {
+                            Some(index) => ImagePickerSorting::Ascending(index),
+                            None => ImagePickerSorting::new_ascending(files.len()),
+                        },
+                        Sorting::Descending => match index {
+                            Some(index) => ImagePickerSorting::Descending(index),
+                            None => ImagePickerSorting::new_descending(),
+                        },
+                    };
+                }
+                (
+                    ImagePickerSorting::Descending(_) | ImagePickerSorting::Ascending(_),
+                    Sorting::Random,
+                ) if path_changed => {
+                    // If the path was changed, use a new random sorting
+                    self.sorting = ImagePickerSorting::new_random(drawn_images_queue_size, seed);
+                }
+                // The path has changed, use a new random sorting, otherwise we reuse the current
+                // drawn_images
+                (_, Sorting::Random) if path_changed => {
+                    self.sorting = ImagePickerSorting::new_random(drawn_images_queue_size, seed);
+                }
+                (
+                    ImagePickerSorting::Descending(_) | ImagePickerSorting::Ascending(_),
+                    Sorting::Random,
+                ) => {
+                    // if the path was not changed, use the current image as the first image of
+                    // the drawn_images
+                    let mut queue = Queue::with_capacity(drawn_images_queue_size).seeded(seed);
+                    queue.push(self.current_img.clone());
+                    self.sorting = ImagePickerSorting::Random(queue);
+                }
+                // No need to update the sorting if it's the same
+                (_, _) => {}
+            }
+        } else {
+            self.sorting = ImagePickerSorting::new_random(drawn_images_queue_size, seed);
+        }
+    }
+
+    pub fn update_queue_size(&mut self, drawn_images_queue_size: usize) {
+        match &mut self.sorting {
+            ImagePickerSorting::Random(queue) => {
+                queue.resize(drawn_images_queue_size);
+            }
+            ImagePickerSorting::Ascending(_) | ImagePickerSorting::Descending(_) => {}
+            ImagePickerSorting::GroupedRandom(group) => {
+                group
+                    .group
+                    .borrow_mut()
+                    .queue
+                    .resize(drawn_images_queue_size);
+            }
+        }
+    }
+
+    /// Restart the random order from the new seed
+    pub fn update_seed(&mut self, seed: Option<u64>) {
+        match &mut self.sorting {
+            ImagePickerSorting::Random(queue) => queue.reseed(seed),
+            ImagePickerSorting::Ascending(_) | ImagePickerSorting::Descending(_) => {}
+            ImagePickerSorting::GroupedRandom(group) => {
+                group.group.borrow_mut().queue.reseed(seed);
+            }
+        }
+    }
+
+    /// Show the current image on the displays mirroring this one
+    pub fn update_mirrors(&self, qh: &QueueHandle<Wpaperd>) {
+        self.groups
+            .borrow_mut()
+            .get_mirror(&self.output)
+            .borrow_mut()
+            .show(&self.current_img, qh);
+    }
+
+    /// Start or stop mirroring another display
+    pub fn update_mirror(&mut self, mirror: Option<&str>, wl_surface: &WlSurface) {
+        self.mirror = None;
+        self.mirror = new_mirror_member(mirror, wl_surface, &self.groups);
+    }
+
+    /// Join or leave the shared or partitioned pool of the directory
+    pub fn update_pool(&mut self, wallpaper_info: &WallpaperInfo, wl_surface: &WlSurface) {
+        // Leave the previous pool first
+        self.pool = None;
+        self.pool = new_pool_member(wallpaper_info, wl_surface, &self.groups, &self.output);
+        if let Some(member) = &self.pool {
+            if !self.current_img.as_os_str().is_empty() {
+                member.pool.borrow_mut().show(
+                    &member.surface,
+                    &self.current_img,
+                    wallpaper_info.drawn_images_queue_size,
+                );
+            }
+        }
+    }
+
+    /// Use the new source, if it has changed
+    pub fn update_source(&mut self, source: Option<&Source>) {
+        self.source = source.map(|source| new_source(source, &self.output));
+        self.source_requested = false;
+    }
+
+    /// Use the new filter, the attributes of the images are read again
+    pub fn update_filter(&mut self, filter: Option<&ImageFilter>) {
+        self.filter = filter.cloned();
+        self.attributes.borrow_mut().clear();
+    }
+
+    /// Use the new script, if it has changed
+    pub fn update_script(&mut self, script: Option<&Path>) {
+        self.script = script.and_then(new_script);
+    }
+
+    #[inline]
+    pub fn reload(&mut self) {
+        self.reload = true;
+    }
+
+    #[inline]
+    pub fn reloaded(&mut self) {
+        self.reload = false;
+    }
+
+    #[inline]
+    pub fn is_reloading(&self) -> bool {
+        self.reload
+    }
+}
+
+fn new_pool_member(
+    wallpaper_info: &WallpaperInfo,
+    wl_surface: &WlSurface,
+    groups: &Rc<RefCell<WallpaperGroups>>,
+    output: &str,
+) -> Option<PoolMember> {
+    let partition = (wallpaper_info.pool == Pool::Partitioned).then_some(output);
+    (wallpaper_info.pool != Pool::Independent && wallpaper_info.path.is_dir()).then(|| PoolMember {
+        surface: wl_surface.clone(),
+        pool: groups
+            .borrow_mut()
+            .get_or_insert_pool(&wallpaper_info.path, wl_surface, partition),
+    })
+}
+
+fn new_mirror_member(
+    mirror: Option<&str>,
+    wl_surface: &WlSurface,
+    groups: &Rc<RefCell<WallpaperGroups>>,
+) -> Option<MirrorMember> {
+    let mirror = groups.borrow_mut().get_mirror(mirror?);
+    mirror.borrow_mut().surfaces.insert(wl_surface.clone());
+    Some(MirrorMember {
+        surface: wl_surface.clone(),
+        mirror,
+    })
+}
+
+fn new_script(path: &Path) -> Option<SelectionScript> {
+    SelectionScript::new(path)
+        .inspect_err(|err| warn!("{err:?}"))
+        .ok()
+}
+
+/// Pick a random image, avoiding the ones in the queue and in `excluded`
+fn next_random_image(
+    current_image: &Path,
+    queue: &mut Queue,
+    files: &[PathBuf],
+    excluded: &HashSet<PathBuf>,
+) -> (usize, PathBuf) {
+    // Use the next images in the queue, if any
+    while let Some((next, index)) = queue.next() {
+        if next.exists() {
+            return (index, next.to_path_buf());
+        }
+    }
+    // If there is only one image just return it
+    if files.len() == 1 {
+        return (0, files[0].to_path_buf());
+    }
+
+    // Otherwise pick a new random image that has not been drawn before
+    // Try 5 times, then get a random image. We do this because it might happen
+    // that the queue is bigger than the amount of available wallpapers
+    let mut tries = 5;
+    loop {
+        let index = queue.rng.gen_range(0..files.len());
+        // search for an image that has not been drawn yet
+        // fail after 5 tries
+        if !queue.contains(&files[index]) && !excluded.contains(&files[index]) {
+            break (index, files[index].to_path_buf());
+        }
+
+        // We have already tried a bunch of times
+        // We still need a new image, get the first one that is different than
+        // the current one. We also know that there is more than one image
+        if tries == 0 {
+            // Prefer the images that are not excluded, if there are any left
+            let allowed: Vec<usize> = (0..files.len())
+                .filter(|&index| files[index] != current_image && !excluded.contains(&files[index]))
+                .collect();
+            if !excluded.is_empty() && !allowed.is_empty() {
+                let index = allowed[queue.rng.gen_range(0..allowed.len())];
+                break (index, files[index].to_path_buf());
+            }
+            break loop {
+                let index = queue.rng.gen_range(0..files.len());
+                if files[index] != current_image {
+                    break (index, files[index].to_path_buf());
+                }
+            };
+        }
+
+        tries -= 1;
+    }
+}
+
+/// Pick the image not shown for the longest time, by any display, avoiding the ones in the queue
+/// and in `excluded`. The ties go to the image shown fewer times, then to a random one
+fn next_least_recent_image(
+    current_image: &Path,
+    queue: &mut Queue,
+    files: &[PathBuf],
+    excluded: &HashSet<PathBuf>,
+    stats: impl Fn(&Path) -> ImageStats,
+) -> (usize, PathBuf) {
+    // Use the next images in the queue, if any
+    while let Some((next, index)) = queue.next() {
+        if next.exists() {
+            return (index, next.to_path_buf());
+        }
+    }
+    let candidates: Vec<(usize, ImageStats)> = files
+        .iter()
+        .enumerate()
+        .filter(|(_, file)| {
+            *file != current_image && !queue.contains(file) && !excluded.contains(*file)
+        })
+        .map(|(index, file)| (index, stats(file)))
+        .collect();
+    let Some(oldest) = candidates.iter().map(|(_, stats)| *stats).min() else {
+        // All of them have been shown recently
+        return next_random_image(current_image, queue, files, excluded);
+    };
+    let oldest: Vec<usize> = candidates
+        .into_iter()
+        .filter_map(|(index, stats)| (stats == oldest).then_some(index))
+        .collect();
+    let index = oldest[queue.rng.gen_range(0..oldest.len())];
+    (index, files[index].to_path_buf())
+}
+
+fn get_previous_image_for_random(current_image: &Path, queue: &mut Queue) -> (usize, PathBuf) {
+    while let Some((prev, index)) = queue.previous() {
+        if prev.exists() {
+            return (index, prev.to_path_buf());
+        }
+    }
+
+    // We didn't find any suitable image, reset to the last working one
+    queue.set_current_to(current_image);
+    (usize::MAX, current_image.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    // Note this useful idiom: importing names from outer (for mod tests) scope.
+    use super::*;
+
+    #[test]
+    fn test_push() {
+        let mut queue = Queue::with_capacity(2);
+        queue.push(PathBuf::from("mypath"));
+        queue.push(PathBuf::from("mypath2"));
+        assert_eq!(Path::new("mypath2"), queue.current());
+        assert_eq!(Some((Path::new("mypath"), 0)), queue.previous());
+        assert_eq!(Path::new("mypath"), queue.current());
+
+        assert_eq!(None, queue.previous());
+    }
+
+    #[test]
+    fn test_resize() {
+        let mut queue = Queue::with_capacity(5);
+        queue.push(PathBuf::from("mypath"));
+        queue.push(PathBuf::from("mypath2"));
+        queue.push(PathBuf::from("mypath3"));
+        queue.push(PathBuf::from("mypath4"));
+        queue.push(PathBuf::from("mypath5"));
+        assert_eq!(queue.buffer.len(), 5);
+        assert_eq!(Path::new("mypath5"), queue.current());
+        assert_eq!(Some((Path::new("mypath4"), 3)), queue.previous());
+
+        // Test that the current index works when it's inside the resizing range
+        queue.resize(2);
+        assert_eq!(Path::new("mypath4"), queue.current());
+        assert_eq!(None, queue.previous());
+        assert_eq!(Some((Path::new("mypath5"), 1)), queue.next());
+    }
+
+    #[test]
+    fn test_resize2() {
+        let mut queue = Queue::with_capacity(5);
+        queue.push(PathBuf::from("mypath"));
+        queue.push(PathBuf::from("mypath2"));
+        queue.push(PathBuf::from("mypath3"));
+        queue.push(PathBuf::from("mypath4"));
+        queue.push(PathBuf::from("mypath5"));
+        queue.push(PathBuf::from("mypath6"));
+        queue.push(PathBuf::from("mypath7"));
+        queue.push(PathBuf::from("mypath8"));
+        assert_eq!(queue.buffer.len(), 5);
+        assert_eq!(Path::new("mypath8"), queue.current());
+        assert_eq!(Some((Path::new("mypath7"), 3)), queue.previous());
+        assert_eq!(Some((Path::new("mypath6"), 2)), queue.previous());
+        assert_eq!(Some((Path::new("mypath5"), 1)), queue.previous());
+
+        // Test that the current item point to the first item available
+        queue.resize(2);
+        assert_eq!(Path::new("mypath7"), queue.current());
+        assert_eq!(Some((Path::new("mypath8"), 1)), queue.next());
+        assert_eq!(None, queue.next());
+    }
+
+    #[test]
+    fn test_upcoming() {
+        let mut queue = Queue::with_capacity(3);
+        assert!(queue.upcoming().is_empty());
+        queue.push(PathBuf::from("mypath"));
+        queue.push(PathBuf::from("mypath2"));
+        queue.push(PathBuf::from("mypath3"));
+        queue.push(PathBuf::from("mypath4"));
+        assert!(queue.upcoming().is_empty());
+        queue.previous();
+        queue.previous();
+        assert_eq!(
+            vec![PathBuf::from("mypath3"), PathBuf::from("mypath4")],
+            queue.upcoming()
+        );
+    }
+
+    #[test]
+    fn test_next_random_image_excluded() {
+        let files: Vec<PathBuf> = (0..4).map(|i| PathBuf::from(format!("{i}.jpg"))).collect();
+        let mut queue = Queue::with_capacity(1);
+        let excluded = HashSet::from([files[0].clone(), files[1].clone(), files[2].clone()]);
+        for _ in 0..10 {
+            let (_, image) = next_random_image(Path::new(""), &mut queue, &files, &excluded);
+            assert_eq!(image, files[3]);
+        }
+
+        // Pick another image when all of them are excluded
+        let excluded = files.iter().cloned().collect();
+        let (_, image) = next_random_image(&files[3], &mut queue, &files, &excluded);
+        assert_ne!(image, files[3]);
+    }
+
+    #[test]
+    fn test_next_least_recent_image() {
+        let files: Vec<PathBuf> = (0..4).map(|i| PathBuf::from(format!("{i}.jpg"))).collect();
+        let stats = |image: &Path| {
+            let (last_shown_at, times_shown) = match image.to_str().unwrap() {
+                "0.jpg" => (300, 1),
+                "1.jpg" => (100, 5),
+                "2.jpg" => (100, 2),
+                _ => (200, 1),
+            };
+            ImageStats {
+                last_shown_at,
+                times_shown,
+            }
+        };
+        let mut queue = Queue::with_capacity(2);
+        let (_, image) =
+            next_least_recent_image(&files[0], &mut queue, &files, &HashSet::new(), stats);
+        assert_eq!(image, files[2]);
+        // The images in the queue have just been shown
+        queue.push(files[2].clone());
+        queue.push(files[1].clone());
+        let (_, image) =
+            next_least_recent_image(&files[1], &mut queue, &files, &HashSet::new(), stats);
+        assert_eq!(image, files[3]);
+    }
+}
@@ -0,0 +1,135 @@
+//! The inhibitors stopping the cycling on all the displays, e.g. while a game is running or while
+//! the screen is being recorded.
+//!
+//! Each inhibitor has a name, so that the programs adding them don't release the ones of the
+//! others: the cycling resumes once all of them are removed. An inhibitor can expire after a
+//! timeout, so that a recorder that crashed doesn't stop the cycling forever.
+
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use log::info;
+use smithay_client_toolkit::reexports::calloop::{
+    timer::{TimeoutAction, Timer},
+    LoopHandle, RegistrationToken,
+};
+use wpaperd_ipc::Inhibitor;
+
+use crate::Wpaperd;
+
+#[derive(Default)]
+pub struct Inhibitors {
+    /// The instant each one expires at, by name
+    entries: BTreeMap<String, Option<Instant>>,
+    /// The timer removing the first one that expires, along with its deadline
+    timer: Option<(Instant, RegistrationToken)>,
+}
+
+impl Inhibitors {
+    /// Add the inhibitor, or replace its timeout if it already exists. A timeout too far to be
+    /// represented never expires
+    pub fn add(&mut self, name: String, timeout: Option<Duration>) {
+        info!("Inhibiting the cycling on all the displays for {name}");
+        self.entries.insert(
+            name,
+            timeout.and_then(|timeout| Instant::now().checked_add(timeout)),
+        );
+    }
+
+    /// Remove the inhibitor, or all of them when `name` is None. Return false if it didn't exist
+    pub fn remove(&mut self, name: Option<&str>) -> bool {
+        match name {
+            Some(name) => self.entries.remove(name).is_some(),
+            None => {
+                let removed = !self.entries.is_empty();
+                self.entries.clear();
+                removed
+            }
+        }
+    }
+
+    /// True if at least one inhibitor has not expired
+    pub fn is_active(&self) -> bool {
+        let now = Instant::now();
+        self.entries
+            .values()
+            .any(|expiry| expiry.map_or(true, |expiry| expiry > now))
+    }
+
+    pub fn list(&self) -> Vec<Inhibitor> {
+        let now = Instant::now();
+        self.entries
+            .iter()
+            .filter(|(_, expiry)| expiry.map_or(true, |expiry| expiry > now))
+            .map(|(name, expiry)| Inhibitor {
+                name: name.clone(),
+                expires_in: expiry.map(|expiry| expiry - now),
+            })
+            .collect()
+    }
+
+    fn remove_expired(&mut self) {
+        let now = Instant::now();
+        self.entries.retain(|name, expiry| {
+            let expired = expiry.is_some_and(|expiry| expiry <= now);
+            if expired {
+                info!("The inhibitor {name} has expired");
+            }
+            !expired
+        });
+    }
+
+    /// Called by the main loop, wake it up when the first inhibitor expires
+    pub fn update_timer(&mut self, handle: &LoopHandle<Wpaperd>) {
+        let next_expiry = self.entries.values().flatten().min().copied();
+        if next_expiry == self.timer.as_ref().map(|(deadline, _)| *deadline) {
+            return;
+        }
+        if let Some((_, registration_token)) = self.timer.take() {
+            handle.remove(registration_token);
+        }
+        let Some(deadline) = next_expiry else {
+            return;
+        };
+        let registration_token = handle
+            .insert_source(
+                Timer::from_deadline(deadline),
+                |_deadline, _: &mut (), wpaperd: &mut Wpaperd| {
+                    wpaperd.inhibitors.timer = None;
+                    wpaperd.inhibitors.remove_expired();
+                    TimeoutAction::Drop
+                },
+            )
+            .expect("Failed to insert event source!");
+        self.timer = Some((deadline, registration_token));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inhibitors() {
+        let mut inhibitors = Inhibitors::default();
+        assert!(!inhibitors.is_active());
+
+        // They stack, the cycling resumes once both are removed
+        inhibitors.add("gamemode".to_string(), None);
+        inhibitors.add("obs".to_string(), Some(Duration::from_secs(3600)));
+        assert!(inhibitors.remove(Some("gamemode")));
+        assert!(inhibitors.is_active());
+        assert_eq!(inhibitors.list()[0].name, "obs");
+        assert!(inhibitors.list()[0].expires_in.is_some());
+        assert!(!inhibitors.remove(Some("gamemode")));
+
+        // Adding it again replaces the timeout, expiring right away
+        inhibitors.add("obs".to_string(), Some(Duration::ZERO));
+        assert!(!inhibitors.is_active());
+        assert!(inhibitors.list().is_empty());
+        inhibitors.remove_expired();
+        assert!(!inhibitors.remove(None));
+    }
+}
@@ -0,0 +1,1103 @@
+//! IPC socket server.
+//! Based on <https://github.com/catacombing/catacomb/blob/master/src/ipc_server.rs>
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre::{anyhow, ensure, Context};
+use color_eyre::{Result, Section};
+use image::ImageFormat;
+use log::{error, info, warn};
+use smithay_client_toolkit::reexports::calloop::{channel, LoopHandle};
+use smithay_client_toolkit::reexports::client::QueueHandle;
+use wpaperd_ipc::{
+    read_message, read_message_with_limit, write_message, AnimationAction, IpcError, IpcMessage,
+    IpcResponse, OutputMatch, OutputStatus, ReloadScope, RemoteAddress, CAPABILITIES,
+    MAX_MESSAGE_SIZE, MAX_TOKEN_SIZE, PROTOCOL_VERSION,
+};
+
+use crate::backend::{Backend, RendererKind};
+use crate::clipboard::ClipboardContent;
+use crate::events::Event;
+use crate::sidecar::{Crop, Sidecar};
+use crate::socket::SocketSource;
+use crate::surface::Surface;
+use crate::thumbnails::{preview_strip_path, save_preview_strip, thumbnails, thumbnails_dir};
+use crate::wallpaper_info::Source;
+use crate::Wpaperd;
+
+/// Maximum time spent reading a message
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+/// Maximum time a remote client has to send the token and the message, and to read the answer
+const REMOTE_DEADLINE: Duration = Duration::from_secs(5);
+/// Remote clients served at once, the connections beyond are closed right away
+const MAX_REMOTE_CLIENTS: usize = 8;
+/// The capabilities of the messages refused to the remote clients
+const LOCAL_ONLY: &[&str] = &["apply", "screenshot"];
+
+/// Create an IPC socket.
+pub fn listen_on_ipc_socket(socket_path: &Path) -> Result<SocketSource> {
+    // Try to delete the socket if it exists already.
+    if socket_path.exists() {
+        fs::remove_file(socket_path)?;
+    }
+
+    // Spawn unix socket event source.
+    let listener = UnixListener::bind(socket_path)?;
+    let socket = SocketSource::new(listener)?;
+    Ok(socket)
+}
+
+/// Listen for remote clients on `address`, they must send `token` before each message.
+///
+/// Each client is read in its own thread, at most [MAX_REMOTE_CLIENTS] at once, so that a slow
+/// client doesn't block the event loop; only the message received is handled in the event loop.
+pub fn listen_on_remote_socket(
+    address: &RemoteAddress,
+    token: String,
+    handle: &LoopHandle<Wpaperd>,
+    qh: QueueHandle<Wpaperd>,
+) -> Result<()> {
+    // The clients send it as a JSON string, the quotes and the escapes count too
+    ensure!(
+        serde_json::to_vec(&token)?.len() + 4 <= MAX_TOKEN_SIZE as usize,
+        "the token is longer than {MAX_TOKEN_SIZE} bytes"
+    );
+    let (sender, requests) = channel::channel::<RemoteRequest>();
+    handle
+        .insert_source(requests, move |event, _, wpaperd| {
+            if let channel::Event::Msg((message, reply)) = event {
+                let answer = handle_remote_message(message, &qh, wpaperd);
+                if let Err(err) = answer.send(Reply::Remote(reply), wpaperd) {
+                    error!("{err:?}");
+                }
+            }
+        })
+        .map_err(|e| anyhow!("inserting the remote IPC channel in the event loop: {e}"))?;
+    let clients = Arc::new(AtomicUsize::new(0));
+    match address {
+        RemoteAddress::Tcp(address) => {
+            let listener = TcpListener::bind(address)
+                .with_context(|| format!("binding the TCP address {address}"))?;
+            handle.insert_source(
+                SocketSource::new(listener)?,
+                move |stream: TcpStream, _, _| {
+                    spawn_remote_client(stream, &token, &sender, &clients);
+                },
+            )?;
+        }
+        RemoteAddress::Abstract(name) => {
+            let listener = SocketAddr::from_abstract_name(name)
+                .and_then(|address| UnixListener::bind_addr(&address))
+                .with_context(|| format!("binding the abstract socket @{name}"))?;
+            handle.insert_source(
+                SocketSource::new(listener)?,
+                move |stream: UnixStream, _, _| {
+                    spawn_remote_client(stream, &token, &sender, &clients);
+                },
+            )?;
+        }
+    }
+    info!("Listening for remote clients on {address}");
+    Ok(())
+}
+
+/// A message sent by a remote client, with the channel waiting for the answer
+type RemoteRequest = (IpcMessage, mpsc::Sender<Result<IpcResponse, IpcError>>);
+
+/// The sockets accepted from the remote clients
+trait RemoteStream: Read + Write + Send + 'static {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl RemoteStream for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+}
+
+impl RemoteStream for UnixStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_write_timeout(self, timeout)
+    }
+}
+
+/// Read from the stream until the deadline, a client sending a byte at a time can't keep the
+/// connection open longer
+struct DeadlineReader<S> {
+    stream: S,
+    deadline: Instant,
+}
+
+impl<S: RemoteStream> Read for DeadlineReader<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "the client has not sent its message in time",
+            ));
+        }
+        self.stream.set_read_timeout(Some(remaining))?;
+        self.stream.read(buf)
+    }
+}
+
+fn spawn_remote_client<S: RemoteStream>(
+    stream: S,
+    token: &str,
+    sender: &channel::Sender<RemoteRequest>,
+    clients: &Arc<AtomicUsize>,
+) {
+    if clients.fetch_add(1, Ordering::AcqRel) >= MAX_REMOTE_CLIENTS {
+        clients.fetch_sub(1, Ordering::AcqRel);
+        warn!("too many remote IPC clients at once, closing the new connection");
+        return;
+    }
+    let token = token.to_string();
+    let sender = sender.clone();
+    let clients_clone = clients.clone();
+    if let Err(err) = thread::Builder::new()
+        .name("wpaperd-remote".to_string())
+        .spawn(move || {
+            if let Err(err) = serve_remote_client(stream, &token, &sender) {
+                error!("{err:?}");
+            }
+            clients_clone.fetch_sub(1, Ordering::AcqRel);
+        })
+    {
+        clients.fetch_sub(1, Ordering::AcqRel);
+        error!("spawning the thread of a remote IPC client: {err}");
+    }
+}
+
+/// Read the token and the message of a remote client, then write the answer of the event loop
+fn serve_remote_client<S: RemoteStream>(
+    stream: S,
+    token: &str,
+    sender: &channel::Sender<RemoteRequest>,
+) -> Result<()> {
+    let mut reader = DeadlineReader {
+        stream,
+        deadline: Instant::now() + REMOTE_DEADLINE,
+    };
+    let resp = match read_message_with_limit::<_, String>(&mut reader, MAX_TOKEN_SIZE) {
+        Ok(received) if token_matches(token, &received) => match read_message(&mut reader) {
+            Ok(message) => {
+                let (reply, answer) = mpsc::channel();
+                sender
+                    .send((message, reply))
+                    .context("sending the remote IPC message to the event loop")?;
+                answer
+                    .recv()
+                    .context("waiting for the answer of the event loop")?
+            }
+            // The client closed the connection without sending anything
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(err) => {
+                warn!("error while reading message from a remote IPC client: {err}");
+                Err(IpcError::InvalidMessage {
+                    error: err.to_string(),
+                })
+            }
+        },
+        Ok(_) => {
+            warn!("a remote IPC client has sent a wrong token");
+            Err(IpcError::Unauthorized)
+        }
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+        Err(err) => {
+            warn!("error while reading the token from a remote IPC client: {err}");
+            Err(IpcError::Unauthorized)
+        }
+    };
+
+    let stream = reader.stream;
+    stream
+        .set_write_timeout(Some(REMOTE_DEADLINE))
+        .context("setting the timeout of the remote IPC socket")?;
+    write_message(BufWriter::new(stream), &resp)
+        .context("unable to write response to the remote IPC client")?;
+    Ok(())
+}
+
+/// The messages that run the commands of the configuration or write files are only accepted
+/// from the local socket
+fn handle_remote_message(
+    message: IpcMessage,
+    qh: &QueueHandle<Wpaperd>,
+    wpaperd: &mut Wpaperd,
+) -> Answer {
+    match message {
+        IpcMessage::Apply { .. } | IpcMessage::Screenshot { .. } => {
+            Answer::Now(Err(IpcError::LocalOnly))
+        }
+        IpcMessage::Handshake { version } => Answer::Now(
+            handle_ipc_message(IpcMessage::Handshake { version }, qh, wpaperd).map(
+                |resp| match resp {
+                    IpcResponse::Handshake {
+                        version,
+                        capabilities,
+                    } => IpcResponse::Handshake {
+                        version,
+                        capabilities: capabilities
+                            .into_iter()
+                            .filter(|capability| !LOCAL_ONLY.contains(&capability.as_str()))
+                            .collect(),
+                    },
+                    resp => resp,
+                },
+            ),
+        ),
+        message => answer(message, qh, wpaperd),
+    }
+}
+
+/// Compare the tokens in constant time, to not leak how much of it is right
+fn token_matches(expected: &str, received: &str) -> bool {
+    expected.len() == received.len()
+        && expected
+            .bytes()
+            .zip(received.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// The end of a hold lasting `duration`, refused when it is too far to be represented
+fn hold_deadline(duration: Duration) -> Result<Instant, IpcError> {
+    Instant::now()
+        .checked_add(duration)
+        .ok_or_else(|| IpcError::InvalidMessage {
+            error: format!("the duration of {}s is too long", duration.as_secs()),
+        })
+}
+
+fn check_monitors(wpaperd: &Wpaperd, monitors: &Vec<String>) -> Result<(), IpcError> {
+    for monitor in monitors {
+        if !wpaperd
+            .surfaces
+            .iter()
+            .any(|surface| &surface.name() == monitor)
+        {
+            return Err(IpcError::MonitorNotFound {
+                monitor: monitor.to_owned(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+fn collect_surfaces(wpaperd: &mut Wpaperd, monitors: Vec<String>) -> Vec<&mut Surface> {
+    let monitors: HashSet<String> = HashSet::from_iter(monitors);
+    if monitors.is_empty() {
+        return wpaperd.surfaces.iter_mut().collect();
+    };
+
+    wpaperd
+        .surfaces
+        .iter_mut()
+        .filter(|surface| monitors.contains(&surface.name()))
+        .collect()
+}
+
+/// The images the display picks from
+fn display_images(wpaperd: &Wpaperd, monitor: String) -> Result<Vec<PathBuf>, IpcError> {
+    let surface = wpaperd
+        .surfaces
+        .iter()
+        .find(|surface| surface.name() == monitor)
+        .ok_or(IpcError::MonitorNotFound { monitor })?;
+    let path = &surface.wallpaper_info.path;
+    Ok(if path.is_dir() {
+        wpaperd
+            .filelist_cache
+            .borrow()
+            .get(path, surface.wallpaper_info.filelist_options())
+            .to_vec()
+    } else if path.is_file() {
+        vec![path.clone()]
+    } else {
+        // The images come from a source
+        Vec::new()
+    })
+}
+
+/// Handle IPC socket messages.
+pub fn handle_message(
+    ustream: UnixStream,
+    qh: QueueHandle<Wpaperd>,
+    wpaperd: &mut Wpaperd,
+) -> Result<()> {
+    // Do not block the event loop on clients that don't send anything
+    ustream
+        .set_read_timeout(Some(READ_TIMEOUT))
+        .context("setting the timeout of the IPC socket")?;
+
+    let mut reader = BufReader::new(&ustream);
+    let answer = match read_message(&mut reader) {
+        Ok(message) => answer(message, &qh, wpaperd),
+        // The client closed the connection without sending anything
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+        Err(err) => {
+            warn!("error while reading message from IPC: {err}");
+            Answer::Now(Err(IpcError::InvalidMessage {
+                error: err.to_string(),
+            }))
+        }
+    };
+
+    answer.send(Reply::Local(ustream), wpaperd)
+}
+
+/// The answer to a message, computed right away or in the pool of the image loader
+enum Answer {
+    Now(Result<IpcResponse, IpcError>),
+    /// Creates thumbnails, it would block the event loop
+    Later(Box<dyn FnOnce() -> Result<IpcResponse, IpcError> + Send>),
+}
+
+/// Where the answer to a message goes
+enum Reply {
+    Local(UnixStream),
+    /// To the thread of the remote client
+    Remote(mpsc::Sender<Result<IpcResponse, IpcError>>),
+}
+
+impl Reply {
+    fn send(self, resp: Result<IpcResponse, IpcError>) -> Result<()> {
+        match self {
+            Reply::Local(ustream) => write_message(BufWriter::new(ustream), &resp)
+                .context("unable to write response to the IPC client")
+                .suggestion("Probably the client died, try running it again"),
+            Reply::Remote(sender) => {
+                // The client thread might have given up already
+                let _ = sender.send(resp);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Answer {
+    fn send(self, reply: Reply, wpaperd: &Wpaperd) -> Result<()> {
+        match self {
+            Answer::Now(resp) => reply.send(resp),
+            Answer::Later(answer) => {
+                wpaperd.image_loader.borrow().pool().spawn(move || {
+                    if let Err(err) = reply.send(answer()) {
+                        error!("{err:?}");
+                    }
+                });
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The replies are split so that each one fits in [MAX_MESSAGE_SIZE], with room for the rest of
+/// the response
+const PAGE_SIZE: usize = MAX_MESSAGE_SIZE as usize / 2;
+
+/// The items from `offset` whose `size` adds up to [PAGE_SIZE], at least one, and the offset of
+/// the items left
+fn page<T>(items: &[T], offset: usize, size: impl Fn(&T) -> usize) -> (&[T], Option<usize>) {
+    let items = items.get(offset..).unwrap_or_default();
+    let mut total = 0;
+    let count = items
+        .iter()
+        .take_while(|item| {
+            total += size(item);
+            total <= PAGE_SIZE
+        })
+        .count()
+        .max(1)
+        .min(items.len());
+    let next = (count < items.len()).then_some(offset + count);
+    (&items[..count], next)
+}
+
+/// The size of `path` in a JSON message, with its separator
+fn json_size(path: &Path) -> usize {
+    serde_json::to_vec(path).map_or(0, |json| json.len()) + 1
+}
+
+/// Answer the messages creating thumbnails later, the others with [handle_ipc_message]
+fn answer(message: IpcMessage, qh: &QueueHandle<Wpaperd>, wpaperd: &mut Wpaperd) -> Answer {
+    match message {
+        IpcMessage::Thumbnails { size: 0, .. } => Answer::Now(Err(IpcError::InvalidMessage {
+            error: "the size of the thumbnails must be greater than 0".to_string(),
+        })),
+        IpcMessage::Thumbnails {
+            monitor,
+            size,
+            offset,
+        } => {
+            let images = match display_images(wpaperd, monitor) {
+                Ok(images) => images,
+                Err(err) => return Answer::Now(Err(err)),
+            };
+            let thumbnail_size = json_size(&thumbnails_dir(size).join("0123456789abcdef.png"));
+            let (images, next) = page(&images, offset, |image| {
+                json_size(image) + thumbnail_size + 2
+            });
+            let images = images.to_vec();
+            let limits = wpaperd.config.decode_limits;
+            Answer::Later(Box::new(move || {
+                Ok(IpcResponse::Thumbnails {
+                    entries: thumbnails(&images, size, &limits),
+                    next,
+                })
+            }))
+        }
+
+        IpcMessage::PreviewStrip { count: 0, .. } | IpcMessage::PreviewStrip { size: 0, .. } => {
+            Answer::Now(Err(IpcError::InvalidMessage {
+                error: "the count and the size of the preview strip must be greater than 0"
+                    .to_string(),
+            }))
+        }
+        IpcMessage::PreviewStrip {
+            monitor,
+            count,
+            size,
+        } => {
+            let Some(surface) = wpaperd
+                .surfaces
+                .iter()
+                .find(|surface| surface.name() == monitor)
+            else {
+                return Answer::Now(Err(IpcError::MonitorNotFound { monitor }));
+            };
+            let (queued, upcoming) = surface
+                .image_picker
+                .upcoming_images(&surface.wallpaper_info.path);
+            let images: Vec<PathBuf> = queued.into_iter().chain(upcoming).take(count).collect();
+            let limits = wpaperd.config.decode_limits;
+            Answer::Later(Box::new(move || {
+                let (images, thumbnails): (Vec<PathBuf>, Vec<PathBuf>) =
+                    thumbnails(&images, size, &limits).into_iter().unzip();
+                if images.is_empty() {
+                    return Err(IpcError::NoUpcomingImages { monitor });
+                }
+                let path = preview_strip_path(&monitor);
+                save_preview_strip(&thumbnails, size, &path).map_err(|err| {
+                    IpcError::PreviewStripNotSaved {
+                        path: path.clone(),
+                        error: format!("{err:#}"),
+                    }
+                })?;
+                Ok(IpcResponse::PreviewStrip { path, images })
+            }))
+        }
+
+        message => Answer::Now(handle_ipc_message(message, qh, wpaperd)),
+    }
+}
+
+fn handle_ipc_message(
+    message: IpcMessage,
+    qh: &QueueHandle<Wpaperd>,
+    wpaperd: &mut Wpaperd,
+) -> Result<IpcResponse, IpcError> {
+    match message {
+        IpcMessage::Handshake { version } if version != PROTOCOL_VERSION => {
+            Err(IpcError::VersionMismatch {
+                daemon: PROTOCOL_VERSION,
+                client: version,
+            })
+        }
+        IpcMessage::Handshake { version: _ } => Ok(IpcResponse::Handshake {
+            version: PROTOCOL_VERSION,
+            capabilities: CAPABILITIES.iter().map(|c| c.to_string()).collect(),
+        }),
+        IpcMessage::CurrentWallpaper { monitor } => wpaperd
+            .surfaces
+            .iter()
+            .find(|surface| surface.name() == monitor)
+            .map(|surface| surface.image_picker.current_image())
+            .map(|path| IpcResponse::CurrentWallpaper { path })
+            .ok_or(IpcError::MonitorNotFound { monitor }),
+        IpcMessage::CopyPath { monitor, image } => {
+            let surface = match &monitor {
+                Some(monitor) => wpaperd
+                    .surfaces
+                    .iter()
+                    .find(|surface| surface.name() == *monitor)
+                    .ok_or_else(|| IpcError::MonitorNotFound {
+                        monitor: monitor.clone(),
+                    })?,
+                None => wpaperd
+                    .surfaces
+                    .first()
+                    .ok_or_else(|| IpcError::ClipboardUnavailable {
+                        error: "there are no outputs".to_string(),
+                    })?,
+            };
+            let path = surface.image_picker.current_image();
+            if !surface.has_been_drawn() || path.as_os_str().is_empty() {
+                return Err(IpcError::NoWallpaper {
+                    monitor: surface.name(),
+                });
+            }
+            let seat = wpaperd.pointers.seat_state.seats().next().ok_or_else(|| {
+                IpcError::ClipboardUnavailable {
+                    error: "the compositor has no seat".to_string(),
+                }
+            })?;
+            wpaperd
+                .clipboard
+                .copy(&seat, ClipboardContent::new(path.clone(), image), qh)
+                .map_err(|error| IpcError::ClipboardUnavailable { error })?;
+            Ok(IpcResponse::CurrentWallpaper { path })
+        }
+        IpcMessage::AllWallpapers => Ok(IpcResponse::AllWallpapers {
+            entries: wpaperd
+                .surfaces
+                .iter()
+                .map(|surface| {
+                    (
+                        surface.name().to_string(),
+                        surface.image_picker.current_image(),
+                    )
+                })
+                .collect::<Vec<(String, PathBuf)>>(),
+        }),
+
+        IpcMessage::PreviousWallpaper { monitors } => {
+            check_monitors(wpaperd, &monitors).map(|_| {
+                for surface in collect_surfaces(wpaperd, monitors) {
+                    surface.image_picker.previous_image();
+                    surface.request_change();
+                    surface.queue_draw(qh);
+                }
+
+                IpcResponse::Ok
+            })
+        }
+
+        IpcMessage::NextWallpaper { monitors } => check_monitors(wpaperd, &monitors).map(|_| {
+            for surface in collect_surfaces(wpaperd, monitors) {
+                surface
+                    .image_picker
+                    .next_image(&surface.wallpaper_info.path, qh);
+                surface.request_change();
+                surface.queue_draw(qh);
+            }
+
+            IpcResponse::Ok
+        }),
+
+        IpcMessage::ReloadWallpaper { monitors } => check_monitors(wpaperd, &monitors).map(|_| {
+            for surface in collect_surfaces(wpaperd, monitors) {
+                surface.image_picker.reload();
+                surface.request_change();
+                surface.queue_draw(qh);
+            }
+
+            IpcResponse::Ok
+        }),
+
+        IpcMessage::Reload { monitors, scope } => check_monitors(wpaperd, &monitors).map(|_| {
+            if scope == ReloadScope::Config {
+                // Follow the same path as a change of the files, the main loop reads them
+                if let Some(events) = &wpaperd.config.events {
+                    events.send(Event::IpcCommand);
+                }
+                return IpcResponse::Ok;
+            }
+            let filelist_cache = wpaperd.filelist_cache.clone();
+            for surface in collect_surfaces(wpaperd, monitors) {
+                let dir = match &surface.wallpaper_info.source {
+                    Some(Source::Newest { dir, .. }) => dir,
+                    _ => &surface.wallpaper_info.path,
+                };
+                if dir.is_dir() {
+                    filelist_cache.borrow().rescan(dir);
+                }
+                if scope == ReloadScope::Outputs {
+                    surface.image_picker.reload();
+                    surface.request_change();
+                    surface.queue_draw(qh);
+                }
+            }
+
+            IpcResponse::Ok
+        }),
+
+        IpcMessage::PauseWallpaper { monitors } => check_monitors(wpaperd, &monitors).map(|_| {
+            for surface in collect_surfaces(wpaperd, monitors) {
+                surface.pause();
+            }
+            IpcResponse::Ok
+        }),
+
+        IpcMessage::ResumeWallpaper { monitors } => check_monitors(wpaperd, &monitors).map(|_| {
+            for surface in collect_surfaces(wpaperd, monitors) {
+                surface.resume();
+            }
+            IpcResponse::Ok
+        }),
+
+        IpcMessage::HoldWallpaper { monitors, duration } => {
+            check_monitors(wpaperd, &monitors)?;
+            let until = hold_deadline(duration)?;
+            for surface in collect_surfaces(wpaperd, monitors) {
+                surface.hold(until);
+            }
+            Ok(IpcResponse::Ok)
+        }
+
+        IpcMessage::Queue { monitor } => wpaperd
+            .surfaces
+            .iter()
+            .find(|surface| surface.name() == monitor)
+            .map(|surface| {
+                let (queued, upcoming) = surface
+                    .image_picker
+                    .upcoming_images(&surface.wallpaper_info.path);
+                IpcResponse::Queue { queued, upcoming }
+            })
+            .ok_or(IpcError::MonitorNotFound { monitor }),
+
+        IpcMessage::QueueNext { monitor, path } => {
+            if path.is_file() {
+                wpaperd
+                    .surfaces
+                    .iter_mut()
+                    .find(|surface| surface.name() == monitor)
+                    .map(|surface| {
+                        surface.image_picker.queue_next(path);
+                        IpcResponse::Ok
+                    })
+                    .ok_or(IpcError::MonitorNotFound { monitor })
+            } else {
+                Err(IpcError::InvalidPath { path })
+            }
+        }
+
+        IpcMessage::ShowOnce {
+            monitor,
+            path,
+            duration,
+        } => {
+            if path.is_file() {
+                let until = duration.map(hold_deadline).transpose()?;
+                wpaperd
+                    .surfaces
+                    .iter_mut()
+                    .find(|surface| surface.name() == monitor)
+                    .map(|surface| {
+                        surface.image_picker.show_once(path, until.is_some());
+                        surface.request_change();
+                        if let Some(until) = until {
+                            surface.hold(until);
+                        }
+                        surface.queue_draw(qh);
+                        IpcResponse::Ok
+                    })
+                    .ok_or(IpcError::MonitorNotFound { monitor })
+            } else {
+                Err(IpcError::InvalidPath { path })
+            }
+        }
+
+        IpcMessage::QueueRemove { monitor, path } => wpaperd
+            .surfaces
+            .iter_mut()
+            .find(|surface| surface.name() == monitor)
+            .ok_or(IpcError::MonitorNotFound { monitor })
+            .and_then(|surface| {
+                if surface.image_picker.remove_queued(&path) {
+                    Ok(IpcResponse::Ok)
+                } else {
+                    Err(IpcError::NotQueued { path })
+                }
+            }),
+
+        IpcMessage::SetDuration {
+            monitor,
+            duration,
+            persist,
+        } => check_monitors(wpaperd, &vec![monitor.clone()]).and_then(|_| {
+            wpaperd
+                .config
+                .override_duration(&monitor, duration)
+                .map_err(|err| IpcError::InvalidConfig {
+                    error: format!("{err:#}"),
+                })?;
+            if persist {
+                wpaperd
+                    .state
+                    .borrow_mut()
+                    .set_duration(&monitor, Some(duration));
+            }
+            Ok(IpcResponse::Ok)
+        }),
+
+        IpcMessage::SetMode { monitor, mode } => check_monitors(wpaperd, &vec![monitor.clone()])
+            .and_then(|_| {
+                let values =
+                    toml::Table::from_iter([("mode".to_string(), toml::Value::String(mode))]);
+                wpaperd
+                    .config
+                    .add_override(&monitor, values)
+                    .map_err(|err| IpcError::InvalidConfig {
+                        error: format!("{err:#}"),
+                    })?;
+                Ok(IpcResponse::Ok)
+            }),
+
+        IpcMessage::Apply { config } => toml::from_str::<toml::Table>(&config)
+            .map_err(|err| IpcError::InvalidConfig {
+                error: err.to_string(),
+            })
+            .and_then(|sections| {
+                wpaperd
+                    .config
+                    .add_overrides(sections)
+                    .map_err(|err| IpcError::InvalidConfig {
+                        error: format!("{err:#}"),
+                    })
+            })
+            .map(|_| IpcResponse::Ok),
+
+        IpcMessage::ReduceMotion { enabled } => Ok(IpcResponse::ReduceMotion {
+            enabled: wpaperd.config.set_reduce_motion(enabled),
+        }),
+
+        IpcMessage::HighContrast { enabled } => Ok(IpcResponse::HighContrast {
+            enabled: wpaperd.config.set_high_contrast(enabled),
+        }),
+
+        IpcMessage::Zen { enabled } => Ok(IpcResponse::Zen {
+            enabled: wpaperd.config.set_zen(enabled),
+        }),
+
+        IpcMessage::Inhibit { name, timeout } => {
+            wpaperd.inhibitors.add(name, timeout);
+            Ok(IpcResponse::Inhibitors {
+                inhibitors: wpaperd.inhibitors.list(),
+            })
+        }
+
+        IpcMessage::Uninhibit { name } => {
+            if wpaperd.inhibitors.remove(name.as_deref()) || name.is_none() {
+                Ok(IpcResponse::Inhibitors {
+                    inhibitors: wpaperd.inhibitors.list(),
+                })
+            } else {
+                Err(IpcError::InhibitorNotFound {
+                    name: name.unwrap_or_default(),
+                })
+            }
+        }
+
+        IpcMessage::Backend { renderer, device } => {
+            if let Some(renderer) = renderer {
+                let renderer = renderer
+                    .parse::<RendererKind>()
+                    .map_err(|error| IpcError::InvalidConfig { error })?;
+                let backend = Backend {
+                    renderer: Some(renderer),
+                    device,
+                };
+                let previous = wpaperd.config.set_backend(Some(backend));
+                if *wpaperd.config.backend() != *wpaperd.backend() {
+                    if let Err(err) = wpaperd.switch_backend(qh) {
+                        wpaperd.config.set_backend(previous);
+                        return Err(IpcError::InvalidConfig {
+                            error: format!("{err:#}"),
+                        });
+                    }
+                }
+            }
+            let backend = wpaperd.backend();
+            Ok(IpcResponse::Backend {
+                renderer: backend.renderer.unwrap_or(RendererKind::Gpu).to_string(),
+                device: backend.device.clone(),
+                driver: wpaperd.driver().map(str::to_string),
+            })
+        }
+
+        IpcMessage::Animation { monitors, action } => {
+            check_monitors(wpaperd, &monitors)?;
+            // Only the monitors passed must be showing an animation
+            let all = monitors.is_empty();
+            let mut animations = Vec::new();
+            for surface in collect_surfaces(wpaperd, monitors) {
+                let Some(status) = surface.animation_status() else {
+                    if all {
+                        continue;
+                    }
+                    return Err(IpcError::NoAnimation {
+                        monitor: surface.name(),
+                    });
+                };
+                match action {
+                    AnimationAction::Pause | AnimationAction::Resume => {
+                        surface.pause_animation(action == AnimationAction::Pause, qh);
+                    }
+                    AnimationAction::Seek { frame } => match surface.seek_animation(frame, qh) {
+                        Ok(true) => {}
+                        Ok(false) => {
+                            return Err(IpcError::FrameNotFound {
+                                monitor: surface.name(),
+                                frames: status.frames,
+                            })
+                        }
+                        Err(err) => {
+                            return Err(IpcError::DrawErrors(vec![(
+                                surface.name(),
+                                format!("{err:#}"),
+                            )]))
+                        }
+                    },
+                }
+                animations.extend(surface.animation_status());
+            }
+            Ok(IpcResponse::Animations { animations })
+        }
+
+        IpcMessage::Trace { monitors, after } => {
+            check_monitors(wpaperd, &monitors)?;
+            let mut tracer = wpaperd.tracer.borrow_mut();
+            let started = tracer.start();
+            Ok(IpcResponse::Trace {
+                events: tracer.events(&monitors, after),
+                started,
+            })
+        }
+
+        IpcMessage::Handoff => {
+            info!("Replaced by a new instance of wpaperd, shutting down");
+            wpaperd.replaced = true;
+            wpaperd.should_exit = true;
+            Ok(IpcResponse::Ok)
+        }
+
+        IpcMessage::Stats => Ok(IpcResponse::Stats(wpaperd.metrics.borrow().stats())),
+
+        IpcMessage::Status => Ok(IpcResponse::Status {
+            outputs: wpaperd
+                .surfaces
+                .iter()
+                .map(|surface| surface.runtime_state())
+                .collect(),
+        }),
+
+        IpcMessage::Crop { monitor, region } => wpaperd
+            .surfaces
+            .iter()
+            .find(|surface| surface.name() == monitor)
+            .ok_or(IpcError::MonitorNotFound {
+                monitor: monitor.clone(),
+            })
+            .map(|surface| surface.image_picker.current_image())
+            .and_then(|image| {
+                if image.as_os_str().is_empty() || !image.is_file() {
+                    return Err(IpcError::NoWallpaper { monitor });
+                }
+                let crop = region.map(|region| Crop {
+                    x: region.x,
+                    y: region.y,
+                    width: region.width,
+                    height: region.height,
+                });
+                Sidecar::save_crop(&image, crop).map_err(|err| IpcError::SidecarNotSaved {
+                    path: image.clone(),
+                    error: format!("{err:#}"),
+                })?;
+                // The crop applies to all the displays showing the image
+                for surface in wpaperd
+                    .surfaces
+                    .iter_mut()
+                    .filter(|surface| surface.image_picker.current_image() == image)
+                {
+                    surface.image_picker.reload();
+                    surface.queue_draw(qh);
+                }
+                Ok(IpcResponse::Ok)
+            }),
+
+        // Answered in the pool of the image loader
+        IpcMessage::Thumbnails { .. } | IpcMessage::PreviewStrip { .. } => {
+            unreachable!("answered by answer()")
+        }
+
+        IpcMessage::Screenshot { monitor, path } => {
+            let surface = wpaperd
+                .surfaces
+                .iter_mut()
+                .find(|surface| surface.name() == monitor)
+                .ok_or_else(|| IpcError::MonitorNotFound {
+                    monitor: monitor.clone(),
+                })?;
+            if !surface.has_been_drawn() {
+                return Err(IpcError::NoWallpaper { monitor });
+            }
+            let screenshot = surface
+                .screenshot()
+                .and_then(|image| {
+                    image
+                        .save_with_format(&path, ImageFormat::Png)
+                        .with_context(|| format!("saving {path:?}"))?;
+                    Ok(image)
+                })
+                .map_err(|err| IpcError::ScreenshotFailed {
+                    monitor,
+                    error: format!("{err:#}"),
+                })?;
+            Ok(IpcResponse::Screenshot {
+                path,
+                width: screenshot.width(),
+                height: screenshot.height(),
+            })
+        }
+
+        IpcMessage::Images { monitor, offset } => {
+            let images = display_images(wpaperd, monitor)?;
+            let (paths, next) = page(&images, offset, |image| json_size(image));
+            Ok(IpcResponse::Images {
+                paths: paths.to_vec(),
+                next,
+            })
+        }
+
+        IpcMessage::TextContrast { monitor } => {
+            let surface = wpaperd
+                .surfaces
+                .iter()
+                .find(|surface| surface.name() == monitor)
+                .ok_or(IpcError::MonitorNotFound {
+                    monitor: monitor.clone(),
+                })?;
+            if surface.wallpaper_info.text_region.is_none() {
+                return Err(IpcError::NoTextRegion { monitor });
+            }
+            surface
+                .text_contrast
+                .clone()
+                .map(IpcResponse::TextContrast)
+                .ok_or(IpcError::NoWallpaper { monitor })
+        }
+
+        IpcMessage::Reset => {
+            wpaperd.config.reset_overrides();
+            wpaperd.state.borrow_mut().clear_durations();
+            Ok(IpcResponse::Ok)
+        }
+
+        IpcMessage::DumpConfig => wpaperd
+            .config
+            .dump()
+            .map(|config| IpcResponse::Config { config })
+            .map_err(|err| IpcError::InvalidConfig {
+                error: format!("{err:#}"),
+            }),
+
+        IpcMessage::ListOutputs => Ok(IpcResponse::Outputs {
+            outputs: wpaperd
+                .surfaces
+                .iter()
+                .map(|surface| {
+                    let info = surface.display_info();
+                    let current_image = surface.image_picker.current_image();
+                    OutputStatus {
+                        name: info.name.clone(),
+                        description: info.description.clone(),
+                        width: info.scaled_width(),
+                        height: info.scaled_height(),
+                        scale: info.scale,
+                        transform: info.transform_name().to_string(),
+                        section: wpaperd
+                            .config
+                            .matched_section(&info.name)
+                            .map(|section| section.to_string()),
+                        wallpaper: (surface.has_been_drawn()
+                            && !current_image.as_os_str().is_empty())
+                        .then_some(current_image),
+                    }
+                })
+                .collect(),
+        }),
+
+        IpcMessage::ConfigErrors => Ok(IpcResponse::ConfigErrors {
+            errors: wpaperd.config.errors().to_vec(),
+        }),
+
+        IpcMessage::ConfigPath => Ok(IpcResponse::ConfigPath {
+            path: wpaperd.config.path.clone(),
+            exists: wpaperd.config.path.exists(),
+            system_path: wpaperd.config.options.system_path.clone(),
+            defaults_file: wpaperd.config.defaults_file().cloned(),
+        }),
+
+        IpcMessage::PrintMatches => {
+            let config = &wpaperd.config;
+            let outputs: Vec<String> = wpaperd
+                .surfaces
+                .iter()
+                .map(|surface| surface.name())
+                .collect();
+            let matches = outputs
+                .iter()
+                .map(|output| OutputMatch {
+                    output: output.clone(),
+                    sections: config
+                        .sections_chain(output)
+                        .into_iter()
+                        .map(|section| section.to_string())
+                        .collect(),
+                    runtime_changes: config.has_overrides(output),
+                    error: config
+                        .get_output_by_name(output)
+                        .err()
+                        .map(|err| format!("{err:#}")),
+                })
+                .collect();
+            let mut unmatched_sections: Vec<String> = config
+                .section_names()
+                .filter(|section| {
+                    !outputs
+                        .iter()
+                        .any(|output| config.sections_chain(output).contains(&section.as_str()))
+                })
+                .cloned()
+                .collect();
+            unmatched_sections.sort_unstable();
+            Ok(IpcResponse::Matches {
+                matches,
+                unmatched_sections,
+            })
+        }
+
+        IpcMessage::TogglePauseWallpaper { monitors } => {
+            check_monitors(wpaperd, &monitors).map(|_| {
+                for surface in collect_surfaces(wpaperd, monitors) {
+                    surface.toggle_pause();
+                }
+
+                IpcResponse::Ok
+            })
+        }
+    }
+}
@@ -0,0 +1,75 @@
+//! Core of wpaperd: configuration parsing, image picking, surface management and rendering.
+//!
+//! The `wpaperd` binary is a thin wrapper around this crate, that takes care of the command
+//! line, the logging and the event loop.
+
+extern crate khronos_egl as egl;
+
+pub mod animation;
+#[cfg(feature = "audio")]
+pub mod audio;
+pub mod backend;
+pub mod bench;
+pub mod brightness;
+pub mod calendar;
+pub mod capabilities;
+pub mod child;
+pub mod clipboard;
+pub mod cold_start;
+pub mod color_scheme;
+pub mod color_space;
+pub mod compositor;
+pub mod config;
+pub mod conflicts;
+pub mod cron;
+pub mod decode_queue;
+pub mod diagnostic;
+pub mod display_info;
+pub mod dither;
+pub mod events;
+pub mod exif;
+pub mod filelist_cache;
+pub mod filter;
+pub mod fullscreen;
+pub mod handoff;
+#[cfg(feature = "heif")]
+pub mod heif;
+pub mod hooks;
+pub mod image_loader;
+pub mod image_metadata;
+pub mod image_picker;
+pub mod inhibit;
+pub mod ipc_server;
+pub mod lock_image;
+pub mod metrics;
+pub mod migrate;
+pub mod parallax;
+pub mod quiet_hours;
+pub mod render;
+pub mod runtime_state;
+pub mod sandbox;
+pub mod script;
+pub mod setup;
+pub mod sidecar;
+pub mod socket;
+pub mod source;
+pub mod stable_hash;
+pub mod state;
+pub mod surface;
+pub mod sync_clock;
+pub mod text_region;
+pub mod theme;
+pub mod thumbnails;
+pub mod tone_mapping;
+pub mod trace;
+pub mod wall_clock;
+pub mod wallpaper_groups;
+pub mod wallpaper_info;
+pub mod watcher;
+pub mod weather;
+pub mod worker;
+pub mod workspaces;
+pub mod wpaperd;
+pub mod xmp;
+
+pub use crate::wpaperd::Wpaperd;
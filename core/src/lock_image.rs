@@ -0,0 +1,157 @@
+//! Blurred and dimmed copies of the wallpapers, for the screen lockers.
+//!
+//! Each time a display with `lock-image` set changes its wallpaper, a copy at the resolution of
+//! the display is saved into XDG_CACHE_HOME/wpaperd/lock and the symlink `<display>.png` in the
+//! same directory is moved to it, so that swaylock or hyprlock can always use the same path.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use color_eyre::{eyre::Context, Result};
+use image::{
+    imageops::{self, FilterType},
+    ImageFormat, RgbaImage,
+};
+use log::warn;
+use serde::Deserialize;
+
+use crate::worker::LatestWorker;
+
+/// The blur is applied on a smaller copy of the image, it is much faster and looks the same
+const DOWNSCALE: u32 = 4;
+
+/// Creates the images of each display one at a time, they would replace the same symlink
+static WORKER: LatestWorker<String> = LatestWorker::new();
+
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LockImage {
+    /// Standard deviation of the gaussian blur, in pixels of the display
+    pub blur: f32,
+    /// How much darker the image is, from 0 to 1
+    pub dim: f32,
+}
+
+impl Default for LockImage {
+    fn default() -> Self {
+        Self {
+            blur: 20.0,
+            dim: 0.4,
+        }
+    }
+}
+
+/// The directory containing the images and their symlinks
+pub fn lock_images_dir() -> PathBuf {
+    dirs::cache_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("wpaperd")
+        .join("lock")
+}
+
+/// The symlink always pointing to the last image created for `display`
+pub fn lock_image_path(display: &str) -> PathBuf {
+    lock_images_dir().join(format!("{display}.png"))
+}
+
+impl LockImage {
+    /// Create the copy of `image` for `display`, whose size is `width`x`height` pixels, in another
+    /// thread as blurring and encoding the image takes a while. Only the last wallpaper of
+    /// `display` is used when it changed again while an image was being created
+    pub fn generate(self, image: RgbaImage, display: String, width: u32, height: u32) {
+        WORKER.submit(display.clone(), move || {
+            let data = self.apply(&image, width, height);
+            if let Err(err) = save(&data, &display) {
+                warn!("{err:?}");
+            }
+        });
+    }
+
+    /// Scale `image` to fill `width`x`height`, cropping its center, then blur and dim it
+    pub fn apply(&self, image: &RgbaImage, width: u32, height: u32) -> RgbaImage {
+        let small_width = (width / DOWNSCALE).max(1);
+        let small_height = (height / DOWNSCALE).max(1);
+        let scale = f64::max(
+            small_width as f64 / image.width() as f64,
+            small_height as f64 / image.height() as f64,
+        );
+        let scaled_width = ((image.width() as f64 * scale).ceil() as u32).max(small_width);
+        let scaled_height = ((image.height() as f64 * scale).ceil() as u32).max(small_height);
+        let scaled = imageops::resize(image, scaled_width, scaled_height, FilterType::Triangle);
+        let mut small = imageops::crop_imm(
+            &scaled,
+            (scaled_width - small_width) / 2,
+            (scaled_height - small_height) / 2,
+            small_width,
+            small_height,
+        )
+        .to_image();
+        if self.blur > 0.0 {
+            small = imageops::blur(&small, self.blur / DOWNSCALE as f32);
+        }
+        for pixel in small.pixels_mut() {
+            for channel in &mut pixel.0[..3] {
+                *channel = (*channel as f32 * (1.0 - self.dim)).round() as u8;
+            }
+        }
+        imageops::resize(&small, width, height, FilterType::Triangle)
+    }
+}
+
+/// Save the image under a new name, then replace the symlink of `display` and remove the image
+/// it pointed to. The lockers reading the symlink never see a partially written image
+fn save(data: &RgbaImage, display: &str) -> Result<()> {
+    let dir = lock_images_dir();
+    fs::create_dir_all(&dir).with_context(|| format!("creating directory {dir:?}"))?;
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let name = format!("{display}-{millis}.png");
+    let image = dir.join(&name);
+    data.save_with_format(&image, ImageFormat::Png)
+        .with_context(|| format!("saving lock image {image:?}"))?;
+
+    let symlink = lock_image_path(display);
+    let previous = fs::read_link(&symlink).ok();
+    let tmp = symlink.with_extension("png.tmp");
+    let _ = fs::remove_file(&tmp);
+    std::os::unix::fs::symlink(&name, &tmp)
+        .and_then(|_| fs::rename(&tmp, &symlink))
+        .with_context(|| format!("updating symlink {symlink:?}"))?;
+    if let Some(previous) = previous.filter(|previous| *previous != Path::new(&name)) {
+        let _ = fs::remove_file(dir.join(previous));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        // A wide image, half white and half black
+        let image = RgbaImage::from_fn(400, 100, |x, _| {
+            if x < 200 {
+                Rgba([255, 255, 255, 255])
+            } else {
+                Rgba([0, 0, 0, 255])
+            }
+        });
+        let lock_image = LockImage {
+            blur: 0.0,
+            dim: 0.5,
+        };
+        // The center of the image fills the display
+        let data = lock_image.apply(&image, 80, 80);
+        assert_eq!(data.dimensions(), (80, 80));
+        assert_eq!(data.get_pixel(0, 40), &Rgba([128, 128, 128, 255]));
+        assert_eq!(data.get_pixel(79, 40), &Rgba([0, 0, 0, 255]));
+    }
+}
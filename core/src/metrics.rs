@@ -0,0 +1,126 @@
+//! Counters about the work done by wpaperd, returned by `wpaperctl stats`.
+
+use std::{
+    fs,
+    time::{Duration, Instant},
+};
+
+use wpaperd_ipc::{DecodeTimeBucket, Stats};
+
+/// Upper bounds of the buckets of the decode time histogram, in milliseconds
+const DECODE_TIME_BUCKETS: [u64; 8] = [10, 25, 50, 100, 250, 500, 1000, 2500];
+
+pub struct Metrics {
+    started: Instant,
+    rotations: u64,
+    /// The number of images decoded in each bucket, the last one has no upper bound
+    decode_times: [u64; DECODE_TIME_BUCKETS.len() + 1],
+    decode_time_total: Duration,
+    cache_hits: u64,
+    cache_misses: u64,
+    errors: u64,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            rotations: 0,
+            decode_times: [0; DECODE_TIME_BUCKETS.len() + 1],
+            decode_time_total: Duration::ZERO,
+            cache_hits: 0,
+            cache_misses: 0,
+            errors: 0,
+        }
+    }
+}
+
+impl Metrics {
+    /// A new wallpaper has been shown on a display
+    pub fn rotation(&mut self) {
+        self.rotations += 1;
+    }
+
+    /// An image has been decoded by the image loader
+    pub fn decoded(&mut self, time: Duration) {
+        let millis = time.as_millis();
+        let bucket = DECODE_TIME_BUCKETS
+            .iter()
+            .position(|bound| millis <= *bound as u128)
+            .unwrap_or(DECODE_TIME_BUCKETS.len());
+        self.decode_times[bucket] += 1;
+        self.decode_time_total += time;
+    }
+
+    /// The image loader has returned an image `hit` already decoded for another display,
+    /// otherwise it has started decoding it
+    pub fn cache_access(&mut self, hit: bool) {
+        if hit {
+            self.cache_hits += 1;
+        } else {
+            self.cache_misses += 1;
+        }
+    }
+
+    /// An image could not be loaded or a display could not be drawn
+    pub fn error(&mut self) {
+        self.errors += 1;
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            uptime_secs: self.started.elapsed().as_secs(),
+            rotations: self.rotations,
+            decode_times: DECODE_TIME_BUCKETS
+                .iter()
+                .map(|bound| Some(*bound))
+                .chain([None])
+                .zip(self.decode_times)
+                .map(|(max_ms, count)| DecodeTimeBucket { max_ms, count })
+                .collect(),
+            decode_time_total_ms: self.decode_time_total.as_millis() as u64,
+            cache_hits: self.cache_hits,
+            cache_misses: self.cache_misses,
+            memory_bytes: resident_memory(),
+            errors: self.errors,
+        }
+    }
+}
+
+/// The memory used by wpaperd in bytes, read from /proc
+fn resident_memory() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let kilobytes = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kilobytes * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_time_histogram() {
+        let mut metrics = Metrics::default();
+        metrics.decoded(Duration::from_millis(5));
+        metrics.decoded(Duration::from_millis(10));
+        metrics.decoded(Duration::from_millis(300));
+        metrics.decoded(Duration::from_secs(10));
+
+        let stats = metrics.stats();
+        let counts: Vec<u64> = stats
+            .decode_times
+            .iter()
+            .map(|bucket| bucket.count)
+            .collect();
+        assert_eq!(counts, [2, 0, 0, 0, 0, 1, 0, 0, 1]);
+        assert_eq!(stats.decode_times.last().unwrap().max_ms, None);
+        assert_eq!(stats.decode_time_total_ms, 10315);
+    }
+}
@@ -0,0 +1,229 @@
+//! `wpaperd migrate-config`: rewrite the keys renamed since an older version of wpaperd.
+//!
+//! The configuration is rejected when it contains an unknown key, so the users upgrading from a
+//! version using the old names would be left with the previous configuration until they find out
+//! what changed. The keys in [RENAMED_KEYS] are renamed in each section and in each set, keeping
+//! their values, their comments and the formatting of the rest of the file.
+
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::{
+    eyre::{anyhow, Context},
+    owo_colors::OwoColorize,
+    Result, Section,
+};
+use toml_edit::{DocumentMut, Item, Key, Table};
+
+use crate::config::is_json;
+
+/// The old and the new name of the keys renamed in the sections, renamed in 1.1
+pub const RENAMED_KEYS: &[(&str, &str)] = &[
+    ("transition_time", "transition-time"),
+    ("queue_size", "queue-size"),
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Change {
+    Renamed {
+        section: String,
+        from: &'static str,
+        to: &'static str,
+    },
+    /// Both the old and the new key are set, the old one is left for the user to remove
+    Conflict {
+        section: String,
+        from: &'static str,
+        to: &'static str,
+    },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Renamed { section, from, to } => write!(
+                f,
+                "{}: renamed {} to {}",
+                section.bold().magenta(),
+                from.bold().italic().blue(),
+                to.bold().italic().blue()
+            ),
+            Change::Conflict { section, from, to } => write!(
+                f,
+                "{}: {} is also set as {}, remove one of them",
+                section.bold().magenta(),
+                from.bold().italic().blue(),
+                to.bold().italic().blue()
+            ),
+        }
+    }
+}
+
+/// Rename the keys of `document`, return what has been changed
+pub fn migrate(document: &mut DocumentMut) -> Vec<Change> {
+    let mut changes = Vec::new();
+    for (name, item) in document.iter_mut() {
+        let Some(table) = item.as_table_mut() else {
+            continue;
+        };
+        if name.get() == "set" {
+            for (set, item) in table.iter_mut() {
+                if let Some(table) = item.as_table_mut() {
+                    migrate_table(&format!("set.{}", set.get()), table, &mut changes);
+                }
+            }
+        } else {
+            migrate_table(name.get(), table, &mut changes);
+        }
+    }
+    changes
+}
+
+fn migrate_table(section: &str, table: &mut Table, changes: &mut Vec<Change>) {
+    for (from, to) in RENAMED_KEYS {
+        if !table.contains_key(from) {
+            continue;
+        }
+        if table.contains_key(to) {
+            changes.push(Change::Conflict {
+                section: section.to_string(),
+                from,
+                to,
+            });
+            continue;
+        }
+        rename_key(table, from, to);
+        changes.push(Change::Renamed {
+            section: section.to_string(),
+            from,
+            to,
+        });
+    }
+}
+
+/// Rename a key keeping its position and its comments, the table doesn't allow it
+fn rename_key(table: &mut Table, from: &str, to: &str) {
+    let keys: Vec<String> = table.iter().map(|(key, _)| key.to_string()).collect();
+    for name in keys {
+        let (key, item): (Key, Item) = table
+            .remove_entry(&name)
+            .expect("the key to be in the table");
+        let key = if name == from {
+            Key::new(to)
+                .with_leaf_decor(key.leaf_decor().clone())
+                .with_dotted_decor(key.dotted_decor().clone())
+        } else {
+            key
+        };
+        table.insert_formatted(&key, item);
+    }
+}
+
+/// Migrate `config_file` in place, saving the previous version next to it with the `.bak`
+/// extension. Nothing is written when `dry_run` is true or when there is nothing to change
+pub fn migrate_file(config_file: &Path, dry_run: bool) -> Result<Vec<Change>> {
+    if is_json(config_file) {
+        return Err(anyhow!(
+            "only the TOML configurations can be migrated, {config_file:?} is in JSON"
+        ))
+        .suggestion("Rename the keys in the program generating it");
+    }
+    let content = fs::read_to_string(config_file)
+        .with_context(|| format!("reading configuration file {config_file:?}"))?;
+    let mut document: DocumentMut = content
+        .parse()
+        .with_context(|| format!("parsing configuration file {config_file:?}"))
+        .suggestion("Fix the syntax of the file before migrating it")?;
+    let changes = migrate(&mut document);
+    let renamed = changes
+        .iter()
+        .any(|change| matches!(change, Change::Renamed { .. }));
+    if dry_run || !renamed {
+        return Ok(changes);
+    }
+
+    let backup = backup_path(config_file);
+    fs::copy(config_file, &backup)
+        .with_context(|| format!("saving the configuration into {backup:?}"))?;
+    let tmp = backup.with_extension("tmp");
+    let res = fs::write(&tmp, document.to_string()).and_then(|_| fs::rename(&tmp, config_file));
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    res.with_context(|| format!("writing configuration file {config_file:?}"))
+        .with_suggestion(|| format!("The previous configuration is still in {backup:?}"))?;
+    Ok(changes)
+}
+
+/// `config.toml.bak`, next to the configuration
+pub fn backup_path(config_file: &Path) -> PathBuf {
+    let mut backup = config_file.as_os_str().to_owned();
+    backup.push(".bak");
+    PathBuf::from(backup)
+}
+
+/// Return an error when the file doesn't exist, instead of creating it
+pub fn ensure_exists(config_file: &Path) -> Result<()> {
+    if config_file.exists() {
+        Ok(())
+    } else {
+        Err(anyhow!("configuration file {config_file:?} not found"))
+            .suggestion("Pass the configuration to migrate with --config")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrate() {
+        let mut document: DocumentMut = r#"
+[default]
+path = "~/Pictures"
+# Seconds
+transition_time = 600 # slow
+queue_size = 5
+
+[set.dark]
+queue_size = 3
+
+[DP-1]
+transition_time = 100
+transition-time = 200
+"#
+        .parse()
+        .unwrap();
+        let changes = migrate(&mut document);
+        assert_eq!(
+            document.to_string(),
+            r#"
+[default]
+path = "~/Pictures"
+# Seconds
+transition-time = 600 # slow
+queue-size = 5
+
+[set.dark]
+queue-size = 3
+
+[DP-1]
+transition_time = 100
+transition-time = 200
+"#
+        );
+        assert_eq!(changes.len(), 4);
+        assert!(changes.contains(&Change::Renamed {
+            section: "set.dark".to_string(),
+            from: "queue_size",
+            to: "queue-size",
+        }));
+        assert!(changes.contains(&Change::Conflict {
+            section: "DP-1".to_string(),
+            from: "transition_time",
+            to: "transition-time",
+        }));
+    }
+}
@@ -0,0 +1,165 @@
+//! Shift the wallpaper slightly towards the pointer, on the displays with `parallax` set.
+//!
+//! The compositor sends the position of the pointer only while it is over the wallpaper itself,
+//! i.e. over an empty area of the desktop. The layer surfaces of the other displays have an empty
+//! input region, so the pointer passes through them; on the ones with `parallax` set the input
+//! region covers the whole display, and wpaperd sets the default cursor when the pointer enters.
+//!
+//! The image is zoomed in by twice `parallax`, a fraction of the display, so that it can be
+//! shifted by up to `parallax` on each side without showing its borders. When the pointer leaves,
+//! the wallpaper stays where it was. `reduce-motion` disables it.
+
+use log::warn;
+use smithay_client_toolkit::{
+    reexports::client::{
+        globals::GlobalList,
+        protocol::{wl_pointer::WlPointer, wl_seat::WlSeat},
+        Connection, Proxy, QueueHandle,
+    },
+    seat::{
+        pointer::{
+            CursorIcon, PointerData, PointerEvent, PointerEventKind, PointerHandler, ThemeSpec,
+            ThemedPointer,
+        },
+        Capability, SeatHandler, SeatState,
+    },
+};
+
+use crate::Wpaperd;
+
+/// The pointers of all the seats
+pub struct PointerTracker {
+    pub seat_state: SeatState,
+    pointers: Vec<ThemedPointer>,
+}
+
+impl PointerTracker {
+    pub fn new(globals: &GlobalList, qh: &QueueHandle<Wpaperd>) -> Self {
+        Self {
+            seat_state: SeatState::new(globals, qh),
+            pointers: Vec::new(),
+        }
+    }
+}
+
+/// How much of the display the image is shifted by with the pointer at `position`, relative to
+/// the size of the display; zero when the pointer is in the middle
+pub fn offset(position: (f64, f64), size: (i32, i32), parallax: f32) -> [f32; 2] {
+    let relative = |position: f64, size: i32| {
+        if size <= 0 {
+            return 0.0;
+        }
+        ((position / size as f64) as f32 * 2.0 - 1.0).clamp(-1.0, 1.0)
+    };
+    [
+        relative(position.0, size.0) * parallax,
+        relative(position.1, size.1) * parallax,
+    ]
+}
+
+impl SeatHandler for Wpaperd {
+    fn seat_state(&mut self) -> &mut SeatState {
+        &mut self.pointers.seat_state
+    }
+
+    fn new_seat(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _seat: WlSeat) {}
+
+    fn new_capability(
+        &mut self,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+        seat: WlSeat,
+        capability: Capability,
+    ) {
+        if capability != Capability::Pointer {
+            return;
+        }
+        let surface = self.compositor_state.create_surface(qh);
+        match self.pointers.seat_state.get_pointer_with_theme(
+            qh,
+            &seat,
+            self.shm_state.wl_shm(),
+            surface,
+            ThemeSpec::default(),
+        ) {
+            Ok(pointer) => self.pointers.pointers.push(pointer),
+            Err(err) => warn!("getting the pointer of the seat: {err}"),
+        }
+    }
+
+    fn remove_capability(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        seat: WlSeat,
+        capability: Capability,
+    ) {
+        if capability != Capability::Pointer {
+            return;
+        }
+        self.pointers.pointers.retain(|pointer| {
+            let removed = pointer
+                .pointer()
+                .data::<PointerData>()
+                .is_some_and(|data| *data.seat() == seat);
+            if removed && pointer.pointer().version() >= 3 {
+                pointer.pointer().release();
+            }
+            !removed
+        });
+    }
+
+    fn remove_seat(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _seat: WlSeat) {}
+}
+
+impl PointerHandler for Wpaperd {
+    fn pointer_frame(
+        &mut self,
+        conn: &Connection,
+        qh: &QueueHandle<Self>,
+        pointer: &WlPointer,
+        events: &[PointerEvent],
+    ) {
+        for event in events {
+            let Some(surface) = self
+                .surfaces
+                .iter_mut()
+                .find(|surface| *surface.wl_surface() == event.surface)
+            else {
+                continue;
+            };
+            match event.kind {
+                PointerEventKind::Enter { .. } => {
+                    // The input region covers the display, the cursor is ours to set
+                    let themed = self
+                        .pointers
+                        .pointers
+                        .iter()
+                        .find(|themed| themed.pointer() == pointer);
+                    if let Some(Err(err)) =
+                        themed.map(|themed| themed.set_cursor(conn, CursorIcon::Default))
+                    {
+                        warn!("setting the cursor: {err}");
+                    }
+                    surface.move_pointer(event.position, qh);
+                }
+                PointerEventKind::Motion { .. } => surface.move_pointer(event.position, qh),
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offset() {
+        assert_eq!(offset((960.0, 540.0), (1920, 1080), 0.02), [0.0, 0.0]);
+        assert_eq!(offset((0.0, 1080.0), (1920, 1080), 0.02), [-0.02, 0.02]);
+        // Outside of the display while it is being resized
+        assert_eq!(offset((3840.0, 0.0), (1920, 1080), 0.02), [0.02, -0.02]);
+        assert_eq!(offset((10.0, 10.0), (0, 0), 0.02), [0.0, 0.0]);
+    }
+}
@@ -0,0 +1,79 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Local, NaiveTime};
+use serde::{de::Error, Deserialize, Deserializer};
+
+/// A period of the day during which the wallpapers are not changed automatically, i.e. from
+/// `22:00` to `07:00`. The images can still be changed by using wpaperctl.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QuietHours {
+    #[serde(deserialize_with = "deserialize_time")]
+    pub start: NaiveTime,
+    #[serde(deserialize_with = "deserialize_time")]
+    pub end: NaiveTime,
+}
+
+pub(crate) fn deserialize_time<'de, D>(deserializer: D) -> Result<NaiveTime, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let time = String::deserialize(deserializer)?;
+    NaiveTime::parse_from_str(&time, "%H:%M")
+        .map_err(|err| D::Error::custom(format!("invalid time {time:?}, expected HH:MM: {err}")))
+}
+
+impl QuietHours {
+    /// True if `time` is inside the quiet hours, `end` is excluded
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            // The quiet hours span midnight
+            time >= self.start || time < self.end
+        }
+    }
+
+    /// Time left until the quiet hours start or end
+    pub fn until_change(&self, now: DateTime<Local>) -> Duration {
+        [self.start, self.end]
+            .into_iter()
+            .filter_map(|time| {
+                let date = if time > now.time() {
+                    now.date_naive()
+                } else {
+                    now.date_naive().succ_opt()?
+                };
+                date.and_time(time).and_local_timezone(Local).earliest()
+            })
+            .filter_map(|change| change.signed_duration_since(now).to_std().ok())
+            .min()
+            // Check again in one hour if the time doesn't exist, i.e. daylight saving time
+            .unwrap_or(Duration::from_secs(3600))
+            // Make sure that the change has happened when the timer fires
+            + Duration::from_secs(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contains() {
+        let quiet_hours: QuietHours = toml::from_str(
+            r#"
+            start = "22:00"
+            end = "07:00"
+            "#,
+        )
+        .unwrap();
+        let time = |hour, minute| NaiveTime::from_hms_opt(hour, minute, 0).unwrap();
+        assert!(quiet_hours.contains(time(23, 30)));
+        assert!(quiet_hours.contains(time(6, 59)));
+        assert!(!quiet_hours.contains(time(7, 0)));
+        assert!(!quiet_hours.contains(time(12, 0)));
+
+        assert!(toml::from_str::<QuietHours>("start = \"25:00\"\nend = \"07:00\"").is_err());
+    }
+}
@@ -14,8 +14,10 @@ use color_eyre::{
 use coordinates::{get_opengl_point_coordinates, Coordinates};
 use image::DynamicImage;
 
+use crate::wallpaper_info::ResizeFilter;
+
 pub use egl_context::EglContext;
-pub use renderer::Renderer;
+pub use renderer::{default_offset, texture_scale, untransform_buffer, Renderer, AUDIO_BANDS};
 pub use transition::Transition;
 
 pub mod gl {
@@ -123,7 +125,18 @@ fn initialize_objects(gl: &gl::Gl) -> Result<(gl::types::GLuint, gl::types::GLui
     }
 }
 
-fn load_texture(gl: &gl::Gl, image: DynamicImage) -> Result<gl::types::GLuint> {
+/// When `srgb` is true, the GPU decodes the colors to linear light before interpolating them, the
+/// shader encodes them again
+pub(crate) fn load_texture(
+    gl: &gl::Gl,
+    image: DynamicImage,
+    filter: ResizeFilter,
+    srgb: bool,
+) -> Result<gl::types::GLuint> {
+    let filter = match filter {
+        ResizeFilter::Nearest => gl::NEAREST,
+        ResizeFilter::Bilinear | ResizeFilter::Lanczos3 => gl::LINEAR,
+    } as i32;
     Ok(unsafe {
         let mut texture = 0;
         gl.GenTextures(1, &mut texture);
@@ -135,7 +148,9 @@ fn load_texture(gl: &gl::Gl, image: DynamicImage) -> Result<gl::types::GLuint> {
         gl.TexImage2D(
             gl::TEXTURE_2D,
             0,
-            gl::RGBA.try_into().unwrap(),
+            if srgb { gl::SRGB8_ALPHA8_EXT } else { gl::RGBA }
+                .try_into()
+                .unwrap(),
             image.width().try_into().unwrap(),
             image.height().try_into().unwrap(),
             0,
@@ -146,9 +161,9 @@ fn load_texture(gl: &gl::Gl, image: DynamicImage) -> Result<gl::types::GLuint> {
         gl_check!(gl, "defining the texture");
         gl.GenerateMipmap(gl::TEXTURE_2D);
         gl_check!(gl, "generating the mipmap");
-        gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+        gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, filter);
         gl_check!(gl, "defining the texture min filter");
-        gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+        gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, filter);
         gl_check!(gl, "defining the texture mag filter");
 
         texture
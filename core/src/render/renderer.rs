@@ -0,0 +1,1029 @@
+use std::{cell::RefCell, ffi::CStr, ops::Deref, rc::Rc};
+
+use color_eyre::{
+    eyre::{bail, ensure, Context},
+    Result,
+};
+use egl::API as egl;
+use image::{DynamicImage, RgbaImage};
+use log::error;
+use smithay_client_toolkit::reexports::client::protocol::wl_output::Transform;
+
+use crate::{
+    display_info::DisplayInfo,
+    gl_check,
+    image_loader::downscale_to_fit,
+    render::{
+        initialize_objects, load_texture,
+        shader::{create_shader, FRAGMENT_SHADER_SOURCE, VERTEX_SHADER_SOURCE},
+    },
+    wallpaper_info::{BackgroundMode, Color, Recolor, ResizeFilter},
+};
+
+use super::{gl, wallpaper::Wallpaper, Transition};
+
+/// The colors of the images in the display color space are left as they are
+const IDENTITY: [f32; 9] = [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0];
+
+/// The size of the display relative to the image drawn on it, on each axis. The shader maps the
+/// coordinates of the display to the ones of the image with `(uv - offset) * scale + offset`
+pub fn texture_scale(
+    mode: BackgroundMode,
+    [display_width, display_height]: [f32; 2],
+    [image_width, image_height]: [f32; 2],
+    tile_scale: f32,
+) -> [f32; 2] {
+    let display_ratio = display_width / display_height;
+    let image_ratio: f32 = image_width / image_height;
+    match mode {
+        BackgroundMode::Stretch => [1.0, 1.0],
+        BackgroundMode::Center => [
+            (display_ratio / image_ratio).min(1.0),
+            (image_ratio / display_ratio).min(1.0),
+        ],
+        BackgroundMode::Fit | BackgroundMode::FitBorderColor => {
+            // Portrait mode
+            // In this case we calculate the width relative to the height of the
+            // screen with the ratio of the image
+            let width = display_height * image_ratio;
+            // Same thing as above, just with the width
+            let height = display_width / image_ratio;
+            // Then we calculate the proportions
+            [
+                (display_width / width).max(1.0),
+                (display_height / height).max(1.0),
+            ]
+        }
+        BackgroundMode::Integer => {
+            let factor =
+                integer_factor([display_width, display_height], [image_width, image_height]);
+            [
+                display_width / (image_width * factor),
+                display_height / (image_height * factor),
+            ]
+        }
+        // How many tiles fit in the display
+        BackgroundMode::Tile => [
+            display_width / (image_width * tile_scale),
+            display_height / (image_height * tile_scale),
+        ],
+    }
+}
+
+/// The biggest integer factor the image can be scaled by to fit in the display, the images bigger
+/// than the display are drawn at their size and cropped
+pub fn integer_factor(
+    [display_width, display_height]: [f32; 2],
+    [image_width, image_height]: [f32; 2],
+) -> f32 {
+    (display_width / image_width)
+        .min(display_height / image_height)
+        .floor()
+        .max(1.0)
+}
+
+/// The offset used on the axes where it is not set
+pub fn default_offset(mode: BackgroundMode) -> f32 {
+    match mode {
+        BackgroundMode::Stretch
+        | BackgroundMode::Center
+        | BackgroundMode::Fit
+        | BackgroundMode::FitBorderColor
+        | BackgroundMode::Integer => 0.5,
+        BackgroundMode::Tile => 0.0,
+    }
+}
+
+/// The offset centering an image of `size` pixels once scaled, on a display of `display` pixels,
+/// starting it on a pixel boundary: otherwise the nearest filter draws some of its pixels bigger
+/// than the others
+fn aligned_offset(display: f32, size: f32) -> f32 {
+    let gap = display - size;
+    if gap.abs() < 1.0 {
+        return 0.5;
+    }
+    // The image starts at (offset - offset / scale) * display, scale being display / size
+    (gap / 2.0).floor() / gap
+}
+
+fn transparent_image() -> RgbaImage {
+    RgbaImage::from_raw(1, 1, vec![0, 0, 0, 0]).unwrap()
+}
+
+#[derive(Debug)]
+pub enum TransitionStatus {
+    Started,
+    Running { started: u32, progress: f32 },
+    Ended,
+}
+
+/// The frequency bands of the audio passed to the shader
+pub const AUDIO_BANDS: usize = 8;
+
+pub struct Renderer {
+    gl: gl::Gl,
+    pub program: gl::types::GLuint,
+    vbo: gl::types::GLuint,
+    eab: gl::types::GLuint,
+    // milliseconds time for the transition
+    transition_time: u32,
+    pub display_info: Rc<RefCell<DisplayInfo>>,
+    prev_wallpaper: Option<Wallpaper>,
+    current_wallpaper: Wallpaper,
+    transparent_texture: gl::types::GLuint,
+    /// contains the progress of the current animation
+    transition_status: TransitionStatus,
+    /// Width and height of the biggest texture supported by the GPU
+    max_texture_size: u32,
+    /// See [Recolor::stops]
+    recolor: Option<[[f32; 3]; 3]>,
+    /// From 0 to 1, see [crate::brightness::Brightness]
+    brightness: f32,
+    /// The color drawn where the image doesn't cover the display, see [Renderer::set_mode]
+    background_color: Color,
+    /// Drawn under the transparent areas, only set in integer mode
+    fill_color: [f32; 4],
+    /// Size of the tiles relative to the image, in tile mode
+    tile_scale: f32,
+    /// Space between the tiles, in pixels
+    tile_gap: u32,
+    /// Scale the images in linear light, see [crate::image_loader::resize]
+    linear_scaling: bool,
+    /// The GL_RENDERER of the driver, e.g. llvmpipe when drawing on the CPU
+    driver: String,
+    /// How much the wallpaper pulses with the audio, zero to ignore it
+    audio_reactive: f32,
+    /// The level of the audio and of its bands, from 0 to 1
+    audio_level: f32,
+    audio_bands: [f32; AUDIO_BANDS],
+    /// The image is shifted by up to this fraction of the display, see [crate::parallax]
+    parallax: f32,
+    /// The shift towards the pointer, relative to the size of the display
+    parallax_offset: [f32; 2],
+}
+
+impl Renderer {
+    /// # Safety
+    ///
+    /// The EGL context of the surface must be current on the calling thread.
+    pub unsafe fn new(
+        image: DynamicImage,
+        display_info: Rc<RefCell<DisplayInfo>>,
+        transition_time: u32,
+        transition: Transition,
+        transform: Transform,
+    ) -> Result<Self> {
+        let gl = gl::Gl::load_with(|name| {
+            egl.get_proc_address(name)
+                .expect("egl.get_proc_address to work") as *const std::ffi::c_void
+        });
+
+        let program = create_program(&gl, transition)
+            .context("unable to create program during openGL ES initialization")?;
+
+        let (vbo, eab) = initialize_objects(&gl)?;
+
+        let current_wallpaper = Wallpaper::new();
+
+        let transparent_texture = load_texture(
+            &gl,
+            transparent_image().into(),
+            ResizeFilter::default(),
+            false,
+        )?;
+
+        let mut max_texture_size = 0;
+        gl.GetIntegerv(gl::MAX_TEXTURE_SIZE, &mut max_texture_size);
+        gl_check!(gl, "getting the maximum texture size");
+
+        let driver = gl.GetString(gl::RENDERER);
+        gl_check!(gl, "getting the name of the renderer");
+        let driver = if driver.is_null() {
+            String::new()
+        } else {
+            CStr::from_ptr(driver as _).to_string_lossy().into_owned()
+        };
+
+        let mut renderer = Self {
+            gl,
+            program,
+            vbo,
+            eab,
+            transition_time,
+            prev_wallpaper: None,
+            current_wallpaper,
+            display_info,
+            transparent_texture,
+            transition_status: TransitionStatus::Ended,
+            max_texture_size: max_texture_size.try_into().unwrap_or(0),
+            recolor: None,
+            brightness: 1.0,
+            background_color: Color::default(),
+            fill_color: [0.0; 4],
+            tile_scale: 1.0,
+            tile_gap: 0,
+            linear_scaling: false,
+            driver,
+            audio_reactive: 0.0,
+            audio_level: 0.0,
+            audio_bands: [0.0; AUDIO_BANDS],
+            parallax: 0.0,
+            parallax_offset: [0.0; 2],
+        };
+
+        renderer.load_wallpaper(
+            image,
+            BackgroundMode::Stretch,
+            [None, None],
+            ResizeFilter::default(),
+        )?;
+        renderer.set_projection_matrix(transform)?;
+
+        Ok(renderer)
+    }
+
+    #[inline]
+    pub fn max_texture_size(&self) -> u32 {
+        self.max_texture_size
+    }
+
+    #[inline]
+    pub fn driver(&self) -> &str {
+        &self.driver
+    }
+
+    #[inline]
+    pub fn check_error(&self, msg: &str) -> Result<()> {
+        unsafe {
+            gl_check!(self.gl, msg);
+        }
+        Ok(())
+    }
+
+    /// # Safety
+    ///
+    /// The EGL context of the surface must be current on the calling thread.
+    pub unsafe fn draw(&mut self) -> Result<()> {
+        self.gl.Clear(gl::COLOR_BUFFER_BIT);
+        self.check_error("clearing the screen")?;
+
+        let loc = self
+            .gl
+            .GetUniformLocation(self.program, c"progress".as_ptr() as *const _);
+        self.check_error("getting the uniform location")?;
+        self.gl.Uniform1f(
+            loc,
+            match self.transition_status {
+                TransitionStatus::Started => 0.0,
+                TransitionStatus::Running {
+                    started: _,
+                    progress,
+                } => progress,
+                TransitionStatus::Ended => 1.0,
+            },
+        );
+        self.check_error("calling Uniform1i")?;
+
+        let loc = self
+            .gl
+            .GetUniformLocation(self.program, c"recolor".as_ptr() as *const _);
+        self.check_error("getting the uniform location")?;
+        self.gl.Uniform1i(loc, self.recolor.is_some().into());
+        self.check_error("calling Uniform1i")?;
+        if let Some(stops) = &self.recolor {
+            let loc = self
+                .gl
+                .GetUniformLocation(self.program, c"recolorStops".as_ptr() as *const _);
+            self.check_error("getting the uniform location")?;
+            self.gl.Uniform3fv(loc, 3, stops.as_ptr() as *const _);
+            self.check_error("calling Uniform3fv on recolorStops")?;
+        }
+
+        let loc = self
+            .gl
+            .GetUniformLocation(self.program, c"brightness".as_ptr() as *const _);
+        self.check_error("getting the uniform location")?;
+        self.gl.Uniform1f(loc, self.brightness);
+        self.check_error("calling Uniform1f on brightness")?;
+
+        let loc = self
+            .gl
+            .GetUniformLocation(self.program, c"fillColor".as_ptr() as *const _);
+        self.check_error("getting the uniform location")?;
+        self.gl.Uniform4fv(loc, 1, self.fill_color.as_ptr());
+        self.check_error("calling Uniform4fv on fillColor")?;
+
+        let loc = self
+            .gl
+            .GetUniformLocation(self.program, c"audioReactive".as_ptr() as *const _);
+        self.check_error("getting the uniform location")?;
+        self.gl.Uniform1f(loc, self.audio_reactive);
+        self.check_error("calling Uniform1f on audioReactive")?;
+        let zoom = 1.0 + 2.0 * self.parallax;
+        let loc = self
+            .gl
+            .GetUniformLocation(self.program, c"parallaxZoom".as_ptr() as *const _);
+        self.check_error("getting the uniform location")?;
+        self.gl.Uniform1f(loc, zoom);
+        self.check_error("calling Uniform1f on parallaxZoom")?;
+        let loc = self
+            .gl
+            .GetUniformLocation(self.program, c"parallaxOffset".as_ptr() as *const _);
+        self.check_error("getting the uniform location")?;
+        let offset = self.parallax_offset.map(|offset| offset / zoom);
+        self.gl.Uniform2fv(loc, 1, offset.as_ptr());
+        self.check_error("calling Uniform2fv on parallaxOffset")?;
+
+        if self.audio_reactive > 0.0 {
+            let loc = self
+                .gl
+                .GetUniformLocation(self.program, c"audioLevel".as_ptr() as *const _);
+            self.check_error("getting the uniform location")?;
+            self.gl.Uniform1f(loc, self.audio_level);
+            self.check_error("calling Uniform1f on audioLevel")?;
+            let loc = self
+                .gl
+                .GetUniformLocation(self.program, c"audioBands".as_ptr() as *const _);
+            self.check_error("getting the uniform location")?;
+            self.gl
+                .Uniform1fv(loc, AUDIO_BANDS as i32, self.audio_bands.as_ptr());
+            self.check_error("calling Uniform1fv on audioBands")?;
+        }
+
+        let srgb_textures = [
+            (c"srgbTexture", self.current_wallpaper.srgb),
+            (
+                c"prevSrgbTexture",
+                self.prev_wallpaper
+                    .as_ref()
+                    .is_some_and(|wallpaper| wallpaper.srgb),
+            ),
+        ];
+        for (name, srgb) in srgb_textures {
+            let loc = self
+                .gl
+                .GetUniformLocation(self.program, name.as_ptr() as *const _);
+            self.check_error("getting the uniform location")?;
+            self.gl.Uniform1i(loc, srgb.into());
+            self.check_error("calling Uniform1i")?;
+        }
+
+        let conversions = [
+            (c"colorMatrix", self.current_wallpaper.color_conversion),
+            (
+                c"prevColorMatrix",
+                self.prev_wallpaper
+                    .as_ref()
+                    .and_then(|wallpaper| wallpaper.color_conversion),
+            ),
+        ];
+        let convert = conversions.iter().any(|(_, matrix)| matrix.is_some());
+        let loc = self
+            .gl
+            .GetUniformLocation(self.program, c"convertColors".as_ptr() as *const _);
+        self.check_error("getting the uniform location")?;
+        self.gl.Uniform1i(loc, convert.into());
+        self.check_error("calling Uniform1i")?;
+        if convert {
+            for (name, matrix) in conversions {
+                let loc = self
+                    .gl
+                    .GetUniformLocation(self.program, name.as_ptr() as *const _);
+                self.check_error("getting the uniform location")?;
+                let matrix = matrix.unwrap_or(IDENTITY);
+                self.gl
+                    .UniformMatrix3fv(loc, 1, gl::TRUE, matrix.as_ptr() as *const _);
+                self.check_error("calling UniformMatrix3fv on the color matrices")?;
+            }
+        }
+
+        self.gl
+            .DrawElements(gl::TRIANGLES, 6, gl::UNSIGNED_INT, std::ptr::null());
+        self.check_error("drawing the triangles")?;
+
+        Ok(())
+    }
+
+    /// Read the pixels drawn by [Renderer::draw], before the buffers are swapped. The rows go
+    /// from the top of the buffer to the bottom
+    ///
+    /// # Safety
+    ///
+    /// The EGL context of the surface must be current on the calling thread.
+    pub unsafe fn read_pixels(&self, width: u32, height: u32) -> Result<RgbaImage> {
+        let mut data = vec![0u8; width as usize * height as usize * 4];
+        self.gl.PixelStorei(gl::PACK_ALIGNMENT, 1);
+        self.check_error("setting the pack alignment")?;
+        self.gl.ReadPixels(
+            0,
+            0,
+            width as i32,
+            height as i32,
+            gl::RGBA,
+            gl::UNSIGNED_BYTE,
+            data.as_mut_ptr() as *mut _,
+        );
+        self.check_error("reading the pixels")?;
+        let image = RgbaImage::from_raw(width, height, data)
+            .expect("the buffer to have the size of the image");
+        // The first row read is the bottom one
+        Ok(image::imageops::flip_vertical(&image))
+    }
+
+    /// Update the transition status with the current time
+    #[inline]
+    pub fn update_transition_status(&mut self, time: u32) -> bool {
+        let started = match self.transition_status {
+            TransitionStatus::Started => time,
+            TransitionStatus::Running {
+                started,
+                progress: _,
+            } => started,
+            TransitionStatus::Ended => unreachable!(),
+        };
+        let progress =
+            ((time.saturating_sub(started)) as f32 / self.transition_time as f32).min(1.0);
+        if progress == 1.0 {
+            self.transition_status = TransitionStatus::Ended;
+            false
+        } else {
+            self.transition_status = TransitionStatus::Running { started, progress };
+            true
+        }
+    }
+
+    pub fn load_wallpaper(
+        &mut self,
+        image: DynamicImage,
+        mode: BackgroundMode,
+        offset: [Option<f32>; 2],
+        filter: ResizeFilter,
+    ) -> Result<()> {
+        let image = self.fit_image(image);
+        self.prev_wallpaper = Some(std::mem::take(&mut self.current_wallpaper));
+        self.current_wallpaper
+            .load_image(&self.gl, image, filter, self.linear_scaling)?;
+
+        self.bind_wallpapers(mode, offset)?;
+
+        Ok(())
+    }
+
+    /// Replace the image shown without a transition with the next frame of its animation. The
+    /// frames are already scaled by the image loader, the texture is only updated when it has
+    /// the same size
+    pub fn replace_frame(&mut self, image: &RgbaImage, filter: ResizeFilter) -> Result<()> {
+        let wallpaper = &mut self.current_wallpaper;
+        if image.dimensions() == (wallpaper.image_width, wallpaper.image_height)
+            && wallpaper.srgb == self.linear_scaling
+        {
+            wallpaper.update_image(&self.gl, image)
+        } else {
+            wallpaper.load_image(
+                &self.gl,
+                DynamicImage::ImageRgba8(image.clone()),
+                filter,
+                self.linear_scaling,
+            )
+        }
+    }
+
+    /// Downscale the image to the maximum texture size, if needed. The loader scales the images
+    /// to the display in its pool, see [crate::image_loader::Scaling]
+    fn fit_image(&self, image: DynamicImage) -> DynamicImage {
+        // The image loader has already downscaled it, unless it wasn't aware of the limit yet
+        if self.max_texture_size > 0 {
+            // The textures are uploaded with 8 bits
+            downscale_to_fit(image, self.max_texture_size, self.linear_scaling)
+                .into_rgba8()
+                .into()
+        } else {
+            image
+        }
+    }
+
+    fn bind_wallpapers(&mut self, mode: BackgroundMode, offset: [Option<f32>; 2]) -> Result<()> {
+        self.set_mode(mode, offset)?;
+
+        unsafe {
+            self.gl.ActiveTexture(gl::TEXTURE0);
+            self.check_error("activating gl::TEXTURE0")?;
+            self.prev_wallpaper
+                .as_ref()
+                .expect("previous wallpaper to be set")
+                .bind(&self.gl)?;
+
+            // current_wallpaper is already binded to TEXTURE1, as load_texture loads the image
+            // there
+        }
+
+        Ok(())
+    }
+
+    /// `offset` is the offset of the image on each axis, from 0.0 to 1.0
+    pub fn set_mode(&mut self, mode: BackgroundMode, offset: [Option<f32>; 2]) -> Result<()> {
+        let display_info = (*self.display_info).borrow();
+        let display_width = display_info.scaled_width() as f32;
+        let display_height = display_info.scaled_height() as f32;
+        let display_ratio = display_width / display_height;
+        self.fill_color = match mode {
+            BackgroundMode::Integer => {
+                let [r, g, b] = self
+                    .background_color
+                    .0
+                    .map(|channel| channel as f32 / 255.0);
+                [r, g, b, 1.0]
+            }
+            _ => [0.0; 4],
+        };
+        let gen_texture_scale = |image_width: f32, image_height: f32| {
+            Box::new(texture_scale(
+                mode,
+                [display_width, display_height],
+                [image_width, image_height],
+                self.tile_scale,
+            ))
+        };
+        // The gap between the tiles, relative to the size of a tile
+        let gen_tile_gap = |image_width: f32, image_height: f32| match mode {
+            BackgroundMode::Tile => [
+                self.tile_gap as f32 / (image_width * self.tile_scale),
+                self.tile_gap as f32 / (image_height * self.tile_scale),
+            ],
+            _ => [0.0, 0.0],
+        };
+        let texture_scale = gen_texture_scale(
+            self.current_wallpaper.image_width as f32,
+            self.current_wallpaper.image_height as f32,
+        );
+        let (prev_image_width, prev_image_height) = if let Some(prev_wp) = &self.prev_wallpaper {
+            (prev_wp.image_width as f32, prev_wp.image_height as f32)
+        } else {
+            (1.0, 1.0)
+        };
+
+        let prev_texture_scale = gen_texture_scale(prev_image_width, prev_image_height);
+        let tile_gap = gen_tile_gap(
+            self.current_wallpaper.image_width as f32,
+            self.current_wallpaper.image_height as f32,
+        );
+        let prev_tile_gap = gen_tile_gap(prev_image_width, prev_image_height);
+
+        unsafe {
+            let loc = self
+                .gl
+                .GetUniformLocation(self.program, c"textureScale".as_ptr() as *const _);
+            self.check_error("getting the uniform location")?;
+            ensure!(loc > 0, "textureScale not found");
+            self.gl
+                .Uniform2fv(loc, 1, texture_scale.as_ptr() as *const _);
+            self.check_error("calling Uniform2fv on textureScale")?;
+
+            let loc = self
+                .gl
+                .GetUniformLocation(self.program, c"prevTextureScale".as_ptr() as *const _);
+            self.check_error("getting the uniform location")?;
+            ensure!(loc > 0, "prevTextureScale not found");
+            self.gl
+                .Uniform2fv(loc, 1, prev_texture_scale.as_ptr() as *const _);
+            self.check_error("calling Uniform2fv on prevTextureScale")?;
+
+            let loc = self
+                .gl
+                .GetUniformLocation(self.program, c"tileGap".as_ptr() as *const _);
+            self.check_error("getting the uniform location")?;
+            self.gl.Uniform2fv(loc, 1, tile_gap.as_ptr());
+            self.check_error("calling Uniform2fv on tileGap")?;
+
+            let loc = self
+                .gl
+                .GetUniformLocation(self.program, c"prevTileGap".as_ptr() as *const _);
+            self.check_error("getting the uniform location")?;
+            self.gl.Uniform2fv(loc, 1, prev_tile_gap.as_ptr());
+            self.check_error("calling Uniform2fv on prevTileGap")?;
+
+            let loc = self
+                .gl
+                .GetUniformLocation(self.program, c"ratio".as_ptr() as *const _);
+            self.check_error("getting the uniform location")?;
+            self.gl.Uniform1f(loc, display_ratio);
+            self.check_error("calling Uniform1f")?;
+
+            let offset = match mode {
+                BackgroundMode::Integer => {
+                    let display = [display_width, display_height];
+                    let image = [
+                        self.current_wallpaper.image_width as f32,
+                        self.current_wallpaper.image_height as f32,
+                    ];
+                    let factor = integer_factor(display, image);
+                    [0, 1].map(|axis| {
+                        offset[axis]
+                            .unwrap_or_else(|| aligned_offset(display[axis], image[axis] * factor))
+                    })
+                }
+                _ => offset.map(|offset| offset.unwrap_or(default_offset(mode))),
+            };
+
+            let loc = self
+                .gl
+                .GetUniformLocation(self.program, c"texture_offset".as_ptr() as *const _);
+            self.check_error("getting the uniform location")?;
+            self.gl.Uniform2fv(loc, 1, offset.as_ptr());
+            self.check_error("calling Uniform2fv on texture_offset")?;
+
+            let texture_wrap = match mode {
+                BackgroundMode::Stretch
+                | BackgroundMode::Center
+                | BackgroundMode::Fit
+                | BackgroundMode::Integer => gl::CLAMP_TO_BORDER_EXT,
+                BackgroundMode::Tile => gl::REPEAT,
+                BackgroundMode::FitBorderColor => gl::CLAMP_TO_EDGE,
+            } as i32;
+
+            self.gl.ActiveTexture(gl::TEXTURE0);
+            self.check_error("activating gl::TEXTURE0")?;
+            self.gl
+                .TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, texture_wrap);
+            self.check_error("defining the texture wrap_s")?;
+            self.gl
+                .TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, texture_wrap);
+            self.check_error("defining the texture wrap_t")?;
+
+            self.gl.ActiveTexture(gl::TEXTURE1);
+            self.check_error("activating gl::TEXTURE1")?;
+            self.gl
+                .TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, texture_wrap);
+            self.check_error("defining the texture wrap_s")?;
+            self.gl
+                .TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, texture_wrap);
+            self.check_error("defining the texture wrap_t")?;
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    pub fn start_transition(&mut self, transition_time: u32) {
+        match self.transition_status {
+            TransitionStatus::Started | TransitionStatus::Running { .. } => unreachable!(),
+            TransitionStatus::Ended => self.transition_status = TransitionStatus::Started,
+        }
+        // Needed to skip the initial transition depending on the configuration
+        self.transition_time = transition_time;
+    }
+
+    #[inline]
+    pub fn clear_after_draw(&self) -> Result<()> {
+        unsafe {
+            // Unbind the framebuffer and renderbuffer before deleting.
+            // self.gl.BindBuffer(gl::PIXEL_UNPACK_BUFFER, 0);
+            // self.check_error("unbinding the unpack buffer")?;
+            self.gl.BindFramebuffer(gl::FRAMEBUFFER, 0);
+            self.check_error("unbinding the framebuffer")?;
+            self.gl.BindRenderbuffer(gl::RENDERBUFFER, 0);
+            self.check_error("unbinding the render buffer")?;
+        }
+
+        Ok(())
+    }
+
+    pub fn resize(&mut self) -> Result<()> {
+        let info = (*self.display_info).borrow();
+        unsafe {
+            self.gl
+                .Viewport(0, 0, info.adjusted_width(), info.adjusted_height());
+            self.check_error("resizing the viewport")
+        }
+    }
+
+    #[inline]
+    pub fn update_transition_time(&mut self, transition_time: u32) {
+        self.transition_time = transition_time;
+    }
+
+    #[inline]
+    pub fn set_recolor(&mut self, recolor: Option<Recolor>) {
+        self.recolor = recolor.map(Recolor::stops);
+    }
+
+    /// Used for the wallpapers loaded from now on
+    #[inline]
+    pub fn set_linear_scaling(&mut self, linear_scaling: bool) {
+        self.linear_scaling = linear_scaling;
+    }
+
+    /// Convert the colors of the wallpaper loaded last, see [crate::color_space]
+    #[inline]
+    pub fn set_color_conversion(&mut self, matrix: Option<[f32; 9]>) {
+        self.current_wallpaper.color_conversion = matrix;
+    }
+
+    #[inline]
+    pub fn brightness(&self) -> f32 {
+        self.brightness
+    }
+
+    #[inline]
+    pub fn set_brightness(&mut self, brightness: f32) {
+        self.brightness = brightness;
+    }
+
+    #[inline]
+    pub fn audio(&self) -> (f32, [f32; AUDIO_BANDS]) {
+        (self.audio_level, self.audio_bands)
+    }
+
+    /// `reactive` scales the effect of the levels, zero draws the wallpaper as it is
+    #[inline]
+    pub fn set_audio(&mut self, reactive: f32, level: f32, bands: [f32; AUDIO_BANDS]) {
+        self.audio_reactive = reactive;
+        self.audio_level = level;
+        self.audio_bands = bands;
+    }
+
+    #[inline]
+    pub fn parallax_offset(&self) -> [f32; 2] {
+        self.parallax_offset
+    }
+
+    /// Zoom in by twice `parallax` and shift the image by `offset`, both relative to the display
+    #[inline]
+    pub fn set_parallax(&mut self, parallax: f32, offset: [f32; 2]) {
+        self.parallax = parallax;
+        self.parallax_offset = offset;
+    }
+
+    /// Used by the next call to [Renderer::set_mode]
+    #[inline]
+    pub fn set_background_color(&mut self, color: Color) {
+        self.background_color = color;
+    }
+
+    /// Used by the next call to [Renderer::set_mode]
+    #[inline]
+    pub fn set_tile(&mut self, scale: f32, gap: u32) {
+        self.tile_scale = scale;
+        self.tile_gap = gap;
+    }
+
+    #[inline]
+    pub fn transition_finished(&mut self) {
+        // By binding transparent pixel into the old wallpaper, we can delete the texture,
+        // freeing space from the GPU
+        unsafe {
+            // The previous wallpaper is always binding in TEXTURE0
+            self.gl.ActiveTexture(gl::TEXTURE0);
+            self.gl
+                .BindTexture(gl::TEXTURE_2D, self.transparent_texture);
+            self.prev_wallpaper.take();
+        }
+    }
+
+    #[inline]
+    pub fn force_transition_end(&mut self) {
+        // Force the transition to end
+        self.transition_status = TransitionStatus::Ended;
+    }
+
+    #[inline]
+    pub fn update_transition(&mut self, transition: Transition, transform: Transform) {
+        match create_program(&self.gl, transition) {
+            Ok(program) => {
+                unsafe {
+                    self.gl.DeleteProgram(self.program);
+                }
+                // Stop the transition immediately
+                if self.transition_running() {
+                    self.force_transition_end();
+                }
+                self.program = program;
+                unsafe {
+                    if let Err(err) = self.set_projection_matrix(transform) {
+                        error!("{err:?}");
+                    }
+                }
+            }
+            Err(err) => error!("{err:?}"),
+        }
+    }
+
+    #[inline]
+    pub fn transition_running(&self) -> bool {
+        match self.transition_status {
+            TransitionStatus::Started | TransitionStatus::Running { .. } => true,
+            TransitionStatus::Ended => false,
+        }
+    }
+
+    /// # Safety
+    ///
+    /// The EGL context of the surface must be current on the calling thread.
+    pub unsafe fn set_projection_matrix(&self, transform: Transform) -> Result<()> {
+        let projection_matrix = projection_matrix(transform);
+        let loc = self
+            .gl
+            .GetUniformLocation(self.program, c"projection_matrix".as_ptr() as *const _);
+        self.check_error("getting the uniform location for projection_matrix")?;
+        ensure!(loc > 0, "projection_matrix not found");
+        self.gl
+            .UniformMatrix2fv(loc, 1, 0, projection_matrix.as_ptr());
+        //self.gl
+        //    .UniformMatrix2fv(loc, 1, 0, [1.0, 0.0, 0.0, 1.0].as_ptr());
+
+        self.check_error("calling Uniform1i")?;
+
+        Ok(())
+    }
+}
+
+fn create_program(gl: &gl::Gl, transition: Transition) -> Result<gl::types::GLuint> {
+    unsafe {
+        let program = gl.CreateProgram();
+        gl_check!(gl, "calling CreateProgram");
+
+        let vertex_shader = create_shader(gl, gl::VERTEX_SHADER, &[VERTEX_SHADER_SOURCE.as_ptr()])
+            .expect("vertex shader creation succeed");
+        let (uniform_callback, shader) = transition.clone().shader();
+        let fragment_shader = create_shader(
+            gl,
+            gl::FRAGMENT_SHADER,
+            &[FRAGMENT_SHADER_SOURCE.as_ptr(), shader.as_ptr()],
+        )
+        .with_context(|| {
+            format!("unable to create fragment_shader with transisition {transition:?}")
+        })?;
+
+        gl.AttachShader(program, vertex_shader);
+        gl_check!(gl, "attach vertex shader");
+        gl.AttachShader(program, fragment_shader);
+        gl_check!(gl, "attach fragment shader");
+        gl.LinkProgram(program);
+        gl_check!(gl, "linking the program");
+        {
+            // This shouldn't be needed, gl_check already checks the status of LinkProgram
+            let mut status: i32 = 0;
+            gl.GetProgramiv(program, gl::LINK_STATUS, &mut status as *mut _);
+            ensure!(status == 1, "Program was not linked correctly");
+        }
+        gl_check!(gl, "calling UseProgram");
+        gl.DeleteShader(vertex_shader);
+        gl_check!(gl, "deleting the vertex shader");
+        gl.DeleteShader(fragment_shader);
+        gl_check!(gl, "deleting the fragment shader");
+        gl.UseProgram(program);
+        gl_check!(gl, "calling UseProgram");
+
+        // We need to setup the uniform each time we create a program
+        let loc = gl.GetUniformLocation(program, c"u_prev_texture".as_ptr() as *const _);
+        gl_check!(gl, "getting the uniform location for u_prev_texture");
+        ensure!(loc > 0, "u_prev_texture not found");
+        gl.Uniform1i(loc, 0);
+        gl_check!(gl, "calling Uniform1i");
+        let loc = gl.GetUniformLocation(program, c"u_texture".as_ptr() as *const _);
+        gl_check!(gl, "getting the uniform location for u_texture");
+        ensure!(loc > 0, "u_texture not found");
+        gl.Uniform1i(loc, 1);
+        gl_check!(gl, "calling Uniform1i");
+
+        uniform_callback(gl, program)?;
+
+        Ok(program)
+    }
+}
+
+/// Undo the transform the buffer is drawn with, so that the image is the one seen on the
+/// display. The transforms rotate counter-clockwise, after flipping horizontally
+pub fn untransform_buffer(buffer: RgbaImage, transform: Transform) -> RgbaImage {
+    use image::imageops::{flip_horizontal, rotate180, rotate270, rotate90};
+    match transform {
+        Transform::_90 => rotate90(&buffer),
+        Transform::_180 => rotate180(&buffer),
+        Transform::_270 => rotate270(&buffer),
+        Transform::Flipped => flip_horizontal(&buffer),
+        Transform::Flipped90 => flip_horizontal(&rotate90(&buffer)),
+        Transform::Flipped180 => flip_horizontal(&rotate180(&buffer)),
+        Transform::Flipped270 => flip_horizontal(&rotate270(&buffer)),
+        _ => buffer,
+    }
+}
+
+#[rustfmt::skip]
+fn projection_matrix(transform: Transform) -> [f32; 4] {
+    match transform {
+        Transform::Normal => {
+            [
+                1.0, 0.0,
+                0.0, 1.0,
+            ]
+        }
+        Transform::_90 => {
+            [
+                0.0, -1.0,
+                1.0, 0.0,
+            ]
+        }
+        Transform::_180 => {
+            [
+                -1.0, 0.0,
+                0.0, -1.0,
+            ]
+        }
+        Transform::_270 => {
+            [
+                0.0, 1.0,
+                -1.0, 0.0,
+            ]
+        }
+        Transform::Flipped => {
+            [
+                -1.0, 0.0,
+                0.0, 1.0,
+            ]
+        }
+        Transform::Flipped90 => {
+            [
+                0.0, -1.0,
+                -1.0, 0.0,
+            ]
+        }
+        Transform::Flipped180 => {
+            [
+                1.0, 0.0,
+                0.0, -1.0,
+            ]
+        }
+        Transform::Flipped270 => {
+            [
+                0.0, 1.0,
+                1.0, 0.0,
+            ]
+        }
+        _ => unreachable!()
+    }
+}
+
+impl Deref for Renderer {
+    type Target = gl::Gl;
+
+    fn deref(&self) -> &Self::Target {
+        &self.gl
+    }
+}
+
+impl Drop for Renderer {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.DeleteTextures(1, &self.current_wallpaper.texture);
+            if let Some(wp) = &self.prev_wallpaper {
+                self.gl.DeleteTextures(1, &wp.texture);
+            }
+            self.gl.DeleteBuffers(1, &self.eab);
+            self.gl.DeleteBuffers(1, &self.vbo);
+            self.gl.DeleteProgram(self.program);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_integer_mode() {
+        // A 320x180 image is scaled 6 times on a 1920x1080 display, so it covers it
+        let scale = texture_scale(
+            BackgroundMode::Integer,
+            [1920.0, 1080.0],
+            [320.0, 180.0],
+            1.0,
+        );
+        assert_eq!(scale, [1.0, 1.0]);
+        // Only 4 times on a 1366x768 display, as 5 would not fit vertically
+        assert_eq!(integer_factor([1366.0, 768.0], [320.0, 180.0]), 4.0);
+        // Bigger images are not scaled down
+        assert_eq!(integer_factor([1920.0, 1080.0], [3840.0, 2160.0]), 1.0);
+
+        // 1366 - 1280 leaves 43 pixels on the left, not 43.5
+        let offset = aligned_offset(1366.0, 1280.0);
+        let scale = 1366.0 / 1280.0;
+        assert!(((offset - offset / scale) * 1366.0 - 43.0).abs() < 0.01);
+        assert_eq!(aligned_offset(1920.0, 1920.0), 0.5);
+    }
+
+    #[test]
+    fn test_untransform_buffer() {
+        // The buffer of a display rotated by 90 degrees is turned clockwise
+        let mut buffer = RgbaImage::new(2, 1);
+        buffer.put_pixel(0, 0, image::Rgba([255, 0, 0, 255]));
+        let image = untransform_buffer(buffer.clone(), Transform::_90);
+        assert_eq!(image.dimensions(), (1, 2));
+        assert_eq!(image.get_pixel(0, 0).0, [255, 0, 0, 255]);
+        let image = untransform_buffer(buffer.clone(), Transform::Flipped);
+        assert_eq!(image.get_pixel(1, 0).0, [255, 0, 0, 255]);
+        assert_eq!(
+            untransform_buffer(buffer.clone(), Transform::Normal),
+            buffer
+        );
+    }
+}
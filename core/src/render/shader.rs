@@ -0,0 +1,187 @@
+use std::ffi::CStr;
+
+use color_eyre::{
+    eyre::{bail, ensure},
+    Result,
+};
+
+use crate::gl_check;
+
+use super::gl;
+
+pub unsafe fn create_shader(
+    gl: &gl::Gl,
+    shader: gl::types::GLenum,
+    sources: &[*const i8],
+) -> Result<gl::types::GLuint> {
+    let shader = gl.CreateShader(shader);
+    gl_check!(gl, "calling CreateShader");
+    gl.ShaderSource(
+        shader,
+        sources.len() as i32,
+        sources.as_ptr().cast(),
+        std::ptr::null(),
+    );
+    gl_check!(gl, "calling Shadersource");
+    gl.CompileShader(shader);
+    gl_check!(gl, "calling CompileShader");
+
+    let mut status: i32 = 0;
+    gl.GetShaderiv(shader, gl::COMPILE_STATUS, &mut status as *mut _);
+    gl_check!(gl, "calling GetShaderiv");
+    if status == 0 {
+        let mut max_length: i32 = 0;
+        let mut length: i32 = 0;
+        gl.GetShaderiv(shader, gl::INFO_LOG_LENGTH, &mut max_length as *mut _);
+        gl_check!(gl, "calling GetShaderiv");
+        let mut log: Vec<u8> = vec![0; max_length as _];
+        gl.GetShaderInfoLog(
+            shader,
+            max_length,
+            &mut length as *mut _,
+            log.as_mut_ptr() as _,
+        );
+        gl_check!(gl, "calling GetShaderInfoLog");
+        let res = String::from_utf8(log);
+        match res {
+            Ok(log) => Err(color_eyre::eyre::anyhow!(log)),
+            Err(err) => Err(color_eyre::eyre::anyhow!(err)),
+        }
+    } else {
+        Ok(shader)
+    }
+}
+
+pub const VERTEX_SHADER_SOURCE: &CStr = c"
+#version 320 es
+precision mediump float;
+
+layout (location = 0) in vec2 aPosition;
+layout (location = 1) in vec2 aTexCoord;
+
+layout (location = 10) uniform mat2 projection_matrix;
+
+out vec2 v_texcoord;
+
+void main() {
+    gl_Position = vec4(aPosition* projection_matrix, 1.0, 1.0);
+    v_texcoord = aTexCoord;
+}";
+
+pub const FRAGMENT_SHADER_SOURCE: &CStr = c"
+#version 320 es
+precision mediump float;
+out vec4 FragColor;
+
+in vec2 v_texcoord;
+
+layout (location = 2) uniform vec2 textureScale;
+layout (location = 3) uniform vec2 prevTextureScale;
+layout (location = 4) uniform sampler2D u_prev_texture;
+layout (location = 5) uniform sampler2D u_texture;
+
+uniform float progress;
+uniform float ratio;
+uniform vec2 texture_offset;
+// The colors the black, the middle gray and the white become, when recolor is set
+uniform bool recolor;
+uniform vec3 recolorStops[3];
+// Drawn under the transparent areas, e.g. around the image in integer mode
+uniform vec4 fillColor;
+// Multiplies the colors, to dim the wallpaper in the dark
+uniform float brightness;
+// The textures decoded to linear light by the GPU, when linear-scaling is set
+uniform bool srgbTexture;
+uniform bool prevSrgbTexture;
+// Convert the images to the color space of the display, see crate::color_space
+uniform bool convertColors;
+uniform mat3 colorMatrix;
+uniform mat3 prevColorMatrix;
+// Space between the tiles relative to their size, zero when not in tile mode
+uniform vec2 tileGap;
+uniform vec2 prevTileGap;
+// The levels of the audio played, from 0 to 1, see crate::audio
+uniform float audioReactive;
+uniform float audioLevel;
+uniform float audioBands[8];
+// Zoom in and shift the image towards the pointer, see crate::parallax
+uniform float parallaxZoom;
+uniform vec2 parallaxOffset;
+
+vec4 transition(vec2);
+
+// The gaps are transparent, like the borders in fit mode
+vec4 tiled(sampler2D tex, vec2 uv, vec2 gap) {
+    vec2 tile = mod(uv, 1.0 + gap);
+    if (tile.x > 1.0 || tile.y > 1.0) {
+        return vec4(0.0);
+    }
+    return texture(tex, tile);
+}
+
+// The sRGB transfer function, used by Display P3 as well
+vec3 toLinear(vec3 color) {
+    return mix(color / 12.92, pow((color + 0.055) / 1.055, vec3(2.4)), step(0.04045, color));
+}
+
+vec3 fromLinear(vec3 linear) {
+    return mix(linear * 12.92, 1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055,
+        step(0.0031308, linear));
+}
+
+// Return the sRGB color of the texel, converted when needed. The matrix applies to linear light
+vec4 convertColor(vec4 color, bool srgb, mat3 matrix) {
+    if (convertColors) {
+        vec3 linear = srgb ? color.rgb : toLinear(color.rgb);
+        color.rgb = fromLinear(clamp(matrix * linear, 0.0, 1.0));
+    } else if (srgb) {
+        color.rgb = fromLinear(color.rgb);
+    }
+    return color;
+}
+
+// Zoom in slightly with the bass
+vec2 audioZoom(vec2 uv) {
+    if (audioReactive <= 0.0) {
+        return uv;
+    }
+    float bass = (audioBands[0] + audioBands[1]) * 0.5;
+    return 0.5 + (uv - 0.5) / (1.0 + 0.04 * audioReactive * bass);
+}
+
+vec2 parallax(vec2 uv) {
+    return 0.5 + (uv - 0.5) / parallaxZoom + parallaxOffset;
+}
+
+vec4 getFromColor(vec2 uv) {
+    uv = parallax(audioZoom(uv));
+    uv = (uv - texture_offset) * prevTextureScale + (texture_offset);
+    vec4 color = prevTileGap != vec2(0.0)
+        ? tiled(u_prev_texture, uv, prevTileGap)
+        : texture(u_prev_texture, uv);
+    return convertColor(color, prevSrgbTexture, prevColorMatrix);
+}
+
+vec4 getToColor(vec2 uv) {
+    uv = parallax(audioZoom(uv));
+    uv = (uv - texture_offset) * textureScale + (texture_offset);
+    vec4 color = tileGap != vec2(0.0) ? tiled(u_texture, uv, tileGap) : texture(u_texture, uv);
+    return convertColor(color, srgbTexture, colorMatrix);
+}
+
+void main() {
+    FragColor = transition(v_texcoord);
+    if (recolor) {
+        float luminance = dot(FragColor.rgb, vec3(0.2126, 0.7152, 0.0722));
+        FragColor.rgb = luminance < 0.5
+            ? mix(recolorStops[0], recolorStops[1], luminance * 2.0)
+            : mix(recolorStops[1], recolorStops[2], luminance * 2.0 - 1.0);
+    }
+    FragColor += fillColor * (1.0 - FragColor.a);
+    FragColor.rgb *= brightness;
+    if (audioReactive > 0.0) {
+        // Pulse with the bass, the whole level keeps the quiet parts visible
+        float bass = (audioBands[0] + audioBands[1]) * 0.5;
+        FragColor.rgb *= 1.0 + audioReactive * (0.3 * bass + 0.1 * audioLevel - 0.1);
+    }
+}";
@@ -128,6 +128,23 @@ macro_rules! transition_shader {
                 }
             }
 
+            /// Return `other`, taking the parameters it doesn't set from `self` when they are the
+            /// same transition
+            pub fn merge(&self, other: &Self) -> Self {
+                match other.clone() {
+                    $($enum::$variant { $($field_name),* } => {
+                        $(
+                            let $field_name = $field_name.or_else(|| match self {
+                                $enum::$variant { $field_name: base, .. } => base.clone(),
+                                #[allow(unreachable_patterns)]
+                                _ => None,
+                            });
+                        )*
+                        $enum::$variant { $($field_name),* }
+                    })*
+                }
+            }
+
             pub const fn default_transition_time(&self) -> u32 {
                 match self {
                     $($enum::$variant { .. } => $default_time,)*
@@ -0,0 +1,91 @@
+use std::ffi::{c_void, CStr};
+
+use color_eyre::{
+    eyre::{bail, ensure},
+    Result,
+};
+use image::{DynamicImage, RgbaImage};
+
+use crate::{gl_check, render::gl, wallpaper_info::ResizeFilter};
+
+use super::load_texture;
+
+#[derive(Default)]
+pub struct Wallpaper {
+    pub texture: gl::types::GLuint,
+    pub image_width: u32,
+    pub image_height: u32,
+    /// Converts its colors to the color space of the display, see [crate::color_space]
+    pub color_conversion: Option<[f32; 9]>,
+    /// The texture holds sRGB colors decoded by the GPU, see [super::load_texture]
+    pub srgb: bool,
+}
+
+impl Wallpaper {
+    pub const fn new() -> Self {
+        Self {
+            texture: 0,
+            image_width: 10,
+            image_height: 10,
+            color_conversion: None,
+            srgb: false,
+        }
+    }
+
+    pub fn bind(&self, gl: &gl::Gl) -> Result<()> {
+        unsafe {
+            gl.BindTexture(gl::TEXTURE_2D, self.texture);
+            gl_check!(gl, "binding textures");
+        }
+
+        Ok(())
+    }
+
+    pub fn load_image(
+        &mut self,
+        gl: &gl::Gl,
+        image: DynamicImage,
+        filter: ResizeFilter,
+        srgb: bool,
+    ) -> Result<()> {
+        self.image_width = image.width();
+        self.image_height = image.height();
+        self.srgb = srgb;
+
+        let texture = load_texture(gl, image, filter, srgb)?;
+
+        unsafe {
+            // Delete from memory the previous texture
+            gl.DeleteTextures(1, &self.texture);
+        }
+        self.texture = texture;
+
+        Ok(())
+    }
+
+    /// Copy an image of the same size into the texture, instead of creating a new one
+    pub fn update_image(&mut self, gl: &gl::Gl, image: &RgbaImage) -> Result<()> {
+        unsafe {
+            gl.ActiveTexture(gl::TEXTURE1);
+            gl_check!(gl, "activating textures");
+            gl.BindTexture(gl::TEXTURE_2D, self.texture);
+            gl_check!(gl, "binding textures");
+            gl.TexSubImage2D(
+                gl::TEXTURE_2D,
+                0,
+                0,
+                0,
+                image.width().try_into().unwrap(),
+                image.height().try_into().unwrap(),
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                image.as_raw().as_ptr() as *const c_void,
+            );
+            gl_check!(gl, "updating the texture");
+            gl.GenerateMipmap(gl::TEXTURE_2D);
+            gl_check!(gl, "generating the mipmap");
+        }
+
+        Ok(())
+    }
+}
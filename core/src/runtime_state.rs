@@ -0,0 +1,107 @@
+//! The state of the displays, kept in `$XDG_RUNTIME_DIR/wpaperd/state.json` for the scripts that
+//! would rather read a file than implement the IPC protocol.
+//!
+//! The file is written again each time the state changes and replaced at once, so that it can be
+//! read at any moment. It is removed when wpaperd exits. The persisted state, e.g. the durations
+//! set with `wpaperctl set-duration --persist`, is in [crate::state] instead.
+
+use std::{fs, path::PathBuf};
+
+use color_eyre::{eyre::Context, Result};
+use log::warn;
+use wpaperd_ipc::RuntimeState;
+
+use crate::Wpaperd;
+
+/// The file of the wpaperd running on `seat`, named like [wpaperd_ipc::seat_socket_path]
+pub fn runtime_state_path(seat: Option<&str>) -> PathBuf {
+    let name = match seat {
+        None | Some("seat0") => "state.json".to_string(),
+        Some(seat) => format!("state-{seat}.json"),
+    };
+    dirs::runtime_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("wpaperd")
+        .join(name)
+}
+
+pub struct RuntimeStateFile {
+    path: PathBuf,
+    /// The state last written, the file is left untouched until it changes
+    last: Option<RuntimeState>,
+}
+
+impl RuntimeStateFile {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path, last: None }
+    }
+
+    /// Called on each iteration of the event loop, write the state if it changed
+    pub fn update(&mut self, wpaperd: &Wpaperd) {
+        let state = RuntimeState {
+            outputs: wpaperd
+                .surfaces
+                .iter()
+                .map(|surface| surface.runtime_state())
+                .collect(),
+            config_errors: wpaperd.config.errors().to_vec(),
+        };
+        if self.last.as_ref() == Some(&state) {
+            return;
+        }
+        if let Err(err) = self.save(&state) {
+            warn!("{err:?}");
+        }
+        // Do not retry on every iteration when the file cannot be written
+        self.last = Some(state);
+    }
+
+    fn save(&self, state: &RuntimeState) -> Result<()> {
+        let dir = self.path.parent().expect("the path to have a parent");
+        fs::create_dir_all(dir).with_context(|| format!("creating directory {dir:?}"))?;
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(state)?)
+            .and_then(|_| fs::rename(&tmp, &self.path))
+            .with_context(|| format!("writing the state into {:?}", self.path))
+    }
+
+    /// Do not leave a stale state behind
+    pub fn remove(&self) {
+        if let Err(err) = fs::remove_file(&self.path) {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                warn!("Could not remove the state file {:?}: {err:?}", self.path);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wpaperd_ipc::RuntimeOutput;
+
+    use super::*;
+
+    #[test]
+    fn test_save() {
+        let dir = std::env::temp_dir().join(format!("wpaperd-runtime-{}", std::process::id()));
+        let file = RuntimeStateFile::new(dir.join("state.json"));
+        let state = RuntimeState {
+            outputs: vec![RuntimeOutput {
+                name: "DP-1".to_string(),
+                wallpaper: Some(PathBuf::from("/image.png")),
+                paused: false,
+                next_change: Some(1_700_000_000),
+                error: None,
+                metadata: None,
+            }],
+            config_errors: Vec::new(),
+        };
+        file.save(&state).unwrap();
+        let saved = fs::read_to_string(dir.join("state.json")).unwrap();
+        assert_eq!(serde_json::from_str::<RuntimeState>(&saved).unwrap(), state);
+        assert!(!dir.join("state.json.tmp").exists());
+        file.remove();
+        assert!(!dir.join("state.json").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
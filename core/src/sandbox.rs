@@ -0,0 +1,344 @@
+//! Opt-in hardening of the daemon, enabled with `--sandbox`.
+//!
+//! wpaperd decodes images that could come from anywhere, so a bug in a decoder could be exploited
+//! by a crafted file. Landlock restricts the files that can be accessed to the ones wpaperd needs,
+//! and a seccomp filter refuses the syscalls that it never uses. Both apply to the threads and the
+//! processes started afterwards, and cannot be lifted: the directories added to the configuration
+//! later cannot be read until wpaperd is restarted.
+
+use std::path::PathBuf;
+
+use color_eyre::Result;
+
+/// How a file or directory can be accessed once the sandbox is applied
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Access {
+    Read,
+    /// Also create, write and remove the files in the directory
+    ReadWrite,
+}
+
+/// Read by the libraries, the graphics drivers and the commands run by wpaperd
+const SYSTEM_DIRS: &[&str] = &[
+    "/usr",
+    "/bin",
+    "/sbin",
+    "/lib",
+    "/lib32",
+    "/lib64",
+    "/etc",
+    "/opt",
+    "/nix/store",
+    "/gnu/store",
+    "/proc",
+    "/sys",
+    "/run",
+];
+
+/// The files and directories that can be accessed once the sandbox is applied
+pub struct Sandbox {
+    rules: Vec<(PathBuf, Access)>,
+}
+
+impl Default for Sandbox {
+    fn default() -> Self {
+        let mut rules: Vec<_> = SYSTEM_DIRS
+            .iter()
+            .map(|dir| (PathBuf::from(dir), Access::Read))
+            .collect();
+        // The render nodes of the GPU are opened for writing
+        rules.push((PathBuf::from("/dev"), Access::ReadWrite));
+        Self { rules }
+    }
+}
+
+impl Sandbox {
+    /// Allow accessing `path` and everything inside it. The paths that don't exist are skipped
+    pub fn allow(&mut self, path: impl Into<PathBuf>, access: Access) {
+        self.rules.push((path.into(), access));
+    }
+
+    /// Restrict the current thread and the ones started afterwards. The restrictions not
+    /// supported by the kernel are skipped with a warning
+    #[cfg(target_os = "linux")]
+    pub fn apply(&self) -> Result<()> {
+        use color_eyre::eyre::Context;
+
+        // Required by both, it also prevents gaining privileges through setuid binaries
+        if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+            return Err(std::io::Error::last_os_error()).context("setting no_new_privs");
+        }
+        landlock::restrict(&self.rules)?;
+        seccomp::install()
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn apply(&self) -> Result<()> {
+        Err(color_eyre::eyre::anyhow!(
+            "the sandbox is only supported on Linux"
+        ))
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod landlock {
+    use std::{
+        fs::File,
+        io,
+        os::{
+            fd::{AsRawFd, FromRawFd, OwnedFd},
+            unix::fs::OpenOptionsExt,
+        },
+        path::PathBuf,
+    };
+
+    use color_eyre::{eyre::Context, Result};
+    use log::{debug, warn};
+
+    use super::Access;
+
+    const CREATE_RULESET_VERSION: libc::c_uint = 1;
+    const RULE_PATH_BENEATH: libc::c_int = 1;
+
+    // The access rights of the first version of Landlock, in linux/landlock.h
+    const EXECUTE: u64 = 1 << 0;
+    const WRITE_FILE: u64 = 1 << 1;
+    const READ_FILE: u64 = 1 << 2;
+    const READ_DIR: u64 = 1 << 3;
+    const REMOVE_DIR: u64 = 1 << 4;
+    const REMOVE_FILE: u64 = 1 << 5;
+    const MAKE_CHAR: u64 = 1 << 6;
+    const MAKE_DIR: u64 = 1 << 7;
+    const MAKE_REG: u64 = 1 << 8;
+    const MAKE_SOCK: u64 = 1 << 9;
+    const MAKE_FIFO: u64 = 1 << 10;
+    const MAKE_BLOCK: u64 = 1 << 11;
+    const MAKE_SYM: u64 = 1 << 12;
+    /// Added in the third version
+    const TRUNCATE: u64 = 1 << 14;
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: i32,
+    }
+
+    pub fn restrict(rules: &[(PathBuf, Access)]) -> Result<()> {
+        let abi = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                std::ptr::null::<RulesetAttr>(),
+                0,
+                CREATE_RULESET_VERSION,
+            )
+        };
+        if abi < 1 {
+            warn!("Landlock is not supported by the kernel, the files accessed are not restricted");
+            return Ok(());
+        }
+        let truncate = if abi >= 3 { TRUNCATE } else { 0 };
+        let read = EXECUTE | READ_FILE | READ_DIR;
+        let write = WRITE_FILE
+            | REMOVE_DIR
+            | REMOVE_FILE
+            | MAKE_DIR
+            | MAKE_REG
+            | MAKE_SOCK
+            | MAKE_FIFO
+            | MAKE_SYM
+            | truncate;
+
+        let attr = RulesetAttr {
+            handled_access_fs: read | write | MAKE_CHAR | MAKE_BLOCK,
+        };
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &attr as *const RulesetAttr,
+                std::mem::size_of::<RulesetAttr>(),
+                0,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error()).context("creating the Landlock ruleset");
+        }
+        let ruleset = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+
+        for (path, access) in rules {
+            let file = match File::options()
+                .read(true)
+                .custom_flags(libc::O_PATH)
+                .open(path)
+            {
+                Ok(file) => file,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                    debug!("Not allowing {path:?} in the sandbox, it doesn't exist");
+                    continue;
+                }
+                Err(err) => return Err(err).with_context(|| format!("opening {path:?}")),
+            };
+            let mut allowed = match access {
+                Access::Read => read,
+                Access::ReadWrite => read | write,
+            };
+            // Only the rights about the content apply to the files
+            if !file.metadata()?.is_dir() {
+                allowed &= EXECUTE | READ_FILE | WRITE_FILE | truncate;
+            }
+            let rule = PathBeneathAttr {
+                allowed_access: allowed,
+                parent_fd: file.as_raw_fd(),
+            };
+            if unsafe {
+                libc::syscall(
+                    libc::SYS_landlock_add_rule,
+                    ruleset.as_raw_fd(),
+                    RULE_PATH_BENEATH,
+                    &rule as *const PathBeneathAttr,
+                    0,
+                )
+            } < 0
+            {
+                return Err(io::Error::last_os_error())
+                    .with_context(|| format!("allowing {path:?} in the Landlock ruleset"));
+            }
+        }
+
+        if unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset.as_raw_fd(), 0) } < 0 {
+            return Err(io::Error::last_os_error()).context("applying the Landlock ruleset");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod seccomp {
+    use std::io;
+
+    use color_eyre::{eyre::Context, Result};
+    use libc::sock_filter;
+
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: Option<u32> = Some(0xc000_003e);
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: Option<u32> = Some(0xc000_00b7);
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    const AUDIT_ARCH: Option<u32> = None;
+
+    const SET_MODE_FILTER: libc::c_uint = 1;
+    /// Apply the filter to all the threads, including the ones already running
+    const FILTER_FLAG_TSYNC: libc::c_ulong = 1;
+
+    /// Syscalls never used by wpaperd nor by the graphics drivers, they fail with EPERM
+    const DENIED: &[libc::c_long] = &[
+        libc::SYS_ptrace,
+        libc::SYS_process_vm_readv,
+        libc::SYS_process_vm_writev,
+        libc::SYS_mount,
+        libc::SYS_umount2,
+        libc::SYS_pivot_root,
+        libc::SYS_chroot,
+        libc::SYS_unshare,
+        libc::SYS_setns,
+        libc::SYS_kexec_load,
+        libc::SYS_kexec_file_load,
+        libc::SYS_init_module,
+        libc::SYS_finit_module,
+        libc::SYS_delete_module,
+        libc::SYS_reboot,
+        libc::SYS_swapon,
+        libc::SYS_swapoff,
+        libc::SYS_bpf,
+        libc::SYS_perf_event_open,
+        libc::SYS_userfaultfd,
+        libc::SYS_keyctl,
+        libc::SYS_add_key,
+        libc::SYS_request_key,
+        libc::SYS_acct,
+        libc::SYS_settimeofday,
+        libc::SYS_clock_settime,
+        libc::SYS_adjtimex,
+        libc::SYS_name_to_handle_at,
+        libc::SYS_open_by_handle_at,
+        libc::SYS_quotactl,
+        libc::SYS_syslog,
+    ];
+
+    fn statement(code: u32, k: u32) -> sock_filter {
+        jump(code, k, 0, 0)
+    }
+
+    fn jump(code: u32, k: u32, jt: u8, jf: u8) -> sock_filter {
+        sock_filter {
+            code: code as u16,
+            jt,
+            jf,
+            k,
+        }
+    }
+
+    /// The BPF program checked on each syscall. The offsets are the ones of seccomp_data
+    fn program(arch: u32) -> Vec<sock_filter> {
+        use libc::{
+            BPF_ABS, BPF_JEQ, BPF_JGE, BPF_JMP, BPF_K, BPF_LD, BPF_RET, BPF_W, EPERM,
+            SECCOMP_RET_ALLOW, SECCOMP_RET_ERRNO,
+        };
+        let denied = DENIED.len();
+        let errno = SECCOMP_RET_ERRNO | EPERM as u32;
+        let mut program = vec![
+            statement(BPF_LD | BPF_W | BPF_ABS, 4),
+            // The syscalls of other architectures have different numbers
+            jump(BPF_JMP | BPF_JEQ | BPF_K, arch, 1, 0),
+            statement(BPF_RET | BPF_K, errno),
+            statement(BPF_LD | BPF_W | BPF_ABS, 0),
+            // The x32 syscalls share the architecture of x86_64
+            jump(BPF_JMP | BPF_JGE | BPF_K, 0x4000_0000, denied as u8 + 1, 0),
+        ];
+        for (i, nr) in DENIED.iter().enumerate() {
+            // Jump to the last instruction
+            program.push(jump(
+                BPF_JMP | BPF_JEQ | BPF_K,
+                *nr as u32,
+                (denied - i) as u8,
+                0,
+            ));
+        }
+        program.push(statement(BPF_RET | BPF_K, SECCOMP_RET_ALLOW));
+        program.push(statement(BPF_RET | BPF_K, errno));
+        program
+    }
+
+    pub fn install() -> Result<()> {
+        let Some(arch) = AUDIT_ARCH else {
+            log::warn!("The syscalls are not filtered on this architecture");
+            return Ok(());
+        };
+        let mut program = program(arch);
+        let filter = libc::sock_fprog {
+            len: program.len() as u16,
+            filter: program.as_mut_ptr(),
+        };
+        let res = unsafe {
+            libc::syscall(
+                libc::SYS_seccomp,
+                SET_MODE_FILTER,
+                FILTER_FLAG_TSYNC,
+                &filter as *const libc::sock_fprog,
+            )
+        };
+        match res {
+            0 => Ok(()),
+            res if res < 0 => {
+                Err(io::Error::last_os_error()).context("installing the seccomp filter")
+            }
+            thread => Err(color_eyre::eyre::anyhow!(
+                "installing the seccomp filter: thread {thread} could not be synchronized"
+            )),
+        }
+    }
+}
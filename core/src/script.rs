@@ -0,0 +1,224 @@
+use std::{
+    collections::VecDeque,
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use chrono::{Datelike, Local, Timelike};
+use color_eyre::{
+    eyre::{anyhow, Context},
+    Result,
+};
+use log::{debug, info};
+use rhai::{Array, Dynamic, Engine, Map, Scope, AST};
+
+/// How many images shown are passed to the script
+const HISTORY_SIZE: usize = 50;
+/// Stop the scripts that take too long, i.e. an infinite loop
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// A rhai script choosing the next image among the ones in the directory.
+///
+/// The script must define a function `select(context)` returning either the path of the image or
+/// its index in `context.images`. The script is compiled again when the file changes.
+pub struct SelectionScript {
+    engine: Engine,
+    ast: AST,
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    /// The images shown on the display, the most recent is the last one
+    history: VecDeque<PathBuf>,
+}
+
+impl SelectionScript {
+    pub fn new(path: &Path) -> Result<Self> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.on_print(|text| info!("{text}"));
+        engine.on_debug(|text, _, pos| debug!("{pos:?}: {text}"));
+        let mut script = Self {
+            ast: AST::empty(),
+            engine,
+            path: path.to_path_buf(),
+            modified: None,
+            history: VecDeque::new(),
+        };
+        script.compile()?;
+        Ok(script)
+    }
+
+    fn compile(&mut self) -> Result<()> {
+        self.modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        self.ast = self
+            .engine
+            .compile_file(self.path.clone())
+            .map_err(|err| anyhow!("{err}"))
+            .with_context(|| format!("compiling script {:?}", self.path))?;
+        Ok(())
+    }
+
+    /// Ask the script for the image to show. `previous` is true when the user asked for the
+    /// previous wallpaper.
+    pub fn select(
+        &mut self,
+        output: &str,
+        previous: bool,
+        current: &Path,
+        images: &[PathBuf],
+    ) -> Result<(usize, PathBuf)> {
+        if fs::metadata(&self.path).and_then(|m| m.modified()).ok() != self.modified {
+            self.compile()?;
+        }
+
+        let mut context = Map::new();
+        context.insert("output".into(), output.into());
+        context.insert(
+            "action".into(),
+            if previous { "previous" } else { "next" }.into(),
+        );
+        context.insert(
+            "current".into(),
+            if current.as_os_str().is_empty() {
+                Dynamic::UNIT
+            } else {
+                path_to_dynamic(current)
+            },
+        );
+        context.insert("images".into(), paths_to_array(images.iter()).into());
+        context.insert("history".into(), paths_to_array(self.history.iter()).into());
+        context.insert("time".into(), time().into());
+        context.insert(
+            "battery".into(),
+            battery().map_or(Dynamic::UNIT, Dynamic::from),
+        );
+
+        let result: Dynamic = self
+            .engine
+            .call_fn(&mut Scope::new(), &self.ast, "select", (context,))
+            .map_err(|err| anyhow!("{err}"))
+            .with_context(|| format!("running function select of script {:?}", self.path))?;
+
+        if let Some(index) = result.clone().try_cast::<rhai::INT>() {
+            let image = usize::try_from(index)
+                .ok()
+                .and_then(|index| images.get(index).map(|image| (index, image.clone())));
+            return image.ok_or_else(|| {
+                anyhow!(
+                    "script {:?} returned index {index}, but there are {} images",
+                    self.path,
+                    images.len()
+                )
+            });
+        }
+        if let Some(path) = result.clone().try_cast::<String>() {
+            let path = PathBuf::from(path);
+            if !path.is_file() {
+                return Err(anyhow!(
+                    "script {:?} returned {path:?}, which is not an existing file",
+                    self.path
+                ));
+            }
+            let index = images.iter().position(|image| image == &path).unwrap_or(0);
+            return Ok((index, path));
+        }
+        Err(anyhow!(
+            "script {:?} returned a value of type {}, instead of a path or an index",
+            self.path,
+            result.type_name()
+        ))
+    }
+
+    /// Add the image shown to the history passed to the script
+    pub fn push_history(&mut self, path: PathBuf) {
+        if self.history.len() == HISTORY_SIZE {
+            self.history.pop_front();
+        }
+        self.history.push_back(path);
+    }
+}
+
+fn path_to_dynamic(path: &Path) -> Dynamic {
+    path.to_string_lossy().to_string().into()
+}
+
+fn paths_to_array<'a>(paths: impl Iterator<Item = &'a PathBuf>) -> Array {
+    paths.map(|path| path_to_dynamic(path)).collect()
+}
+
+/// The local time, split in its components
+fn time() -> Map {
+    let now = Local::now();
+    let mut time = Map::new();
+    time.insert("year".into(), (now.year() as rhai::INT).into());
+    time.insert("month".into(), (now.month() as rhai::INT).into());
+    time.insert("day".into(), (now.day() as rhai::INT).into());
+    // From 1 (monday) to 7 (sunday)
+    time.insert(
+        "weekday".into(),
+        (now.weekday().number_from_monday() as rhai::INT).into(),
+    );
+    time.insert("hour".into(), (now.hour() as rhai::INT).into());
+    time.insert("minute".into(), (now.minute() as rhai::INT).into());
+    time.insert("second".into(), (now.second() as rhai::INT).into());
+    time.insert("timestamp".into(), now.timestamp().into());
+    time
+}
+
+/// The charge of the first battery found, if any
+fn battery() -> Option<Map> {
+    fs::read_dir("/sys/class/power_supply")
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .find(|path| {
+            fs::read_to_string(path.join("type")).is_ok_and(|kind| kind.trim() == "Battery")
+        })
+        .and_then(|path| {
+            let capacity: rhai::INT = fs::read_to_string(path.join("capacity"))
+                .ok()?
+                .trim()
+                .parse()
+                .ok()?;
+            let status = fs::read_to_string(path.join("status")).unwrap_or_default();
+            let mut battery = Map::new();
+            battery.insert("capacity".into(), capacity.into());
+            battery.insert(
+                "charging".into(),
+                matches!(status.trim(), "Charging" | "Full").into(),
+            );
+            Some(battery)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select() {
+        let path = std::env::temp_dir().join(format!("wpaperd-test-{}.rhai", std::process::id()));
+        fs::write(
+            &path,
+            r#"
+            fn select(context) {
+                if context.action == "previous" {
+                    return 0;
+                }
+                context.images.len() - 1
+            }
+            "#,
+        )
+        .unwrap();
+        let images = vec![PathBuf::from("/a.png"), PathBuf::from("/b.png")];
+        let mut script = SelectionScript::new(&path).unwrap();
+        let next = script
+            .select("DP-1", false, Path::new(""), &images)
+            .unwrap();
+        let previous = script.select("DP-1", true, &images[1], &images).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(next, (1, images[1].clone()));
+        assert_eq!(previous, (0, images[0].clone()));
+    }
+}
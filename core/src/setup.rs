@@ -0,0 +1,103 @@
+//! `wpaperd setup`: write a first configuration from the answers given for each display.
+//!
+//! The questions are asked by the daemon, this module only turns the answers into the file. The
+//! values of the first display go into the default section, so that the displays connected later
+//! get them too, and the other displays only get a section for the values they change.
+
+use std::{fmt::Write, path::Path, time::Duration};
+
+use humantime_serde::re::humantime::format_duration;
+use toml_edit::Key;
+
+/// The wallpapers chosen for a display
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Choice {
+    /// The directory as written by the user, `~` is expanded when reading the configuration
+    pub path: String,
+    pub duration: Duration,
+}
+
+/// The content of the configuration for `default` and the displays in `outputs`
+pub fn config_content(default: &Choice, outputs: &[(String, Choice)]) -> String {
+    let mut content = String::from("# Written by wpaperd setup, see `man 5 wpaperd-output`\n");
+    write_section(
+        &mut content,
+        "default",
+        Some(&default.path),
+        Some(default.duration),
+    );
+    for (name, choice) in outputs {
+        let path = Some(&choice.path).filter(|path| **path != default.path);
+        let duration = Some(choice.duration).filter(|duration| *duration != default.duration);
+        if path.is_some() || duration.is_some() {
+            write_section(&mut content, name, path, duration);
+        }
+    }
+    content
+}
+
+fn write_section(
+    content: &mut String,
+    name: &str,
+    path: Option<&String>,
+    duration: Option<Duration>,
+) {
+    // The names with characters not allowed in a bare key are quoted, e.g. `seat/DP-1`
+    let _ = writeln!(content, "\n[{}]", Key::new(name));
+    if let Some(path) = path {
+        let _ = writeln!(content, "path = {}", toml::Value::String(path.clone()));
+    }
+    if let Some(duration) = duration {
+        let _ = writeln!(content, "duration = \"{}\"", format_duration(duration));
+    }
+}
+
+/// A systemd user unit starting `exe` with the graphical session
+pub fn systemd_unit(exe: &Path) -> String {
+    format!(
+        "[Unit]
+Description=Wallpaper daemon for Wayland
+PartOf=graphical-session.target
+After=graphical-session.target
+
+[Service]
+ExecStart={}
+Restart=on-failure
+
+[Install]
+WantedBy=graphical-session.target
+",
+        exe.display()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_content() {
+        let choice = |path: &str, minutes: u64| Choice {
+            path: path.to_string(),
+            duration: Duration::from_secs(minutes * 60),
+        };
+        let content = config_content(
+            &choice("~/Pictures/Wallpapers", 30),
+            &[
+                ("DP-1".to_string(), choice("~/Pictures/Wallpapers", 30)),
+                ("HDMI-A-1".to_string(), choice("~/Pictures/Wallpapers", 90)),
+                ("seat/DP-2".to_string(), choice("~/My \"Photos\"", 30)),
+            ],
+        );
+        assert!(!content.contains("[DP-1]"));
+        let table: toml::Table = toml::from_str(&content).unwrap();
+        assert_eq!(
+            table["default"]["path"].as_str(),
+            Some("~/Pictures/Wallpapers")
+        );
+        assert_eq!(table["default"]["duration"].as_str(), Some("30m"));
+        assert_eq!(table["HDMI-A-1"]["duration"].as_str(), Some("1h 30m"));
+        assert!(table["HDMI-A-1"].get("path").is_none());
+        assert_eq!(table["seat/DP-2"]["path"].as_str(), Some("~/My \"Photos\""));
+    }
+}
@@ -0,0 +1,246 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use color_eyre::{eyre::Context, Result};
+use image::DynamicImage;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::wallpaper_info::BackgroundMode;
+
+/// Settings for a single image, read from the file with the same name and extension `toml` or
+/// `json`, i.e. `panorama.toml` for `panorama.jpg`.
+/// They take precedence over the configuration of the display.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Sidecar {
+    /// How long the image is shown, when the display changes the wallpaper periodically
+    #[serde(default, with = "humantime_serde")]
+    pub duration: Option<Duration>,
+    pub mode: Option<BackgroundMode>,
+    pub offset: Option<f32>,
+    /// Only show this region of the image
+    pub crop: Option<Crop>,
+    #[serde(default)]
+    pub filters: Filters,
+}
+
+/// A region of the image, in pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Crop {
+    #[serde(default)]
+    pub x: u32,
+    #[serde(default)]
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Applied to the image after cropping, in the order they are listed here
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Filters {
+    #[serde(default)]
+    pub grayscale: bool,
+    #[serde(default)]
+    pub invert: bool,
+    /// Gaussian blur, the value is the standard deviation
+    pub blur: Option<f32>,
+    /// Value added to each color channel, can be negative
+    pub brightness: Option<i32>,
+    /// Contrast adjustment, negative values decrease it
+    pub contrast: Option<f32>,
+    /// Rotate the hue by these degrees
+    pub hue_rotate: Option<i32>,
+}
+
+impl Sidecar {
+    /// The files that can contain the settings for `image`, by precedence
+    fn paths(image: &Path) -> [PathBuf; 2] {
+        [image.with_extension("toml"), image.with_extension("json")]
+    }
+
+    /// Read the settings of `image`, the default ones are returned if there is no sidecar file
+    pub fn load(image: &Path) -> Self {
+        let Some(path) = Self::paths(image).into_iter().find(|path| path.is_file()) else {
+            return Self::default();
+        };
+        match Self::read(&path) {
+            Ok(sidecar) => sidecar,
+            Err(err) => {
+                warn!("{err:?}");
+                Self::default()
+            }
+        }
+    }
+
+    /// Write `crop` into the sidecar file of `image`, keeping the other settings. The file is
+    /// created if it doesn't exist, and the crop is removed when `crop` is None. Return the path
+    /// of the file
+    pub fn save_crop(image: &Path, crop: Option<Crop>) -> Result<PathBuf> {
+        let [toml_path, json_path] = Self::paths(image);
+        if json_path.is_file() && !toml_path.is_file() {
+            let content = fs::read_to_string(&json_path)
+                .with_context(|| format!("reading sidecar file {json_path:?}"))?;
+            let mut sidecar: serde_json::Map<String, serde_json::Value> =
+                serde_json::from_str(&content)
+                    .with_context(|| format!("parsing sidecar file {json_path:?}"))?;
+            match crop {
+                Some(crop) => {
+                    sidecar.insert("crop".to_string(), serde_json::to_value(crop)?);
+                }
+                None => {
+                    sidecar.remove("crop");
+                }
+            }
+            let content = serde_json::to_string_pretty(&sidecar)?;
+            fs::write(&json_path, content)
+                .with_context(|| format!("writing sidecar file {json_path:?}"))?;
+            return Ok(json_path);
+        }
+
+        let mut sidecar = if toml_path.is_file() {
+            let content = fs::read_to_string(&toml_path)
+                .with_context(|| format!("reading sidecar file {toml_path:?}"))?;
+            toml::from_str(&content)
+                .with_context(|| format!("parsing sidecar file {toml_path:?}"))?
+        } else {
+            toml::Table::new()
+        };
+        match crop {
+            Some(crop) => {
+                sidecar.insert("crop".to_string(), toml::Value::try_from(crop)?);
+            }
+            None => {
+                sidecar.remove("crop");
+            }
+        }
+        fs::write(&toml_path, toml::to_string(&sidecar)?)
+            .with_context(|| format!("writing sidecar file {toml_path:?}"))?;
+        Ok(toml_path)
+    }
+
+    fn read(path: &Path) -> Result<Self> {
+        let content =
+            fs::read_to_string(path).with_context(|| format!("reading sidecar file {path:?}"))?;
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "json")
+        {
+            serde_json::from_str(&content).with_context(|| format!("parsing sidecar file {path:?}"))
+        } else {
+            toml::from_str(&content).with_context(|| format!("parsing sidecar file {path:?}"))
+        }
+    }
+
+    /// The same settings for the image decoded at `ratio` of its size, see
+    /// [crate::image_loader::decode_capped]
+    pub fn scaled(&self, ratio: f64) -> Self {
+        let scale = |value: u32| (value as f64 * ratio).round() as u32;
+        Self {
+            crop: self.crop.map(|crop| Crop {
+                x: scale(crop.x),
+                y: scale(crop.y),
+                width: scale(crop.width).max(1),
+                height: scale(crop.height).max(1),
+            }),
+            ..self.clone()
+        }
+    }
+
+    /// Crop the image and apply the filters
+    pub fn apply(&self, mut image: DynamicImage) -> DynamicImage {
+        if let Some(crop) = self
+            .crop
+            .filter(|_| image.width() > 0 && image.height() > 0)
+        {
+            // Keep the region inside the image
+            let x = crop.x.min(image.width().saturating_sub(1));
+            let y = crop.y.min(image.height().saturating_sub(1));
+            let width = crop.width.clamp(1, image.width() - x);
+            let height = crop.height.clamp(1, image.height() - y);
+            if (x, y, width, height) != (crop.x, crop.y, crop.width, crop.height) {
+                warn!(
+                    "crop region {crop:?} exceeds the image size {}x{}",
+                    image.width(),
+                    image.height()
+                );
+            }
+            image = image.crop_imm(x, y, width, height);
+        }
+
+        let filters = &self.filters;
+        if filters.grayscale {
+            image = image.grayscale();
+        }
+        if filters.invert {
+            image.invert();
+        }
+        if let Some(sigma) = filters.blur {
+            image = image.blur(sigma);
+        }
+        if let Some(value) = filters.brightness {
+            image = image.brighten(value);
+        }
+        if let Some(contrast) = filters.contrast {
+            image = image.adjust_contrast(contrast);
+        }
+        if let Some(degrees) = filters.hue_rotate {
+            image = image.huerotate(degrees);
+        }
+        image
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::RgbaImage;
+
+    use super::*;
+
+    #[test]
+    fn test_crop() {
+        let sidecar: Sidecar = toml::from_str(
+            r#"
+            duration = "1h"
+            crop = { x = 2, y = 1, width = 100, height = 2 }
+            filters = { grayscale = true }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(sidecar.duration, Some(Duration::from_secs(3600)));
+
+        let image = sidecar.apply(RgbaImage::new(10, 10).into());
+        // The width is clamped to the image size
+        assert_eq!((image.width(), image.height()), (8, 2));
+    }
+
+    #[test]
+    fn test_save_crop() {
+        let dir = std::env::temp_dir().join(format!("wpaperd-sidecar-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let image = dir.join("image.png");
+        let crop = Crop {
+            x: 10,
+            y: 20,
+            width: 300,
+            height: 200,
+        };
+
+        // The other settings are kept
+        fs::write(image.with_extension("toml"), "mode = \"fit\"\n").unwrap();
+        Sidecar::save_crop(&image, Some(crop)).unwrap();
+        let sidecar = Sidecar::load(&image);
+        assert_eq!(sidecar.crop, Some(crop));
+        assert_eq!(sidecar.mode, Some(BackgroundMode::Fit));
+
+        Sidecar::save_crop(&image, None).unwrap();
+        assert_eq!(Sidecar::load(&image).crop, None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
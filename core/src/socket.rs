@@ -1,9 +1,11 @@
 //! Calloop socket event source.
 //!
-//! This module provides a Calloop event source for Unix domain sockets.
+//! This module provides a Calloop event source for Unix domain and TCP sockets.
 //! <https://github.com/catacombing/catacomb/blob/master/src/socket.rs>
 
 use std::io::{self, ErrorKind};
+use std::net::{TcpListener, TcpStream};
+use std::os::fd::AsFd;
 use std::os::unix::net::{UnixListener, UnixStream};
 
 use smithay_client_toolkit::reexports::calloop::generic::Generic;
@@ -11,19 +13,51 @@ use smithay_client_toolkit::reexports::calloop::{
     self, EventSource, Interest, Mode, Poll, PostAction, Readiness, Token, TokenFactory,
 };
 
-/// Unix domain socket source.
+/// A socket accepting connections
+pub trait Listener: AsFd {
+    type Stream;
+
+    fn accept_stream(&self) -> io::Result<Self::Stream>;
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+}
+
+impl Listener for UnixListener {
+    type Stream = UnixStream;
+
+    fn accept_stream(&self) -> io::Result<UnixStream> {
+        self.accept().map(|(stream, _)| stream)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        UnixListener::set_nonblocking(self, nonblocking)
+    }
+}
+
+impl Listener for TcpListener {
+    type Stream = TcpStream;
+
+    fn accept_stream(&self) -> io::Result<TcpStream> {
+        self.accept().map(|(stream, _)| stream)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpListener::set_nonblocking(self, nonblocking)
+    }
+}
+
+/// Socket source, a Unix domain socket by default.
 #[derive(Debug)]
-pub struct SocketSource {
-    socket: Generic<UnixListener>,
+pub struct SocketSource<L: Listener = UnixListener> {
+    socket: Generic<L>,
 }
 
-impl SocketSource {
+impl<L: Listener> SocketSource<L> {
     /// Create a new socket event source.
     ///
-    /// This will always call [`UnixListener::set_nonblocking`] on the socket
+    /// This will always call [`Listener::set_nonblocking`] on the socket
     /// automatically, to prevent it from blocking up the calloop event
     /// loop.
-    pub fn new(socket: UnixListener) -> calloop::Result<Self> {
+    pub fn new(socket: L) -> calloop::Result<Self> {
         // Ensure we'll get `WouldBlock` when reading from an empty socket.
         socket.set_nonblocking(true)?;
 
@@ -33,9 +67,9 @@ impl SocketSource {
     }
 }
 
-impl EventSource for SocketSource {
+impl<L: Listener> EventSource for SocketSource<L> {
     type Error = io::Error;
-    type Event = UnixStream;
+    type Event = L::Stream;
     type Metadata = ();
     type Ret = ();
 
@@ -50,8 +84,8 @@ impl EventSource for SocketSource {
     {
         self.socket.process_events(readiness, token, |_, socket| {
             // Accept next connection, separating `WouldBlock` from other errors.
-            let accept_next = || match socket.accept() {
-                Ok((stream, _)) => Ok(Some(stream)),
+            let accept_next = || match socket.accept_stream() {
+                Ok(stream) => Ok(Some(stream)),
                 Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(None),
                 Err(err) => Err(err),
             };
@@ -0,0 +1,183 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::mpsc,
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use color_eyre::{
+    eyre::{bail, Context, ContextCompat},
+    Result,
+};
+
+use super::{SourceAction, SourceRequest, SourceStatus, WallpaperSource};
+use crate::{
+    child::{self, read_to_end_limited},
+    config::tilde_expansion,
+};
+
+/// The command is killed if it doesn't exit in time
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// Images bigger than this are refused
+const MAX_IMAGE_SIZE: u64 = 100 * 1024 * 1024;
+
+/// Run a command every time the image must change, both for next and previous, and show what it
+/// prints on stdout: either the path of an image or the image itself.
+///
+/// The command is run with `sh -c`, the request is passed in the environment variables
+/// `WPAPERD_OUTPUT`, `WPAPERD_ACTION` and `WPAPERD_CURRENT`. When it fails or doesn't exit in
+/// [TIMEOUT], the image shown is kept.
+pub struct CommandSource {
+    command: String,
+    /// Where the images printed by the command are stored
+    cache_dir: PathBuf,
+    output: String,
+    /// Alternate between two files, so that the image shown is not overwritten
+    images: usize,
+    thread_handle: Option<JoinHandle<Result<PathBuf>>>,
+}
+
+impl CommandSource {
+    pub fn new(command: String, output: &str) -> Self {
+        let cache_dir = dirs::cache_dir()
+            .unwrap_or_else(std::env::temp_dir)
+            .join("wpaperd")
+            .join("command");
+        Self {
+            command,
+            cache_dir,
+            output: output.to_string(),
+            images: 0,
+            thread_handle: None,
+        }
+    }
+}
+
+impl WallpaperSource for CommandSource {
+    fn request(&mut self, request: SourceRequest) {
+        let command = self.command.clone();
+        let cache_dir = self.cache_dir.clone();
+        let name = format!("{}-{}", self.output, self.images);
+        self.images = (self.images + 1) % 2;
+        self.thread_handle = Some(std::thread::spawn(move || {
+            let stdout = run(&command, &request)?;
+            image_from_output(stdout, &cache_dir, &name)
+                .with_context(|| format!("reading the output of command {command:?}"))
+        }));
+    }
+
+    fn poll(&mut self) -> SourceStatus {
+        let Some(handle) = self.thread_handle.take() else {
+            return SourceStatus::Waiting;
+        };
+        if !handle.is_finished() {
+            self.thread_handle = Some(handle);
+            return SourceStatus::Waiting;
+        }
+        match handle.join() {
+            Ok(Ok(path)) => SourceStatus::Ready(path),
+            Ok(Err(err)) => SourceStatus::Error(format!("{err:?}")),
+            Err(err) => SourceStatus::Error(format!("{err:?}")),
+        }
+    }
+}
+
+/// Run the command and return its stdout, killing it after [TIMEOUT]
+fn run(command: &str, request: &SourceRequest) -> Result<Vec<u8>> {
+    let action = match request.action {
+        SourceAction::Next => "next",
+        SourceAction::Previous => "previous",
+    };
+    let started = Instant::now();
+    let mut child = child::spawn(
+        Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .env("WPAPERD_OUTPUT", &request.output)
+            .env("WPAPERD_ACTION", action)
+            .env(
+                "WPAPERD_CURRENT",
+                request.current.as_deref().unwrap_or(Path::new("")),
+            )
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped()),
+    )
+    .with_context(|| format!("running command {command:?}"))?;
+    let stdout = child
+        .stdout
+        .take()
+        .context("command stdout not available")?;
+    // Read it in another thread, the command blocks when the pipe is full
+    let (sender, output) = mpsc::channel();
+    std::thread::spawn(move || sender.send(read_to_end_limited(stdout, MAX_IMAGE_SIZE)));
+
+    let Some(status) = child::wait_timeout(&mut child, TIMEOUT)? else {
+        bail!("command {command:?} did not exit in {}s", TIMEOUT.as_secs());
+    };
+    if !status.success() {
+        bail!("command {command:?} failed with {status}");
+    }
+    // The processes it left in the background might still hold stdout
+    match output.recv_timeout(TIMEOUT.saturating_sub(started.elapsed())) {
+        Ok(data) => data.with_context(|| format!("reading the output of command {command:?}")),
+        Err(_) => {
+            child::kill(&mut child);
+            bail!(
+                "the output of command {command:?} was not closed in {}s",
+                TIMEOUT.as_secs()
+            );
+        }
+    }
+}
+
+/// Return the path printed by the command, or store the image it printed into `dir`, adding the
+/// extension matching its format to `name`
+fn image_from_output(stdout: Vec<u8>, dir: &Path, name: &str) -> Result<PathBuf> {
+    if let Ok(text) = std::str::from_utf8(&stdout) {
+        let path = tilde_expansion(Path::new(text.trim()));
+        if !text.trim().is_empty() && path.is_file() {
+            return Ok(path);
+        }
+    }
+    // The image loader needs the extension to know the format
+    let extension = image::guess_format(&stdout)
+        .ok()
+        .and_then(|format| format.extensions_str().first())
+        .context("it is neither the path of an existing file nor an image")?;
+    let file = dir.join(format!("{name}.{extension}"));
+    fs::create_dir_all(dir).with_context(|| format!("creating directory {dir:?}"))?;
+    fs::write(&file, stdout).with_context(|| format!("writing file {file:?}"))?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_from_output() {
+        let dir = std::env::temp_dir().join(format!("wpaperd-command-{}", std::process::id()));
+        let request = SourceRequest {
+            output: "DP-1".to_string(),
+            action: SourceAction::Next,
+            current: None,
+        };
+
+        // The raw bytes of an image
+        let png = run("printf '\\211PNG\\r\\n\\032\\n'", &request).unwrap();
+        let image = image_from_output(png, &dir, "DP-1-0").unwrap();
+        assert_eq!(image, dir.join("DP-1-0.png"));
+
+        // The path of an image, followed by a newline
+        let output = run(&format!("echo {}", image.display()), &request).unwrap();
+        assert_eq!(image_from_output(output, &dir, "DP-1-1").unwrap(), image);
+
+        let output = run("echo $WPAPERD_OUTPUT $WPAPERD_ACTION", &request).unwrap();
+        assert_eq!(output, b"DP-1 next\n");
+        assert!(image_from_output(output, &dir, "DP-1-1").is_err());
+        assert!(run("exit 1", &request).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,71 @@
+mod command;
+mod newest;
+mod playlist;
+mod provider;
+mod url;
+
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+pub use command::CommandSource;
+pub use newest::NewestSource;
+pub use playlist::PlaylistSource;
+pub use provider::ProviderSource;
+pub use url::UrlSource;
+
+use crate::wallpaper_info::Source;
+
+/// Which image is requested to a [WallpaperSource]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceAction {
+    Next,
+    Previous,
+}
+
+/// Sent to a [WallpaperSource] to get a new image
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SourceRequest {
+    /// The name of the display
+    pub output: String,
+    pub action: SourceAction,
+    /// The image currently shown, if any
+    pub current: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+pub enum SourceStatus {
+    /// The image is ready to be loaded
+    Ready(PathBuf),
+    /// The image is still being fetched, poll again later
+    Waiting,
+    /// No image can be returned for the last request
+    Error(String),
+}
+
+/// Decide the images shown on a display, in place of the sorting methods used for the
+/// directories in `path`.
+///
+/// [WallpaperSource::request] is called every time the image must change, then
+/// [WallpaperSource::poll] is called on each frame until it doesn't return
+/// [SourceStatus::Waiting] anymore.
+pub trait WallpaperSource {
+    /// Start fetching a new image. It must not block, slow work belongs in [WallpaperSource::poll]
+    /// or in a separate thread.
+    fn request(&mut self, request: SourceRequest);
+
+    /// Return the status of the last request
+    fn poll(&mut self) -> SourceStatus;
+}
+
+/// Create the [WallpaperSource] configured for the display
+pub fn new_source(source: &Source, output: &str) -> Box<dyn WallpaperSource> {
+    match source {
+        Source::Playlist(path) => Box::new(PlaylistSource::new(path.clone())),
+        Source::Url(url) => Box::new(UrlSource::new(url.clone(), output)),
+        Source::Provider(command) => Box::new(ProviderSource::new(command.clone())),
+        Source::Command(command) => Box::new(CommandSource::new(command.clone(), output)),
+        Source::Newest { dir, blur } => Box::new(NewestSource::new(dir.clone(), *blur, output)),
+    }
+}
@@ -0,0 +1,160 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    thread::JoinHandle,
+    time::SystemTime,
+};
+
+use color_eyre::{
+    eyre::{anyhow, Context},
+    Result,
+};
+use image::{imageops::FilterType, ImageFormat};
+
+use super::{SourceRequest, SourceStatus, WallpaperSource};
+use crate::capabilities::codec_for;
+
+/// The blur is applied on a smaller copy of the image, like for [crate::lock_image]
+const DOWNSCALE: u32 = 4;
+
+/// The image found in the directory, with its modification time, and the file shown for it
+type Found = (PathBuf, SystemTime, PathBuf);
+
+/// Show the image of the directory modified last, both for next and previous, e.g. the last
+/// screenshot taken.
+///
+/// The directory is watched by [crate::filelist_cache], which asks for a new image each time it
+/// changes. With `newest-blur`, a blurred copy stored in `XDG_CACHE_HOME/wpaperd/newest` is shown
+/// in place of the image.
+pub struct NewestSource {
+    dir: PathBuf,
+    /// Standard deviation of the gaussian blur, in pixels of the image
+    blur: Option<f32>,
+    /// Where the blurred copies are stored
+    cache_dir: PathBuf,
+    output: String,
+    last: Option<Found>,
+    /// Alternate between two files, so that the image shown is not overwritten
+    copies: usize,
+    thread_handle: Option<JoinHandle<Result<Found>>>,
+}
+
+impl NewestSource {
+    pub fn new(dir: PathBuf, blur: Option<f32>, output: &str) -> Self {
+        let cache_dir = dirs::cache_dir()
+            .unwrap_or_else(std::env::temp_dir)
+            .join("wpaperd")
+            .join("newest");
+        Self {
+            dir,
+            blur,
+            cache_dir,
+            output: output.to_string(),
+            last: None,
+            copies: 0,
+            thread_handle: None,
+        }
+    }
+}
+
+impl WallpaperSource for NewestSource {
+    fn request(&mut self, _request: SourceRequest) {
+        let dir = self.dir.clone();
+        let blur = self.blur;
+        let cache_dir = self.cache_dir.clone();
+        let name = format!("{}-{}", self.output, self.copies);
+        let last = self.last.clone();
+        self.thread_handle = Some(std::thread::spawn(move || {
+            let (image, modified) = newest_image(&dir)?;
+            // Keep showing the same file, so that the display doesn't change
+            if let Some(last) = last.filter(|(path, time, _)| *path == image && *time == modified) {
+                return Ok(last);
+            }
+            let shown = match blur {
+                Some(sigma) => blurred_copy(&image, sigma, &cache_dir, &name)?,
+                None => image.clone(),
+            };
+            Ok((image, modified, shown))
+        }));
+    }
+
+    fn poll(&mut self) -> SourceStatus {
+        let Some(handle) = self.thread_handle.take() else {
+            return SourceStatus::Waiting;
+        };
+        if !handle.is_finished() {
+            self.thread_handle = Some(handle);
+            return SourceStatus::Waiting;
+        }
+        match handle.join() {
+            Ok(Ok(found)) => {
+                let shown = found.2.clone();
+                if self.blur.is_some() && self.last.as_ref().map(|last| &last.2) != Some(&shown) {
+                    self.copies = (self.copies + 1) % 2;
+                }
+                self.last = Some(found);
+                SourceStatus::Ready(shown)
+            }
+            Ok(Err(err)) => SourceStatus::Error(format!("{err:?}")),
+            Err(err) => SourceStatus::Error(format!("{err:?}")),
+        }
+    }
+}
+
+/// The image of `dir` modified last, with its modification time. The hidden files are skipped,
+/// some programs write the image into one before renaming it
+fn newest_image(dir: &Path) -> Result<(PathBuf, SystemTime)> {
+    fs::read_dir(dir)
+        .with_context(|| format!("reading directory {dir:?}"))?
+        .flatten()
+        .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && codec_for(path).is_some_and(|codec| codec.enabled))
+        .filter_map(|path| {
+            let modified = fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+            Some((path, modified))
+        })
+        .max_by_key(|(_, modified)| *modified)
+        .ok_or_else(|| anyhow!("directory {dir:?} does not contain any image"))
+}
+
+/// Save a blurred copy of `image` into `dir` as `name.png`
+fn blurred_copy(image: &Path, sigma: f32, dir: &Path, name: &str) -> Result<PathBuf> {
+    let data = image::open(image).with_context(|| format!("opening image {image:?}"))?;
+    let small = data.resize_exact(
+        (data.width() / DOWNSCALE).max(1),
+        (data.height() / DOWNSCALE).max(1),
+        FilterType::Triangle,
+    );
+    let file = dir.join(format!("{name}.png"));
+    fs::create_dir_all(dir).with_context(|| format!("creating directory {dir:?}"))?;
+    small
+        .blur(sigma / DOWNSCALE as f32)
+        .save_with_format(&file, ImageFormat::Png)
+        .with_context(|| format!("writing file {file:?}"))?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_newest_image() {
+        let dir = std::env::temp_dir().join(format!("wpaperd-newest-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        assert!(newest_image(&dir).is_err());
+
+        let now = SystemTime::now();
+        for (name, age) in [("old.png", 60), ("new.png", 0), (".partial.png", 0)] {
+            let file = fs::File::create(dir.join(name)).unwrap();
+            file.set_modified(now - Duration::from_secs(age)).unwrap();
+        }
+        fs::write(dir.join("notes.txt"), "").unwrap();
+        assert_eq!(newest_image(&dir).unwrap().0, dir.join("new.png"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,102 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::{eyre::Context, Result};
+
+use super::{SourceAction, SourceRequest, SourceStatus, WallpaperSource};
+use crate::config::tilde_expansion;
+
+/// Show the images listed in a file, one per line, in order.
+///
+/// Empty lines and lines starting with `#` are skipped, so that m3u playlists can be used as well.
+/// Relative paths are relative to the directory containing the playlist.
+pub struct PlaylistSource {
+    path: PathBuf,
+    /// The position of the last image returned
+    index: Option<usize>,
+    status: Option<SourceStatus>,
+}
+
+impl PlaylistSource {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            index: None,
+            status: None,
+        }
+    }
+
+    /// Read the playlist every time, so that changes are picked up without reloading
+    fn entries(&self) -> Result<Vec<PathBuf>> {
+        let content = fs::read_to_string(&self.path)
+            .with_context(|| format!("reading playlist {:?}", self.path))?;
+        let parent = self.path.parent().unwrap_or(Path::new("/"));
+        Ok(parse_playlist(&content, parent))
+    }
+}
+
+impl WallpaperSource for PlaylistSource {
+    fn request(&mut self, request: SourceRequest) {
+        let entries = match self.entries() {
+            Ok(entries) => entries,
+            Err(err) => {
+                self.status = Some(SourceStatus::Error(format!("{err:?}")));
+                return;
+            }
+        };
+        if entries.is_empty() {
+            self.status = Some(SourceStatus::Error(format!(
+                "playlist {:?} does not contain any image",
+                self.path
+            )));
+            return;
+        }
+
+        // The playlist might have been edited, look for the current image first
+        let current = request
+            .current
+            .and_then(|current| entries.iter().position(|entry| entry == &current))
+            .or(self.index);
+        let index = match (current, request.action) {
+            (None, _) => 0,
+            (Some(index), SourceAction::Next) => (index + 1) % entries.len(),
+            (Some(index), SourceAction::Previous) => {
+                (index.min(entries.len()) + entries.len() - 1) % entries.len()
+            }
+        };
+        self.index = Some(index);
+        self.status = Some(SourceStatus::Ready(entries[index].clone()));
+    }
+
+    fn poll(&mut self) -> SourceStatus {
+        self.status.take().unwrap_or(SourceStatus::Waiting)
+    }
+}
+
+fn parse_playlist(content: &str, parent: &Path) -> Vec<PathBuf> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| parent.join(tilde_expansion(Path::new(line))))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_playlist() {
+        let content = "#EXTM3U\n\n/abs/image.jpg\n  relative/image.png  \n# comment\n";
+        assert_eq!(
+            parse_playlist(content, Path::new("/playlists")),
+            vec![
+                PathBuf::from("/abs/image.jpg"),
+                PathBuf::from("/playlists/relative/image.png")
+            ]
+        );
+    }
+}
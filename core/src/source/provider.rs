@@ -0,0 +1,172 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    path::PathBuf,
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::mpsc::{self, Receiver, TryRecvError},
+    time::{Duration, Instant},
+};
+
+use color_eyre::{
+    eyre::{Context, ContextCompat},
+    Result,
+};
+use log::warn;
+use serde::Deserialize;
+
+use super::{SourceRequest, SourceStatus, WallpaperSource};
+use crate::{child, config::tilde_expansion};
+
+/// The provider is restarted if it doesn't answer in time
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Sent by the provider for each request
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ProviderResponse {
+    Image { path: PathBuf },
+    Error { error: String },
+}
+
+struct ProviderProcess {
+    child: Child,
+    stdin: ChildStdin,
+    /// The lines written by the provider, read in a separate thread
+    lines: Receiver<String>,
+}
+
+impl ProviderProcess {
+    fn spawn(command: &str) -> Result<Self> {
+        let mut child = child::spawn(
+            Command::new("sh")
+                .arg("-c")
+                .arg(command)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped()),
+        )
+        .with_context(|| format!("running provider {command:?}"))?;
+        let stdin = child.stdin.take().context("provider stdin not available")?;
+        let stdout = child
+            .stdout
+            .take()
+            .context("provider stdout not available")?;
+        let (sender, lines) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Self {
+            child,
+            stdin,
+            lines,
+        })
+    }
+
+    fn send(&mut self, request: &SourceRequest) -> Result<()> {
+        let mut line = serde_json::to_string(request)?;
+        line.push('\n');
+        self.stdin.write_all(line.as_bytes())?;
+        self.stdin.flush()?;
+        Ok(())
+    }
+}
+
+impl Drop for ProviderProcess {
+    fn drop(&mut self) {
+        child::kill(&mut self.child);
+    }
+}
+
+/// Ask an external process for the images to show.
+///
+/// The command is run with `sh -c` and receives each [SourceRequest] as a line of JSON on
+/// stdin. For each request it must write a line of JSON on stdout, either
+/// `{"path": "/path/to/image"}` or `{"error": "message"}`.
+/// The process is started again if it exits or doesn't answer in time.
+pub struct ProviderSource {
+    command: String,
+    process: Option<ProviderProcess>,
+    /// When the pending request has been sent
+    requested: Option<Instant>,
+}
+
+impl ProviderSource {
+    pub fn new(command: String) -> Self {
+        Self {
+            command,
+            process: None,
+            requested: None,
+        }
+    }
+
+    fn send(&mut self, request: &SourceRequest) -> Result<()> {
+        let running = match &mut self.process {
+            Some(process) => matches!(process.child.try_wait(), Ok(None)),
+            None => false,
+        };
+        if !running {
+            self.process = Some(ProviderProcess::spawn(&self.command)?);
+        }
+        self.process.as_mut().unwrap().send(request)
+    }
+}
+
+impl WallpaperSource for ProviderSource {
+    fn request(&mut self, request: SourceRequest) {
+        if let Some(process) = &self.process {
+            // Discard the answers to the previous requests
+            while process.lines.try_recv().is_ok() {}
+        }
+        match self.send(&request) {
+            Ok(()) => self.requested = Some(Instant::now()),
+            Err(err) => {
+                warn!("{err:?}");
+                self.process = None;
+                self.requested = None;
+            }
+        }
+    }
+
+    fn poll(&mut self) -> SourceStatus {
+        let (Some(requested), Some(process)) = (self.requested, &self.process) else {
+            return SourceStatus::Error(format!(
+                "provider {:?} could not receive the request",
+                self.command
+            ));
+        };
+        let line = match process.lines.try_recv() {
+            Ok(line) => line,
+            Err(TryRecvError::Empty) if requested.elapsed() < TIMEOUT => {
+                return SourceStatus::Waiting
+            }
+            Err(TryRecvError::Empty) => {
+                self.process = None;
+                self.requested = None;
+                return SourceStatus::Error(format!(
+                    "provider {:?} did not answer in {}s",
+                    self.command,
+                    TIMEOUT.as_secs()
+                ));
+            }
+            Err(TryRecvError::Disconnected) => {
+                self.process = None;
+                self.requested = None;
+                return SourceStatus::Error(format!("provider {:?} exited", self.command));
+            }
+        };
+        self.requested = None;
+        match serde_json::from_str(&line) {
+            Ok(ProviderResponse::Image { path }) => SourceStatus::Ready(tilde_expansion(&path)),
+            Ok(ProviderResponse::Error { error }) => {
+                SourceStatus::Error(format!("provider {:?}: {error}", self.command))
+            }
+            Err(err) => SourceStatus::Error(format!(
+                "provider {:?} sent an invalid answer {line:?}: {err}",
+                self.command
+            )),
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use color_eyre::{
+    eyre::{anyhow, Context},
+    Result,
+};
+use image::ImageFormat;
+
+use super::{SourceRequest, SourceStatus, WallpaperSource};
+use crate::child::read_to_end_limited;
+
+/// Images bigger than this are refused
+const MAX_IMAGE_SIZE: u64 = 100 * 1024 * 1024;
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Download a new image from the url every time the image must change, both for next and
+/// previous. Useful for the services returning a random image on each request.
+pub struct UrlSource {
+    url: String,
+    /// Where the downloaded images are stored
+    cache_dir: PathBuf,
+    output: String,
+    /// Alternate between two files, so that the image shown is not overwritten
+    downloads: usize,
+    thread_handle: Option<JoinHandle<Result<PathBuf>>>,
+}
+
+impl UrlSource {
+    pub fn new(url: String, output: &str) -> Self {
+        let cache_dir = dirs::cache_dir()
+            .unwrap_or_else(std::env::temp_dir)
+            .join("wpaperd")
+            .join("url");
+        Self {
+            url,
+            cache_dir,
+            output: output.to_string(),
+            downloads: 0,
+            thread_handle: None,
+        }
+    }
+}
+
+impl WallpaperSource for UrlSource {
+    fn request(&mut self, _request: SourceRequest) {
+        let url = self.url.clone();
+        let cache_dir = self.cache_dir.clone();
+        let name = format!("{}-{}", self.output, self.downloads);
+        self.downloads = (self.downloads + 1) % 2;
+        self.thread_handle = Some(std::thread::spawn(move || {
+            download(&url, &cache_dir, &name)
+        }));
+    }
+
+    fn poll(&mut self) -> SourceStatus {
+        let Some(handle) = self.thread_handle.take() else {
+            return SourceStatus::Waiting;
+        };
+        if !handle.is_finished() {
+            self.thread_handle = Some(handle);
+            return SourceStatus::Waiting;
+        }
+        match handle.join() {
+            Ok(Ok(path)) => SourceStatus::Ready(path),
+            Ok(Err(err)) => SourceStatus::Error(format!("{err:?}")),
+            Err(err) => SourceStatus::Error(format!("{err:?}")),
+        }
+    }
+}
+
+/// Download the image into `dir`, adding the extension matching its content type to `name`
+fn download(url: &str, dir: &Path, name: &str) -> Result<PathBuf> {
+    let response = ureq::get(url)
+        .timeout(TIMEOUT)
+        .call()
+        .with_context(|| format!("downloading {url}"))?;
+    // The image loader needs the extension to know the format
+    let extension = ImageFormat::from_mime_type(response.content_type())
+        .and_then(|format| format.extensions_str().first())
+        .ok_or_else(|| {
+            anyhow!(
+                "{url} returned content of type {}, instead of an image",
+                response.content_type()
+            )
+        })?;
+    let file = dir.join(format!("{name}.{extension}"));
+
+    let data = read_to_end_limited(response.into_reader(), MAX_IMAGE_SIZE)
+        .with_context(|| format!("downloading {url}"))?;
+    fs::create_dir_all(dir).with_context(|| format!("creating directory {dir:?}"))?;
+    fs::write(&file, data).with_context(|| format!("writing file {file:?}"))?;
+    Ok(file)
+}
@@ -0,0 +1,155 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use color_eyre::{eyre::Context, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+/// Information about each display that is kept between restarts
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DisplayState {
+    /// The last image drawn on the display
+    #[serde(rename = "last-shown")]
+    pub last_shown: Option<PathBuf>,
+    /// The duration set with `wpaperctl set-duration --persist`
+    #[serde(default, with = "humantime_serde")]
+    pub duration: Option<Duration>,
+}
+
+/// How often an image has been shown, on any display
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ImageStats {
+    /// Seconds since the epoch, compared first by `least-recently-shown`
+    #[serde(rename = "last-shown-at")]
+    pub last_shown_at: u64,
+    #[serde(rename = "times-shown")]
+    pub times_shown: u64,
+}
+
+/// State of wpaperd persisted into XDG_STATE_HOME/wpaperd/state.json
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    #[serde(default)]
+    displays: HashMap<String, DisplayState>,
+    #[serde(default)]
+    images: HashMap<PathBuf, ImageStats>,
+    #[serde(skip)]
+    path: PathBuf,
+    /// Changes that couldn't be written yet, tried again by [State::flush]
+    #[serde(skip)]
+    dirty: bool,
+}
+
+impl State {
+    /// Load the state from the file, an empty state is returned if it can't be read
+    pub fn load(path: &Path) -> Self {
+        let mut state = if path.exists() {
+            match fs::read_to_string(path)
+                .context("reading the file")
+                .and_then(|content| {
+                    serde_json::from_str::<Self>(&content).context("parsing the file")
+                }) {
+                Ok(state) => state,
+                Err(err) => {
+                    warn!("Ignoring the state file {path:?}: {err:?}");
+                    Self::default()
+                }
+            }
+        } else {
+            Self::default()
+        };
+        state.path = path.to_path_buf();
+        state
+    }
+
+    pub fn display(&self, name: &str) -> Option<&DisplayState> {
+        self.displays.get(name)
+    }
+
+    pub fn displays(&self) -> impl Iterator<Item = (&String, &DisplayState)> {
+        self.displays.iter()
+    }
+
+    pub fn set_duration(&mut self, name: &str, duration: Option<Duration>) {
+        let display = self.displays.entry(name.to_string()).or_default();
+        if display.duration == duration {
+            return;
+        }
+        display.duration = duration;
+        self.dirty = true;
+        self.flush();
+    }
+
+    /// Remove the durations set with `wpaperctl set-duration --persist`
+    pub fn clear_durations(&mut self) {
+        if self
+            .displays
+            .values()
+            .all(|display| display.duration.is_none())
+        {
+            return;
+        }
+        for display in self.displays.values_mut() {
+            display.duration = None;
+        }
+        self.dirty = true;
+        self.flush();
+    }
+
+    /// The statistics of an image never shown are zero
+    pub fn image_stats(&self, image: &Path) -> ImageStats {
+        self.images.get(image).copied().unwrap_or_default()
+    }
+
+    /// The last `count` images shown on any display, the most recent first
+    pub fn recently_shown(&self, count: usize) -> Vec<PathBuf> {
+        let mut images: Vec<(&PathBuf, &ImageStats)> = self.images.iter().collect();
+        images.sort_unstable_by_key(|(_, stats)| std::cmp::Reverse(stats.last_shown_at));
+        images
+            .into_iter()
+            .take(count)
+            .map(|(image, _)| image.clone())
+            .collect()
+    }
+
+    /// Called each time a new image is shown on the display
+    pub fn set_last_shown(&mut self, name: &str, image: &Path) {
+        let display = self.displays.entry(name.to_string()).or_default();
+        if display.last_shown.as_deref() == Some(image) {
+            return;
+        }
+        display.last_shown = Some(image.to_path_buf());
+        let stats = self.images.entry(image.to_path_buf()).or_default();
+        stats.times_shown += 1;
+        stats.last_shown_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.dirty = true;
+        self.flush();
+    }
+
+    /// Write the changes not saved yet, called on each change and when wpaperd exits
+    pub fn flush(&mut self) {
+        if !self.dirty {
+            return;
+        }
+        match self.save() {
+            Ok(()) => self.dirty = false,
+            Err(err) => warn!("{err:?}"),
+        }
+    }
+
+    /// Write the state into a temporary file first, so that it can't be left half written
+    fn save(&self) -> Result<()> {
+        let tmp_path = self.path.with_extension("json.tmp");
+        let content = serde_json::to_string_pretty(self)?;
+        fs::write(&tmp_path, content)
+            .and_then(|_| fs::rename(&tmp_path, &self.path))
+            .with_context(|| format!("writing the state file {:?}", self.path))
+    }
+}
@@ -0,0 +1,1926 @@
+use std::{
+    cell::{Ref, RefCell},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use chrono::Local;
+use color_eyre::eyre::{Context, ContextCompat};
+use color_eyre::Result;
+use image::RgbaImage;
+use log::{error, info, warn};
+use smithay_client_toolkit::reexports::calloop::{LoopHandle, RegistrationToken};
+use smithay_client_toolkit::reexports::client::protocol::wl_output::{Transform, WlOutput};
+use smithay_client_toolkit::reexports::client::protocol::wl_surface;
+use smithay_client_toolkit::reexports::client::QueueHandle;
+use smithay_client_toolkit::shell::wlr_layer::{LayerSurface, LayerSurfaceConfigure};
+use smithay_client_toolkit::{
+    reexports::calloop::timer::{TimeoutAction, Timer},
+    shell::WaylandSurface,
+};
+use wpaperd_ipc::{AnimationStatus, ImageMetadata, RuntimeOutput, TextContrast, TraceStage};
+
+use crate::animation::{self, Animation, Step};
+#[cfg(feature = "audio")]
+use crate::audio;
+use crate::cold_start::ColdStart;
+use crate::diagnostic::strip_colors;
+use crate::parallax;
+use crate::render::{untransform_buffer, EglContext, Renderer};
+use crate::sidecar::Sidecar;
+use crate::state::State;
+use crate::sync_clock::SyncClock;
+use crate::trace::Tracer;
+use crate::wall_clock;
+use crate::wpaperd::Wpaperd;
+use crate::{
+    decode_queue::Priority,
+    hooks::{self, PreChangeHook, PreChangeStatus},
+    image_loader::{DisplayFit, ImageInfo, ImageLoader, ImageLoaderStatus, Scaling},
+    image_picker::ImagePicker,
+    metrics::Metrics,
+    text_region::{self, Placement},
+    theme,
+};
+use crate::{
+    display_info::DisplayInfo,
+    wallpaper_info::{BackgroundMode, Recolor, ResizeFilter, TransitionSkip, WallpaperInfo},
+};
+
+#[derive(Debug)]
+pub enum EventSource {
+    NotSet,
+    Running(RegistrationToken),
+    // The contained value is the duration that was left on the previous timer, used for starting the next timer.
+    Paused(Duration),
+}
+
+pub struct Surface {
+    wl_surface: wl_surface::WlSurface,
+    wl_output: WlOutput,
+    layer: LayerSurface,
+    egl_context: EglContext,
+    renderer: Renderer,
+    pub image_picker: ImagePicker,
+    event_source: EventSource,
+    pub wallpaper_info: WallpaperInfo,
+    info: Rc<RefCell<DisplayInfo>>,
+    image_loader: Rc<RefCell<ImageLoader>>,
+    metrics: Rc<RefCell<Metrics>>,
+    tracer: Rc<RefCell<Tracer>>,
+    state: Rc<RefCell<State>>,
+    window_drawn: bool,
+    loading_image: Option<(PathBuf, usize)>,
+    loading_image_tries: u8,
+    /// The settings read from the sidecar file of the image shown
+    image_settings: Sidecar,
+    /// Determines whether we should skip the next transition. Used to skip
+    /// the first transition when starting up.
+    ///
+    /// See [crate::wallpaper_info::WallpaperInfo]'s `initial_transition` field
+    skip_next_transition: bool,
+    /// Pause state of the automatic wallpaper sequence.
+    /// Setting this to true will mean only an explicit next/previous wallpaper command will change
+    /// the wallpaper.
+    should_pause: bool,
+    /// The automatic wallpaper sequence is paused until this instant, see [`Surface::hold`]
+    held_until: Option<Instant>,
+    /// The timer resuming the sequence when the hold expires, along with its deadline
+    hold_timer: Option<(Instant, RegistrationToken)>,
+    /// The quiet hours are in progress, the automatic wallpaper sequence is paused as well
+    quiet: bool,
+    /// A fullscreen window covers the display, the automatic wallpaper sequence is paused as well
+    covered: bool,
+    /// The sequence is inhibited on all the displays, e.g. while a game is running
+    inhibited: bool,
+    /// Zen is enabled, the sequence is frozen so that the same wallpaper is shown again after it
+    zen: bool,
+    /// A preview of the image being loaded is shown, see [ImageLoaderStatus::Preview]
+    preview_shown: bool,
+    /// When the last frame was drawn, by any of the transitions, animations, parallax or audio,
+    /// used to limit the frame rate
+    last_frame_time: Option<Instant>,
+    /// Asks for the next frame once max-fps allows it, see [Surface::request_frame_after]
+    frame_timer: Option<RegistrationToken>,
+    /// The high-contrast color has been loaded in place of the wallpaper
+    high_contrast_shown: bool,
+    /// The zen image is shown in place of the high-contrast color, it could be loaded
+    zen_image_shown: bool,
+    /// The error color has been loaded, no wallpaper could be
+    error_color_shown: bool,
+    /// The frames of the animated image shown, see [crate::animation]
+    animation: Option<Animation>,
+    /// Increased for each image shown, the timers of the previous animations stop
+    animation_id: u64,
+    event_loop_handle: LoopHandle<'static, Wpaperd>,
+    /// Draws the levels of the audio, see [Surface::react_to_audio]
+    #[cfg(feature = "audio")]
+    audio_timer: Option<RegistrationToken>,
+    /// A frame has been requested to draw the new position of the pointer
+    parallax_frame_queued: bool,
+    /// The contrast of the text over the image shown, when text-region is set
+    pub text_contrast: Option<TextContrast>,
+    /// The last error loading the image or drawing it, cleared once an image is shown
+    error: Option<String>,
+    /// The metadata of the image shown, read once when it is committed
+    image_metadata: Option<(PathBuf, ImageMetadata)>,
+    /// Run before the automatic changes, see [crate::hooks]
+    pre_change_hook: PreChangeHook,
+    cold_start: Rc<RefCell<ColdStart>>,
+    /// The first image loaded, kept until the other displays are ready, see [crate::cold_start]
+    held_first_image: Option<ImageLoaderStatus>,
+    /// The image being loaded has been asked for with wpaperctl, it goes before the others
+    requested: bool,
+}
+
+impl Surface {
+    pub fn new(
+        wpaperd: &Wpaperd,
+        wl_layer: LayerSurface,
+        wl_output: WlOutput,
+        info: DisplayInfo,
+        wallpaper_info: WallpaperInfo,
+        egl_display: egl::Display,
+        qh: &QueueHandle<Wpaperd>,
+    ) -> Self {
+        let wl_surface = wl_layer.wl_surface().clone();
+        let egl_context = EglContext::new(egl_display, &wl_surface);
+        // Make the egl context as current to make the renderer creation work
+        egl_context
+            .make_current()
+            .expect("EGL context switching to work");
+
+        // Commit the surface
+        wl_surface.commit();
+
+        let image_picker = ImagePicker::new(
+            &wallpaper_info,
+            &wl_surface,
+            wpaperd.filelist_cache.clone(),
+            wpaperd.wallpaper_groups.clone(),
+            &info.name,
+            wpaperd.state.clone(),
+        );
+
+        let info = Rc::new(RefCell::new(info));
+
+        let renderer = unsafe {
+            create_renderer(&info, &wallpaper_info).expect("unable to create the renderer")
+        };
+        // Images bigger than the textures supported are downscaled while decoding them
+        wpaperd
+            .image_loader
+            .borrow_mut()
+            .set_max_texture_size(renderer.max_texture_size());
+
+        let first_transition = !wallpaper_info.initial_transition;
+        let mut surface = Self {
+            wl_output,
+            layer: wl_layer,
+            info,
+            wl_surface,
+            egl_context,
+            renderer,
+            image_picker,
+            event_source: EventSource::NotSet,
+            wallpaper_info,
+            window_drawn: false,
+            should_pause: false,
+            held_until: None,
+            hold_timer: None,
+            quiet: false,
+            covered: false,
+            inhibited: false,
+            zen: false,
+            high_contrast_shown: false,
+            zen_image_shown: false,
+            error_color_shown: false,
+            animation: None,
+            animation_id: 0,
+            event_loop_handle: wpaperd.filelist_cache.borrow().event_loop_handle().clone(),
+            #[cfg(feature = "audio")]
+            audio_timer: None,
+            parallax_frame_queued: false,
+            text_contrast: None,
+            error: None,
+            image_metadata: None,
+            pre_change_hook: PreChangeHook::default(),
+            cold_start: wpaperd.cold_start.clone(),
+            held_first_image: None,
+            requested: false,
+            last_frame_time: None,
+            frame_timer: None,
+            preview_shown: false,
+            image_loader: wpaperd.image_loader.clone(),
+            metrics: wpaperd.metrics.clone(),
+            tracer: wpaperd.tracer.clone(),
+            state: wpaperd.state.clone(),
+            loading_image: None,
+            loading_image_tries: 0,
+            image_settings: Sidecar::default(),
+            skip_next_transition: first_transition,
+        };
+
+        // Start loading the wallpaper as soon as possible (i.e. surface creation)
+        // It will still be loaded as a texture when we have an openGL context
+        if let Err(err) = surface.load_wallpaper(qh) {
+            warn!("{err:?}");
+        }
+        #[cfg(feature = "audio")]
+        surface.update_audio_timer(qh);
+
+        surface
+    }
+
+    /// Create the EGL context and the renderer again on `egl_display`, after it has been
+    /// initialized with another backend. The image shown is loaded again
+    pub fn recreate_renderer(
+        &mut self,
+        egl_display: egl::Display,
+        qh: &QueueHandle<Wpaperd>,
+    ) -> Result<()> {
+        self.egl_context = EglContext::new(egl_display, &self.wl_surface);
+        self.egl_context.make_current()?;
+        self.renderer = unsafe { create_renderer(&self.info, &self.wallpaper_info)? };
+        self.image_loader
+            .borrow_mut()
+            .set_max_texture_size(self.renderer.max_texture_size());
+        // Show the same image right away, without fading in from the background color
+        self.skip_next_transition = true;
+        self.high_contrast_shown = false;
+        self.error_color_shown = false;
+        self.image_picker.reload();
+        self.resize(qh)
+    }
+
+    /// The driver drawing on this display
+    pub fn driver(&self) -> &str {
+        self.renderer.driver()
+    }
+
+    /// Returns true if something has been drawn to the surface
+    pub fn draw(&mut self, qh: &QueueHandle<Wpaperd>, time: Option<u32>) -> Result<()> {
+        let info = self.info.borrow();
+        let width = info.adjusted_width();
+        let height = info.adjusted_height();
+        // Drop the borrow to self
+        drop(info);
+
+        // Use the correct context before loading the texture and drawing
+        self.egl_context.make_current()?;
+        self.parallax_frame_queued = false;
+
+        let wallpaper_loaded = self.load_wallpaper(qh)?;
+
+        if let Some(delay) = self.frame_delay() {
+            // Skip this frame and draw at the first one that max-fps allows
+            self.request_frame_after(delay, qh);
+            return Ok(());
+        }
+        if self.renderer.transition_running() {
+            // Recalculate the current progress, the transition might end now
+            let transition_running = self.renderer.update_transition_status(time.unwrap_or(0));
+            // If we don't have any time passed, just consider the transition to be ended
+            if transition_running {
+                // Don't call queue_draw as it calls load_wallpaper again
+                self.wl_surface.frame(qh, self.wl_surface.clone());
+            } else {
+                self.renderer.transition_finished();
+            }
+        } else if !wallpaper_loaded {
+            self.wl_surface.frame(qh, self.wl_surface.clone());
+            if self.window_drawn {
+                // We need to call commit, otherwise the call to frame above doesn't work
+                self.wl_surface().commit();
+                return Ok(());
+            }
+        }
+
+        self.last_frame_time = Some(Instant::now());
+        unsafe { self.renderer.draw()? }
+
+        self.renderer.clear_after_draw()?;
+        self.egl_context.swap_buffers()?;
+
+        // Reset the context
+        egl::API
+            .make_current(self.egl_context.display, None, None, None)
+            .context("Resetting the GL context")?;
+
+        // Mark the entire surface as damaged
+        self.wl_surface.damage_buffer(0, 0, width, height);
+
+        // Finally, commit the surface
+        self.wl_surface.commit();
+
+        Ok(())
+    }
+
+    /// The shortest time between two frames with the max-fps of the display, zero without it
+    fn frame_interval(&self) -> Duration {
+        self.wallpaper_info
+            .max_fps
+            .map_or(Duration::ZERO, |max_fps| {
+                Duration::from_secs_f64(1.0 / max_fps.max(1) as f64)
+            })
+    }
+
+    /// How long to wait before drawing the next frame, `None` if the max-fps of the display
+    /// allows it now
+    fn frame_delay(&self) -> Option<Duration> {
+        self.last_frame_time
+            .and_then(|last_frame_time| {
+                self.frame_interval().checked_sub(last_frame_time.elapsed())
+            })
+            .filter(|delay| !delay.is_zero())
+    }
+
+    /// Ask for a frame callback once `delay` has passed. The frame callbacks are not requested
+    /// in the meantime, so that wpaperd doesn't wake up at each refresh of the display
+    fn request_frame_after(&mut self, delay: Duration, qh: &QueueHandle<Wpaperd>) {
+        if self.frame_timer.is_some() {
+            return;
+        }
+        let (name, timer_qh) = (self.name(), qh.clone());
+        self.frame_timer = self
+            .event_loop_handle
+            .insert_source(
+                Timer::from_duration(delay),
+                move |_, _, wpaperd: &mut Wpaperd| {
+                    if let Some(surface) = wpaperd.surface_from_name(&name) {
+                        surface.frame_timer = None;
+                        surface
+                            .wl_surface
+                            .frame(&timer_qh, surface.wl_surface.clone());
+                        surface.wl_surface.commit();
+                    }
+                    TimeoutAction::Drop
+                },
+            )
+            .map_err(|err| warn!("inserting the timer of the next frame in the event loop: {err}"))
+            .ok();
+        if self.frame_timer.is_none() {
+            // Draw at one of the next refreshes instead
+            self.wl_surface.frame(qh, self.wl_surface.clone());
+            self.wl_surface.commit();
+        }
+    }
+
+    /// Same as [`Surface::draw`], but catch any panic happening while rendering, so that a
+    /// faulty display doesn't take down the entire daemon.
+    /// Returns false when the surface is in an unknown state and needs to be recreated.
+    pub fn draw_catching_panic(&mut self, qh: &QueueHandle<Wpaperd>, time: Option<u32>) -> bool {
+        match panic::catch_unwind(AssertUnwindSafe(|| self.draw(qh, time))) {
+            Ok(Ok(())) => true,
+            Ok(Err(err)) => {
+                error!("Error drawing surface: {err:?}");
+                self.metrics.borrow_mut().error();
+                self.error = Some(strip_colors(&format!("{err:#}")));
+                true
+            }
+            Err(_) => {
+                error!("The rendering for display {} panicked", self.name());
+                self.metrics.borrow_mut().error();
+                self.error = Some("the rendering panicked".to_string());
+                false
+            }
+        }
+    }
+
+    // Call surface::frame when this return false
+    pub fn load_wallpaper(&mut self, qh: &QueueHandle<Wpaperd>) -> Result<bool> {
+        if let Some(color) = self.wallpaper_info.high_contrast {
+            self.cold_start.borrow_mut().remove_display(&self.name());
+            // Wait for the running transition to end, draw() keeps requesting new frames
+            if !self.high_contrast_shown && !self.renderer.transition_running() {
+                let image = match self.wallpaper_info.zen_image.clone() {
+                    // Loaded like the other wallpapers, within the decode-limits
+                    Some(path) => match self.image_loader.borrow_mut().background_load(
+                        path.clone(),
+                        self.name(),
+                        false,
+                        self.scaling(),
+                        Priority::Interactive,
+                    ) {
+                        ImageLoaderStatus::Loaded(data, _) => Some(data),
+                        ImageLoaderStatus::Waiting | ImageLoaderStatus::Preview(..) => {
+                            return Ok(false)
+                        }
+                        ImageLoaderStatus::Error => {
+                            warn!("The zen image {path:?} could not be loaded, showing the color");
+                            None
+                        }
+                    },
+                    None => None,
+                };
+                self.egl_context.make_current()?;
+                self.high_contrast_shown = true;
+                self.zen_image_shown = image.is_some();
+                let (mode, offset) = self.mode();
+                self.renderer.load_wallpaper(
+                    image.unwrap_or_else(|| color.image()).into(),
+                    mode,
+                    offset,
+                    ResizeFilter::default(),
+                )?;
+                self.renderer.start_transition(0);
+            }
+            return Ok(true);
+        }
+        // The background color is shown until startup-delay has passed
+        if self.cold_start.borrow().is_delayed() {
+            return Ok(true);
+        }
+
+        Ok(loop {
+            // If we were not already trying to load an image
+            if self.loading_image.is_none() {
+                if let Some(item) = self
+                    .image_picker
+                    .get_image_from_path(&self.wallpaper_info.path, qh)
+                {
+                    if self.image_picker.current_image() == item.0
+                        && !self.image_picker.is_reloading()
+                    {
+                        break true;
+                    } else {
+                        // We are trying to load a new image
+                        self.trace(TraceStage::Pick, None, || {
+                            format!("{:?}, {}", item.0, self.image_picker.queue_state())
+                        });
+                        self.loading_image = Some(item);
+                    }
+                } else if self.image_picker.is_waiting_for_source() {
+                    // poll the source again on the next frame
+                    break false;
+                } else {
+                    // we don't need to load any image
+                    self.cold_start.borrow_mut().remove_display(&self.name());
+                    if self.image_picker.current_image().as_os_str().is_empty()
+                        && self.image_picker.has_no_images(&self.wallpaper_info.path)
+                    {
+                        self.error = Some(format!(
+                            "{:?} does not contain any valid image files",
+                            self.wallpaper_info.path
+                        ));
+                        self.show_error_color()?;
+                    }
+                    break true;
+                }
+            }
+            let (image_path, index) = self
+                .loading_image
+                .as_ref()
+                .expect("loading image to be set")
+                .clone();
+
+            if self.renderer.transition_running() {
+                break true;
+            }
+
+            // Show a preview of big images at startup, when nothing has been drawn yet
+            let preview = self.image_picker.current_image().as_os_str().is_empty();
+            let priority = if self.requested {
+                Priority::Interactive
+            } else {
+                Priority::Visible
+            };
+            let res = match self.held_first_image.take() {
+                Some(res) => res,
+                None => self.image_loader.borrow_mut().background_load(
+                    image_path.to_owned(),
+                    self.name(),
+                    preview,
+                    self.scaling(),
+                    priority,
+                ),
+            };
+            if preview && !matches!(res, ImageLoaderStatus::Waiting) {
+                let mut cold_start = self.cold_start.borrow_mut();
+                cold_start.ready(&self.name());
+                if cold_start.is_holding() {
+                    self.held_first_image = Some(res);
+                    break false;
+                }
+            }
+            match res {
+                ImageLoaderStatus::Loaded(data, info) => {
+                    // The image is moved into the texture, take the colors of the theme first
+                    let theme_samples = self
+                        .wallpaper_info
+                        .theme
+                        .as_ref()
+                        .filter(|_| !self.image_picker.is_reloading())
+                        .map(|_| theme::sample(&data));
+                    self.save_lock_image(&data);
+                    self.load_texture(data, &info, &image_path)?;
+                    if let (Some(theme), Some(samples)) =
+                        (&self.wallpaper_info.theme, theme_samples)
+                    {
+                        theme.apply(samples, image_path.clone());
+                    }
+
+                    self.start_animation(&image_path, qh);
+                    if self.image_picker.is_reloading() {
+                        self.image_picker.reloaded();
+                    } else if self.preview_shown {
+                        // Replace the preview without a transition
+                        self.preview_shown = false;
+                    } else {
+                        self.start_showing(image_path, index, &info, qh);
+                    }
+                    // Restart the counter
+                    self.loading_image_tries = 0;
+                    self.loading_image = None;
+                    self.requested = false;
+                    self.error = None;
+                    break true;
+                }
+                ImageLoaderStatus::Preview(data, info) => {
+                    self.load_texture(data, &info, &image_path)?;
+                    self.start_showing(image_path, index, &info, qh);
+                    self.preview_shown = true;
+                    // Keep polling until the full image has been loaded
+                    break false;
+                }
+                ImageLoaderStatus::Waiting => {
+                    // wait until the image has been loaded
+                    break false;
+                }
+                ImageLoaderStatus::Error => {
+                    self.preview_shown = false;
+                    self.error = Some(format!("could not load image {image_path:?}"));
+                    // We don't want to try too many times
+                    self.loading_image_tries += 1;
+                    // The image we were trying to load failed
+                    self.loading_image = None;
+                    if self.image_loader.borrow().has_timed_out(&image_path) {
+                        // Loading it again would take as long, pick the next one
+                        self.image_picker.next_image(&self.wallpaper_info.path, qh);
+                    }
+                }
+            }
+            // If we have tried too many times, stop
+            if self.loading_image_tries >= 5 {
+                self.show_error_color()?;
+                break true;
+            }
+        })
+    }
+
+    /// Save the copy of the image for the screen lockers, if the display has lock-image set
+    fn save_lock_image(&self, data: &RgbaImage) {
+        let Some(lock_image) = self.wallpaper_info.lock_image else {
+            return;
+        };
+        if self.image_picker.is_reloading() {
+            return;
+        }
+        let display_info = self.display_info();
+        let width = (display_info.width * display_info.scale) as u32;
+        let height = (display_info.height * display_info.scale) as u32;
+        if width > 0 && height > 0 {
+            lock_image.generate(data.clone(), self.name(), width, height);
+        }
+    }
+
+    /// Analyze the region set in text-region and write the result for the bars
+    fn update_text_contrast(
+        &mut self,
+        data: &RgbaImage,
+        mode: BackgroundMode,
+        offset: [Option<f32>; 2],
+        image_path: &Path,
+    ) {
+        let Some(region) = self.wallpaper_info.text_region else {
+            self.text_contrast = None;
+            return;
+        };
+        let placement = {
+            let display_info = self.display_info();
+            Placement {
+                mode,
+                offset,
+                tile_scale: self.wallpaper_info.tile_scale,
+                background_color: self.wallpaper_info.background_color,
+                display: [display_info.width as u32, display_info.height as u32],
+                scale: display_info.scale as u32,
+            }
+        };
+        let contrast = region.analyze(data, &placement, image_path);
+        if let Some(new) = contrast
+            .as_ref()
+            .filter(|new| Some(*new) != self.text_contrast.as_ref())
+        {
+            if let Err(err) = text_region::save(new, &self.name()) {
+                warn!("{err:?}");
+            }
+        }
+        self.text_contrast = contrast;
+    }
+
+    /// Load the image in an openGL texture
+    fn load_texture(&mut self, data: RgbaImage, info: &ImageInfo, image_path: &Path) -> Result<()> {
+        self.error_color_shown = false;
+        self.animation = None;
+        self.image_settings = Sidecar::load(image_path);
+        // Set the correct opengl context
+        self.egl_context.make_current()?;
+        let (mode, offset) = self.mode();
+        self.update_text_contrast(&data, mode, offset, image_path);
+        self.renderer
+            .load_wallpaper(data.into(), mode, offset, self.resize_filter(mode))?;
+        self.renderer
+            .set_color_conversion(info.color_space.conversion(self.wallpaper_info.color_space));
+        Ok(())
+    }
+
+    /// How the images are scaled in the pool of the loader, the renderer only uploads them
+    fn scaling(&self) -> Scaling {
+        let display_info = self.display_info();
+        let display =
+            (self.wallpaper_info.resize_filter == ResizeFilter::Lanczos3).then(|| DisplayFit {
+                width: display_info.scaled_width().try_into().unwrap_or(0),
+                height: display_info.scaled_height().try_into().unwrap_or(0),
+                mode: self.wallpaper_info.mode,
+            });
+        Scaling {
+            max_decode_size: self.wallpaper_info.max_decode_size,
+            linear: self.wallpaper_info.linear_scaling,
+            display,
+            dither: self.wallpaper_info.dither,
+        }
+    }
+
+    fn resize_filter(&self, mode: BackgroundMode) -> ResizeFilter {
+        match mode {
+            // Keep the pixels sharp
+            BackgroundMode::Integer => ResizeFilter::Nearest,
+            _ => self.wallpaper_info.resize_filter,
+        }
+    }
+
+    /// Decode all the frames of the image just loaded in the background, if it is animated, then
+    /// play them with a timer
+    fn start_animation(&mut self, image_path: &Path, qh: &QueueHandle<Wpaperd>) {
+        self.animation_id += 1;
+        self.animation = None;
+        if !self.wallpaper_info.animate || !animation::may_be_animated(image_path) {
+            return;
+        }
+        let frames = self
+            .image_loader
+            .borrow()
+            .load_frames(image_path.to_path_buf(), self.scaling());
+        if self.insert_animation_timer(animation::POLL_INTERVAL, qh) {
+            self.animation = Some(Animation::new(
+                self.animation_id,
+                frames,
+                self.wallpaper_info.start_paused,
+            ));
+        }
+    }
+
+    /// Call [Surface::next_frame] after `delay`, for the current animation
+    fn insert_animation_timer(&self, delay: Duration, qh: &QueueHandle<Wpaperd>) -> bool {
+        let (name, id, qh) = (self.name(), self.animation_id, qh.clone());
+        self.event_loop_handle
+            .insert_source(
+                Timer::from_duration(delay),
+                move |_, _, wpaperd: &mut Wpaperd| match wpaperd.surface_from_name(&name) {
+                    Some(surface) => surface.next_frame(id, &qh),
+                    None => TimeoutAction::Drop,
+                },
+            )
+            .map_err(|err| warn!("inserting the timer of the animation in the event loop: {err}"))
+            .is_ok()
+    }
+
+    /// Draw the next frame of the animation `id`, return when the one after is due
+    fn next_frame(&mut self, id: u64, qh: &QueueHandle<Wpaperd>) -> TimeoutAction {
+        if self.high_contrast_shown || self.error_color_shown {
+            self.animation = None;
+        }
+        let Some(animation) = self
+            .animation
+            .as_mut()
+            .filter(|animation| animation.id == id)
+        else {
+            return TimeoutAction::Drop;
+        };
+        if self.covered || self.quiet || self.inhibited || self.zen {
+            // Nobody sees it, or it stays on its frame while the sequence is paused, e.g. so that
+            // a game keeps stable frame times
+            return TimeoutAction::ToDuration(animation::POLL_INTERVAL);
+        }
+        let (image, delay) = match animation.step() {
+            Step::Wait => return TimeoutAction::ToDuration(animation::POLL_INTERVAL),
+            Step::Show(frame) => (Arc::clone(&frame.image), frame.delay),
+            Step::Paused => return TimeoutAction::Drop,
+            Step::Stop => {
+                self.animation = None;
+                return TimeoutAction::Drop;
+            }
+        };
+        if let Err(err) = self.draw_frame(&image, qh) {
+            warn!("{err:?}");
+            self.animation = None;
+            return TimeoutAction::Drop;
+        }
+        // The frames shorter than max-fps last longer, the animation plays slower
+        TimeoutAction::ToDuration(
+            delay
+                .div_f32(self.wallpaper_info.animation_speed)
+                .max(self.frame_interval()),
+        )
+    }
+
+    /// Replace the image shown with a frame of its animation
+    fn draw_frame(&mut self, image: &RgbaImage, qh: &QueueHandle<Wpaperd>) -> Result<()> {
+        let (mode, _) = self.mode();
+        self.egl_context.make_current()?;
+        self.renderer
+            .replace_frame(image, self.resize_filter(mode))?;
+        // A running transition draws the new frame on its own
+        if self.renderer.transition_running() {
+            Ok(())
+        } else {
+            self.draw(qh, None)
+        }
+    }
+
+    /// The frame of the animation shown, `None` when the image is not animated
+    pub fn animation_status(&self) -> Option<AnimationStatus> {
+        self.animation.as_ref().map(|animation| {
+            let (frame, frames) = animation.position();
+            AnimationStatus {
+                monitor: self.name(),
+                frame,
+                frames,
+                paused: animation.is_paused(),
+            }
+        })
+    }
+
+    /// Pause or resume the animation, return false when the image is not animated
+    pub fn pause_animation(&mut self, paused: bool, qh: &QueueHandle<Wpaperd>) -> bool {
+        let Some(animation) = &mut self.animation else {
+            return false;
+        };
+        if animation.set_paused(paused) {
+            // The frame shown lasts its delay again
+            let delay = animation
+                .frame()
+                .map_or(Duration::ZERO, |frame| frame.delay);
+            let ticking =
+                self.insert_animation_timer(delay.div_f32(self.wallpaper_info.animation_speed), qh);
+            if let Some(animation) = &mut self.animation {
+                animation.ticking = ticking;
+            }
+        }
+        true
+    }
+
+    /// Show `frame` of the animation, return false when it doesn't exist
+    pub fn seek_animation(&mut self, frame: usize, qh: &QueueHandle<Wpaperd>) -> Result<bool> {
+        let Some(image) = self
+            .animation
+            .as_mut()
+            .and_then(|animation| animation.seek(frame))
+            .map(|frame| Arc::clone(&frame.image))
+        else {
+            return Ok(false);
+        };
+        self.draw_frame(&image, qh)?;
+        Ok(true)
+    }
+
+    /// Draw the error color when no wallpaper has been shown yet, instead of leaving the
+    /// background color, so that the display is noticed even without reading the logs
+    fn show_error_color(&mut self) -> Result<()> {
+        if self.error_color_shown || !self.image_picker.current_image().as_os_str().is_empty() {
+            return Ok(());
+        }
+        self.egl_context.make_current()?;
+        self.error_color_shown = true;
+        let (mode, offset) = self.mode();
+        self.renderer.load_wallpaper(
+            self.wallpaper_info.error_color.image().into(),
+            mode,
+            offset,
+            ResizeFilter::default(),
+        )?;
+        self.renderer.start_transition(0);
+        Ok(())
+    }
+
+    /// Start the transition to the image loaded with [Surface::load_texture]
+    fn start_showing(
+        &mut self,
+        image_path: PathBuf,
+        index: usize,
+        info: &ImageInfo,
+        qh: &QueueHandle<Wpaperd>,
+    ) {
+        let transition_time = if self.skip_next_transition || self.skips_transition(info) {
+            0
+        } else {
+            self.wallpaper_info.transition_time
+        };
+        self.skip_next_transition = false;
+
+        if self.image_picker.once_loaded(&image_path) {
+            self.renderer.start_transition(transition_time);
+            return;
+        }
+        self.state
+            .borrow_mut()
+            .set_last_shown(&self.name(), &image_path);
+        if let Some(hooks) = &self.wallpaper_info.hooks {
+            let previous = self.image_picker.current_image();
+            hooks::post_change(hooks, &self.name(), &image_path, &previous);
+        }
+        self.trace(TraceStage::Commit, None, || {
+            format!("{image_path:?}, transition of {transition_time}ms")
+        });
+        self.image_metadata = Some((image_path.clone(), info.metadata.clone()));
+        self.image_picker.update_current_image(image_path, index);
+        self.image_picker.update_mirrors(qh);
+        self.renderer.start_transition(transition_time);
+        self.metrics.borrow_mut().rotation();
+    }
+
+    /// True if the image is one of the kinds set in `transition-skip`
+    fn skips_transition(&self, info: &ImageInfo) -> bool {
+        self.wallpaper_info
+            .transition_skip
+            .iter()
+            .any(|skip| match skip {
+                TransitionSkip::Animated => self.wallpaper_info.animate && info.animated,
+            })
+    }
+
+    /// The mode and offset of the image shown, its sidecar file can override the configuration
+    fn mode(&self) -> (BackgroundMode, [Option<f32>; 2]) {
+        if self.high_contrast_shown && self.zen_image_shown {
+            return (self.wallpaper_info.mode, [None, None]);
+        }
+        if self.high_contrast_shown || self.error_color_shown {
+            // Cover the entire display with the color
+            return (BackgroundMode::Stretch, [None, None]);
+        }
+        let offset = match self.image_settings.offset {
+            Some(offset) => [Some(offset); 2],
+            None => self.wallpaper_info.offset(self.wallpaper_info.offset),
+        };
+        (
+            self.image_settings.mode.unwrap_or(self.wallpaper_info.mode),
+            offset,
+        )
+    }
+
+    /// The high-contrast color is drawn as it is
+    fn recolor(&self) -> Option<Recolor> {
+        self.wallpaper_info
+            .recolor
+            .filter(|_| self.wallpaper_info.high_contrast.is_none())
+    }
+
+    /// How much the wallpaper pulses with the audio, the high-contrast color doesn't
+    #[cfg(feature = "audio")]
+    fn audio_reactive(&self) -> f32 {
+        match self.wallpaper_info.high_contrast {
+            Some(_) => 0.0,
+            None => self.wallpaper_info.audio_reactive,
+        }
+    }
+
+    /// Start drawing the levels of the audio when audio-reactive is set, stop otherwise
+    #[cfg(feature = "audio")]
+    fn update_audio_timer(&mut self, qh: &QueueHandle<Wpaperd>) {
+        let reactive = self.audio_reactive();
+        let (level, bands) = self.renderer.audio();
+        self.renderer.set_audio(reactive, level, bands);
+        match (reactive > 0.0, self.audio_timer.take()) {
+            (true, Some(token)) => self.audio_timer = Some(token),
+            (false, Some(token)) => {
+                self.event_loop_handle.remove(token);
+                self.renderer.set_audio(0.0, 0.0, [0.0; audio::AUDIO_BANDS]);
+                self.queue_draw(qh);
+            }
+            (true, None) => {
+                let (name, qh) = (self.name(), qh.clone());
+                self.audio_timer = self
+                    .event_loop_handle
+                    .insert_source(
+                        Timer::from_duration(audio::FRAME_INTERVAL),
+                        move |_, _, wpaperd: &mut Wpaperd| {
+                            let levels = wpaperd.audio.levels();
+                            match wpaperd.surface_from_name(&name) {
+                                Some(surface) => surface.react_to_audio(levels, &qh),
+                                None => TimeoutAction::Drop,
+                            }
+                        },
+                    )
+                    .map_err(|err| {
+                        warn!("inserting the timer of the audio in the event loop: {err}")
+                    })
+                    .ok();
+            }
+            (false, None) => {}
+        }
+    }
+
+    /// Draw the wallpaper with the new levels of the audio, when they changed visibly
+    #[cfg(feature = "audio")]
+    fn react_to_audio(
+        &mut self,
+        levels: audio::AudioLevels,
+        qh: &QueueHandle<Wpaperd>,
+    ) -> TimeoutAction {
+        let interval = audio::FRAME_INTERVAL.max(self.frame_interval());
+        let (level, bands) = self.renderer.audio();
+        let shown = audio::AudioLevels { level, bands };
+        // Nobody sees it while covered
+        if self.covered || !levels.differs(&shown) {
+            return TimeoutAction::ToDuration(interval);
+        }
+        self.renderer
+            .set_audio(self.audio_reactive(), levels.level, levels.bands);
+        // A running transition draws the new levels on its own
+        if !self.renderer.transition_running() {
+            if let Err(err) = self.draw(qh, None) {
+                warn!("{err:?}");
+            }
+        }
+        TimeoutAction::ToDuration(interval)
+    }
+
+    /// Shift the wallpaper towards the pointer at `position`, when parallax is set
+    pub fn move_pointer(&mut self, position: (f64, f64), qh: &QueueHandle<Wpaperd>) {
+        if self.wallpaper_info.parallax == 0.0 {
+            return;
+        }
+        let info = self.info.borrow();
+        let size = (info.width, info.height);
+        drop(info);
+        let offset = parallax::offset(position, size, self.wallpaper_info.parallax);
+        let shown = self.renderer.parallax_offset();
+        // Skip the moves shorter than a pixel
+        let moved = [size.0, size.1]
+            .into_iter()
+            .enumerate()
+            .any(|(axis, size)| (offset[axis] - shown[axis]).abs() * size as f32 >= 1.0);
+        if !moved {
+            return;
+        }
+        self.renderer
+            .set_parallax(self.wallpaper_info.parallax, offset);
+        // The pointer moves many times between two frames, draw the last position only
+        if !self.parallax_frame_queued {
+            self.parallax_frame_queued = true;
+            self.queue_draw(qh);
+        }
+    }
+
+    /// Compute the brightness again, the light or the time might have changed
+    pub fn update_brightness(&mut self, qh: &QueueHandle<Wpaperd>) {
+        let brightness = brightness(&self.wallpaper_info);
+        // Avoid redrawing for changes that can't be seen
+        if (brightness - self.renderer.brightness()).abs() >= 0.005 {
+            self.renderer.set_brightness(brightness);
+            self.queue_draw(qh);
+        }
+    }
+
+    /// How long the image shown is kept, its sidecar file can override the configuration
+    fn duration(&self) -> Option<Duration> {
+        self.wallpaper_info
+            .duration
+            .map(|duration| self.image_settings.duration.unwrap_or(duration))
+    }
+
+    pub fn name(&self) -> String {
+        self.info.borrow().name.to_string()
+    }
+
+    /// Resize the surface
+    pub fn resize(&mut self, qh: &QueueHandle<Wpaperd>) -> Result<()> {
+        let info = self.info.borrow();
+        let width = info.adjusted_width();
+        let height = info.adjusted_height();
+        // Drop the borrow to self
+        drop(info);
+        // self.layer.set_size(width as u32, height as u32);
+        let display_name = self.name();
+        self.egl_context
+            .resize(&self.wl_surface, width, height)
+            .with_context(|| {
+                format!("unable to switch resize EGL context for display {display_name}",)
+            })?;
+        self.egl_context.make_current().with_context(|| {
+            format!("unable to switch the openGL context for display {display_name}")
+        })?;
+        self.renderer.resize().with_context(|| {
+            format!("unable to resize the GL window for display {display_name}")
+        })?;
+        // If we resize, stop immediately any lingering transition
+        self.renderer.force_transition_end();
+
+        // Queue drawing for the next frame. We can directly draw here, but we would still
+        // need to queue the draw for the next frame, otherwise wpaperd doesn't work at startup
+        self.queue_draw(qh);
+
+        Ok(())
+    }
+
+    pub fn change_size(&mut self, configure: LayerSurfaceConfigure, qh: &QueueHandle<Wpaperd>) {
+        let mut info = self.info.borrow_mut();
+        if info.change_size(configure) {
+            drop(info);
+            if let Err(err) = self.resize(qh) {
+                error!("{err:?}");
+            }
+        }
+    }
+
+    pub fn change_transform(&mut self, transform: Transform, qh: &QueueHandle<Wpaperd>) {
+        let mut info = self.info.borrow_mut();
+        if info.change_transform(transform) {
+            drop(info);
+            self.wl_surface.set_buffer_transform(transform);
+            if let Err(err) = self
+                .resize(qh)
+                .and_then(|_| {
+                    let (mode, offset) = self.mode();
+                    self.renderer.set_mode(mode, offset)
+                })
+                .and_then(|_| unsafe { self.renderer.set_projection_matrix(transform) })
+            {
+                error!("{err:?}");
+            }
+        }
+    }
+
+    pub fn change_scale_factor(&mut self, scale_factor: i32, qh: &QueueHandle<Wpaperd>) {
+        let mut info = self.info.borrow_mut();
+        if info.change_scale_factor(scale_factor) {
+            drop(info);
+            self.wl_surface.set_buffer_scale(scale_factor);
+            // Resize the gl viewport, the image covers a different number of pixels
+            if let Err(err) = self.resize(qh).and_then(|_| {
+                let (mode, offset) = self.mode();
+                self.renderer.set_mode(mode, offset)
+            }) {
+                error!("{err:?}");
+            }
+            // Some compositors send the scale after the first frame has been committed. The
+            // image downscaled on the CPU fits the previous scale and would look blurry
+            let (mode, _) = self.mode();
+            if self.has_been_drawn() && self.resize_filter(mode) == ResizeFilter::Lanczos3 {
+                info!(
+                    "The scale of display {} changed to {scale_factor}, scaling its wallpaper again",
+                    self.name()
+                );
+                self.image_picker.reload();
+                self.queue_draw(qh);
+            }
+        }
+    }
+
+    /// Check that the dimensions are valid
+    pub fn is_configured(&self) -> bool {
+        let info = self.info.borrow();
+        info.width != 0 && info.height != 0
+    }
+
+    pub fn display_info(&self) -> Ref<'_, DisplayInfo> {
+        self.info.borrow()
+    }
+
+    /// Draw the wallpaper again and read it back, as it is shown on the display
+    pub fn screenshot(&mut self) -> Result<RgbaImage> {
+        let info = self.info.borrow();
+        let (width, height) = (info.adjusted_width(), info.adjusted_height());
+        let transform = info.transform;
+        drop(info);
+
+        self.egl_context.make_current()?;
+        // The frame is not swapped, the next draw replaces it
+        let buffer = unsafe {
+            self.renderer
+                .draw()
+                .and_then(|_| self.renderer.read_pixels(width as u32, height as u32))
+        };
+        self.renderer.clear_after_draw()?;
+        egl::API
+            .make_current(self.egl_context.display, None, None, None)
+            .context("Resetting the GL context")?;
+        Ok(untransform_buffer(buffer?, transform))
+    }
+
+    /// Load the first wallpaper without drawing anything, the surface is not mapped until it
+    /// returns true. It replaces the wallpaper of the old instance without a transition, see
+    /// [crate::handoff]
+    pub fn load_first_wallpaper(&mut self, qh: &QueueHandle<Wpaperd>) -> bool {
+        self.skip_next_transition = true;
+        let loaded = self
+            .egl_context
+            .make_current()
+            .and_then(|_| self.load_wallpaper(qh));
+        if let Err(err) = egl::API.make_current(self.egl_context.display, None, None, None) {
+            error!("Resetting the GL context: {err}");
+        }
+        // The errors are shown by the first draw
+        !matches!(loaded, Ok(false))
+    }
+
+    pub fn has_been_drawn(&self) -> bool {
+        self.window_drawn
+    }
+
+    pub fn drawn(&mut self) {
+        self.window_drawn = true;
+    }
+
+    /// Update the wallpaper_info of this Surface
+    /// return true if the duration has changed
+    pub fn update_wallpaper_info(
+        &mut self,
+        handle: &LoopHandle<Wpaperd>,
+        qh: &QueueHandle<Wpaperd>,
+        mut wallpaper_info: WallpaperInfo,
+    ) {
+        if self.wallpaper_info == wallpaper_info {
+            return;
+        }
+
+        // Put the new value in place
+        std::mem::swap(&mut self.wallpaper_info, &mut wallpaper_info);
+        // Reading the same directory with different options results in a different list of files
+        let path_changed = self.wallpaper_info.path != wallpaper_info.path
+            || self.wallpaper_info.filelist_options() != wallpaper_info.filelist_options()
+            || self.wallpaper_info.source != wallpaper_info.source
+            || self.wallpaper_info.script != wallpaper_info.script
+            || self.wallpaper_info.filter != wallpaper_info.filter;
+        if self.wallpaper_info.filter != wallpaper_info.filter {
+            self.image_picker
+                .update_filter(self.wallpaper_info.filter.as_ref());
+        }
+        if self.wallpaper_info.script != wallpaper_info.script {
+            self.image_picker
+                .update_script(self.wallpaper_info.script.as_deref());
+        }
+        if self.wallpaper_info.source != wallpaper_info.source {
+            self.image_picker
+                .update_source(self.wallpaper_info.source.as_ref());
+        }
+        self.image_picker.update_sorting(
+            self.wallpaper_info.picker_sorting(),
+            &self.wallpaper_info.path,
+            self.wallpaper_info.filelist_options(),
+            path_changed,
+            wallpaper_info.drawn_images_queue_size,
+            self.wallpaper_info.seed,
+        );
+        if self.wallpaper_info.mirror != wallpaper_info.mirror {
+            self.image_picker
+                .update_mirror(self.wallpaper_info.mirror.as_deref(), &self.wl_surface);
+            self.queue_draw(qh);
+        }
+        if path_changed || self.wallpaper_info.pool != wallpaper_info.pool {
+            self.image_picker
+                .update_pool(&self.wallpaper_info, &self.wl_surface);
+        }
+        if path_changed
+            && self.wallpaper_info.workspace != wallpaper_info.workspace
+            && !self.wallpaper_info.workspace_transition
+        {
+            // Cut to the wallpaper of the new workspace
+            self.skip_next_transition = true;
+        }
+        if path_changed {
+            // ask the image_picker to pick a new a image
+            self.image_picker.next_image(&self.wallpaper_info.path, qh);
+            self.queue_draw(qh);
+        }
+        let clock = SyncClock::new(&self.wallpaper_info);
+        let clock_changed = self.image_picker.clock() != clock.as_ref();
+        if clock_changed {
+            self.image_picker.update_clock(clock);
+            self.queue_draw(qh);
+        }
+        if self.wallpaper_info.duration != wallpaper_info.duration {
+            // The time spent paused doesn't count
+            let old_duration = wallpaper_info
+                .duration
+                .map(|duration| self.image_settings.duration.unwrap_or(duration));
+            let elapsed = match (&self.event_source, old_duration) {
+                (EventSource::Paused(remaining), Some(old_duration)) => {
+                    old_duration.saturating_sub(*remaining)
+                }
+                _ => self.image_picker.image_changed_instant.elapsed(),
+            };
+            // The image shown might override the duration
+            let update = try_update(self.duration(), elapsed, path_changed);
+            match (update, &self.event_source) {
+                (TimerUpdate::Remove, event_source) => {
+                    if let EventSource::Running(registration_token) = event_source {
+                        handle.remove(*registration_token);
+                    }
+                    self.event_source = EventSource::NotSet;
+                }
+                // Keep it paused, resuming waits for the time left of the new duration
+                (update, EventSource::Paused(_)) => {
+                    self.event_source = EventSource::Paused(update.remaining());
+                }
+                (update, event_source) => {
+                    if let EventSource::Running(registration_token) = event_source {
+                        handle.remove(*registration_token);
+                    }
+                    self.event_source = EventSource::NotSet;
+                    // The next timer will be set to the new duration
+                    self.add_timer(
+                        Some(Timer::from_duration(update.remaining())),
+                        handle,
+                        qh.clone(),
+                    );
+                }
+            }
+        }
+
+        if self.wallpaper_info.schedule != wallpaper_info.schedule || clock_changed {
+            if let EventSource::Running(registration_token) = self.event_source {
+                handle.remove(registration_token);
+            }
+            self.event_source = EventSource::NotSet;
+            // Start the timer for the new schedule or for the duration that replaced it
+            self.add_timer(None, handle, qh.clone());
+        }
+
+        if self.wallpaper_info.animate != wallpaper_info.animate {
+            // Start or stop the animation of the image shown
+            self.image_picker.reload();
+            self.queue_draw(qh);
+        }
+
+        if self.error_color_shown && self.wallpaper_info.error_color != wallpaper_info.error_color {
+            self.error_color_shown = false;
+            if let Err(err) = self.show_error_color() {
+                warn!("{err:?}");
+            }
+            self.queue_draw(qh);
+        }
+
+        if self.wallpaper_info.high_contrast != wallpaper_info.high_contrast
+            || self.wallpaper_info.zen_image != wallpaper_info.zen_image
+        {
+            self.high_contrast_shown = false;
+            if self.wallpaper_info.high_contrast.is_none() {
+                // Show the wallpaper again
+                self.image_picker.reload();
+            }
+            self.queue_draw(qh);
+        }
+
+        if self.wallpaper_info.recolor != wallpaper_info.recolor
+            || self.wallpaper_info.high_contrast != wallpaper_info.high_contrast
+        {
+            self.renderer.set_recolor(self.recolor());
+            self.queue_draw(qh);
+        }
+
+        if self.wallpaper_info.brightness != wallpaper_info.brightness
+            || self.wallpaper_info.high_contrast != wallpaper_info.high_contrast
+        {
+            self.update_brightness(qh);
+        }
+
+        if self.wallpaper_info.parallax != wallpaper_info.parallax {
+            // Back to the middle, until the pointer moves again
+            self.renderer
+                .set_parallax(self.wallpaper_info.parallax, [0.0; 2]);
+            self.queue_draw(qh);
+        }
+
+        #[cfg(feature = "audio")]
+        if self.wallpaper_info.audio_reactive != wallpaper_info.audio_reactive
+            || self.wallpaper_info.high_contrast != wallpaper_info.high_contrast
+        {
+            self.update_audio_timer(qh);
+        }
+
+        self.renderer
+            .set_linear_scaling(self.wallpaper_info.linear_scaling);
+        if (self.wallpaper_info.resize_filter != wallpaper_info.resize_filter
+            || self.wallpaper_info.max_decode_size != wallpaper_info.max_decode_size
+            || self.wallpaper_info.linear_scaling != wallpaper_info.linear_scaling
+            || self.wallpaper_info.dither != wallpaper_info.dither
+            // The filter of the texture changes
+            || (self.wallpaper_info.mode == BackgroundMode::Integer)
+                != (wallpaper_info.mode == BackgroundMode::Integer)
+            || self.wallpaper_info.color_space != wallpaper_info.color_space)
+            && !path_changed
+        {
+            // Scale the image shown again
+            self.image_picker.reload();
+            self.queue_draw(qh);
+        }
+
+        if self.wallpaper_info.transition != wallpaper_info.transition {
+            match self.egl_context.make_current() {
+                Ok(_) => {
+                    let transform = self.renderer.display_info.borrow().transform;
+                    self.renderer
+                        .update_transition(self.wallpaper_info.transition.clone(), transform);
+                    // The uniforms belong to the program, set them again so that the next
+                    // frames keep showing the current image until the new one is loaded
+                    let (mode, offset) = self.mode();
+                    if let Err(err) = self.renderer.set_mode(mode, offset) {
+                        error!("{err:?}");
+                    }
+                }
+                Err(err) => {
+                    error!("{err:?}");
+                }
+            }
+        }
+        if self.wallpaper_info.mode != wallpaper_info.mode
+            || self.wallpaper_info.offset != wallpaper_info.offset
+            || self.wallpaper_info.horizontal_align != wallpaper_info.horizontal_align
+            || self.wallpaper_info.vertical_align != wallpaper_info.vertical_align
+            || self.wallpaper_info.tile_scale != wallpaper_info.tile_scale
+            || self.wallpaper_info.tile_gap != wallpaper_info.tile_gap
+            || self.wallpaper_info.background_color != wallpaper_info.background_color
+        {
+            self.renderer
+                .set_tile(self.wallpaper_info.tile_scale, self.wallpaper_info.tile_gap);
+            self.renderer
+                .set_background_color(self.wallpaper_info.background_color);
+            let (mode, offset) = self.mode();
+            if let Err(err) = self
+                .egl_context
+                .make_current()
+                .and_then(|_| self.renderer.set_mode(mode, offset))
+            {
+                error!("{err:?}");
+            }
+            if !path_changed {
+                // We should draw immediately
+                if let Err(err) = self.draw(qh, None) {
+                    warn!("{err:?}");
+                }
+            }
+        }
+        if self.wallpaper_info.drawn_images_queue_size != wallpaper_info.drawn_images_queue_size {
+            self.image_picker
+                .update_queue_size(self.wallpaper_info.drawn_images_queue_size);
+        }
+        if self.wallpaper_info.seed != wallpaper_info.seed {
+            self.image_picker.update_seed(self.wallpaper_info.seed);
+        }
+        if self.wallpaper_info.transition_time != wallpaper_info.transition_time {
+            self.renderer
+                .update_transition_time(self.wallpaper_info.transition_time);
+        }
+    }
+
+    /// Add a new timer in the event_loop for the current duration or schedule
+    /// Stop if there is already a timer added
+    /// `timer` is ignored when using a schedule or a [SyncClock], the next change happens at the
+    /// time set
+    pub fn add_timer(
+        &mut self,
+        timer: Option<Timer>,
+        handle: &LoopHandle<Wpaperd>,
+        qh: QueueHandle<Wpaperd>,
+    ) {
+        if matches!(self.event_source, EventSource::Running(_)) {
+            return;
+        }
+        let timer = match (&self.wallpaper_info.schedule, self.duration()) {
+            // Change the image at the start of the next slot
+            _ if self.image_picker.clock().is_some() => {
+                let clock = self.image_picker.clock().unwrap();
+                Timer::from_duration(clock.until_slot(clock.current_slot() + 1))
+            }
+            (Some(schedule), _) => {
+                Timer::from_duration(wall_clock::next_check(schedule.until_next()))
+            }
+            (None, Some(duration)) => timer.unwrap_or(Timer::from_duration(duration)),
+            (None, None) => return,
+        };
+        // The time of the next change of the schedule, the timer checks the wall clock until then
+        let mut scheduled_change = self
+            .wallpaper_info
+            .schedule
+            .as_ref()
+            .and_then(|schedule| schedule.next_after(Local::now()));
+
+        let name = self.name().clone();
+        let registration_token = handle
+            .insert_source(
+                timer,
+                move |_deadline, _: &mut (), wpaperd: &mut Wpaperd| {
+                    let surface = match wpaperd
+                        .surface_from_name(&name)
+                        .with_context(|| format!("expecting surface {name} to be available"))
+                    {
+                        Ok(surface) => surface,
+                        Err(err) => {
+                            error!("{err:?}");
+                            return TimeoutAction::Drop;
+                        }
+                    };
+
+                    if let Some(clock) = surface.image_picker.clock().cloned() {
+                        if let Some(action) = surface.wait_pre_change() {
+                            surface.trace(TraceStage::Timer, None, || {
+                                "waiting for the pre-change hook".to_string()
+                            });
+                            return action;
+                        }
+                        let slot = clock.nearest_slot();
+                        surface.trace(TraceStage::Timer, None, || format!("slot {slot}"));
+                        surface
+                            .image_picker
+                            .show_slot(slot, &surface.wallpaper_info.path, &qh);
+                        surface.queue_draw(&qh);
+                        TimeoutAction::ToDuration(clock.until_slot(slot + 1))
+                    } else if let Some(schedule) = surface.wallpaper_info.schedule.clone() {
+                        if let Some(left) = scheduled_change.and_then(wall_clock::until) {
+                            return TimeoutAction::ToDuration(wall_clock::next_check(left));
+                        }
+                        if let Some(action) = surface.wait_pre_change() {
+                            surface.trace(TraceStage::Timer, None, || {
+                                "waiting for the pre-change hook".to_string()
+                            });
+                            return action;
+                        }
+                        surface.trace(TraceStage::Timer, None, || {
+                            format!(
+                                "schedule, next change in {:.1}s",
+                                schedule.until_next().as_secs_f32()
+                            )
+                        });
+                        surface
+                            .image_picker
+                            .next_image(&surface.wallpaper_info.path, &qh);
+                        surface.queue_draw(&qh);
+                        scheduled_change = schedule.next_after(Local::now());
+                        TimeoutAction::ToDuration(wall_clock::next_check(schedule.until_next()))
+                    } else if let Some(duration) = surface.duration() {
+                        // Check that the timer has expired
+                        // if the daemon received a next or previous image command
+                        // the timer will be reset and we need to account that here
+                        // i.e. there is a timer of 1 minute. The user changes the image
+                        // with a previous wallpaper command at 50 seconds.
+                        // The timer will be reset to 1 minute and the image will be changed
+                        if let Some(remaining_time) =
+                            remaining_duration(duration, surface.image_picker.image_changed_instant)
+                        {
+                            surface.trace(TraceStage::Timer, None, || {
+                                format!(
+                                    "the wallpaper has changed since, {:.1}s left",
+                                    remaining_time.as_secs_f32()
+                                )
+                            });
+                            TimeoutAction::ToDuration(remaining_time)
+                        } else if let Some(action) = surface.wait_pre_change() {
+                            surface.trace(TraceStage::Timer, None, || {
+                                "waiting for the pre-change hook".to_string()
+                            });
+                            action
+                        } else {
+                            surface.trace(TraceStage::Timer, None, || {
+                                format!("duration of {:.1}s", duration.as_secs_f32())
+                            });
+                            // Change the drawn image
+                            surface
+                                .image_picker
+                                .next_image(&surface.wallpaper_info.path, &qh);
+                            surface.queue_draw(&qh);
+                            TimeoutAction::ToDuration(duration)
+                        }
+                    } else {
+                        TimeoutAction::Drop
+                    }
+                },
+            )
+            .expect("Failed to insert event source!");
+
+        self.event_source = EventSource::Running(registration_token);
+    }
+
+    /// Record a stage of the rotation of this display, see [crate::trace]
+    fn trace(
+        &self,
+        stage: TraceStage,
+        duration: Option<Duration>,
+        detail: impl FnOnce() -> String,
+    ) {
+        let mut tracer = self.tracer.borrow_mut();
+        if tracer.is_enabled() {
+            tracer.record(&self.name(), stage, duration, detail);
+        }
+    }
+
+    /// Run the `pre-change` hook before an automatic change, return the action of the timer while
+    /// the change has to wait for it
+    fn wait_pre_change(&mut self) -> Option<TimeoutAction> {
+        let hooks = self.wallpaper_info.hooks.as_ref();
+        let wallpaper = self.image_picker.current_image();
+        match self.pre_change_hook.poll(hooks, &self.name(), &wallpaper) {
+            PreChangeStatus::Allowed => None,
+            PreChangeStatus::Running => Some(TimeoutAction::ToDuration(hooks::POLL_INTERVAL)),
+            // The hooks might have been removed while it was running
+            PreChangeStatus::Vetoed => Some(TimeoutAction::ToDuration(
+                hooks.map_or(Duration::ZERO, |hooks| hooks.retry),
+            )),
+        }
+    }
+
+    /// Handle updating the timer based on the pause state of the automatic wallpaper sequence.
+    /// Remove the timer if pausing, and add a new timer with the remaining duration of the old
+    /// timer when resuming.
+    pub fn handle_pause_state(&mut self, handle: &LoopHandle<Wpaperd>, qh: QueueHandle<Wpaperd>) {
+        match (self.held_until, self.hold_timer) {
+            (Some(until), None) => self.add_hold_timer(until, handle, qh.clone()),
+            // The hold has been replaced by a shorter one
+            (Some(until), Some((deadline, registration_token))) if deadline > until => {
+                handle.remove(registration_token);
+                self.add_hold_timer(until, handle, qh.clone());
+            }
+            _ => {}
+        }
+        match (self.sequence_paused(), &self.event_source) {
+            // Should pause, but timer is still currently running
+            (true, EventSource::Running(registration_token)) => {
+                let remaining_duration = self.get_remaining_duration().unwrap_or_default();
+                self.trace(TraceStage::Pause, None, || {
+                    let reasons = [
+                        (self.should_pause(), "paused"),
+                        (self.quiet, "quiet hours"),
+                        (self.covered, "covered by a fullscreen window"),
+                        (self.inhibited, "inhibited"),
+                        (self.zen, "zen"),
+                    ];
+                    let reasons = reasons
+                        .iter()
+                        .filter(|(active, _)| *active)
+                        .map(|(_, reason)| *reason)
+                        .collect::<Vec<_>>();
+                    format!(
+                        "{}, {:.1}s left",
+                        reasons.join(", "),
+                        remaining_duration.as_secs_f32()
+                    )
+                });
+
+                handle.remove(*registration_token);
+                self.event_source = EventSource::Paused(remaining_duration);
+            }
+            // Should resume, but timer is not currently running
+            (false, EventSource::Paused(duration)) => {
+                let duration = *duration;
+                self.trace(TraceStage::Resume, None, || {
+                    format!("next change in {:.1}s", duration.as_secs_f32())
+                });
+                self.add_timer(Some(Timer::from_duration(duration)), handle, qh.clone());
+            }
+            // Otherwise no update is necessary
+            (_, _) => {}
+        }
+    }
+
+    /// Clear [`Surface::held_until`] when it expires. The timer follows the hold when it gets
+    /// extended, see [`Surface::handle_pause_state`] for the shorter ones
+    fn add_hold_timer(
+        &mut self,
+        until: Instant,
+        handle: &LoopHandle<Wpaperd>,
+        qh: QueueHandle<Wpaperd>,
+    ) {
+        let name = self.name();
+        let registration_token = handle
+            .insert_source(
+                Timer::from_deadline(until),
+                move |_deadline, _: &mut (), wpaperd: &mut Wpaperd| {
+                    let Some(surface) = wpaperd.surface_from_name(&name) else {
+                        return TimeoutAction::Drop;
+                    };
+                    match surface.held_until {
+                        Some(until) if until > Instant::now() => {
+                            if let Some((deadline, _)) = &mut surface.hold_timer {
+                                *deadline = until;
+                            }
+                            TimeoutAction::ToInstant(until)
+                        }
+                        _ => {
+                            surface.held_until = None;
+                            surface.hold_timer = None;
+                            surface.image_picker.end_held_show_once();
+                            surface.queue_draw(&qh);
+                            TimeoutAction::Drop
+                        }
+                    }
+                },
+            )
+            .expect("Failed to insert event source!");
+        self.hold_timer = Some((until, registration_token));
+    }
+
+    #[inline]
+    pub fn queue_draw(&mut self, qh: &QueueHandle<Wpaperd>) {
+        // Start loading the next image immediately
+        if let Err(err) = self.load_wallpaper(qh) {
+            warn!("{err:?}");
+        }
+        self.wl_surface.frame(qh, self.wl_surface.clone());
+        self.wl_surface.commit();
+    }
+
+    #[inline]
+    fn get_remaining_duration(&self) -> Option<Duration> {
+        let duration = self.duration()?;
+        remaining_duration(duration, self.image_picker.image_changed_instant)
+    }
+
+    /// How long before the timer changes the wallpaper, `None` while paused
+    fn next_change(&self) -> Option<Duration> {
+        if !matches!(self.event_source, EventSource::Running(_)) {
+            return None;
+        }
+        if let Some(clock) = self.image_picker.clock() {
+            Some(clock.until_slot(clock.current_slot() + 1))
+        } else if let Some(schedule) = &self.wallpaper_info.schedule {
+            Some(schedule.until_next())
+        } else {
+            Some(self.get_remaining_duration().unwrap_or_default())
+        }
+    }
+
+    /// The entry of this display in the state file, see [crate::runtime_state]
+    pub fn runtime_state(&self) -> RuntimeOutput {
+        let wallpaper = self.image_picker.current_image();
+        let next_change = self.next_change().map(|remaining| {
+            (SystemTime::now() + remaining)
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs()
+        });
+        let metadata = self
+            .image_metadata
+            .as_ref()
+            .filter(|(path, _)| *path == wallpaper)
+            .map(|(_, metadata)| metadata.clone());
+        RuntimeOutput {
+            name: self.name(),
+            wallpaper: (!wallpaper.as_os_str().is_empty()).then_some(wallpaper),
+            paused: self.sequence_paused(),
+            next_change,
+            error: self.error.clone(),
+            metadata,
+        }
+    }
+
+    /// Indicate to the main event loop that the automatic wallpaper sequence for this [`Surface`]
+    /// should be paused.
+    /// The actual pausing/resuming is handled in [`Surface::handle_pause_state`]
+    #[inline]
+    pub fn pause(&mut self) {
+        self.should_pause = true;
+    }
+    /// Indicate to the main event loop that the automatic wallpaper sequence for this [`Surface`]
+    /// should be resumed.
+    /// The actual pausing/resuming is handled in [`Surface::handle_pause_state`]
+    #[inline]
+    pub fn resume(&mut self) {
+        self.should_pause = false;
+        self.held_until = None;
+    }
+
+    /// Load the next image before the ones of the other displays and the animations, because
+    /// it has been asked for with wpaperctl
+    #[inline]
+    pub fn request_change(&mut self) {
+        self.requested = true;
+    }
+
+    /// Pause the automatic wallpaper sequence until `until`, then resume it. Calling it again
+    /// replaces the previous hold.
+    /// The actual pausing/resuming is handled in [`Surface::handle_pause_state`]
+    #[inline]
+    pub fn hold(&mut self, until: Instant) {
+        self.held_until = Some(until);
+    }
+
+    /// Pause the automatic wallpaper sequence during the quiet hours, independently of
+    /// [`Surface::pause`].
+    /// The actual pausing/resuming is handled in [`Surface::handle_pause_state`]
+    #[inline]
+    pub fn set_quiet(&mut self, quiet: bool) {
+        self.quiet = quiet;
+    }
+
+    /// Pause the automatic wallpaper sequence while a fullscreen window covers the display, so that
+    /// no image is loaded and drawn without being seen.
+    /// The actual pausing/resuming is handled in [`Surface::handle_pause_state`]
+    #[inline]
+    pub fn set_covered(&mut self, covered: bool) {
+        self.covered = covered;
+    }
+
+    /// Pause the automatic wallpaper sequence while it is inhibited on all the displays, see
+    /// [`crate::inhibit`].
+    /// The actual pausing/resuming is handled in [`Surface::handle_pause_state`]
+    #[inline]
+    pub fn set_inhibited(&mut self, inhibited: bool) {
+        self.inhibited = inhibited;
+    }
+
+    /// Pause the automatic wallpaper sequence while zen is enabled, see
+    /// [`crate::config::Config::zen`].
+    /// The actual pausing/resuming is handled in [`Surface::handle_pause_state`]
+    #[inline]
+    pub fn set_zen(&mut self, zen: bool) {
+        self.zen = zen;
+    }
+
+    /// True if the automatic wallpaper sequence is paused for any reason
+    fn sequence_paused(&self) -> bool {
+        self.should_pause() || self.quiet || self.covered || self.inhibited || self.zen
+    }
+
+    /// Toggle the pause state for this [`Surface`], which is responsible for indicating to the main
+    /// event loop that the automatic wallpaper sequence should be paused.
+    /// The actual pausing/resuming is handled in [`Surface::handle_pause_state`]
+    #[inline]
+    pub fn toggle_pause(&mut self) {
+        if self.should_pause() {
+            self.resume();
+        } else {
+            self.pause();
+        };
+    }
+
+    /// Returns a boolean representing whether this [`Surface`] is set to indicate to the main event
+    /// loop that its automatic wallpaper sequence should be paused.
+    #[inline]
+    pub fn should_pause(&self) -> bool {
+        self.should_pause || self.held_until.is_some()
+    }
+
+    pub fn wl_surface(&self) -> &wl_surface::WlSurface {
+        &self.wl_surface
+    }
+
+    pub fn wl_output(&self) -> &WlOutput {
+        &self.wl_output
+    }
+
+    pub fn layer(&self) -> &LayerSurface {
+        &self.layer
+    }
+}
+
+/// The timers find their surface by name, they would otherwise keep firing for the surface
+/// created again for the same display, which starts its own timers
+impl Drop for Surface {
+    fn drop(&mut self) {
+        #[cfg(feature = "audio")]
+        if let Some(token) = self.audio_timer.take() {
+            self.event_loop_handle.remove(token);
+        }
+        if let EventSource::Running(token) = self.event_source {
+            self.event_loop_handle.remove(token);
+        }
+        if let Some((_, token)) = self.hold_timer.take() {
+            self.event_loop_handle.remove(token);
+        }
+        if let Some(token) = self.frame_timer.take() {
+            self.event_loop_handle.remove(token);
+        }
+    }
+}
+
+/// The high-contrast color is drawn as it is, like for [Surface::recolor]
+fn brightness(wallpaper_info: &WallpaperInfo) -> f32 {
+    wallpaper_info
+        .brightness
+        .filter(|_| wallpaper_info.high_contrast.is_none())
+        .map_or(1.0, |brightness| brightness.now())
+}
+
+/// The renderer shows the background color until the first wallpaper has been loaded, which then
+/// fades in from it
+///
+/// # Safety
+///
+/// The EGL context of the surface must be current on the calling thread.
+unsafe fn create_renderer(
+    info: &Rc<RefCell<DisplayInfo>>,
+    wallpaper_info: &WallpaperInfo,
+) -> Result<Renderer> {
+    let transform = info.borrow().transform;
+    let mut renderer = Renderer::new(
+        wallpaper_info.background_color.image().into(),
+        info.clone(),
+        0,
+        wallpaper_info.transition.clone(),
+        transform,
+    )?;
+    renderer.set_linear_scaling(wallpaper_info.linear_scaling);
+    renderer.set_background_color(wallpaper_info.background_color);
+    renderer.set_recolor(
+        wallpaper_info
+            .recolor
+            .filter(|_| wallpaper_info.high_contrast.is_none()),
+    );
+    renderer.set_tile(wallpaper_info.tile_scale, wallpaper_info.tile_gap);
+    renderer.set_brightness(brightness(wallpaper_info));
+    renderer.set_parallax(wallpaper_info.parallax, [0.0; 2]);
+    Ok(renderer)
+}
+
+/// What happens to the timer of the automatic sequence when a reload changes the duration
+#[derive(Debug, PartialEq, Eq)]
+enum TimerUpdate {
+    /// There is no duration anymore
+    Remove,
+    /// Wait for the time left of the new duration
+    Remaining(Duration),
+    /// The new duration has already expired or the image set has changed, change the image now
+    Immediate,
+}
+
+impl TimerUpdate {
+    fn remaining(&self) -> Duration {
+        match self {
+            TimerUpdate::Remaining(remaining) => *remaining,
+            TimerUpdate::Remove | TimerUpdate::Immediate => Duration::ZERO,
+        }
+    }
+}
+
+/// Replace the timer running for the old duration, instead of waiting for it to expire first.
+/// `elapsed` is how long the image has been shown
+fn try_update(duration: Option<Duration>, elapsed: Duration, path_changed: bool) -> TimerUpdate {
+    match duration {
+        None => TimerUpdate::Remove,
+        Some(_) if path_changed => TimerUpdate::Immediate,
+        Some(duration) => match duration.checked_sub(elapsed) {
+            Some(remaining) if !remaining.is_zero() => TimerUpdate::Remaining(remaining),
+            _ => TimerUpdate::Immediate,
+        },
+    }
+}
+
+fn remaining_duration(duration: Duration, image_changed: Instant) -> Option<Duration> {
+    // The timer has already expired
+    let diff = image_changed.elapsed();
+    if duration.saturating_sub(diff).is_zero() {
+        None
+    } else {
+        Some(duration - diff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_try_update() {
+        let minutes = |minutes: u64| Duration::from_secs(minutes * 60);
+        // Shown for 2 minutes, the duration goes from 10 to 5 minutes
+        assert_eq!(
+            try_update(Some(minutes(5)), minutes(2), false),
+            TimerUpdate::Remaining(minutes(3))
+        );
+        // The longer duration extends the timer
+        assert_eq!(
+            try_update(Some(minutes(30)), minutes(2), false),
+            TimerUpdate::Remaining(minutes(28))
+        );
+        // The new duration has already expired
+        assert_eq!(
+            try_update(Some(minutes(1)), minutes(2), false),
+            TimerUpdate::Immediate
+        );
+        assert_eq!(
+            try_update(Some(minutes(2)), minutes(2), false),
+            TimerUpdate::Immediate
+        );
+        // The new image set is shown at once
+        assert_eq!(
+            try_update(Some(minutes(5)), minutes(2), true),
+            TimerUpdate::Immediate
+        );
+        assert_eq!(try_update(None, minutes(2), false), TimerUpdate::Remove);
+        assert_eq!(TimerUpdate::Immediate.remaining(), Duration::ZERO);
+    }
+}
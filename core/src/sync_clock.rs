@@ -0,0 +1,168 @@
+//! Pick the wallpapers from the time, for `sync = true` and `rotation = "daily"`.
+//!
+//! The time since the unix epoch is split in slots lasting `duration`, or in calendar days. The
+//! image shown during a slot only depends on its number, the seed and the sorting, so all the
+//! instances reading the same files with the same configuration and a synchronized clock (e.g.
+//! using NTP) change to the same image at the same time, without talking to each other.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chrono::{Datelike, Local, NaiveDate, TimeZone};
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+use crate::wallpaper_info::{Rotation, Sorting, WallpaperInfo};
+
+/// The slot that has just started is the one of this instant, the timers can fire a bit early
+const TIMER_MARGIN: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Period {
+    Fixed(Duration),
+    /// The days in the local timezone, counted from the common era
+    Daily,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Order {
+    Random,
+    Ascending,
+    Descending,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncClock {
+    period: Period,
+    seed: u64,
+    order: Order,
+}
+
+impl SyncClock {
+    /// None when the images don't depend on the time
+    pub fn new(wallpaper_info: &WallpaperInfo) -> Option<Self> {
+        let period = match (wallpaper_info.rotation, wallpaper_info.duration) {
+            (Some(Rotation::Daily), _) => Period::Daily,
+            (None, Some(duration)) if wallpaper_info.sync => Period::Fixed(duration),
+            _ => return None,
+        };
+        let order = match wallpaper_info.picker_sorting() {
+            Some(Sorting::Ascending) => Order::Ascending,
+            Some(Sorting::Descending) => Order::Descending,
+            _ => Order::Random,
+        };
+        Some(Self {
+            period,
+            seed: wallpaper_info.seed.unwrap_or_default(),
+            order,
+        })
+    }
+
+    fn since_epoch() -> Duration {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+    }
+
+    /// The slot running at `since_epoch`
+    fn slot_at(&self, since_epoch: Duration) -> u64 {
+        match self.period {
+            Period::Fixed(duration) => {
+                (since_epoch.as_millis() / duration.as_millis().max(1)) as u64
+            }
+            Period::Daily => Local
+                .timestamp_millis_opt(since_epoch.as_millis() as i64)
+                .earliest()
+                .map(|time| time.date_naive().num_days_from_ce() as u64)
+                .unwrap_or_default(),
+        }
+    }
+
+    /// The slot running now
+    pub fn current_slot(&self) -> u64 {
+        self.slot_at(Self::since_epoch())
+    }
+
+    /// The slot starting closest to now, the timers might fire a bit before its start
+    pub fn nearest_slot(&self) -> u64 {
+        let margin = match self.period {
+            Period::Fixed(duration) => TIMER_MARGIN.min(duration / 2),
+            Period::Daily => TIMER_MARGIN,
+        };
+        self.slot_at(Self::since_epoch() + margin)
+    }
+
+    /// Time left until the start of `slot`
+    pub fn until_slot(&self, slot: u64) -> Duration {
+        let start = match self.period {
+            Period::Fixed(duration) => {
+                Duration::from_millis((slot as u128 * duration.as_millis().max(1)) as u64)
+            }
+            Period::Daily => {
+                let midnight = NaiveDate::from_num_days_from_ce_opt(slot as i32)
+                    .and_then(|date| date.and_hms_opt(0, 0, 0))
+                    .and_then(|midnight| Local.from_local_datetime(&midnight).earliest());
+                match midnight {
+                    Some(midnight) => Duration::from_millis(midnight.timestamp_millis() as u64),
+                    // There is no midnight when the DST starts, check again in an hour
+                    None => Self::since_epoch() + Duration::from_secs(60 * 60),
+                }
+            }
+        };
+        start.saturating_sub(Self::since_epoch())
+    }
+
+    /// The index of the image shown during `slot`, out of `len` images. The random order shows
+    /// every image once before repeating them, like the sorted ones
+    pub fn image_index(&self, slot: u64, len: usize) -> usize {
+        let position = (slot % len as u64) as usize;
+        match self.order {
+            Order::Ascending => position,
+            Order::Descending => len - 1 - position,
+            Order::Random => {
+                let round = slot / len as u64;
+                let mut indexes: Vec<usize> = (0..len).collect();
+                indexes.shuffle(&mut StdRng::seed_from_u64(self.seed ^ round));
+                indexes[position]
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_index() {
+        let mut clock = SyncClock {
+            period: Period::Fixed(Duration::from_secs(60)),
+            seed: 42,
+            order: Order::Ascending,
+        };
+        let indexes = |clock: &SyncClock| -> Vec<usize> {
+            (10..15).map(|slot| clock.image_index(slot, 4)).collect()
+        };
+        assert_eq!(indexes(&clock), [2, 3, 0, 1, 2]);
+
+        clock.order = Order::Descending;
+        assert_eq!(indexes(&clock), [1, 0, 3, 2, 1]);
+
+        // Each round of slots shows all the images, in the same order on every instance
+        clock.order = Order::Random;
+        let mut round: Vec<usize> = (8..12).map(|slot| clock.image_index(slot, 4)).collect();
+        assert_eq!(
+            round,
+            (8..12)
+                .map(|slot| clock.image_index(slot, 4))
+                .collect::<Vec<_>>()
+        );
+        round.sort();
+        assert_eq!(round, [0, 1, 2, 3]);
+
+        // The next day starts at the next midnight
+        clock.period = Period::Daily;
+        let until_tomorrow = clock.until_slot(clock.current_slot() + 1);
+        assert!(
+            until_tomorrow > Duration::ZERO && until_tomorrow <= Duration::from_secs(25 * 3600)
+        );
+    }
+}
@@ -0,0 +1,219 @@
+//! Whether light or dark text is readable over a region of the wallpaper, e.g. where a bar or a
+//! clock is drawn.
+//!
+//! The region is set in logical pixels of the display and mapped to the image in the same way as
+//! the shader does, following the background mode and the offset. The contrast ratios are the
+//! ones of WCAG, computed over the average luminance of the pixels sampled in the region.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::{eyre::Context, Result};
+use image::RgbaImage;
+use serde::Deserialize;
+use wpaperd_ipc::TextContrast;
+
+use crate::{
+    render::{default_offset, texture_scale},
+    wallpaper_info::{BackgroundMode, Color},
+};
+
+/// Maximum number of pixels sampled on each axis of the region
+const SAMPLES: u32 = 64;
+
+/// A region of the display, in logical pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TextRegion {
+    #[serde(default)]
+    pub x: u32,
+    #[serde(default)]
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// How the image is drawn on the display
+pub struct Placement {
+    pub mode: BackgroundMode,
+    pub offset: [Option<f32>; 2],
+    pub tile_scale: f32,
+    /// Drawn around the image in integer mode
+    pub background_color: Color,
+    /// Logical size of the display
+    pub display: [u32; 2],
+    pub scale: u32,
+}
+
+impl TextRegion {
+    /// Return the contrast of the text over the region of `image`, `None` if it is outside the
+    /// display
+    pub fn analyze(
+        &self,
+        image: &RgbaImage,
+        placement: &Placement,
+        wallpaper: &Path,
+    ) -> Option<TextContrast> {
+        let [display_width, display_height] = placement.display;
+        let width = self.width.min(display_width.checked_sub(self.x)?);
+        let height = self.height.min(display_height.checked_sub(self.y)?);
+        if width == 0 || height == 0 || image.width() == 0 || image.height() == 0 {
+            return None;
+        }
+        let scale = texture_scale(
+            placement.mode,
+            [
+                (display_width * placement.scale) as f32,
+                (display_height * placement.scale) as f32,
+            ],
+            [image.width() as f32, image.height() as f32],
+            placement.tile_scale,
+        );
+        let offset = placement
+            .offset
+            .map(|offset| offset.unwrap_or(default_offset(placement.mode)));
+
+        let (columns, rows) = (width.min(SAMPLES), height.min(SAMPLES));
+        let mut total = 0.0;
+        for row in 0..rows {
+            for column in 0..columns {
+                // The center of each cell of the grid
+                let point = [
+                    (self.x as f32 + (column as f32 + 0.5) * width as f32 / columns as f32)
+                        / display_width as f32,
+                    (self.y as f32 + (row as f32 + 0.5) * height as f32 / rows as f32)
+                        / display_height as f32,
+                ];
+                total += luminance_at(image, placement, point, scale, offset);
+            }
+        }
+        let luminance = total / (columns * rows) as f32;
+        let light_contrast = contrast_ratio(1.0, luminance);
+        let dark_contrast = contrast_ratio(luminance, 0.0);
+        let foreground = if light_contrast >= dark_contrast {
+            "light"
+        } else {
+            "dark"
+        };
+        Some(TextContrast {
+            foreground: foreground.to_string(),
+            luminance,
+            light_contrast,
+            dark_contrast,
+            wallpaper: wallpaper.to_path_buf(),
+        })
+    }
+}
+
+/// The luminance of the image drawn at `point` of the display, both from 0 to 1
+fn luminance_at(
+    image: &RgbaImage,
+    placement: &Placement,
+    point: [f32; 2],
+    scale: [f32; 2],
+    offset: [f32; 2],
+) -> f32 {
+    let mut uv = [0, 1].map(|axis| (point[axis] - offset[axis]) * scale[axis] + offset[axis]);
+    match placement.mode {
+        BackgroundMode::Tile => uv = uv.map(|value| value.rem_euclid(1.0)),
+        // The borders are black in fit mode, and drawn with the background color in integer mode
+        BackgroundMode::Integer if uv.iter().any(|value| !(0.0..=1.0).contains(value)) => {
+            return luminance(placement.background_color.0)
+        }
+        BackgroundMode::Fit if uv.iter().any(|value| !(0.0..=1.0).contains(value)) => return 0.0,
+        _ => uv = uv.map(|value| value.clamp(0.0, 1.0)),
+    }
+    let x = ((uv[0] * image.width() as f32) as u32).min(image.width() - 1);
+    let y = ((uv[1] * image.height() as f32) as u32).min(image.height() - 1);
+    let [r, g, b, _] = image.get_pixel(x, y).0;
+    luminance([r, g, b])
+}
+
+fn luminance([r, g, b]: [u8; 3]) -> f32 {
+    0.2126 * linear(r) + 0.7152 * linear(g) + 0.0722 * linear(b)
+}
+
+/// Convert a channel from sRGB to linear light
+fn linear(channel: u8) -> f32 {
+    let value = channel as f32 / 255.0;
+    if value <= 0.03928 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn contrast_ratio(lighter: f32, darker: f32) -> f32 {
+    (lighter + 0.05) / (darker + 0.05)
+}
+
+/// Where the contrast of the region of `display` is written, for the bars reading it
+pub fn text_contrast_path(display: &str) -> PathBuf {
+    dirs::runtime_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("wpaperd")
+        .join("text-contrast")
+        .join(format!("{display}.json"))
+}
+
+/// Write `contrast` into [text_contrast_path], replacing the previous file at once
+pub fn save(contrast: &TextContrast, display: &str) -> Result<()> {
+    let path = text_contrast_path(display);
+    let dir = path.parent().expect("the path to have a parent");
+    fs::create_dir_all(dir).with_context(|| format!("creating directory {dir:?}"))?;
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string(contrast)?)
+        .and_then(|_| fs::rename(&tmp, &path))
+        .with_context(|| format!("writing text contrast into {path:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+
+    #[test]
+    fn test_analyze() {
+        // Black on the top half and white on the bottom half
+        let image = RgbaImage::from_fn(100, 100, |_, y| {
+            if y < 50 {
+                Rgba([0, 0, 0, 255])
+            } else {
+                Rgba([255, 255, 255, 255])
+            }
+        });
+        let placement = Placement {
+            mode: BackgroundMode::Center,
+            offset: [None, None],
+            tile_scale: 1.0,
+            background_color: Color::default(),
+            display: [200, 100],
+            scale: 1,
+        };
+        let wallpaper = Path::new("image.png");
+        let bar = TextRegion {
+            x: 0,
+            y: 0,
+            width: 200,
+            height: 20,
+        };
+        let contrast = bar.analyze(&image, &placement, wallpaper).unwrap();
+        assert_eq!(contrast.foreground, "light");
+        assert_eq!(contrast.luminance, 0.0);
+        assert!(contrast.light_contrast > 20.9);
+
+        // In center mode the image fills the display, its top and bottom are cropped
+        let bottom = TextRegion { y: 80, ..bar };
+        let contrast = bottom.analyze(&image, &placement, wallpaper).unwrap();
+        assert_eq!(contrast.foreground, "dark");
+        assert!(contrast.dark_contrast > 20.9);
+
+        // Outside the display
+        assert!(TextRegion { x: 300, ..bar }
+            .analyze(&image, &placement, wallpaper)
+            .is_none());
+    }
+}
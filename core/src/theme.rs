@@ -0,0 +1,270 @@
+//! Colors extracted from the wallpaper, written into templates each time it changes.
+//!
+//! The templates use the placeholders of pywal, e.g. `{color4}`, `{background.strip}` or
+//! `{foreground.rgb}`, so that the configuration files of the bars, terminals and launchers can
+//! follow the wallpaper. The palette is computed with a median cut of a sample of the pixels.
+
+use std::{
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use color_eyre::{eyre::Context, Result};
+use image::RgbaImage;
+use log::warn;
+use serde::{Deserialize, Deserializer};
+
+use crate::{config::tilde_expansion, wallpaper_info::Color, worker::LatestWorker};
+
+/// Number of pixels sampled from the images, enough to find their main colors
+const SAMPLES: u64 = 4096;
+
+/// The templates of all the displays can be the same files, they are written one wallpaper at
+/// a time
+static WORKER: LatestWorker<()> = LatestWorker::new();
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Theme {
+    /// The display whose wallpaper the colors are taken from, the last one changed when unset
+    #[serde(default)]
+    pub output: Option<String>,
+    #[serde(default, rename = "template")]
+    pub templates: Vec<Template>,
+    /// Run with `sh -c` once the templates have been written, to reload the programs using them
+    #[serde(default)]
+    pub reload_command: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Template {
+    #[serde(deserialize_with = "deserialize_path")]
+    pub input: PathBuf,
+    #[serde(deserialize_with = "deserialize_path")]
+    pub output: PathBuf,
+}
+
+fn deserialize_path<'de, D>(deserializer: D) -> Result<PathBuf, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let path = String::deserialize(deserializer)?;
+    Ok(tilde_expansion(Path::new(&path)))
+}
+
+impl Theme {
+    /// Write the templates with the colors of `samples`, taken from the image in `wallpaper`,
+    /// then run the reload command. It happens in another thread, as the command can take a while;
+    /// the wallpapers changed in the meantime are skipped, only the last one is applied
+    pub fn apply(&self, samples: Vec<[u8; 3]>, wallpaper: PathBuf) {
+        let theme = self.clone();
+        WORKER.submit((), move || {
+            let palette = Palette::from_samples(samples);
+            for template in &theme.templates {
+                if let Err(err) = template.write(&palette, &wallpaper) {
+                    warn!("{err:?}");
+                }
+            }
+            let Some(command) = &theme.reload_command else {
+                return;
+            };
+            match Command::new("sh").arg("-c").arg(command).status() {
+                Ok(status) if !status.success() => {
+                    warn!("theme reload-command {command:?} failed with {status}")
+                }
+                Ok(_) => {}
+                Err(err) => warn!("running theme reload-command {command:?}: {err:?}"),
+            }
+        });
+    }
+}
+
+impl Template {
+    fn write(&self, palette: &Palette, wallpaper: &Path) -> Result<()> {
+        let template = fs::read_to_string(&self.input)
+            .with_context(|| format!("reading theme template {:?}", self.input))?;
+        if let Some(parent) = self.output.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("creating directory {parent:?}"))?;
+        }
+        // Replace the file at once, the programs might read it while it is being written
+        let mut tmp = OsString::from(&self.output);
+        tmp.push(".tmp");
+        fs::write(&tmp, palette.render(&template, wallpaper))
+            .and_then(|_| fs::rename(&tmp, &self.output))
+            .with_context(|| format!("writing theme template into {:?}", self.output))
+    }
+}
+
+/// Pick the pixels used to compute the palette, the transparent ones are skipped
+pub fn sample(image: &RgbaImage) -> Vec<[u8; 3]> {
+    let pixels = image.width() as u64 * image.height() as u64;
+    let step = (pixels / SAMPLES).max(1) as usize;
+    image
+        .pixels()
+        .step_by(step)
+        .filter(|pixel| pixel[3] > 0)
+        .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+        .collect()
+}
+
+/// The 16 colors of a terminal: `color0` is the background and `color15` the foreground
+#[derive(Debug, PartialEq)]
+pub struct Palette {
+    pub colors: [Color; 16],
+}
+
+impl Palette {
+    pub fn from_samples(samples: Vec<[u8; 3]>) -> Self {
+        let mut main = quantize(samples, 8);
+        // The images with very few pixels have less colors
+        while main.len() < 8 {
+            main.push(main.last().copied().unwrap_or_default());
+        }
+        let background = mix(main[0], [0, 0, 0], 0.6);
+        let foreground = mix(main[7], [255, 255, 255], 0.6);
+        let mut colors = [Color::default(); 16];
+        colors[0] = Color(background);
+        colors[7] = Color(foreground);
+        colors[8] = Color(mix(background, [255, 255, 255], 0.3));
+        colors[15] = Color(foreground);
+        for (i, color) in main[1..7].iter().enumerate() {
+            colors[i + 1] = Color(*color);
+            colors[i + 9] = Color(*color);
+        }
+        Self { colors }
+    }
+
+    /// Replace the placeholders in `template`, `{{` and `}}` are written as a single brace. The
+    /// text between braces that is not a placeholder is left as it is
+    pub fn render(&self, template: &str, wallpaper: &Path) -> String {
+        let mut rendered = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find(['{', '}']) {
+            rendered.push_str(&rest[..start]);
+            rest = &rest[start..];
+            if rest.starts_with("{{") || rest.starts_with("}}") {
+                rendered.push_str(&rest[..1]);
+                rest = &rest[2..];
+                continue;
+            }
+            let value = rest
+                .strip_prefix('{')
+                .and_then(|rest| rest.find('}').map(|end| &rest[..end]))
+                .and_then(|name| Some((name.len(), self.placeholder(name, wallpaper)?)));
+            match value {
+                Some((len, value)) => {
+                    rendered.push_str(&value);
+                    rest = &rest[len + 2..];
+                }
+                None => {
+                    rendered.push_str(&rest[..1]);
+                    rest = &rest[1..];
+                }
+            }
+        }
+        rendered.push_str(rest);
+        rendered
+    }
+
+    fn placeholder(&self, name: &str, wallpaper: &Path) -> Option<String> {
+        if name == "wallpaper" {
+            return Some(wallpaper.display().to_string());
+        }
+        let (color, format) = name.split_once('.').unwrap_or((name, ""));
+        let index = match color {
+            "background" => 0,
+            "foreground" | "cursor" => 15,
+            _ => color
+                .strip_prefix("color")?
+                .parse()
+                .ok()
+                .filter(|i| *i < 16)?,
+        };
+        let [r, g, b] = self.colors[index].0;
+        match format {
+            "" => Some(format!("#{r:02x}{g:02x}{b:02x}")),
+            "strip" => Some(format!("{r:02x}{g:02x}{b:02x}")),
+            "rgb" => Some(format!("{r},{g},{b}")),
+            _ => None,
+        }
+    }
+}
+
+/// Split the colors into `count` groups with median cut, return their averages from the darkest
+fn quantize(colors: Vec<[u8; 3]>, count: usize) -> Vec<[u8; 3]> {
+    let mut groups = vec![colors];
+    while groups.len() < count {
+        // Split the group with the widest range on one of the channels, then the biggest one
+        let Some((index, channel, _)) = groups
+            .iter()
+            .enumerate()
+            .filter(|(_, group)| group.len() > 1)
+            .flat_map(|(index, group)| {
+                (0..3).map(move |channel| {
+                    let values = group.iter().map(|color| color[channel]);
+                    let range = values.clone().max().unwrap() - values.min().unwrap();
+                    (index, channel, (range, group.len()))
+                })
+            })
+            .max_by_key(|(_, _, key)| *key)
+        else {
+            break;
+        };
+        let mut group = groups.swap_remove(index);
+        group.sort_unstable_by_key(|color| color[channel]);
+        let upper = group.split_off(group.len() / 2);
+        groups.push(group);
+        groups.push(upper);
+    }
+    let mut averages: Vec<[u8; 3]> = groups
+        .iter()
+        .filter(|group| !group.is_empty())
+        .map(|group| {
+            let mut sum = [0u64; 3];
+            for color in group {
+                for (sum, value) in sum.iter_mut().zip(color) {
+                    *sum += *value as u64;
+                }
+            }
+            sum.map(|value| (value / group.len() as u64) as u8)
+        })
+        .collect();
+    averages.sort_by_key(|&[r, g, b]| 299 * r as u32 + 587 * g as u32 + 114 * b as u32);
+    averages
+}
+
+/// Move `color` towards `target` by `amount`, from 0 to 1
+fn mix(color: [u8; 3], target: [u8; 3], amount: f32) -> [u8; 3] {
+    std::array::from_fn(|channel| {
+        (color[channel] as f32 * (1.0 - amount) + target[channel] as f32 * amount).round() as u8
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        // Half dark blue and half light yellow
+        let samples: Vec<[u8; 3]> = (0..100)
+            .map(|i| if i < 50 { [0, 0, 100] } else { [250, 250, 150] })
+            .collect();
+        let palette = Palette::from_samples(samples);
+        assert_eq!(palette.colors[0], Color([0, 0, 40]));
+        assert_eq!(palette.colors[1], Color([0, 0, 100]));
+        assert_eq!(palette.colors[6], Color([250, 250, 150]));
+        assert_eq!(palette.colors[15], Color([253, 253, 213]));
+
+        let template = "#bar {{ background: {background}; color: rgb({foreground.rgb}); }}\n\
+            color4={color4.strip} {color16} {unknown} {wallpaper}";
+        assert_eq!(
+            palette.render(template, Path::new("/tmp/image.png")),
+            "#bar { background: #000028; color: rgb(253,253,213); }\n\
+            color4=fafa96 {color16} {unknown} /tmp/image.png"
+        );
+    }
+}
@@ -0,0 +1,164 @@
+//! Thumbnails of the images a display picks from, for `wpaperctl thumbnails` and
+//! `wpaperctl preview-strip`.
+//!
+//! They are stored in XDG_CACHE_HOME/wpaperd/thumbnails/<size>, named after a [StableHasher] of
+//! the path, the length and the modification time of the image, so that a changed image gets a
+//! new one. They are created in the pool of the image loader, without blocking the event loop.
+//! The strip of the upcoming images of each display is saved as
+//! XDG_CACHE_HOME/wpaperd/preview/<display>.png, replaced each time it is requested.
+
+use std::{
+    fs,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use color_eyre::{eyre::Context, Result};
+use image::{imageops, ImageFormat, RgbaImage};
+use log::warn;
+use rayon::prelude::*;
+
+use crate::{
+    image_loader::{decode_capped, DecodeLimits},
+    stable_hash::StableHasher,
+};
+
+/// Where the thumbnails with their longest side of `size` pixels are stored
+pub fn thumbnails_dir(size: u32) -> PathBuf {
+    dirs::cache_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("wpaperd")
+        .join("thumbnails")
+        .join(size.to_string())
+}
+
+/// Where the preview strip of the display `name` is saved
+pub fn preview_strip_path(name: &str) -> PathBuf {
+    dirs::cache_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("wpaperd")
+        .join("preview")
+        .join(format!("{name}.png"))
+}
+
+/// Return the thumbnail of `image` stored in `dir`, creating it if it's not there
+pub fn thumbnail(image: &Path, size: u32, dir: &Path, limits: &DecodeLimits) -> Result<PathBuf> {
+    let metadata = image
+        .metadata()
+        .with_context(|| format!("reading the metadata of image {image:?}"))?;
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    let hash = StableHasher::new()
+        // The paths never contain a NUL, it ends this one
+        .write(image.as_os_str().as_bytes())
+        .write(&[0])
+        .write(&metadata.len().to_le_bytes())
+        .write(&modified.as_nanos().to_le_bytes())
+        .finish();
+    let thumbnail = dir.join(format!("{hash:016x}.png"));
+    if thumbnail.is_file() {
+        return Ok(thumbnail);
+    }
+
+    fs::create_dir_all(dir).with_context(|| format!("creating directory {dir:?}"))?;
+    let data = decode_capped(image, Some(size), limits, true)?.into_rgba8();
+    // Never leave a partially written thumbnail behind
+    let partial = thumbnail.with_extension("png.part");
+    data.save_with_format(&partial, ImageFormat::Png)
+        .and_then(|_| Ok(fs::rename(&partial, &thumbnail)?))
+        .with_context(|| format!("saving thumbnail {thumbnail:?}"))?;
+    Ok(thumbnail)
+}
+
+/// Create the thumbnails of `images` in parallel, in the thread pool it is called from. Return
+/// them along with their image, skipping the ones that could not be created
+pub fn thumbnails(images: &[PathBuf], size: u32, limits: &DecodeLimits) -> Vec<(PathBuf, PathBuf)> {
+    let dir = thumbnails_dir(size);
+    images
+        .par_iter()
+        .filter_map(|image| match thumbnail(image, size, &dir, limits) {
+            Ok(thumbnail) => Some((image.clone(), thumbnail)),
+            Err(err) => {
+                warn!("{err:?}");
+                None
+            }
+        })
+        .collect()
+}
+
+/// Place the thumbnails side by side, from left to right, each one centered in a transparent
+/// square of `size` pixels, and save them into `path`
+pub fn save_preview_strip(thumbnails: &[PathBuf], size: u32, path: &Path) -> Result<()> {
+    let mut strip = RgbaImage::new(size * thumbnails.len() as u32, size);
+    for (index, thumbnail) in thumbnails.iter().enumerate() {
+        let thumbnail = image::open(thumbnail)
+            .with_context(|| format!("reading thumbnail {thumbnail:?}"))?
+            .into_rgba8();
+        let x = index as u32 * size + (size.saturating_sub(thumbnail.width())) / 2;
+        let y = size.saturating_sub(thumbnail.height()) / 2;
+        imageops::overlay(&mut strip, &thumbnail, x as i64, y as i64);
+    }
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("creating directory {dir:?}"))?;
+    }
+    // The widgets reading it never see a partially written strip
+    let partial = path.with_extension("png.part");
+    strip
+        .save_with_format(&partial, ImageFormat::Png)
+        .and_then(|_| Ok(fs::rename(&partial, path)?))
+        .with_context(|| format!("saving preview strip {path:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbImage;
+
+    #[test]
+    fn test_thumbnail() {
+        let dir = std::env::temp_dir().join(format!("wpaperd-thumbnails-{}", std::process::id()));
+        let image = dir.join("image.png");
+        fs::create_dir_all(&dir).unwrap();
+        RgbImage::new(1000, 500).save(&image).unwrap();
+
+        let first = thumbnail(&image, 100, &dir.join("100"), &DecodeLimits::default()).unwrap();
+        let size = image::image_dimensions(&first).unwrap();
+        // The cached thumbnail is returned
+        let second = thumbnail(&image, 100, &dir.join("100"), &DecodeLimits::default()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(size, (100, 50));
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn test_preview_strip() {
+        let dir = std::env::temp_dir().join(format!("wpaperd-strip-{}", std::process::id()));
+        let wide = dir.join("wide.png");
+        let tall = dir.join("tall.png");
+        fs::create_dir_all(&dir).unwrap();
+        RgbaImage::from_pixel(100, 50, image::Rgba([255, 0, 0, 255]))
+            .save(&wide)
+            .unwrap();
+        RgbaImage::from_pixel(50, 100, image::Rgba([0, 0, 255, 255]))
+            .save(&tall)
+            .unwrap();
+
+        let strip_path = dir.join("preview").join("DP-1.png");
+        save_preview_strip(&[wide, tall], 100, &strip_path).unwrap();
+        let strip = image::open(&strip_path).unwrap().into_rgba8();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(strip.dimensions(), (200, 100));
+        // Each thumbnail is centered in its square
+        assert_eq!(strip.get_pixel(50, 10).0, [0, 0, 0, 0]);
+        assert_eq!(strip.get_pixel(50, 50).0, [255, 0, 0, 255]);
+        assert_eq!(strip.get_pixel(110, 50).0, [0, 0, 0, 0]);
+        assert_eq!(strip.get_pixel(150, 50).0, [0, 0, 255, 255]);
+    }
+}
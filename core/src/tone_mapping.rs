@@ -0,0 +1,120 @@
+//! The conversion of the high bit depth images, e.g. the 16-bit TIFF and the EXR renders, to the 8
+//! bits per channel of the textures.
+//!
+//! The floating point images hold linear light that can go beyond 1.0, so they are exposed,
+//! compressed into 0..1 by the operator and encoded in sRGB, kept with 16 bits; clamping them
+//! would burn the highlights and show them too dark. The 16-bit images are already encoded for
+//! the display. Both are scaled with their 16 bits and only reduced to 8 bits at the end, see
+//! [crate::dither], following `dither`.
+
+use image::DynamicImage;
+use serde::Deserialize;
+
+use crate::color_space::srgb_encode;
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ToneMapping {
+    pub operator: Operator,
+    /// In stops, applied to the floating point images before the operator
+    pub exposure: f32,
+    /// Dither the images when reducing them to 8 bits, see [crate::dither]
+    pub dither: bool,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        Self {
+            operator: Operator::default(),
+            exposure: 0.0,
+            dither: true,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Operator {
+    /// Clamp the values above 1.0
+    Clip,
+    /// x / (1 + x), keeps the colors but flattens the contrast
+    Reinhard,
+    /// The filmic curve of ACES, as approximated by Krzysztof Narkowicz
+    #[default]
+    Aces,
+}
+
+impl Operator {
+    fn map(self, value: f32) -> f32 {
+        let value = value.max(0.0);
+        match self {
+            Operator::Clip => value,
+            Operator::Reinhard => value / (1.0 + value),
+            Operator::Aces => {
+                (value * (2.51 * value + 0.03)) / (value * (2.43 * value + 0.59) + 0.14)
+            }
+        }
+        .min(1.0)
+    }
+}
+
+impl ToneMapping {
+    /// Map the floating point images to 16-bit sRGB, the others are returned as they are
+    pub fn apply(&self, image: DynamicImage) -> DynamicImage {
+        match image {
+            DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => {
+                let mut image = image.into_rgba32f();
+                let scale = self.exposure.exp2();
+                for pixel in image.pixels_mut() {
+                    for channel in &mut pixel.0[..3] {
+                        *channel = srgb_encode(self.operator.map(*channel * scale));
+                    }
+                }
+                DynamicImage::ImageRgba16(DynamicImage::ImageRgba32F(image).into_rgba16())
+            }
+            image => image,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{ImageBuffer, Rgb};
+
+    use super::*;
+    use crate::dither::quantize;
+
+    #[test]
+    fn test_apply() {
+        // Linear light: black, mid gray and a highlight far above 1.0
+        let hdr = ImageBuffer::from_fn(3, 1, |x, _| Rgb([[0.0, 0.18, 16.0][x as usize]; 3]));
+        let clip = ToneMapping {
+            operator: Operator::Clip,
+            ..Default::default()
+        };
+        let image = quantize(clip.apply(DynamicImage::ImageRgb32F(hdr.clone())), false);
+        assert_eq!(image.get_pixel(0, 0).0, [0, 0, 0, 255]);
+        // Mid gray is encoded in sRGB, not left at 18%
+        assert_eq!(image.get_pixel(1, 0).0[0], 118);
+        assert_eq!(image.get_pixel(2, 0).0[0], 255);
+
+        // The highlight is compressed, one more stop brightens the midtones
+        let aces = ToneMapping::default().apply(DynamicImage::ImageRgb32F(hdr.clone()));
+        // The levels are kept for the scaling
+        assert!(aces.as_rgba16().is_some());
+        let aces = quantize(aces, false);
+        assert!(aces.get_pixel(2, 0).0[0] >= 250);
+        let exposed = ToneMapping {
+            exposure: 1.0,
+            ..Default::default()
+        };
+        let brighter = quantize(exposed.apply(DynamicImage::ImageRgb32F(hdr)), false);
+        assert!(brighter.get_pixel(1, 0).0[0] > aces.get_pixel(1, 0).0[0]);
+
+        // The images already encoded for the display are left to the scaling
+        let rgb16 = DynamicImage::ImageRgb16(ImageBuffer::from_pixel(4, 4, Rgb([33025u16; 3])));
+        assert_eq!(ToneMapping::default().apply(rgb16.clone()), rgb16);
+        let rgb8 = DynamicImage::new_rgb8(1, 1);
+        assert_eq!(ToneMapping::default().apply(rgb8.clone()), rgb8);
+    }
+}
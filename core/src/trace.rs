@@ -0,0 +1,124 @@
+//! The stages of the rotation of the wallpapers, returned by `wpaperctl trace`.
+//!
+//! Nothing is recorded until the trace is started, by `wpaperd --trace` or by the first
+//! `wpaperctl trace`. Then each stage of every display is recorded with its time: the timer
+//! firing, the automatic changes being paused or resumed, the image picked with the state of the
+//! queue, decoding and scaling it in the thread pool, and committing it. Only the last [CAPACITY]
+//! events are kept.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use chrono::Local;
+use log::info;
+use wpaperd_ipc::{TraceEvent, TraceStage};
+
+pub const CAPACITY: usize = 1000;
+
+#[derive(Default)]
+pub struct Tracer {
+    enabled: bool,
+    events: VecDeque<TraceEvent>,
+    next_id: u64,
+    /// When the image being loaded by each display has been picked
+    picked: HashMap<String, Instant>,
+}
+
+impl Tracer {
+    /// Start recording, return false if it was already
+    pub fn start(&mut self) -> bool {
+        if self.enabled {
+            return false;
+        }
+        info!("Recording the trace of the rotation");
+        self.enabled = true;
+        true
+    }
+
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Record a stage of `monitor`, `detail` is only evaluated while recording
+    pub fn record(
+        &mut self,
+        monitor: &str,
+        stage: TraceStage,
+        duration: Option<Duration>,
+        detail: impl FnOnce() -> String,
+    ) {
+        if !self.enabled {
+            return;
+        }
+        let duration = match stage {
+            TraceStage::Pick => {
+                self.picked.insert(monitor.to_string(), Instant::now());
+                duration
+            }
+            TraceStage::Commit => self
+                .picked
+                .remove(monitor)
+                .map(|picked| picked.elapsed())
+                .or(duration),
+            _ => duration,
+        };
+        if self.events.len() == CAPACITY {
+            self.events.pop_front();
+        }
+        self.events.push_back(TraceEvent {
+            id: self.next_id,
+            time: Local::now().format("%Y-%m-%d %H:%M:%S%.3f").to_string(),
+            monitor: monitor.to_string(),
+            stage,
+            duration,
+            detail: detail(),
+        });
+        self.next_id += 1;
+    }
+
+    /// The events recorded after `after`, for the monitors passed or all of them when empty
+    pub fn events(&self, monitors: &[String], after: Option<u64>) -> Vec<TraceEvent> {
+        self.events
+            .iter()
+            .filter(|event| after.map_or(true, |after| event.id > after))
+            .filter(|event| monitors.is_empty() || monitors.contains(&event.monitor))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracer() {
+        let mut tracer = Tracer::default();
+        tracer.record("DP-1", TraceStage::Timer, None, String::new);
+        assert!(tracer.events(&[], None).is_empty());
+
+        assert!(tracer.start());
+        assert!(!tracer.start());
+        tracer.record("DP-1", TraceStage::Pick, None, || "a.png".to_string());
+        tracer.record("HDMI-A-1", TraceStage::Timer, None, String::new);
+        tracer.record("DP-1", TraceStage::Commit, None, String::new);
+        let events = tracer.events(&[], None);
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].detail, "a.png");
+        // The time since the image has been picked
+        assert!(events[2].duration.is_some());
+        let dp1 = tracer.events(&["DP-1".to_string()], Some(0));
+        assert_eq!(dp1.len(), 1);
+        assert_eq!(dp1[0].stage, TraceStage::Commit);
+
+        for _ in 0..CAPACITY {
+            tracer.record("DP-1", TraceStage::Timer, None, String::new);
+        }
+        let events = tracer.events(&[], None);
+        assert_eq!(events.len(), CAPACITY);
+        assert_eq!(events[0].id, 3);
+    }
+}
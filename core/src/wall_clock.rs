@@ -0,0 +1,40 @@
+//! The timers waiting for a time of the day: the cron schedules, midnight and the quiet hours.
+//!
+//! The timers of the event loop follow the monotonic clock, that stops while the system is
+//! suspended: a timer set at 7:00 for 8:00 would fire at 10:00 after sleeping from 7:30 to 9:30.
+//! These timers wake up at least every [CHECK_INTERVAL] instead, and compare the wall clock with
+//! the time they wait for each time, so that they catch up shortly after a resume.
+
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+
+/// How late the timers can fire after a resume
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long a timer waiting `duration` sleeps before checking the wall clock again
+pub fn next_check(duration: Duration) -> Duration {
+    duration.min(CHECK_INTERVAL)
+}
+
+/// How long until `time`, `None` once it has been reached
+pub fn until(time: DateTime<Local>) -> Option<Duration> {
+    (time - Local::now())
+        .to_std()
+        .ok()
+        .filter(|duration| !duration.is_zero())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wall_clock() {
+        assert_eq!(next_check(Duration::from_secs(5)), Duration::from_secs(5));
+        assert_eq!(next_check(Duration::from_secs(3600)), CHECK_INTERVAL);
+        let now = Local::now();
+        assert_eq!(until(now - chrono::Duration::seconds(1)), None);
+        assert!(until(now + chrono::Duration::hours(1)).is_some_and(|left| left > CHECK_INTERVAL));
+    }
+}
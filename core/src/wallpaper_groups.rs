@@ -0,0 +1,194 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use smithay_client_toolkit::reexports::client::{protocol::wl_surface::WlSurface, QueueHandle};
+
+use crate::{image_picker::Queue, wpaperd::Wpaperd};
+
+pub struct WallpaperGroup {
+    pub index: usize,
+    pub current_image: PathBuf,
+    pub loading_image: Option<(usize, PathBuf)>,
+    pub surfaces: HashSet<WlSurface>,
+    pub queue: Queue,
+}
+
+impl WallpaperGroup {
+    pub fn new(queue_size: usize, seed: Option<u64>) -> Self {
+        Self {
+            index: 0,
+            current_image: PathBuf::from(""),
+            loading_image: None,
+            surfaces: HashSet::new(),
+            queue: Queue::with_capacity(queue_size).seeded(seed),
+        }
+    }
+
+    pub fn queue_all_surfaces(&self, qh: &QueueHandle<Wpaperd>) {
+        for surface in &self.surfaces {
+            surface.frame(qh, surface.clone());
+            surface.commit();
+        }
+    }
+}
+
+/// The images shown by the displays using `pool = "shared"` or `pool = "partitioned"` with the
+/// same directory, so that they don't pick the same images
+#[derive(Default)]
+pub struct SharedPool {
+    /// The image shown on each display
+    shown: HashMap<WlSurface, PathBuf>,
+    /// The images recently shown on any display of the pool
+    history: VecDeque<PathBuf>,
+    /// The displays splitting the images between them, by name so that each one always gets the
+    /// same part
+    partitions: BTreeMap<String, WlSurface>,
+}
+
+impl SharedPool {
+    /// `partition` is the name of the display, when it uses `pool = "partitioned"`
+    pub fn join(&mut self, wl_surface: &WlSurface, partition: Option<&str>) {
+        self.shown.entry(wl_surface.clone()).or_default();
+        if let Some(name) = partition {
+            self.partitions.insert(name.to_string(), wl_surface.clone());
+        }
+    }
+
+    pub fn leave(&mut self, wl_surface: &WlSurface) {
+        self.shown.remove(wl_surface);
+        self.partitions.retain(|_, surface| surface != wl_surface);
+    }
+
+    /// The images of `files` that the display can pick, None if it doesn't split them with the
+    /// others. The files are dealt in turn, so that the parts have the same size; when there are
+    /// less files than displays, some of them show the same image
+    pub fn partition(&self, wl_surface: &WlSurface, files: &[PathBuf]) -> Option<Vec<PathBuf>> {
+        let index = self
+            .partitions
+            .values()
+            .position(|surface| surface == wl_surface)?;
+        Some(deal(files, index, self.partitions.len()))
+    }
+
+    /// `path` is now shown on the display, each display remembers `queue_size` images
+    pub fn show(&mut self, wl_surface: &WlSurface, path: &Path, queue_size: usize) {
+        self.shown.insert(wl_surface.clone(), path.to_path_buf());
+        self.history.retain(|image| image != path);
+        self.history.push_back(path.to_path_buf());
+        let max_len = queue_size * self.shown.len();
+        while self.history.len() > max_len {
+            self.history.pop_front();
+        }
+    }
+
+    /// The images that the display should avoid picking
+    pub fn excluded(&self, wl_surface: &WlSurface) -> HashSet<PathBuf> {
+        self.shown
+            .iter()
+            .filter(|(surface, _)| *surface != wl_surface)
+            .map(|(_, path)| path)
+            .chain(&self.history)
+            .filter(|path| !path.as_os_str().is_empty())
+            .cloned()
+            .collect()
+    }
+}
+
+/// The part `index` of `files` split in `count` parts
+fn deal(files: &[PathBuf], index: usize, count: usize) -> Vec<PathBuf> {
+    if files.is_empty() {
+        return Vec::new();
+    }
+    let part: Vec<PathBuf> = files.iter().skip(index).step_by(count).cloned().collect();
+    if part.is_empty() {
+        vec![files[index % files.len()].clone()]
+    } else {
+        part
+    }
+}
+
+/// The image shown by a display and the displays mirroring it
+#[derive(Default)]
+pub struct Mirror {
+    pub image: PathBuf,
+    pub surfaces: HashSet<WlSurface>,
+}
+
+impl Mirror {
+    /// `image` is now shown on the display, draw it on the mirrors too
+    pub fn show(&mut self, image: &Path, qh: &QueueHandle<Wpaperd>) {
+        if self.image == image {
+            return;
+        }
+        self.image = image.to_path_buf();
+        for surface in &self.surfaces {
+            surface.frame(qh, surface.clone());
+            surface.commit();
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct WallpaperGroups {
+    groups: HashMap<u8, Rc<RefCell<WallpaperGroup>>>,
+    /// The shared pools, by directory
+    pools: HashMap<PathBuf, Rc<RefCell<SharedPool>>>,
+    /// By the name of the display being mirrored
+    mirrors: HashMap<String, Rc<RefCell<Mirror>>>,
+}
+
+impl WallpaperGroups {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get_or_insert(
+        &mut self,
+        group: u8,
+        wl_surface: &WlSurface,
+        queue_size: usize,
+        seed: Option<u64>,
+    ) -> Rc<RefCell<WallpaperGroup>> {
+        self.groups
+            .entry(group)
+            .or_insert_with(|| Rc::new(RefCell::new(WallpaperGroup::new(queue_size, seed))));
+        let wp_group = self.groups.get_mut(&group).unwrap();
+        wp_group.borrow_mut().surfaces.insert(wl_surface.clone());
+        wp_group.clone()
+    }
+
+    pub fn get_mirror(&mut self, output: &str) -> Rc<RefCell<Mirror>> {
+        self.mirrors.entry(output.to_string()).or_default().clone()
+    }
+
+    pub fn get_or_insert_pool(
+        &mut self,
+        path: &Path,
+        wl_surface: &WlSurface,
+        partition: Option<&str>,
+    ) -> Rc<RefCell<SharedPool>> {
+        let pool = self.pools.entry(path.to_path_buf()).or_default();
+        pool.borrow_mut().join(wl_surface, partition);
+        pool.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deal() {
+        let files: Vec<PathBuf> = ["a", "b", "c", "d", "e"].map(PathBuf::from).into();
+        assert_eq!(deal(&files, 0, 2), ["a", "c", "e"].map(PathBuf::from));
+        assert_eq!(deal(&files, 1, 2), ["b", "d"].map(PathBuf::from));
+        assert_eq!(deal(&files, 0, 1), files);
+        // More displays than images
+        assert_eq!(deal(&files[..2], 3, 4), [PathBuf::from("b")]);
+        assert!(deal(&[], 0, 2).is_empty());
+    }
+}
@@ -0,0 +1,420 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use image::{Rgba, RgbaImage};
+use serde::Deserialize;
+// Shared with the command lines of wpaperd and wpaperctl
+pub use wpaperd_ipc::{BackgroundMode, ResizeFilter};
+
+use crate::{
+    brightness::Brightness, color_space::ColorSpace, config::tilde_expansion, cron::CronSchedule,
+    filelist_cache::FilelistOptions, filter::ImageFilter, hooks::Hooks, image_picker::ImagePicker,
+    lock_image::LockImage, render::Transition, text_region::TextRegion, theme::Theme,
+};
+
+#[derive(PartialEq, Debug)]
+pub struct WallpaperInfo {
+    pub path: PathBuf,
+    pub duration: Option<Duration>,
+    /// Change the wallpaper at the times matching the expression, `duration` is unset
+    pub schedule: Option<CronSchedule>,
+    /// Change the wallpaper once per period, `duration` and `schedule` are unset
+    pub rotation: Option<Rotation>,
+    pub apply_shadow: bool,
+    pub sorting: Option<Sorting>,
+    /// Go through the images in the opposite order, when they are sorted
+    pub reverse: bool,
+    pub mode: BackgroundMode,
+    pub drawn_images_queue_size: usize,
+    pub transition_time: u32,
+    /// Maximum number of frames drawn each second during the transitions
+    pub max_fps: Option<u32>,
+    pub resize_filter: ResizeFilter,
+    /// The images are decoded with their longest side capped to this size, in pixels
+    pub max_decode_size: Option<u32>,
+    /// Replace the `dither` of the `tone-mapping` section for the images shown on the display
+    pub dither: Option<bool>,
+    /// Scale the images in linear light, slower but the bright details keep their brightness
+    pub linear_scaling: bool,
+    /// The color space of the display
+    pub color_space: ColorSpace,
+    /// Draw the images with the colors of a gradient
+    pub recolor: Option<Recolor>,
+    /// Size of the tiles relative to the image, in tile mode
+    pub tile_scale: f32,
+    /// Space between the tiles in pixels, in tile mode
+    pub tile_gap: u32,
+    /// Play the animated images, see [crate::animation]
+    pub animate: bool,
+    /// Divides the delays of the frames of the animations
+    pub animation_speed: f32,
+    /// The animations stay on their first frame until they are resumed
+    pub start_paused: bool,
+    /// How much the wallpaper pulses with the audio, zero when the `audio` feature is disabled
+    pub audio_reactive: f32,
+    /// Shift the image towards the pointer, see [crate::parallax]
+    pub parallax: f32,
+    /// The workspace whose directory is shown, see [crate::workspaces]
+    pub workspace: Option<String>,
+    /// Show a transition when the workspace changes
+    pub workspace_transition: bool,
+    /// The kinds of images shown without a transition
+    pub transition_skip: Vec<TransitionSkip>,
+
+    /// Determines if we should show the transition between black and first
+    /// wallpaper. `false` means we instantly cut to the first wallpaper,
+    /// `true` means we fade from black to the first wallpaper.
+    pub initial_transition: bool,
+    pub transition: Transition,
+    /// Shown at startup until the first wallpaper has been loaded
+    pub background_color: Color,
+    /// Shown in place of the wallpaper when none can be loaded, e.g. when `path` has no images
+    pub error_color: Color,
+
+    /// Determine the offset for the wallpaper to be drawn into the screen
+    /// Must be from 0.0 to 1.0, by default is 0.0 in tile mode and 0.5 in all the others
+    pub offset: Option<f32>,
+    /// Replace `offset` on each axis
+    pub horizontal_align: Option<HorizontalAlign>,
+    pub vertical_align: Option<VerticalAlign>,
+
+    /// Follow the symlinks when reading the directory in `path`
+    pub follow_symlinks: bool,
+
+    /// Skip the images in `path` that have the same content of another one
+    pub deduplicate: bool,
+
+    /// Skip the images in `path` rated below this number of stars
+    pub min_rating: Option<u8>,
+
+    /// Only show the images in `path` matching this expression
+    pub filter: Option<ImageFilter>,
+
+    /// Seed used to pick the images when sorting is random, so that the order is reproducible
+    pub seed: Option<u64>,
+
+    /// Whether the displays showing the same directory share or split its images
+    pub pool: Pool,
+
+    /// Change the image at the same time as the other instances of wpaperd with the same
+    /// configuration. See [crate::sync_clock::SyncClock]
+    pub sync: bool,
+
+    /// The image to show when wpaperd starts, `None` means that it depends on the sorting
+    pub initial: Option<InitialImage>,
+
+    /// Where the images come from when `path` is not set
+    pub source: Option<Source>,
+
+    /// Script choosing the images in the directory, in place of the sorting
+    pub script: Option<PathBuf>,
+
+    /// Save a blurred and dimmed copy of the images shown, for the screen lockers
+    pub lock_image: Option<LockImage>,
+
+    /// Compute the contrast of the text drawn over this region of the display
+    pub text_region: Option<TextRegion>,
+
+    /// Dim the images in the dark
+    pub brightness: Option<Brightness>,
+
+    /// Commands run before and after the wallpaper changes
+    pub hooks: Option<Hooks>,
+
+    /// Solid color shown in place of the images, when high-contrast or zen is enabled
+    pub high_contrast: Option<Color>,
+    /// Image shown in place of the color above, when zen is enabled
+    pub zen_image: Option<PathBuf>,
+
+    /// Show the same images of this display, the other values are the ones of that display
+    pub mirror: Option<String>,
+
+    /// Write the colors of the images shown into the templates, on the display chosen by the
+    /// theme
+    pub theme: Option<Theme>,
+}
+
+impl Default for WallpaperInfo {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::new(),
+            duration: None,
+            schedule: None,
+            rotation: None,
+            apply_shadow: false,
+            sorting: None,
+            reverse: false,
+            mode: BackgroundMode::default(),
+            drawn_images_queue_size: ImagePicker::DEFAULT_DRAWN_IMAGES_QUEUE_SIZE,
+            transition_time: Transition::Fade {}.default_transition_time(),
+            max_fps: None,
+            resize_filter: ResizeFilter::default(),
+            max_decode_size: None,
+            dither: None,
+            linear_scaling: true,
+            color_space: ColorSpace::default(),
+            recolor: None,
+            tile_scale: 1.0,
+            tile_gap: 0,
+            animate: false,
+            animation_speed: 1.0,
+            start_paused: false,
+            audio_reactive: 0.0,
+            parallax: 0.0,
+            workspace: None,
+            workspace_transition: true,
+            transition_skip: Vec::new(),
+            initial_transition: true,
+            transition: Transition::Fade {},
+            background_color: Color::default(),
+            error_color: Color::ERROR,
+            offset: None,
+            horizontal_align: None,
+            vertical_align: None,
+            follow_symlinks: FilelistOptions::default().follow_symlinks,
+            deduplicate: FilelistOptions::default().deduplicate,
+            min_rating: None,
+            filter: None,
+            seed: None,
+            pool: Pool::default(),
+            sync: false,
+            initial: None,
+            source: None,
+            script: None,
+            lock_image: None,
+            text_region: None,
+            hooks: None,
+            brightness: None,
+            high_contrast: None,
+            zen_image: None,
+            mirror: None,
+            theme: None,
+        }
+    }
+}
+
+impl WallpaperInfo {
+    /// The options used to read the directory in `path`
+    pub fn filelist_options(&self) -> FilelistOptions {
+        FilelistOptions {
+            follow_symlinks: self.follow_symlinks,
+            deduplicate: self.deduplicate,
+            min_rating: self.min_rating,
+            sort_by_date: self.sorting == Some(Sorting::ExifDate),
+        }
+    }
+
+    /// The order used by the image picker: `reverse` is applied and `exif-date` is ascending,
+    /// since the files are already sorted by date
+    pub fn picker_sorting(&self) -> Option<Sorting> {
+        self.sorting.map(|sorting| match (sorting, self.reverse) {
+            (Sorting::Ascending | Sorting::ExifDate, false) | (Sorting::Descending, true) => {
+                Sorting::Ascending
+            }
+            (Sorting::Ascending | Sorting::ExifDate, true) | (Sorting::Descending, false) => {
+                Sorting::Descending
+            }
+            (sorting, _) => sorting,
+        })
+    }
+
+    /// The offset of the image on each axis, `offset` is used for the axes without an alignment
+    pub fn offset(&self, offset: Option<f32>) -> [Option<f32>; 2] {
+        [
+            self.horizontal_align
+                .map(HorizontalAlign::offset)
+                .or(offset),
+            self.vertical_align.map(VerticalAlign::offset).or(offset),
+        ]
+    }
+
+    /// True when the images are shown in order
+    pub fn is_sorted(&self) -> bool {
+        matches!(
+            self.sorting,
+            Some(Sorting::Ascending | Sorting::Descending | Sorting::ExifDate)
+        )
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sorting {
+    #[default]
+    Random,
+    GroupedRandom {
+        group: u8,
+    },
+    Ascending,
+    Descending,
+    /// Ascending, by the date the photos were taken
+    #[serde(rename = "exif-date")]
+    ExifDate,
+    /// Random, preferring the images that have not been shown for the longest time
+    #[serde(rename = "least-recently-shown")]
+    LeastRecentlyShown,
+}
+
+/// The first image drawn when path is a directory
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+#[serde(from = "String")]
+pub enum InitialImage {
+    /// The first image of the directory
+    First,
+    /// A random image, also when the sorting is ascending or descending
+    Random,
+    /// The image shown before wpaperd was stopped, stored in the state file
+    LastShown,
+    /// A specific image
+    Path(PathBuf),
+}
+
+impl From<String> for InitialImage {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "first" => Self::First,
+            "random" => Self::Random,
+            "last-shown" => Self::LastShown,
+            path => Self::Path(tilde_expansion(Path::new(path))),
+        }
+    }
+}
+
+/// The sources that decide the images by themselves, see [crate::source::WallpaperSource]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    /// A file listing the images
+    Playlist(PathBuf),
+    /// A url returning an image
+    Url(String),
+    /// A command answering the requests for new images
+    Provider(String),
+    /// A command run each time, printing the image
+    Command(String),
+    /// The image of the directory modified last, blurred with this standard deviation
+    Newest { dir: PathBuf, blur: Option<f32> },
+}
+
+/// Map the luminance of the images to a gradient, so that they match the colors of the desktop
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Recolor {
+    /// Shades of the color, from black to white
+    Tint(Color),
+    /// From the first color in the shadows to the second one in the highlights
+    Duotone(Color, Color),
+}
+
+impl Recolor {
+    /// The colors that the black, the middle gray and the white of the images become
+    pub fn stops(self) -> [[f32; 3]; 3] {
+        let rgb = |color: Color| color.0.map(|channel| channel as f32 / 255.0);
+        match self {
+            Self::Tint(color) => [[0.0; 3], rgb(color), [1.0; 3]],
+            Self::Duotone(shadow, highlight) => {
+                let (shadow, highlight) = (rgb(shadow), rgb(highlight));
+                let middle = [0, 1, 2].map(|i| (shadow[i] + highlight[i]) / 2.0);
+                [shadow, middle, highlight]
+            }
+        }
+    }
+}
+
+/// A color written as `#rrggbb`
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Color(pub [u8; 3]);
+
+impl TryFrom<String> for Color {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let hex = value.strip_prefix('#').unwrap_or(&value);
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("invalid color {value:?}, expected #rrggbb"));
+        }
+        let channel = |i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+        Ok(Self([channel(0), channel(2), channel(4)]))
+    }
+}
+
+impl Color {
+    /// A dim red, distinct from the black shown at startup
+    pub const ERROR: Color = Color([0x4c, 0x1d, 0x1d]);
+    /// A dark gray, shown by zen when no color is set
+    pub const NEUTRAL: Color = Color([0x30, 0x30, 0x30]);
+
+    /// An image of a single pixel with this color
+    pub fn image(&self) -> RgbaImage {
+        let [r, g, b] = self.0;
+        RgbaImage::from_pixel(1, 1, Rgba([r, g, b, 255]))
+    }
+}
+
+/// How the displays showing the same directory pick their random images
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pool {
+    /// Each display has its own queue and can show the same images of the others
+    #[default]
+    Independent,
+    /// The displays avoid the images shown or recently shown by the others
+    Shared,
+    /// The images are split between the displays, each one only picks the images of its part
+    Partitioned,
+}
+
+/// Rotations following the calendar, the image is picked from the seed and the date
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rotation {
+    /// Show one image per day, changing it at midnight
+    Daily,
+}
+
+/// The part of the image kept when it is cropped horizontally
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HorizontalAlign {
+    Left,
+    Center,
+    Right,
+}
+
+impl HorizontalAlign {
+    fn offset(self) -> f32 {
+        match self {
+            HorizontalAlign::Left => 0.0,
+            HorizontalAlign::Center => 0.5,
+            HorizontalAlign::Right => 1.0,
+        }
+    }
+}
+
+/// The part of the image kept when it is cropped vertically
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VerticalAlign {
+    Top,
+    Center,
+    Bottom,
+}
+
+impl VerticalAlign {
+    fn offset(self) -> f32 {
+        match self {
+            VerticalAlign::Top => 0.0,
+            VerticalAlign::Center => 0.5,
+            VerticalAlign::Bottom => 1.0,
+        }
+    }
+}
+
+/// The images shown without a transition, see `transition-skip`
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransitionSkip {
+    /// The animations played with `animate`, fading into them would move the image twice
+    Animated,
+}
@@ -0,0 +1,212 @@
+//! Watch files and directories for changes.
+//! It uses inotify (via hotwatch) when possible and falls back to polling the modification
+//! time of the files when inotify is not available, i.e. when the watch limit has been
+//! reached or the filesystem doesn't support it.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use color_eyre::{
+    eyre::{Context, Report},
+    Result,
+};
+use hotwatch::{
+    notify::{self, PollWatcher, RecursiveMode, Watcher as _},
+    EventKind, Hotwatch,
+};
+use log::{error, warn};
+
+pub struct Watcher {
+    hotwatch: Option<Hotwatch>,
+    poll_watchers: HashMap<PathBuf, PollWatcher>,
+    poll_interval: Duration,
+}
+
+impl Watcher {
+    pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+    /// Create a new watcher. If `poll_interval` is set, always poll the files
+    /// instead of using inotify.
+    pub fn new(poll_interval: Option<Duration>) -> Self {
+        let hotwatch = if poll_interval.is_some() {
+            None
+        } else {
+            match Hotwatch::new().context("hotwatch failed to initialize") {
+                Ok(hotwatch) => Some(hotwatch),
+                Err(err) => {
+                    warn!("{err:?}\nFalling back to polling for file changes");
+                    None
+                }
+            }
+        };
+
+        Self {
+            hotwatch,
+            poll_watchers: HashMap::new(),
+            poll_interval: poll_interval.unwrap_or(Self::DEFAULT_POLL_INTERVAL),
+        }
+    }
+
+    pub fn watch<F>(&mut self, path: &Path, mut handler: F) -> Result<()>
+    where
+        F: 'static + FnMut(EventKind) + Send + Clone,
+    {
+        self.watch_paths(path, move |kind, _| handler(kind))
+    }
+
+    /// Watch `file` through the directory containing it, so that the handler is also called
+    /// when the file is created, or replaced by renaming another file over it, as done by some
+    /// editors. The directory must exist
+    pub fn watch_file<F>(&mut self, file: &Path, handler: F) -> Result<()>
+    where
+        F: 'static + FnMut(EventKind) + Send + Clone,
+    {
+        self.watch_files(&[file.to_path_buf()], handler).map(|_| ())
+    }
+
+    /// Same as [Watcher::watch_file] for multiple files, each directory is watched once. Return
+    /// the directories watched, to be passed to [Watcher::unwatch]
+    pub fn watch_files<F>(&mut self, files: &[PathBuf], handler: F) -> Result<Vec<PathBuf>>
+    where
+        F: 'static + FnMut(EventKind) + Send + Clone,
+    {
+        let mut dirs: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+        for file in files {
+            let (Some(dir), Some(name)) = (file.parent(), file.file_name()) else {
+                self.watch(file, handler.clone())?;
+                dirs.entry(file.clone()).or_default();
+                continue;
+            };
+            let dir = if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            };
+            let dir = dir
+                .canonicalize()
+                .with_context(|| format!("reading directory {dir:?}"))?;
+            let target = dir.join(name);
+            dirs.entry(dir).or_default().push(target);
+        }
+        for (dir, targets) in &dirs {
+            if targets.is_empty() {
+                continue;
+            }
+            let targets = targets.clone();
+            let mut handler = handler.clone();
+            self.watch_paths(dir, move |kind, paths| {
+                if paths.iter().any(|path| targets.contains(path)) {
+                    handler(kind);
+                }
+            })?;
+        }
+        Ok(dirs.into_keys().collect())
+    }
+
+    fn watch_paths<F>(&mut self, path: &Path, mut handler: F) -> Result<()>
+    where
+        F: 'static + FnMut(EventKind, &[PathBuf]) + Send + Clone,
+    {
+        if let Some(hotwatch) = &mut self.hotwatch {
+            let mut hotwatch_handler = handler.clone();
+            match hotwatch.watch(path, move |event: hotwatch::Event| {
+                hotwatch_handler(event.kind, &event.paths)
+            }) {
+                Ok(()) => return Ok(()),
+                Err(err) => warn!(
+                    "{:?}\nFalling back to polling for file changes",
+                    Report::new(err).wrap_err(format!("watching {path:?} with inotify"))
+                ),
+            }
+        }
+
+        let path_clone = path.to_path_buf();
+        let mut poll_watcher = PollWatcher::new(
+            move |res: notify::Result<notify::Event>| match res {
+                Ok(event) => handler(event.kind, &event.paths),
+                Err(err) => error!("error while polling {path_clone:?}: {err:?}"),
+            },
+            notify::Config::default().with_poll_interval(self.poll_interval),
+        )
+        .context("creating the polling watcher")?;
+        poll_watcher
+            .watch(path, RecursiveMode::Recursive)
+            .with_context(|| format!("polling {path:?} for changes"))?;
+        self.poll_watchers.insert(path.to_path_buf(), poll_watcher);
+
+        Ok(())
+    }
+
+    pub fn unwatch(&mut self, path: &Path) -> Result<()> {
+        // Dropping the watcher stops its thread
+        if self.poll_watchers.remove(path).is_some() {
+            return Ok(());
+        }
+
+        if let Some(hotwatch) = &mut self.hotwatch {
+            hotwatch
+                .unwatch(path)
+                .with_context(|| format!("unwatching {path:?}"))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The symlinks are followed at most this many times, like the kernel does
+const MAX_SYMLINKS: usize = 40;
+
+/// `file` followed by the targets of the symlinks leading from it to the actual file, stopping
+/// at the first one whose directory doesn't exist. The watch on the inode of a target is lost
+/// when it's replaced, watch these through their directories instead
+pub fn symlink_chain(file: &Path) -> Vec<PathBuf> {
+    let mut chain = vec![file.to_path_buf()];
+    while chain.len() <= MAX_SYMLINKS {
+        let link = chain.last().expect("the chain to start with the file");
+        let Ok(target) = fs::read_link(link) else {
+            break;
+        };
+        // A relative target starts from the directory of the link
+        let target = link.parent().unwrap_or(Path::new("")).join(target);
+        if !target.parent().is_some_and(Path::is_dir) {
+            break;
+        }
+        chain.push(target);
+    }
+    chain
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+
+    #[test]
+    fn test_symlink_chain() {
+        let dir = std::env::temp_dir().join(format!("wpaperd-symlinks-{}", std::process::id()));
+        fs::create_dir_all(dir.join("dotfiles")).unwrap();
+        fs::write(dir.join("dotfiles/wpaperd.toml"), "").unwrap();
+        symlink("dotfiles/wpaperd.toml", dir.join("current.toml")).unwrap();
+        symlink(dir.join("current.toml"), dir.join("config.toml")).unwrap();
+        symlink("/nonexistent/config.toml", dir.join("dangling.toml")).unwrap();
+
+        let chain = symlink_chain(&dir.join("config.toml"));
+        let dangling = symlink_chain(&dir.join("dangling.toml"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            chain,
+            [
+                dir.join("config.toml"),
+                dir.join("current.toml"),
+                dir.join("dotfiles/wpaperd.toml"),
+            ]
+        );
+        assert_eq!(dangling, [dir.join("dangling.toml")]);
+    }
+}
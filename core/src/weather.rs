@@ -0,0 +1,356 @@
+//! The weather outside, to show other directories when it rains, snows or the sky is clear.
+//!
+//! The current condition is fetched periodically in another thread, from MET Norway or from
+//! OpenWeatherMap. When it cannot be fetched, e.g. while offline, the last condition is kept for
+//! [STALE_AFTER], then the displays go back to their own `path` until the next success.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    time::{Duration, Instant},
+};
+
+use color_eyre::{
+    eyre::{anyhow, Context, ContextCompat},
+    owo_colors::OwoColorize,
+    Result, Section,
+};
+use log::{debug, info, warn};
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+use smithay_client_toolkit::reexports::calloop::{
+    self,
+    channel::{Event, Sender},
+    LoopHandle,
+};
+
+use crate::{config::tilde_expansion, Wpaperd};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// How long the last condition fetched is used when the provider cannot be reached
+const STALE_AFTER: Duration = Duration::from_secs(3 * 60 * 60);
+/// Wait at most this long before trying again after an error
+const RETRY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// MET Norway refuses the requests without an identifying user agent
+const USER_AGENT: &str = concat!(
+    "wpaperd/",
+    env!("CARGO_PKG_VERSION"),
+    " https://github.com/danyspin97/wpaperd"
+);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    /// The forecast of the Norwegian Meteorological Institute, free and without an api key
+    #[default]
+    Metno,
+    OpenWeatherMap,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Weather {
+    #[serde(default)]
+    pub provider: Provider,
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Required by OpenWeatherMap
+    #[serde(default)]
+    pub api_key: Option<String>,
+    /// How often the weather is fetched
+    #[serde(default = "default_interval", with = "humantime_serde")]
+    pub interval: Duration,
+}
+
+fn default_interval() -> Duration {
+    Duration::from_secs(30 * 60)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Condition {
+    Clear,
+    Clouds,
+    Fog,
+    Rain,
+    Snow,
+    Thunderstorm,
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Condition::Clear => "clear",
+            Condition::Clouds => "clouds",
+            Condition::Fog => "fog",
+            Condition::Rain => "rain",
+            Condition::Snow => "snow",
+            Condition::Thunderstorm => "thunderstorm",
+        };
+        f.write_str(name)
+    }
+}
+
+impl Condition {
+    /// Read a symbol code of MET Norway, e.g. `lightrainshowers_day` or `partlycloudy_night`
+    fn from_metno(symbol: &str) -> Option<Self> {
+        let symbol = symbol.split('_').next().unwrap_or(symbol);
+        if symbol.contains("thunder") {
+            Some(Condition::Thunderstorm)
+        } else if symbol.contains("snow") || symbol.contains("sleet") {
+            Some(Condition::Snow)
+        } else if symbol.contains("rain") {
+            Some(Condition::Rain)
+        } else if symbol == "fog" {
+            Some(Condition::Fog)
+        } else if symbol == "clearsky" || symbol == "fair" {
+            Some(Condition::Clear)
+        } else if symbol.ends_with("cloudy") {
+            Some(Condition::Clouds)
+        } else {
+            None
+        }
+    }
+
+    /// Read the main group of an OpenWeatherMap condition, e.g. `Drizzle` or `Mist`
+    fn from_openweathermap(main: &str) -> Option<Self> {
+        match main {
+            "Thunderstorm" | "Squall" | "Tornado" => Some(Condition::Thunderstorm),
+            "Drizzle" | "Rain" => Some(Condition::Rain),
+            "Snow" => Some(Condition::Snow),
+            "Clear" => Some(Condition::Clear),
+            "Clouds" => Some(Condition::Clouds),
+            "Mist" | "Smoke" | "Haze" | "Dust" | "Fog" | "Sand" | "Ash" => Some(Condition::Fog),
+            _ => None,
+        }
+    }
+}
+
+/// Read the directories of a section, `<condition> = "<path>"`
+pub fn deserialize_weather_paths<'de, D>(
+    deserializer: D,
+) -> Result<Option<BTreeMap<Condition, PathBuf>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let paths = BTreeMap::<Condition, String>::deserialize(deserializer)?;
+    Ok(Some(
+        paths
+            .into_iter()
+            .map(|(condition, path)| (condition, tilde_expansion(Path::new(&path))))
+            .collect(),
+    ))
+}
+
+impl Weather {
+    pub fn validate(&self) -> Result<()> {
+        for (attribute, value, limit) in [
+            ("latitude", self.latitude, 90.0),
+            ("longitude", self.longitude, 180.0),
+        ] {
+            if !(-limit..=limit).contains(&value) {
+                return Err(anyhow!(
+                    "value {} of attribute {} is not valid",
+                    value.italic().yellow(),
+                    attribute.bold().italic().blue(),
+                ))
+                .with_suggestion(|| format!("set it between -{limit} and {limit}"));
+            }
+        }
+        if self.provider == Provider::OpenWeatherMap && self.api_key.is_none() {
+            return Err(anyhow!(
+                "attribute {} is required by provider {}",
+                "api-key".bold().italic().blue(),
+                "openweathermap".italic().yellow(),
+            ))
+            .with_suggestion(|| {
+                format!(
+                    "set {} or use provider {}, which doesn't need one",
+                    "api-key".bold().italic().blue(),
+                    "metno".italic().yellow(),
+                )
+            });
+        }
+        Ok(())
+    }
+
+    fn fetch(&self) -> Result<Condition> {
+        match self.provider {
+            Provider::Metno => {
+                // MET Norway asks to send at most 4 decimals, to cache the responses
+                let url = format!(
+                    "https://api.met.no/weatherapi/locationforecast/2.0/compact?lat={:.4}&lon={:.4}",
+                    self.latitude, self.longitude
+                );
+                let response = get_json(ureq::get(&url).set("User-Agent", USER_AGENT))
+                    .context("fetching the weather from MET Norway")?;
+                let symbol = response
+                    .pointer("/properties/timeseries/0/data/next_1_hours/summary/symbol_code")
+                    .and_then(Value::as_str)
+                    .context("MET Norway returned no weather symbol")?;
+                Condition::from_metno(symbol)
+                    .with_context(|| format!("unknown weather symbol {symbol:?} from MET Norway"))
+            }
+            Provider::OpenWeatherMap => {
+                let url = format!(
+                    "https://api.openweathermap.org/data/2.5/weather?lat={}&lon={}&appid={}",
+                    self.latitude,
+                    self.longitude,
+                    self.api_key.as_deref().unwrap_or_default()
+                );
+                let response = get_json(ureq::get(&url))
+                    .context("fetching the weather from OpenWeatherMap")?;
+                let main = response
+                    .pointer("/weather/0/main")
+                    .and_then(Value::as_str)
+                    .context("OpenWeatherMap returned no weather condition")?;
+                Condition::from_openweathermap(main).with_context(|| {
+                    format!("unknown weather condition {main:?} from OpenWeatherMap")
+                })
+            }
+        }
+    }
+}
+
+fn get_json(request: ureq::Request) -> Result<Value> {
+    let body = request.timeout(TIMEOUT).call()?.into_string()?;
+    Ok(serde_json::from_str(&body)?)
+}
+
+/// Fetch the weather in another thread, the displays are updated with
+/// [crate::config::Config::set_weather_condition] each time the condition changes
+pub struct WeatherWatcher {
+    weather: Option<Weather>,
+    sender: mpsc::Sender<Option<Weather>>,
+}
+
+impl WeatherWatcher {
+    pub fn new(
+        weather: Option<Weather>,
+        event_loop_handle: &LoopHandle<'static, Wpaperd>,
+    ) -> Result<Self> {
+        let (condition_sender, channel) = calloop::channel::channel();
+        event_loop_handle
+            .insert_source(channel, |event, _, wpaperd| {
+                if let Event::Msg(condition) = event {
+                    wpaperd.config.set_weather_condition(condition);
+                }
+            })
+            .map_err(|e| anyhow!("inserting the weather channel in the event loop: {e}"))?;
+        let (sender, receiver) = mpsc::channel();
+        let thread_weather = weather.clone();
+        std::thread::Builder::new()
+            .name("weather".to_string())
+            .spawn(move || watch(thread_weather, receiver, condition_sender))
+            .context("spawning the weather thread")?;
+        Ok(Self { weather, sender })
+    }
+
+    /// Called when the configuration is reloaded, the weather is fetched again if it changed
+    pub fn update(&mut self, weather: Option<&Weather>) {
+        if self.weather.as_ref() != weather {
+            self.weather = weather.cloned();
+            let _ = self.sender.send(self.weather.clone());
+        }
+    }
+}
+
+/// Fetch the weather until the [WeatherWatcher] is dropped, sending the condition when it changes
+fn watch(
+    mut weather: Option<Weather>,
+    receiver: Receiver<Option<Weather>>,
+    sender: Sender<Option<Condition>>,
+) {
+    let mut last: Option<(Condition, Instant)> = None;
+    let mut current = None;
+    let mut failing = false;
+    loop {
+        let timeout = match &weather {
+            Some(weather) => match weather.fetch() {
+                Ok(condition) => {
+                    last = Some((condition, Instant::now()));
+                    failing = false;
+                    weather.interval
+                }
+                Err(err) => {
+                    // Only warn once, the provider stays unreachable while offline
+                    if failing {
+                        debug!("{err:?}");
+                    } else {
+                        warn!("{err:?}");
+                    }
+                    failing = true;
+                    weather.interval.min(RETRY_INTERVAL)
+                }
+            },
+            None => {
+                last = None;
+                Duration::MAX
+            }
+        };
+        let condition = last
+            .filter(|(_, fetched)| fetched.elapsed() < STALE_AFTER)
+            .map(|(condition, _)| condition);
+        if condition != current {
+            match condition {
+                Some(condition) => info!("The weather is now {condition}"),
+                None => info!("The weather is not known, showing the default paths"),
+            }
+            current = condition;
+            if sender.send(condition).is_err() {
+                return;
+            }
+        }
+        match receiver.recv_timeout(timeout) {
+            Ok(new_weather) => weather = new_weather,
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conditions() {
+        assert_eq!(
+            Condition::from_metno("lightrainshowersandthunder_day"),
+            Some(Condition::Thunderstorm)
+        );
+        assert_eq!(Condition::from_metno("heavysleet"), Some(Condition::Snow));
+        assert_eq!(Condition::from_metno("rain"), Some(Condition::Rain));
+        assert_eq!(Condition::from_metno("fair_night"), Some(Condition::Clear));
+        assert_eq!(
+            Condition::from_metno("partlycloudy_polartwilight"),
+            Some(Condition::Clouds)
+        );
+        assert_eq!(Condition::from_metno("unknown"), None);
+        assert_eq!(
+            Condition::from_openweathermap("Drizzle"),
+            Some(Condition::Rain)
+        );
+        assert_eq!(Condition::from_openweathermap("Mist"), Some(Condition::Fog));
+
+        let weather: Weather = toml::from_str(
+            "provider = \"openweathermap\"\nlatitude = 45.46\nlongitude = 9.19\ninterval = \"1h\"",
+        )
+        .unwrap();
+        assert_eq!(weather.interval, Duration::from_secs(3600));
+        assert!(weather.validate().is_err());
+
+        #[derive(Deserialize)]
+        struct Section {
+            #[serde(deserialize_with = "deserialize_weather_paths")]
+            weather: Option<BTreeMap<Condition, PathBuf>>,
+        }
+        let section: Section =
+            toml::from_str("weather = { rain = \"/rain\", snow = \"/snow\" }").unwrap();
+        let weather = section.weather.unwrap();
+        assert_eq!(weather[&Condition::Rain], Path::new("/rain"));
+        assert_eq!(weather.len(), 2);
+    }
+}
@@ -0,0 +1,101 @@
+//! A thread running the jobs that only matter for the last wallpaper, e.g. writing the theme
+//! templates or the lock images.
+//!
+//! The wallpapers can change faster than the jobs run, with a short `duration` or `wpaperctl next`
+//! pressed repeatedly. A thread per job would write the same files concurrently, and the last one
+//! to finish would win instead of the last one started. The jobs are run one at a time instead,
+//! and a job still waiting is replaced by the newer one with the same key.
+
+use std::{collections::BTreeMap, sync::Mutex};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+pub struct LatestWorker<K> {
+    state: Mutex<WorkerState<K>>,
+}
+
+struct WorkerState<K> {
+    pending: BTreeMap<K, Job>,
+    running: bool,
+}
+
+impl<K: Ord + Send + 'static> LatestWorker<K> {
+    pub const fn new() -> Self {
+        Self {
+            state: Mutex::new(WorkerState {
+                pending: BTreeMap::new(),
+                running: false,
+            }),
+        }
+    }
+
+    /// Run `job` after the ones already running, replacing the one waiting for `key`. The
+    /// thread is started when needed and stops once there is nothing left to run
+    pub fn submit(&'static self, key: K, job: impl FnOnce() + Send + 'static) {
+        let mut state = self.state.lock().unwrap();
+        state.pending.insert(key, Box::new(job));
+        if !state.running {
+            state.running = true;
+            std::thread::spawn(move || self.run());
+        }
+    }
+
+    fn run(&self) {
+        loop {
+            let job = {
+                let mut state = self.state.lock().unwrap();
+                match state.pending.pop_first() {
+                    Some((_, job)) => job,
+                    None => {
+                        state.running = false;
+                        return;
+                    }
+                }
+            };
+            job();
+        }
+    }
+}
+
+impl<K: Ord + Send + 'static> Default for LatestWorker<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{mpsc, Arc},
+        time::Duration,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_latest_worker() {
+        static WORKER: LatestWorker<u32> = LatestWorker::new();
+        let ran = Arc::new(Mutex::new(Vec::new()));
+        // Keep the first job running while the next ones are submitted
+        let (started_sender, started) = mpsc::channel();
+        let (sender, receiver) = mpsc::channel::<()>();
+        let first = ran.clone();
+        WORKER.submit(0, move || {
+            started_sender.send(()).unwrap();
+            receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+            first.lock().unwrap().push((0, 0));
+        });
+        started.recv_timeout(Duration::from_secs(10)).unwrap();
+        for i in 1..=3 {
+            for key in [0, 1] {
+                let ran = ran.clone();
+                WORKER.submit(key, move || ran.lock().unwrap().push((key, i)));
+            }
+        }
+        sender.send(()).unwrap();
+        while WORKER.state.lock().unwrap().running {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(*ran.lock().unwrap(), [(0, 0), (0, 3), (1, 3)]);
+    }
+}
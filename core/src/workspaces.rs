@@ -0,0 +1,210 @@
+//! The workspace shown on each display, to show other directories on some workspaces.
+//!
+//! The displays with `workspaces` set use the directory of the active workspace in place of their
+//! `path`; it is read from the compositor:
+//! - Sway and Hyprland, from their IPC, see [crate::compositor]. The workspaces are listed again
+//!   after each event changing them
+//! - river, with its status protocol. The workspace is the first tag focused, e.g. `3` when
+//!   `0b100` is focused
+//!
+//! Sway and Hyprland are watched in another thread, started once a display sets `workspaces`,
+//! unless `compositor-ipc` is disabled.
+//! With `workspace-transition = false` the new wallpaper is shown immediately.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use log::{info, warn};
+use serde::{Deserialize, Deserializer};
+use smithay_client_toolkit::reexports::{
+    calloop::{
+        channel::{self, Event, Sender},
+        LoopHandle,
+    },
+    client::{
+        globals::GlobalList, protocol::wl_output::WlOutput, Connection, Dispatch, QueueHandle,
+    },
+};
+
+use self::river_status::{
+    zriver_output_status_v1::{self, ZriverOutputStatusV1},
+    zriver_status_manager_v1::{self, ZriverStatusManagerV1},
+};
+use crate::{
+    compositor::{Compositor, Workspaces},
+    config::tilde_expansion,
+    Wpaperd,
+};
+
+#[allow(non_upper_case_globals, clippy::all)]
+mod river_status {
+    use smithay_client_toolkit::reexports::client as wayland_client;
+    use wayland_client::backend as wayland_backend;
+    use wayland_client::protocol::*;
+
+    pub mod __interfaces {
+        use super::wayland_backend;
+        use smithay_client_toolkit::reexports::client::protocol::__interfaces::*;
+        wayland_scanner::generate_interfaces!("protocols/river-status-unstable-v1.xml");
+    }
+    use self::__interfaces::*;
+
+    wayland_scanner::generate_client_code!("protocols/river-status-unstable-v1.xml");
+}
+
+pub fn deserialize_workspace_paths<'de, D>(
+    deserializer: D,
+) -> Result<Option<BTreeMap<String, PathBuf>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let paths = BTreeMap::<String, String>::deserialize(deserializer)?;
+    Ok(Some(
+        paths
+            .into_iter()
+            .map(|(workspace, path)| (workspace, tilde_expansion(Path::new(&path))))
+            .collect(),
+    ))
+}
+
+pub struct WorkspaceWatcher {
+    /// None when the compositor is not river
+    river: Option<ZriverStatusManagerV1>,
+    statuses: Vec<(WlOutput, ZriverOutputStatusV1)>,
+    /// Taken by the thread watching Sway or Hyprland, once started
+    sender: Option<Sender<Workspaces>>,
+}
+
+impl WorkspaceWatcher {
+    pub fn new(
+        globals: &GlobalList,
+        qh: &QueueHandle<Wpaperd>,
+        event_loop_handle: &LoopHandle<'static, Wpaperd>,
+    ) -> Self {
+        let river = globals
+            .bind::<ZriverStatusManagerV1, _, _>(qh, 1..=1, ())
+            .ok();
+        let (sender, channel) = channel::channel::<Workspaces>();
+        let sender = event_loop_handle
+            .insert_source(channel, |event, _, wpaperd| {
+                // The thread keeps running when compositor-ipc is disabled by a reload
+                if !wpaperd.config.compositor_ipc() {
+                    return;
+                }
+                if let Event::Msg(workspaces) = event {
+                    for (output, workspace) in workspaces {
+                        wpaperd.config.set_workspace(&output, workspace);
+                    }
+                }
+            })
+            .inspect_err(|e| warn!("inserting the workspaces channel in the event loop: {e}"))
+            .ok()
+            .map(|_| sender);
+        Self {
+            river,
+            statuses: Vec::new(),
+            sender,
+        }
+    }
+
+    /// Start watching the workspaces of Sway or Hyprland, called when a display sets
+    /// `workspaces`. river sends them without being asked
+    pub fn start(&mut self) {
+        if self.river.is_some() {
+            return;
+        }
+        let Some(sender) = self.sender.take() else {
+            return;
+        };
+        let Some(compositor) = Compositor::detect() else {
+            info!(
+                "The compositor is not Sway, Hyprland or river, the workspaces can't be detected"
+            );
+            return;
+        };
+        let spawned = std::thread::Builder::new()
+            .name("workspaces".to_string())
+            .spawn(move || {
+                if let Err(err) = compositor.watch_workspaces(&sender) {
+                    warn!("{err:?}");
+                }
+            });
+        if let Err(err) = spawned {
+            warn!("spawning the workspaces thread: {err}");
+        }
+    }
+
+    /// Follow the tags focused on the display `name`, on river
+    pub fn add_output(&mut self, output: &WlOutput, name: String, qh: &QueueHandle<Wpaperd>) {
+        if let Some(river) = &self.river {
+            let status = river.get_river_output_status(output, qh, name);
+            self.statuses.push((output.clone(), status));
+        }
+    }
+
+    pub fn remove_output(&mut self, output: &WlOutput) {
+        self.statuses.retain(|(watched, status)| {
+            if watched == output {
+                status.destroy();
+            }
+            watched != output
+        });
+    }
+}
+
+impl Drop for WorkspaceWatcher {
+    fn drop(&mut self) {
+        for (_, status) in &self.statuses {
+            status.destroy();
+        }
+        if let Some(river) = &self.river {
+            river.destroy();
+        }
+    }
+}
+
+/// The workspace of river: the first tag focused, counting from 1
+fn river_workspace(tags: u32) -> Option<String> {
+    (tags != 0).then(|| (tags.trailing_zeros() + 1).to_string())
+}
+
+impl Dispatch<ZriverStatusManagerV1, ()> for Wpaperd {
+    fn event(
+        _state: &mut Self,
+        _proxy: &ZriverStatusManagerV1,
+        _event: zriver_status_manager_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ZriverOutputStatusV1, String> for Wpaperd {
+    fn event(
+        state: &mut Self,
+        _proxy: &ZriverOutputStatusV1,
+        event: zriver_output_status_v1::Event,
+        name: &String,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let zriver_output_status_v1::Event::FocusedTags { tags } = event {
+            state.config.set_workspace(name, river_workspace(tags));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_river_workspace() {
+        assert_eq!(river_workspace(0b100).as_deref(), Some("3"));
+        assert_eq!(river_workspace(0b110).as_deref(), Some("2"));
+        assert_eq!(river_workspace(0), None);
+    }
+}
@@ -0,0 +1,664 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use color_eyre::eyre::Context;
+use color_eyre::owo_colors::OwoColorize;
+use color_eyre::Result;
+use log::{error, info, warn};
+use smithay_client_toolkit::compositor::{CompositorHandler, CompositorState, Region};
+use smithay_client_toolkit::output::{OutputHandler, OutputInfo, OutputState};
+use smithay_client_toolkit::reexports::calloop::LoopHandle;
+use smithay_client_toolkit::reexports::client::globals::GlobalList;
+use smithay_client_toolkit::reexports::client::protocol::{wl_display, wl_output, wl_surface};
+use smithay_client_toolkit::reexports::client::{Connection, QueueHandle};
+use smithay_client_toolkit::registry::{ProvidesRegistryState, RegistryState};
+use smithay_client_toolkit::seat::SeatState;
+use smithay_client_toolkit::shell::wlr_layer::{
+    Anchor, Layer, LayerShell, LayerShellHandler, LayerSurface, LayerSurfaceConfigure,
+};
+use smithay_client_toolkit::shm::{Shm, ShmHandler};
+use smithay_client_toolkit::{
+    delegate_compositor, delegate_layer, delegate_output, delegate_pointer, delegate_registry,
+    delegate_seat, delegate_shm, registry_handlers,
+};
+
+#[cfg(feature = "audio")]
+use crate::audio::AudioCapture;
+use crate::backend::{self, Backend};
+use crate::clipboard::Clipboard;
+use crate::cold_start::ColdStart;
+use crate::compositor::{self, Compositor};
+use crate::config::Config;
+use crate::conflicts::{self, Conflict, OnConflict};
+use crate::display_info::DisplayInfo;
+use crate::filelist_cache::FilelistCache;
+use crate::fullscreen::FullscreenTracker;
+use crate::image_loader::ImageLoader;
+use crate::inhibit::Inhibitors;
+use crate::metrics::Metrics;
+use crate::parallax::PointerTracker;
+use crate::state::State;
+use crate::surface::Surface;
+use crate::trace::Tracer;
+use crate::wallpaper_groups::WallpaperGroups;
+use crate::wallpaper_info::WallpaperInfo;
+use crate::workspaces::WorkspaceWatcher;
+
+pub struct Wpaperd {
+    pub compositor_state: CompositorState,
+    pub output_state: OutputState,
+    pub shm_state: Shm,
+    pub layer_state: LayerShell,
+    pub registry_state: RegistryState,
+    pub surfaces: Vec<Surface>,
+    pub config: Config,
+    egl_display: egl::Display,
+    /// The EGL display is got from it again when the backend changes
+    wl_display: wl_display::WlDisplay,
+    /// The backend the EGL display has been initialized with
+    backend: Backend,
+    pub filelist_cache: Rc<RefCell<FilelistCache>>,
+    pub image_loader: Rc<RefCell<ImageLoader>>,
+    pub wallpaper_groups: Rc<RefCell<WallpaperGroups>>,
+    pub state: Rc<RefCell<State>>,
+    /// The displays covered by a fullscreen window
+    pub fullscreen: FullscreenTracker,
+    /// Move the wallpapers with parallax set
+    pub pointers: PointerTracker,
+    /// The workspace shown on each display, for the ones with workspaces set
+    pub workspaces: WorkspaceWatcher,
+    /// The wallpaper copied by `wpaperctl copy-path`
+    pub clipboard: Clipboard,
+    pub metrics: Rc<RefCell<Metrics>>,
+    /// The stages of the rotation, once `wpaperctl trace` or `--trace` has started recording them
+    pub tracer: Rc<RefCell<Tracer>>,
+    /// The first wallpapers of the displays are shown together
+    pub cold_start: Rc<RefCell<ColdStart>>,
+    /// How many times the surface of each display has been recreated after a crash
+    surface_recoveries: HashMap<String, u8>,
+    /// The other programs drawing the background, found by [Wpaperd::check_conflicts]
+    conflicts: Vec<Conflict>,
+    /// The displays left to the other programs, with on-conflict set to yield
+    yielded: Vec<(String, wl_output::WlOutput)>,
+    /// Set when wpaperd has received a termination signal
+    pub should_exit: bool,
+    /// Set when a new instance started with `--replace` has taken over, see [crate::handoff]
+    pub replaced: bool,
+    /// The wallpapers are not changed automatically on any display while one is active
+    pub inhibitors: Inhibitors,
+    /// The audio played, captured for the displays with audio-reactive set
+    #[cfg(feature = "audio")]
+    pub audio: AudioCapture,
+}
+
+impl Wpaperd {
+    pub fn new(
+        qh: &QueueHandle<Self>,
+        globals: &GlobalList,
+        config: Config,
+        wl_display: wl_display::WlDisplay,
+        filelist_cache: Rc<RefCell<FilelistCache>>,
+        wallpaper_groups: Rc<RefCell<WallpaperGroups>>,
+        state: Rc<RefCell<State>>,
+    ) -> Result<Self> {
+        let shm_state = Shm::bind(globals, qh)?;
+        let egl_display = config.backend().display(&wl_display)?;
+
+        let metrics = Rc::new(RefCell::new(Metrics::default()));
+        let tracer = Rc::new(RefCell::new(Tracer::default()));
+        let image_loader = Rc::new(RefCell::new(ImageLoader::new(
+            config.threads,
+            metrics.clone(),
+            tracer.clone(),
+        )?));
+        image_loader.borrow_mut().set_limits(config.decode_limits);
+        image_loader
+            .borrow_mut()
+            .set_tone_mapping(config.tone_mapping);
+
+        let mut workspaces =
+            WorkspaceWatcher::new(globals, qh, filelist_cache.borrow().event_loop_handle());
+        if config.uses_workspaces() && config.compositor_ipc() {
+            workspaces.start();
+        }
+
+        Ok(Self {
+            compositor_state: CompositorState::bind(globals, qh)?,
+            output_state: OutputState::new(globals, qh),
+            shm_state,
+            layer_state: LayerShell::bind(globals, qh)?,
+            registry_state: RegistryState::new(globals),
+            surfaces: Vec::new(),
+            backend: config.backend().clone(),
+            config,
+            egl_display,
+            wl_display,
+            filelist_cache,
+            image_loader,
+            wallpaper_groups,
+            state,
+            fullscreen: FullscreenTracker::new(globals, qh),
+            pointers: PointerTracker::new(globals, qh),
+            workspaces,
+            clipboard: Clipboard::new(globals, qh),
+            metrics,
+            tracer,
+            cold_start: Rc::new(RefCell::new(ColdStart::default())),
+            surface_recoveries: HashMap::new(),
+            conflicts: Vec::new(),
+            yielded: Vec::new(),
+            should_exit: false,
+            replaced: false,
+            inhibitors: Inhibitors::default(),
+            #[cfg(feature = "audio")]
+            audio: AudioCapture::default(),
+        })
+    }
+
+    /// Maximum number of times we recreate the surface of a display before giving up
+    const MAX_SURFACE_RECOVERIES: u8 = 5;
+
+    pub fn update_surfaces(&mut self, ev_handle: LoopHandle<Wpaperd>, qh: &QueueHandle<Wpaperd>) {
+        self.image_loader
+            .borrow_mut()
+            .set_limits(self.config.decode_limits);
+        self.image_loader
+            .borrow_mut()
+            .set_tone_mapping(self.config.tone_mapping);
+        if *self.config.backend() != self.backend {
+            if let Err(err) = self.switch_backend(qh) {
+                error!("{err:?}");
+            }
+        }
+        if self.config.uses_workspaces() && self.config.compositor_ipc() {
+            self.workspaces.start();
+        }
+        for surface in &mut self.surfaces {
+            let res = self.config.get_output_by_name(&surface.name());
+            match res {
+                Ok(wallpaper_info) => {
+                    surface.update_wallpaper_info(&ev_handle, qh, wallpaper_info);
+                    set_input_region(
+                        &self.compositor_state,
+                        surface.wl_surface(),
+                        surface.wallpaper_info.parallax > 0.0,
+                    );
+                }
+                Err(err) => warn!(
+                    "Configuration error for display {}: {err:?}",
+                    surface.name()
+                ),
+            }
+        }
+        #[cfg(feature = "audio")]
+        self.update_audio();
+    }
+
+    /// Stop capturing the audio once no display reacts to it, or try again to capture it
+    #[cfg(feature = "audio")]
+    fn update_audio(&mut self) {
+        if self
+            .surfaces
+            .iter()
+            .any(|surface| surface.wallpaper_info.audio_reactive > 0.0)
+        {
+            self.audio.retry();
+        } else {
+            self.audio.stop();
+        }
+    }
+
+    /// Initialize the EGL display of the backend of the configuration, then recreate the contexts
+    /// of all the surfaces. When the backend cannot be used, the previous one is kept
+    pub fn switch_backend(&mut self, qh: &QueueHandle<Wpaperd>) -> Result<()> {
+        let backend = self.config.backend().clone();
+        info!("Switching to the {} backend", backend.bold());
+        backend::terminate(self.egl_display)?;
+        let res = match backend.display(&self.wl_display) {
+            Ok(egl_display) => {
+                self.egl_display = egl_display;
+                self.backend = backend;
+                Ok(())
+            }
+            Err(err) => {
+                self.egl_display = self
+                    .backend
+                    .display(&self.wl_display)
+                    .context("initializing the previous backend again")?;
+                Err(err)
+            }
+        };
+
+        let mut failed_outputs = Vec::new();
+        for surface in &mut self.surfaces {
+            if let Err(err) = surface.recreate_renderer(self.egl_display, qh) {
+                error!("{err:?}");
+                failed_outputs.push(surface.wl_output().clone());
+            }
+        }
+        for output in failed_outputs {
+            self.recreate_surface(qh, output);
+        }
+        if let Some(driver) = self.driver() {
+            info!("Drawing the wallpapers with {}", driver.bold());
+        }
+        res
+    }
+
+    /// The backend the wallpapers are drawn with
+    pub fn backend(&self) -> &Backend {
+        &self.backend
+    }
+
+    /// The driver drawing the wallpapers, `None` when there are no displays
+    pub fn driver(&self) -> Option<&str> {
+        self.surfaces.first().map(|surface| surface.driver())
+    }
+
+    /// Called every [crate::brightness::UPDATE_INTERVAL]
+    pub fn update_brightness(&mut self, qh: &QueueHandle<Wpaperd>) {
+        for surface in &mut self.surfaces {
+            surface.update_brightness(qh);
+        }
+    }
+
+    /// Called every [crate::conflicts::CHECK_INTERVAL] and when the configuration changes. Warn
+    /// about the other programs drawing the background and, with on-conflict set to yield, stop
+    /// drawing on their displays until they exit
+    pub fn check_conflicts(&mut self, qh: &QueueHandle<Self>) {
+        let conflicts = conflicts::detect();
+        for conflict in conflicts
+            .iter()
+            .filter(|conflict| !self.conflicts.contains(conflict))
+        {
+            warn!(
+                "{} (pid {}) is also drawing the background of {}, the wallpapers can flicker. \
+                 Stop it, or set {} to stop drawing on its displays",
+                conflict.program.bold(),
+                conflict.pid,
+                conflict.displays(),
+                "on-conflict = \"yield\"".italic().yellow(),
+            );
+        }
+        let yielding = self.config.on_conflict == OnConflict::Yield;
+        let covered = |name: &str| yielding && conflicts.iter().any(|c| c.covers(name));
+
+        let mut index = 0;
+        while index < self.surfaces.len() {
+            let name = self.surfaces[index].name();
+            if covered(&name) {
+                info!(
+                    "Leaving display {} to the other program",
+                    name.bold().magenta()
+                );
+                let surface = self.surfaces.swap_remove(index);
+                self.cold_start.borrow_mut().remove_display(&name);
+                self.yielded.push((name, surface.wl_output().clone()));
+            } else {
+                index += 1;
+            }
+        }
+        let (yielded, released): (Vec<_>, Vec<_>) = std::mem::take(&mut self.yielded)
+            .into_iter()
+            .partition(|(name, _)| covered(name));
+        self.yielded = yielded;
+        for (name, output) in released {
+            info!("Drawing on display {} again", name.bold().magenta());
+            self.add_surface(qh, output);
+        }
+        self.conflicts = conflicts;
+    }
+
+    pub fn surface_from_name(&mut self, name: &str) -> Option<&mut Surface> {
+        self.surfaces
+            .iter_mut()
+            .find(|surface| surface.name() == name)
+    }
+
+    /// Tear down the surface drawn on the output and create a new one, leaving the other
+    /// displays untouched. Used when the rendering of a surface fails unexpectedly or
+    /// when the compositor closes it.
+    pub fn recreate_surface(&mut self, qh: &QueueHandle<Self>, output: wl_output::WlOutput) {
+        let Some(index) = self
+            .surfaces
+            .iter()
+            .position(|surface| *surface.wl_output() == output)
+        else {
+            error!("could not find display while recreating its surface");
+            return;
+        };
+        let surface = self.surfaces.swap_remove(index);
+        let name = surface.name();
+        drop(surface);
+
+        let recoveries = self.surface_recoveries.entry(name.clone()).or_default();
+        *recoveries += 1;
+        if *recoveries > Self::MAX_SURFACE_RECOVERIES {
+            error!(
+                "The surface for display {} failed too many times, giving up",
+                name.bold().magenta()
+            );
+            return;
+        }
+
+        warn!(
+            "Recreating the surface for display {}",
+            name.bold().magenta()
+        );
+        self.add_surface(qh, output);
+    }
+
+    /// Forget the failures of the surfaces that have been recreated and drawn since, so that
+    /// [Self::MAX_SURFACE_RECOVERIES] only counts the failures in a row
+    pub fn forget_recoveries(&mut self) {
+        let surfaces = &self.surfaces;
+        self.surface_recoveries.retain(|name, _| {
+            !surfaces
+                .iter()
+                .any(|surface| surface.name() == *name && surface.has_been_drawn())
+        });
+    }
+
+    /// The information sent by wl_output, completed by the IPC of the compositor when it doesn't
+    /// name the display
+    fn output_info(&self, output: &wl_output::WlOutput) -> Option<OutputInfo> {
+        let mut info = self.output_state.info(output)?;
+        if info.name.is_some() || !self.config.compositor_ipc() {
+            return Some(info);
+        }
+        let Some(compositor) = Compositor::detect() else {
+            return Some(info);
+        };
+        match compositor.outputs() {
+            Ok(outputs) if compositor::complete_output_info(&mut info, &outputs) => info!(
+                "Display {} has been named by the IPC of {}",
+                info.name.as_deref().unwrap_or_default().bold().magenta(),
+                compositor.name()
+            ),
+            Ok(_) => warn!(
+                "The display {} {} at {:?} is not listed by {}",
+                info.make,
+                info.model,
+                info.location,
+                compositor.name()
+            ),
+            Err(err) => warn!("{err:?}"),
+        }
+        Some(info)
+    }
+
+    fn add_surface(&mut self, qh: &QueueHandle<Self>, output: wl_output::WlOutput) {
+        let surface = self.compositor_state.create_surface(qh);
+
+        let info = match self.output_info(&output) {
+            Some(info) => info,
+            None => {
+                error!("could not get info about output");
+                return;
+            }
+        };
+        surface.set_buffer_scale(info.scale_factor);
+        surface.set_buffer_transform(info.transform);
+
+        let name = info
+            .name
+            .as_ref()
+            .map(|name| name.to_string())
+            .unwrap_or_else(|| "unnamed".to_string());
+        let display_info = DisplayInfo::new(info);
+
+        let layer = self.layer_state.create_layer_surface(
+            qh,
+            surface.clone(),
+            Layer::Background,
+            Some(format!("wpaperd-{}", name)),
+            Some(&output),
+        );
+        layer.set_anchor(Anchor::TOP | Anchor::LEFT | Anchor::RIGHT | Anchor::BOTTOM);
+        layer.set_exclusive_zone(-1);
+        layer.set_size(
+            display_info.adjusted_width() as u32,
+            display_info.adjusted_height() as u32,
+        );
+
+        let wallpaper_info = match self.config.get_output_by_name(&name) {
+            Ok(wallpaper_info) => wallpaper_info,
+            Err(err) => {
+                warn!(
+                    "Configuration error on display {}: {err:?}",
+                    name.bold().magenta()
+                );
+                WallpaperInfo::default()
+            }
+        };
+
+        if !set_input_region(
+            &self.compositor_state,
+            &surface,
+            wallpaper_info.parallax > 0.0,
+        ) {
+            warn!("could not create region, cursor won't be shown for display {name}");
+            return;
+        }
+
+        self.cold_start.borrow_mut().add_display(&name);
+        self.surfaces.push(Surface::new(
+            self,
+            layer,
+            output,
+            display_info,
+            wallpaper_info,
+            self.egl_display,
+            qh,
+        ));
+    }
+
+    pub fn surface_from_wl_surface(&mut self, surface: &wl_surface::WlSurface) -> &mut Surface {
+        self.surfaces
+            .iter_mut()
+            .find(|s| surface == s.wl_surface())
+            .expect("surface to be registered in wpaperd")
+    }
+}
+
+/// The pointer goes to the surface only when `pointer` is true, see [crate::parallax]. Return false
+/// when the region cannot be created
+fn set_input_region(
+    compositor_state: &CompositorState,
+    surface: &wl_surface::WlSurface,
+    pointer: bool,
+) -> bool {
+    if pointer {
+        // The whole surface
+        surface.set_input_region(None);
+        return true;
+    }
+    match Region::new(compositor_state) {
+        Ok(region) => {
+            // Wayland clients are expected to render the cursor on their input region. By setting the
+            // input region to an empty region, the compositor renders the default cursor. Without
+            // this, and empty desktop won't render a cursor.
+            surface.set_input_region(Some(region.wl_region()));
+
+            // From `wl_surface::set_opaque_region`:
+            // > Setting the pending opaque region has copy semantics, and the
+            // > wl_region object can be destroyed immediately.
+            region.wl_region().destroy();
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+impl CompositorHandler for Wpaperd {
+    fn scale_factor_changed(
+        &mut self,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+        surface: &wl_surface::WlSurface,
+        new_factor: i32,
+    ) {
+        self.surface_from_wl_surface(surface)
+            .change_scale_factor(new_factor, qh);
+    }
+
+    fn frame(
+        &mut self,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+        surface: &wl_surface::WlSurface,
+        time: u32,
+    ) {
+        let surface = self.surface_from_wl_surface(surface);
+
+        if !surface.draw_catching_panic(qh, Some(time)) {
+            let output = surface.wl_output().clone();
+            self.recreate_surface(qh, output);
+        }
+    }
+
+    fn transform_changed(
+        &mut self,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+        surface: &wl_surface::WlSurface,
+        new_transform: wl_output::Transform,
+    ) {
+        self.surface_from_wl_surface(surface)
+            .change_transform(new_transform, qh);
+    }
+
+    fn surface_enter(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _surface: &wl_surface::WlSurface,
+        _output: &wl_output::WlOutput,
+    ) {
+    }
+
+    fn surface_leave(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _surface: &wl_surface::WlSurface,
+        _output: &wl_output::WlOutput,
+    ) {
+    }
+}
+
+impl OutputHandler for Wpaperd {
+    fn output_state(&mut self) -> &mut OutputState {
+        &mut self.output_state
+    }
+
+    fn new_output(
+        &mut self,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+        output: wl_output::WlOutput,
+    ) {
+        if let Some(name) = self.output_info(&output).and_then(|info| info.name) {
+            self.workspaces.add_output(&output, name, qh);
+        }
+        self.add_surface(qh, output);
+    }
+
+    fn update_output(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _output: wl_output::WlOutput,
+    ) {
+        // TODO: Do we need to do something here?
+    }
+
+    fn output_destroyed(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        output: wl_output::WlOutput,
+    ) {
+        self.workspaces.remove_output(&output);
+        // Find the destroyed output and remove it
+        if let Some(index) = self
+            .yielded
+            .iter()
+            .position(|(_, yielded)| *yielded == output)
+        {
+            self.yielded.swap_remove(index);
+            return;
+        }
+        match self
+            .surfaces
+            .iter()
+            .enumerate()
+            .find(|(_, surface)| *surface.wl_output() == output)
+        {
+            Some((index, _)) => {
+                let surface = self.surfaces.swap_remove(index);
+                self.cold_start.borrow_mut().remove_display(&surface.name());
+                #[cfg(feature = "audio")]
+                self.update_audio();
+            }
+            None => error!("could not find display while handling output_destroyed"),
+        }
+    }
+}
+
+impl LayerShellHandler for Wpaperd {
+    fn closed(&mut self, _conn: &Connection, qh: &QueueHandle<Self>, layer: &LayerSurface) {
+        // The compositor destroyed our surface, create a new one for the same output
+        match self
+            .surfaces
+            .iter()
+            .find(|surface| surface.layer() == layer)
+        {
+            Some(surface) => {
+                let output = surface.wl_output().clone();
+                self.recreate_surface(qh, output);
+            }
+            None => error!("could not find display while handling closed in wayland"),
+        }
+    }
+
+    fn configure(
+        &mut self,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+        layer: &LayerSurface,
+        configure: LayerSurfaceConfigure,
+        _serial: u32,
+    ) {
+        match self
+            .surfaces
+            .iter_mut()
+            .find(|surface| surface.layer() == layer)
+        {
+            Some(surface) => surface.change_size(configure, qh),
+            None => error!("could not find display while handling configure in wayland"),
+        }
+    }
+}
+
+impl ShmHandler for Wpaperd {
+    fn shm_state(&mut self) -> &mut Shm {
+        &mut self.shm_state
+    }
+}
+
+delegate_compositor!(Wpaperd);
+delegate_output!(Wpaperd);
+delegate_shm!(Wpaperd);
+delegate_registry!(Wpaperd);
+delegate_layer!(Wpaperd);
+delegate_seat!(Wpaperd);
+delegate_pointer!(Wpaperd);
+
+impl ProvidesRegistryState for Wpaperd {
+    fn registry(&mut self) -> &mut RegistryState {
+        &mut self.registry_state
+    }
+    registry_handlers![OutputState, SeatState];
+}
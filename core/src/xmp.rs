@@ -0,0 +1,134 @@
+//! Read the rating and the keywords of an image from its XMP metadata, for `min-rating` and
+//! `filter`.
+//!
+//! The rating is the one set by the photo managers, like digiKam and darktable, from -1 for the
+//! rejected images to 5 stars, and the keywords are their tags. They are read from the XMP
+//! sidecar file first, `photo.jpg.xmp` or `photo.xmp`, then from the metadata embedded in the
+//! image. The images without a rating are unrated, i.e. 0 stars.
+
+use std::{
+    fs::{self, File},
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+use image::ImageFormat;
+
+/// Only the start of the images other than JPEG is searched for the XMP packet
+const MAX_SCANNED: u64 = 1024 * 1024;
+const PACKET_START: &str = "<x:xmpmeta";
+const PACKET_END: &str = "</x:xmpmeta>";
+const RATING: &str = "xmp:Rating";
+const SUBJECT_START: &str = "<dc:subject>";
+const SUBJECT_END: &str = "</dc:subject>";
+
+/// The rating of the image at `path`, 0 when it has none
+pub fn rating(path: &Path) -> i8 {
+    packets(path)
+        .find_map(|xmp| parse_rating(&xmp))
+        .unwrap_or(0)
+}
+
+/// The keywords of the image at `path`, i.e. `dc:subject`
+pub fn keywords(path: &Path) -> Vec<String> {
+    packets(path)
+        .map(|xmp| parse_keywords(&xmp))
+        .find(|keywords| !keywords.is_empty())
+        .unwrap_or_default()
+}
+
+/// The XMP packets of the image, the ones in the sidecar files first. They are only read when
+/// the previous ones don't have the value
+fn packets(path: &Path) -> impl Iterator<Item = String> + '_ {
+    let mut appended = path.as_os_str().to_owned();
+    appended.push(".xmp");
+    [appended.into(), path.with_extension("xmp")]
+        .into_iter()
+        .filter_map(|sidecar: PathBuf| fs::read_to_string(sidecar).ok())
+        .chain(std::iter::once_with(|| embedded_packet(path)).flatten())
+}
+
+fn embedded_packet(path: &Path) -> Option<String> {
+    let file = File::open(path).ok()?;
+    if ImageFormat::from_path(path).ok()? == ImageFormat::Jpeg {
+        let mut decoder = jpeg_decoder::Decoder::new(BufReader::new(file));
+        decoder.read_info().ok()?;
+        return Some(String::from_utf8_lossy(decoder.xmp_data()?).into_owned());
+    }
+    // The XMP packet is stored as plain text, so that it can be found without parsing the format
+    let mut data = Vec::new();
+    file.take(MAX_SCANNED).read_to_end(&mut data).ok()?;
+    let data = String::from_utf8_lossy(&data);
+    let start = data.find(PACKET_START)?;
+    let end = data[start..].find(PACKET_END)? + start;
+    Some(data[start..end].to_string())
+}
+
+/// Parse `xmp:Rating`, written either as an attribute, `xmp:Rating="4"`, or as an element,
+/// `<xmp:Rating>4</xmp:Rating>`
+fn parse_rating(xmp: &str) -> Option<i8> {
+    xmp.match_indices(RATING).find_map(|(index, _)| {
+        let rest = xmp[index + RATING.len()..].trim_start();
+        let value = if let Some(rest) = rest.strip_prefix('=') {
+            let rest = rest.trim_start();
+            let quote = rest.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+            rest[1..].split(quote).next()?
+        } else {
+            rest.strip_prefix('>')?.split('<').next()?
+        };
+        // It is a real number, although the photo managers only write integers
+        let rating = value.trim().parse::<f32>().ok()?;
+        Some(rating.clamp(-1.0, 5.0) as i8)
+    })
+}
+
+/// Parse the items of `dc:subject`, e.g. `<dc:subject><rdf:Bag><rdf:li>dark</rdf:li></rdf:Bag>`
+fn parse_keywords(xmp: &str) -> Vec<String> {
+    let Some(start) = xmp.find(SUBJECT_START) else {
+        return Vec::new();
+    };
+    let subject = &xmp[start + SUBJECT_START.len()..];
+    let subject = &subject[..subject.find(SUBJECT_END).unwrap_or(subject.len())];
+    subject
+        .split("<rdf:li")
+        .skip(1)
+        .filter_map(|item| {
+            let (_, value) = item.split_once('>')?;
+            let keyword = value.split('<').next()?.trim();
+            (!keyword.is_empty()).then(|| unescape(keyword))
+        })
+        .collect()
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rating() {
+        let digikam = r#"<rdf:Description rdf:about="" xmp:Rating="4" xmp:CreatorTool="digiKam"/>"#;
+        assert_eq!(parse_rating(digikam), Some(4));
+        let darktable = "<rdf:Description>\n  <xmp:Rating>-1</xmp:Rating>\n</rdf:Description>";
+        assert_eq!(parse_rating(darktable), Some(-1));
+        assert_eq!(parse_rating("<xmp:Rating = '5.0' />"), Some(5));
+        assert_eq!(parse_rating(r#"<rdf:Description xmp:Label="Red"/>"#), None);
+    }
+
+    #[test]
+    fn test_parse_keywords() {
+        let xmp = "<dc:subject>\n <rdf:Bag>\n  <rdf:li>dark</rdf:li>\n  \
+            <rdf:li xml:lang=\"en\">Sea &amp; sky</rdf:li>\n </rdf:Bag>\n</dc:subject>\n\
+            <lr:hierarchicalSubject><rdf:Bag><rdf:li>places|sea</rdf:li></rdf:Bag>\
+            </lr:hierarchicalSubject>";
+        assert_eq!(parse_keywords(xmp), ["dark", "Sea & sky"]);
+        assert!(parse_keywords("<rdf:Description xmp:Rating=\"4\"/>").is_empty());
+    }
+}
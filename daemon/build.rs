@@ -1,6 +1,3 @@
-extern crate gl_generator;
-
-use gl_generator::{Api, Fallbacks, Profile, Registry, StructGenerator};
 use std::env;
 use std::fs::File;
 use std::io::Error;
@@ -48,17 +45,5 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     build_shell_completion(&comp_path)?;
     build_manpages(&man_path)?;
 
-    let mut file = File::create(Path::new(&outdir).join("gl_bindings.rs")).unwrap();
-
-    Registry::new(
-        Api::Gles2,
-        (2, 0),
-        Profile::Core,
-        Fallbacks::All,
-        ["GL_EXT_texture_border_clamp"],
-    )
-    .write_bindings(StructGenerator, &mut file)
-    .unwrap();
-
     Ok(())
 }
@@ -0,0 +1,63 @@
+//! Minimal journald log writer.
+//! It talks the native journal protocol described in
+//! <https://systemd.io/JOURNAL_NATIVE_PROTOCOL/>
+
+use std::{io::Write, os::unix::net::UnixDatagram};
+
+use flexi_logger::{writers::LogWriter, DeferredNow};
+use log::{Level, Record};
+
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+pub struct JournaldWriter {
+    socket: UnixDatagram,
+}
+
+impl JournaldWriter {
+    pub fn new() -> std::io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(JOURNALD_SOCKET)?;
+        Ok(Self { socket })
+    }
+}
+
+/// Append a field to the datagram. Values containing a newline need to be
+/// serialized with their length prepended.
+fn append_field(buf: &mut Vec<u8>, key: &str, value: &[u8]) {
+    buf.extend_from_slice(key.as_bytes());
+    if value.contains(&b'\n') {
+        buf.push(b'\n');
+        buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        buf.push(b'=');
+    }
+    buf.extend_from_slice(value);
+    buf.push(b'\n');
+}
+
+fn priority(level: Level) -> &'static [u8] {
+    match level {
+        Level::Error => b"3",
+        Level::Warn => b"4",
+        Level::Info => b"6",
+        Level::Debug | Level::Trace => b"7",
+    }
+}
+
+impl LogWriter for JournaldWriter {
+    fn write(&self, _now: &mut DeferredNow, record: &Record) -> std::io::Result<()> {
+        let mut message = Vec::new();
+        write!(message, "{}", record.args())?;
+
+        let mut buf = Vec::with_capacity(message.len() + 64);
+        append_field(&mut buf, "PRIORITY", priority(record.level()));
+        append_field(&mut buf, "SYSLOG_IDENTIFIER", b"wpaperd");
+        append_field(&mut buf, "MESSAGE", &message);
+        self.socket.send(&buf)?;
+        Ok(())
+    }
+
+    fn flush(&self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
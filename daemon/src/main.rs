@@ -1,88 +1,295 @@
-mod config;
-mod display_info;
-mod filelist_cache;
-mod image_loader;
-mod image_picker;
-mod ipc_server;
+mod journald;
 mod opts;
-mod render;
-mod socket;
-mod surface;
-mod wallpaper_groups;
-mod wallpaper_info;
-mod wpaperd;
+mod self_test;
+mod setup;
 
 extern crate khronos_egl as egl;
 
 use std::{
     cell::RefCell,
+    env,
     fs::File,
     io::Write,
     os::fd::FromRawFd,
+    path::{Path, PathBuf},
     process::exit,
     rc::Rc,
-    sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc,
-    },
+    time::Duration,
 };
 
-use clap::Parser;
+use chrono::{Local, NaiveTime};
+
+use clap::{CommandFactory, Parser};
 use color_eyre::{
-    eyre::{anyhow, ContextCompat, WrapErr},
+    eyre::{anyhow, ensure, ContextCompat, WrapErr},
     Result, Section,
 };
-use config::Config;
 use egl::API as egl;
-use filelist_cache::FilelistCache;
-use flexi_logger::{Duplicate, FileSpec, Logger};
-use hotwatch::Hotwatch;
-use ipc_server::{handle_message, listen_on_ipc_socket};
-use log::error;
+use flexi_logger::{Cleanup, Criterion, Duplicate, FileSpec, Logger, Naming};
+use journald::JournaldWriter;
+use log::{debug, error, info, warn};
 use nix::unistd::fork;
-use opts::Opts;
+use opts::{Command, LogTarget, Opts};
 use smithay_client_toolkit::reexports::{
-    calloop,
+    calloop::{
+        self,
+        signals::{Signal, Signals},
+        timer::{TimeoutAction, Timer},
+        LoopHandle, RegistrationToken,
+    },
     calloop_wayland_source::WaylandSource,
     client::{globals::registry_queue_init, Connection, Proxy},
 };
-use wallpaper_groups::WallpaperGroups;
-use wallpaper_info::Sorting;
-use wpaperd_ipc::socket_path;
+use wpaperd_core::{
+    backend::{render_nodes, Backend},
+    bench::{bench, BenchOptions},
+    brightness,
+    capabilities::{backends, CODECS, PROTOCOLS},
+    color_scheme::ColorSchemeWatcher,
+    config::{
+        tilde_expansion, Config, ConfigErrorKind, LoadOptions, SerializedWallpaperInfo,
+        RELOAD_RETRY_INTERVAL,
+    },
+    conflicts,
+    events::{self, Event},
+    filelist_cache::FilelistCache,
+    handoff::Handoff,
+    ipc_server::{handle_message, listen_on_ipc_socket, listen_on_remote_socket},
+    migrate,
+    runtime_state::{runtime_state_path, RuntimeStateFile},
+    sandbox::{Access, Sandbox},
+    state::State,
+    wall_clock,
+    wallpaper_groups::WallpaperGroups,
+    wallpaper_info::Sorting,
+    watcher::Watcher,
+    weather::WeatherWatcher,
+    Wpaperd,
+};
+use wpaperd_ipc::{seat, socket_path, RemoteAddress};
 use xdg::BaseDirectories;
 
-use crate::wpaperd::Wpaperd;
+/// Rotate the log file once it reaches this size (in bytes)
+const LOG_FILE_MAX_SIZE: u64 = 10 * 1024 * 1024;
+/// Number of rotated log files to keep around
+const LOG_FILES_KEPT: usize = 3;
+
+/// How often the first wallpapers are checked while replacing another instance
+const HANDOFF_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Signals handled by the event loop
+const HANDLED_SIGNALS: [Signal; 3] = [Signal::SIGTERM, Signal::SIGINT, Signal::SIGHUP];
+
+/// The first configuration file found in `dirs`, searched in order
+fn find_in(dirs: impl IntoIterator<Item = PathBuf>) -> Option<PathBuf> {
+    dirs.into_iter().find_map(|dir| {
+        // The legacy file has precedence, the JSON one is usually generated
+        ["wallpaper.toml", "config.toml", "config.json"]
+            .iter()
+            .map(|name| dir.join(name))
+            .find(|file| file.exists())
+    })
+}
 
-fn run(opts: Opts, xdg_dirs: BaseDirectories) -> Result<()> {
-    // Path passed from the CLI or the wpaperd.toml file has precedence
-    let config_file = if let Some(config) = opts.config {
-        config
-    } else {
-        // Read the new config or the legacy file
-        let legacy_config_file = xdg_dirs
-            .place_config_file("wallpaper.toml")
-            .context("unable to identify legacy config file wallpaper.toml")?;
-        if legacy_config_file.exists() {
-            legacy_config_file
+/// Return the configuration file of the user that exists, if any
+fn find_existing_config_file(xdg_dirs: &BaseDirectories) -> Option<PathBuf> {
+    // XDG_CONFIG_HOME might point somewhere else
+    let home = env::var_os("HOME").map(|home| PathBuf::from(home).join(".config/wpaperd"));
+    find_in(std::iter::once(xdg_dirs.get_config_home()).chain(home))
+}
+
+/// Return the configuration file of the user, the one in XDG_CONFIG_HOME by default
+fn find_config_file(xdg_dirs: &BaseDirectories) -> Result<PathBuf> {
+    match find_existing_config_file(xdg_dirs) {
+        Some(file) => Ok(file),
+        // Watched until it gets created
+        None => xdg_dirs
+            .place_config_file("config.toml")
+            .context("unable to identify config file config.toml"),
+    }
+}
+
+/// Return the system-wide configuration, the file of the user is merged over it
+fn find_system_config_file(xdg_dirs: &BaseDirectories) -> Option<PathBuf> {
+    find_in(
+        xdg_dirs
+            .get_config_dirs()
+            .into_iter()
+            .chain([PathBuf::from("/etc/wpaperd")]),
+    )
+}
+
+/// Only allow the files used by wpaperd, see [Sandbox]
+fn apply_sandbox(
+    config: &Config,
+    xdg_dirs: &BaseDirectories,
+    token_file: Option<&Path>,
+) -> Result<()> {
+    let mut sandbox = Sandbox::default();
+    // The directories are watched, to see the files created
+    for config_file in config.config_files() {
+        sandbox.allow(config_file.parent().unwrap_or(config_file), Access::Read);
+    }
+    for path in config.accessed_paths() {
+        // The sidecar files are saved next to the images
+        let access = if path.is_dir() {
+            Access::ReadWrite
         } else {
-            xdg_dirs
-                .place_config_file("config.toml")
-                .context("unable to identify config file config.toml")?
+            Access::Read
+        };
+        sandbox.allow(path, access);
+    }
+    // Create them now, their parents cannot be written anymore afterwards
+    for dir in [xdg_dirs.get_state_home(), xdg_dirs.get_cache_home()] {
+        std::fs::create_dir_all(&dir).with_context(|| format!("creating directory {dir:?}"))?;
+        sandbox.allow(dir, Access::ReadWrite);
+    }
+    if let Ok(runtime_dir) = xdg_dirs.get_runtime_directory() {
+        sandbox.allow(runtime_dir, Access::ReadWrite);
+    }
+    sandbox.allow(env::temp_dir(), Access::ReadWrite);
+    if let Some(token_file) = token_file {
+        sandbox.allow(token_file, Access::Read);
+    }
+    for template in config.theme.iter().flat_map(|theme| &theme.templates) {
+        sandbox.allow(&template.input, Access::Read);
+        if let Some(dir) = template.output.parent() {
+            std::fs::create_dir_all(dir).with_context(|| format!("creating directory {dir:?}"))?;
+            sandbox.allow(dir, Access::ReadWrite);
+        }
+    }
+    if config.reads_light_sensor() {
+        for path in brightness::sensor_paths() {
+            sandbox.allow(path, Access::Read);
+        }
+    }
+    sandbox.apply()
+}
+
+/// How the configuration is read, following the command line
+fn load_options(opts: &Opts, system_path: Option<PathBuf>) -> LoadOptions {
+    LoadOptions {
+        strict: opts.strict,
+        cli_defaults: SerializedWallpaperInfo {
+            path: opts.path.as_deref().map(tilde_expansion),
+            duration: opts.duration,
+            ..Default::default()
+        },
+        system_path,
+    }
+}
+
+/// The configuration file, for the commands that only read it
+fn read_only_config_file(opts: &Opts, xdg_dirs: &BaseDirectories) -> PathBuf {
+    // The file might be read-only, e.g. in the Nix store, and its directory must not be created
+    opts.config
+        .clone()
+        .or_else(|| find_existing_config_file(xdg_dirs))
+        .unwrap_or_else(|| xdg_dirs.get_config_home().join("config.toml"))
+}
+
+/// The backend set in the configuration, the default one when it cannot be read
+fn configured_backend(opts: &Opts, xdg_dirs: &BaseDirectories) -> Backend {
+    let options = load_options(opts, find_system_config_file(xdg_dirs));
+    Config::new_from_path(&read_only_config_file(opts, xdg_dirs), &options)
+        .map(|config| config.backend().clone())
+        .unwrap_or_default()
+}
+
+/// Read the configuration like at startup, without writing anything, and return the exit code of
+/// `wpaperd --check-config`, telling apart the classes of errors for the activation scripts
+fn check_config(opts: &Opts, xdg_dirs: &BaseDirectories) -> i32 {
+    let config_file = read_only_config_file(opts, xdg_dirs);
+    let options = load_options(opts, find_system_config_file(xdg_dirs));
+    match Config::new_from_path(&config_file, &options) {
+        Ok(config) => match config.error_kind() {
+            None => {
+                println!("Configuration {config_file:?} is valid");
+                0
+            }
+            Some(kind) => {
+                for error in config.errors() {
+                    eprintln!("{error}\n");
+                }
+                kind.exit_code()
+            }
+        },
+        Err(err) => {
+            eprintln!("{err:?}");
+            ConfigErrorKind::of(&err).exit_code()
         }
+    }
+}
+
+fn migrate_config(opts: &Opts, xdg_dirs: &BaseDirectories, dry_run: bool) -> Result<()> {
+    let config_file = read_only_config_file(opts, xdg_dirs);
+    migrate::ensure_exists(&config_file)?;
+    let changes = migrate::migrate_file(&config_file, dry_run)?;
+    if changes.is_empty() {
+        println!("Configuration {config_file:?} is up to date");
+        return Ok(());
+    }
+    for change in &changes {
+        println!("{change}");
+    }
+    let renamed = changes
+        .iter()
+        .any(|change| matches!(change, migrate::Change::Renamed { .. }));
+    if dry_run {
+        println!("\nConfiguration {config_file:?} left untouched, run again without --dry-run");
+    } else if renamed {
+        println!(
+            "\nConfiguration {config_file:?} migrated, the previous one is in {:?}",
+            migrate::backup_path(&config_file)
+        );
+    }
+    Ok(())
+}
+
+fn run(opts: Opts, xdg_dirs: BaseDirectories, signals: Signals) -> Result<()> {
+    // Path passed from the CLI has precedence
+    let config_file = match &opts.config {
+        Some(config) => config.clone(),
+        None => find_config_file(&xdg_dirs)?,
     };
+    info!("Using configuration file {config_file:?}");
+    let system_config_file = find_system_config_file(&xdg_dirs);
+    if let Some(system_config_file) = &system_config_file {
+        info!("Using system configuration file {system_config_file:?}");
+    }
 
-    let reloaded = Arc::new(AtomicBool::new(false));
+    let (event_sender, event_receiver, event_source) = events::channel()?;
+    let options = load_options(&opts, system_config_file);
     // Do not stop when the configuration is invalid, we can always reload it at runtime
-    let mut config = match Config::new_from_path(&config_file) {
-        Ok(config) => config,
-        Err(err) => {
-            error!("{err:?}");
-            let mut config = Config::default();
-            config.path = config_file;
-            config
+    let mut config = if !config_file.exists() && options.system_path.is_none() {
+        info!(
+            "Configuration file {config_file:?} not found, using the default values and the ones passed from the command line"
+        );
+        Config::without_file(&config_file, &options)
+    } else {
+        match Config::new_from_path(&config_file, &options) {
+            Ok(config) => config,
+            Err(err) if opts.strict => return Err(err),
+            Err(err) => {
+                error!("{err:?}");
+                let mut config = Config::default();
+                config.path = config_file;
+                config.options = options;
+                config
+            }
         }
     };
-    config.reloaded = Some(reloaded);
+    config.events = Some(event_sender.clone());
+    config.seed = opts.seed;
+    config.threads = opts.threads.map(usize::from);
+    config.seat = seat();
+
+    // Before starting the other threads, they are restricted as well
+    if opts.sandbox {
+        apply_sandbox(&config, &xdg_dirs, opts.token_file.as_deref())
+            .context("applying the sandbox")?;
+        info!("Sandbox applied");
+    }
 
     // we use the OpenGL ES API because it's more widely supported
     // and it's used by wlroots
@@ -93,13 +300,6 @@ fn run(opts: Opts, xdg_dirs: BaseDirectories) -> Result<()> {
         .context("connecting to wayland")
         .suggestion("Are you running a wayland compositor?")?;
 
-    let egl_display = unsafe {
-        egl.get_display(conn.display().id().as_ptr() as *mut std::ffi::c_void)
-            .context("getting the display from the WlDisplay")?
-    };
-    egl.initialize(egl_display)
-        .context("initializing the egl display")?;
-
     let (globals, event_queue) =
         registry_queue_init(&conn).context("initializing the wayland registry queue")?;
     let qh = event_queue.handle();
@@ -110,42 +310,198 @@ fn run(opts: Opts, xdg_dirs: BaseDirectories) -> Result<()> {
         .insert(event_loop.handle())
         .map_err(|e| anyhow!("insterting the wayland source into the event loop: {e}"))?;
 
-    let (ping, ping_source) =
-        calloop::ping::make_ping().context("Unable to create a calloop::ping::Ping")?;
+    // The events are taken at the start of each iteration of the main loop
     event_loop
         .handle()
-        .insert_source(ping_source, |_, _, _| {})
-        .map_err(|e| anyhow!("inserting the hotwatch event listener in the event loop: {e}"))?;
+        .insert_source(event_source, |_, _, _| {})
+        .map_err(|e| anyhow!("inserting the event channel in the event loop: {e}"))?;
+
+    event_loop
+        .handle()
+        .insert_source(signals, move |event, _, wpaperd| match event.signal() {
+            // Follow the same path as a configuration change detected by hotwatch
+            Signal::SIGHUP => {
+                info!("Received SIGHUP, reloading the configuration");
+                if let Some(events) = &wpaperd.config.events {
+                    events.send(Event::ConfigChanged);
+                }
+            }
+            signal => {
+                info!("Received {signal}, shutting down");
+                wpaperd.should_exit = true;
+            }
+        })
+        .map_err(|e| anyhow!("inserting the signals event source in the event loop: {e}"))?;
 
-    let mut hotwatch = Hotwatch::new().context("hotwatch failed to initialize")?;
-    config.listen_to_changes(&mut hotwatch, ping)?;
+    // Evaluate the calendars again when the day changes
+    let mut today = Local::now().date_naive();
+    event_loop
+        .handle()
+        .insert_source(
+            Timer::from_duration(wall_clock::next_check(until_midnight())),
+            move |_, _, wpaperd| {
+                if Local::now().date_naive() != today {
+                    today = Local::now().date_naive();
+                    wpaperd.config.change_day();
+                }
+                TimeoutAction::ToDuration(wall_clock::next_check(until_midnight()))
+            },
+        )
+        .map_err(|e| anyhow!("inserting the midnight timer in the event loop: {e}"))?;
 
-    let (ping, filelist_cache) =
-        FilelistCache::new(config.paths(), &mut hotwatch, event_loop.handle())?;
+    // Follow the light and the time of day, on the displays dimming the wallpaper
+    let brightness_qh = qh.clone();
+    event_loop
+        .handle()
+        .insert_source(
+            Timer::from_duration(brightness::UPDATE_INTERVAL),
+            move |_, _, wpaperd| {
+                wpaperd.update_brightness(&brightness_qh);
+                TimeoutAction::ToDuration(brightness::UPDATE_INTERVAL)
+            },
+        )
+        .map_err(|e| anyhow!("inserting the brightness timer in the event loop: {e}"))?;
+
+    // Look for the other programs drawing the background
+    let conflicts_qh = qh.clone();
+    event_loop
+        .handle()
+        .insert_source(Timer::immediate(), move |_, _, wpaperd| {
+            wpaperd.check_conflicts(&conflicts_qh);
+            TimeoutAction::ToDuration(conflicts::CHECK_INTERVAL)
+        })
+        .map_err(|e| anyhow!("inserting the conflicts timer in the event loop: {e}"))?;
+
+    // Show the directories set for the weather, when it is known
+    let mut weather = WeatherWatcher::new(config.weather.clone(), &event_loop.handle())?;
+    // Show the directories set for dark or light mode
+    let mut color_scheme = ColorSchemeWatcher::new(&event_loop.handle())?;
+    color_scheme.update(config.uses_color_scheme());
+
+    let mut watcher = Watcher::new(opts.poll_interval);
+    // Do not stop when the config file cannot be watched, wpaperd can still work without
+    // hot reloading. With --oneshot it can still be reloaded with SIGHUP
+    let mut config_dirs = Vec::new();
+    if !opts.oneshot {
+        match config.listen_to_changes(&mut watcher) {
+            Ok(dirs) => config_dirs = dirs,
+            Err(err) => error!("{err:?}"),
+        }
+    }
+
+    let (ping, filelist_cache) = FilelistCache::new(
+        config.paths(),
+        &mut watcher,
+        event_sender,
+        event_loop.handle(),
+        qh.clone(),
+    )?;
+    let mut filelist_cache = filelist_cache;
+    filelist_cache.delay_startup(config.startup_delay.is_some(), config.startup_scan_rate);
     let filelist_cache = Rc::new(RefCell::new(filelist_cache));
 
     let groups = Rc::new(RefCell::new(WallpaperGroups::new()));
 
+    let state_file = xdg_dirs
+        .place_state_file("state.json")
+        .context("unable to identify state file state.json")?;
+    let state = State::load(&state_file);
+    // Restore the values changed at runtime that have been persisted
+    for (name, display) in state.displays() {
+        if let Some(duration) = display.duration {
+            if let Err(err) = config.override_duration(name, duration) {
+                warn!("Ignoring the persisted duration for display {name}: {err:?}");
+            }
+        }
+    }
+    let state = Rc::new(RefCell::new(state));
+
     let mut wpaperd = Wpaperd::new(
         &qh,
         &globals,
         config,
-        egl_display,
+        conn.display(),
         filelist_cache.clone(),
         groups,
+        state,
     )?;
 
-    // Start listening on the IPC socket
-    let socket = listen_on_ipc_socket(&socket_path()?).context("spawning the ipc socket")?;
+    if opts.trace {
+        wpaperd.tracer.borrow_mut().start();
+    }
 
-    // Add source to calloop loop.
-    event_loop
-        .handle()
-        .insert_source(socket, |stream, _, wpaperd| {
-            if let Err(err) = handle_message(stream, qh.clone(), wpaperd) {
-                error!("{:?}", err);
-            }
-        })?;
+    // Show the background colors during startup-delay, then read the directories and load the
+    // wallpapers
+    if let Some(delay) = wpaperd.config.startup_delay {
+        wpaperd.cold_start.borrow_mut().delay();
+        let delay_qh = qh.clone();
+        let delay_ping = ping.clone();
+        event_loop
+            .handle()
+            .insert_source(Timer::from_duration(delay), move |_, _, wpaperd| {
+                wpaperd.cold_start.borrow_mut().resume();
+                wpaperd.filelist_cache.borrow_mut().resume(&delay_ping);
+                for surface in &mut wpaperd.surfaces {
+                    surface.queue_draw(&delay_qh);
+                }
+                TimeoutAction::Drop
+            })
+            .map_err(|e| anyhow!("inserting the startup delay timer in the event loop: {e}"))?;
+    }
+
+    // Start listening on the IPC socket, wpaperctl cannot change the wallpapers with --oneshot
+    let socket_path = if opts.oneshot {
+        None
+    } else {
+        Some(socket_path()?)
+    };
+    // The instance already running keeps its wallpapers until the ones of this instance are shown
+    let mut handoff = None;
+    let mut handoff_timer = None;
+    if let (true, Some(socket_path)) = (opts.replace, &socket_path) {
+        handoff = Handoff::start(socket_path).context("replacing the running instance")?;
+        if handoff.is_none() {
+            info!("No instance of wpaperd is running, nothing to replace");
+        } else {
+            // The surfaces are not mapped yet, so they get no frame callbacks: wake up the loop
+            // until the first wallpapers have been loaded
+            handoff_timer = Some(
+                event_loop
+                    .handle()
+                    .insert_source(Timer::immediate(), |_, _, _| {
+                        TimeoutAction::ToDuration(HANDOFF_POLL_INTERVAL)
+                    })
+                    .map_err(|e| anyhow!("inserting the handoff timer in the event loop: {e}"))?,
+            );
+        }
+    }
+    if let Some(socket_path) = &socket_path {
+        let socket = listen_on_ipc_socket(socket_path).context("spawning the ipc socket")?;
+
+        // Add source to calloop loop.
+        let qh_clone = qh.clone();
+        event_loop
+            .handle()
+            .insert_source(socket, move |stream, _, wpaperd| {
+                if let Err(err) = handle_message(stream, qh_clone.clone(), wpaperd) {
+                    error!("{:?}", err);
+                }
+            })?;
+    }
+
+    if let (Some(address), Some(token_file)) = (&opts.listen, &opts.token_file) {
+        let address: RemoteAddress = address
+            .parse()
+            .map_err(|err| anyhow!("{err}"))
+            .context("parsing the address passed to --listen")?;
+        let token = std::fs::read_to_string(token_file)
+            .with_context(|| format!("reading the token from {token_file:?}"))?
+            .trim()
+            .to_string();
+        ensure!(!token.is_empty(), "the token in {token_file:?} is empty");
+        listen_on_remote_socket(&address, token, &event_loop.handle(), qh.clone())
+            .context("spawning the remote ipc socket")?;
+    }
 
     if let Some(notify) = opts.notify {
         let mut f = unsafe { File::from_raw_fd(notify as i32) };
@@ -155,27 +511,102 @@ fn run(opts: Opts, xdg_dirs: BaseDirectories) -> Result<()> {
         }
     }
 
+    // Wake up when the quiet hours start or end
+    let mut quiet_hours_timer = insert_quiet_hours_timer(&event_loop.handle(), &wpaperd.config);
+    let mut reload_retry_timer = None;
+    // The state of the displays for the scripts, in $XDG_RUNTIME_DIR/wpaperd/state.json
+    let mut runtime_state =
+        RuntimeStateFile::new(runtime_state_path(wpaperd.config.seat.as_deref()));
+
     loop {
-        // If the config has been modified, this value will return true
-        if wpaperd
-            .config
-            .reloaded
-            .as_ref()
-            .unwrap()
-            .load(Ordering::Acquire)
-            && wpaperd.config.update()
+        // The configuration has been changed at runtime using wpaperctl
+        let overrides_changed = wpaperd.config.take_overrides_changed();
+        // The paths in the calendars must be evaluated again
+        let day_changed = wpaperd.config.take_day_changed();
+        // The paths set for the weather must be evaluated again
+        let weather_changed = wpaperd.config.take_weather_changed();
+        // The paths set for the color schemes must be evaluated again
+        let color_scheme_changed = wpaperd.config.take_color_scheme_changed();
+        // The active workspace changed on a display with directories for it
+        let workspaces_changed = wpaperd.config.take_workspaces_changed();
+        let received = event_receiver.take();
+        for event in &received {
+            debug!("Received {event:?}");
+            if let Event::DirChanged(dir) = event {
+                filelist_cache.borrow().rescan(dir);
+            }
+        }
+        // The configuration files have changed, or wpaperctl asked to read them again
+        let file_changed = received
+            .iter()
+            .any(|event| matches!(event, Event::ConfigChanged | Event::IpcCommand));
+        if file_changed && !opts.oneshot {
+            // The file might have been replaced through a symlink pointing somewhere else now
+            match wpaperd.config.listen_to_changes(&mut watcher) {
+                Ok(dirs) => {
+                    for dir in config_dirs.iter().filter(|dir| !dirs.contains(dir)) {
+                        if let Err(err) = watcher.unwatch(dir) {
+                            error!("{err:?}");
+                        }
+                    }
+                    config_dirs = dirs;
+                }
+                Err(err) => error!("{err:?}"),
+            }
+        }
+        // If the config has been modified, this value will return true. The events have been
+        // taken, the file must be read even when something else changed too
+        let config_updated = file_changed && wpaperd.config.update();
+        if overrides_changed
+            || day_changed
+            || weather_changed
+            || color_scheme_changed
+            || workspaces_changed
+            || config_updated
         {
             // Update the filelist cache, keep it up to date
             // We need to call this before because updating the surfaces
             // will start loading the wallpapers in the background
             filelist_cache.borrow_mut().update_paths(
                 wpaperd.config.paths(),
-                &mut hotwatch,
+                &mut watcher,
                 ping.clone(),
             );
 
             // Read the config, update the paths in the surfaces
             wpaperd.update_surfaces(event_loop.handle(), &qh);
+
+            // The quiet hours might have changed
+            if let Some(token) = quiet_hours_timer.take() {
+                event_loop.handle().remove(token);
+            }
+            quiet_hours_timer = insert_quiet_hours_timer(&event_loop.handle(), &wpaperd.config);
+
+            // The weather might be fetched from another place
+            weather.update(wpaperd.config.weather.as_ref());
+            color_scheme.update(wpaperd.config.uses_color_scheme());
+
+            // on-conflict might have changed
+            wpaperd.check_conflicts(&qh);
+        }
+        // The configuration file could not be loaded, it might still be being written
+        if wpaperd.config.take_retry_reload() {
+            if let Some(token) = reload_retry_timer.take() {
+                event_loop.handle().remove(token);
+            }
+            reload_retry_timer = event_loop
+                .handle()
+                .insert_source(
+                    Timer::from_duration(RELOAD_RETRY_INTERVAL),
+                    |_, _, wpaperd| {
+                        if let Some(events) = &wpaperd.config.events {
+                            events.send(Event::ConfigChanged);
+                        }
+                        TimeoutAction::Drop
+                    },
+                )
+                .map_err(|e| error!("inserting the reload timer in the event loop: {e}"))
+                .ok();
         }
 
         // Due to how LayerSurface works, we cannot attach the egl window right away.
@@ -183,6 +614,12 @@ fn run(opts: Opts, xdg_dirs: BaseDirectories) -> Result<()> {
         // Afterwards we need to draw for the first time and then add a timer if needed.
         // We cannot use WlSurface::frame() because it only works for windows that are
         // already visible, hence we need to draw for the first time and then commit.
+        let quiet = wpaperd.config.is_quiet();
+        wpaperd.inhibitors.update_timer(&event_loop.handle());
+        let inhibited = wpaperd.inhibitors.is_active();
+        let zen = wpaperd.config.zen();
+        let mut crashed_outputs = Vec::new();
+        let replacing = handoff.is_some();
         wpaperd.surfaces.iter_mut().for_each(|surface| {
             if !surface.is_configured() {
                 return;
@@ -190,14 +627,27 @@ fn run(opts: Opts, xdg_dirs: BaseDirectories) -> Result<()> {
 
             // This is only true once per surface at startup (or when a new display gets connected)
             if !surface.has_been_drawn() {
-                surface.add_timer(None, &event_loop.handle(), qh.clone());
-                if let Err(err) = surface.draw(&qh, None) {
-                    error!("{err:?}");
-                };
+                // The surface is mapped over the one of the old instance only once it can draw
+                // its wallpaper
+                if replacing && !surface.load_first_wallpaper(&qh) {
+                    return;
+                }
+                // The wallpaper drawn first is kept with --oneshot
+                if !opts.oneshot {
+                    surface.add_timer(None, &event_loop.handle(), qh.clone());
+                }
+                if !surface.draw_catching_panic(&qh, None) {
+                    crashed_outputs.push(surface.wl_output().clone());
+                    return;
+                }
                 surface.drawn();
             } else {
                 // If the surface has already been drawn for the first time, then handle pausing/resuming
                 // the automatic wallpaper sequence.
+                surface.set_quiet(quiet);
+                surface.set_covered(wpaperd.fullscreen.covers(surface.wl_output()));
+                surface.set_inhibited(inhibited);
+                surface.set_zen(zen);
                 surface.handle_pause_state(&event_loop.handle(), qh.clone());
                 if matches!(
                     surface.wallpaper_info.sorting,
@@ -210,13 +660,186 @@ fn run(opts: Opts, xdg_dirs: BaseDirectories) -> Result<()> {
             #[cfg(debug_assertions)]
             wpaperd.image_loader.borrow_mut().check_lingering_threads();
         });
+        for output in crashed_outputs {
+            wpaperd.recreate_surface(&qh, output);
+        }
+        wpaperd.forget_recoveries();
+        let all_drawn = !wpaperd.surfaces.is_empty()
+            && wpaperd
+                .surfaces
+                .iter()
+                .all(|surface| surface.has_been_drawn());
+        if handoff
+            .as_ref()
+            .is_some_and(|handoff| all_drawn || handoff.is_timed_out())
+        {
+            match handoff.take().expect("handoff to be pending").finish() {
+                Ok(()) => info!("The previous instance of wpaperd has been replaced"),
+                Err(err) => error!("{err:?}"),
+            }
+            if let Some(token) = handoff_timer.take() {
+                event_loop.handle().remove(token);
+            }
+        }
+        runtime_state.update(&wpaperd);
 
         event_loop
             .dispatch(None, &mut wpaperd)
             .context("dispatching the event loop")?;
+
+        if wpaperd.should_exit {
+            break;
+        }
+    }
+
+    // The new instance uses the same socket and runtime state
+    if wpaperd.replaced {
+        return Ok(());
+    }
+    runtime_state.remove();
+    wpaperd.state.borrow_mut().flush();
+    // Do not leave a stale socket behind
+    if let Some(socket_path) = socket_path {
+        if let Err(err) = std::fs::remove_file(&socket_path) {
+            error!("Could not remove the IPC socket {socket_path:?}: {err:?}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Add a timer that fires each time the quiet hours start or end, so that the surfaces are paused
+/// or resumed in the main loop. It also wakes up the loop to check the wall clock, see
+/// [wall_clock]
+fn insert_quiet_hours_timer(
+    handle: &LoopHandle<Wpaperd>,
+    config: &Config,
+) -> Option<RegistrationToken> {
+    let duration = config.quiet_hours.as_ref()?.until_change(Local::now());
+    handle
+        .insert_source(
+            Timer::from_duration(wall_clock::next_check(duration)),
+            |_, _, wpaperd| match &wpaperd.config.quiet_hours {
+                Some(quiet_hours) => TimeoutAction::ToDuration(wall_clock::next_check(
+                    quiet_hours.until_change(Local::now()),
+                )),
+                None => TimeoutAction::Drop,
+            },
+        )
+        .map_err(|e| error!("inserting the quiet hours timer in the event loop: {e}"))
+        .ok()
+}
+
+/// Run `wpaperd bench` and print the times
+fn run_bench(image: &Path, options: &BenchOptions) -> Result<()> {
+    // The upload is measured only when running inside a wayland compositor
+    let egl_display = Connection::connect_to_env()
+        .map_err(|err| anyhow!(err))
+        .and_then(|conn| unsafe {
+            egl.bind_api(egl::OPENGL_ES_API)?;
+            let egl_display = egl
+                .get_display(conn.display().id().as_ptr() as *mut std::ffi::c_void)
+                .context("getting the display from the WlDisplay")?;
+            egl.initialize(egl_display)?;
+            // The connection must stay open while the display is used
+            std::mem::forget(conn);
+            Ok(egl_display)
+        })
+        .inspect_err(|err| warn!("The upload can't be measured: {err:#}"))
+        .ok();
+
+    let times = bench(image, options, egl_display)
+        .with_context(|| format!("running the benchmark on {image:?}"))?;
+    let millis = |duration: Duration| format!("{:.1}ms", duration.as_secs_f64() * 1000.0);
+    println!("decode: {}", millis(times.decode));
+    println!("filter: {}", millis(times.filter));
+    println!("scale: {}", millis(times.scale));
+    if let Some(upload) = times.upload {
+        println!("upload: {}", millis(upload));
+    }
+    println!(
+        "total: {}",
+        millis(times.decode + times.filter + times.scale + times.upload.unwrap_or_default())
+    );
+    Ok(())
+}
+
+/// Print the man page built from the same definitions of the arguments, for `wpaperd gen-man`
+fn gen_man(dir: Option<&Path>) -> std::io::Result<()> {
+    match dir {
+        Some(dir) => clap_mangen::generate_to(Opts::command(), dir),
+        None => clap_mangen::Man::new(Opts::command()).render(&mut std::io::stdout()),
+    }
+}
+
+/// Print what this build supports, for `wpaperd --capabilities`
+fn print_capabilities(backend: &Backend) {
+    let width = CODECS
+        .iter()
+        .map(|codec| codec.name.len())
+        .max()
+        .unwrap_or(0);
+    println!("Image formats:");
+    for codec in CODECS {
+        let status = match (codec.enabled, codec.feature) {
+            (true, _) => "yes".to_string(),
+            (false, Some(feature)) => format!("no, build with feature {feature}"),
+            (false, None) => "no, not supported".to_string(),
+        };
+        println!(
+            "  {:width$}  {status} ({})",
+            codec.name,
+            codec.extensions.join(", ")
+        );
+    }
+    println!("Wayland protocols:");
+    for (protocol, required) in PROTOCOLS {
+        let status = if *required { "required" } else { "optional" };
+        println!("  {protocol} ({status})");
+    }
+    println!("Backends:");
+    for (backend, available) in backends() {
+        let status = if available { "yes" } else { "no" };
+        println!("  {backend}: {status}");
+    }
+    let render_node = backend.render_node();
+    println!("GPUs:");
+    let nodes = render_nodes();
+    if nodes.is_empty() {
+        println!("  none found");
+    }
+    for (index, node) in nodes.into_iter().enumerate() {
+        let chosen = matches!(&render_node, Ok(Some(path)) if *path == node.path);
+        println!(
+            "  {index}: {} {} ({}){}",
+            node.path.display(),
+            node.tag.as_deref().unwrap_or("not a PCI device"),
+            node.driver.as_deref().unwrap_or("unknown driver"),
+            if chosen { ", used" } else { "" }
+        );
+    }
+    match render_node {
+        Ok(_) => println!("Backend used: {backend}"),
+        Err(err) => println!("Backend used: {backend}, {err:#}"),
     }
 }
 
+/// Time left until the next midnight, in local time
+fn until_midnight() -> Duration {
+    let now = Local::now();
+    let tomorrow = now.date_naive().succ_opt().unwrap_or(now.date_naive());
+    tomorrow
+        .and_time(NaiveTime::MIN)
+        .and_local_timezone(Local)
+        .earliest()
+        .map(|midnight| midnight.signed_duration_since(now))
+        .and_then(|duration| duration.to_std().ok())
+        // Check again in one hour if midnight doesn't exist, i.e. daylight saving time
+        .unwrap_or(Duration::from_secs(3600))
+        // Make sure that the date has changed when the timer fires
+        + Duration::from_secs(1)
+}
+
 fn main() -> Result<()> {
     color_eyre::install()?;
 
@@ -224,23 +847,99 @@ fn main() -> Result<()> {
 
     let opts = Opts::parse();
 
+    if opts.capabilities {
+        print_capabilities(&configured_backend(&opts, &xdg_dirs));
+        return Ok(());
+    }
+
+    if opts.check_config {
+        exit(check_config(&opts, &xdg_dirs));
+    }
+
+    match &opts.command {
+        Some(Command::Completions { shell }) => {
+            let mut command = Opts::command();
+            clap_complete::generate(*shell, &mut command, "wpaperd", &mut std::io::stdout());
+            return Ok(());
+        }
+        Some(Command::GenMan { dir }) => {
+            return gen_man(dir.as_deref()).context("writing the man page");
+        }
+        Some(Command::SelfTest {
+            compositor,
+            timeout,
+        }) => {
+            let passed = self_test::run(compositor.as_deref(), *timeout)?;
+            exit(if passed { 0 } else { 1 });
+        }
+        Some(Command::MigrateConfig { dry_run }) => {
+            return migrate_config(&opts, &xdg_dirs, *dry_run);
+        }
+        Some(Command::Setup) => {
+            return setup::run(&read_only_config_file(&opts, &xdg_dirs));
+        }
+        _ => {}
+    }
+
+    if let Some(Command::Bench {
+        image,
+        size: (width, height),
+        mode,
+        resize_filter,
+        no_linear_scaling,
+        iterations,
+    }) = opts.command
+    {
+        Logger::try_with_env_or_str("warn")?.start()?;
+        let options = BenchOptions {
+            width,
+            height,
+            mode,
+            resize_filter,
+            linear_scaling: !no_linear_scaling,
+            iterations,
+        };
+        return run_bench(&image, &options);
+    }
+
+    // Signals::new blocks the signals in the current thread. Do it before spawning any other
+    // thread, otherwise they might be delivered to a thread that doesn't block them and
+    // terminate wpaperd
+    let signals = Signals::new(&HANDLED_SIGNALS).context("creating the signals event source")?;
+
     let mut logger = Logger::try_with_env_or_str(if opts.verbose { "debug" } else { "info" })?;
 
+    // If wpaperd detach, then log to files by default,
+    // otherwise prints everything in the stdout/stderr
+    let log_target = opts.log_target.unwrap_or(if opts.daemon {
+        LogTarget::File
+    } else {
+        LogTarget::Stderr
+    });
+    logger = match log_target {
+        LogTarget::Stderr => logger.duplicate_to_stderr(Duplicate::Warn),
+        LogTarget::Journald => logger.log_to_writer(Box::new(
+            JournaldWriter::new().context("connecting to the systemd journal")?,
+        )),
+        LogTarget::File => logger
+            .log_to_file(FileSpec::default().directory(xdg_dirs.get_state_home()))
+            .rotate(
+                Criterion::Size(LOG_FILE_MAX_SIZE),
+                Naming::Numbers,
+                Cleanup::KeepLogFiles(LOG_FILES_KEPT),
+            ),
+    };
+
     if opts.daemon {
-        // If wpaperd detach, then log to files
-        logger = logger.log_to_file(FileSpec::default().directory(xdg_dirs.get_state_home()));
         match unsafe { fork()? } {
             nix::unistd::ForkResult::Parent { child: _ } => exit(0),
             nix::unistd::ForkResult::Child => {}
         }
-    } else {
-        // otherwise prints everything in the stdout/stderr
-        logger = logger.duplicate_to_stderr(Duplicate::Warn);
     }
 
     logger.start()?;
 
-    if let Err(err) = run(opts, xdg_dirs) {
+    if let Err(err) = run(opts, xdg_dirs, signals) {
         error!("{err:?}");
         Err(err)
     } else {
@@ -1,6 +1,85 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
 use clap::Parser;
+use wpaperd_ipc::{BackgroundMode, ResizeFilter};
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogTarget {
+    /// Print the logs to the standard error
+    Stderr,
+    /// Send the logs to the systemd journal
+    Journald,
+    /// Write the logs into XDG_STATE_HOME/wpaperd, rotating them by size
+    File,
+}
+
+/// Parse a size in the format `<width>x<height>`
+fn parse_size(size: &str) -> Result<(u32, u32), String> {
+    let (width, height) = size
+        .split_once('x')
+        .ok_or_else(|| format!("{size:?} is not in the format <width>x<height>"))?;
+    let parse = |side: &str| {
+        side.parse::<u32>()
+            .ok()
+            .filter(|side| *side > 0)
+            .ok_or_else(|| format!("{side:?} is not a valid size"))
+    };
+    Ok((parse(width)?, parse(height)?))
+}
+
+#[derive(clap::Subcommand)]
+pub enum Command {
+    /// Measure the time taken to decode, filter, scale and upload an image, using the settings in
+    /// its sidecar file
+    Bench {
+        image: PathBuf,
+        /// Size of the display
+        #[clap(long, value_parser = parse_size, default_value = "3840x2160")]
+        size: (u32, u32),
+        #[clap(long, value_enum, default_value = "center")]
+        mode: BackgroundMode,
+        #[clap(long, value_enum, default_value = "bilinear")]
+        resize_filter: ResizeFilter,
+        /// Scale the sRGB values directly instead of linear light, faster but darker
+        #[clap(long)]
+        no_linear_scaling: bool,
+        /// Load the image this many times and print the average
+        #[clap(long, value_parser = clap::value_parser!(u32).range(1..), default_value = "5")]
+        iterations: u32,
+    },
+    /// Run this build inside a nested compositor, in a temporary directory, and check that the
+    /// surfaces, the rotation, the IPC and the hot reloading work. Exit with 1 if a check fails
+    SelfTest {
+        /// Command of the compositor, run by sh with XDG_RUNTIME_DIR set to the temporary
+        /// directory. Headless sway by default
+        #[clap(long)]
+        compositor: Option<String>,
+        /// How long each check can take
+        #[clap(long, value_parser = humantime::parse_duration, default_value = "10s")]
+        timeout: Duration,
+    },
+    /// Ask which wallpapers to show on each display, write the configuration and offer to start
+    /// wpaperd with the graphical session using systemd
+    Setup,
+    /// Rename the keys of the configuration renamed since an older version of wpaperd, saving the
+    /// previous file with the .bak extension
+    MigrateConfig {
+        /// Print the changes without writing the configuration
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Print the completions of wpaperd for the shell
+    Completions {
+        #[clap(value_enum)]
+        shell: clap_complete::Shell,
+    },
+    /// Print the man page of wpaperd
+    GenMan {
+        /// Write a man page for wpaperd and one for each subcommand into this directory instead
+        #[clap(long)]
+        dir: Option<PathBuf>,
+    },
+}
 
 #[derive(Parser)]
 #[clap(
@@ -16,6 +95,17 @@ pub struct Opts {
         help = "Path to the configuration (XDG_CONFIG_HOME/wpaperd/config.toml by default)"
     )]
     pub config: Option<PathBuf>,
+    #[clap(
+        long,
+        help = "Show the images in this directory on the displays whose configuration doesn't set a path, also when there is no configuration file"
+    )]
+    pub path: Option<PathBuf>,
+    #[clap(
+        long,
+        value_parser = humantime::parse_duration,
+        help = "Change the wallpaper with this interval on the displays whose configuration doesn't set one"
+    )]
+    pub duration: Option<Duration>,
     #[clap(
         action,
         short,
@@ -25,9 +115,83 @@ pub struct Opts {
     pub daemon: bool,
     #[clap(short, long, help = "Increase the verbosity of wpaperd")]
     pub verbose: bool,
+    #[clap(
+        long,
+        value_enum,
+        help = "Where to write the logs (file when running with --daemon, stderr otherwise)"
+    )]
+    pub log_target: Option<LogTarget>,
+    #[clap(
+        long,
+        value_parser = humantime::parse_duration,
+        help = "Poll the configuration and the wallpaper directories for changes with this interval, instead of using inotify"
+    )]
+    pub poll_interval: Option<Duration>,
+    #[clap(
+        long,
+        help = "Seed used to pick the wallpapers randomly, for the displays that don't set one"
+    )]
+    pub seed: Option<u64>,
+    #[clap(
+        long,
+        value_parser = clap::value_parser!(u16).range(1..),
+        help = "Number of threads used to load the images (one for each CPU by default)"
+    )]
+    pub threads: Option<u16>,
+    #[clap(
+        long,
+        help = "Refuse the configuration when any section has an error, instead of ignoring the sections with an error"
+    )]
+    pub strict: bool,
+    #[clap(
+        long,
+        help = "Only allow accessing the files used by wpaperd and block the syscalls it doesn't need, using Landlock and seccomp"
+    )]
+    pub sandbox: bool,
     #[clap(
         long,
         help = "Readiness fd used by wpaperd to signal that it has started correctly"
     )]
     pub notify: Option<u8>,
+    #[clap(
+        long,
+        help = "Draw the wallpapers once and keep them shown without changing them: no timers, no hot reloading and no IPC socket"
+    )]
+    pub oneshot: bool,
+    #[clap(
+        long,
+        conflicts_with = "oneshot",
+        help = "Take over from the instance of wpaperd already running, which exits once the new wallpapers are shown"
+    )]
+    pub replace: bool,
+    #[clap(
+        long,
+        help = "Record each stage of the rotation of the wallpapers from startup, printed by wpaperctl trace"
+    )]
+    pub trace: bool,
+    #[clap(
+        long,
+        requires = "token_file",
+        conflicts_with = "oneshot",
+        help = "Also accept commands from remote clients on this address, <host>:<port> or @<abstract socket name>"
+    )]
+    pub listen: Option<String>,
+    #[clap(
+        long,
+        requires = "listen",
+        help = "File containing the token that the remote clients must send"
+    )]
+    pub token_file: Option<PathBuf>,
+    #[clap(
+        long,
+        help = "Print the image formats, the Wayland protocols and the backends supported by this build, then exit"
+    )]
+    pub capabilities: bool,
+    #[clap(
+        long,
+        help = "Check the configuration and exit: 0 when it is valid, 65 when it cannot be parsed, 66 when a path does not exist, 78 when a value is not valid"
+    )]
+    pub check_config: bool,
+    #[clap(subcommand)]
+    pub command: Option<Command>,
 }
@@ -0,0 +1,291 @@
+//! `wpaperd self-test`: run this build of wpaperd inside a nested compositor and check that it
+//! works, for the users and the packagers of unusual setups.
+//!
+//! Everything happens in a temporary directory used as `XDG_RUNTIME_DIR`, `XDG_CONFIG_HOME`,
+//! `XDG_STATE_HOME` and `XDG_CACHE_HOME`, so that the wpaperd running in the session is not
+//! touched. The compositor is started there, headless sway by default, and wpaperd is started
+//! on the Wayland socket it creates, with a configuration showing generated images. Then the
+//! surfaces, the rotation, the IPC and the hot reloading are checked in turn.
+
+use std::{
+    fs,
+    io::Write,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use color_eyre::{
+    eyre::{anyhow, ensure, Context},
+    Result, Section,
+};
+use wpaperd_core::wallpaper_info::Color;
+use wpaperd_ipc::{request, IpcMessage, IpcResponse, PROTOCOL_VERSION};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+const IPC_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The files of the test, removed when it ends
+struct TestDir {
+    root: PathBuf,
+}
+
+impl TestDir {
+    fn new() -> Result<Self> {
+        let root = std::env::temp_dir().join(format!("wpaperd-self-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for dir in ["runtime", "config", "state", "cache", "first", "second"] {
+            fs::create_dir_all(root.join(dir))
+                .with_context(|| format!("creating the directory {:?}", root.join(dir)))?;
+        }
+        // Required by the compositors for XDG_RUNTIME_DIR
+        fs::set_permissions(root.join("runtime"), fs::Permissions::from_mode(0o700))
+            .context("setting the permissions of the runtime directory")?;
+        Ok(Self { root })
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.root.join(name)
+    }
+
+    /// Set the directories of a child process to the ones of the test
+    fn apply(&self, command: &mut Command) {
+        command
+            .env("XDG_RUNTIME_DIR", self.path("runtime"))
+            .env("XDG_CONFIG_HOME", self.path("config"))
+            .env("XDG_CONFIG_DIRS", self.path("config"))
+            .env("XDG_STATE_HOME", self.path("state"))
+            .env("XDG_CACHE_HOME", self.path("cache"))
+            .env_remove("WAYLAND_DISPLAY")
+            .env_remove("XDG_SEAT")
+            .env_remove("SWAYSOCK");
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+/// A child process killed when the test ends
+struct Process(Child);
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// Run the checks, print their results and return true if all of them have passed.
+/// `compositor` is run by `sh -c`, `timeout` is how long each check can take.
+pub fn run(compositor: Option<&str>, timeout: Duration) -> Result<bool> {
+    let dir = TestDir::new()?;
+    for (name, color) in [
+        ("first/a.png", 0x20),
+        ("first/b.png", 0x60),
+        ("second/c.png", 0xa0),
+    ] {
+        Color([color, 0x40, 0x80])
+            .image()
+            .save(dir.path(name))
+            .with_context(|| format!("writing the image {name}"))?;
+    }
+    let config_file = dir.path("config/config.toml");
+    write_config(&config_file, &dir.path("first"))?;
+
+    let _compositor = start_compositor(&dir, compositor)?;
+    let wayland_display = wait_for(timeout, || wayland_socket(&dir.path("runtime")))
+        .context("waiting for the compositor to create its Wayland socket")
+        .suggestion("Pass the command of a wlroots compositor with --compositor")?;
+    println!("compositor started on {wayland_display}");
+
+    let log_file = dir.path("wpaperd.log");
+    let mut command = Command::new(std::env::current_exe().context("finding wpaperd")?);
+    dir.apply(&mut command);
+    command
+        .env("WAYLAND_DISPLAY", &wayland_display)
+        .args(["--config".as_ref(), config_file.as_os_str()])
+        .args(["--log-target", "stderr", "--verbose"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(fs::File::create(&log_file).context("creating the log file of wpaperd")?);
+    let mut wpaperd = Process(command.spawn().context("starting wpaperd")?);
+    let socket = dir.path("runtime").join("wpaperd.sock");
+
+    let first = dir.path("first");
+    let second = dir.path("second");
+    let wallpaper = || wallpaper(&socket);
+    let mut results = Vec::new();
+    let mut check = |name: &str, result: Result<String>| {
+        match &result {
+            Ok(detail) => println!("{name}: ok, {detail}"),
+            Err(err) => println!("{name}: FAILED, {err:#}"),
+        }
+        results.push(result.is_ok());
+    };
+
+    check(
+        "ipc",
+        wait_for(timeout, || {
+            let handshake = IpcMessage::Handshake {
+                version: PROTOCOL_VERSION,
+            };
+            match request(&socket, &handshake, IPC_TIMEOUT) {
+                Ok(Ok(IpcResponse::Handshake { capabilities, .. })) => {
+                    Some(format!("{} capabilities", capabilities.len()))
+                }
+                _ => None,
+            }
+        })
+        .context("wpaperd doesn't answer on its socket"),
+    );
+    let shown = wait_for(timeout, || {
+        wallpaper().filter(|path| path.starts_with(&first))
+    })
+    .context("no wallpaper has been shown");
+    check(
+        "surface",
+        shown
+            .as_ref()
+            .map(|path| format!("showing {path:?}"))
+            .map_err(|err| anyhow!("{err:#}")),
+    );
+    let shown = shown.ok();
+    check(
+        "rotation",
+        wait_for(timeout, || {
+            wallpaper().filter(|path| path.starts_with(&first) && Some(path) != shown.as_ref())
+        })
+        .map(|path| format!("changed to {path:?}"))
+        .context("the wallpaper has not changed after its duration"),
+    );
+    let before = wallpaper();
+    check(
+        "next-wallpaper",
+        match request(
+            &socket,
+            &IpcMessage::NextWallpaper { monitors: vec![] },
+            IPC_TIMEOUT,
+        ) {
+            Ok(Ok(_)) => wait_for(timeout, || {
+                wallpaper().filter(|path| Some(path) != before.as_ref())
+            })
+            .map(|path| format!("changed to {path:?}"))
+            .context("the wallpaper has not changed"),
+            Ok(Err(err)) => Err(anyhow!("wpaperd refused the message: {err:?}")),
+            Err(err) => Err(err).context("sending next-wallpaper"),
+        },
+    );
+    check(
+        "reload",
+        write_config(&config_file, &second).and_then(|_| {
+            wait_for(timeout, || {
+                wallpaper().filter(|path| path.starts_with(&second))
+            })
+            .map(|path| format!("changed to {path:?}"))
+            .context("the new path has not been applied")
+        }),
+    );
+
+    let exited = wpaperd.0.try_wait().ok().flatten();
+    if let Some(status) = exited {
+        println!("wpaperd has exited with {status}");
+    }
+    let passed = results.iter().all(|passed| *passed) && exited.is_none();
+    if !passed {
+        // The test directory is removed at the end
+        if let Ok(log) = fs::read_to_string(&log_file) {
+            println!("\nThe logs of wpaperd:\n{log}");
+        }
+    }
+    Ok(passed)
+}
+
+fn write_config(config_file: &Path, path: &Path) -> Result<()> {
+    let mut file = fs::File::create(config_file)
+        .with_context(|| format!("writing the configuration {config_file:?}"))?;
+    writeln!(
+        file,
+        "[default]\npath = {:?}\nduration = \"1s\"\nsorting = \"ascending\"\ntransition-time = 0",
+        path.to_string_lossy()
+    )
+    .with_context(|| format!("writing the configuration {config_file:?}"))
+}
+
+fn start_compositor(dir: &TestDir, compositor: Option<&str>) -> Result<Process> {
+    let mut command = match compositor {
+        Some(compositor) => {
+            let mut command = Command::new("sh");
+            command.args(["-c", compositor]);
+            command
+        }
+        None => {
+            // A headless output is created once sway has started
+            let config = dir.path("sway.conf");
+            fs::write(&config, "exec swaymsg create_output\n")
+                .context("writing the configuration of sway")?;
+            let mut command = Command::new("sway");
+            command
+                .arg("--config")
+                .arg(config)
+                .env("WLR_BACKENDS", "headless")
+                .env("WLR_LIBINPUT_NO_DEVICES", "1");
+            command
+        }
+    };
+    dir.apply(&mut command);
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    let mut child = command
+        .spawn()
+        .context("starting the compositor")
+        .suggestion("Install sway or pass the command of another compositor with --compositor")?;
+    // Exiting right away usually means that it cannot run here
+    thread::sleep(POLL_INTERVAL);
+    if let Ok(Some(status)) = child.try_wait() {
+        return Err(anyhow!("the compositor has exited with {status}"));
+    }
+    Ok(Process(child))
+}
+
+/// The name of the Wayland socket created in `runtime`, e.g. `wayland-1`
+fn wayland_socket(runtime: &Path) -> Option<String> {
+    fs::read_dir(runtime)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .find(|name| name.starts_with("wayland-") && !name.ends_with(".lock"))
+}
+
+/// The wallpaper of the first output
+fn wallpaper(socket: &Path) -> Option<PathBuf> {
+    match request(socket, &IpcMessage::AllWallpapers, IPC_TIMEOUT) {
+        Ok(Ok(IpcResponse::AllWallpapers { entries })) => entries
+            .into_iter()
+            .map(|(_, path)| path)
+            .find(|path| !path.as_os_str().is_empty()),
+        _ => None,
+    }
+}
+
+/// Call `f` until it returns a value
+fn wait_for<T>(timeout: Duration, mut f: impl FnMut() -> Option<T>) -> Result<T> {
+    let started = Instant::now();
+    loop {
+        if let Some(value) = f() {
+            return Ok(value);
+        }
+        ensure!(
+            started.elapsed() < timeout,
+            "timed out after {:.1}s",
+            timeout.as_secs_f32()
+        );
+        thread::sleep(POLL_INTERVAL);
+    }
+}
@@ -0,0 +1,303 @@
+//! `wpaperd setup`: ask the new users which wallpapers to show on each display, write the
+//! configuration and start wpaperd with the graphical session.
+//!
+//! The displays are listed by connecting to the compositor, their names come with version 4 of
+//! `wl_output`; with an older compositor only the default section is asked for. Nothing is
+//! changed without asking first, and an existing configuration is saved with the `.bak`
+//! extension before being replaced.
+
+use std::{
+    env, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+use color_eyre::{
+    eyre::{ensure, Context},
+    Result, Section,
+};
+use smithay_client_toolkit::reexports::client::{
+    globals::{registry_queue_init, GlobalListContents},
+    protocol::{
+        wl_output,
+        wl_registry::{self, WlRegistry},
+    },
+    Connection, Dispatch, QueueHandle,
+};
+use wpaperd_core::{
+    config::{tilde_expansion, Config, LoadOptions},
+    migrate::backup_path,
+    setup::{config_content, systemd_unit, Choice},
+};
+use wpaperd_ipc::{request, socket_path, IpcMessage, PROTOCOL_VERSION};
+use xdg::BaseDirectories;
+
+const DEFAULT_DURATION: &str = "30m";
+const UNIT: &str = "wpaperd.service";
+
+/// Ask the questions and write `config_file`
+pub fn run(config_file: &Path) -> Result<()> {
+    println!("This writes the configuration of wpaperd into {config_file:?}.");
+    if config_file.exists() {
+        let backup = backup_path(config_file);
+        if !confirm(
+            &format!("The configuration already exists, replace it? It is saved into {backup:?}"),
+            false,
+        )? {
+            return Ok(());
+        }
+        fs::copy(config_file, &backup)
+            .with_context(|| format!("saving the configuration into {backup:?}"))?;
+    }
+
+    let outputs = detect_outputs().unwrap_or_else(|err| {
+        println!("The displays could not be listed ({err:#}), only the defaults are asked for.");
+        Vec::new()
+    });
+    if !outputs.is_empty() {
+        println!("Displays found: {}", outputs.join(", "));
+    }
+    let mut default = Choice {
+        path: default_directory(),
+        duration: humantime::parse_duration(DEFAULT_DURATION).expect("a valid duration"),
+    };
+    let mut choices = Vec::new();
+    if outputs.is_empty() {
+        default = ask_choice("all the displays", &default)?;
+    }
+    for (index, name) in outputs.iter().enumerate() {
+        // The answers for a display are the defaults for the next one
+        let previous = choices.last().map_or(&default, |(_, choice)| choice);
+        let choice = ask_choice(name, previous)?;
+        if index == 0 {
+            default = choice.clone();
+        }
+        choices.push((name.clone(), choice));
+    }
+
+    if let Some(dir) = config_file.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("creating directory {dir:?}"))?;
+    }
+    fs::write(config_file, config_content(&default, &choices))
+        .with_context(|| format!("writing the configuration {config_file:?}"))?;
+    println!("Configuration written into {config_file:?}.");
+    let config = Config::new_from_path(config_file, &LoadOptions::default())
+        .context("reading the configuration written")?;
+    for error in config.errors() {
+        println!("{error}");
+    }
+
+    if running() {
+        println!("wpaperd is running and shows the new configuration right away.");
+    }
+    offer_systemd()
+}
+
+/// The directory and the duration of the display `name`
+fn ask_choice(name: &str, default: &Choice) -> Result<Choice> {
+    let path = loop {
+        let path = ask(
+            &format!("Directory of the wallpapers for {name}"),
+            &default.path,
+        )?;
+        if tilde_expansion(Path::new(&path)).is_dir() {
+            break path;
+        }
+        println!("{path:?} is not a directory.");
+    };
+    let default_duration = humantime::format_duration(default.duration).to_string();
+    let duration = loop {
+        let duration = ask(
+            &format!("Change the wallpaper of {name} every"),
+            &default_duration,
+        )?;
+        match humantime::parse_duration(&duration) {
+            Ok(duration) if !duration.is_zero() => break duration,
+            Ok(_) => println!("The duration must be greater than zero."),
+            Err(err) => println!("{duration:?} is not a valid duration, e.g. 30m or 1h: {err}."),
+        }
+    };
+    Ok(Choice { path, duration })
+}
+
+/// `~/Pictures/Wallpapers` if it exists, `~/Pictures` otherwise
+fn default_directory() -> String {
+    let wallpapers = tilde_expansion(Path::new("~/Pictures/Wallpapers"));
+    if wallpapers.is_dir() {
+        "~/Pictures/Wallpapers".to_string()
+    } else {
+        "~/Pictures".to_string()
+    }
+}
+
+/// Print `prompt` and return the line typed, without the surrounding spaces
+fn read_answer(prompt: &str) -> Result<String> {
+    print!("{prompt} ");
+    io::stdout().flush()?;
+    let mut line = String::new();
+    ensure!(
+        io::stdin()
+            .read_line(&mut line)
+            .context("reading the answer")?
+            > 0,
+        "the input has been closed"
+    );
+    Ok(line.trim().to_string())
+}
+
+/// Return the answer to `question`, or `default` when it's empty
+fn ask(question: &str, default: &str) -> Result<String> {
+    let answer = read_answer(&format!("{question} [{default}]:"))?;
+    Ok(if answer.is_empty() {
+        default.to_string()
+    } else {
+        answer
+    })
+}
+
+fn confirm(question: &str, default: bool) -> Result<bool> {
+    let hint = if default { "[Y/n]" } else { "[y/N]" };
+    loop {
+        match read_answer(&format!("{question} {hint}"))?
+            .to_lowercase()
+            .as_str()
+        {
+            "" => return Ok(default),
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => println!("Answer yes or no."),
+        }
+    }
+}
+
+/// The names of the displays, as used for the sections of the configuration
+struct Outputs(Vec<Option<String>>);
+
+impl Dispatch<WlRegistry, GlobalListContents> for Outputs {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WlRegistry,
+        _event: wl_registry::Event,
+        _data: &GlobalListContents,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<wl_output::WlOutput, usize> for Outputs {
+    fn event(
+        state: &mut Self,
+        _proxy: &wl_output::WlOutput,
+        event: wl_output::Event,
+        index: &usize,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let wl_output::Event::Name { name } = event {
+            state.0[*index] = Some(name);
+        }
+    }
+}
+
+fn detect_outputs() -> Result<Vec<String>> {
+    let conn = Connection::connect_to_env()
+        .context("connecting to wayland")
+        .suggestion("Run wpaperd setup inside the Wayland session")?;
+    let (globals, mut event_queue) =
+        registry_queue_init::<Outputs>(&conn).context("initializing the wayland registry queue")?;
+    let qh = event_queue.handle();
+    let mut outputs = Outputs(Vec::new());
+    for global in globals.contents().clone_list() {
+        // The names are only sent since version 4
+        if global.interface == "wl_output" && global.version >= 4 {
+            let _: wl_output::WlOutput =
+                globals
+                    .registry()
+                    .bind(global.name, 4, &qh, outputs.0.len());
+            outputs.0.push(None);
+        }
+    }
+    event_queue
+        .roundtrip(&mut outputs)
+        .context("receiving the names of the displays")?;
+    let mut names: Vec<String> = outputs.0.into_iter().flatten().collect();
+    names.sort();
+    Ok(names)
+}
+
+/// True if wpaperd answers on its socket
+fn running() -> bool {
+    let handshake = IpcMessage::Handshake {
+        version: PROTOCOL_VERSION,
+    };
+    socket_path().is_ok_and(|socket| {
+        matches!(
+            request(&socket, &handshake, Duration::from_secs(1)),
+            Ok(Ok(_))
+        )
+    })
+}
+
+fn systemctl(args: &[&str]) -> bool {
+    Command::new("systemctl")
+        .arg("--user")
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Enable the systemd user unit of wpaperd, writing one if the package doesn't ship it
+fn offer_systemd() -> Result<()> {
+    if !systemctl(&["show-environment"]) {
+        println!(
+            "systemd is not managing this session, start wpaperd from the configuration of the \
+             compositor, e.g. with `exec wpaperd -d` for sway."
+        );
+        return Ok(());
+    }
+    if systemctl(&["is-enabled", "--quiet", UNIT]) {
+        println!("The systemd unit {UNIT} is already enabled.");
+        return Ok(());
+    }
+    if !confirm(
+        "Start wpaperd with the graphical session, using systemd?",
+        true,
+    )? {
+        return Ok(());
+    }
+    if !systemctl(&["cat", UNIT]) {
+        let unit = unit_path()?;
+        let exe = env::current_exe().context("finding wpaperd")?;
+        fs::write(&unit, systemd_unit(&exe))
+            .with_context(|| format!("writing the systemd unit {unit:?}"))?;
+        println!("Systemd unit written into {unit:?}.");
+        ensure!(
+            systemctl(&["daemon-reload"]),
+            "systemctl --user daemon-reload failed"
+        );
+    }
+    // The instance already running is left alone, the unit starts it from the next login
+    let args: &[&str] = if running() {
+        &["enable", UNIT]
+    } else {
+        &["enable", "--now", UNIT]
+    };
+    ensure!(
+        systemctl(args),
+        "systemctl --user {} failed",
+        args.join(" ")
+    );
+    println!("wpaperd is started with the graphical session from now on.");
+    Ok(())
+}
+
+fn unit_path() -> Result<PathBuf> {
+    BaseDirectories::new()?
+        .place_config_file(Path::new("systemd/user").join(UNIT))
+        .context("creating the directory of the systemd user units")
+}
@@ -1,34 +1,842 @@
-use std::path::PathBuf;
+//! Types and framing of the IPC protocol between wpaperd and its clients.
+//!
+//! Clients connect to the unix socket returned by [socket_path] and send a single
+//! [IpcMessage]. wpaperd answers with a `Result<IpcResponse, IpcError>` and closes the
+//! connection. Each message is encoded in JSON (using the serde representation of these
+//! types) and prefixed by its length in bytes, as a 32-bit little-endian integer. Use
+//! [write_message] and [read_message] to send and receive them.
+//!
+//! Clients should send [IpcMessage::Handshake] first, to check the [PROTOCOL_VERSION] of the
+//! running daemon and which messages it supports, listed in [CAPABILITIES]. The version is
+//! increased when an existing message changes in an incompatible way; adding new messages only
+//! adds new capabilities. wpaperd answers with [IpcError::VersionMismatch] when the version of
+//! the client is not its own.
+//!
+//! wpaperd can also listen for remote clients, on the [RemoteAddress] passed to `--listen`. The
+//! protocol is the same, except that the clients must send the shared token first, as a JSON
+//! string framed like the messages and at most [MAX_TOKEN_SIZE] bytes long. wpaperd answers with
+//! [IpcError::Unauthorized] when it does not match. The token and the message must arrive within
+//! a few seconds, and [IpcMessage::Apply] and [IpcMessage::Screenshot] are refused with
+//! [IpcError::LocalOnly], as they run the commands of the configuration and write files.
 
-use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use xdg::{BaseDirectories, BaseDirectoriesError};
 
+/// Version of the protocol implemented by this crate
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// The messages supported, in kebab-case. Returned by [IpcMessage::Handshake].
+pub const CAPABILITIES: &[&str] = &[
+    "current-wallpaper",
+    "next-wallpaper",
+    "previous-wallpaper",
+    "pause-wallpaper",
+    "resume-wallpaper",
+    "toggle-pause-wallpaper",
+    "hold-wallpaper",
+    "all-wallpapers",
+    "reload-wallpaper",
+    "queue",
+    "queue-next",
+    "queue-remove",
+    "show-once",
+    "set-duration",
+    "set-mode",
+    "apply",
+    "reset",
+    "dump-config",
+    "list-outputs",
+    "print-matches",
+    "config-errors",
+    "config-path",
+    "reduce-motion",
+    "high-contrast",
+    "stats",
+    "crop",
+    "thumbnails",
+    "preview-strip",
+    "screenshot",
+    "images",
+    "text-contrast",
+    "inhibit",
+    "uninhibit",
+    "backend",
+    "status",
+    "animation",
+    "handoff",
+    "trace",
+    "copy-path",
+    "zen",
+    "reload",
+];
+
+/// Messages bigger than this are refused
+pub const MAX_MESSAGE_SIZE: u32 = 1024 * 1024;
+/// Maximum size of the token sent by the remote clients, once framed
+pub const MAX_TOKEN_SIZE: u32 = 1024;
+
+/// Messages sent by the clients. The commands taking a list of monitors apply to all of them
+/// when the list is empty.
 #[derive(Serialize, Deserialize)]
 pub enum IpcMessage {
-    CurrentWallpaper { monitor: String },
-    NextWallpaper { monitors: Vec<String> },
-    PreviousWallpaper { monitors: Vec<String> },
-    PauseWallpaper { monitors: Vec<String> },
-    ResumeWallpaper { monitors: Vec<String> },
-    TogglePauseWallpaper { monitors: Vec<String> },
+    /// Return the protocol version and the capabilities of the daemon
+    Handshake {
+        /// The protocol version implemented by the client
+        version: u32,
+    },
+    /// Return the wallpaper currently displayed
+    CurrentWallpaper {
+        monitor: String,
+    },
+    /// Show the next wallpaper
+    NextWallpaper {
+        monitors: Vec<String>,
+    },
+    /// Show the previous wallpaper
+    PreviousWallpaper {
+        monitors: Vec<String>,
+    },
+    /// Stop changing the wallpaper when the duration expires
+    PauseWallpaper {
+        monitors: Vec<String>,
+    },
+    ResumeWallpaper {
+        monitors: Vec<String>,
+    },
+    TogglePauseWallpaper {
+        monitors: Vec<String>,
+    },
+    /// Pause the wallpaper for duration, then resume it
+    HoldWallpaper {
+        monitors: Vec<String>,
+        duration: Duration,
+    },
+    /// Return the wallpaper displayed on each monitor
     AllWallpapers,
-    ReloadWallpaper { monitors: Vec<String> },
+    /// Load the current wallpaper again from the disk
+    ReloadWallpaper {
+        monitors: Vec<String>,
+    },
+    /// Read again the parts of the configuration or of the directories in `scope`
+    Reload {
+        monitors: Vec<String>,
+        scope: ReloadScope,
+    },
+    /// Return the images queued by the user and the upcoming ones
+    Queue {
+        monitor: String,
+    },
+    /// Show this image next
+    QueueNext {
+        monitor: String,
+        path: PathBuf,
+    },
+    /// Remove an image added with [IpcMessage::QueueNext]
+    QueueRemove {
+        monitor: String,
+        path: PathBuf,
+    },
+    /// Show this image now without changing the images shown next. With a duration, the
+    /// wallpaper is held and the previous image comes back afterwards
+    ShowOnce {
+        monitor: String,
+        path: PathBuf,
+        duration: Option<Duration>,
+    },
+    /// Change the duration until wpaperd is restarted, or permanently if persist is true
+    SetDuration {
+        monitor: String,
+        duration: Duration,
+        persist: bool,
+    },
+    /// Change the background mode until wpaperd is restarted.
+    /// mode is one of the background modes accepted in the configuration
+    SetMode {
+        monitor: String,
+        mode: String,
+    },
+    /// Apply a configuration over the running one until wpaperd is restarted.
+    /// config is a TOML fragment with the same format of the configuration file
+    Apply {
+        config: String,
+    },
+    /// Drop all the changes made with Apply, SetDuration, SetMode, ReduceMotion, HighContrast
+    /// and Backend
+    Reset,
+    /// Return the configuration file with the changes made at runtime applied over it
+    DumpConfig,
+    ListOutputs,
+    PrintMatches,
+    /// Return the errors found when the configuration file was last read
+    ConfigErrors,
+    /// Return the configuration file used by wpaperd
+    ConfigPath,
+    /// Disable the transitions on all the monitors until wpaperd is restarted.
+    /// The current value is toggled when enabled is None
+    ReduceMotion {
+        enabled: Option<bool>,
+    },
+    /// Show the high-contrast color in place of the wallpapers on all the monitors until
+    /// wpaperd is restarted. The current value is toggled when enabled is None
+    HighContrast {
+        enabled: Option<bool>,
+    },
+    /// Show the zen color or image on all the monitors and freeze the wallpaper sequence, the
+    /// wallpapers are shown again as they were when it is disabled. The current value is toggled
+    /// when enabled is None
+    Zen {
+        enabled: Option<bool>,
+    },
+    /// Return the counters about the work done by wpaperd
+    Stats,
+    /// Return the state of each monitor, along with the error preventing it from showing a
+    /// wallpaper, if any
+    Status,
+    /// Only show a region of the image displayed on the monitor, saved into the sidecar file
+    /// of the image. The crop is removed when region is None
+    Crop {
+        monitor: String,
+        region: Option<CropRegion>,
+    },
+    /// Return the thumbnails of the images the monitor picks from, with their longest side of
+    /// `size` pixels, starting from the image at `offset`. They are created and cached by wpaperd
+    Thumbnails {
+        monitor: String,
+        size: u32,
+        #[serde(default)]
+        offset: usize,
+    },
+    /// Save the thumbnails of the next `count` images of the monitor, the queued ones first,
+    /// side by side in a single PNG, each one in a square of `size` pixels
+    PreviewStrip {
+        monitor: String,
+        count: usize,
+        size: u32,
+    },
+    /// Save the wallpaper drawn on the monitor into the PNG at `path`, as it is shown, after the
+    /// mode, the filters and the transitions are applied
+    Screenshot {
+        monitor: String,
+        path: PathBuf,
+    },
+    /// Put the path of the wallpaper of the monitor on the clipboard, the first monitor when
+    /// None, and the image itself when `image` is true. Return the path copied
+    CopyPath {
+        monitor: Option<String>,
+        image: bool,
+    },
+    /// Return the images the monitor picks from, starting from the one at `offset`
+    Images {
+        monitor: String,
+        #[serde(default)]
+        offset: usize,
+    },
+    /// Return whether light or dark text is readable over the region of the wallpaper set in
+    /// `text-region` for the monitor
+    TextContrast {
+        monitor: String,
+    },
+    /// Stop changing the wallpapers on all the monitors, e.g. while a game is running or the
+    /// screen is being recorded, until all the inhibitors are removed or expire. Adding an
+    /// inhibitor with the same name replaces its timeout
+    Inhibit {
+        name: String,
+        timeout: Option<Duration>,
+    },
+    /// Remove an inhibitor added with [IpcMessage::Inhibit], all of them when name is None
+    Uninhibit {
+        name: Option<String>,
+    },
+    /// Switch the backend drawing the wallpapers until wpaperd is restarted, recreating the
+    /// surfaces. renderer is `gpu` or `software`, device selects the GPU by its index or its PCI
+    /// tag and is the default one when None. Only return the backend used when renderer is None
+    Backend {
+        renderer: Option<String>,
+        device: Option<String>,
+    },
+    /// Pause or resume the animations shown on the monitors, or show one of their frames
+    Animation {
+        monitors: Vec<String>,
+        action: AnimationAction,
+    },
+    /// Return the events of the rotation recorded after the event `after`, all the ones kept when
+    /// None. The first request starts recording them, unless wpaperd was started with `--trace`
+    Trace {
+        monitors: Vec<String>,
+        after: Option<u64>,
+    },
+    /// Sent by the instance started with `wpaperd --replace` once its wallpapers are shown. The
+    /// daemon exits, leaving its socket and its runtime state to the new instance
+    Handoff,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnimationAction {
+    Pause,
+    Resume,
+    /// Show this frame, the first one is 0. A paused animation stays on it
+    Seek {
+        frame: usize,
+    },
+}
+
+/// What [IpcMessage::Reload] reads again
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReloadScope {
+    /// Rescan the directories of the monitors and load their wallpapers again from the disk
+    Outputs,
+    /// Only rescan the directories of the monitors, the wallpapers shown stay the same
+    Directories,
+    /// Only read the configuration files again, like after `SIGHUP`. It applies to all the
+    /// monitors
+    Config,
+}
+
+/// How the images are scaled to the size of the display, the `mode` of the configuration
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Default, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum BackgroundMode {
+    Stretch,
+    #[default]
+    #[cfg_attr(feature = "clap", value(alias = "fill"))]
+    Center,
+    Fit,
+    Tile,
+    FitBorderColor,
+    /// Scale the image by the biggest integer factor that fits, for pixel art
+    Integer,
+}
+
+/// How the images are resized to the display, the `resize-filter` of the configuration
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum ResizeFilter {
+    /// Keep the pixels sharp, for pixel art
+    Nearest,
+    /// Interpolated by the GPU
+    #[default]
+    Bilinear,
+    /// The images bigger than the display are downscaled on the CPU, slower but sharper
+    Lanczos3,
+}
+
+/// A region of an image, in pixels
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct CropRegion {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct OutputStatus {
+    pub name: String,
+    pub description: String,
+    pub width: i32,
+    pub height: i32,
+    pub scale: i32,
+    pub transform: String,
+    /// The section of the configuration used for this output
+    pub section: Option<String>,
+    /// The wallpaper currently displayed, if any
+    pub wallpaper: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct OutputMatch {
+    pub output: String,
+    /// The sections used for this output, from the highest precedence to the lowest
+    pub sections: Vec<String>,
+    /// True if some values have been changed at runtime with wpaperctl
+    pub runtime_changes: bool,
+    /// The error that prevents the configuration to be used, if any
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DecodeTimeBucket {
+    /// Upper bound of the bucket, in milliseconds. The last bucket has no bound
+    pub max_ms: Option<u64>,
+    /// The number of images decoded in this time, not counting the previous buckets
+    pub count: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Stats {
+    pub uptime_secs: u64,
+    /// How many times a new wallpaper has been shown on any display
+    pub rotations: u64,
+    /// Histogram of the time taken to decode, resize and filter the images
+    pub decode_times: Vec<DecodeTimeBucket>,
+    pub decode_time_total_ms: u64,
+    /// Images that were already decoded for another display
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    /// Resident memory of wpaperd, when it can be read
+    pub memory_bytes: Option<u64>,
+    /// Images that could not be loaded and displays that could not be drawn
+    pub errors: u64,
+}
+
+/// How readable the text is over the region of the wallpaper set in `text-region`. It is also
+/// written into `$XDG_RUNTIME_DIR/wpaperd/text-contrast/<monitor>.json` each time it changes
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TextContrast {
+    /// `"light"` or `"dark"`, the text with the highest contrast
+    pub foreground: String,
+    /// Average relative luminance of the region, from 0 to 1
+    pub luminance: f32,
+    /// Contrast ratio of white text over the region, from 1 to 21
+    pub light_contrast: f32,
+    /// Contrast ratio of black text over the region, from 1 to 21
+    pub dark_contrast: f32,
+    pub wallpaper: PathBuf,
+}
+
+/// A stage of the rotation of the wallpaper of a monitor, see [IpcMessage::Trace]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TraceEvent {
+    /// Increasing for each event, pass the last one received to only get the next ones
+    pub id: u64,
+    /// Local time, e.g. `2024-05-01 10:00:00.012`
+    pub time: String,
+    pub monitor: String,
+    pub stage: TraceStage,
+    /// Time spent in this stage, when it is measured
+    pub duration: Option<Duration>,
+    /// e.g. the image picked and the state of the queue
+    pub detail: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TraceStage {
+    /// The timer of the monitor has fired
+    Timer,
+    /// The automatic changes have been paused or resumed
+    Pause,
+    Resume,
+    /// The next image has been picked
+    Pick,
+    /// The image has been decoded and its settings applied, in the thread pool
+    Decode,
+    /// The image has been scaled to fit the texture
+    Scale,
+    /// The image is committed to the monitor and its transition starts, the duration is the time
+    /// since it has been picked
+    Commit,
+}
+
+impl fmt::Display for TraceStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TraceStage::Timer => "timer",
+            TraceStage::Pause => "pause",
+            TraceStage::Resume => "resume",
+            TraceStage::Pick => "pick",
+            TraceStage::Decode => "decode",
+            TraceStage::Scale => "scale",
+            TraceStage::Commit => "commit",
+        })
+    }
 }
 
+/// Stops the cycling on all the monitors, see [IpcMessage::Inhibit]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Inhibitor {
+    pub name: String,
+    /// None when it lasts until it is removed
+    pub expires_in: Option<Duration>,
+}
+
+/// The content of `$XDG_RUNTIME_DIR/wpaperd/state.json`, replaced at once by wpaperd each time it
+/// changes, for the scripts that read it instead of sending messages
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct RuntimeState {
+    pub outputs: Vec<RuntimeOutput>,
+    /// The errors found when the configuration file was last read
+    pub config_errors: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RuntimeOutput {
+    pub name: String,
+    /// The wallpaper currently displayed, if any
+    pub wallpaper: Option<PathBuf>,
+    /// True while the wallpaper is not changed automatically, e.g. during the quiet hours
+    pub paused: bool,
+    /// When the wallpaper changes automatically, in seconds since the Unix epoch
+    pub next_change: Option<u64>,
+    /// The last error loading the wallpaper or drawing it, cleared once an image is shown
+    pub error: Option<String>,
+    /// Read from the file of the wallpaper when it is shown
+    #[serde(default)]
+    pub metadata: Option<ImageMetadata>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ImageMetadata {
+    /// The size of the image in the file, before it is scaled. None when it cannot be read
+    /// without decoding the image
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// The format of the image, e.g. `jpeg`
+    pub format: Option<String>,
+    /// Size of the file, in bytes
+    pub size: u64,
+    /// The make and model of the camera, from the EXIF metadata
+    pub camera: Option<String>,
+    /// When the photo was taken, from the EXIF metadata, e.g. `2024-05-01 10:00:00`
+    pub taken: Option<String>,
+}
+
+/// The animation shown on a monitor
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AnimationStatus {
+    pub monitor: String,
+    /// The frame shown, the first one is 0
+    pub frame: usize,
+    /// Zero while the frames are being decoded
+    pub frames: usize,
+    pub paused: bool,
+}
+
+/// Responses sent by wpaperd, each one matches a message or a group of messages
 #[derive(Serialize, Deserialize)]
 pub enum IpcResponse {
-    CurrentWallpaper { path: PathBuf },
-    AllWallpapers { entries: Vec<(String, PathBuf)> },
+    Handshake {
+        version: u32,
+        capabilities: Vec<String>,
+    },
+    CurrentWallpaper {
+        path: PathBuf,
+    },
+    AllWallpapers {
+        entries: Vec<(String, PathBuf)>,
+    },
+    Queue {
+        queued: Vec<PathBuf>,
+        upcoming: Vec<PathBuf>,
+    },
+    Outputs {
+        outputs: Vec<OutputStatus>,
+    },
+    Matches {
+        matches: Vec<OutputMatch>,
+        /// The sections that are not used by any output
+        unmatched_sections: Vec<String>,
+    },
+    ReduceMotion {
+        enabled: bool,
+    },
+    HighContrast {
+        enabled: bool,
+    },
+    Zen {
+        enabled: bool,
+    },
+    /// The inhibitors left after [IpcMessage::Inhibit] and [IpcMessage::Uninhibit]
+    Inhibitors {
+        inhibitors: Vec<Inhibitor>,
+    },
+    Stats(Stats),
+    Status {
+        outputs: Vec<RuntimeOutput>,
+    },
+    Animations {
+        animations: Vec<AnimationStatus>,
+    },
+    Trace {
+        events: Vec<TraceEvent>,
+        /// True when this request has started recording the events
+        started: bool,
+    },
+    /// The backend drawing the wallpapers
+    Backend {
+        renderer: String,
+        device: Option<String>,
+        /// The name of the OpenGL renderer, e.g. llvmpipe for the software one. None when there
+        /// are no displays
+        driver: Option<String>,
+    },
+    /// Each image with its thumbnail. The lists too long for a single message are split, `next`
+    /// is the offset of the following part
+    Thumbnails {
+        entries: Vec<(PathBuf, PathBuf)>,
+        #[serde(default)]
+        next: Option<usize>,
+    },
+    /// Split like [IpcResponse::Thumbnails]
+    Images {
+        paths: Vec<PathBuf>,
+        #[serde(default)]
+        next: Option<usize>,
+    },
+    /// The PNG saved by [IpcMessage::Screenshot], with the size of the display in pixels
+    Screenshot {
+        path: PathBuf,
+        width: u32,
+        height: u32,
+    },
+    /// The PNG saved by [IpcMessage::PreviewStrip] and the images in it, from left to right
+    PreviewStrip {
+        path: PathBuf,
+        images: Vec<PathBuf>,
+    },
+    TextContrast(TextContrast),
+    /// Each error with the line of the configuration file it is about, when known
+    ConfigErrors {
+        errors: Vec<String>,
+    },
+    /// The content of a configuration file
+    Config {
+        config: String,
+    },
+    /// The file is watched and read once created when it doesn't exist
+    ConfigPath {
+        path: PathBuf,
+        exists: bool,
+        /// The system-wide configuration the file is merged over
+        system_path: Option<PathBuf>,
+        /// The values merged beneath the default section, see `defaults-file`
+        #[serde(default)]
+        defaults_file: Option<PathBuf>,
+    },
     Ok,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum IpcError {
-    MonitorNotFound { monitor: String },
+    MonitorNotFound {
+        monitor: String,
+    },
     DrawErrors(Vec<(String, String)>),
+    InvalidPath {
+        path: PathBuf,
+    },
+    NotQueued {
+        path: PathBuf,
+    },
+    InvalidConfig {
+        error: String,
+    },
+    /// The monitor is not showing an image
+    NoWallpaper {
+        monitor: String,
+    },
+    /// The sidecar file of the image could not be written
+    SidecarNotSaved {
+        path: PathBuf,
+        error: String,
+    },
+    /// The message could not be read or it is not supported
+    InvalidMessage {
+        error: String,
+    },
+    /// The remote client has not sent the right token
+    Unauthorized,
+    /// The message is only accepted from the local socket
+    LocalOnly,
+    /// The monitor doesn't have `text-region` set
+    NoTextRegion {
+        monitor: String,
+    },
+    InhibitorNotFound {
+        name: String,
+    },
+    /// The monitor is not showing an animated image
+    NoAnimation {
+        monitor: String,
+    },
+    /// The monitor has no images queued or upcoming, e.g. with random sorting
+    NoUpcomingImages {
+        monitor: String,
+    },
+    /// The wallpaper could not be read back or saved
+    ScreenshotFailed {
+        monitor: String,
+        error: String,
+    },
+    /// The preview strip could not be written
+    PreviewStripNotSaved {
+        path: PathBuf,
+        error: String,
+    },
+    /// The animation doesn't have the frame, or its frames are still being decoded
+    FrameNotFound {
+        monitor: String,
+        frames: usize,
+    },
+    /// The compositor doesn't allow to set the clipboard, or there is no seat
+    ClipboardUnavailable {
+        error: String,
+    },
+    /// The client sent a [IpcMessage::Handshake] for another [PROTOCOL_VERSION]
+    VersionMismatch {
+        daemon: u32,
+        client: u32,
+    },
+}
+
+/// Address where wpaperd listens for remote clients
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RemoteAddress {
+    /// A TCP address, `<host>:<port>`
+    Tcp(String),
+    /// A unix socket in the abstract namespace, written as `@<name>`
+    Abstract(String),
+}
+
+impl FromStr for RemoteAddress {
+    type Err = String;
+
+    fn from_str(address: &str) -> Result<Self, Self::Err> {
+        match address.strip_prefix('@') {
+            Some("") => Err("the name of the abstract socket is empty".to_string()),
+            Some(name) => Ok(Self::Abstract(name.to_string())),
+            None if address
+                .rsplit_once(':')
+                .is_some_and(|(_, port)| port.parse::<u16>().is_ok()) =>
+            {
+                Ok(Self::Tcp(address.to_string()))
+            }
+            None => Err(format!(
+                "{address:?} is neither <host>:<port> nor @<abstract socket name>"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for RemoteAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(address) => write!(f, "{address}"),
+            Self::Abstract(name) => write!(f, "@{name}"),
+        }
+    }
 }
 
+/// The socket of the wpaperd running on the current seat
 pub fn socket_path() -> Result<PathBuf, BaseDirectoriesError> {
+    seat_socket_path(seat().as_deref())
+}
+
+/// The socket of the wpaperd running on `seat`. Each seat has its own instance of wpaperd, the
+/// first seat (or no seat at all) uses `wpaperd.sock`
+pub fn seat_socket_path(seat: Option<&str>) -> Result<PathBuf, BaseDirectoriesError> {
+    let name = match seat {
+        None | Some("seat0") => "wpaperd.sock".to_string(),
+        Some(seat) => format!("wpaperd-{seat}.sock"),
+    };
     let xdg_dirs = BaseDirectories::with_prefix("wpaperd")?;
-    Ok(xdg_dirs.get_runtime_directory()?.join("wpaperd.sock"))
+    Ok(xdg_dirs.get_runtime_directory()?.join(name))
+}
+
+/// The seat of the session, read from `XDG_SEAT`
+pub fn seat() -> Option<String> {
+    std::env::var("XDG_SEAT")
+        .ok()
+        .filter(|seat| !seat.is_empty())
+}
+
+/// Write a message prefixed by its length
+pub fn write_message<W: Write, T: Serialize>(mut writer: W, message: &T) -> io::Result<()> {
+    let buf = serde_json::to_vec(message)?;
+    let len = u32::try_from(buf.len())
+        .ok()
+        .filter(|len| *len <= MAX_MESSAGE_SIZE)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "message too big"))?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(&buf)?;
+    writer.flush()
+}
+
+/// Read a message written by [write_message]
+pub fn read_message<R: Read, T: DeserializeOwned>(reader: R) -> io::Result<T> {
+    read_message_with_limit(reader, MAX_MESSAGE_SIZE)
+}
+
+/// Read a message written by [write_message], refusing the ones bigger than `limit` bytes
+/// before reading them
+pub fn read_message_with_limit<R: Read, T: DeserializeOwned>(
+    mut reader: R,
+    limit: u32,
+) -> io::Result<T> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len);
+    if len > limit {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("message of {len} bytes is too big"),
+        ));
+    }
+    let mut buf = vec![0; len as usize];
+    reader.read_exact(&mut buf)?;
+    Ok(serde_json::from_slice(&buf)?)
+}
+
+/// Send `message` to the wpaperd listening on `socket` and return its answer, waiting at most
+/// `timeout` for it
+pub fn request(
+    socket: &Path,
+    message: &IpcMessage,
+    timeout: Duration,
+) -> io::Result<Result<IpcResponse, IpcError>> {
+    let stream = UnixStream::connect(socket)?;
+    stream.set_read_timeout(Some(timeout))?;
+    write_message(&stream, message)?;
+    read_message(&stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_roundtrip() {
+        let mut buf = Vec::new();
+        write_message(&mut buf, &IpcMessage::Handshake { version: 1 }).unwrap();
+        assert_eq!(
+            buf.len() - 4,
+            u32::from_le_bytes(buf[..4].try_into().unwrap()) as usize
+        );
+        let message: IpcMessage = read_message(buf.as_slice()).unwrap();
+        assert!(matches!(message, IpcMessage::Handshake { version: 1 }));
+
+        // The length is bigger than the data sent
+        buf.truncate(buf.len() - 1);
+        assert!(read_message::<_, IpcMessage>(buf.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_parse_remote_address() {
+        assert_eq!(
+            "0.0.0.0:9876".parse(),
+            Ok(RemoteAddress::Tcp("0.0.0.0:9876".to_string()))
+        );
+        assert_eq!(
+            "[::1]:9876".parse(),
+            Ok(RemoteAddress::Tcp("[::1]:9876".to_string()))
+        );
+        assert_eq!(
+            "@wpaperd".parse(),
+            Ok(RemoteAddress::Abstract("wpaperd".to_string()))
+        );
+        assert!("@".parse::<RemoteAddress>().is_err());
+        assert!("localhost".parse::<RemoteAddress>().is_err());
+        assert!("localhost:port".parse::<RemoteAddress>().is_err());
+    }
 }